@@ -0,0 +1,82 @@
+//! Compare the reading paths of the generic image reader, for several compression methods and resolutions:
+//! all channels as flat samples, specific rgba channels in a pixel vector, and `f32` channel planes.
+//! The images are generated in memory, so that the benchmark does not measure the file system.
+//!
+//! ```sh
+//! cargo bench --bench read_paths
+//! ```
+
+extern crate exr;
+extern crate criterion;
+
+use criterion::{criterion_group, criterion_main, Criterion, BenchmarkId, Throughput, black_box};
+use exr::prelude::*;
+use exr::image::pixel_vec::PixelVec;
+use std::io::Cursor;
+
+const COMPRESSIONS: [Compression; 4] = [ Compression::Uncompressed, Compression::RLE, Compression::ZIP16, Compression::PIZ ];
+const RESOLUTIONS: [usize; 2] = [ 256, 1024 ];
+
+/// A smooth gradient with some noise, encoded as an rgba `f16` image.
+fn generate_file(size: usize, compression: Compression) -> Vec<u8> {
+    let noise = |position: Vec2<usize>| {
+        let mut hash = (position.x() as u32).wrapping_mul(0x9E37_79B9) ^ (position.y() as u32).wrapping_mul(0x85EB_CA6B);
+        hash ^= hash >> 15; hash = hash.wrapping_mul(0x2C1B_3C6D); hash ^= hash >> 12;
+        (hash & 0xffff) as f32 / 0xffff as f32 * 0.05
+    };
+
+    let pixels = |position: Vec2<usize>| {
+        let (x, y) = (position.x() as f32 / size as f32, position.y() as f32 / size as f32);
+        (f16::from_f32(x + noise(position)), f16::from_f32(y), f16::from_f32(x * y), f16::ONE)
+    };
+
+    let encoding = Encoding { compression, .. Encoding::FAST_LOSSLESS };
+    let layer = Layer::new((size, size), LayerAttributes::default(), encoding, SpecificChannels::rgba(pixels));
+
+    let mut bytes = Vec::new();
+    Image::from_layer(layer).write().to_buffered(Cursor::new(&mut bytes)).unwrap();
+    bytes
+}
+
+fn read_paths(criterion: &mut Criterion) {
+    let mut group = criterion.benchmark_group("read");
+    group.sample_size(10);
+
+    for &size in &RESOLUTIONS {
+        for &compression in &COMPRESSIONS {
+            let file = generate_file(size, compression);
+            let parameter = format!("{:?} {}x{}", compression, size, size);
+            group.throughput(Throughput::Bytes((size * size * 4 * 2) as u64));
+
+            group.bench_with_input(BenchmarkId::new("all channels", &parameter), &file, |bench, file| bench.iter(|| {
+                let image = read().no_deep_data().largest_resolution_level().all_channels()
+                    .first_valid_layer().all_attributes()
+                    .from_buffered(Cursor::new(file.as_slice())).unwrap();
+
+                black_box(image)
+            }));
+
+            group.bench_with_input(BenchmarkId::new("specific rgba channels", &parameter), &file, |bench, file| bench.iter(|| {
+                let image = read().no_deep_data().largest_resolution_level()
+                    .rgba_channels(PixelVec::<(f16, f16, f16, f16)>::constructor, PixelVec::set_pixel)
+                    .first_valid_layer().all_attributes()
+                    .from_buffered(Cursor::new(file.as_slice())).unwrap();
+
+                black_box(image)
+            }));
+
+            group.bench_with_input(BenchmarkId::new("f32 planes", &parameter), &file, |bench, file| bench.iter(|| {
+                let image = read().no_deep_data().f32_planes().largest_resolution_level().all_channels()
+                    .first_valid_layer().all_attributes()
+                    .from_buffered(Cursor::new(file.as_slice())).unwrap();
+
+                black_box(image)
+            }));
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, read_paths);
+criterion_main!(benches);
@@ -0,0 +1,63 @@
+// exr imports
+extern crate exr;
+
+/// Read an image from an object storage, such as S3, fetching only the required chunks.
+/// Instead of wrapping the storage into a `Read + Seek` implementation,
+/// implement `ChunkSource`, which fetches a range of bytes at once.
+/// Requires the `remote` feature, which is used for the http requests.
+///
+/// This example reads public objects, or objects using a pre-signed url.
+/// For private objects, sign each request using your storage client instead.
+/// Run with `cargo run --example 9_object_storage --features remote -- <bucket> <region> <key>`.
+fn main() {
+    use exr::prelude::*;
+    use exr::block::source::ChunkSource;
+    use std::ops::Range;
+    use std::io::Read;
+
+    /// An object in an S3 bucket, which is accessed using http range requests.
+    struct S3Object {
+        agent: ureq::Agent,
+        url: String,
+    }
+
+    impl S3Object {
+        fn new(bucket: &str, region: &str, key: &str) -> Self {
+            S3Object {
+                agent: ureq::Agent::new(),
+                url: format!("https://{}.s3.{}.amazonaws.com/{}", bucket, region, key),
+            }
+        }
+    }
+
+    impl ChunkSource for S3Object {
+        fn get_bytes(&mut self, bytes: Range<u64>) -> std::io::Result<Vec<u8>> {
+            let response = self.agent.get(&self.url)
+                .set("Range", &format!("bytes={}-{}", bytes.start, bytes.end - 1))
+                .call();
+
+            let response = match response {
+                Ok(response) => response,
+                Err(ureq::Error::Status(416, _)) => return Ok(Vec::new()), // after the end of the object
+                Err(error) => return Err(std::io::Error::new(std::io::ErrorKind::Other, error)),
+            };
+
+            let mut downloaded = Vec::new();
+            response.into_reader().take(bytes.end - bytes.start).read_to_end(&mut downloaded)?;
+            Ok(downloaded)
+        }
+    }
+
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    let (bucket, region, key) = match arguments.as_slice() {
+        [ bucket, region, key ] => (bucket, region, key),
+        _ => return eprintln!("usage: 9_object_storage <bucket> <region> <key>"),
+    };
+
+    let image = read()
+        .no_deep_data().largest_resolution_level().all_channels().all_layers().all_attributes()
+        .from_source(S3Object::new(bucket, region, key))
+        .expect("the object could not be read");
+
+    println!("image was read: {:#?}", image.attributes);
+}
@@ -0,0 +1,222 @@
+//! Derive macros for the `exr` crate.
+//! Enable the `derive` feature of `exr` and use `exr::prelude::ExrPixel` instead of depending on this crate directly.
+
+#![forbid(unsafe_code)]
+#![warn(missing_docs)]
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, GenericArgument, Lit, Meta, NestedMeta, PathArguments, Type};
+
+
+/// Store the fields of a struct as separate channels of an exr image.
+/// Each field must be an `f16`, `f32` or `u32`, or an `Option` of one of these.
+/// Fields with an `Option` type are read as optional channels, and are `None` if the file does not contain the channel.
+///
+/// Single-letter field names are converted to upper case, such that the field `r` refers to the channel `R`.
+/// All other field names are used as they are. Use `#[exr(channel = "diffuse.R")]` on a field to specify the channel name.
+///
+/// Use `#[exr(default = 1.0)]` on a field without `Option` to read it as an optional channel,
+/// which contains the default value if the file does not contain the channel.
+/// Negative values can be specified as a string, for example `#[exr(default = "-1.0")]`.
+#[proc_macro_derive(ExrPixel, attributes(exr))]
+pub fn derive_exr_pixel(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    derive(input).unwrap_or_else(|error| error.to_compile_error()).into()
+}
+
+/// A single field of the pixel struct, which is stored in a single channel.
+struct Channel {
+    field: syn::Ident,
+    channel_name: String,
+    field_type: Type,
+
+    /// The type inside the `Option`, if this channel is optional.
+    optional_sample_type: Option<Type>,
+
+    /// The value to use if the file does not contain this channel.
+    default_value: Option<f32>,
+}
+
+/// The contents of the `#[exr(...)]` attributes of a field.
+#[derive(Default)]
+struct FieldAttributes {
+    channel_name: Option<String>,
+    default_value: Option<f32>,
+}
+
+fn derive(input: DeriveInput) -> syn::Result<TokenStream2> {
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(&input.generics, "`ExrPixel` cannot be derived for generic structs"));
+    }
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(Error::new_spanned(&input.ident, "`ExrPixel` can only be derived for structs with named fields")),
+        },
+        _ => return Err(Error::new_spanned(&input.ident, "`ExrPixel` can only be derived for structs")),
+    };
+
+    if fields.is_empty() {
+        return Err(Error::new_spanned(&input.ident, "`ExrPixel` requires at least one field"));
+    }
+
+    let channels = fields.iter().map(|field| {
+        let ident = field.ident.clone().expect("named field without name");
+        let attributes = field_attributes(&field.attrs)?;
+        let optional_sample_type = option_inner_type(&field.ty).cloned();
+
+        if optional_sample_type.is_some() && attributes.default_value.is_some() {
+            return Err(Error::new_spanned(&field.ty, "a field with a default value must not be an `Option`"));
+        }
+
+        Ok(Channel {
+            channel_name: attributes.channel_name.unwrap_or_else(|| default_channel_name(&ident)),
+            default_value: attributes.default_value,
+            optional_sample_type,
+            field_type: field.ty.clone(),
+            field: ident,
+        })
+    }).collect::<syn::Result<Vec<Channel>>>()?;
+
+    for (index, channel) in channels.iter().enumerate() {
+        if channels[.. index].iter().any(|previous| previous.channel_name == channel.channel_name) {
+            return Err(Error::new_spanned(&channel.field, format!("the channel name `{}` is duplicate", channel.channel_name)));
+        }
+    }
+
+    let name = &input.ident;
+    let exr = quote!(::exr);
+    let recursive = quote!(#exr::image::recursive::Recursive);
+    let none_more = quote!(#exr::image::recursive::NoneMore);
+    let read = quote!(#exr::image::read::specific_channels);
+
+    // the first field is the innermost recursive value
+    let mut recursive_pixel_type = none_more.clone();
+    let mut recursive_pixel_value = none_more.clone();
+    let mut read_channels_type = none_more.clone();
+    let mut read_channels_value = none_more.clone();
+    let mut descriptions_type = none_more.clone();
+    let mut descriptions_value = none_more.clone();
+
+    for channel in &channels {
+        let Channel { field, channel_name, field_type, optional_sample_type, default_value } = channel;
+        let sample_type = optional_sample_type.as_ref().unwrap_or(field_type);
+
+        recursive_pixel_type = quote!(#recursive<#recursive_pixel_type, #field_type>);
+        recursive_pixel_value = quote!(#recursive::new(#recursive_pixel_value, self.#field));
+
+        if optional_sample_type.is_some() {
+            read_channels_type = quote!(#read::ReadOptionalChannel<#read_channels_type, #field_type>);
+            read_channels_value = quote!(#read::ReadSpecificChannel::optional(#read_channels_value, #channel_name, ::core::option::Option::None));
+        }
+        else if let Some(default_value) = default_value {
+            let default_sample = quote!(<#field_type as #exr::block::samples::FromNativeSample>::from_f32(#default_value));
+            read_channels_type = quote!(#read::ReadOptionalChannel<#read_channels_type, #field_type>);
+            read_channels_value = quote!(#read::ReadSpecificChannel::optional(#read_channels_value, #channel_name, #default_sample));
+        }
+        else {
+            read_channels_type = quote!(#read::ReadRequiredChannel<#read_channels_type, #field_type>);
+            read_channels_value = quote!(#read::ReadSpecificChannel::required(#read_channels_value, #channel_name));
+        }
+
+        descriptions_type = quote!(#recursive<#descriptions_type, #exr::meta::attribute::ChannelDescription>);
+        descriptions_value = quote!(#recursive::new(#descriptions_value, #exr::meta::attribute::ChannelDescription::named(
+            #channel_name, <#sample_type as #exr::image::IntoSample>::PREFERRED_SAMPLE_TYPE
+        )));
+    }
+
+    let field_types = channels.iter().map(|channel| &channel.field_type);
+    let field_values = channels.iter().enumerate().map(|(index, channel)| {
+        let field = &channel.field;
+        let index = syn::Index::from(index);
+        quote!(#field: tuple.#index)
+    });
+
+    Ok(quote! {
+        impl #exr::image::recursive::IntoRecursive for #name {
+            type Recursive = #recursive_pixel_type;
+            fn into_recursive(self) -> Self::Recursive { #recursive_pixel_value }
+        }
+
+        impl #exr::image::pixel_struct::ExrPixel for #name {
+            type ReadChannels = #read_channels_type;
+            type Tuple = ( #( #field_types, )* );
+            type ChannelDescriptions = #descriptions_type;
+
+            fn read_channels() -> Self::ReadChannels { #read_channels_value }
+            fn from_tuple(tuple: Self::Tuple) -> Self { #name { #( #field_values ),* } }
+            fn channel_descriptions() -> Self::ChannelDescriptions { #descriptions_value }
+        }
+    })
+}
+
+/// Parse `#[exr(channel = "name", default = 1.0)]`.
+fn field_attributes(attributes: &[syn::Attribute]) -> syn::Result<FieldAttributes> {
+    let mut field_attributes = FieldAttributes::default();
+
+    for attribute in attributes.iter().filter(|attribute| attribute.path.is_ident("exr")) {
+        let list = match attribute.parse_meta()? {
+            Meta::List(list) => list,
+            other => return Err(Error::new_spanned(other, "expected `#[exr(channel = \"name\")]`")),
+        };
+
+        for nested in list.nested {
+            match nested {
+                NestedMeta::Meta(Meta::NameValue(name_value)) if name_value.path.is_ident("channel") => {
+                    match name_value.lit {
+                        Lit::Str(name) if !name.value().is_empty() => field_attributes.channel_name = Some(name.value()),
+                        other => return Err(Error::new_spanned(other, "the channel name must be a non-empty string")),
+                    }
+                },
+
+                NestedMeta::Meta(Meta::NameValue(name_value)) if name_value.path.is_ident("default") => {
+                    let value = match &name_value.lit {
+                        Lit::Float(float) => float.base10_parse::<f32>().ok(),
+                        Lit::Int(int) => int.base10_parse::<f32>().ok(),
+                        Lit::Str(string) => string.value().trim().parse::<f32>().ok().filter(|value| value.is_finite()),
+                        _ => None,
+                    };
+
+                    match value {
+                        Some(value) => field_attributes.default_value = Some(value),
+                        None => return Err(Error::new_spanned(name_value.lit, "the default value must be a number")),
+                    }
+                },
+
+                other => return Err(Error::new_spanned(other, "unknown attribute, expected `channel = \"name\"` or `default = 1.0`")),
+            }
+        }
+    }
+
+    Ok(field_attributes)
+}
+
+/// Single letters are converted to upper case, all other names are kept.
+fn default_channel_name(field: &syn::Ident) -> String {
+    let name = field.to_string();
+    let name = name.strip_prefix("r#").unwrap_or(&name);
+    if name.chars().count() == 1 { name.to_uppercase() } else { name.to_string() }
+}
+
+/// If this type is an `Option<T>`, returns `T`.
+fn option_inner_type(field_type: &Type) -> Option<&Type> {
+    let path = match field_type {
+        Type::Path(path) if path.qself.is_none() => &path.path,
+        _ => return None,
+    };
+
+    let segment = path.segments.last()?;
+    if segment.ident != "Option" { return None; }
+
+    match &segment.arguments {
+        PathArguments::AngleBracketed(arguments) if arguments.args.len() == 1 => match arguments.args.first()? {
+            GenericArgument::Type(inner) => Some(inner),
+            _ => None,
+        },
+
+        _ => None,
+    }
+}
@@ -0,0 +1,128 @@
+//! Convert an OpenEXR file, using the conversions from `exr::image::transcode`.
+//! Requires the `cli` feature:
+//!
+//! ```sh
+//! cargo run --release --features cli --bin exrconvert -- input.exr output.exr --compression piz --f16
+//! ```
+//!
+//! Does not support deep data.
+
+extern crate exr;
+
+use exr::prelude::*;
+use exr::image::transcode;
+
+const USAGE: &str = "usage: exrconvert <input> <output> [options]
+options:
+  --compression <name>       none, rle, zips, zip, piz, pxr24, b44, b44a, dwaa, or dwab
+  --f16                      convert 32-bit float channels to 16-bit float channels
+  --layer <name>             keep only the layer with this name
+  --crop-to-display-window   remove all pixels outside the display window
+  --scan-lines               store the pixels as scan lines
+  --tiles <width>x<height>   store the pixels as tiles of the specified size";
+
+
+/// The conversions requested on the command line, applied in the order of the fields.
+#[derive(Default)]
+struct Conversion {
+    layer: Option<String>,
+    crop_to_display_window: bool,
+    f16: bool,
+    compression: Option<Compression>,
+    blocks: Option<Blocks>,
+}
+
+fn main() {
+    let mut arguments = std::env::args().skip(1);
+    let mut paths = Vec::new();
+    let mut conversion = Conversion::default();
+
+    while let Some(argument) = arguments.next() {
+        let result = match argument.as_str() {
+            "-h" | "--help" => { println!("{}", USAGE); return; },
+            "--compression" => parse_compression(arguments.next()).map(|compression| conversion.compression = Some(compression)),
+            "--f16" => { conversion.f16 = true; Ok(()) },
+            "--layer" => arguments.next().ok_or("missing layer name").map(|name| conversion.layer = Some(name)),
+            "--crop-to-display-window" => { conversion.crop_to_display_window = true; Ok(()) },
+            "--scan-lines" => { conversion.blocks = Some(Blocks::ScanLines); Ok(()) },
+            "--tiles" => parse_tile_size(arguments.next()).map(|size| conversion.blocks = Some(Blocks::Tiles(size))),
+            flag if flag.starts_with("--") => Err("unknown option"),
+            path => { paths.push(path.to_string()); Ok(()) },
+        };
+
+        if let Err(message) = result {
+            exit_with_usage(&format!("{}: {}", message, argument));
+        }
+    }
+
+    let (input, output) = match paths.as_slice() {
+        [input, output] => (input, output),
+        _ => exit_with_usage("expected one input and one output path"),
+    };
+
+    let result = read_all_data_from_file(input)
+        .and_then(|image| conversion.apply(image))
+        .and_then(|image| image.write().to_file(output));
+
+    if let Err(error) = result {
+        eprintln!("could not convert {}: {}", input, error);
+        std::process::exit(1);
+    }
+}
+
+impl Conversion {
+    fn apply(&self, mut image: AnyImage) -> Result<AnyImage> {
+        if let Some(layer) = &self.layer {
+            image = transcode::extract_layer(image, layer)?;
+        }
+
+        if self.crop_to_display_window {
+            image = transcode::crop_to_display_window(image)?;
+        }
+
+        if self.f16 {
+            image = transcode::convert_f32_to_f16(image);
+        }
+
+        if let Some(compression) = self.compression {
+            image = transcode::recompress(image, compression);
+        }
+
+        if let Some(blocks) = self.blocks {
+            image = transcode::repack_blocks(image, blocks)?;
+        }
+
+        Ok(image)
+    }
+}
+
+fn parse_compression(name: Option<String>) -> std::result::Result<Compression, &'static str> {
+    Ok(match name.ok_or("missing compression name")?.to_lowercase().as_str() {
+        "none" | "uncompressed" => Compression::Uncompressed,
+        "rle" => Compression::RLE,
+        "zips" => Compression::ZIP1,
+        "zip" => Compression::ZIP16,
+        "piz" => Compression::PIZ,
+        "pxr24" => Compression::PXR24,
+        "b44" => Compression::B44,
+        "b44a" => Compression::B44A,
+        "dwaa" => Compression::DWAA(None),
+        "dwab" => Compression::DWAB(None),
+        _ => return Err("unknown compression"),
+    })
+}
+
+fn parse_tile_size(size: Option<String>) -> std::result::Result<Vec2<usize>, &'static str> {
+    let size = size.ok_or("missing tile size")?;
+    let mut dimensions = size.split('x').map(|dimension| dimension.parse::<usize>());
+
+    match (dimensions.next(), dimensions.next(), dimensions.next()) {
+        (Some(Ok(width)), Some(Ok(height)), None) if width > 0 && height > 0 => Ok(Vec2(width, height)),
+        _ => Err("invalid tile size"),
+    }
+}
+
+fn exit_with_usage(message: &str) -> ! {
+    eprintln!("{}\n{}", message, USAGE);
+    std::process::exit(2);
+}
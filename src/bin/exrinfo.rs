@@ -0,0 +1,282 @@
+//! Print the layers, channels, attributes, and per-channel statistics of OpenEXR files,
+//! and check the files for errors. Requires the `cli` feature:
+//!
+//! ```sh
+//! cargo run --release --features cli --bin exrinfo -- [--json] [--no-statistics] image.exr ...
+//! ```
+//!
+//! Exits with a non-zero status if any of the files is invalid.
+
+extern crate exr;
+
+use std::path::Path;
+use std::fs::File;
+use std::io::BufReader;
+use std::fmt::Write;
+
+use exr::prelude::*;
+use exr::block::reader::Reader;
+use exr::meta::header::Header;
+use exr::meta::attribute::{AttributeValue, ChannelDescription};
+use exr::meta::dump::{json_number, json_string};
+
+const USAGE: &str = "usage: exrinfo [--json] [--no-statistics] <file>...";
+
+
+/// Everything that is printed about a single file.
+struct FileInfo {
+    path: String,
+    headers: Vec<Header>,
+    statistics: Option<Vec<Vec<ChannelStatistics>>>,
+    errors: Vec<String>,
+    warnings: Vec<String>,
+}
+
+/// Summary of all samples in a channel of the largest resolution level.
+struct ChannelStatistics {
+    min: Option<f32>,
+    max: Option<f32>,
+    mean: Option<f64>,
+    nan_count: usize,
+    infinite_count: usize,
+}
+
+fn main() {
+    let mut json = false;
+    let mut statistics = true;
+    let mut paths = Vec::new();
+
+    for argument in std::env::args().skip(1) {
+        match argument.as_str() {
+            "--json" => json = true,
+            "--no-statistics" => statistics = false,
+            "-h" | "--help" => { println!("{}", USAGE); return; },
+            flag if flag.starts_with("--") => {
+                eprintln!("unknown option {}\n{}", flag, USAGE);
+                std::process::exit(2);
+            },
+            path => paths.push(path.to_string()),
+        }
+    }
+
+    if paths.is_empty() {
+        eprintln!("{}", USAGE);
+        std::process::exit(2);
+    }
+
+    let files: Vec<FileInfo> = paths.into_iter()
+        .map(|path| inspect_file(path, statistics))
+        .collect();
+
+    if json { print!("{}", files_to_json(&files)); }
+    else { for file in &files { print!("{}", file_to_text(file)); } }
+
+    if files.iter().any(|file| !file.errors.is_empty()) {
+        std::process::exit(1);
+    }
+}
+
+/// Read the meta data, the offset tables, and optionally all pixels.
+/// Errors are collected instead of aborting, such that as much as possible is printed.
+fn inspect_file(path: String, compute_statistics: bool) -> FileInfo {
+    let mut info = FileInfo { headers: Vec::new(), statistics: None, errors: Vec::new(), warnings: Vec::new(), path };
+
+    let reader = File::open(&info.path).map_err(Error::from)
+        .and_then(|file| Reader::read_from_buffered(BufReader::new(file), false));
+
+    let mut reader = match reader {
+        Ok(reader) => reader,
+        Err(error) => { info.errors.push(error.to_string()); return info; }
+    };
+
+    info.headers = reader.headers().to_vec();
+
+    // the reader above is lenient, such that the meta data of slightly broken files can still be printed
+    if let Err(error) = MetaData::read_from_file(&info.path, true) {
+        info.errors.push(format!("strict meta data check: {}", error));
+    }
+
+    match reader.inspect_offset_tables() {
+        Ok(warnings) => info.errors.extend(warnings.iter().map(|warning| warning.to_string())),
+        Err(error) => info.errors.push(format!("offset tables: {}", error)),
+    }
+
+    if compute_statistics {
+        let image = read().no_deep_data().largest_resolution_level().all_channels().all_layers().all_attributes()
+            .pedantic().from_file(Path::new(&info.path));
+
+        match image {
+            Ok(image) => info.statistics = Some(
+                image.layer_data.iter()
+                    .map(|layer| layer.channel_data.list.iter().map(|channel| ChannelStatistics::from_samples(&channel.sample_data)).collect())
+                    .collect()
+            ),
+
+            // unsupported features do not make the file invalid
+            Err(Error::NotSupported(message)) => info.warnings.push(format!("pixels not inspected: {} not supported", message)),
+            Err(error) => info.errors.push(format!("pixels: {}", error)),
+        }
+    }
+
+    info
+}
+
+impl ChannelStatistics {
+    fn from_samples(samples: &FlatSamples) -> Self {
+        let mut statistics = ChannelStatistics { min: None, max: None, mean: None, nan_count: 0, infinite_count: 0 };
+        let mut sum = 0.0_f64;
+        let mut finite_count = 0_usize;
+
+        for value in samples.values_as_f32() {
+            if value.is_nan() { statistics.nan_count += 1; }
+            else if value.is_infinite() { statistics.infinite_count += 1; }
+            else {
+                statistics.min = Some(statistics.min.map_or(value, |min| min.min(value)));
+                statistics.max = Some(statistics.max.map_or(value, |max| max.max(value)));
+                sum += value as f64;
+                finite_count += 1;
+            }
+        }
+
+        if finite_count != 0 {
+            statistics.mean = Some(sum / finite_count as f64);
+        }
+
+        statistics
+    }
+}
+
+/// All attributes of the header, including the required ones, in the order they would be written to a file.
+/// Excludes the channel list, which is printed separately.
+fn all_attributes(header: &Header) -> Vec<(Text, AttributeValue)> {
+    header.ordered_attributes()
+        .filter(|(_, value)| !matches!(value.as_ref(), AttributeValue::ChannelList(_)))
+        .map(|(name, value)| (Text::from_slice_unchecked(name), value.into_owned()))
+        .collect()
+}
+
+fn layer_name(header: &Header) -> String {
+    header.own_attributes.layer_name.as_ref().map(Text::to_string).unwrap_or_default()
+}
+
+fn blocks_to_string(header: &Header) -> String {
+    match header.blocks {
+        exr::meta::BlockDescription::ScanLines => String::from("scan lines"),
+        exr::meta::BlockDescription::Tiles(tiles) => format!(
+            "tiles {}x{} ({:?}, rounding {:?})",
+            tiles.tile_size.width(), tiles.tile_size.height(), tiles.level_mode, tiles.rounding_mode
+        ),
+    }
+}
+
+fn sample_type_to_str(channel: &ChannelDescription) -> &'static str {
+    match channel.sample_type {
+        SampleType::F16 => "f16",
+        SampleType::F32 => "f32",
+        SampleType::U32 => "u32",
+    }
+}
+
+
+fn file_to_text(file: &FileInfo) -> String {
+    let mut text = String::new();
+    writeln!(text, "file {}", file.path).unwrap();
+
+    for (layer_index, header) in file.headers.iter().enumerate() {
+        let window = header.data_window();
+
+        writeln!(text, "  layer {} \"{}\"", layer_index, layer_name(header)).unwrap();
+        writeln!(text, "    size {}x{} at ({}, {})", window.size.width(), window.size.height(), window.position.x(), window.position.y()).unwrap();
+        writeln!(text, "    compression {}", header.compression).unwrap();
+        writeln!(text, "    blocks {}, line order {:?}, {} chunks", blocks_to_string(header), header.line_order, header.chunk_count).unwrap();
+        if header.deep { writeln!(text, "    deep data").unwrap(); }
+
+        writeln!(text, "    channels").unwrap();
+        for (channel_index, channel) in header.channels.list.iter().enumerate() {
+            write!(text, "      {} {}", channel.name, sample_type_to_str(channel)).unwrap();
+            if channel.sampling != Vec2(1, 1) { write!(text, ", sampling {}x{}", channel.sampling.x(), channel.sampling.y()).unwrap(); }
+            if channel.quantize_linearly { write!(text, ", linear").unwrap(); }
+
+            let statistics = file.statistics.as_ref().and_then(|layers| layers.get(layer_index)?.get(channel_index));
+            if let Some(statistics) = statistics {
+                let display = |value: Option<String>| value.unwrap_or_else(|| String::from("-"));
+
+                write!(
+                    text, ", min {}, max {}, mean {}",
+                    display(statistics.min.map(|min| min.to_string())),
+                    display(statistics.max.map(|max| max.to_string())),
+                    display(statistics.mean.map(|mean| mean.to_string())),
+                ).unwrap();
+
+                if statistics.nan_count != 0 { write!(text, ", {} nan", statistics.nan_count).unwrap(); }
+                if statistics.infinite_count != 0 { write!(text, ", {} infinite", statistics.infinite_count).unwrap(); }
+            }
+
+            writeln!(text).unwrap();
+        }
+
+        writeln!(text, "    attributes").unwrap();
+        for (name, value) in all_attributes(header) {
+            writeln!(text, "      {}: {}", name, value).unwrap();
+        }
+    }
+
+    if file.errors.is_empty() { writeln!(text, "  valid").unwrap(); }
+    for error in &file.errors { writeln!(text, "  error: {}", error).unwrap(); }
+    for warning in &file.warnings { writeln!(text, "  warning: {}", warning).unwrap(); }
+
+    text
+}
+
+
+fn files_to_json(files: &[FileInfo]) -> String {
+    let files: Vec<String> = files.iter().map(file_to_json).collect();
+    format!("{{\"files\":[{}]}}\n", files.join(","))
+}
+
+fn file_to_json(file: &FileInfo) -> String {
+    let layers: Vec<String> = file.headers.iter().enumerate().map(|(layer_index, header)| {
+        let window = header.data_window();
+
+        let channels: Vec<String> = header.channels.list.iter().enumerate().map(|(channel_index, channel)| {
+            let statistics = file.statistics.as_ref()
+                .and_then(|layers| layers.get(layer_index)?.get(channel_index))
+                .map(|statistics| format!(
+                    "{{\"min\":{},\"max\":{},\"mean\":{},\"nan_count\":{},\"infinite_count\":{}}}",
+                    json_optional_number(statistics.min), json_optional_number(statistics.max), json_optional_number(statistics.mean),
+                    statistics.nan_count, statistics.infinite_count
+                ))
+                .unwrap_or_else(|| String::from("null"));
+
+            format!(
+                "{{\"name\":{},\"sample_type\":\"{}\",\"sampling\":[{},{}],\"quantize_linearly\":{},\"statistics\":{}}}",
+                json_string(&channel.name.to_string()), sample_type_to_str(channel),
+                channel.sampling.x(), channel.sampling.y(), channel.quantize_linearly, statistics
+            )
+        }).collect();
+
+        let attributes: Vec<String> = all_attributes(header).iter()
+            .map(|(name, value)| format!("{}:{}", json_string(&name.to_string()), value.to_json()))
+            .collect();
+
+        format!(
+            "{{\"name\":{},\"data_window\":{{\"position\":[{},{}],\"size\":[{},{}]}},\"compression\":{},\"blocks\":{},\
+            \"line_order\":\"{:?}\",\"chunk_count\":{},\"deep\":{},\"channels\":[{}],\"attributes\":{{{}}}}}",
+            json_string(&layer_name(header)), window.position.x(), window.position.y(), window.size.width(), window.size.height(),
+            json_string(&header.compression.to_string()), json_string(&blocks_to_string(header)),
+            header.line_order, header.chunk_count, header.deep, channels.join(","), attributes.join(",")
+        )
+    }).collect();
+
+    let json_strings = |strings: &[String]| strings.iter().map(|string| json_string(string)).collect::<Vec<_>>().join(",");
+
+    format!(
+        "{{\"path\":{},\"layers\":[{}],\"validation\":{{\"valid\":{},\"errors\":[{}],\"warnings\":[{}]}}}}",
+        json_string(&file.path), layers.join(","), file.errors.is_empty(),
+        json_strings(&file.errors), json_strings(&file.warnings)
+    )
+}
+
+fn json_optional_number<T: Into<f64> + std::fmt::Display + Copy>(number: Option<T>) -> String {
+    number.map_or_else(|| String::from("null"), json_number)
+}
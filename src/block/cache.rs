@@ -0,0 +1,286 @@
+//! Keep recently decompressed blocks in memory, to avoid decompressing them again.
+//!
+//! Interactive applications, such as image viewers that allow panning and zooming,
+//! read overlapping regions of the same file over and over again.
+//! Pass a shared `ChunkCache` to `ReadImage::chunk_cache` to keep the decompressed blocks
+//! of these reads. The next read then only decompresses the blocks that are not in the cache yet.
+//! The cache works regardless of where the bytes come from, be it a file, a buffer, or a `ChunkSource`.
+//!
+//! Cached blocks are still copied once per read, because the image reader takes ownership of each block,
+//! and newly decompressed blocks are copied into the cache. This is much faster than decompressing the blocks again.
+//!
+//! The cache holds at most the specified number of pixel bytes.
+//! When it is full, the least recently used blocks are evicted.
+//! Blocks are identified by a file id, which is chosen by the application,
+//! the index of the layer, and the index of the chunk within the layer.
+//! Use a different file id for each file. When a file changes, call `remove_file` to discard its blocks.
+
+use std::collections::{HashMap, BTreeMap};
+use std::fmt::{Debug, Formatter};
+use std::io::{Read, Seek};
+use std::sync::{Arc, Mutex};
+use crate::block::{UncompressedBlock, BlockIndex};
+use crate::block::chunk::TileCoordinates;
+use crate::block::reader::ChunksReader;
+use crate::meta::header::Header;
+use crate::error::{Result, UnitResult};
+
+
+/// Identifies a file in a `ChunkCache`. Chosen by the application,
+/// for example by hashing the path of the file, or by counting the opened files.
+pub type FileId = u64;
+
+/// Identifies a block in a `ChunkCache`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ChunkKey {
+
+    /// The file that contains the chunk.
+    pub file: FileId,
+
+    /// The index of the layer in the file that contains the chunk.
+    pub layer: usize,
+
+    /// The index of the chunk in the layer, in increasing y order,
+    /// as computed by `Header::chunk_index_increasing_y`.
+    pub chunk_index: usize,
+}
+
+impl ChunkKey {
+
+    /// Compute the key of the decompressed block in the specified file.
+    pub fn for_block(file: FileId, headers: &[Header], block: BlockIndex) -> Result<Self> {
+        let header = headers.get(block.layer)
+            .ok_or_else(|| crate::error::Error::invalid("block layer index"))?;
+
+        let tile = TileCoordinates {
+            tile_index: block.pixel_position / header.max_block_pixel_size(),
+            level_index: block.level,
+        };
+
+        Ok(ChunkKey { file, layer: block.layer, chunk_index: header.chunk_index_increasing_y(tile)? })
+    }
+}
+
+/// A least-recently-used cache of decompressed blocks, shared across reads and threads.
+/// See the module documentation for more details.
+pub struct ChunkCache {
+    state: Mutex<CacheState>,
+    max_byte_size: usize,
+}
+
+/// How many blocks could be taken from a `ChunkCache`, and how many blocks had to be decompressed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct ChunkCacheStatistics {
+
+    /// The number of blocks that were found in the cache.
+    pub hits: usize,
+
+    /// The number of blocks that were not found in the cache.
+    pub misses: usize,
+
+    /// The number of blocks that were removed to make room for other blocks.
+    pub evictions: usize,
+}
+
+#[derive(Default)]
+struct CacheState {
+    blocks: HashMap<ChunkKey, CachedBlock>,
+
+    /// The keys of all blocks, sorted by the time they were last used.
+    usage: BTreeMap<u64, ChunkKey>,
+
+    time: u64,
+    byte_size: usize,
+    statistics: ChunkCacheStatistics,
+}
+
+struct CachedBlock {
+    block: Arc<UncompressedBlock>,
+    last_used: u64,
+}
+
+impl ChunkCache {
+
+    /// Create an empty cache, which keeps blocks with a total of at most `max_byte_size` pixel bytes.
+    pub fn new(max_byte_size: usize) -> Self {
+        ChunkCache { state: Mutex::new(CacheState::default()), max_byte_size }
+    }
+
+    /// Return the block, if it is in the cache, and mark it as recently used.
+    pub fn get(&self, key: ChunkKey) -> Option<Arc<UncompressedBlock>> {
+        let mut state = self.lock();
+        state.time += 1;
+        let time = state.time;
+
+        match state.blocks.get_mut(&key) {
+            Some(cached) => {
+                let previous_use = std::mem::replace(&mut cached.last_used, time);
+                let block = cached.block.clone();
+
+                state.usage.remove(&previous_use);
+                state.usage.insert(time, key);
+                state.statistics.hits += 1;
+                Some(block)
+            },
+
+            None => {
+                state.statistics.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Add the block to the cache, replacing any previous block with the same key,
+    /// and evict the least recently used blocks if the cache is full.
+    /// Blocks larger than the whole cache are not added.
+    pub fn insert(&self, key: ChunkKey, block: Arc<UncompressedBlock>) {
+        let byte_size = block.data.len();
+        if byte_size > self.max_byte_size { return; }
+
+        let mut state = self.lock();
+        state.time += 1;
+        let time = state.time;
+
+        if let Some(previous) = state.blocks.insert(key, CachedBlock { block, last_used: time }) {
+            state.usage.remove(&previous.last_used);
+            state.byte_size -= previous.block.data.len();
+        }
+
+        state.usage.insert(time, key);
+        state.byte_size += byte_size;
+
+        while state.byte_size > self.max_byte_size {
+            let oldest_time = *state.usage.keys().next().expect("cache usage bug");
+            let oldest_key = state.usage.remove(&oldest_time).expect("cache usage bug");
+            let oldest = state.blocks.remove(&oldest_key).expect("cache usage bug");
+            state.byte_size -= oldest.block.data.len();
+            state.statistics.evictions += 1;
+        }
+    }
+
+    /// Whether the block is in the cache. Does not mark the block as recently used.
+    pub fn contains(&self, key: ChunkKey) -> bool { self.lock().blocks.contains_key(&key) }
+
+    /// Decompress all blocks of the file that are not in the cache yet, and add them to the cache.
+    /// Use this to prefetch a file before it is displayed, for example the next frame of a sequence.
+    /// Does not support deep data.
+    pub fn load_file(&self, file: FileId, read: impl Read + Seek, pedantic: bool) -> UnitResult {
+        crate::block::read(read, pedantic)?
+            .filter_chunks(pedantic, |meta, tile, block| {
+                match meta.headers[block.layer].chunk_index_increasing_y(tile) {
+                    Ok(chunk_index) => !self.contains(ChunkKey { file, layer: block.layer, chunk_index }),
+                    Err(_) => true, // let the reader report the invalid tile
+                }
+            })?
+            .decompress_sequential(pedantic, |meta, block| {
+                self.insert(ChunkKey::for_block(file, &meta.headers, block.index)?, Arc::new(block));
+                Ok(())
+            })
+    }
+
+    /// Discard all blocks of the file, for example because the file has changed.
+    pub fn remove_file(&self, file: FileId) {
+        let mut state = self.lock();
+        let CacheState { blocks, usage, byte_size, .. } = &mut *state;
+
+        blocks.retain(|key, cached| {
+            if key.file != file { return true; }
+
+            usage.remove(&cached.last_used);
+            *byte_size -= cached.block.data.len();
+            false
+        });
+    }
+
+    /// Discard all blocks.
+    pub fn clear(&self) {
+        let mut state = self.lock();
+        state.blocks.clear();
+        state.usage.clear();
+        state.byte_size = 0;
+    }
+
+    /// The number of blocks in the cache.
+    pub fn len(&self) -> usize { self.lock().blocks.len() }
+
+    /// Whether the cache contains no blocks.
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    /// The sum of the pixel bytes of all blocks in the cache.
+    pub fn byte_size(&self) -> usize { self.lock().byte_size }
+
+    /// The maximum number of pixel bytes in the cache.
+    pub fn max_byte_size(&self) -> usize { self.max_byte_size }
+
+    /// The number of hits, misses, and evictions so far.
+    pub fn statistics(&self) -> ChunkCacheStatistics { self.lock().statistics }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CacheState> {
+        // the state is always consistent, even if another thread panicked
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Debug for ChunkCache {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        formatter.debug_struct("ChunkCache")
+            .field("block_count", &self.len())
+            .field("byte_size", &self.byte_size())
+            .field("max_byte_size", &self.max_byte_size)
+            .finish()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::math::Vec2;
+
+    fn block(byte_size: usize) -> Arc<UncompressedBlock> {
+        Arc::new(UncompressedBlock {
+            index: BlockIndex { layer: 0, level: Vec2(0, 0), pixel_position: Vec2(0, 0), pixel_size: Vec2(byte_size, 1) },
+            data: vec![0; byte_size],
+        })
+    }
+
+    fn key(file: FileId, chunk_index: usize) -> ChunkKey {
+        ChunkKey { file, layer: 0, chunk_index }
+    }
+
+    #[test]
+    fn evict_least_recently_used() {
+        let cache = ChunkCache::new(300);
+        cache.insert(key(0, 0), block(100));
+        cache.insert(key(0, 1), block(100));
+        cache.insert(key(1, 0), block(100));
+        assert_eq!(cache.byte_size(), 300);
+
+        // use the first block, such that the second block is the oldest
+        assert!(cache.get(key(0, 0)).is_some());
+        cache.insert(key(1, 1), block(100));
+
+        assert!(cache.get(key(0, 1)).is_none());
+        assert!(cache.get(key(0, 0)).is_some());
+        assert!(cache.get(key(1, 1)).is_some());
+        assert_eq!(cache.len(), 3);
+
+        assert_eq!(cache.statistics(), ChunkCacheStatistics { hits: 3, misses: 1, evictions: 1 });
+
+        // replacing a block does not count twice
+        cache.insert(key(1, 1), block(50));
+        assert_eq!(cache.byte_size(), 250);
+
+        // too large for the cache
+        cache.insert(key(2, 0), block(400));
+        assert!(cache.get(key(2, 0)).is_none());
+
+        cache.remove_file(1);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.byte_size(), 100);
+
+        cache.clear();
+        assert!(cache.is_empty());
+        assert_eq!(cache.byte_size(), 0);
+    }
+}
@@ -165,21 +165,21 @@ impl TileCoordinates {
     /// These coordinates are only valid inside the corresponding one header.
     /// Will start at 0 and always be positive.
     pub fn to_data_indices(&self, tile_size: Vec2<usize>, max: Vec2<usize>) -> Result<IntegerBounds> {
-        let x = self.tile_index.x() * tile_size.width();
-        let y = self.tile_index.y() * tile_size.height();
+        let x = self.tile_index.x().checked_mul(tile_size.width());
+        let y = self.tile_index.y().checked_mul(tile_size.height());
 
-        if x >= max.x() || y >= max.y() {
-            Err(Error::invalid("tile index"))
-        }
-        else {
-            Ok(IntegerBounds {
-                position: Vec2(usize_to_i32(x), usize_to_i32(y)),
-                size: Vec2(
-                    calculate_block_size(max.x(), tile_size.width(), x)?,
-                    calculate_block_size(max.y(), tile_size.height(), y)?,
-                ),
-            })
-        }
+        let (x, y) = match (x, y) {
+            (Some(x), Some(y)) if x < max.x() && y < max.y() => (x, y),
+            _ => return Err(Error::invalid("tile index")),
+        };
+
+        Ok(IntegerBounds {
+            position: Vec2(usize_to_i32(x), usize_to_i32(y)),
+            size: Vec2(
+                calculate_block_size(max.x(), tile_size.width(), x)?,
+                calculate_block_size(max.y(), tile_size.height(), y)?,
+            ),
+        })
     }
 
     /// Absolute coordinates inside the global 2D space of a file, may be negative.
@@ -197,6 +197,8 @@ impl TileCoordinates {
 
 
 use crate::meta::{MetaData, BlockDescription, calculate_block_size};
+use crate::block::pool::{BlockBufferPool, HeapBuffers};
+use crate::compression::ByteVec;
 
 impl CompressedScanLineBlock {
 
@@ -204,15 +206,24 @@ impl CompressedScanLineBlock {
     pub fn write<W: Write>(&self, write: &mut W) -> UnitResult {
         debug_assert_ne!(self.compressed_pixels.len(), 0, "empty blocks should not be put in the file bug");
 
-        i32::write(self.y_coordinate, write)?;
-        u8::write_i32_sized_slice(write, &self.compressed_pixels)?;
+        let mut block_header = [0_u8; 8];
+        let mut header_bytes = &mut block_header[..];
+        i32::write(self.y_coordinate, &mut header_bytes)?;
+        i32::write(usize_to_i32(self.compressed_pixels.len()), &mut header_bytes)?;
+
+        write_all_vectored(write, &block_header, &self.compressed_pixels)?;
         Ok(())
     }
 
     /// Read the value without validating.
     pub fn read(read: &mut impl Read, max_block_byte_size: usize) -> Result<Self> {
+        Self::read_with_pool(read, max_block_byte_size, &HeapBuffers)
+    }
+
+    /// Read the value without validating, taking the buffer for the compressed pixels from the pool.
+    pub fn read_with_pool(read: &mut impl Read, max_block_byte_size: usize, pool: &dyn BlockBufferPool) -> Result<Self> {
         let y_coordinate = i32::read(read)?;
-        let compressed_pixels = u8::read_i32_sized_vec(read, max_block_byte_size, Some(max_block_byte_size), "scan line block sample count")?;
+        let compressed_pixels = read_compressed_pixels(read, max_block_byte_size, pool, "scan line block sample count")?;
         Ok(CompressedScanLineBlock { y_coordinate, compressed_pixels })
     }
 }
@@ -223,19 +234,41 @@ impl CompressedTileBlock {
     pub fn write<W: Write>(&self, write: &mut W) -> UnitResult {
         debug_assert_ne!(self.compressed_pixels.len(), 0, "empty blocks should not be put in the file bug");
 
-        self.coordinates.write(write)?;
-        u8::write_i32_sized_slice(write, &self.compressed_pixels)?;
+        let mut block_header = [0_u8; 20];
+        let mut header_bytes = &mut block_header[..];
+        self.coordinates.write(&mut header_bytes)?;
+        i32::write(usize_to_i32(self.compressed_pixels.len()), &mut header_bytes)?;
+
+        write_all_vectored(write, &block_header, &self.compressed_pixels)?;
         Ok(())
     }
 
     /// Read the value without validating.
     pub fn read(read: &mut impl Read, max_block_byte_size: usize) -> Result<Self> {
+        Self::read_with_pool(read, max_block_byte_size, &HeapBuffers)
+    }
+
+    /// Read the value without validating, taking the buffer for the compressed pixels from the pool.
+    pub fn read_with_pool(read: &mut impl Read, max_block_byte_size: usize, pool: &dyn BlockBufferPool) -> Result<Self> {
         let coordinates = TileCoordinates::read(read)?;
-        let compressed_pixels = u8::read_i32_sized_vec(read, max_block_byte_size, Some(max_block_byte_size), "tile block sample count")?;
+        let compressed_pixels = read_compressed_pixels(read, max_block_byte_size, pool, "tile block sample count")?;
         Ok(CompressedTileBlock { coordinates, compressed_pixels })
     }
 }
 
+/// Read the byte count of the compressed pixels, and then the pixels into a buffer from the pool.
+fn read_compressed_pixels(read: &mut impl Read, max_block_byte_size: usize, pool: &dyn BlockBufferPool, purpose: &'static str) -> Result<ByteVec> {
+    let byte_count = usize::try_from(i32::read(read)?)?;
+    if byte_count > max_block_byte_size {
+        return Err(Error::invalid(purpose));
+    }
+
+    let mut compressed_pixels = pool.take(byte_count);
+    compressed_pixels.clear(); // the pool should return an empty buffer, but make sure
+    u8::read_into_vec(read, &mut compressed_pixels, byte_count, max_block_byte_size, Some(max_block_byte_size), purpose)?;
+    Ok(compressed_pixels)
+}
+
 impl CompressedDeepScanLineBlock {
 
     /// Without validation, write this instance to the byte stream.
@@ -254,9 +287,9 @@ impl CompressedDeepScanLineBlock {
     /// Read the value without validating.
     pub fn read(read: &mut impl Read, max_block_byte_size: usize) -> Result<Self> {
         let y_coordinate = i32::read(read)?;
-        let compressed_pixel_offset_table_size = u64_to_usize(u64::read(read)?);
-        let compressed_sample_data_size = u64_to_usize(u64::read(read)?);
-        let decompressed_sample_data_size = u64_to_usize(u64::read(read)?);
+        let compressed_pixel_offset_table_size = usize::try_from(u64::read(read)?)?;
+        let compressed_sample_data_size = usize::try_from(u64::read(read)?)?;
+        let decompressed_sample_data_size = usize::try_from(u64::read(read)?)?;
 
         // doc said i32, try u8
         let compressed_pixel_offset_table = i8::read_vec(
@@ -299,9 +332,9 @@ impl CompressedDeepTileBlock {
     /// Read the value without validating.
     pub fn read(read: &mut impl Read, hard_max_block_byte_size: usize) -> Result<Self> {
         let coordinates = TileCoordinates::read(read)?;
-        let compressed_pixel_offset_table_size = u64_to_usize(u64::read(read)?);
-        let compressed_sample_data_size = u64_to_usize(u64::read(read)?); // TODO u64 just guessed
-        let decompressed_sample_data_size = u64_to_usize(u64::read(read)?);
+        let compressed_pixel_offset_table_size = usize::try_from(u64::read(read)?)?;
+        let compressed_sample_data_size = usize::try_from(u64::read(read)?)?; // TODO u64 just guessed
+        let decompressed_sample_data_size = usize::try_from(u64::read(read)?)?;
 
         let compressed_pixel_offset_table = i8::read_vec(
             read, compressed_pixel_offset_table_size,
@@ -324,12 +357,117 @@ impl CompressedDeepTileBlock {
     }
 }
 
-use crate::error::{UnitResult, Result, Error, u64_to_usize, usize_to_i32, i32_to_usize};
+impl CompressedDeepScanLineBlock {
+
+    /// Decompress the pixel offset table and compute the number of samples of each pixel in this block.
+    /// Returns one count for each pixel in the block, row by row.
+    pub fn sample_counts(&self, header: &Header, pedantic: bool) -> Result<Vec<usize>> {
+        let tile = header.get_scan_line_block_tile_coordinates(self.y_coordinate)?;
+        decompress_sample_counts(header, tile, &self.compressed_pixel_offset_table, self.decompressed_sample_data_size, pedantic)
+    }
+}
+
+impl CompressedDeepTileBlock {
+
+    /// Decompress the pixel offset table and compute the number of samples of each pixel in this tile.
+    /// Returns one count for each pixel in the tile, row by row.
+    /// Respects the resolution level of the tile and tiles that are cut off at the edge of the level.
+    pub fn sample_counts(&self, header: &Header, pedantic: bool) -> Result<Vec<usize>> {
+        decompress_sample_counts(header, self.coordinates, &self.compressed_pixel_offset_table, self.decompressed_sample_data_size, pedantic)
+    }
+}
+
+/// The pixel offset table contains, for each pixel, the accumulated sample count
+/// of this pixel and all pixels to the left of it in the same row.
+fn decompress_sample_counts(
+    header: &Header, tile: TileCoordinates, compressed_table: &[i8],
+    decompressed_sample_data_size: usize, pedantic: bool
+) -> Result<Vec<usize>>
+{
+    if !header.deep { return Err(Error::invalid("deep block in flat layer")); }
+
+    let block_size = header.get_absolute_block_pixel_coordinates(tile)?.size;
+    let compressed_table: Vec<u8> = compressed_table.iter().map(|&byte| byte as u8).collect();
+    let table = header.compression.decompress_deep_offset_table(&compressed_table, block_size.area(), pedantic)?;
+
+    let mut counts = Vec::with_capacity(block_size.area());
+    for row in table.chunks_exact(block_size.width() * std::mem::size_of::<i32>()) {
+        let mut previous_offset = 0;
+
+        for offset in row.chunks_exact(std::mem::size_of::<i32>()) {
+            let offset = i32::from_le_bytes([ offset[0], offset[1], offset[2], offset[3] ]);
+            if offset < previous_offset { return Err(Error::invalid("deep pixel offset table")); }
+
+            counts.push(i32_to_usize(offset - previous_offset, "deep sample count")?);
+            previous_offset = offset;
+        }
+    }
+
+    let sample_count: usize = counts.iter().sum();
+    if sample_count * header.channels.bytes_per_pixel != decompressed_sample_data_size {
+        return Err(Error::invalid("deep sample data size"));
+    }
+
+    Ok(counts)
+}
+
+use crate::error::{UnitResult, Result, Error, usize_to_i32, i32_to_usize};
+use std::convert::TryFrom;
+use crate::meta::header::Header;
 use crate::math::Vec2;
+use crate::block::BlockIndex;
 
 /// Validation of chunks is done while reading and writing the actual data. (For example in exr::full_image)
 impl Chunk {
 
+    /// The header of the layer that this chunk belongs to.
+    pub fn header<'h>(&self, headers: &'h [Header]) -> Result<&'h Header> {
+        headers.get(self.layer_index).ok_or(Error::invalid("chunk layer index"))
+    }
+
+    /// The tile index and resolution level of this chunk.
+    /// Scan line blocks are converted to tile coordinates with a tile index of `(0, y)`.
+    pub fn tile_coordinates(&self, headers: &[Header]) -> Result<TileCoordinates> {
+        self.header(headers)?.get_block_data_indices(&self.compressed_block)
+    }
+
+    /// The index of this chunk in its header, as if all chunks were sorted in increasing line order.
+    /// Use this index to write a chunk that was read from another file
+    /// with `ChunksWriter::write_chunk`, without decompressing it.
+    pub fn index_in_header_increasing_y(&self, headers: &[Header]) -> Result<usize> {
+        self.header(headers)?.chunk_index_increasing_y(self.tile_coordinates(headers)?)
+    }
+
+    /// The location of the pixels of this chunk in the image.
+    pub fn block_index(&self, headers: &[Header]) -> Result<BlockIndex> {
+        Ok(self.pixel_section(headers)?.0)
+    }
+
+    /// The number of bytes that the pixels of this chunk occupy after decompression.
+    pub fn decompressed_byte_size(&self, headers: &[Header]) -> Result<usize> {
+        let block = self.block_index(headers)?;
+        Ok(block.pixel_size.area() * self.header(headers)?.channels.bytes_per_pixel)
+    }
+
+    /// The location of the pixels of this chunk in the image,
+    /// and the validated absolute pixel bounds of this chunk in its layer.
+    pub(crate) fn pixel_section(&self, headers: &[Header]) -> Result<(BlockIndex, IntegerBounds)> {
+        let header = self.header(headers)?;
+        let tile_data_indices = header.get_block_data_indices(&self.compressed_block)?;
+        let absolute_indices = header.get_absolute_block_pixel_coordinates(tile_data_indices)?;
+
+        absolute_indices.validate(Some(header.layer_size))?;
+
+        let block = BlockIndex {
+            layer: self.layer_index,
+            pixel_position: absolute_indices.position.to_usize("data indices start")?,
+            level: tile_data_indices.level_index,
+            pixel_size: absolute_indices.size,
+        };
+
+        Ok((block, absolute_indices))
+    }
+
     /// Without validation, write this instance to the byte stream.
     pub fn write(&self, write: &mut impl Write, header_count: usize) -> UnitResult {
         debug_assert!(self.layer_index < header_count, "layer index bug"); // validation is done in full_image or simple_image
@@ -347,6 +485,12 @@ impl Chunk {
 
     /// Read the value without validating.
     pub fn read(read: &mut impl Read, meta_data: &MetaData) -> Result<Self> {
+        Self::read_with_pool(read, meta_data, &HeapBuffers)
+    }
+
+    /// Read the value without validating.
+    /// The buffers for the compressed pixels are taken from the pool. Deep data does not use the pool.
+    pub fn read_with_pool(read: &mut impl Read, meta_data: &MetaData, pool: &dyn BlockBufferPool) -> Result<Self> {
         let layer_number = i32_to_usize(
             if meta_data.requirements.is_multilayer() { i32::read(read)? } // documentation says u64, but is i32
             else { 0_i32 }, // reference the first header for single-layer images
@@ -357,6 +501,8 @@ impl Chunk {
             return Err(Error::invalid("chunk data part number"));
         }
 
+        trace_span!(TRACE, "read chunk", layer = layer_number);
+
         let header = &meta_data.headers[layer_number];
         let max_block_byte_size = header.max_block_byte_size();
 
@@ -364,8 +510,8 @@ impl Chunk {
             layer_index: layer_number,
             compressed_block: match header.blocks {
                 // flat data
-                BlockDescription::ScanLines if !header.deep => CompressedBlock::ScanLine(CompressedScanLineBlock::read(read, max_block_byte_size)?),
-                BlockDescription::Tiles(_) if !header.deep     => CompressedBlock::Tile(CompressedTileBlock::read(read, max_block_byte_size)?),
+                BlockDescription::ScanLines if !header.deep => CompressedBlock::ScanLine(CompressedScanLineBlock::read_with_pool(read, max_block_byte_size, pool)?),
+                BlockDescription::Tiles(_) if !header.deep     => CompressedBlock::Tile(CompressedTileBlock::read_with_pool(read, max_block_byte_size, pool)?),
 
                 // deep data
                 BlockDescription::ScanLines   => CompressedBlock::DeepScanLine(CompressedDeepScanLineBlock::read(read, max_block_byte_size)?),
@@ -377,3 +523,129 @@ impl Chunk {
     }
 }
 
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::meta::attribute::*;
+    use crate::compression::Compression;
+    use crate::math::RoundingMode;
+    use smallvec::smallvec;
+
+    fn deep_header(compression: Compression, blocks: BlockDescription) -> Header {
+        let channels = smallvec![ ChannelDescription::named("Z", SampleType::F32) ];
+        let mut header = Header::new(Text::from("deep"), (5, 3), channels)
+            .with_encoding(compression, blocks, LineOrder::Increasing);
+
+        header.deep = true;
+        header
+    }
+
+    fn offset_table(compression: Compression, offsets: &[i32]) -> Vec<i8> {
+        let bytes: Vec<u8> = offsets.iter().flat_map(|offset| offset.to_le_bytes()).collect();
+        compression.compress_deep_offset_table(&bytes).unwrap().into_iter().map(|byte| byte as i8).collect()
+    }
+
+    #[test]
+    fn copy_raw_chunks() {
+        use crate::prelude::*;
+        use crate::block::writer::ChunksWriter;
+        use std::io::Cursor;
+
+        let size = Vec2(70, 40);
+        let tiles = Blocks::Tiles(Vec2(16, 16));
+        let encoding = Encoding { compression: Compression::ZIP16, blocks: tiles, line_order: LineOrder::Decreasing };
+        let channels = SpecificChannels::rgb(|position: Vec2<usize>| (position.x() as f32, position.y() as f32, 0.5_f32));
+        let image = Image::from_encoded_channels(size, encoding, channels);
+
+        let mut original = Vec::new();
+        image.write().non_parallel().to_buffered(Cursor::new(&mut original)).unwrap();
+
+        let reader = crate::block::read(Cursor::new(&original), true).unwrap();
+        let headers = reader.headers().to_vec();
+        let chunks = reader.all_chunks(true).unwrap();
+
+        let mut copy = Vec::new();
+        crate::block::write(Cursor::new(&mut copy), headers.into(), true, |meta, writer| {
+            for chunk in chunks {
+                let chunk = chunk?;
+                let index = chunk.index_in_header_increasing_y(&meta.headers)?;
+                writer.write_chunk(index, chunk)?;
+            }
+
+            Ok(())
+        }).unwrap();
+
+        let read_image = |bytes: &[u8]| read().no_deep_data().largest_resolution_level().all_channels()
+            .first_valid_layer().all_attributes().non_parallel().from_buffered(Cursor::new(bytes)).unwrap();
+
+        assert_eq!(read_image(&original), read_image(&copy));
+    }
+
+    #[test]
+    fn chunk_index_of_mip_map_tile() {
+        let tiles = TileDescription { tile_size: Vec2(4, 4), level_mode: LevelMode::MipMap, rounding_mode: RoundingMode::Down };
+        let header = deep_header(Compression::RLE, BlockDescription::Tiles(tiles));
+
+        for (index, tile) in header.blocks_increasing_y_order().enumerate() {
+            assert_eq!(header.chunk_index_increasing_y(tile.location).unwrap(), index);
+        }
+
+        let outside = TileCoordinates { tile_index: Vec2(2, 0), level_index: Vec2(0, 0) };
+        assert!(header.chunk_index_increasing_y(outside).is_err());
+    }
+
+    #[test]
+    fn deep_tile_sample_counts_of_smaller_level() {
+        let tiles = TileDescription { tile_size: Vec2(4, 4), level_mode: LevelMode::MipMap, rounding_mode: RoundingMode::Down };
+        let header = deep_header(Compression::RLE, BlockDescription::Tiles(tiles));
+
+        // level 1 has a resolution of 2x1 pixels
+        let block = CompressedDeepTileBlock {
+            coordinates: TileCoordinates { tile_index: Vec2(0, 0), level_index: Vec2(1, 1) },
+            compressed_pixel_offset_table: offset_table(Compression::RLE, &[ 2, 3 ]),
+            decompressed_sample_data_size: 3 * 4,
+            compressed_sample_data: vec![ 0; 12 ],
+        };
+
+        assert_eq!(block.sample_counts(&header, true).unwrap(), vec![ 2, 1 ]);
+
+        let chunk = CompressedBlock::DeepTile(block.clone());
+        assert_eq!(header.get_block_data_indices(&chunk).unwrap(), block.coordinates);
+
+        let wrong_size = CompressedDeepTileBlock { decompressed_sample_data_size: 4, .. block };
+        assert!(wrong_size.sample_counts(&header, true).is_err());
+    }
+
+    #[test]
+    fn deep_scan_line_sample_counts_accumulate_per_row() {
+        let header = deep_header(Compression::ZIP1, BlockDescription::ScanLines);
+
+        let block = CompressedDeepScanLineBlock {
+            y_coordinate: 2,
+            compressed_pixel_offset_table: offset_table(Compression::ZIP1, &[ 0, 1, 1, 4, 5 ]),
+            decompressed_sample_data_size: 5 * 4,
+            compressed_sample_data: vec![ 0; 20 ],
+        };
+
+        assert_eq!(block.sample_counts(&header, true).unwrap(), vec![ 0, 1, 0, 3, 1 ]);
+
+        let decreasing = CompressedDeepScanLineBlock {
+            compressed_pixel_offset_table: offset_table(Compression::ZIP1, &[ 0, 2, 1, 4, 5 ]),
+            .. block
+        };
+
+        assert!(decreasing.sample_counts(&header, true).is_err());
+    }
+
+    #[test]
+    fn tile_indices_do_not_overflow() {
+        let tile = TileCoordinates { tile_index: Vec2(usize::MAX / 4, 0), level_index: Vec2(0, 0) };
+        assert!(tile.to_data_indices(Vec2(16, 16), Vec2(64, 64)).is_err());
+
+        let tile = TileCoordinates { tile_index: Vec2(3, 1), level_index: Vec2(0, 0) };
+        let bounds = tile.to_data_indices(Vec2(16, 16), Vec2(60, 64)).unwrap();
+        assert_eq!(bounds, IntegerBounds::new(Vec2(48, 16), Vec2(12, 16)));
+    }
+}
@@ -68,70 +68,76 @@ impl LineIndex {
     /// Iterates the lines of this block index in interleaved fashion:
     /// For each line in this block, this iterator steps once through each channel.
     /// This is how lines are stored in a pixel data block.
+    /// Subsampled channels only contain a line in every n-th row, and fewer samples in each line.
     ///
     /// Does not check whether `self.layer_index`, `self.level`, `self.size` and `self.position` are valid indices.__
     // TODO be sure this cannot produce incorrect data, as this is not further checked but only handled with panics
     #[inline]
     #[must_use]
     pub fn lines_in_block(block: BlockIndex, channels: &ChannelList) -> impl Iterator<Item=(Range<usize>, LineIndex)> {
+        /// The sample count, the byte size, and the vertical sampling rate of the lines of a channel.
+        type ChannelLines = (usize, usize, usize);
+
         struct LineIter {
-            layer: usize, level: Vec2<usize>, width: usize,
-            end_y: usize, x: usize, channel_sizes: SmallVec<[usize; 8]>,
+            layer: usize, level: Vec2<usize>,
+            end_y: usize, x: usize, channels: SmallVec<[ChannelLines; 8]>,
             byte: usize, channel: usize, y: usize,
         }
 
-        // FIXME what about sub sampling??
-
         impl Iterator for LineIter {
             type Item = (Range<usize>, LineIndex);
             // TODO size hint?
 
             fn next(&mut self) -> Option<Self::Item> {
-                if self.y < self.end_y {
-
-                    // compute return value before incrementing
-                    let byte_len = self.channel_sizes[self.channel];
-                    let return_value = (
-                        (self.byte .. self.byte + byte_len),
-                        LineIndex {
-                            channel: self.channel,
-                            layer: self.layer,
-                            level: self.level,
-                            position: Vec2(self.x, self.y),
-                            sample_count: self.width,
-                        }
-                    );
+                while self.y < self.end_y && !self.channels.is_empty() {
+                    let (channel, y) = (self.channel, self.y);
+                    let (sample_count, byte_len, sampling_y) = self.channels[channel];
 
                     { // increment indices
-                        self.byte += byte_len;
                         self.channel += 1;
 
-                        if self.channel == self.channel_sizes.len() {
+                        if self.channel == self.channels.len() {
                             self.channel = 0;
                             self.y += 1;
                         }
                     }
 
-                    Some(return_value)
-                }
+                    // the data window starts at a multiple of the sampling rate,
+                    // so the rows can be counted from the start of the layer
+                    if y % sampling_y != 0 { continue; }
+
+                    let return_value = (
+                        (self.byte .. self.byte + byte_len),
+                        LineIndex {
+                            channel,
+                            layer: self.layer,
+                            level: self.level,
+                            position: Vec2(self.x, y),
+                            sample_count,
+                        }
+                    );
 
-                else {
-                    None
+                    self.byte += byte_len;
+                    return Some(return_value);
                 }
+
+                None
             }
         }
 
-        let channel_line_sizes: SmallVec<[usize; 8]> = channels.list.iter()
-            .map(move |channel| block.pixel_size.0 * channel.sample_type.bytes_per_sample()) // FIXME is it fewer samples per tile or just fewer tiles for sampled images???
+        let channel_lines: SmallVec<[ChannelLines; 8]> = channels.list.iter()
+            .map(move |channel| {
+                let sample_count = block.pixel_size.width() / channel.sampling.x();
+                (sample_count, sample_count * channel.sample_type.bytes_per_sample(), channel.sampling.y())
+            })
             .collect();
 
         LineIter {
             layer: block.layer,
             level: block.level,
-            width: block.pixel_size.0,
             x: block.pixel_position.0,
             end_y: block.pixel_position.y() + block.pixel_size.height(),
-            channel_sizes: channel_line_sizes,
+            channels: channel_lines,
 
             byte: 0,
             channel: 0,
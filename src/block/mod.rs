@@ -6,6 +6,11 @@
 //!
 //! Start with the `block::read(...)`
 //! and `block::write(...)` functions.
+//!
+//! To move chunks from one file to another without decompressing any pixels,
+//! for example in an archiver, read them with `block::read(...).all_chunks(...)`,
+//! and pass each chunk to `ChunksWriter::write_chunk` inside `block::write(...)`,
+//! using `Chunk::index_in_header_increasing_y` to compute the required chunk index.
 
 
 pub mod writer;
@@ -14,6 +19,15 @@ pub mod reader;
 pub mod lines;
 pub mod samples;
 pub mod chunk;
+pub mod pool;
+pub mod source;
+pub mod cache;
+
+#[cfg(any(unix, windows))]
+pub mod unbuffered;
+
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub mod uring;
 
 
 use std::io::{Read, Seek, Write};
@@ -24,8 +38,36 @@ use crate::compression::ByteVec;
 use crate::block::chunk::{CompressedBlock, CompressedTileBlock, CompressedScanLineBlock, Chunk, TileCoordinates};
 use crate::meta::header::Header;
 use crate::block::lines::{LineIndex, LineRef, LineSlice, LineRefMut};
-use crate::meta::attribute::ChannelList;
+use crate::meta::attribute::{ChannelList, LineOrder};
+use crate::block::pool::{BlockBufferPool, HeapBuffers};
+use crate::block::writer::ChunksWriter;
+use smallvec::{SmallVec, smallvec};
+
+
+/// The environment variable that limits the number of threads which compress or decompress blocks,
+/// unless a thread count is specified in the code, for example `EXR_THREADS=4`.
+pub const THREAD_COUNT_VARIABLE: &str = "EXR_THREADS";
+
+/// The number of threads which compress or decompress blocks, unless a thread count is specified in the code.
+/// This is the value of the environment variable `EXR_THREADS`, if it contains a positive number,
+/// and the number of cpus otherwise.
+pub fn default_thread_count() -> usize {
+    let variable = std::env::var(THREAD_COUNT_VARIABLE).ok();
+
+    parse_thread_count(variable.as_deref())
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |threads| threads.get()))
+}
+
+/// The thread count in the value of the environment variable, if it contains a positive number.
+fn parse_thread_count(variable: Option<&str>) -> Option<usize> {
+    variable
+        .and_then(|count| count.trim().parse::<usize>().ok())
+        .filter(|&count| count > 0)
+}
 
+/// For each layer, one flag for each channel in the channel list of the layer,
+/// specifying whether the samples of that channel need to be decompressed.
+pub type RequestedChannels = Vec<SmallVec<[bool; 8]>>;
 
 /// Specifies where a block of pixel data should be placed in the actual image.
 /// This is a globally unique identifier which
@@ -66,7 +108,8 @@ pub struct UncompressedBlock {
 /// From the reader, you can pull each compressed chunk from the file.
 /// Alternatively, you can create a decompressor, and pull the uncompressed data from it.
 /// The reader is assumed to be buffered.
-pub fn read<R: Read + Seek>(buffered_read: R, pedantic: bool) -> Result<self::reader::Reader<R>> {
+/// Reading only some of the chunks requires the reader to be `Seek`, but reading all chunks does not.
+pub fn read<R: Read>(buffered_read: R, pedantic: bool) -> Result<self::reader::Reader<R>> {
     self::reader::Reader::read_from_buffered(buffered_read, pedantic)
 }
 
@@ -82,7 +125,95 @@ pub fn write<W: Write + Seek>(
     self::writer::write_chunks_with(buffered_write, headers, compatibility_checks, write_chunks)
 }
 
+/// Write a single scan line layer from scan lines whose samples are already packed as in the file,
+/// without converting any pixels. Use this if your pixels are already stored in the exr layout.
+/// Each item contains all samples of one scan line, from top to bottom:
+/// first all samples of the first channel, then all samples of the next channel,
+/// in the order of `header.channels.list`, which is sorted by channel name.
+/// The samples are stored as little-endian `f16`, `f32`, or `u32` values, as specified by each channel, like in the file,
+/// for example created with `f32::to_le_bytes`. The bytes are written unchanged on all machines.
+/// Compresses the blocks on multiple threads where the layer is compressed.
+/// Tiles, deep data, subsampled channels, and decreasing line order are not supported.
+/// The writer is assumed to be buffered.
+pub fn write_packed_scan_lines<W: Write + Seek>(
+    buffered_write: W, header: Header, compatibility_checks: bool,
+    packed_lines: impl IntoIterator<Item = impl AsRef<[u8]>>
+) -> UnitResult {
+    if header.deep { return Err(Error::unsupported("packed scan lines of deep data")); }
+    if header.line_order == LineOrder::Decreasing { return Err(Error::unsupported("packed scan lines in decreasing line order")); }
 
+    if let BlockDescription::Tiles(_) = header.blocks {
+        return Err(Error::unsupported("packed scan lines of a tiled layer"));
+    }
+
+    if header.channels.list.iter().any(|channel| channel.sampling != Vec2(1, 1)) {
+        return Err(Error::unsupported("packed scan lines of subsampled channels"));
+    }
+
+    let line_byte_size = header.layer_size.width() * header.channels.bytes_per_pixel;
+    let mut packed_lines = packed_lines.into_iter();
+
+    write(buffered_write, smallvec![ header ], compatibility_checks, |meta, chunk_writer| {
+        let header = &meta.headers[0];
+
+        // collect as many lines as each block contains, in increasing y order
+        let mut next_block = header.blocks_increasing_y_order().enumerate().map(|(index_in_header, tile)| {
+            let index = header.get_block_index(0, tile.location)?;
+            let mut data = Vec::with_capacity(index.pixel_size.height() * line_byte_size);
+
+            for _ in 0 .. index.pixel_size.height() {
+                let line = packed_lines.next().ok_or_else(|| Error::invalid("too few packed scan lines"))?;
+                let line = line.as_ref();
+
+                if line.len() != line_byte_size { return Err(Error::invalid("packed scan line byte size")); }
+                data.extend_from_slice(line);
+            }
+
+            Ok((index_in_header, UncompressedBlock { index, data }))
+        });
+
+        match chunk_writer.parallel_blocks_compressor(&meta) {
+            Some(mut compressor) => for block in &mut next_block {
+                let (index_in_header, block) = block?;
+                compressor.add_block_to_compression_queue(index_in_header, block)?;
+            },
+
+            None => {
+                let mut compressor = chunk_writer.sequential_blocks_compressor(&meta);
+                for block in &mut next_block {
+                    let (index_in_header, block) = block?;
+                    compressor.compress_block(index_in_header, block)?;
+                }
+            },
+        }
+
+        drop(next_block);
+        if packed_lines.next().is_some() { return Err(Error::invalid("too many packed scan lines")); }
+        Ok(())
+    })
+}
+
+
+
+/// Decompress the pixels of a chunk directly into a buffer that is supplied by the caller,
+/// instead of allocating a new `UncompressedBlock`. Returns the location of the pixels in the image.
+/// The target must have exactly the size returned by `chunk.decompressed_byte_size(headers)`.
+/// The pixels are stored in the target with the same layout as in `UncompressedBlock::data`.
+/// See `Compression::decompress_image_section_into` for the compression methods that do not allocate at all.
+pub fn decode_chunk_into(chunk: &Chunk, meta_data: &MetaData, pedantic: bool, target: &mut [u8]) -> Result<BlockIndex> {
+    let header: &Header = chunk.header(&meta_data.headers)?;
+    let (index, absolute_indices) = chunk.pixel_section(&meta_data.headers)?;
+
+    match chunk.compressed_block {
+        CompressedBlock::Tile(CompressedTileBlock { ref compressed_pixels, .. }) |
+        CompressedBlock::ScanLine(CompressedScanLineBlock { ref compressed_pixels, .. }) => {
+            header.compression.decompress_image_section_into(header, compressed_pixels, absolute_indices, pedantic, target)?;
+            Ok(index)
+        },
+
+        _ => Err(Error::unsupported("deep data not supported yet"))
+    }
+}
 
 
 /// This iterator tells you the block indices of all blocks that must be in the image.
@@ -94,15 +225,7 @@ pub fn write<W: Write + Seek>(
 pub fn enumerate_ordered_header_block_indices(headers: &[Header]) -> impl '_ + Iterator<Item=(usize, BlockIndex)> {
     headers.iter().enumerate().flat_map(|(layer_index, header)|{
         header.enumerate_ordered_blocks().map(move |(index_in_header, tile)|{
-            let data_indices = header.get_absolute_block_pixel_coordinates(tile.location).expect("tile coordinate bug");
-
-            let block = BlockIndex {
-                layer: layer_index,
-                level: tile.location.level_index,
-                pixel_position: data_indices.position.to_usize("data indices start").expect("data index bug"),
-                pixel_size: data_indices.size,
-            };
-
+            let block = header.get_block_index(layer_index, tile.location).expect("tile coordinate bug");
             (index_in_header, block)
         })
     })
@@ -116,26 +239,46 @@ impl UncompressedBlock {
     #[inline]
     #[must_use]
     pub fn decompress_chunk(chunk: Chunk, meta_data: &MetaData, pedantic: bool) -> Result<Self> {
-        let header: &Header = meta_data.headers.get(chunk.layer_index)
-            .ok_or(Error::invalid("chunk layer index"))?;
+        Self::decompress_chunk_with_pool(chunk, meta_data, pedantic, &HeapBuffers)
+    }
 
-        let tile_data_indices = header.get_block_data_indices(&chunk.compressed_block)?;
-        let absolute_indices = header.get_absolute_block_pixel_coordinates(tile_data_indices)?;
+    /// Decompress the possibly compressed chunk and returns an `UncompressedBlock`.
+    /// The compressed buffer of the chunk is given back to the pool, unless it is reused for the uncompressed block.
+    #[must_use]
+    pub fn decompress_chunk_with_pool(chunk: Chunk, meta_data: &MetaData, pedantic: bool, pool: &dyn BlockBufferPool) -> Result<Self> {
+        Self::decompress_chunk_channels_with_pool(chunk, meta_data, pedantic, pool, None)
+    }
 
-        absolute_indices.validate(Some(header.layer_size))?;
+    /// Decompress the possibly compressed chunk, like `decompress_chunk_with_pool`,
+    /// but only reconstruct the samples of the requested channels of the layer, where the compression method allows it.
+    /// The bytes of the other channels in the block contain arbitrary values.
+    /// Without requested channels, or if the layer is not contained in the requested channels, all channels are decompressed.
+    #[must_use]
+    pub fn decompress_chunk_channels_with_pool(
+        chunk: Chunk, meta_data: &MetaData, pedantic: bool, pool: &dyn BlockBufferPool,
+        requested_channels: Option<&RequestedChannels>
+    ) -> Result<Self> {
+        let header: &Header = chunk.header(&meta_data.headers)?;
+        let layer_channels = requested_channels.and_then(|requested| requested.get(chunk.layer_index));
+        let (index, absolute_indices) = chunk.pixel_section(&meta_data.headers)?;
+
+        trace_span!(
+            TRACE, "decompress block", layer = index.layer, level = ?index.level,
+            x = index.pixel_position.x(), y = index.pixel_position.y(), compression = ?header.compression
+        );
 
         match chunk.compressed_block {
             CompressedBlock::Tile(CompressedTileBlock { compressed_pixels, .. }) |
             CompressedBlock::ScanLine(CompressedScanLineBlock { compressed_pixels, .. }) => {
-                Ok(UncompressedBlock {
-                    data: header.compression.decompress_image_section(header, compressed_pixels, absolute_indices, pedantic)?,
-                    index: BlockIndex {
-                        layer: chunk.layer_index,
-                        pixel_position: absolute_indices.position.to_usize("data indices start")?,
-                        level: tile_data_indices.level_index,
-                        pixel_size: absolute_indices.size,
-                    }
-                })
+                let data = match layer_channels {
+                    Some(channels) => header.compression.decompress_image_section_channels_with_pool(
+                        header, compressed_pixels, absolute_indices, pedantic, pool, channels
+                    )?,
+
+                    None => header.compression.decompress_image_section_with_pool(header, compressed_pixels, absolute_indices, pedantic, pool)?,
+                };
+
+                Ok(UncompressedBlock { data, index })
             },
 
             _ => return Err(Error::unsupported("deep data not supported yet"))
@@ -152,6 +295,11 @@ impl UncompressedBlock {
         let header: &Header = headers.get(index.layer)
             .expect("block layer index bug");
 
+        trace_span!(
+            TRACE, "compress block", layer = index.layer, level = ?index.level,
+            x = index.pixel_position.x(), y = index.pixel_position.y(), compression = ?header.compression
+        );
+
         let expected_byte_size = header.channels.bytes_per_pixel * self.index.pixel_size.area(); // TODO sampling??
         if expected_byte_size != data.len() {
             panic!("get_line byte size should be {} but was {}", expected_byte_size, data.len());
@@ -204,10 +352,18 @@ impl UncompressedBlock {
             .map(move |(bytes, line)| LineSlice { location: line, value: &self.data[bytes] })
     }
 
-    /* TODO pub fn lines_mut<'s>(&'s mut self, header: &Header) -> impl 's + Iterator<Item=LineRefMut<'s>> {
-        LineIndex::lines_in_block(self.index, &header.channels)
-            .map(move |(bytes, line)| LineSlice { location: line, value: &mut self.data[bytes] })
-    }*/
+    /// Iterate all the lines in this block, allowing the samples to be modified in place.
+    /// Each line contains the all samples for one of the channels.
+    pub fn lines_mut(&mut self, channels: &ChannelList) -> impl Iterator<Item=LineRefMut<'_>> {
+        let mut remaining_bytes = self.data.as_mut_slice();
+
+        // the lines are stored one after another, so each line is split off the start of the remaining bytes
+        LineIndex::lines_in_block(self.index, channels).map(move |(bytes, line)| {
+            let (value, rest) = std::mem::take(&mut remaining_bytes).split_at_mut(bytes.len());
+            remaining_bytes = rest;
+            LineSlice { location: line, value }
+        })
+    }
 
     /*// TODO make iterator
     /// Call a closure for each line of samples in this uncompressed block.
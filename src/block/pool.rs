@@ -0,0 +1,157 @@
+//! Reuse the byte buffers of blocks, instead of allocating new buffers for each chunk.
+//!
+//! Reading an image allocates a buffer for the compressed bytes of each chunk,
+//! and another buffer for the decompressed bytes of each chunk.
+//! Long-running processes that read many images can pass a `BlockBufferPool`
+//! to `Reader::with_buffer_pool` to reuse these buffers, avoiding heap fragmentation.
+//! After decompressing a chunk, its compressed buffer is returned to the pool.
+//! After inserting a decompressed block into an image, return its bytes with `pool.recycle(block.data)`,
+//! such that they can be reused for reading the next chunk.
+//!
+//! The pool provides the compressed buffers of all flat chunks.
+//! It provides the decompressed buffers of uncompressed, RLE, and ZIP compressed chunks,
+//! while the other compression methods still allocate their own buffers.
+//! Writing an image does not use a pool.
+
+use std::fmt::Debug;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::compression::ByteVec;
+
+
+/// Provides the byte buffers for the chunks of an image, and takes them back when they are not used anymore.
+/// Must be shareable across threads, as chunks may be decompressed in parallel.
+pub trait BlockBufferPool: Debug + Send + Sync {
+
+    /// Return an empty buffer with a capacity of at least `byte_size` bytes.
+    fn take(&self, byte_size: usize) -> ByteVec;
+
+    /// Give back a buffer that is not used anymore.
+    /// The pool may keep the buffer for a later call to `take`, or drop it.
+    fn recycle(&self, buffer: ByteVec);
+}
+
+/// Allocates a new buffer for every chunk, and drops all recycled buffers.
+/// This is the default behaviour when no pool is specified.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HeapBuffers;
+
+impl BlockBufferPool for HeapBuffers {
+    fn take(&self, byte_size: usize) -> ByteVec { Vec::with_capacity(byte_size) }
+    fn recycle(&self, _: ByteVec) {}
+}
+
+/// Keeps a limited number of recycled buffers, and reuses them for later chunks.
+/// Counts the allocations, such that the allocation pressure can be measured.
+#[derive(Debug)]
+pub struct RecyclingBufferPool {
+    buffers: Mutex<Vec<ByteVec>>,
+    max_buffer_count: usize,
+
+    allocations: AtomicUsize,
+    allocated_bytes: AtomicUsize,
+    reuses: AtomicUsize,
+}
+
+/// How many buffers a `RecyclingBufferPool` had to allocate, and how many buffers it could reuse.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct BufferPoolStatistics {
+
+    /// The number of buffers that were newly allocated or grown.
+    pub allocations: usize,
+
+    /// The sum of the byte capacities that were allocated.
+    pub allocated_bytes: usize,
+
+    /// The number of buffers that were taken from the pool without allocating.
+    pub reuses: usize,
+}
+
+impl RecyclingBufferPool {
+
+    /// Create an empty pool, which keeps at most `max_buffer_count` recycled buffers.
+    /// Use about one buffer for each thread, plus the number of prefetched chunks.
+    pub fn new(max_buffer_count: usize) -> Self {
+        RecyclingBufferPool {
+            buffers: Mutex::new(Vec::with_capacity(max_buffer_count)),
+            max_buffer_count,
+            allocations: AtomicUsize::new(0),
+            allocated_bytes: AtomicUsize::new(0),
+            reuses: AtomicUsize::new(0),
+        }
+    }
+
+    /// The number of allocations and reuses so far.
+    pub fn statistics(&self) -> BufferPoolStatistics {
+        BufferPoolStatistics {
+            allocations: self.allocations.load(Ordering::Relaxed),
+            allocated_bytes: self.allocated_bytes.load(Ordering::Relaxed),
+            reuses: self.reuses.load(Ordering::Relaxed),
+        }
+    }
+
+    /// The number of recycled buffers that are currently waiting to be reused.
+    pub fn available_buffer_count(&self) -> usize {
+        self.buffers.lock().expect("buffer pool lock poisoned").len()
+    }
+}
+
+impl BlockBufferPool for RecyclingBufferPool {
+    fn take(&self, byte_size: usize) -> ByteVec {
+        let recycled = self.buffers.lock().expect("buffer pool lock poisoned").pop();
+
+        match recycled {
+            Some(buffer) if buffer.capacity() >= byte_size => {
+                self.reuses.fetch_add(1, Ordering::Relaxed);
+                buffer
+            },
+
+            // grow the smaller buffer, which may be able to extend its allocation in place
+            Some(mut buffer) => {
+                buffer.reserve_exact(byte_size);
+                self.allocations.fetch_add(1, Ordering::Relaxed);
+                self.allocated_bytes.fetch_add(buffer.capacity(), Ordering::Relaxed);
+                buffer
+            },
+
+            None => {
+                self.allocations.fetch_add(1, Ordering::Relaxed);
+                self.allocated_bytes.fetch_add(byte_size, Ordering::Relaxed);
+                Vec::with_capacity(byte_size)
+            },
+        }
+    }
+
+    fn recycle(&self, mut buffer: ByteVec) {
+        if buffer.capacity() == 0 { return; }
+
+        let mut buffers = self.buffers.lock().expect("buffer pool lock poisoned");
+        if buffers.len() < self.max_buffer_count {
+            buffer.clear();
+            buffers.push(buffer);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reuse_recycled_buffers() {
+        let pool = RecyclingBufferPool::new(1);
+
+        let mut buffer = pool.take(64);
+        buffer.extend_from_slice(&[ 7; 64 ]);
+        pool.recycle(buffer);
+        pool.recycle(vec![ 0; 8 ]); // pool is full, buffer is dropped
+        assert_eq!(pool.available_buffer_count(), 1);
+
+        let buffer = pool.take(32);
+        assert!(buffer.is_empty(), "recycled buffers are cleared");
+        assert!(buffer.capacity() >= 64);
+
+        assert_eq!(pool.statistics(), BufferPoolStatistics { allocations: 1, allocated_bytes: 64, reuses: 1 });
+    }
+}
@@ -4,16 +4,22 @@
 use std::convert::TryFrom;
 use std::fmt::Debug;
 use std::io::{Read, Seek};
+use std::ops::Range;
+use std::collections::{BTreeMap, VecDeque};
 
 use smallvec::alloc::sync::Arc;
 
-use crate::block::{BlockIndex, UncompressedBlock};
+use crate::block::{BlockIndex, UncompressedBlock, RequestedChannels, default_thread_count};
 use crate::block::chunk::{Chunk, TileCoordinates};
 use crate::compression::Compression;
-use crate::error::{Error, Result, u64_to_usize, UnitResult};
+use crate::error::{Error, Result, UnitResult};
 use crate::io::{PeekRead, Tracking};
-use crate::meta::{MetaData, OffsetTables};
+use crate::meta::{MetaData, OffsetTables, BlockDescription};
 use crate::meta::header::Header;
+use crate::meta::attribute::LineOrder;
+use crate::io::Data;
+use crate::block::pool::{BlockBufferPool, HeapBuffers};
+use crate::compression::filter::SampleFilter;
 
 /// Decode the meta data from a byte source, keeping the source ready for further reading.
 /// Continue decoding the remaining bytes by calling `filtered_chunks` or `all_chunks`.
@@ -21,17 +27,38 @@ use crate::meta::header::Header;
 pub struct Reader<R> {
     meta_data: MetaData,
     remaining_reader: PeekRead<Tracking<R>>, // TODO does R need to be Seek or is Tracking enough?
+    buffer_pool: Arc<dyn BlockBufferPool>,
 }
 
-impl<R: Read + Seek> Reader<R> {
+impl<R: Read> Reader<R> {
 
     /// Start the reading process.
     /// Immediately decodes the meta data into an internal field.
     /// Access it via`meta_data()`.
     pub fn read_from_buffered(read: R, pedantic: bool) -> Result<Self> {
+        trace_span!(DEBUG, "read meta data", pedantic);
+
         let mut remaining_reader = PeekRead::new(Tracking::new(read));
         let meta_data = MetaData::read_validated_from_buffered_peekable(&mut remaining_reader, pedantic)?;
-        Ok(Self { meta_data, remaining_reader })
+        Ok(Self { meta_data, remaining_reader, buffer_pool: Arc::new(HeapBuffers) })
+    }
+
+    /// Take the buffers for the compressed bytes of each chunk from the specified pool,
+    /// instead of allocating new buffers for each chunk. RLE and ZIP chunks are also decompressed into buffers from the pool.
+    /// The decompressors give the compressed buffers back to this pool after decompressing each chunk.
+    /// See the `block::pool` module for more details.
+    pub fn with_buffer_pool(self, buffer_pool: Arc<dyn BlockBufferPool>) -> Self {
+        Self { buffer_pool, ..self }
+    }
+
+    /// Revert these custom sample filters when decompressing, in addition to the built-in filters.
+    /// Decompressing a block that uses an unknown filter fails. See the `compression::filter` module.
+    pub fn with_sample_filters(mut self, custom_filters: &[Arc<dyn SampleFilter>]) -> Self {
+        for header in &mut self.meta_data.headers {
+            header.own_attributes.sample_filters.resolve_custom_filters(custom_filters);
+        }
+
+        self
     }
 
     // must not be mutable, as reading the file later on relies on the meta data
@@ -47,6 +74,7 @@ impl<R: Read + Seek> Reader<R> {
     /// Prepare to read all the chunks from the file.
     /// Does not decode the chunks now, but returns a decoder.
     /// Reading all chunks reduces seeking the file, but some chunks might be read without being used.
+    /// Does not require seeking, so this can also read a stream, for example from standard input.
     pub fn all_chunks(mut self, pedantic: bool) -> Result<AllChunksReader<R>> {
         let total_chunk_count = {
             if pedantic {
@@ -64,33 +92,48 @@ impl<R: Read + Seek> Reader<R> {
             meta_data: self.meta_data,
             remaining_chunks: 0 .. total_chunk_count,
             remaining_bytes: self.remaining_reader,
+            buffer_pool: self.buffer_pool,
             pedantic
         })
     }
+}
+
+impl<R: Read + Seek> Reader<R> {
+
+    /// Read the offset tables and check them for inconsistencies, without reading any chunks.
+    /// Afterwards, the chunks can still be read from this reader.
+    /// See `inspect_offset_tables` for more details.
+    pub fn inspect_offset_tables(&mut self) -> Result<Vec<OffsetTableWarning>> {
+        let offset_tables_start_byte = self.remaining_reader.byte_position();
+        let offset_tables = MetaData::read_offset_tables(&mut self.remaining_reader, &self.meta_data.headers)?;
+        let warnings = inspect_offset_tables(&self.meta_data.headers, &offset_tables, self.remaining_reader.byte_position());
+
+        self.remaining_reader.skip_to(offset_tables_start_byte)?;
+        Ok(warnings)
+    }
 
     /// Prepare to read some the chunks from the file.
     /// Does not decode the chunks now, but returns a decoder.
     /// Reading only some chunks may seeking the file, potentially skipping many bytes.
+    /// If the offset tables contain only zeroes, the offsets are reconstructed by reading all chunks first.
+    /// The chunks that pass the filter are kept in memory while doing so,
+    /// such that they are not read twice, and such that a stream never needs to seek back.
+    /// If `pedantic` is true, returns an error for the first of the `inspect_offset_tables` warnings
+    /// of the (possibly reconstructed) offset tables.
     // TODO tile indices add no new information to block index??
     pub fn filter_chunks(mut self, pedantic: bool, mut filter: impl FnMut(&MetaData, TileCoordinates, BlockIndex) -> bool) -> Result<FilteredChunksReader<R>> {
         let offset_tables = MetaData::read_offset_tables(&mut self.remaining_reader, &self.meta_data.headers)?;
+        let chunks_start_byte = self.remaining_reader.byte_position();
 
-        // TODO regardless of pedantic, if invalid, read all chunks instead, and filter after reading each chunk?
-        if pedantic {
-            validate_offset_tables(
-                self.meta_data.headers.as_slice(), &offset_tables,
-                self.remaining_reader.byte_position()
-            )?;
-        }
-
-        let mut filtered_offsets = Vec::with_capacity(
-            (self.meta_data.headers.len() * 32).min(2*2048)
-        );
+        // for each layer, whether each chunk is desired, in increasing y order
+        let mut filtered_chunks: Vec<Vec<bool>> = Vec::with_capacity(self.meta_data.headers.len());
 
         // TODO detect whether the filter actually would skip chunks, and aviod sorting etc when not filtering is applied
 
         for (header_index, header) in self.meta_data.headers.iter().enumerate() { // offset tables are stored same order as headers
-            for (block_index, tile) in header.blocks_increasing_y_order().enumerate() { // in increasing_y order
+            let mut filtered_layer_chunks = Vec::with_capacity(header.chunk_count);
+
+            for tile in header.blocks_increasing_y_order() { // in increasing_y order
                 let data_indices = header.get_absolute_block_pixel_coordinates(tile.location)?;
 
                 let block = BlockIndex {
@@ -100,43 +143,312 @@ impl<R: Read + Seek> Reader<R> {
                     pixel_size: data_indices.size,
                 };
 
-                if filter(&self.meta_data, tile.location, block) {
-                    filtered_offsets.push(offset_tables[header_index][block_index]) // safe indexing from `enumerate()`
-                }
-            };
+                filtered_layer_chunks.push(filter(&self.meta_data, tile.location, block));
+            }
+
+            filtered_chunks.push(filtered_layer_chunks);
         }
 
-        filtered_offsets.sort_unstable(); // enables reading continuously if possible (already sorted where line order increasing)
+        // files written with `OffsetTablePlacement::Zeroed` contain no offsets, so find the chunks by reading all of them
+        let mut scanned_chunks = BTreeMap::new();
+        let offset_tables = {
+            if offset_tables.iter().flatten().all(|&offset| offset == 0) { self.reconstruct_offset_tables(&filtered_chunks, &mut scanned_chunks)? }
+            else { offset_tables }
+        };
 
+        // TODO regardless of pedantic, if invalid, read all chunks instead, and filter after reading each chunk?
         if pedantic {
-            // table is sorted. if any two neighbours are equal, we have duplicates. this is invalid.
-            if filtered_offsets.windows(2).any(|pair| pair[0] == pair[1]) {
-                return Err(Error::invalid("chunk offset table"))
-            }
+            validate_offset_tables(self.meta_data.headers.as_slice(), &offset_tables, chunks_start_byte)?;
         }
 
+        let mut filtered_offsets: Vec<u64> = offset_tables.iter().zip(&filtered_chunks)
+            .flat_map(|(offsets, filtered)| offsets.iter().zip(filtered))
+
+            // an offset of zero marks a chunk that was not written yet, see `ChunkWriter::checkpoint`
+            .filter(|&(&offset, &is_filtered)| offset != 0 && is_filtered)
+            .map(|(&offset, _)| offset)
+            .collect();
+
+        filtered_offsets.sort_unstable(); // enables reading continuously if possible (already sorted where line order increasing)
+
         Ok(FilteredChunksReader {
             meta_data: self.meta_data,
             expected_filtered_chunk_count: filtered_offsets.len(),
             remaining_filtered_chunk_indices: filtered_offsets.into_iter(),
-            remaining_bytes: self.remaining_reader
+            scanned_chunks: scanned_chunks.into_iter().map(|(_offset, chunk)| chunk).collect(),
+            remaining_bytes: self.remaining_reader,
+            buffer_pool: self.buffer_pool,
+            requested_channels: None,
         })
     }
+
+    /// Find the byte position of each chunk by reading all chunks, for files without any offsets in their offset tables,
+    /// such as files written with `OffsetTablePlacement::Zeroed`. Stops at the first chunk that cannot be read,
+    /// such that the remaining chunks are treated as not written yet.
+    /// Keeps the chunks that pass the filter, by their byte position, instead of seeking back to read them again.
+    fn reconstruct_offset_tables(&mut self, filtered_chunks: &[Vec<bool>], scanned_chunks: &mut BTreeMap<u64, Chunk>) -> Result<OffsetTables> {
+        let headers = &self.meta_data.headers;
+
+        let mut offset_tables: OffsetTables = headers.iter()
+            .map(|header| vec![0; header.chunk_count]).collect();
+
+        let total_chunk_count: usize = headers.iter().map(|header| header.chunk_count).sum();
+
+        for _ in 0 .. total_chunk_count {
+            let offset = self.remaining_reader.byte_position();
+
+            let chunk = Chunk::read_with_pool(&mut self.remaining_reader, &self.meta_data, self.buffer_pool.as_ref())
+                .and_then(|chunk| Ok((chunk.index_in_header_increasing_y(headers)?, chunk)));
+
+            let (chunk_index, chunk) = match chunk {
+                Ok(chunk) => chunk,
+                Err(_) => break, // the file is incomplete or damaged from here on
+            };
+
+            let layer_index = chunk.layer_index;
+            match offset_tables.get_mut(layer_index).and_then(|table| table.get_mut(chunk_index)) {
+                Some(slot) if *slot == 0 => *slot = offset,
+                _ => break,
+            }
+
+            if filtered_chunks[layer_index][chunk_index] {
+                scanned_chunks.insert(offset, chunk);
+            }
+        }
+
+        Ok(offset_tables)
+    }
 }
 
 
-fn validate_offset_tables(headers: &[Header], offset_tables: &OffsetTables, chunks_start_byte: usize) -> UnitResult {
-    let max_pixel_bytes: usize = headers.iter() // when compressed, chunks are smaller, but never larger than max
-        .map(|header| header.max_pixel_file_bytes())
-        .sum();
+impl<R: Read + Seek> Reader<R> {
+
+    /// Prepare to read only the chunks that contain the specified lines of each scan line layer,
+    /// for example to display a horizontal slice of a huge image.
+    /// The lines are relative to the top of the data window of each layer, and are clamped to the layer height.
+    /// Instead of reading the complete offset tables, this seeks directly to the offsets of the required chunks,
+    /// such that the cost does not depend on the height of the image.
+    /// The resulting blocks may contain a few more lines than requested,
+    /// as each chunk contains a fixed number of lines, depending on the compression method.
+    /// Returns an error for tiled layers.
+    pub fn read_scanline_range(mut self, lines: Range<usize>, pedantic: bool) -> Result<FilteredChunksReader<R>> {
+        let offset_tables_start_byte = self.remaining_reader.byte_position();
+        let total_chunk_count: usize = self.meta_data.headers.iter().map(|header| header.chunk_count).sum();
+        let chunks_start_byte = offset_tables_start_byte + (total_chunk_count * u64::BYTE_SIZE) as u64;
+
+        let mut offsets = Vec::new();
+        let mut table_start_byte = offset_tables_start_byte;
+
+        for header in &self.meta_data.headers {
+            if let BlockDescription::Tiles(_) = header.blocks {
+                return Err(Error::unsupported("reading a range of scan lines from a tiled layer"));
+            }
+
+            // the offset table of a scan line layer is always sorted by increasing y coordinate
+            let lines_per_block = header.compression.scan_lines_per_block();
+            let end = lines.end.min(header.layer_size.height());
+
+            if lines.start < end {
+                let blocks = lines.start / lines_per_block .. (end + lines_per_block - 1) / lines_per_block;
+                self.remaining_reader.skip_to(table_start_byte + (blocks.start * u64::BYTE_SIZE) as u64)?;
+
+                for _ in blocks {
+                    let offset = u64::read(&mut self.remaining_reader)?;
+
+                    if pedantic && offset != 0 && offset < chunks_start_byte {
+                        return Err(Error::invalid("offset table"));
+                    }
+
+                    // an offset of zero marks a chunk that was not written yet, see `ChunkWriter::checkpoint`
+                    if offset != 0 { offsets.push(offset); }
+                }
+            }
+
+            table_start_byte += (header.chunk_count * u64::BYTE_SIZE) as u64;
+        }
+
+        offsets.sort_unstable();
+
+        Ok(FilteredChunksReader {
+            meta_data: self.meta_data,
+            expected_filtered_chunk_count: offsets.len(),
+            remaining_filtered_chunk_indices: offsets.into_iter(),
+            scanned_chunks: VecDeque::new(),
+            remaining_bytes: self.remaining_reader,
+            buffer_pool: self.buffer_pool,
+            requested_channels: None,
+        })
+    }
+}
+
+
+fn validate_offset_tables(headers: &[Header], offset_tables: &OffsetTables, chunks_start_byte: u64) -> UnitResult {
+    match inspect_offset_tables(headers, offset_tables, chunks_start_byte).first() {
+        Some(warning) => Err(Error::invalid(format!("offset table: {}", warning))),
+        None => Ok(()),
+    }
+}
+
+/// A possible inconsistency in the offset tables, which contain the byte position of each chunk in the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OffsetTableWarning {
+
+    /// The index of the layer whose offset table contains the chunk.
+    pub layer_index: usize,
+
+    /// The index of the chunk in the offset table of the layer, which is in increasing line order.
+    pub chunk_index: usize,
+
+    /// The byte position of the chunk in the file, as stored in the offset table.
+    pub offset: u64,
+
+    /// What is wrong with the offset.
+    pub problem: OffsetTableProblem,
+}
+
+/// What is wrong with an offset in the offset table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OffsetTableProblem {
+
+    /// The chunk would start before the first chunk or after the largest possible end of the file.
+    OutOfBounds,
+
+    /// The chunk is not located after the previous chunk, as specified by the line order of the layer.
+    /// Not reported for layers with unspecified line order.
+    NotIncreasing,
+
+    /// The chunk starts at the same byte as another chunk, or too close to it, such that the chunks would overlap.
+    Overlapping,
+}
+
+impl std::fmt::Display for OffsetTableWarning {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let problem = match self.problem {
+            OffsetTableProblem::OutOfBounds => "is out of bounds",
+            OffsetTableProblem::NotIncreasing => "is not in line order",
+            OffsetTableProblem::Overlapping => "overlaps another chunk",
+        };
+
+        write!(formatter, "chunk {} of layer {} at byte {} {}", self.chunk_index, self.layer_index, self.offset, problem)
+    }
+}
+
+/// Check the offset tables for inconsistencies, without reading any chunks.
+/// Returns all found inconsistencies. Use `Reader::inspect_offset_tables` to check a file.
+/// When reading pedantically, any inconsistency is an error.
+/// Otherwise, the offset tables are trusted without being checked, which is faster.
+pub fn inspect_offset_tables(headers: &[Header], offset_tables: &OffsetTables, chunks_start_byte: u64) -> Vec<OffsetTableWarning> {
+    // a chunk contains at least the y coordinate and the byte size of a scan line block
+    const MIN_CHUNK_BYTE_SIZE: u64 = 2 * i32::BYTE_SIZE as u64;
+
+    let mut warnings = Vec::new();
+
+    // when compressed, chunks are smaller, but never larger than max.
+    // the number of samples in deep layers is unknown before reading them, so their chunks may end anywhere
+    let max_pixel_bytes = headers.iter()
+        .map(|header| if header.deep { u64::MAX } else { header.max_pixel_file_bytes() })
+        .fold(0, u64::saturating_add);
 
     // check that each offset is within the bounds
-    let end_byte = chunks_start_byte + max_pixel_bytes;
-    let is_invalid = offset_tables.iter().flatten().map(|&u64| u64_to_usize(u64))
-        .any(|chunk_start| chunk_start < chunks_start_byte || chunk_start > end_byte);
+    let end_byte = chunks_start_byte.saturating_add(max_pixel_bytes);
+    let start_byte = chunks_start_byte;
+
+    for (layer_index, table) in offset_tables.iter().enumerate() {
+        for (chunk_index, &offset) in table.iter().enumerate() {
+            if offset < start_byte || offset > end_byte {
+                warnings.push(OffsetTableWarning { layer_index, chunk_index, offset, problem: OffsetTableProblem::OutOfBounds });
+            }
+        }
+    }
+
+    // check that the chunks appear in the specified line order
+    for (layer_index, (header, table)) in headers.iter().zip(offset_tables).enumerate() {
+        if header.line_order == LineOrder::Unspecified || table.len() != header.chunk_count { continue; }
+
+        let mut previous_offset = None;
+        for (chunk_index, _) in header.enumerate_ordered_blocks() {
+            let offset = table[chunk_index];
 
-    if is_invalid { Err(Error::invalid("offset table")) }
-    else { Ok(()) }
+            if previous_offset.map_or(false, |previous| offset <= previous) {
+                warnings.push(OffsetTableWarning { layer_index, chunk_index, offset, problem: OffsetTableProblem::NotIncreasing });
+            }
+
+            previous_offset = Some(offset);
+        }
+    }
+
+    // check that no two chunks share any bytes, across all layers
+    let mut sorted_offsets: Vec<(u64, usize, usize)> = offset_tables.iter().enumerate()
+        .flat_map(|(layer_index, table)| table.iter().enumerate()
+            .map(move |(chunk_index, &offset)| (offset, layer_index, chunk_index)))
+        .collect();
+
+    sorted_offsets.sort_unstable();
+
+    for pair in sorted_offsets.windows(2) {
+        let (previous_offset, _, _) = pair[0];
+        let (offset, layer_index, chunk_index) = pair[1];
+
+        if offset - previous_offset < MIN_CHUNK_BYTE_SIZE {
+            warnings.push(OffsetTableWarning { layer_index, chunk_index, offset, problem: OffsetTableProblem::Overlapping });
+        }
+    }
+
+    warnings
+}
+
+/// Estimate the number of bytes to read for each of the chunks at the sorted offsets,
+/// for readers that fetch each chunk with a single request instead of reading the chunk header first.
+/// The estimate may be too small for unusual files. Use `read_chunk_from_bytes` to find out whether more bytes are needed.
+pub(crate) fn estimate_chunk_byte_counts(meta_data: &MetaData, sorted_offsets: &[u64]) -> Vec<usize> {
+    // the number of bytes read for a chunk whose size cannot be estimated, for example of deep data
+    const DEFAULT_CHUNK_BYTE_COUNT: usize = 64 * 1024;
+
+    // the maximum number of bytes in front of the compressed pixels of a flat chunk
+    const MAX_CHUNK_HEADER_SIZE: usize = 64;
+
+    // a flat chunk is never larger than its header plus the uncompressed pixels,
+    // except for unusual compressors, in which case the remaining bytes are read later
+    let max_chunk_size = if meta_data.headers.iter().any(|header| header.deep) { None } else {
+        meta_data.headers.iter().map(|header| header.max_block_byte_size() + MAX_CHUNK_HEADER_SIZE).max()
+    };
+
+    sorted_offsets.iter().enumerate()
+        .map(|(index, &offset)| {
+            // the chunk ends before the next chunk starts, if the file is valid
+            let next_chunk_distance = sorted_offsets.get(index + 1)
+                .and_then(|&next| usize::try_from(next.saturating_sub(offset)).ok())
+                .filter(|&distance| distance > 0);
+
+            match (next_chunk_distance, max_chunk_size) {
+                (Some(distance), Some(max)) => distance.min(max),
+                (Some(distance), None) => distance,
+                (None, Some(max)) => max,
+                (None, None) => DEFAULT_CHUNK_BYTE_COUNT,
+            }
+        })
+        .collect()
+}
+
+/// Parse the chunk at the start of the bytes.
+/// Returns `None` if the chunk continues after the end of the bytes, which means that more bytes must be read.
+pub(crate) fn read_chunk_from_bytes(bytes: &[u8], meta_data: &MetaData, pool: &dyn BlockBufferPool) -> Option<Result<Chunk>> {
+    /// Reads from a byte slice and remembers whether more bytes were requested than available.
+    struct SliceReader<'b> { bytes: &'b [u8], exhausted: bool }
+
+    impl Read for SliceReader<'_> {
+        fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+            if buffer.len() > self.bytes.len() { self.exhausted = true; }
+            self.bytes.read(buffer)
+        }
+    }
+
+    let mut read = SliceReader { bytes, exhausted: false };
+
+    match Chunk::read_with_pool(&mut read, meta_data, pool) {
+        Err(_) if read.exhausted => None,
+        chunk => Some(chunk),
+    }
 }
 
 
@@ -152,7 +464,14 @@ pub struct FilteredChunksReader<R> {
     meta_data: MetaData,
     expected_filtered_chunk_count: usize,
     remaining_filtered_chunk_indices: std::vec::IntoIter<u64>,
+
+    /// The chunks that have already been read while reconstructing the offset tables, in the same order as the offsets.
+    /// Empty if the file contains offset tables.
+    scanned_chunks: VecDeque<Chunk>,
+
     remaining_bytes: PeekRead<Tracking<R>>,
+    buffer_pool: Arc<dyn BlockBufferPool>,
+    requested_channels: Option<Arc<RequestedChannels>>,
 }
 
 /// Decode all chunks in the file without seeking.
@@ -165,9 +484,31 @@ pub struct AllChunksReader<R> {
     meta_data: MetaData,
     remaining_chunks: std::ops::Range<usize>,
     remaining_bytes: PeekRead<Tracking<R>>,
+    buffer_pool: Arc<dyn BlockBufferPool>,
     pedantic: bool,
 }
 
+/// Return all chunks of another reader in the line order of each layer,
+/// buffering the chunks that are stored out of order. Created by `AllChunksReader::in_line_order`.
+/// Chunks of different layers may still be interleaved.
+#[derive(Debug)]
+pub struct LineOrderChunksReader<R> {
+    chunks_reader: AllChunksReader<R>,
+    max_pending_chunks: usize,
+
+    /// For each layer, the position of each chunk in the line order of the layer, by increasing y index.
+    line_order_positions: Vec<Vec<usize>>,
+
+    /// For each layer, the position in line order of the next chunk to be returned.
+    next_positions: Vec<usize>,
+
+    /// Chunks that were read before one of the preceding chunks in their layer, by layer and position in line order.
+    pending_chunks: BTreeMap<(usize, usize), Chunk>,
+
+    /// Chunks that are in line order and can be returned immediately.
+    ready_chunks: VecDeque<Chunk>,
+}
+
 /// Decode chunks in the file without seeking.
 /// Calls the supplied closure for each chunk.
 /// The decoded chunks can be decompressed by calling
@@ -197,6 +538,16 @@ pub trait ChunksReader: Sized + Iterator<Item=Result<Chunk>> + ExactSizeIterator
     /// Can be less than the total number of chunks in the file, if some chunks are skipped.
     fn expected_chunk_count(&self) -> usize;
 
+    /// The pool that provides the buffers of the compressed chunks, if any.
+    /// The decompressors give the compressed buffers back to this pool.
+    /// See `Reader::with_buffer_pool`.
+    fn buffer_pool(&self) -> Option<Arc<dyn BlockBufferPool>> { None }
+
+    /// The channels of each layer that need to be decompressed, if not all channels.
+    /// The decompressors may leave the samples of all other channels undefined.
+    /// See `FilteredChunksReader::with_requested_channels`.
+    fn requested_channels(&self) -> Option<Arc<RequestedChannels>> { None }
+
     /// Read the next compressed chunk from the file.
     /// Equivalent to `.next()`, as this also is an iterator.
     /// Returns `None` if all chunks have been read.
@@ -216,10 +567,19 @@ pub trait ChunksReader: Sized + Iterator<Item=Result<Chunk>> + ExactSizeIterator
     // FIXME try async + futures instead of rayon! Maybe even allows for external async decoding? (-> impl Stream<UncompressedBlock>)
     fn decompress_parallel(
         self, pedantic: bool,
+        insert_block: impl FnMut(&MetaData, UncompressedBlock) -> UnitResult
+    ) -> UnitResult
+    {
+        self.decompress_parallel_with_threads(pedantic, default_thread_count(), insert_block)
+    }
+
+    /// Like `decompress_parallel`, but uses at most the specified number of threads.
+    fn decompress_parallel_with_threads(
+        self, pedantic: bool, thread_count: usize,
         mut insert_block: impl FnMut(&MetaData, UncompressedBlock) -> UnitResult
     ) -> UnitResult
     {
-        let mut decompressor = match self.parallel_decompressor(pedantic) {
+        let mut decompressor = match self.parallel_decompressor_with_threads(pedantic, thread_count) {
             Err(old_self) => return old_self.decompress_sequential(pedantic, insert_block),
             Ok(decompressor) => decompressor,
         };
@@ -232,14 +592,97 @@ pub trait ChunksReader: Sized + Iterator<Item=Result<Chunk>> + ExactSizeIterator
         Ok(())
     }
 
+    /// Decompress all blocks in the file, using multiple cpu cores, and call the supplied closure
+    /// for each block, from within the thread that decompressed the block.
+    /// In contrast to `decompress_parallel`, the blocks are not sent back to the current thread,
+    /// which avoids waiting for a single thread to insert all blocks into the image.
+    /// The closure must thus be able to insert blocks concurrently, for example using a `SharedPixelVec`.
+    /// The order of the blocks is not deterministic.
+    /// Panics in the closure are propagated to the current thread.
+    fn decompress_parallel_shared(
+        self, pedantic: bool,
+        insert_block: impl Fn(&MetaData, UncompressedBlock) -> UnitResult + Send + Sync + 'static
+    ) -> UnitResult
+    {
+        let pool = threadpool::Builder::new()
+            .thread_name("OpenEXR Block Decompressor".to_string())
+            .num_threads(default_thread_count())
+            .build();
+
+        let max_jobs = pool.max_count().max(1) + 2; // ca one block for each thread at all times
+        trace_event!(DEBUG, threads = pool.max_count(), max_jobs, "decompressing blocks in parallel");
+
+        let meta_data = Arc::new(self.meta_data().clone());
+        let buffer_pool = self.buffer_pool().unwrap_or_else(|| Arc::new(HeapBuffers));
+        let requested_channels = self.requested_channels();
+        let insert_block = Arc::new(insert_block);
+
+        let (sender, receiver) = flume::unbounded::<std::thread::Result<UnitResult>>();
+        let receive_next = || match receiver.recv().expect("all decompressing senders hung up but more messages were expected") {
+            Ok(result) => result,
+            Err(panic) => std::panic::resume_unwind(panic),
+        };
+
+        let mut running_job_count = 0;
+
+        for chunk in self {
+            let chunk = chunk?;
+
+            if running_job_count == max_jobs {
+                receive_next()?;
+                running_job_count -= 1;
+            }
+
+            let sender = sender.clone();
+            let meta_data = meta_data.clone();
+            let buffer_pool = buffer_pool.clone();
+            let requested_channels = requested_channels.clone();
+            let insert_block = insert_block.clone();
+            running_job_count += 1;
+
+            capture_current_span!(image_span);
+            pool.execute(move || {
+                enter_captured_span!(image_span);
+
+                let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                    let block = UncompressedBlock::decompress_chunk_channels_with_pool(
+                        chunk, &meta_data, pedantic, buffer_pool.as_ref(), requested_channels.as_deref()
+                    )?;
+
+                    insert_block(&meta_data, block)
+                }));
+
+                // release the closure before sending, so that the caller regains
+                // exclusive access to any captured storage as soon as all blocks are inserted
+                drop(insert_block);
+
+                // by now, decompressing could have failed in another thread.
+                // the error is then already handled, so we simply do nothing
+                let _ = sender.send(result);
+            });
+        }
+
+        for _ in 0 .. running_job_count {
+            receive_next()?;
+        }
+
+        Ok(())
+    }
+
     /// Return an iterator that decompresses the chunks with multiple threads.
     /// The order of the blocks is not deterministic.
     /// Use `ParallelBlockDecompressor::new` if you want to use your own thread pool.
-    /// By default, this uses as many threads as there are CPUs.
+    /// By default, this uses as many threads as there are CPUs, see `default_thread_count`.
     /// Returns the `self` if there is no need for parallel decompression.
     fn parallel_decompressor(self, pedantic: bool) -> std::result::Result<ParallelBlockDecompressor<Self>, Self> {
+        self.parallel_decompressor_with_threads(pedantic, default_thread_count())
+    }
+
+    /// Like `parallel_decompressor`, but uses at most the specified number of threads.
+    fn parallel_decompressor_with_threads(self, pedantic: bool, thread_count: usize) -> std::result::Result<ParallelBlockDecompressor<Self>, Self> {
         let pool = threadpool::Builder::new()
             .thread_name("OpenEXR Block Decompressor".to_string())
+            .num_threads(thread_count.max(1))
             // todo no more threads than remaining block count (self.len())
             .build();
 
@@ -266,11 +709,30 @@ pub trait ChunksReader: Sized + Iterator<Item=Result<Chunk>> + ExactSizeIterator
     fn sequential_decompressor(self, pedantic: bool) -> SequentialBlockDecompressor<Self> {
         SequentialBlockDecompressor { remaining_chunks_reader: self, pedantic }
     }
+
+    /// Read and decompress the chunks in a background thread, using multiple threads for decompression.
+    /// In contrast to `parallel_decompressor`, the file is also read in the background,
+    /// so you can process each block while the remaining chunks are still being read and decompressed.
+    /// The order of the blocks is not deterministic.
+    /// Returns an error if the background thread cannot be started.
+    fn background_decompressor(self, pedantic: bool) -> Result<BackgroundBlockDecompressor> where Self: Send + 'static {
+        BackgroundBlockDecompressor::new(self, pedantic)
+    }
+
+    /// Read the upcoming chunks in a background thread, while the previous chunks are being decompressed.
+    /// At most `chunk_count` chunks are read ahead of time.
+    /// This improves throughput where each read has a high latency, for example on network storage or spinning disks.
+    /// Returns an error if the background thread cannot be started.
+    fn prefetch(self, chunk_count: usize) -> Result<PrefetchChunksReader> where Self: Send + 'static {
+        PrefetchChunksReader::new(self, chunk_count)
+    }
 }
 
 impl<R, F> ChunksReader for OnProgressChunksReader<R, F> where R: ChunksReader, F: FnMut(f64) {
     fn meta_data(&self) -> &MetaData { self.chunks_reader.meta_data() }
     fn expected_chunk_count(&self) -> usize { self.chunks_reader.expected_chunk_count() }
+    fn buffer_pool(&self) -> Option<Arc<dyn BlockBufferPool>> { self.chunks_reader.buffer_pool() }
+    fn requested_channels(&self) -> Option<Arc<RequestedChannels>> { self.chunks_reader.requested_channels() }
 }
 
 impl<R, F> ExactSizeIterator for OnProgressChunksReader<R, F> where R: ChunksReader, F: FnMut(f64) {}
@@ -305,19 +767,134 @@ impl<R, F> Iterator for OnProgressChunksReader<R, F> where R: ChunksReader, F: F
     }
 }
 
-impl<R: Read + Seek> ChunksReader for AllChunksReader<R> {
+impl<R: Read> AllChunksReader<R> {
+
+    /// Return the chunks in the line order of each layer, even if they are stored in a different order,
+    /// for example in a file with `LineOrder::Unspecified`. Layers with unspecified line order are returned in increasing y order.
+    /// This does not seek, so it also works for streams. Chunks that are read before one of
+    /// the preceding chunks of their layer are kept in memory until all preceding chunks have been read.
+    /// Returns an error if more than `max_pending_chunks` chunks would have to be kept in memory at the same time.
+    /// When a file ends before all chunks are read, the remaining chunks are returned in line order, skipping the missing chunks.
+    pub fn in_line_order(self, max_pending_chunks: usize) -> LineOrderChunksReader<R> {
+        let headers = &self.meta_data.headers;
+
+        let line_order_positions = headers.iter().map(|header| {
+            let mut positions = vec![0; header.chunk_count];
+
+            for (position, (chunk_index, _)) in header.enumerate_ordered_blocks().enumerate() {
+                positions[chunk_index] = position;
+            }
+
+            positions
+        }).collect();
+
+        LineOrderChunksReader {
+            next_positions: vec![0; headers.len()],
+            line_order_positions,
+            max_pending_chunks,
+            pending_chunks: BTreeMap::new(),
+            ready_chunks: VecDeque::new(),
+            chunks_reader: self,
+        }
+    }
+}
+
+impl<R: Read> LineOrderChunksReader<R> {
+
+    /// The number of chunks that are currently kept in memory, waiting for a preceding chunk of their layer.
+    pub fn pending_chunk_count(&self) -> usize { self.pending_chunks.len() }
+
+    fn insert_chunk(&mut self, chunk: Chunk) -> UnitResult {
+        let layer = chunk.layer_index;
+        let chunk_index = chunk.index_in_header_increasing_y(self.chunks_reader.headers())?;
+
+        let position = *self.line_order_positions.get(layer)
+            .and_then(|positions| positions.get(chunk_index))
+            .ok_or_else(|| Error::invalid("chunk index"))?;
+
+        let next_position = &mut self.next_positions[layer];
+
+        if position < *next_position || self.pending_chunks.contains_key(&(layer, position)) {
+            return Err(Error::invalid("duplicate chunk"));
+        }
+
+        if position != *next_position {
+            if self.pending_chunks.len() >= self.max_pending_chunks {
+                return Err(Error::unsupported(format!(
+                    "more than {} chunks stored out of line order", self.max_pending_chunks
+                )));
+            }
+
+            self.pending_chunks.insert((layer, position), chunk);
+            return Ok(());
+        }
+
+        self.ready_chunks.push_back(chunk);
+        *next_position += 1;
+
+        // the new chunk may have been the only one missing before some of the pending chunks
+        while let Some(pending_chunk) = self.pending_chunks.remove(&(layer, *next_position)) {
+            self.ready_chunks.push_back(pending_chunk);
+            *next_position += 1;
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: Read> ChunksReader for LineOrderChunksReader<R> {
+    fn meta_data(&self) -> &MetaData { self.chunks_reader.meta_data() }
+    fn expected_chunk_count(&self) -> usize { self.chunks_reader.expected_chunk_count() }
+    fn buffer_pool(&self) -> Option<Arc<dyn BlockBufferPool>> { self.chunks_reader.buffer_pool() }
+}
+
+impl<R: Read> ExactSizeIterator for LineOrderChunksReader<R> {}
+impl<R: Read> Iterator for LineOrderChunksReader<R> {
+    type Item = Result<Chunk>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(chunk) = self.ready_chunks.pop_front() {
+                return Some(Ok(chunk));
+            }
+
+            match self.chunks_reader.next() {
+                Some(Ok(chunk)) => if let Err(error) = self.insert_chunk(chunk) {
+                    return Some(Err(error));
+                },
+
+                Some(Err(error)) => return Some(Err(error)),
+
+                // the file ended before the missing chunks were read
+                None => {
+                    let pending_chunks = std::mem::take(&mut self.pending_chunks);
+                    self.ready_chunks.extend(pending_chunks.into_values());
+                    return self.ready_chunks.pop_front().map(Ok);
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.chunks_reader.len() + self.pending_chunks.len() + self.ready_chunks.len();
+        (remaining, Some(remaining))
+    }
+}
+
+impl<R: Read> ChunksReader for AllChunksReader<R> {
     fn meta_data(&self) -> &MetaData { &self.meta_data }
     fn expected_chunk_count(&self) -> usize { self.remaining_chunks.end }
+    fn buffer_pool(&self) -> Option<Arc<dyn BlockBufferPool>> { Some(self.buffer_pool.clone()) }
 }
 
-impl<R: Read + Seek> ExactSizeIterator for AllChunksReader<R> {}
-impl<R: Read + Seek> Iterator for AllChunksReader<R> {
+impl<R: Read> ExactSizeIterator for AllChunksReader<R> {}
+impl<R: Read> Iterator for AllChunksReader<R> {
     type Item = Result<Chunk>;
 
     fn next(&mut self) -> Option<Self::Item> {
         // read as many chunks as the file should contain (inferred from meta data)
         let next_chunk = self.remaining_chunks.next()
-            .map(|_| Chunk::read(&mut self.remaining_bytes, &self.meta_data));
+            .map(|_| Chunk::read_with_pool(&mut self.remaining_bytes, &self.meta_data, self.buffer_pool.as_ref()));
 
         // if no chunks are left, but some bytes remain, return error
         if self.pedantic && next_chunk.is_none() && self.remaining_bytes.peek_u8().is_ok() {
@@ -335,6 +912,59 @@ impl<R: Read + Seek> Iterator for AllChunksReader<R> {
 impl<R: Read + Seek> ChunksReader for FilteredChunksReader<R> {
     fn meta_data(&self) -> &MetaData { &self.meta_data }
     fn expected_chunk_count(&self) -> usize { self.expected_filtered_chunk_count }
+    fn buffer_pool(&self) -> Option<Arc<dyn BlockBufferPool>> { Some(self.buffer_pool.clone()) }
+    fn requested_channels(&self) -> Option<Arc<RequestedChannels>> { self.requested_channels.clone() }
+}
+
+impl<R> FilteredChunksReader<R> {
+
+    /// Only decompress the samples of these channels, for each layer, where the compression method allows it.
+    /// The bytes of all other channels in the decompressed blocks will contain arbitrary values.
+    /// For example, reading only the depth channel of a ZIP compressed image
+    /// does not need to reorder the bytes of the color channels.
+    pub fn with_requested_channels(self, requested_channels: RequestedChannels) -> Self {
+        Self { requested_channels: Some(Arc::new(requested_channels)), ..self }
+    }
+
+    /// Read the remaining chunks from the file, which was opened with `block::unbuffered::open_unbuffered`,
+    /// with at most `queue_depth` reads in flight at once, bypassing the file cache of the operating system on windows.
+    /// The file must be the same file that this reader reads from.
+    /// Returns an error if the reading threads cannot be spawned.
+    /// See the `block::unbuffered` module for more details.
+    #[cfg(any(unix, windows))]
+    pub fn read_unbuffered(self, file: std::fs::File, queue_depth: usize) -> Result<crate::block::unbuffered::UnbufferedChunksReader> {
+        crate::block::unbuffered::UnbufferedChunksReader::new(
+            self.meta_data, file, self.remaining_filtered_chunk_indices.collect(), queue_depth,
+            self.buffer_pool, self.requested_channels,
+        )
+    }
+
+    /// Read the remaining chunks from the file using `io_uring`, with at most `queue_depth` reads in flight at once,
+    /// instead of reading one chunk after another from this reader.
+    /// The file must be the same file that this reader reads from.
+    /// See the `block::uring` module for more details.
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    pub fn read_with_io_uring(self, file: std::fs::File, queue_depth: usize) -> crate::block::uring::UringChunksReader {
+        crate::block::uring::UringChunksReader::new(
+            self.meta_data, file, self.remaining_filtered_chunk_indices.collect(), queue_depth,
+            self.buffer_pool, self.requested_channels,
+        )
+    }
+}
+
+impl<S: crate::block::source::ChunkSource> FilteredChunksReader<crate::block::source::SourceReader<S>> {
+
+    /// Fetch each of the remaining chunks with a single call to `ChunkSource::get_bytes`, where possible,
+    /// instead of reading them through the buffer of the source reader.
+    /// See the `block::source` module for more details.
+    pub fn fetch_chunks(self) -> crate::block::source::SourceChunksReader<S> {
+        let source = self.remaining_bytes.into_inner().into_inner().into_source();
+
+        crate::block::source::SourceChunksReader::new(
+            self.meta_data, source, self.remaining_filtered_chunk_indices.collect(),
+            self.buffer_pool, self.requested_channels,
+        )
+    }
 }
 
 impl<R: Read + Seek> ExactSizeIterator for FilteredChunksReader<R> {}
@@ -344,13 +974,15 @@ impl<R: Read + Seek> Iterator for FilteredChunksReader<R> {
     fn next(&mut self) -> Option<Self::Item> {
         // read as many chunks as we have desired chunk offsets
         self.remaining_filtered_chunk_indices.next().map(|next_chunk_location|{
-            self.remaining_bytes.skip_to( // no-op for seek at current position, uses skip_bytes for small amounts
-                                          usize::try_from(next_chunk_location)
-                                              .expect("too large chunk position for this machine")
-            )?;
+            if let Some(chunk) = self.scanned_chunks.pop_front() {
+                return Ok(chunk);
+            }
+
+            // no-op for seek at current position, uses skip_bytes for small amounts
+            self.remaining_bytes.skip_to(next_chunk_location)?;
 
             let meta_data = &self.meta_data;
-            Chunk::read(&mut self.remaining_bytes, meta_data)
+            Chunk::read_with_pool(&mut self.remaining_bytes, meta_data, self.buffer_pool.as_ref())
         })
 
         // TODO remember last chunk index and then seek to index+size and check whether bytes are left?
@@ -361,6 +993,69 @@ impl<R: Read + Seek> Iterator for FilteredChunksReader<R> {
     }
 }
 
+/// Reads chunks in a background thread ahead of time.
+/// The chunks can be decompressed by calling `decompress_parallel`, `decompress_sequential`,
+/// `sequential_decompressor`, or `parallel_decompressor`.
+/// Dropping this value stops reading further chunks.
+/// Also contains the image meta data.
+#[derive(Debug)]
+pub struct PrefetchChunksReader {
+    meta_data: MetaData,
+    receiver: flume::Receiver<Result<Chunk>>,
+    expected_chunk_count: usize,
+    remaining_chunk_count: usize,
+    buffer_pool: Option<Arc<dyn BlockBufferPool>>,
+    requested_channels: Option<Arc<RequestedChannels>>,
+}
+
+impl PrefetchChunksReader {
+
+    /// Start reading the chunks in a new thread, reading at most `chunk_count` chunks ahead of time.
+    /// Returns an error if the thread cannot be started.
+    pub fn new<R: ChunksReader + Send + 'static>(chunks: R, chunk_count: usize) -> Result<Self> {
+        let meta_data = chunks.meta_data().clone();
+        let expected_chunk_count = chunks.len();
+        let buffer_pool = chunks.buffer_pool();
+        let requested_channels = chunks.requested_channels();
+        let (sender, receiver) = flume::bounded(chunk_count);
+
+        std::thread::Builder::new()
+            .name("OpenEXR Chunk Prefetcher".to_string())
+            .spawn(move || {
+                for chunk in chunks {
+                    let is_error = chunk.is_err();
+
+                    // stop reading if an error occurred or if the receiver has been dropped
+                    if sender.send(chunk).is_err() || is_error { break; }
+                }
+            })?;
+
+        Ok(Self { meta_data, receiver, expected_chunk_count, remaining_chunk_count: expected_chunk_count, buffer_pool, requested_channels })
+    }
+}
+
+impl ChunksReader for PrefetchChunksReader {
+    fn meta_data(&self) -> &MetaData { &self.meta_data }
+    fn expected_chunk_count(&self) -> usize { self.expected_chunk_count }
+    fn buffer_pool(&self) -> Option<Arc<dyn BlockBufferPool>> { self.buffer_pool.clone() }
+    fn requested_channels(&self) -> Option<Arc<RequestedChannels>> { self.requested_channels.clone() }
+}
+
+impl ExactSizeIterator for PrefetchChunksReader {}
+impl Iterator for PrefetchChunksReader {
+    type Item = Result<Chunk>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let chunk = self.receiver.recv().ok()?;
+        self.remaining_chunk_count = self.remaining_chunk_count.saturating_sub(1);
+        Some(chunk)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining_chunk_count, Some(self.remaining_chunk_count))
+    }
+}
+
 /// Read all chunks from the file, decompressing each chunk immediately.
 /// Implements iterator.
 #[derive(Debug)]
@@ -377,7 +1072,14 @@ impl<R: ChunksReader> SequentialBlockDecompressor<R> {
     /// Read and then decompress a single block of pixels from the byte source.
     pub fn decompress_next_block(&mut self) -> Option<Result<UncompressedBlock>> {
         self.remaining_chunks_reader.read_next_chunk().map(|compressed_chunk|{
-            UncompressedBlock::decompress_chunk(compressed_chunk?, &self.remaining_chunks_reader.meta_data(), self.pedantic)
+            let buffer_pool = self.remaining_chunks_reader.buffer_pool();
+            let buffer_pool = buffer_pool.as_ref().map_or(&HeapBuffers as &dyn BlockBufferPool, |pool| pool.as_ref());
+            let requested_channels = self.remaining_chunks_reader.requested_channels();
+
+            UncompressedBlock::decompress_chunk_channels_with_pool(
+                compressed_chunk?, &self.remaining_chunks_reader.meta_data(), self.pedantic,
+                buffer_pool, requested_channels.as_deref()
+            )
         })
     }
 }
@@ -396,6 +1098,8 @@ pub struct ParallelBlockDecompressor<R: ChunksReader> {
     max_threads: usize,
 
     shared_meta_data_ref: Arc<MetaData>,
+    shared_buffer_pool: Arc<dyn BlockBufferPool>,
+    shared_requested_channels: Option<Arc<RequestedChannels>>,
     pedantic: bool,
 
     pool: threadpool::ThreadPool,
@@ -410,14 +1114,18 @@ impl<R: ChunksReader> ParallelBlockDecompressor<R> {
         if chunks.meta_data().headers.iter()
             .all(|head|head.compression == Compression::Uncompressed)
         {
+            trace_event!(DEBUG, "all layers are uncompressed, decompressing sequentially");
             return Err(chunks);
         }
 
         let max_threads = pool.max_count().max(1).min(chunks.len()) + 2; // ca one block for each thread at all times
+        trace_event!(DEBUG, threads = pool.max_count(), max_jobs = max_threads, "decompressing blocks in parallel");
 
         let (send, recv) = flume::unbounded(); // TODO bounded channel simplifies logic?
         Ok(Self {
             shared_meta_data_ref: Arc::new(chunks.meta_data().clone()),
+            shared_buffer_pool: chunks.buffer_pool().unwrap_or_else(|| Arc::new(HeapBuffers)),
+            shared_requested_channels: chunks.requested_channels(),
             currently_decompressing_count: 0,
             remaining_chunks: chunks,
             sender: send,
@@ -463,13 +1171,18 @@ impl<R: ChunksReader> ParallelBlockDecompressor<R> {
 
                 let sender = self.sender.clone();
                 let meta = self.shared_meta_data_ref.clone();
+                let buffer_pool = self.shared_buffer_pool.clone();
+                let requested_channels = self.shared_requested_channels.clone();
                 let pedantic = self.pedantic;
 
                 self.currently_decompressing_count += 1;
 
+                capture_current_span!(image_span);
                 self.pool.execute(move || {
-                    let decompressed_or_err = UncompressedBlock::decompress_chunk(
-                        block, &meta, pedantic
+                    enter_captured_span!(image_span);
+
+                    let decompressed_or_err = UncompressedBlock::decompress_chunk_channels_with_pool(
+                        block, &meta, pedantic, buffer_pool.as_ref(), requested_channels.as_deref()
                     );
 
                     // by now, decompressing could have failed in another thread.
@@ -485,6 +1198,8 @@ impl<R: ChunksReader> ParallelBlockDecompressor<R> {
         }
 
         if self.currently_decompressing_count > 0 {
+            trace_span!(TRACE, "wait for decompressed block", in_flight = self.currently_decompressing_count);
+
             let next = self.receiver.recv()
                 .expect("all decompressing senders hung up but more messages were expected");
 
@@ -502,6 +1217,88 @@ impl<R: ChunksReader> ParallelBlockDecompressor<R> {
     pub fn meta_data(&self) -> &MetaData { self.remaining_chunks.meta_data() }
 }
 
+/// Read and decompress the chunks of a file in a background thread.
+/// Each block is available as soon as it has been decompressed,
+/// while the remaining chunks are still being read and decompressed.
+/// The order of the blocks is not deterministic, use `UncompressedBlock::index` to find out where the pixels belong.
+/// Dropping this value stops reading further chunks, but the blocks currently being decompressed will still finish.
+/// Implements iterator, which waits for the next block.
+#[derive(Debug)]
+pub struct BackgroundBlockDecompressor {
+    meta_data: MetaData,
+    receiver: flume::Receiver<Result<UncompressedBlock>>,
+    remaining_block_count: usize,
+}
+
+impl BackgroundBlockDecompressor {
+
+    /// Start reading and decompressing the chunks in a new thread.
+    /// Uses a parallel decompressor where the image is compressed.
+    /// Returns an error if the thread cannot be started.
+    pub fn new<R: ChunksReader + Send + 'static>(chunks: R, pedantic: bool) -> Result<Self> {
+        let meta_data = chunks.meta_data().clone();
+        let remaining_block_count = chunks.len();
+
+        // limits the memory used by decompressed blocks that have not been received yet
+        let (sender, receiver) = flume::bounded(32);
+
+        std::thread::Builder::new()
+            .name("OpenEXR Block Reader".to_string())
+            .spawn(move || {
+                let send_all_blocks = |blocks: &mut dyn Iterator<Item=Result<UncompressedBlock>>| {
+                    for block in blocks {
+                        let is_error = block.is_err();
+
+                        // stop reading if an error occurred or if the receiver has been dropped
+                        if sender.send(block).is_err() || is_error { break; }
+                    }
+                };
+
+                match chunks.parallel_decompressor(pedantic) {
+                    Ok(mut decompressor) => send_all_blocks(&mut decompressor),
+                    Err(chunks) => send_all_blocks(&mut chunks.sequential_decompressor(pedantic)),
+                }
+            })?;
+
+        Ok(Self { meta_data, receiver, remaining_block_count })
+    }
+
+    /// The extracted meta data of the image file.
+    pub fn meta_data(&self) -> &MetaData { &self.meta_data }
+
+    /// Wait for the next decompressed block.
+    /// Returns `None` if all blocks have been received, or after an error has been returned.
+    pub fn decompress_next_block(&mut self) -> Option<Result<UncompressedBlock>> {
+        let block = self.receiver.recv().ok();
+        self.count_received_block(block)
+    }
+
+    /// Return the next decompressed block, if one has already been decompressed, without waiting.
+    /// Returns `None` if no block is available yet. Use `len()` to check whether any blocks are left.
+    pub fn try_next_block(&mut self) -> Option<Result<UncompressedBlock>> {
+        match self.receiver.try_recv() {
+            Err(flume::TryRecvError::Empty) => None,
+            Ok(block) => self.count_received_block(Some(block)),
+            Err(flume::TryRecvError::Disconnected) => self.count_received_block(None),
+        }
+    }
+
+    /// No more blocks will arrive after an error, or after the background thread has stopped.
+    fn count_received_block(&mut self, block: Option<Result<UncompressedBlock>>) -> Option<Result<UncompressedBlock>> {
+        if let Some(Ok(_)) = block { self.remaining_block_count = self.remaining_block_count.saturating_sub(1); }
+        else { self.remaining_block_count = 0; }
+
+        block
+    }
+}
+
+impl ExactSizeIterator for BackgroundBlockDecompressor {}
+impl Iterator for BackgroundBlockDecompressor {
+    type Item = Result<UncompressedBlock>;
+    fn next(&mut self) -> Option<Self::Item> { self.decompress_next_block() }
+    fn size_hint(&self) -> (usize, Option<usize>) { (self.remaining_block_count, Some(self.remaining_block_count)) }
+}
+
 impl<R: ChunksReader> ExactSizeIterator for SequentialBlockDecompressor<R> {}
 impl<R: ChunksReader> Iterator for SequentialBlockDecompressor<R> {
     type Item = Result<UncompressedBlock>;
@@ -520,6 +1317,481 @@ impl<R: ChunksReader> Iterator for ParallelBlockDecompressor<R> {
 }
 
 
+#[cfg(test)]
+mod test {
+    use crate::prelude::*;
+    use crate::block::reader::ChunksReader;
+    use crate::block::chunk::TileCoordinates;
+    use std::io::Cursor;
+
+    #[test]
+    fn background_decompressor_returns_all_blocks() {
+        let size = Vec2(80, 300);
+        let channels = SpecificChannels::rgb(|position: Vec2<usize>| (position.x() as f32, position.y() as f32, 0.5_f32));
+        let image = Image::from_encoded_channels(size, Encoding::SMALL_LOSSLESS, channels);
+
+        let mut bytes = Vec::new();
+        image.write().non_parallel().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        let sequential_blocks: Vec<_> = crate::block::read(Cursor::new(bytes.clone()), true).unwrap()
+            .all_chunks(true).unwrap().sequential_decompressor(true)
+            .collect::<crate::error::Result<_>>().unwrap();
+
+        let mut decompressor = crate::block::read(Cursor::new(bytes), true).unwrap()
+            .all_chunks(true).unwrap().background_decompressor(true).unwrap();
+
+        assert_eq!(decompressor.len(), sequential_blocks.len());
+        assert_eq!(decompressor.meta_data().headers[0].layer_size, size);
+
+        let background_blocks: Vec<_> = decompressor.by_ref().collect::<crate::error::Result<_>>().unwrap();
+        assert_eq!(decompressor.len(), 0);
+        assert_eq!(background_blocks.len(), sequential_blocks.len());
+
+        for block in &background_blocks {
+            assert!(sequential_blocks.contains(block), "unexpected block at {:?}", block.index);
+        }
+    }
+
+    #[test]
+    fn background_decompressor_is_empty_after_error() {
+        let size = Vec2(80, 300);
+        let channels = SpecificChannels::rgb(|position: Vec2<usize>| (position.x() as f32, position.y() as f32, 0.5_f32));
+        let image = Image::from_encoded_channels(size, Encoding::SMALL_LOSSLESS, channels);
+
+        let mut bytes = Vec::new();
+        image.write().non_parallel().to_buffered(Cursor::new(&mut bytes)).unwrap();
+        bytes.truncate(bytes.len() / 2);
+
+        let mut decompressor = crate::block::read(Cursor::new(bytes), false).unwrap()
+            .all_chunks(false).unwrap().background_decompressor(false).unwrap();
+
+        let total_block_count = decompressor.len();
+        let error = decompressor.by_ref().find(|block| block.is_err());
+
+        assert!(error.is_some(), "truncated file should fail");
+        assert_eq!(decompressor.size_hint(), (0, Some(0)));
+        assert!(decompressor.next().is_none());
+        assert!(total_block_count > 0);
+    }
+
+    #[test]
+    fn reuse_block_buffers_from_pool() {
+        use crate::block::pool::{RecyclingBufferPool, BlockBufferPool};
+        use std::sync::Arc;
+
+        let size = Vec2(80, 300);
+        let channels = SpecificChannels::rgb(|position: Vec2<usize>| (position.x() as f32, position.y() as f32, 0.5_f32));
+        let image = Image::from_encoded_channels(size, Encoding::SMALL_LOSSLESS, channels);
+
+        let mut bytes = Vec::new();
+        image.write().non_parallel().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        let expected_blocks: Vec<_> = crate::block::read(Cursor::new(bytes.clone()), true).unwrap()
+            .all_chunks(true).unwrap().sequential_decompressor(true)
+            .collect::<crate::error::Result<_>>().unwrap();
+
+        // each zip compressed chunk needs a compressed buffer and a decompressed buffer
+        let pool = Arc::new(RecyclingBufferPool::new(4));
+        let chunks = crate::block::read(Cursor::new(bytes), true).unwrap()
+            .with_buffer_pool(pool.clone())
+            .all_chunks(true).unwrap();
+
+        assert!(chunks.buffer_pool().is_some());
+
+        for (block, expected_block) in chunks.sequential_decompressor(true).zip(&expected_blocks) {
+            let block = block.unwrap();
+            assert_eq!(&block, expected_block);
+            pool.recycle(block.data);
+        }
+
+        let statistics = pool.statistics();
+        assert_eq!(statistics.allocations + statistics.reuses, 2 * expected_blocks.len(), "two buffers for each chunk");
+        assert!(statistics.reuses > expected_blocks.len() / 2, "buffers should be reused: {:?}", statistics);
+    }
+
+    #[test]
+    fn decode_chunks_into_supplied_buffer() {
+        let size = Vec2(80, 70);
+        let channels = SpecificChannels::build()
+            .with_channel("B").with_channel("G").with_channel("R")
+            .with_pixel_fn(|position: Vec2<usize>| (f16::from_f32(0.5), (position.x() % 7) as f32, position.y() as u32));
+
+        for &compression in &[ Compression::Uncompressed, Compression::RLE, Compression::ZIP1, Compression::ZIP16, Compression::PIZ ] {
+            let encoding = Encoding { compression, blocks: Blocks::Tiles(Vec2(16, 16)), line_order: LineOrder::Increasing };
+            let image = Image::from_encoded_channels(size, encoding, channels.clone());
+
+            let mut bytes = Vec::new();
+            image.write().non_parallel().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+            let chunks = crate::block::read(Cursor::new(bytes), true).unwrap().all_chunks(true).unwrap();
+            let meta_data = chunks.meta_data().clone();
+            let mut target = Vec::new();
+
+            for chunk in chunks {
+                let chunk = chunk.unwrap();
+                target.resize(chunk.decompressed_byte_size(&meta_data.headers).unwrap(), 0);
+
+                let index = crate::block::decode_chunk_into(&chunk, &meta_data, true, &mut target).unwrap();
+                let expected = crate::block::UncompressedBlock::decompress_chunk(chunk.clone(), &meta_data, true).unwrap();
+
+                assert_eq!(index, expected.index, "{}", compression);
+                assert_eq!(target, expected.data, "{}", compression);
 
+                target.push(0);
+                assert!(crate::block::decode_chunk_into(&chunk, &meta_data, true, &mut target).is_err(), "target size must be checked");
+            }
+        }
+    }
+
+    #[test]
+    fn read_range_of_scan_lines() {
+        let size = Vec2(20, 100);
+        let channels = SpecificChannels::rgb(|position: Vec2<usize>| (position.x() as f32, position.y() as f32, 0.5_f32));
+        let image = Image::from_encoded_channels(size, Encoding { compression: Compression::ZIP16, blocks: Blocks::ScanLines, line_order: LineOrder::Increasing }, channels);
+
+        let mut bytes = Vec::new();
+        image.write().non_parallel().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        let all_blocks: Vec<_> = crate::block::read(Cursor::new(bytes.clone()), true).unwrap()
+            .all_chunks(true).unwrap().sequential_decompressor(true)
+            .collect::<crate::error::Result<_>>().unwrap();
+
+        let chunks = crate::block::read(Cursor::new(bytes.clone()), true).unwrap()
+            .read_scanline_range(20 .. 40, true).unwrap();
+
+        assert_eq!(chunks.len(), 2);
+
+        let blocks: Vec<_> = chunks.sequential_decompressor(true).collect::<crate::error::Result<_>>().unwrap();
+        let positions: Vec<_> = blocks.iter().map(|block| block.index.pixel_position.y()).collect();
+        assert_eq!(positions, vec![ 16, 32 ]);
+
+        for block in &blocks {
+            assert!(all_blocks.contains(block), "unexpected block at {:?}", block.index);
+        }
+
+        let last = crate::block::read(Cursor::new(bytes.clone()), true).unwrap()
+            .read_scanline_range(99 .. 1000, true).unwrap();
+
+        assert_eq!(last.len(), 1);
+
+        let empty = crate::block::read(Cursor::new(bytes), true).unwrap()
+            .read_scanline_range(100 .. 200, true).unwrap();
+
+        assert_eq!(empty.len(), 0);
+    }
+
+    #[test]
+    fn decompress_parallel_into_shared_storage() {
+        use crate::image::pixel_vec::SharedPixelVec;
+        use std::sync::Arc;
+
+        let size = Vec2(80, 300);
+        let channels = SpecificChannels::build()
+            .with_channel("B").with_channel("G").with_channel("R")
+            .with_pixel_fn(|position: Vec2<usize>| (f16::from_f32(0.5), position.y() as f32, position.x() as u32));
+
+        let image = Image::from_encoded_channels(size, Encoding::SMALL_LOSSLESS, channels);
+
+        let mut bytes = Vec::new();
+        image.write().non_parallel().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        let chunks = crate::block::read(Cursor::new(bytes), true).unwrap().all_chunks(true).unwrap();
+        let storage = Arc::new(SharedPixelVec::for_header(&chunks.headers()[0]));
+        let shared_storage = storage.clone();
+
+        chunks.decompress_parallel_shared(true, move |meta_data, block| {
+            shared_storage.insert_block(&meta_data.headers[block.index.layer], &block)
+        }).unwrap();
+
+        let storage = Arc::try_unwrap(storage).unwrap();
+        for y in 0 .. size.height() {
+            for x in 0 .. size.width() {
+                let position = Vec2(x, y);
+                assert_eq!(storage.get_sample(position, 0), 0.5);
+                assert_eq!(storage.get_sample(position, 1), y as f32);
+                assert_eq!(storage.get_sample(position, 2), x as f32);
+            }
+        }
+
+        assert_eq!(storage.into_samples().len(), size.area() * 3);
+    }
+
+    #[test]
+    fn decompress_parallel_shared_returns_errors() {
+        let size = Vec2(8, 40);
+        let channels = SpecificChannels::rgb(|_: Vec2<usize>| (0.0_f32, 0.0_f32, 0.0_f32));
+        let image = Image::from_encoded_channels(size, Encoding::SMALL_LOSSLESS, channels);
+
+        let mut bytes = Vec::new();
+        image.write().non_parallel().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        let chunks = crate::block::read(Cursor::new(bytes), true).unwrap().all_chunks(true).unwrap();
+        let result = chunks.decompress_parallel_shared(true, |_, _| Err(crate::error::Error::invalid("test")));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn prefetch_returns_chunks_in_file_order() {
+        fn tiles_in_file_order(chunks: impl ChunksReader) -> Vec<TileCoordinates> {
+            let header = chunks.headers()[0].clone();
+            chunks.map(|chunk| header.get_block_data_indices(&chunk.unwrap().compressed_block).unwrap()).collect()
+        }
+
+        let size = Vec2(80, 300);
+        let channels = SpecificChannels::rgb(|position: Vec2<usize>| (position.x() as f32, position.y() as f32, 0.5_f32));
+        let image = Image::from_encoded_channels(size, Encoding::FAST_LOSSLESS, channels);
+
+        let mut bytes = Vec::new();
+        image.write().non_parallel().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        let chunks = || crate::block::read(Cursor::new(bytes.clone()), true).unwrap().all_chunks(true).unwrap();
+        let expected_tiles = tiles_in_file_order(chunks());
+
+        for &chunk_count in [ 0, 3 ].iter() {
+            let prefetched = chunks().prefetch(chunk_count).unwrap();
+            assert_eq!(prefetched.expected_chunk_count(), expected_tiles.len());
+            assert_eq!(prefetched.len(), expected_tiles.len());
+            assert_eq!(tiles_in_file_order(prefetched), expected_tiles);
+        }
+    }
+
+    #[test]
+    fn read_chunks_stored_in_any_order() {
+        use crate::block::writer::ChunksWriter;
+        use crate::image::pixel_vec::PixelVec;
+
+        let size = Vec2(70, 50);
+        let channels = SpecificChannels::rgb(|position: Vec2<usize>| (position.x() as f32, position.y() as f32, 0.5_f32));
+        let encoding = Encoding { blocks: Blocks::Tiles(Vec2(16, 16)), .. Encoding::FAST_LOSSLESS };
+        let image = Image::from_encoded_channels(size, encoding, channels);
+
+        let mut bytes = Vec::new();
+        image.write().non_parallel().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        let chunks = crate::block::read(Cursor::new(bytes.clone()), true).unwrap().all_chunks(true).unwrap();
+        let mut headers = chunks.meta_data().headers.clone();
+        let mut chunks: Vec<_> = chunks.collect::<crate::error::Result<_>>().unwrap();
+        let chunk_count = chunks.len();
+
+        // store every pair of chunks in swapped order
+        for pair in chunks.chunks_exact_mut(2) { pair.swap(0, 1); }
+        headers[0].line_order = LineOrder::Unspecified;
+
+        let mut shuffled = Vec::new();
+        crate::block::write(Cursor::new(&mut shuffled), headers.clone(), true, |_, writer| {
+            for chunk in chunks {
+                writer.write_chunk(chunk.index_in_header_increasing_y(&headers)?, chunk)?;
+            }
+
+            Ok(())
+        }).unwrap();
+
+        // a byte slice cannot seek
+        let mut sorted = crate::block::read(shuffled.as_slice(), true).unwrap()
+            .all_chunks(true).unwrap().in_line_order(1);
+
+        assert_eq!(sorted.len(), chunk_count);
+
+        let mut chunk_indices = Vec::new();
+        while let Some(chunk) = sorted.next() {
+            chunk_indices.push(chunk.unwrap().index_in_header_increasing_y(&headers).unwrap());
+            assert!(sorted.pending_chunk_count() <= 1);
+        }
+
+        assert_eq!(chunk_indices, (0 .. chunk_count).collect::<Vec<_>>());
+
+        let no_window = crate::block::read(shuffled.as_slice(), true).unwrap()
+            .all_chunks(true).unwrap().in_line_order(0);
 
+        assert!(no_window.collect::<crate::error::Result<Vec<_>>>().is_err(), "too many chunks out of order");
 
+        let read_rgb = || read().no_deep_data().largest_resolution_level()
+            .rgb_channels(PixelVec::<(f32, f32, f32)>::constructor, PixelVec::set_pixel)
+            .first_valid_layer().all_attributes();
+
+        let expected = read_rgb().from_buffered(Cursor::new(bytes)).unwrap();
+        let streamed = read_rgb().from_stream(shuffled.as_slice()).unwrap();
+        assert_eq!(streamed.layer_data.channel_data.pixels, expected.layer_data.channel_data.pixels);
+    }
+
+    #[test]
+    fn inspect_corrupt_offset_tables() {
+        use crate::block::reader::{OffsetTableWarning, OffsetTableProblem};
+        use std::convert::TryInto;
+
+        let size = Vec2(16, 64);
+        let channels = SpecificChannels::rgb(|position: Vec2<usize>| (position.x() as f32, position.y() as f32, 0.5_f32));
+        let encoding = Encoding { compression: Compression::Uncompressed, blocks: Blocks::ScanLines, line_order: LineOrder::Increasing };
+        let image = Image::from_encoded_channels(size, encoding, channels);
+
+        let mut bytes = Vec::new();
+        image.write().non_parallel().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        let mut reader = crate::block::read(Cursor::new(bytes.clone()), true).unwrap();
+        assert_eq!(reader.inspect_offset_tables().unwrap(), Vec::new());
+        assert!(reader.all_chunks(true).is_ok(), "inspecting does not consume the offset tables");
+
+        // duplicate the first offset into the second slot of the table
+        let table_start = crate::block::read(Cursor::new(bytes.clone()), true).unwrap().remaining_reader.byte_position() as usize;
+        let first_offset: [u8; 8] = bytes[table_start .. table_start + 8].try_into().unwrap();
+        bytes[table_start + 8 .. table_start + 16].copy_from_slice(&first_offset);
+
+        let mut reader = crate::block::read(Cursor::new(bytes.clone()), true).unwrap();
+        let warnings = reader.inspect_offset_tables().unwrap();
+        let offset = u64::from_le_bytes(first_offset);
+
+        assert!(warnings.contains(&OffsetTableWarning { layer_index: 0, chunk_index: 1, offset, problem: OffsetTableProblem::NotIncreasing }));
+        assert!(warnings.iter().any(|warning| warning.problem == OffsetTableProblem::Overlapping));
+
+        assert!(crate::block::read(Cursor::new(bytes.clone()), true).unwrap().all_chunks(true).is_err(), "pedantic");
+        assert!(crate::block::read(Cursor::new(bytes), false).unwrap().all_chunks(false).is_ok(), "trusted offset tables");
+    }
+
+    /// A file that contains a large number of zero bytes between the offset tables and the chunks,
+    /// without allocating them, such that the chunks are located after the first 4GB of the file.
+    struct SparseFile {
+        meta_data: Vec<u8>,
+        gap: u64,
+        chunks: Vec<u8>,
+        position: u64,
+    }
+
+    impl std::io::Read for SparseFile {
+        fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+            let meta_data_end = self.meta_data.len() as u64;
+            let chunks_start = meta_data_end + self.gap;
+
+            let count = if self.position < meta_data_end {
+                (&self.meta_data[self.position as usize ..]).read(buffer)?
+            }
+            else if self.position < chunks_start {
+                let count = buffer.len().min((chunks_start - self.position).min(usize::MAX as u64) as usize);
+                buffer[.. count].iter_mut().for_each(|byte| *byte = 0);
+                count
+            }
+            else {
+                let start = (self.position - chunks_start).min(self.chunks.len() as u64) as usize;
+                (&self.chunks[start ..]).read(buffer)?
+            };
+
+            self.position += count as u64;
+            Ok(count)
+        }
+    }
+
+    impl std::io::Seek for SparseFile {
+        fn seek(&mut self, position: std::io::SeekFrom) -> std::io::Result<u64> {
+            match position {
+                std::io::SeekFrom::Start(position) => self.position = position,
+                _ => unimplemented!("only absolute seeking is used by the reader"),
+            }
+
+            Ok(self.position)
+        }
+    }
+
+    #[test]
+    fn read_chunks_after_4gb() {
+        const GAP: u64 = 5 * 1024 * 1024 * 1024;
+
+        let size = Vec2(16, 64);
+        let channels = SpecificChannels::rgb(|position: Vec2<usize>| (position.x() as f32, position.y() as f32, 0.5_f32));
+        let encoding = Encoding { compression: Compression::RLE, blocks: Blocks::ScanLines, line_order: LineOrder::Increasing };
+        let image = Image::from_encoded_channels(size, encoding, channels);
+
+        let mut bytes = Vec::new();
+        image.write().non_parallel().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        // move all chunks behind the gap, adjusting the offset tables accordingly
+        let reader = crate::block::read(Cursor::new(bytes.clone()), true).unwrap();
+        let chunk_count = reader.headers()[0].chunk_count;
+        let table_start = reader.remaining_reader.byte_position() as usize;
+        let table_end = table_start + chunk_count * 8;
+
+        let mut meta_data = bytes[.. table_end].to_vec();
+        for offset in meta_data[table_start ..].chunks_exact_mut(8) {
+            let moved = u64::from_le_bytes(std::convert::TryInto::try_into(&*offset).unwrap()) + GAP;
+            offset.copy_from_slice(&moved.to_le_bytes());
+        }
+
+        let sparse = SparseFile { meta_data, gap: GAP, chunks: bytes[table_end ..].to_vec(), position: 0 };
+
+        let mut reader = crate::block::read(sparse, false).unwrap();
+        let warnings = reader.inspect_offset_tables().unwrap();
+        assert!(warnings.iter().all(|warning| warning.offset > GAP), "offsets should not be truncated");
+
+        let chunks = reader.filter_chunks(false, |_, _, _| true).unwrap();
+        let original_chunks = crate::block::read(Cursor::new(bytes), false).unwrap().filter_chunks(false, |_, _, _| true).unwrap();
+        assert_eq!(chunks.len(), chunk_count);
+
+        for (chunk, original_chunk) in chunks.zip(original_chunks) {
+            assert_eq!(format!("{:?}", chunk.unwrap()), format!("{:?}", original_chunk.unwrap()));
+        }
+    }
+
+    #[test]
+    fn inspect_offset_tables_of_layers_larger_than_4gb() {
+        use crate::block::reader::inspect_offset_tables;
+        use crate::meta::OffsetTables;
+        use crate::meta::header::Header;
+        use crate::meta::attribute::{ChannelDescription, SampleType};
+        use smallvec::smallvec;
+
+        // 20000 * 20000 pixels with four f32 channels contain 6.4GB of pixels
+        let channels = smallvec![
+            ChannelDescription::named("A", SampleType::F32), ChannelDescription::named("B", SampleType::F32),
+            ChannelDescription::named("G", SampleType::F32), ChannelDescription::named("R", SampleType::F32),
+        ];
+
+        let header = Header::new(Text::from("large"), Vec2(20_000, 20_000), channels);
+        assert!(header.max_pixel_file_bytes() > u64::from(u32::MAX));
+
+        let chunks_start = 1000;
+        let last_offset = chunks_start + 6_000_000_000;
+        let offset_tables: OffsetTables = smallvec![ vec![ chunks_start, chunks_start + 4_500_000_000, last_offset ] ];
+
+        assert_eq!(inspect_offset_tables(&[ header.clone() ], &offset_tables, chunks_start), Vec::new());
+
+        let offset_tables: OffsetTables = smallvec![ vec![ chunks_start, last_offset * 2 ] ];
+        let warnings = inspect_offset_tables(&[ header ], &offset_tables, chunks_start);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].offset, last_offset * 2);
+    }
+
+    #[test]
+    fn decompress_with_limited_threads() {
+        let size = Vec2(80, 300);
+        let channels = SpecificChannels::rgb(|position: Vec2<usize>| (position.x() as f32, position.y() as f32, 0.5_f32));
+        let image = Image::from_encoded_channels(size, Encoding::SMALL_LOSSLESS, channels);
+
+        let mut bytes = Vec::new();
+        image.write().non_parallel().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        let sequential_blocks: Vec<_> = crate::block::read(Cursor::new(bytes.clone()), true).unwrap()
+            .all_chunks(true).unwrap().sequential_decompressor(true)
+            .collect::<crate::error::Result<_>>().unwrap();
+
+        for &thread_count in &[ 1, 2, 5 ] {
+            let mut blocks = Vec::new();
+
+            crate::block::read(Cursor::new(bytes.clone()), true).unwrap()
+                .all_chunks(true).unwrap()
+                .decompress_parallel_with_threads(true, thread_count, |_, block| { blocks.push(block); Ok(()) })
+                .unwrap();
+
+            assert_eq!(blocks.len(), sequential_blocks.len());
+            for block in &blocks { assert!(sequential_blocks.contains(block)); }
+        }
+    }
+
+    #[test]
+    fn thread_count_from_environment() {
+        use crate::block::parse_thread_count;
+
+        assert_eq!(parse_thread_count(Some("3")), Some(3));
+        assert_eq!(parse_thread_count(Some(" 12\n")), Some(12));
+        assert_eq!(parse_thread_count(Some("0")), None);
+        assert_eq!(parse_thread_count(Some("many")), None);
+        assert_eq!(parse_thread_count(None), None);
+    }
+}
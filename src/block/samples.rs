@@ -79,6 +79,70 @@ impl Sample {
             Sample::U32(value) => value == 0,
         }
     }
+
+    /// Is this value neither infinite nor not a number?
+    #[inline]
+    pub fn is_finite(self) -> bool {
+        match self {
+            Sample::F16(value) => value.is_finite(),
+            Sample::F32(value) => value.is_finite(),
+            Sample::U32(_) => true,
+        }
+    }
+
+    /// The type of this sample, as it would be stored in a file.
+    #[inline]
+    pub fn sample_type(self) -> SampleType {
+        match self {
+            Sample::F16(_) => SampleType::F16,
+            Sample::F32(_) => SampleType::F32,
+            Sample::U32(_) => SampleType::U32,
+        }
+    }
+
+    /// Linearly interpolate between this sample, at `0.0`, and the other sample, at `1.0`.
+    /// The result is an `f32` sample, regardless of the types of the two samples.
+    #[inline]
+    pub fn lerp(self, other: impl Into<Sample>, t: f32) -> Self {
+        let start = self.to_f32();
+        Sample::F32(start + (other.into().to_f32() - start) * t)
+    }
+}
+
+/// Implements an arithmetic operator for samples, where both operands are converted to `f32`.
+/// The result is always an `f32` sample.
+macro_rules! impl_promoting_operator {
+    ( $( $Operator: ident, $function: ident, $operator: tt; )* ) => { $(
+        impl<Other> std::ops::$Operator<Other> for Sample where Other: Into<Sample> {
+            type Output = Sample;
+
+            #[inline]
+            fn $function(self, other: Other) -> Sample {
+                Sample::F32(self.to_f32() $operator other.into().to_f32())
+            }
+        }
+    )* };
+}
+
+impl_promoting_operator! {
+    Add, add, +;
+    Sub, sub, -;
+    Mul, mul, *;
+    Div, div, /;
+}
+
+impl std::ops::Neg for Sample {
+    type Output = Sample;
+
+    /// Negates float samples without changing their type. Integer samples are converted to `f32`.
+    #[inline]
+    fn neg(self) -> Sample {
+        match self {
+            Sample::F16(value) => Sample::F16(f16::from_bits(value.to_bits() ^ 0x8000)), // flip the sign bit
+            Sample::F32(value) => Sample::F32(-value),
+            Sample::U32(value) => Sample::F32(-(value as f32)),
+        }
+    }
 }
 
 impl PartialEq for Sample {
@@ -149,6 +213,13 @@ impl FromNativeSample for Sample {
     fn from_u32(value: u32) -> Self { Self::from(value) }
 }
 
+/// Used to read optional channels, where the default value `None` means that the file does not contain the channel.
+impl<T: FromNativeSample> FromNativeSample for Option<T> {
+    fn from_f16(value: f16) -> Self { Some(T::from_f16(value)) }
+    fn from_f32(value: f32) -> Self { Some(T::from_f32(value)) }
+    fn from_u32(value: u32) -> Self { Some(T::from_u32(value)) }
+}
+
 
 /// Convert any type into one of the supported sample types.
 /// Should be compiled to a no-op where the file contains the predicted sample type
@@ -188,5 +259,34 @@ impl IntoNativeSample for Sample {
     fn to_u32(&self) -> u32 { Sample::to_u32(*self) }
 }
 
+/// Writes the default sample where the value is `None`.
+impl<T: IntoNativeSample> IntoNativeSample for Option<T> {
+    fn to_f16(&self) -> f16 { self.unwrap_or_default().to_f16() }
+    fn to_f32(&self) -> f32 { self.unwrap_or_default().to_f32() }
+    fn to_u32(&self) -> u32 { self.unwrap_or_default().to_u32() }
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::prelude::*;
 
+    #[test]
+    fn promoting_arithmetic() {
+        let half = Sample::from(f16::from_f32(0.5));
 
+        assert_eq!(half + 2_u32, Sample::F32(2.5));
+        assert_eq!(half * Sample::F32(3.0), Sample::F32(1.5));
+        assert_eq!(Sample::U32(7) / 2_u32, Sample::F32(3.5), "integers are divided as floats");
+        assert_eq!(Sample::U32(1) - 3_u32, Sample::F32(-2.0), "integers do not underflow");
+        assert_eq!((-half).sample_type(), SampleType::F16);
+        assert!((half + 1.0_f32).sample_type() == SampleType::F32);
+
+        assert_eq!(Sample::U32(10).lerp(20_u32, 0.25), Sample::F32(12.5));
+        assert_eq!(half.lerp(f16::from_f32(1.0), 1.0), Sample::F32(1.0));
+
+        assert!(Sample::U32(u32::MAX).is_finite());
+        assert!(!Sample::F16(f16::INFINITY).is_finite());
+        assert!(!(Sample::F32(1.0) / 0.0_f32).is_finite());
+    }
+}
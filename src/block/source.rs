@@ -0,0 +1,309 @@
+//! Read exr files from sources that can fetch arbitrary byte ranges, such as object storages,
+//! without wrapping them into a `Read + Seek` implementation.
+//!
+//! Implement `ChunkSource` for your storage, and pass it to `ReadImage::from_source`.
+//! The meta data and the offset tables are read first, usually with a single call to `get_bytes`.
+//! Afterwards, each required chunk is fetched with a separate call.
+//! See `examples/9_object_storage.rs` for an implementation of a source.
+
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::io::{Read, Seek, SeekFrom, ErrorKind};
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::block::RequestedChannels;
+use crate::block::chunk::Chunk;
+use crate::block::pool::BlockBufferPool;
+use crate::block::reader::{ChunksReader, estimate_chunk_byte_counts, read_chunk_from_bytes};
+use crate::error::{Error, Result, IoError, IoResult};
+use crate::meta::MetaData;
+
+
+/// The default number of bytes requested at once by a `SourceReader`.
+/// Large enough to contain the meta data and the offset tables of most files.
+pub const DEFAULT_FETCH_SIZE: usize = 64 * 1024;
+
+
+/// A source of bytes that can fetch any range of bytes at once,
+/// for example a file in an object storage, or a file on a web server.
+pub trait ChunkSource {
+
+    /// Fetch the bytes in the range.
+    /// The returned bytes are shorter than the range only if the source ends before the range ends.
+    fn get_bytes(&mut self, bytes: Range<u64>) -> IoResult<Vec<u8>>;
+
+    /// The total number of bytes in the source.
+    /// Only required for seeking relative to the end. Returns an error by default.
+    fn byte_size(&mut self) -> IoResult<u64> {
+        Err(IoError::new(ErrorKind::Other, "the size of the chunk source is unknown"))
+    }
+}
+
+impl ChunkSource for &[u8] {
+    fn get_bytes(&mut self, bytes: Range<u64>) -> IoResult<Vec<u8>> {
+        let end = usize::try_from(bytes.end).unwrap_or(usize::MAX).min(self.len());
+        let start = usize::try_from(bytes.start).unwrap_or(usize::MAX).min(end);
+        Ok(self[start .. end].to_vec())
+    }
+
+    fn byte_size(&mut self) -> IoResult<u64> { Ok(self.len() as u64) }
+}
+
+impl ChunkSource for Vec<u8> {
+    fn get_bytes(&mut self, bytes: Range<u64>) -> IoResult<Vec<u8>> { self.as_slice().get_bytes(bytes) }
+    fn byte_size(&mut self) -> IoResult<u64> { Ok(self.len() as u64) }
+}
+
+#[cfg(any(unix, windows))]
+impl ChunkSource for std::fs::File {
+    fn get_bytes(&mut self, bytes: Range<u64>) -> IoResult<Vec<u8>> {
+        let byte_count = usize::try_from(bytes.end.saturating_sub(bytes.start))
+            .map_err(|_| IoError::new(ErrorKind::InvalidInput, "too many bytes requested"))?;
+
+        let mut buffer = vec![0; byte_count];
+        let mut filled_byte_count = 0;
+
+        while filled_byte_count < byte_count {
+            let position = bytes.start + filled_byte_count as u64;
+
+            match crate::block::unbuffered::read_at_position(self, &mut buffer[filled_byte_count ..], position) {
+                Ok(0) => break,
+                Ok(count) => filled_byte_count += count,
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error),
+            }
+        }
+
+        buffer.truncate(filled_byte_count);
+        Ok(buffer)
+    }
+
+    fn byte_size(&mut self) -> IoResult<u64> { Ok(self.metadata()?.len()) }
+}
+
+impl<S: ChunkSource + ?Sized> ChunkSource for &mut S {
+    fn get_bytes(&mut self, bytes: Range<u64>) -> IoResult<Vec<u8>> { S::get_bytes(self, bytes) }
+    fn byte_size(&mut self) -> IoResult<u64> { S::byte_size(self) }
+}
+
+impl<S: ChunkSource + ?Sized> ChunkSource for Box<S> {
+    fn get_bytes(&mut self, bytes: Range<u64>) -> IoResult<Vec<u8>> { S::get_bytes(self, bytes) }
+    fn byte_size(&mut self) -> IoResult<u64> { S::byte_size(self) }
+}
+
+
+/// Reads a chunk source like a file. Implements `Read` and `Seek`.
+/// Fetches at least `fetch_size` bytes at once, and buffers the most recently fetched bytes,
+/// so it should be passed to `from_buffered` or `block::read` without an additional buffer.
+#[derive(Debug)]
+pub struct SourceReader<S> {
+    source: S,
+    position: u64,
+    fetch_size: usize,
+    buffer: Vec<u8>,
+    buffer_start: u64,
+}
+
+impl<S: ChunkSource> SourceReader<S> {
+
+    /// Read the source from the start.
+    pub fn new(source: S) -> Self {
+        Self { source, position: 0, fetch_size: DEFAULT_FETCH_SIZE, buffer: Vec::new(), buffer_start: 0 }
+    }
+
+    /// Fetch at least this many bytes at once. Larger values require fewer calls to `get_bytes`,
+    /// but may fetch more bytes than required. The default is `DEFAULT_FETCH_SIZE`.
+    pub fn with_fetch_size(self, byte_count: usize) -> Self {
+        Self { fetch_size: byte_count.max(1), ..self }
+    }
+
+    /// The source of the bytes.
+    pub fn source(&self) -> &S { &self.source }
+
+    /// Obtain the source of the bytes.
+    pub fn into_source(self) -> S { self.source }
+}
+
+impl<S: ChunkSource> Read for SourceReader<S> {
+    fn read(&mut self, target: &mut [u8]) -> IoResult<usize> {
+        let buffer_end = self.buffer_start + self.buffer.len() as u64;
+
+        if self.position < self.buffer_start || self.position >= buffer_end {
+            let fetch_size = self.fetch_size.max(target.len()) as u64;
+            self.buffer = self.source.get_bytes(self.position .. self.position + fetch_size)?;
+            self.buffer_start = self.position;
+        }
+
+        let start = usize::try_from(self.position - self.buffer_start).expect("buffer position bug");
+        let count = (&self.buffer[start.min(self.buffer.len()) ..]).read(target)?;
+
+        self.position += count as u64;
+        Ok(count)
+    }
+}
+
+impl<S: ChunkSource> Seek for SourceReader<S> {
+    fn seek(&mut self, target: SeekFrom) -> IoResult<u64> {
+        let position = match target {
+            SeekFrom::Start(position) => Some(position),
+            SeekFrom::Current(offset) => offset_position(self.position, offset),
+            SeekFrom::End(offset) => offset_position(self.source.byte_size()?, offset),
+        };
+
+        self.position = position.ok_or_else(|| IoError::new(ErrorKind::InvalidInput, "seek before the start of the source"))?;
+        Ok(self.position)
+    }
+}
+
+fn offset_position(position: u64, offset: i64) -> Option<u64> {
+    if offset < 0 { position.checked_sub(offset.unsigned_abs()) }
+    else { position.checked_add(offset as u64) }
+}
+
+
+/// Fetches each chunk from a chunk source with a single call to `get_bytes`, where possible.
+/// The decoded chunks can be decompressed by calling
+/// `decompress_parallel`, `decompress_sequential`, or `sequential_decompressor` or `parallel_decompressor`.
+/// Also contains the image meta data.
+#[derive(Debug)]
+pub struct SourceChunksReader<S> {
+    meta_data: MetaData,
+    source: S,
+    remaining_requests: VecDeque<Range<u64>>,
+    expected_chunk_count: usize,
+    buffer_pool: Arc<dyn BlockBufferPool>,
+    requested_channels: Option<Arc<RequestedChannels>>,
+}
+
+impl<S: ChunkSource> SourceChunksReader<S> {
+
+    /// Fetch the chunks at the specified byte offsets. The offsets must be sorted.
+    pub(crate) fn new(
+        meta_data: MetaData, source: S, sorted_offsets: Vec<u64>,
+        buffer_pool: Arc<dyn BlockBufferPool>, requested_channels: Option<Arc<RequestedChannels>>,
+    ) -> Self
+    {
+        let byte_counts = estimate_chunk_byte_counts(&meta_data, &sorted_offsets);
+        let remaining_requests: VecDeque<Range<u64>> = sorted_offsets.into_iter().zip(byte_counts)
+            .map(|(offset, byte_count)| offset .. offset + byte_count as u64)
+            .collect();
+
+        Self {
+            meta_data, source,
+            expected_chunk_count: remaining_requests.len(),
+            remaining_requests, buffer_pool, requested_channels
+        }
+    }
+
+    /// The source of the bytes.
+    pub fn source(&self) -> &S { &self.source }
+
+    /// Fetch the chunk that starts at the beginning of the byte range.
+    /// The byte range is an estimation, and is extended if the chunk turns out to be larger.
+    fn fetch_chunk(&mut self, mut bytes: Range<u64>) -> Result<Chunk> {
+        loop {
+            let fetched = self.source.get_bytes(bytes.clone())?;
+
+            match read_chunk_from_bytes(&fetched, &self.meta_data, self.buffer_pool.as_ref()) {
+                Some(chunk) => return chunk,
+                None if (fetched.len() as u64) < bytes.end - bytes.start => return Err(Error::invalid("reference to missing bytes")),
+                None => bytes.end = bytes.start + (bytes.end - bytes.start) * 2,
+            }
+        }
+    }
+}
+
+impl<S: ChunkSource> ChunksReader for SourceChunksReader<S> {
+    fn meta_data(&self) -> &MetaData { &self.meta_data }
+    fn expected_chunk_count(&self) -> usize { self.expected_chunk_count }
+    fn buffer_pool(&self) -> Option<Arc<dyn BlockBufferPool>> { Some(self.buffer_pool.clone()) }
+    fn requested_channels(&self) -> Option<Arc<RequestedChannels>> { self.requested_channels.clone() }
+}
+
+impl<S: ChunkSource> ExactSizeIterator for SourceChunksReader<S> {}
+impl<S: ChunkSource> Iterator for SourceChunksReader<S> {
+    type Item = Result<Chunk>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bytes = self.remaining_requests.pop_front()?;
+        Some(self.fetch_chunk(bytes))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining_requests.len(), Some(self.remaining_requests.len()))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prelude::*;
+    use std::io::Cursor;
+
+    /// Counts the calls and the fetched bytes.
+    struct CountingSource { bytes: Vec<u8>, calls: usize, fetched_byte_count: u64 }
+
+    impl ChunkSource for CountingSource {
+        fn get_bytes(&mut self, bytes: Range<u64>) -> IoResult<Vec<u8>> {
+            let fetched = self.bytes.get_bytes(bytes)?;
+            self.calls += 1;
+            self.fetched_byte_count += fetched.len() as u64;
+            Ok(fetched)
+        }
+    }
+
+    #[test]
+    fn fetch_only_required_chunks() {
+        let size = Vec2(256, 512);
+        let channels = SpecificChannels::rgb(|position: Vec2<usize>| (position.x() as f32, position.y() as f32, 0.5_f32));
+        let image = Image::from_encoded_channels(size, Encoding::UNCOMPRESSED, channels);
+
+        let mut bytes = Vec::new();
+        image.write().non_parallel().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        let source = CountingSource { bytes: bytes.clone(), calls: 0, fetched_byte_count: 0 };
+        let chunks = crate::block::read(SourceReader::new(source), true).unwrap()
+            .filter_chunks(true, |_, _, block| (100 .. 102).contains(&block.pixel_position.y())).unwrap()
+            .fetch_chunks();
+
+        assert_eq!(chunks.source().calls, 1, "meta data and offset tables should be fetched at once");
+
+        let mut chunks = chunks;
+        let first = chunks.next().unwrap().unwrap();
+        let second = chunks.next().unwrap().unwrap();
+        assert!(chunks.next().is_none());
+
+        assert_eq!((first.layer_index, second.layer_index), (0, 0));
+        assert_eq!(chunks.source().calls, 3, "each chunk should be fetched at once");
+        assert!(chunks.source().fetched_byte_count < bytes.len() as u64 / 4, "fetched too many bytes");
+    }
+
+    #[test]
+    fn read_image_from_sources() {
+        let size = Vec2(64, 100);
+        let channels = SpecificChannels::rgb(|position: Vec2<usize>| (position.x() as f32, position.y() as f32, 0.5_f32));
+        let encoding = Encoding { blocks: Blocks::Tiles(Vec2(16, 16)), .. Encoding::FAST_LOSSLESS };
+        let image = Image::from_encoded_channels(size, encoding, channels);
+
+        let mut bytes = Vec::new();
+        image.write().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        let read_rgb = || read().no_deep_data().largest_resolution_level()
+            .rgb_channels(crate::image::pixel_vec::PixelVec::<(f32, f32, f32)>::constructor, crate::image::pixel_vec::PixelVec::set_pixel)
+            .first_valid_layer().all_attributes();
+
+        let from_slice = read_rgb().from_source(bytes.as_slice()).unwrap();
+        let from_vec = read_rgb().from_source(bytes.clone()).unwrap();
+        let from_buffer = read_rgb().from_buffered(Cursor::new(&bytes)).unwrap();
+
+        assert_eq!(from_slice, from_buffer);
+        assert_eq!(from_vec, from_buffer);
+        assert_eq!(*from_slice.layer_data.channel_data.pixels.get_pixel(Vec2(37, 81)), (37.0, 81.0, 0.5));
+
+        // a source that returns fewer bytes than the file contains
+        let truncated = &bytes[.. bytes.len() - 100];
+        assert!(read_rgb().from_source(truncated).is_err());
+    }
+}
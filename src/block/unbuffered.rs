@@ -0,0 +1,288 @@
+//! Read chunks from a file without the file cache of the operating system,
+//! keeping multiple reads in flight at once. This is meant for playing back image sequences,
+//! where each frame is read only once, and caching the files would only evict more useful data.
+//!
+//! On windows, the file is opened with `FILE_FLAG_NO_BUFFERING`, which requires
+//! every read to start and end at a multiple of the sector size, and the buffer to be aligned to the sector size.
+//! Therefore, each chunk is fetched with a read that is extended to the surrounding sectors.
+//! On other platforms, the file is opened normally, so the operating system still caches the file,
+//! but the chunks are still read in the same way, with multiple reads in flight at once.
+//!
+//! Use `ReadImage::unbuffered_io` to read a complete image this way,
+//! or `FilteredChunksReader::read_unbuffered` for the raw chunks.
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::block::RequestedChannels;
+use crate::block::chunk::Chunk;
+use crate::block::pool::BlockBufferPool;
+use crate::block::reader::{ChunksReader, estimate_chunk_byte_counts, read_chunk_from_bytes};
+use crate::error::{Error, Result, IoResult};
+use crate::meta::MetaData;
+
+
+/// The alignment of reads from unbuffered files, in bytes.
+/// This is a multiple of the sector size of all common drives, which have either 512 or 4096 bytes per sector.
+pub const SECTOR_SIZE: usize = 4096;
+
+/// The `FILE_FLAG_NO_BUFFERING` flag from the windows api.
+#[cfg(windows)]
+const FILE_FLAG_NO_BUFFERING: u32 = 0x2000_0000;
+
+
+/// Open a file for reading without the file cache of the operating system.
+/// Only on windows, the file cache is bypassed. On other platforms, the file is opened normally.
+/// Only reads that are aligned to the `SECTOR_SIZE` are allowed for the returned file.
+/// Use `crate::io::open_file` to read the meta data of the file.
+pub fn open_unbuffered(path: impl AsRef<Path>) -> Result<File> {
+    let path = path.as_ref();
+    let mut options = std::fs::OpenOptions::new();
+    options.read(true);
+
+    #[cfg(windows)] {
+        use std::os::windows::fs::OpenOptionsExt;
+        options.custom_flags(FILE_FLAG_NO_BUFFERING);
+    }
+
+    options.open(path).map_err(|error| crate::io::file_error(path, error))
+}
+
+
+/// Reads the chunks from a file opened with `open_unbuffered`, with multiple reads in flight at once.
+/// Each read starts and ends at a multiple of the `SECTOR_SIZE`.
+/// The chunks are returned in the order in which the reads complete, not in file order.
+/// The decoded chunks can be decompressed by calling
+/// `decompress_parallel`, `decompress_sequential`, or `sequential_decompressor` or `parallel_decompressor`.
+/// Dropping this value stops reading further chunks.
+/// Also contains the image meta data.
+#[derive(Debug)]
+pub struct UnbufferedChunksReader {
+    meta_data: MetaData,
+    receiver: flume::Receiver<Result<Chunk>>,
+    expected_chunk_count: usize,
+    remaining_chunk_count: usize,
+    buffer_pool: Arc<dyn BlockBufferPool>,
+    requested_channels: Option<Arc<RequestedChannels>>,
+}
+
+impl UnbufferedChunksReader {
+
+    /// Read the chunks at the specified byte offsets, with at most `queue_depth` reads in flight.
+    /// The offsets must be sorted. The file must contain the exr image described by the meta data.
+    /// Returns an error if the reading threads cannot be spawned.
+    pub(crate) fn new(
+        meta_data: MetaData, file: File, sorted_offsets: Vec<u64>, queue_depth: usize,
+        buffer_pool: Arc<dyn BlockBufferPool>, requested_channels: Option<Arc<RequestedChannels>>,
+    ) -> Result<Self>
+    {
+        let queue_depth = queue_depth.max(1);
+        let chunk_count = sorted_offsets.len();
+        let byte_counts = estimate_chunk_byte_counts(&meta_data, &sorted_offsets);
+
+        let (request_sender, request_receiver) = flume::unbounded();
+        for (offset, byte_count) in sorted_offsets.into_iter().zip(byte_counts) {
+            request_sender.send(offset .. offset + byte_count as u64).expect("request channel bug");
+        }
+
+        drop(request_sender); // the reading threads stop when all requests have been received
+
+        let (sender, receiver) = flume::bounded(queue_depth);
+        let file = Arc::new(file);
+        let shared_meta_data = Arc::new(meta_data.clone());
+
+        for _ in 0 .. queue_depth.min(chunk_count) {
+            let (file, meta_data, buffer_pool) = (file.clone(), shared_meta_data.clone(), buffer_pool.clone());
+            let (requests, sender) = (request_receiver.clone(), sender.clone());
+
+            std::thread::Builder::new()
+                .name("OpenEXR Unbuffered Chunk Reader".to_string())
+                .spawn(move || {
+                    for bytes in requests {
+                        let chunk = read_chunk(&file, bytes, &meta_data, buffer_pool.as_ref());
+
+                        // stop reading if the receiver has been dropped
+                        if sender.send(chunk).is_err() { break; }
+                    }
+                })?;
+        }
+
+        Ok(Self {
+            meta_data, receiver,
+            expected_chunk_count: chunk_count,
+            remaining_chunk_count: chunk_count,
+            buffer_pool, requested_channels,
+        })
+    }
+}
+
+/// Read the chunk that starts at the beginning of the byte range, using only reads that are aligned to the sector size.
+/// The byte range is an estimation, and is extended if the chunk turns out to be larger.
+fn read_chunk(file: &File, mut bytes: Range<u64>, meta_data: &MetaData, pool: &dyn BlockBufferPool) -> Result<Chunk> {
+    loop {
+        let sectors = align_to_sectors(bytes.clone());
+        let mut buffer = AlignedBuffer::new(usize::try_from(sectors.end - sectors.start)?);
+
+        let end_of_file = read_at(file, &mut buffer, sectors.start)?;
+        let chunk_start = usize::try_from(bytes.start - sectors.start)?;
+        let chunk_bytes = &buffer.bytes()[chunk_start.min(buffer.len()) ..];
+
+        match read_chunk_from_bytes(chunk_bytes, meta_data, pool) {
+            Some(chunk) => return chunk,
+            None if end_of_file => return Err(Error::invalid("reference to missing bytes")),
+            None => bytes.end = bytes.start + (bytes.end - bytes.start) * 2,
+        }
+    }
+}
+
+/// Extend the byte range to the start of its first sector and to the end of its last sector.
+fn align_to_sectors(bytes: Range<u64>) -> Range<u64> {
+    let sector_size = SECTOR_SIZE as u64;
+    let start = bytes.start / sector_size * sector_size;
+    let end = (bytes.end + sector_size - 1) / sector_size * sector_size;
+    start .. end.max(start + sector_size)
+}
+
+/// Fill the buffer with the bytes at the position in the file, with as many reads as necessary.
+/// Truncates the buffer and returns true if the file ends before the buffer is full.
+fn read_at(file: &File, buffer: &mut AlignedBuffer, position: u64) -> IoResult<bool> {
+    let mut filled_byte_count = 0;
+
+    while filled_byte_count < buffer.len() {
+        let unfilled = &mut buffer.bytes_mut()[filled_byte_count ..];
+        let result = read_at_position(file, unfilled, position + filled_byte_count as u64);
+
+        match result {
+            Ok(0) => {
+                buffer.truncate(filled_byte_count);
+                return Ok(true);
+            },
+
+            Ok(byte_count) => filled_byte_count += byte_count,
+            Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error),
+        }
+    }
+
+    Ok(false)
+}
+
+/// Read bytes at the position in the file, without changing the cursor position on unix.
+#[cfg(unix)]
+pub(crate) fn read_at_position(file: &File, buffer: &mut [u8], position: u64) -> IoResult<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buffer, position)
+}
+
+/// Read bytes at the position in the file. Moves the cursor position on windows.
+#[cfg(windows)]
+pub(crate) fn read_at_position(file: &File, buffer: &mut [u8], position: u64) -> IoResult<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buffer, position)
+}
+
+/// A byte buffer whose first byte is aligned to the `SECTOR_SIZE`.
+/// Allocates a few more bytes than required, and skips the unaligned bytes at the start.
+#[derive(Debug)]
+struct AlignedBuffer {
+    allocation: Vec<u8>,
+    start: usize,
+    len: usize,
+}
+
+impl AlignedBuffer {
+    fn new(len: usize) -> Self {
+        let allocation = vec![0; len + SECTOR_SIZE];
+        let start = allocation.as_ptr().align_offset(SECTOR_SIZE);
+        assert!(start < SECTOR_SIZE, "buffer alignment bug");
+        Self { allocation, start, len }
+    }
+
+    fn len(&self) -> usize { self.len }
+    fn truncate(&mut self, len: usize) { self.len = self.len.min(len); }
+    fn bytes(&self) -> &[u8] { &self.allocation[self.start .. self.start + self.len] }
+    fn bytes_mut(&mut self) -> &mut [u8] { &mut self.allocation[self.start .. self.start + self.len] }
+}
+
+impl ChunksReader for UnbufferedChunksReader {
+    fn meta_data(&self) -> &MetaData { &self.meta_data }
+    fn expected_chunk_count(&self) -> usize { self.expected_chunk_count }
+    fn buffer_pool(&self) -> Option<Arc<dyn BlockBufferPool>> { Some(self.buffer_pool.clone()) }
+    fn requested_channels(&self) -> Option<Arc<RequestedChannels>> { self.requested_channels.clone() }
+}
+
+impl ExactSizeIterator for UnbufferedChunksReader {}
+impl Iterator for UnbufferedChunksReader {
+    type Item = Result<Chunk>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining_chunk_count == 0 { return None; }
+
+        let chunk = self.receiver.recv().ok()?;
+        self.remaining_chunk_count -= 1;
+        Some(chunk)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining_chunk_count, Some(self.remaining_chunk_count))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prelude::*;
+    use std::io::BufReader;
+
+    #[test]
+    fn align_byte_ranges_to_sectors() {
+        assert_eq!(align_to_sectors(0 .. 10), 0 .. 4096);
+        assert_eq!(align_to_sectors(4000 .. 4100), 0 .. 8192);
+        assert_eq!(align_to_sectors(4096 .. 8192), 4096 .. 8192);
+        assert_eq!(align_to_sectors(5000 .. 5000), 4096 .. 8192);
+
+        let buffer = AlignedBuffer::new(100);
+        assert_eq!(buffer.bytes().as_ptr() as usize % SECTOR_SIZE, 0);
+        assert_eq!(buffer.len(), 100);
+    }
+
+    #[test]
+    fn read_unbuffered_chunks() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("read_unbuffered_chunks.exr");
+
+        let size = Vec2(80, 300);
+        let channels = SpecificChannels::rgb(|position: Vec2<usize>| (position.x() as f32, position.y() as f32, 0.5_f32));
+        let image = Image::from_encoded_channels(size, Encoding::SMALL_LOSSLESS, channels);
+        image.write().non_parallel().to_file(&path).unwrap();
+
+        let expected_blocks: Vec<_> = crate::block::read(BufReader::new(File::open(&path).unwrap()), true).unwrap()
+            .all_chunks(true).unwrap().sequential_decompressor(true)
+            .collect::<crate::error::Result<_>>().unwrap();
+
+        for &queue_depth in &[ 1, 4 ] {
+            let chunks = crate::block::read(BufReader::new(File::open(&path).unwrap()), true).unwrap()
+                .filter_chunks(true, |_, _, _| true).unwrap()
+                .read_unbuffered(open_unbuffered(&path).unwrap(), queue_depth).unwrap();
+
+            assert_eq!(chunks.len(), expected_blocks.len());
+
+            let blocks: Vec<_> = chunks.sequential_decompressor(true).collect::<crate::error::Result<_>>().unwrap();
+            assert_eq!(blocks.len(), expected_blocks.len());
+            for block in &blocks { assert!(expected_blocks.contains(block)); }
+        }
+
+        // read each chunk with multiple requests, as if the size estimation was too small
+        let mut meta_reader = crate::io::PeekRead::new(BufReader::new(File::open(&path).unwrap()));
+        let meta_data = MetaData::read_validated_from_buffered_peekable(&mut meta_reader, true).unwrap();
+        let offset_tables = MetaData::read_offset_tables(&mut meta_reader, &meta_data.headers).unwrap();
+
+        let file = open_unbuffered(&path).unwrap();
+        for &offset in offset_tables.iter().flatten() {
+            read_chunk(&file, offset .. offset + 3, &meta_data, &crate::block::pool::HeapBuffers).unwrap();
+        }
+    }
+}
@@ -0,0 +1,355 @@
+//! Read chunks from a file on linux using `io_uring`, keeping many reads in flight at once.
+//! This keeps fast storage, such as NVMe drives, busy while the previous chunks are decompressed.
+//! Enable the `io-uring` feature to use this module.
+//!
+//! Use `ReadImage::io_uring` to read a complete image this way,
+//! or `FilteredChunksReader::read_with_io_uring` for the raw chunks.
+//! If `io_uring` is not available, for example because a container forbids it,
+//! the chunks are read one after another with positional reads instead.
+
+#![allow(unsafe_code)] // submitting requests to the kernel requires the buffers to stay alive, see `Drop`
+
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use std::sync::Arc;
+
+use io_uring::{IoUring, opcode, types};
+
+use crate::block::RequestedChannels;
+use crate::block::chunk::Chunk;
+use crate::block::pool::BlockBufferPool;
+use crate::block::reader::{ChunksReader, estimate_chunk_byte_counts, read_chunk_from_bytes};
+use crate::error::{Error, Result, IoError, IoResult};
+use crate::meta::MetaData;
+
+
+/// Reads the chunks from a file with many reads in flight at once.
+/// The chunks are returned in the order in which the reads complete, not in file order.
+/// The decoded chunks can be decompressed by calling
+/// `decompress_parallel`, `decompress_sequential`, or `sequential_decompressor` or `parallel_decompressor`.
+/// Also contains the image meta data.
+pub struct UringChunksReader {
+    meta_data: MetaData,
+    file: File,
+
+    /// Is `None` if `io_uring` is not available, in which case the chunks are read synchronously.
+    ring: Option<IoUring>,
+
+    /// The reads that have not been submitted yet.
+    remaining_requests: VecDeque<ChunkRequest>,
+
+    /// The reads currently owned by the kernel, indexed by the user data of the submission.
+    in_flight_requests: Vec<Option<ChunkRequest>>,
+    in_flight_count: usize,
+
+    expected_chunk_count: usize,
+    remaining_chunk_count: usize,
+    buffer_pool: Arc<dyn BlockBufferPool>,
+    requested_channels: Option<Arc<RequestedChannels>>,
+}
+
+/// The bytes of a single chunk, which may be read with multiple requests.
+#[derive(Debug)]
+struct ChunkRequest {
+    offset: u64,
+    bytes: Vec<u8>,
+    filled_byte_count: usize,
+    end_of_file: bool,
+}
+
+/// Whether a chunk request needs more bytes.
+enum Progress {
+    Incomplete,
+    Complete(Result<Chunk>),
+}
+
+impl UringChunksReader {
+
+    /// Read the chunks at the specified byte offsets, with at most `queue_depth` reads in flight.
+    /// The offsets must be sorted. The file must contain the exr image described by the meta data.
+    pub(crate) fn new(
+        meta_data: MetaData, file: File, sorted_offsets: Vec<u64>, queue_depth: usize,
+        buffer_pool: Arc<dyn BlockBufferPool>, requested_channels: Option<Arc<RequestedChannels>>,
+    ) -> Self
+    {
+        let queue_depth = queue_depth.clamp(1, 4096);
+
+        let byte_counts = estimate_chunk_byte_counts(&meta_data, &sorted_offsets);
+        let remaining_requests: VecDeque<ChunkRequest> = sorted_offsets.into_iter().zip(byte_counts)
+            .map(|(offset, byte_count)| ChunkRequest::new(offset, byte_count))
+            .collect();
+
+        let ring = u32::try_from(queue_depth).ok().and_then(|entries| IoUring::new(entries).ok());
+        let chunk_count = remaining_requests.len();
+
+        Self {
+            meta_data, file, ring, remaining_requests,
+            in_flight_requests: (0 .. queue_depth).map(|_| None).collect(),
+            in_flight_count: 0,
+            expected_chunk_count: chunk_count,
+            remaining_chunk_count: chunk_count,
+            buffer_pool, requested_channels,
+        }
+    }
+
+    /// Whether the chunks are read using `io_uring`.
+    /// Returns false if the kernel does not support `io_uring`, or it is not allowed,
+    /// in which case the chunks are read one after another.
+    pub fn uses_io_uring(&self) -> bool { self.ring.is_some() }
+
+    /// Submit new requests until the queue is full or no chunks are left.
+    fn submit_remaining_requests(&mut self) -> IoResult<()> {
+        while self.in_flight_count < self.in_flight_requests.len() {
+            let request = match self.remaining_requests.pop_front() {
+                Some(request) => request,
+                None => break,
+            };
+
+            let slot = self.in_flight_requests.iter().position(Option::is_none)
+                .expect("in flight count bug");
+
+            self.submit(slot, request)?;
+        }
+
+        Ok(())
+    }
+
+    /// Pass the unfilled bytes of the request to the kernel, and remember the request until it completes.
+    fn submit(&mut self, slot: usize, mut request: ChunkRequest) -> IoResult<()> {
+        let ring = self.ring.as_mut().expect("submitted without io_uring");
+
+        let (position, buffer) = request.unfilled_bytes();
+        let byte_count = u32::try_from(buffer.len()).unwrap_or(u32::MAX);
+
+        let entry = opcode::Read::new(types::Fd(self.file.as_raw_fd()), buffer.as_mut_ptr(), byte_count)
+            .offset(position).build().user_data(slot as u64);
+
+        // the heap buffer of the request does not move when the request is moved into the slot,
+        // and it is neither resized nor dropped until the kernel has completed this read (see `Drop`)
+        unsafe { ring.submission().push(&entry) }
+            .map_err(|_| IoError::new(ErrorKind::Other, "io_uring submission queue is full"))?;
+
+        debug_assert!(self.in_flight_requests[slot].is_none(), "slot already in use");
+        self.in_flight_requests[slot] = Some(request);
+        self.in_flight_count += 1;
+        Ok(())
+    }
+
+    /// Block until the next read has completed, then return the slot of the request and the number of bytes read.
+    fn wait_for_completion(&mut self) -> IoResult<(usize, IoResult<usize>)> {
+        let ring = self.ring.as_mut().expect("waited without io_uring");
+
+        loop {
+            if let Some(entry) = ring.completion().next() {
+                let slot = usize::try_from(entry.user_data()).expect("user data bug");
+                let result = entry.result();
+
+                let result = if result < 0 { Err(IoError::from_raw_os_error(-result)) }
+                    else { Ok(usize::try_from(result).expect("read byte count bug")) };
+
+                self.in_flight_count -= 1;
+                return Ok((slot, result));
+            }
+
+            match ring.submit_and_wait(1) {
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error),
+                Ok(_) => continue,
+            }
+        }
+    }
+
+    /// Read the next chunk using `io_uring`.
+    fn next_from_ring(&mut self) -> Option<Result<Chunk>> {
+        loop {
+            if let Err(error) = self.submit_remaining_requests() { return Some(Err(error.into())); }
+            if self.in_flight_count == 0 { return None; }
+
+            let (slot, result) = match self.wait_for_completion() {
+                Ok(completion) => completion,
+                Err(error) => return Some(Err(error.into())),
+            };
+
+            let mut request = self.in_flight_requests[slot].take().expect("completion of unknown request");
+
+            let byte_count = match result {
+                Ok(byte_count) => byte_count,
+                Err(error) if error.kind() == ErrorKind::Interrupted => { // try again
+                    if let Err(error) = self.submit(slot, request) { return Some(Err(error.into())); }
+                    continue;
+                },
+                Err(error) => return Some(Err(error.into())),
+            };
+
+            match request.on_bytes_read(byte_count, &self.meta_data, self.buffer_pool.as_ref()) {
+                Progress::Complete(chunk) => return Some(chunk),
+                Progress::Incomplete => {
+                    if let Err(error) = self.submit(slot, request) { return Some(Err(error.into())); }
+                },
+            }
+        }
+    }
+
+    /// Read the next chunk synchronously, in case `io_uring` is not available.
+    fn next_from_file(&mut self) -> Option<Result<Chunk>> {
+        let mut request = self.remaining_requests.pop_front()?;
+
+        loop {
+            let (position, buffer) = request.unfilled_bytes();
+
+            let byte_count = match self.file.read_at(buffer, position) {
+                Ok(byte_count) => byte_count,
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => return Some(Err(error.into())),
+            };
+
+            if let Progress::Complete(chunk) = request.on_bytes_read(byte_count, &self.meta_data, self.buffer_pool.as_ref()) {
+                return Some(chunk);
+            }
+        }
+    }
+}
+
+impl ChunkRequest {
+    fn new(offset: u64, byte_count: usize) -> Self {
+        Self { offset, bytes: vec![0; byte_count.max(1)], filled_byte_count: 0, end_of_file: false }
+    }
+
+    /// The file position and the buffer of the bytes that have not been read yet.
+    fn unfilled_bytes(&mut self) -> (u64, &mut [u8]) {
+        (self.offset + self.filled_byte_count as u64, &mut self.bytes[self.filled_byte_count ..])
+    }
+
+    /// Parse the chunk if all requested bytes have been read.
+    /// Requests more bytes if the chunk is larger than estimated.
+    fn on_bytes_read(&mut self, byte_count: usize, meta_data: &MetaData, pool: &dyn BlockBufferPool) -> Progress {
+        if byte_count == 0 { self.end_of_file = true; }
+        self.filled_byte_count += byte_count;
+
+        if self.filled_byte_count < self.bytes.len() && !self.end_of_file {
+            return Progress::Incomplete;
+        }
+
+        match read_chunk_from_bytes(&self.bytes[.. self.filled_byte_count], meta_data, pool) {
+            Some(chunk) => Progress::Complete(chunk),
+            None if self.end_of_file => Progress::Complete(Err(Error::invalid("reference to missing bytes"))),
+
+            None => {
+                let byte_count = self.bytes.len() * 2;
+                self.bytes.resize(byte_count, 0);
+                Progress::Incomplete
+            },
+        }
+    }
+}
+
+impl ChunksReader for UringChunksReader {
+    fn meta_data(&self) -> &MetaData { &self.meta_data }
+    fn expected_chunk_count(&self) -> usize { self.expected_chunk_count }
+    fn buffer_pool(&self) -> Option<Arc<dyn BlockBufferPool>> { Some(self.buffer_pool.clone()) }
+    fn requested_channels(&self) -> Option<Arc<RequestedChannels>> { self.requested_channels.clone() }
+}
+
+impl ExactSizeIterator for UringChunksReader {}
+impl Iterator for UringChunksReader {
+    type Item = Result<Chunk>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let chunk = if self.ring.is_some() { self.next_from_ring() } else { self.next_from_file() };
+        if chunk.is_some() { self.remaining_chunk_count = self.remaining_chunk_count.saturating_sub(1); }
+        chunk
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining_chunk_count, Some(self.remaining_chunk_count))
+    }
+}
+
+impl Drop for UringChunksReader {
+    fn drop(&mut self) {
+        // the kernel may still write to the buffers of the requests in flight,
+        // so they must not be dropped before the reads have completed
+        while self.in_flight_count > 0 {
+            match self.wait_for_completion() {
+                Ok((slot, _)) => { self.in_flight_requests[slot] = None; },
+                Err(_) => {
+                    // cannot know when the kernel is done, so never free the buffers
+                    std::mem::forget(std::mem::take(&mut self.in_flight_requests));
+                    break;
+                },
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for UringChunksReader {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        formatter.debug_struct("UringChunksReader")
+            .field("meta_data", &self.meta_data)
+            .field("uses_io_uring", &self.uses_io_uring())
+            .field("in_flight_count", &self.in_flight_count)
+            .field("remaining_chunk_count", &self.remaining_chunk_count)
+            .finish()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prelude::*;
+    use std::io::BufReader;
+
+    #[test]
+    fn read_chunks_with_io_uring() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("read_chunks_with_io_uring.exr");
+
+        let size = Vec2(80, 300);
+        let channels = SpecificChannels::rgb(|position: Vec2<usize>| (position.x() as f32, position.y() as f32, 0.5_f32));
+        let image = Image::from_encoded_channels(size, Encoding::SMALL_LOSSLESS, channels);
+        image.write().non_parallel().to_file(&path).unwrap();
+
+        let expected_blocks: Vec<_> = crate::block::read(BufReader::new(File::open(&path).unwrap()), true).unwrap()
+            .all_chunks(true).unwrap().sequential_decompressor(true)
+            .collect::<crate::error::Result<_>>().unwrap();
+
+        let open_chunks = |queue_depth: usize| {
+            let file = File::open(&path).unwrap();
+            crate::block::read(BufReader::new(file.try_clone().unwrap()), true).unwrap()
+                .filter_chunks(true, |_, _, _| true).unwrap()
+                .read_with_io_uring(file, queue_depth)
+        };
+
+        let check_chunks = |chunks: UringChunksReader| {
+            assert_eq!(chunks.len(), expected_blocks.len());
+
+            let blocks: Vec<_> = chunks.sequential_decompressor(true).collect::<crate::error::Result<_>>().unwrap();
+            assert_eq!(blocks.len(), expected_blocks.len());
+            for block in &blocks { assert!(expected_blocks.contains(block)); }
+        };
+
+        for &queue_depth in &[ 1, 3, 64 ] {
+            check_chunks(open_chunks(queue_depth));
+
+            // read each chunk with multiple requests, as if the size estimation was too small
+            let mut small_requests = open_chunks(queue_depth);
+            for request in &mut small_requests.remaining_requests { request.bytes.truncate(5); }
+            check_chunks(small_requests);
+
+            // read without io_uring, as if the kernel did not allow it
+            let mut synchronous = open_chunks(queue_depth);
+            synchronous.ring = None;
+            check_chunks(synchronous);
+        }
+
+        // stop reading while requests are in flight
+        let mut chunks = open_chunks(8);
+        assert!(chunks.next().unwrap().is_ok());
+        drop(chunks);
+    }
+}
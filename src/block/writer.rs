@@ -9,11 +9,11 @@ use std::ops::Not;
 use smallvec::alloc::collections::BTreeMap;
 use smallvec::alloc::sync::Arc;
 
-use crate::block::UncompressedBlock;
+use crate::block::{UncompressedBlock, default_thread_count};
 use crate::block::chunk::{Chunk};
 use crate::compression::Compression;
 use crate::error::{Error, Result, UnitResult, usize_to_u64};
-use crate::io::{Data, Tracking, Write};
+use crate::io::{Data, Tracking, Write, ForwardWrite};
 use crate::meta::{Headers, MetaData, OffsetTables};
 use crate::meta::attribute::LineOrder;
 
@@ -24,12 +24,61 @@ pub fn write_chunks_with<W: Write + Seek>(
     buffered_write: W, headers: Headers, pedantic: bool,
     write_chunks: impl FnOnce(MetaData, &mut ChunkWriter<W>) -> UnitResult
 ) -> UnitResult {
+    write_chunks_with_offset_tables(buffered_write, headers, pedantic, OffsetTablePlacement::default(), write_chunks)
+}
+
+/// Write an exr file by writing one chunk after another in a closure,
+/// choosing how the offset tables are written. See `OffsetTablePlacement`.
+pub fn write_chunks_with_offset_tables<W: Write + Seek>(
+    buffered_write: W, headers: Headers, pedantic: bool, offset_tables: OffsetTablePlacement,
+    write_chunks: impl FnOnce(MetaData, &mut ChunkWriter<W>) -> UnitResult
+) -> UnitResult {
+    trace_span!(DEBUG, "write image", layers = headers.len(), pedantic);
+
     // this closure approach ensures that after writing all chunks, the file is always completed and checked and flushed
-    let (meta, mut writer) = ChunkWriter::new_for_buffered(buffered_write, headers, pedantic)?;
+    let (meta, mut writer) = ChunkWriter::new_for_buffered(buffered_write, headers, pedantic, offset_tables)?;
     write_chunks(meta, &mut writer)?;
     writer.complete_meta_data()
 }
 
+/// Write an exr file to a byte destination that cannot seek, such as standard output or a pipe,
+/// by writing one chunk after another in a closure.
+/// The offset tables are written as `OffsetTablePlacement::Zeroed`, so the destination is never seeked.
+pub fn write_chunks_to_stream<W: Write>(
+    buffered_stream: W, headers: Headers, pedantic: bool,
+    write_chunks: impl FnOnce(MetaData, &mut ChunkWriter<ForwardWrite<W>>) -> UnitResult
+) -> UnitResult {
+    write_chunks_with_offset_tables(ForwardWrite::new(buffered_stream), headers, pedantic, OffsetTablePlacement::Zeroed, write_chunks)
+}
+
+/// How the offset tables are written, which contain the byte position of each chunk in the file.
+///
+/// The exr format requires the offset tables to directly follow the meta data, before the first chunk.
+/// No attribute can point to offset tables at a different location, so a file with the offset tables
+/// after the chunks could not be read by any implementation. For that reason, appending
+/// the offset tables after the last chunk is not offered. Instead, the offset tables can be left empty:
+/// OpenEXR reconstructs empty offset tables by reading all chunks, as it does for incomplete files,
+/// and this crate does the same when not reading pedantically.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OffsetTablePlacement {
+
+    /// Reserve space for the offset tables after the meta data, write all chunks,
+    /// and then seek back to fill in the offset tables. This is the default.
+    /// All implementations can read these files, and can seek directly to any chunk.
+    Backfilled,
+
+    /// Write zeroes in place of the offset tables, and never seek back.
+    /// Use this for destinations that can only be appended to, such as files on append-only file systems.
+    /// Destinations that cannot seek at all, such as pipes, can be wrapped in a `ForwardWrite`, see `write_chunks_to_stream`.
+    /// Readers must reconstruct the offset tables by reading all chunks before they can skip any chunk.
+    /// Pedantic readers and some other implementations reject these files.
+    Zeroed,
+}
+
+impl Default for OffsetTablePlacement {
+    fn default() -> Self { OffsetTablePlacement::Backfilled }
+}
+
 /// Can consume compressed pixel chunks, writing them a file.
 /// Use `sequential_blocks_compressor` or `parallel_blocks_compressor` to compress your data,
 /// or use `compress_all_blocks_sequential` or `compress_all_blocks_parallel`.
@@ -41,9 +90,10 @@ pub fn write_chunks_with<W: Write + Seek>(
 pub struct ChunkWriter<W> {
     header_count: usize,
     byte_writer: Tracking<W>,
-    chunk_indices_byte_location: std::ops::Range<usize>,
+    chunk_indices_byte_location: std::ops::Range<u64>,
     chunk_indices_increasing_y: OffsetTables,
     chunk_count: usize, // TODO compose?
+    offset_tables: OffsetTablePlacement,
 }
 
 /// A new writer that triggers a callback
@@ -81,9 +131,16 @@ pub trait ChunksWriter: Sized {
 
     /// Obtain a new writer that can compress blocks to chunks on multiple threads, which are then passed to this writer.
     /// Returns none if the sequential compressor should be used instead (thread pool creation failure or too large performance overhead).
+    /// By default, this uses as many threads as there are CPUs, see `default_thread_count`.
     fn parallel_blocks_compressor<'w>(&'w mut self, meta: &'w MetaData) -> Option<ParallelBlocksCompressor<'w, Self>> {
+        self.parallel_blocks_compressor_with_threads(meta, default_thread_count())
+    }
+
+    /// Like `parallel_blocks_compressor`, but uses at most the specified number of threads.
+    fn parallel_blocks_compressor_with_threads<'w>(&'w mut self, meta: &'w MetaData, thread_count: usize) -> Option<ParallelBlocksCompressor<'w, Self>> {
         let pool = threadpool::Builder::new()
             .thread_name("OpenEXR Block Compressor".to_string())
+            .num_threads(thread_count.max(1))
             // todo no more threads than remaining block count (self.len())
             .build();
 
@@ -108,8 +165,17 @@ pub trait ChunksWriter: Sized {
     /// Compresses all blocks to the file.
     /// The index of the block must be in increasing line order within the header.
     /// Obtain iterator with `MetaData::collect_ordered_blocks(...)` or similar methods.
-    fn compress_all_blocks_parallel(mut self, meta: &MetaData, blocks: impl Iterator<Item=(usize, UncompressedBlock)>) -> UnitResult {
-        let mut parallel_writer = match self.parallel_blocks_compressor(meta) {
+    fn compress_all_blocks_parallel(self, meta: &MetaData, blocks: impl Iterator<Item=(usize, UncompressedBlock)>) -> UnitResult {
+        self.compress_all_blocks_parallel_with_threads(meta, default_thread_count(), blocks)
+    }
+
+    /// Like `compress_all_blocks_parallel`, but uses at most the specified number of threads.
+    fn compress_all_blocks_parallel_with_threads(
+        mut self, meta: &MetaData, thread_count: usize,
+        blocks: impl Iterator<Item=(usize, UncompressedBlock)>
+    ) -> UnitResult
+    {
+        let mut parallel_writer = match self.parallel_blocks_compressor_with_threads(meta, thread_count) {
             None => return self.compress_all_blocks_sequential(meta, blocks),
             Some(writer) => writer,
         };
@@ -146,7 +212,7 @@ impl<W> ChunksWriter for ChunkWriter<W> where W: Write + Seek {
             return Err(Error::invalid(format!("chunk at index {} is already written", index_in_header_increasing_y)));
         }
 
-        *chunk_index_slot = usize_to_u64(self.byte_writer.byte_position());
+        *chunk_index_slot = self.byte_writer.byte_position();
         chunk.write(&mut self.byte_writer, self.header_count)?;
         Ok(())
     }
@@ -156,7 +222,7 @@ impl<W> ChunkWriter<W> where W: Write + Seek {
     // -- the following functions are private, because they must be called in a strict order --
 
     /// Writes the meta data and zeroed offset tables as a placeholder.
-    fn new_for_buffered(buffered_byte_writer: W, headers: Headers, pedantic: bool) -> Result<(MetaData, Self)> {
+    fn new_for_buffered(buffered_byte_writer: W, headers: Headers, pedantic: bool, offset_tables: OffsetTablePlacement) -> Result<(MetaData, Self)> {
         let mut write = Tracking::new(buffered_byte_writer);
         let requirements = MetaData::write_validating_to_buffered(&mut write, headers.as_slice(), pedantic)?;
 
@@ -173,7 +239,7 @@ impl<W> ChunkWriter<W> where W: Write + Seek {
         let offset_table_size: usize = headers.iter().map(|header| header.chunk_count).sum();
 
         let offset_table_start_byte = write.byte_position();
-        let offset_table_end_byte = write.byte_position() + offset_table_size * u64::BYTE_SIZE;
+        let offset_table_end_byte = offset_table_start_byte + usize_to_u64(offset_table_size) * usize_to_u64(u64::BYTE_SIZE);
 
         // skip offset tables, filling with 0, will be updated after the last chunk has been written
         write.seek_write_to(offset_table_end_byte)?;
@@ -190,9 +256,36 @@ impl<W> ChunkWriter<W> where W: Write + Seek {
             chunk_count: offset_table_size,
             chunk_indices_byte_location: offset_table_start_byte .. offset_table_end_byte,
             chunk_indices_increasing_y,
+            offset_tables,
         }))
     }
 
+    /// Write the offset tables of all chunks written so far, and flush the byte writer.
+    /// Afterwards, more chunks can be written as usual.
+    ///
+    /// If the process is interrupted later on, for example by a crash during a long render,
+    /// the file contains all chunks written before the checkpoint, and can be read without pedantic checks.
+    /// Chunks that are not written yet have an offset of zero in the offset table, and are skipped when reading,
+    /// such that their pixels keep their default values.
+    /// With `OffsetTablePlacement::Zeroed`, this only flushes the byte writer, as the offset tables are never written.
+    pub fn checkpoint(&mut self) -> UnitResult {
+        if self.offset_tables == OffsetTablePlacement::Zeroed {
+            self.byte_writer.flush()?;
+            return Ok(());
+        }
+
+        let end_byte = self.byte_writer.byte_position();
+        self.byte_writer.seek_write_to(self.chunk_indices_byte_location.start)?;
+
+        for table in &self.chunk_indices_increasing_y {
+            u64::write_slice(&mut self.byte_writer, table.as_slice())?;
+        }
+
+        self.byte_writer.seek_write_to_written(end_byte)?;
+        self.byte_writer.flush()?;
+        Ok(())
+    }
+
     /// Seek back to the meta data, write offset tables, and flush the byte writer.
     /// Leaves the writer seeked to the middle of the file.
     fn complete_meta_data(mut self) -> UnitResult {
@@ -200,6 +293,11 @@ impl<W> ChunkWriter<W> where W: Write + Seek {
             return Err(Error::invalid("some chunks are not written yet"))
         }
 
+        if self.offset_tables == OffsetTablePlacement::Zeroed {
+            self.byte_writer.flush()?;
+            return Ok(());
+        }
+
         // write all offset tables
         debug_assert_ne!(self.byte_writer.byte_position(), self.chunk_indices_byte_location.end, "offset table has already been updated");
         self.byte_writer.seek_write_to(self.chunk_indices_byte_location.start)?;
@@ -338,7 +436,6 @@ impl<'w, W> SequentialBlocksCompressor<'w, W> where W: 'w + ChunksWriter {
 #[derive(Debug)]
 #[must_use]
 pub struct ParallelBlocksCompressor<'w, W> {
-    meta: &'w MetaData,
     sorted_writer: SortedBlocksWriter<'w, W>,
 
     sender: flume::Sender<Result<(usize, usize, Chunk)>>,
@@ -357,10 +454,12 @@ impl<'w, W> ParallelBlocksCompressor<'w, W> where W: 'w + ChunksWriter {
     /// New blocks writer. Returns none if sequential compression should be used.
     pub fn new(meta: &'w MetaData, chunks_writer: &'w mut W, pool: threadpool::ThreadPool) -> Option<Self> {
         if meta.headers.iter().all(|head|head.compression == Compression::Uncompressed) {
+            trace_event!(DEBUG, "all layers are uncompressed, compressing sequentially");
             return None;
         }
 
         let max_threads = pool.max_count().max(1).min(chunks_writer.total_chunks_count()) + 2; // ca one block for each thread at all times
+        trace_event!(DEBUG, threads = pool.max_count(), max_jobs = max_threads, "compressing blocks in parallel");
         let (send, recv) = flume::unbounded(); // TODO bounded channel simplifies logic?
 
         Some(Self {
@@ -373,7 +472,6 @@ impl<'w, W> ParallelBlocksCompressor<'w, W> where W: 'w + ChunksWriter {
             receiver: recv,
             max_threads,
             pool,
-            meta,
         })
     }
 
@@ -391,8 +489,10 @@ impl<'w, W> ParallelBlocksCompressor<'w, W> where W: 'w + ChunksWriter {
             Use non-parallel decompression to see panic messages."
         );
 
-        let some_compressed_chunk = self.receiver.recv()
-            .expect("cannot receive compressed block");
+        let some_compressed_chunk = {
+            trace_span!(TRACE, "wait for compressed block", in_flight = self.currently_compressing_count);
+            self.receiver.recv().expect("cannot receive compressed block")
+        };
 
         self.currently_compressing_count -= 1;
         let (chunk_file_index, chunk_y_index, chunk) = some_compressed_chunk?;
@@ -426,9 +526,12 @@ impl<'w, W> ParallelBlocksCompressor<'w, W> where W: 'w + ChunksWriter {
         // add the argument chunk to the compression queueue
         let index_in_file = self.next_incoming_chunk_index;
         let sender = self.sender.clone();
-        let meta = self.meta.clone();
+        let meta = self.shared_meta_data_ref.clone();
 
+        capture_current_span!(image_span);
         self.pool.execute(move ||{
+            enter_captured_span!(image_span);
+
             let compressed_or_err = block.compress_to_chunk(&meta.headers);
 
             // by now, decompressing could have failed in another thread.
@@ -257,7 +257,7 @@ fn cpy_u8(src: &[u16], src_i: usize, dst: &mut [u8], dst_i: usize, n: usize) {
 
 pub fn decompress(
     channels: &ChannelList,
-    compressed: &ByteVec,
+    compressed: Bytes<'_>,
     rectangle: IntegerBounds,
     expected_byte_size: usize,
     _pedantic: bool,
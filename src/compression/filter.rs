@@ -0,0 +1,343 @@
+//! Reversible filters that transform the samples of a channel before ZIP compression.
+//!
+//! Smooth channels, like depth or normals, often compress better
+//! if each sample is stored as the difference to the previous sample.
+//! Filters are configured per channel in `LayerAttributes::sample_filters`,
+//! and are stored in the file as a custom attribute, such that they are reverted when the file is read.
+//! Other OpenEXR readers do not know these filters, and will read the filtered samples as-is.
+//!
+//! Filters are only applied to layers compressed with `ZIP1` or `ZIP16`.
+//! To use your own filter, implement `SampleFilter` and add it to the layer attributes before writing.
+//! When reading, pass it to `ReadImage::sample_filter` or `block::reader::Reader::with_sample_filters`,
+//! as only the built-in filters are known otherwise.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+use crate::meta::attribute::{Text, SampleType, ChannelList, IntegerBounds};
+use crate::error::{Result, UnitResult, Error, usize_to_i32};
+use super::mod_p;
+
+
+/// The name of the custom attribute that stores the filters of a layer.
+/// Contains a text vector, with an entry `channel=filter` for each filtered channel.
+pub const ATTRIBUTE_NAME: &[u8] = b"exrsSampleFilters";
+
+/// A reversible transformation of the samples of a single channel.
+/// Must be shareable across threads, as blocks may be compressed in parallel.
+pub trait SampleFilter: Debug + Send + Sync {
+
+    /// The name that identifies this filter in the file. Must not contain the `=` character.
+    fn name(&self) -> &str;
+
+    /// Transform the little-endian samples of one line of a channel in-place, before compressing them.
+    fn apply(&self, samples: &mut [u8], sample_type: SampleType) -> UnitResult;
+
+    /// Undo the transformation of `apply` in-place, after decompressing the samples.
+    fn revert(&self, samples: &mut [u8], sample_type: SampleType) -> UnitResult;
+}
+
+/// Stores each sample as the wrapping difference of its bits to the previous sample in the line.
+/// Improves the compression of smooth channels, such as depth.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Delta;
+
+/// Stores the first byte of all samples in the line, then the second byte of all samples, and so on.
+/// Groups the similar exponent bytes of float samples together.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BytePlanes;
+
+/// A filter that was found in a file, but is not known to this library.
+/// Reverting this filter fails, such that filtered samples are never returned unnoticed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownFilter {
+
+    /// The name of the filter in the file.
+    pub name: String,
+}
+
+/// Which filter is applied to which channel of a layer.
+/// Channels without a filter are compressed as usual.
+#[derive(Debug, Clone, Default)]
+pub struct ChannelFilters {
+
+    /// The name of each filtered channel, and its filter.
+    pub list: Vec<(Text, Arc<dyn SampleFilter>)>,
+}
+
+/// Find the built-in filter with this name.
+pub fn find(name: &str) -> Option<Arc<dyn SampleFilter>> {
+    match name {
+        "delta" => Some(Arc::new(Delta)),
+        "bytePlanes" => Some(Arc::new(BytePlanes)),
+        _ => None,
+    }
+}
+
+
+impl ChannelFilters {
+
+    /// Whether no channel is filtered.
+    pub fn is_empty(&self) -> bool { self.list.is_empty() }
+
+    /// Add a filter for the channel with this name, replacing any previous filter of that channel.
+    pub fn with_filter(mut self, channel: impl Into<Text>, filter: impl SampleFilter + 'static) -> Self {
+        let channel = channel.into();
+        self.list.retain(|(name, _)| name != &channel);
+        self.list.push((channel, Arc::new(filter)));
+        self
+    }
+
+    /// The filter of the channel with this name, if any.
+    pub fn filter_for(&self, channel: &Text) -> Option<&dyn SampleFilter> {
+        self.list.iter().find(|(name, _)| name == channel).map(|(_, filter)| filter.as_ref())
+    }
+
+    /// Parse the attribute value. Filters that are not known are kept as an `UnknownFilter`.
+    pub fn from_attribute(entries: &[Text]) -> Result<Self> {
+        let list = entries.iter().map(|entry| {
+            let entry = entry.to_string();
+            let separator = entry.rfind('=').ok_or(Error::invalid("sample filter attribute"))?;
+            let (channel, name) = (&entry[.. separator], &entry[separator + 1 ..]);
+
+            let filter = find(name).unwrap_or_else(|| Arc::new(UnknownFilter { name: name.to_string() }));
+            Ok((Text::from(channel), filter))
+        });
+
+        Ok(ChannelFilters { list: list.collect::<Result<_>>()? })
+    }
+
+    /// Replace each filter that is not built-in with the custom filter of the same name, if any.
+    /// Used when reading a file that was written with custom filters.
+    pub fn resolve_custom_filters(&mut self, custom_filters: &[Arc<dyn SampleFilter>]) {
+        for (_, filter) in &mut self.list {
+            if find(filter.name()).is_some() { continue; }
+
+            if let Some(custom) = custom_filters.iter().find(|custom| custom.name() == filter.name()) {
+                *filter = custom.clone();
+            }
+        }
+    }
+
+    /// The attribute value that stores these filters in a file.
+    pub fn to_attribute(&self) -> Vec<Text> {
+        self.list.iter()
+            .map(|(channel, filter)| Text::from(format!("{}={}", channel, filter.name()).as_str()))
+            .collect()
+    }
+
+    /// Check that each filtered channel exists, and that each filter has a valid name.
+    pub fn validate(&self, channels: &ChannelList) -> UnitResult {
+        for (channel, filter) in &self.list {
+            if !channels.list.iter().any(|description| &description.name == channel) {
+                return Err(Error::invalid(format!("sample filter for missing channel `{}`", channel)));
+            }
+
+            if filter.name().is_empty() || filter.name().contains('=') || Text::new_or_none(filter.name()).is_none() {
+                return Err(Error::invalid("sample filter name"));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Apply the filters to each line of the uncompressed little-endian block.
+    pub(crate) fn apply(&self, bytes: &mut [u8], channels: &ChannelList, rectangle: IntegerBounds) -> UnitResult {
+        self.for_each_filtered_line(bytes, channels, rectangle, None, |filter, line, sample_type| filter.apply(line, sample_type))
+    }
+
+    /// Revert the filters of each line of the decompressed little-endian block.
+    /// If specified, only the lines of the requested channels are reverted, one flag per channel.
+    pub(crate) fn revert(&self, bytes: &mut [u8], channels: &ChannelList, rectangle: IntegerBounds, requested_channels: Option<&[bool]>) -> UnitResult {
+        self.for_each_filtered_line(bytes, channels, rectangle, requested_channels, |filter, line, sample_type| filter.revert(line, sample_type))
+    }
+
+    fn for_each_filtered_line(
+        &self, bytes: &mut [u8], channels: &ChannelList, rectangle: IntegerBounds, requested_channels: Option<&[bool]>,
+        mut process_line: impl FnMut(&dyn SampleFilter, &mut [u8], SampleType) -> UnitResult
+    ) -> UnitResult {
+        let mut remaining = bytes;
+
+        for y in rectangle.position.y() .. rectangle.end().y() {
+            for (index, channel) in channels.list.iter().enumerate() {
+                if mod_p(y, usize_to_i32(channel.sampling.y())) != 0 { continue; }
+
+                let line_size = rectangle.size.width() / channel.sampling.x() * channel.sample_type.bytes_per_sample();
+                if line_size > remaining.len() { return Err(Error::invalid("block size")); }
+
+                let (line, rest) = std::mem::take(&mut remaining).split_at_mut(line_size);
+                let requested = requested_channels.map_or(true, |requested| requested[index]);
+
+                if let Some(filter) = self.filter_for(&channel.name).filter(|_| requested) {
+                    process_line(filter, line, channel.sample_type)?;
+                }
+
+                remaining = rest;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl PartialEq for ChannelFilters {
+    fn eq(&self, other: &Self) -> bool {
+        self.list.len() == other.list.len() && self.list.iter().zip(&other.list).all(
+            |((channel, filter), (other_channel, other_filter))|
+                channel == other_channel && filter.name() == other_filter.name()
+        )
+    }
+}
+
+
+impl SampleFilter for Delta {
+    fn name(&self) -> &str { "delta" }
+
+    fn apply(&self, samples: &mut [u8], sample_type: SampleType) -> UnitResult {
+        match sample_type {
+            SampleType::F16 => {
+                for index in (1 .. samples.len() / 2).rev() {
+                    let difference = read_u16(samples, index).wrapping_sub(read_u16(samples, index - 1));
+                    write_u16(samples, index, difference);
+                }
+            },
+
+            SampleType::F32 | SampleType::U32 => {
+                for index in (1 .. samples.len() / 4).rev() {
+                    let difference = read_u32(samples, index).wrapping_sub(read_u32(samples, index - 1));
+                    write_u32(samples, index, difference);
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    fn revert(&self, samples: &mut [u8], sample_type: SampleType) -> UnitResult {
+        match sample_type {
+            SampleType::F16 => {
+                for index in 1 .. samples.len() / 2 {
+                    let sample = read_u16(samples, index).wrapping_add(read_u16(samples, index - 1));
+                    write_u16(samples, index, sample);
+                }
+            },
+
+            SampleType::F32 | SampleType::U32 => {
+                for index in 1 .. samples.len() / 4 {
+                    let sample = read_u32(samples, index).wrapping_add(read_u32(samples, index - 1));
+                    write_u32(samples, index, sample);
+                }
+            },
+        }
+
+        Ok(())
+    }
+}
+
+impl SampleFilter for BytePlanes {
+    fn name(&self) -> &str { "bytePlanes" }
+
+    fn apply(&self, samples: &mut [u8], sample_type: SampleType) -> UnitResult {
+        let sample_size = sample_type.bytes_per_sample();
+        let sample_count = samples.len() / sample_size;
+        let interleaved = samples.to_vec();
+
+        for (sample_index, sample) in interleaved.chunks_exact(sample_size).enumerate() {
+            for (byte_index, &byte) in sample.iter().enumerate() {
+                samples[byte_index * sample_count + sample_index] = byte;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn revert(&self, samples: &mut [u8], sample_type: SampleType) -> UnitResult {
+        let sample_size = sample_type.bytes_per_sample();
+        let sample_count = samples.len() / sample_size;
+        let planes = samples.to_vec();
+
+        for (sample_index, sample) in samples.chunks_exact_mut(sample_size).enumerate() {
+            for (byte_index, byte) in sample.iter_mut().enumerate() {
+                *byte = planes[byte_index * sample_count + sample_index];
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl SampleFilter for UnknownFilter {
+    fn name(&self) -> &str { &self.name }
+
+    fn apply(&self, _: &mut [u8], _: SampleType) -> UnitResult {
+        Err(Error::unsupported(format!("sample filter `{}`", self.name)))
+    }
+
+    fn revert(&self, _: &mut [u8], _: SampleType) -> UnitResult {
+        Err(Error::unsupported(format!("sample filter `{}`", self.name)))
+    }
+}
+
+
+fn read_u16(bytes: &[u8], index: usize) -> u16 {
+    u16::from_le_bytes([ bytes[index * 2], bytes[index * 2 + 1] ])
+}
+
+fn write_u16(bytes: &mut [u8], index: usize, value: u16) {
+    bytes[index * 2 .. index * 2 + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn read_u32(bytes: &[u8], index: usize) -> u32 {
+    let mut sample = [0_u8; 4];
+    sample.copy_from_slice(&bytes[index * 4 .. index * 4 + 4]);
+    u32::from_le_bytes(sample)
+}
+
+fn write_u32(bytes: &mut [u8], index: usize, value: u32) {
+    bytes[index * 4 .. index * 4 + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn filters_are_reversible() {
+        let original: Vec<u8> = (0 .. 48_u32).map(|index| (index * index * 7 + 3) as u8).collect();
+
+        for filter in &[ &Delta as &dyn SampleFilter, &BytePlanes ] {
+            for &sample_type in &[ SampleType::F16, SampleType::F32, SampleType::U32 ] {
+                let mut samples = original.clone();
+
+                filter.apply(&mut samples, sample_type).unwrap();
+                assert_ne!(samples, original, "{:?} should change the samples", filter);
+
+                filter.revert(&mut samples, sample_type).unwrap();
+                assert_eq!(samples, original, "{:?} should be reversible", filter);
+            }
+        }
+    }
+
+    #[test]
+    fn attribute_roundtrip() {
+        let filters = ChannelFilters::default().with_filter("Z", Delta).with_filter("N.x", BytePlanes);
+        let attribute = filters.to_attribute();
+        assert_eq!(attribute, vec![ Text::from("Z=delta"), Text::from("N.x=bytePlanes") ]);
+        assert_eq!(ChannelFilters::from_attribute(&attribute).unwrap(), filters);
+
+        let mut unknown = ChannelFilters::from_attribute(&[ Text::from("Z=wavelet") ]).unwrap();
+        assert!(unknown.filter_for(&Text::from("Z")).unwrap().revert(&mut [0; 4], SampleType::F32).is_err());
+
+        #[derive(Debug)]
+        struct Wavelet;
+
+        impl SampleFilter for Wavelet {
+            fn name(&self) -> &str { "wavelet" }
+            fn apply(&self, _: &mut [u8], _: SampleType) -> UnitResult { Ok(()) }
+            fn revert(&self, _: &mut [u8], _: SampleType) -> UnitResult { Ok(()) }
+        }
+
+        unknown.resolve_custom_filters(&[ Arc::new(Wavelet) ]);
+        assert!(unknown.filter_for(&Text::from("Z")).unwrap().revert(&mut [0; 4], SampleType::F32).is_ok());
+    }
+}
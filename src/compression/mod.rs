@@ -1,6 +1,11 @@
 
 //! Contains the compression attribute definition
 //! and methods to compress and decompress data.
+//!
+//! Decompression is bounded by the size that the header implies for each block.
+//! A chunk that would expand to more bytes than expected is rejected as invalid
+//! before the excess is allocated, and the internal PIZ tables are validated
+//! against the compressed data, such that malformed files cannot trigger huge allocations.
 
 
 // private modules make non-breaking changes easier
@@ -10,11 +15,17 @@ mod piz;
 mod pxr24;
 mod b44;
 
+pub mod filter;
 
 
 use crate::meta::attribute::{IntegerBounds, SampleType, ChannelList};
-use crate::error::{Result, Error, usize_to_i32};
+use crate::error::{Result, UnitResult, Error, usize_to_i32};
 use crate::meta::header::Header;
+use crate::meta::compute_chunk_count;
+use crate::block::BlockIndex;
+use crate::block::pool::{BlockBufferPool, HeapBuffers};
+use std::time::Instant;
+use std::ops::Range;
 
 
 /// A byte vector.
@@ -153,6 +164,47 @@ impl std::fmt::Display for Compression {
 }
 
 
+/// How to weigh the file size against the time it takes
+/// to compress and decompress the pixels, when choosing a compression method automatically.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SpeedBias {
+
+    /// Prefer fast reading and writing, accepting larger files.
+    Speed,
+
+    /// Consider both file size and speed.
+    Balanced,
+
+    /// Prefer small files, no matter how long it takes to compress them.
+    Size,
+}
+
+impl Default for SpeedBias {
+    fn default() -> Self { SpeedBias::Balanced }
+}
+
+impl SpeedBias {
+
+    /// How much the compressed size contributes to the cost of a compression method, between zero and one.
+    /// The remainder is contributed by the time required for compressing and decompressing.
+    fn size_weight(self) -> f64 {
+        match self {
+            SpeedBias::Speed => 0.1,
+            SpeedBias::Balanced => 0.5,
+            SpeedBias::Size => 1.0,
+        }
+    }
+}
+
+/// The lossless compression methods that are considered by `Compression::choose_for`.
+const AUTOMATIC_COMPRESSION_CANDIDATES: [Compression; 5] = [
+    Compression::Uncompressed, Compression::RLE, Compression::ZIP1, Compression::ZIP16, Compression::PIZ
+];
+
+/// How many blocks of a layer are compressed to estimate the performance of a compression method.
+const AUTOMATIC_COMPRESSION_SAMPLE_BLOCKS: usize = 3;
+
+
 
 impl Compression {
 
@@ -182,6 +234,11 @@ impl Compression {
         assert!(pixel_section.validate(Some(max_tile_size)).is_ok(), "decompress tile coordinate bug");
         if header.deep { assert!(self.supports_deep_data()) }
 
+        // move the block into the chunk, instead of cloning it
+        if self == Compression::Uncompressed {
+            return Ok(convert_current_to_little_endian(uncompressed, &header.channels, pixel_section));
+        }
+
         // convert data if compression method expects native format
         // see https://github.com/AcademySoftwareFoundation/openexr/blob/3bd93f85bcb74c77255f28cdbb913fdbfbb39dfe/OpenEXR/IlmImf/ImfTiledOutputFile.cpp#L750-L842
         if !self.native_format(header) {
@@ -190,7 +247,15 @@ impl Compression {
 
         use self::Compression::*;
         let compressed = match self {
-            Uncompressed => Ok(uncompressed.clone()), // TODO no clone!
+            Uncompressed => unreachable!("uncompressed data is returned early"),
+
+            // the filtered copy is discarded, such that the unfiltered samples are written if compression does not help
+            ZIP16 | ZIP1 if !header.own_attributes.sample_filters.is_empty() => {
+                let mut filtered = uncompressed.clone();
+                header.own_attributes.sample_filters.apply(&mut filtered, &header.channels, pixel_section)
+                    .and_then(|()| zip::compress_bytes(&filtered))
+            },
+
             ZIP16 => zip::compress_bytes(&uncompressed),
             ZIP1 => zip::compress_bytes(&uncompressed),
             RLE => rle::compress_bytes(&uncompressed),
@@ -216,7 +281,42 @@ impl Compression {
     }
 
     /// Decompress the image section of bytes.
+    /// Never produces more bytes than the pixel section requires,
+    /// returns an `Error::Invalid` instead if the data would expand beyond that size.
     pub fn decompress_image_section(self, header: &Header, compressed: ByteVec, pixel_section: IntegerBounds, pedantic: bool) -> Result<ByteVec> {
+        self.decompress_image_section_with_pool(header, compressed, pixel_section, pedantic, &HeapBuffers)
+    }
+
+    /// Decompress the image section of bytes, like `decompress_image_section`.
+    /// RLE and ZIP data is decompressed into a buffer from the pool, other compression methods allocate their own buffer.
+    /// Afterwards, the compressed buffer is given back to the pool, unless it contains the decompressed pixels.
+    pub fn decompress_image_section_with_pool(
+        self, header: &Header, compressed: ByteVec, pixel_section: IntegerBounds, pedantic: bool, pool: &dyn BlockBufferPool
+    ) -> Result<ByteVec> {
+        self.decompress_section(header, compressed, pixel_section, pedantic, pool, None)
+    }
+
+    /// Decompress the image section of bytes, like `decompress_image_section_with_pool`,
+    /// but only reconstruct the samples of the requested channels, where the compression method allows it.
+    /// The slice contains one flag for each channel in the header.
+    /// After inflating ZIP compressed data, only the bytes of the requested channels are reordered and unfiltered,
+    /// and the bytes of all other channels contain arbitrary values.
+    /// Uncompressed blocks are never copied. Other compression methods always decompress all channels.
+    pub fn decompress_image_section_channels_with_pool(
+        self, header: &Header, compressed: ByteVec, pixel_section: IntegerBounds, pedantic: bool,
+        pool: &dyn BlockBufferPool, requested_channels: &[bool]
+    ) -> Result<ByteVec> {
+        if requested_channels.len() != header.channels.list.len() {
+            return Err(Error::invalid("requested channels do not match the channel list"));
+        }
+
+        self.decompress_section(header, compressed, pixel_section, pedantic, pool, Some(requested_channels))
+    }
+
+    fn decompress_section(
+        self, header: &Header, compressed: ByteVec, pixel_section: IntegerBounds, pedantic: bool,
+        pool: &dyn BlockBufferPool, requested_channels: Option<&[bool]>
+    ) -> Result<ByteVec> {
         let max_tile_size = header.max_block_pixel_size();
 
         assert!(pixel_section.validate(Some(max_tile_size)).is_ok(), "decompress tile coordinate bug");
@@ -230,19 +330,32 @@ impl Compression {
         }
         else {
             use self::Compression::*;
-            let bytes = match self {
-                Uncompressed => Ok(compressed),
-                ZIP16 => zip::decompress_bytes(&compressed),
-                ZIP1 => zip::decompress_bytes(&compressed),
-                RLE => rle::decompress_bytes(&compressed, expected_byte_size, pedantic),
-                PIZ => piz::decompress(&header.channels, compressed, pixel_section, expected_byte_size, pedantic),
-                PXR24 => pxr24::decompress(&header.channels, &compressed, pixel_section, expected_byte_size, pedantic),
-                B44 | B44A => b44::decompress(&header.channels, &compressed, pixel_section, expected_byte_size, pedantic),
-                _ => return Err(Error::unsupported(format!("yet unimplemented compression method: {}", self)))
+            let bytes = if self == Uncompressed { Ok(compressed) } else {
+                let bytes = match self {
+                    Uncompressed => unreachable!("uncompressed data is handled above"),
+
+                    ZIP16 | ZIP1 => match requested_channels {
+                        Some(requested) => zip::decompress_byte_ranges(
+                            &compressed, expected_byte_size,
+                            &requested_byte_ranges(&header.channels, pixel_section, requested)
+                        ),
+
+                        None => decompress_into_pooled_buffer(pool, expected_byte_size, |target| zip::decompress_bytes_into(&compressed, target)),
+                    },
+
+                    RLE => decompress_into_pooled_buffer(pool, expected_byte_size, |target| rle::decompress_bytes_into(&compressed, target, pedantic)),
+                    PIZ => piz::decompress(&header.channels, &compressed, pixel_section, expected_byte_size, pedantic),
+                    PXR24 => pxr24::decompress(&header.channels, &compressed, pixel_section, expected_byte_size, pedantic),
+                    B44 | B44A => b44::decompress(&header.channels, &compressed, pixel_section, expected_byte_size, pedantic),
+                    _ => return Err(Error::unsupported(format!("yet unimplemented compression method: {}", self)))
+                };
+
+                pool.recycle(compressed);
+                bytes
             };
 
             // map all errors to compression errors
-            let bytes = bytes
+            let mut bytes = bytes
                 .map_err(|_| Error::invalid(format!("compressed data ({:?})", self)))?;
 
             if bytes.len() != expected_byte_size {
@@ -250,6 +363,8 @@ impl Compression {
             }
 
             else {
+                self.revert_sample_filters(header, &mut bytes, pixel_section, requested_channels)?;
+
                 // convert data if compression method has output native format
                 if !self.native_format(header) {
                     Ok(convert_little_endian_to_current(bytes, &header.channels, pixel_section))
@@ -260,6 +375,108 @@ impl Compression {
         }
     }
 
+    /// Decompress the image section of bytes directly into the target buffer,
+    /// which must have exactly the byte size of the decompressed pixel section.
+    /// Uncompressed, RLE and ZIP data is decompressed without allocating any intermediate buffers.
+    /// Other compression methods still decompress into a temporary buffer, which is then copied into the target.
+    pub fn decompress_image_section_into(
+        self, header: &Header, compressed: Bytes<'_>, pixel_section: IntegerBounds, pedantic: bool, target: &mut [u8]
+    ) -> UnitResult {
+        let max_tile_size = header.max_block_pixel_size();
+
+        assert!(pixel_section.validate(Some(max_tile_size)).is_ok(), "decompress tile coordinate bug");
+        if header.deep { return Err(Error::unsupported("deep data not supported yet")) }
+
+        let expected_byte_size = pixel_section.size.area() * header.channels.bytes_per_pixel; // FIXME this needs to account for subsampling anywhere
+        if target.len() != expected_byte_size {
+            return Err(Error::invalid("target buffer size does not match the block size"));
+        }
+
+        if compressed.len() == expected_byte_size {
+            // the compressed data was larger than the raw data, so the small raw data has been written
+            target.copy_from_slice(compressed);
+            return Ok(());
+        }
+
+        use self::Compression::*;
+        let result = match self {
+            Uncompressed => Err(Error::invalid("uncompressed data size")),
+            ZIP16 | ZIP1 => zip::decompress_bytes_into(compressed, target),
+            RLE => rle::decompress_bytes_into(compressed, target, pedantic),
+
+            _ => {
+                let bytes = match self {
+                    PIZ => piz::decompress(&header.channels, compressed, pixel_section, expected_byte_size, pedantic),
+                    PXR24 => pxr24::decompress(&header.channels, compressed, pixel_section, expected_byte_size, pedantic),
+                    B44 | B44A => b44::decompress(&header.channels, compressed, pixel_section, expected_byte_size, pedantic),
+                    _ => return Err(Error::unsupported(format!("yet unimplemented compression method: {}", self)))
+                };
+
+                bytes.and_then(|bytes| {
+                    if bytes.len() != expected_byte_size { return Err(Error::invalid("decompressed data")) }
+                    target.copy_from_slice(&bytes);
+                    Ok(())
+                })
+            },
+        };
+
+        // map all errors to compression errors
+        result.map_err(|_| Error::invalid(format!("compressed data ({:?})", self)))?;
+        self.revert_sample_filters(header, target, pixel_section, None)?;
+
+        // like `UncompressedBlock::data`, the target contains little-endian samples on all machines
+        Ok(())
+    }
+
+    /// Revert the sample filters of the layer, if any, after the little-endian pixels have been decompressed.
+    /// Filters are only applied to ZIP compressed blocks.
+    /// If specified, only the filters of the requested channels are reverted.
+    fn revert_sample_filters(self, header: &Header, bytes: &mut [u8], pixel_section: IntegerBounds, requested_channels: Option<&[bool]>) -> UnitResult {
+        let filters = &header.own_attributes.sample_filters;
+
+        if filters.is_empty() || (self != Compression::ZIP1 && self != Compression::ZIP16) { Ok(()) }
+        else { filters.revert(bytes, &header.channels, pixel_section, requested_channels) }
+    }
+
+    /// Compress the pixel offset table of a deep block, which contains one little-endian `i32` per pixel.
+    /// Returns the uncompressed bytes if compression would not make the table smaller.
+    /// Only the compression methods that support deep data can be used.
+    pub fn compress_deep_offset_table(self, uncompressed: &[u8]) -> Result<ByteVec> {
+        use self::Compression::*;
+        let compressed = match self {
+            Uncompressed => return Ok(uncompressed.to_vec()),
+            ZIP16 | ZIP1 => zip::compress_bytes(uncompressed),
+            RLE => rle::compress_bytes(uncompressed),
+            _ => return Err(Error::unsupported(format!("deep data with compression method {}", self))),
+        }?;
+
+        if compressed.len() < uncompressed.len() { Ok(compressed) }
+        else { Ok(uncompressed.to_vec()) }
+    }
+
+    /// Decompress the pixel offset table of a deep block, which contains one little-endian `i32` per pixel.
+    /// Only the compression methods that support deep data can be used.
+    pub fn decompress_deep_offset_table(self, compressed: &[u8], pixel_count: usize, pedantic: bool) -> Result<ByteVec> {
+        let expected_byte_size = pixel_count * std::mem::size_of::<i32>();
+
+        if compressed.len() == expected_byte_size {
+            return Ok(compressed.to_vec());
+        }
+
+        use self::Compression::*;
+        let bytes = match self {
+            Uncompressed => Ok(compressed.to_vec()),
+            ZIP16 | ZIP1 => zip::decompress_bytes(compressed, expected_byte_size),
+            RLE => rle::decompress_bytes(compressed, expected_byte_size, pedantic),
+            _ => return Err(Error::unsupported(format!("deep data with compression method {}", self))),
+        };
+
+        let bytes = bytes.map_err(|_| Error::invalid(format!("compressed deep offset table ({:?})", self)))?;
+
+        if bytes.len() != expected_byte_size { Err(Error::invalid("deep offset table size")) }
+        else { Ok(bytes) }
+    }
+
     /// For scan line images and deep scan line images, one or more scan lines may be
     /// stored together as a scan line block. The number of scan lines per block
     /// depends on how the pixel data are compressed.
@@ -314,6 +531,121 @@ impl Compression {
         }
     }
 
+    /// Choose a lossless compression method for the pixels of a layer, by compressing
+    /// a few blocks, spread across the layer, with each candidate compression method.
+    /// The compression of the specified header is ignored.
+    ///
+    /// The closure extracts the uncompressed bytes of a block, like `ChannelsWriter::extract_uncompressed_block`.
+    /// It is given a header with the candidate compression method,
+    /// as the number of lines per block depends on the compression method.
+    /// The layer index of the block index is always zero.
+    ///
+    /// Unless `SpeedBias::Size` is specified, the result depends on timing measurements,
+    /// so it may differ between two calls with the same pixels.
+    pub fn choose_for(
+        header: &Header,
+        mut extract_uncompressed_block: impl FnMut(&Header, BlockIndex) -> ByteVec,
+        bias: SpeedBias
+    ) -> Result<Compression>
+    {
+        let candidates = AUTOMATIC_COMPRESSION_CANDIDATES.iter()
+            .filter(|compression| !header.deep || compression.supports_deep_data());
+
+        let mut measurements = Vec::with_capacity(AUTOMATIC_COMPRESSION_CANDIDATES.len());
+
+        for &compression in candidates {
+            let candidate_header = Header {
+                compression,
+                chunk_count: compute_chunk_count(compression, header.layer_size, header.blocks),
+                .. header.clone()
+            };
+
+            let blocks: Vec<_> = candidate_header.blocks_increasing_y_order().collect();
+            let sample_count = AUTOMATIC_COMPRESSION_SAMPLE_BLOCKS.min(blocks.len());
+
+            let mut uncompressed_size = 0;
+            let mut compressed_size = 0;
+            let mut duration = std::time::Duration::default();
+
+            for sample in 0 .. sample_count {
+                // the center block of each of the evenly sized sections of the layer
+                let tile = &blocks[(sample * 2 + 1) * blocks.len() / (sample_count * 2)];
+                let pixel_section = candidate_header.get_absolute_block_pixel_coordinates(tile.location)?;
+                let block_index = candidate_header.get_block_index(0, tile.location)?;
+
+                let uncompressed = extract_uncompressed_block(&candidate_header, block_index);
+                uncompressed_size += uncompressed.len();
+
+                let start = Instant::now();
+                let compressed = compression.compress_image_section(&candidate_header, uncompressed, pixel_section)?;
+                compressed_size += compressed.len();
+                compression.decompress_image_section(&candidate_header, compressed, pixel_section, false)?;
+                duration += start.elapsed();
+            }
+
+            let size_ratio = if uncompressed_size == 0 { 1.0 } else { compressed_size as f64 / uncompressed_size as f64 };
+            measurements.push((compression, size_ratio, duration.as_secs_f64()));
+        }
+
+        let slowest = measurements.iter().map(|&(_, _, seconds)| seconds).fold(0.0, f64::max);
+        let size_weight = bias.size_weight();
+
+        let cost = |&(_, size_ratio, seconds): &(Compression, f64, f64)| {
+            let relative_time = if slowest == 0.0 { 0.0 } else { seconds / slowest };
+            size_weight * size_ratio + (1.0 - size_weight) * relative_time
+        };
+
+        let mut best = measurements.first().ok_or(Error::invalid("no compression candidate"))?;
+        for measurement in &measurements {
+            if cost(measurement) < cost(best) { best = measurement; }
+        }
+
+        Ok(best.0)
+    }
+}
+
+/// Decompress into a buffer of exactly `byte_size` bytes from the pool.
+/// If decompressing fails, the buffer is given back to the pool.
+fn decompress_into_pooled_buffer(
+    pool: &dyn BlockBufferPool, byte_size: usize, decompress: impl FnOnce(&mut [u8]) -> UnitResult
+) -> Result<ByteVec> {
+    let mut buffer = pool.take(byte_size);
+    buffer.clear(); // the pool should return an empty buffer, but make sure
+    buffer.resize(byte_size, 0);
+
+    match decompress(&mut buffer) {
+        Ok(()) => Ok(buffer),
+        Err(error) => {
+            pool.recycle(buffer);
+            Err(error)
+        }
+    }
+}
+
+/// The byte ranges that contain the lines of the requested channels, inside an uncompressed block.
+/// Adjacent ranges are merged.
+fn requested_byte_ranges(channels: &ChannelList, rectangle: IntegerBounds, requested_channels: &[bool]) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    let mut start = 0;
+
+    for y in rectangle.position.y() .. rectangle.end().y() {
+        for (channel, &requested) in channels.list.iter().zip(requested_channels) {
+            if mod_p(y, usize_to_i32(channel.sampling.y())) != 0 { continue; }
+
+            let end = start + rectangle.size.width() / channel.sampling.x() * channel.sample_type.bytes_per_sample();
+
+            if requested {
+                match ranges.last_mut() {
+                    Some(previous) if previous.end == start => previous.end = end,
+                    _ => ranges.push(start .. end),
+                }
+            }
+
+            start = end;
+        }
+    }
+
+    ranges
 }
 
 // see https://github.com/AcademySoftwareFoundation/openexr/blob/6a9f8af6e89547bcd370ae3cec2b12849eee0b54/OpenEXR/IlmImf/ImfMisc.cpp#L1456-L1541
@@ -391,7 +723,7 @@ fn div_p (x: i32, y: i32) -> i32 {
     }
 }
 
-fn mod_p(x: i32, y: i32) -> i32 {
+pub(crate) fn mod_p(x: i32, y: i32) -> i32 {
     x - y * div_p(x, y)
 }
 
@@ -438,6 +770,49 @@ mod optimize_bytes {
         separated.copy_from_slice(interleaved.as_slice())
     }
 
+    /// Reconstructs samples from a stream of differences, and writes each sample directly to its interleaved position.
+    /// Equivalent to `differences_to_samples` followed by `interleave_byte_blocks`, but without a temporary buffer.
+    #[derive(Debug)]
+    pub struct InterleavedSamplesWriter<'t> {
+        target: &'t mut [u8],
+        next_index: usize,
+        previous_sample: u8,
+    }
+
+    impl<'t> InterleavedSamplesWriter<'t> {
+
+        /// Write all samples into the target, which must have exactly the size of the decompressed data.
+        pub fn new(target: &'t mut [u8]) -> Self {
+            InterleavedSamplesWriter { target, next_index: 0, previous_sample: 0 }
+        }
+
+        /// Reconstruct the next sample and write it to the target.
+        /// Returns false if the target is already full.
+        #[inline]
+        pub fn push_difference(&mut self, difference: u8) -> bool {
+            let length = self.target.len();
+            if self.next_index >= length { return false; }
+
+            let sample = if self.next_index == 0 { difference }
+                else { (self.previous_sample as i32 + difference as i32 - 128) as u8 };
+
+            let first_half_length = (length + 1) / 2;
+            let interleaved_index =
+                if self.next_index < first_half_length { self.next_index * 2 }
+                else { (self.next_index - first_half_length) * 2 + 1 };
+
+            self.target[interleaved_index] = sample; // index is smaller than length
+            self.previous_sample = sample;
+            self.next_index += 1;
+            true
+        }
+
+        /// Whether the whole target has been written.
+        pub fn is_complete(&self) -> bool {
+            self.next_index == self.target.len()
+        }
+    }
+
     /// Separate the bytes such that the second half contains each other byte.
     pub fn separate_bytes_fragments(source: &mut [u8]) {
         // TODO without extra allocation?
@@ -480,6 +855,25 @@ mod optimize_bytes {
             assert_eq!(source, modified);
         }
 
+        #[test]
+        fn write_interleaved_samples_directly(){
+            for length in 0 .. 12 {
+                let differences: Vec<u8> = (0 .. length).map(|index| (index * 37 + 90) as u8).collect();
+
+                let mut expected = differences.clone();
+                super::differences_to_samples(&mut expected);
+                super::interleave_byte_blocks(&mut expected);
+
+                let mut target = vec![ 0; length ];
+                let mut writer = super::InterleavedSamplesWriter::new(&mut target);
+                for &difference in &differences { assert!(writer.push_difference(difference)); }
+
+                assert!(writer.is_complete());
+                assert!(!writer.push_difference(0), "target is full");
+                assert_eq!(target, expected);
+            }
+        }
+
         #[test]
         fn roundtrip_derive(){
             let source = vec![ 0, 1, 2, 7, 4, 5, 6, 7, 13, 9, 10 ];
@@ -493,3 +887,57 @@ mod optimize_bytes {
         }
     }
 }
+
+
+#[cfg(test)]
+mod test {
+    use crate::compression::{Compression, SpeedBias};
+    use crate::meta::header::Header;
+    use crate::meta::attribute::{ChannelDescription, SampleType, Text, LineOrder};
+    use crate::meta::BlockDescription;
+    use crate::meta::attribute::IntegerBounds;
+    use crate::math::Vec2;
+
+    fn header() -> Header {
+        let channels = smallvec![ ChannelDescription::named("Y", SampleType::F32) ];
+        Header::new(Text::from("layer"), Vec2(40, 70), channels)
+            .with_encoding(Compression::Uncompressed, BlockDescription::ScanLines, LineOrder::Increasing)
+    }
+
+    #[test]
+    fn choose_compression_for_flat_pixels() {
+        let header = header();
+
+        let chosen = Compression::choose_for(&header, |candidate, block| {
+            assert_eq!(candidate.layer_size, header.layer_size);
+            assert_eq!(block.layer, 0);
+            vec![0; block.pixel_size.area() * candidate.channels.bytes_per_pixel]
+        }, SpeedBias::Size).unwrap();
+
+        assert_ne!(chosen, Compression::Uncompressed, "uniform pixels are compressible");
+        assert!(!chosen.may_loose_data());
+    }
+
+    #[test]
+    fn decompress_into_keeps_little_endian_samples() {
+        let header = header();
+        let line = IntegerBounds::new(Vec2(0, 3), Vec2(40, 1));
+
+        let little_endian: Vec<u8> = (0 .. 40).flat_map(|x| (x as f32 * 1.5 - 7.25).to_le_bytes()).collect();
+        let mut target = vec![ 0_u8; little_endian.len() ];
+        Compression::Uncompressed.decompress_image_section_into(&header, &little_endian, line, true, &mut target).unwrap();
+
+        assert_eq!(target, little_endian);
+    }
+
+    #[test]
+    fn choose_only_deep_compression_for_deep_header() {
+        let header = Header { deep: true, .. header() };
+
+        let chosen = Compression::choose_for(&header, |candidate, block| {
+            vec![0; block.pixel_size.area() * candidate.channels.bytes_per_pixel]
+        }, SpeedBias::Balanced).unwrap();
+
+        assert!(chosen.supports_deep_data());
+    }
+}
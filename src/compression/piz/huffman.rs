@@ -20,12 +20,17 @@ pub fn decompress(compressed: &[u8], expected_size: usize) -> Result<Vec<u16>> {
 
     let min_code_index = usize::try_from(u32::read(&mut remaining_compressed)?)?;
     let max_code_index_32 = u32::read(&mut remaining_compressed)?;
-    let _table_size = usize::try_from(u32::read(&mut remaining_compressed)?)?; // TODO check this and return Err?
+    let table_size = usize::try_from(u32::read(&mut remaining_compressed)?)?;
     let bit_count = usize::try_from(u32::read(&mut remaining_compressed)?)?;
     let _skipped = u32::read(&mut remaining_compressed)?; // what is this
 
-    let max_code_index = usize::try_from(max_code_index_32).unwrap();
-    if min_code_index >= ENCODING_TABLE_SIZE || max_code_index >= ENCODING_TABLE_SIZE {
+    let max_code_index = usize::try_from(max_code_index_32)?;
+    if min_code_index >= ENCODING_TABLE_SIZE || max_code_index >= ENCODING_TABLE_SIZE || min_code_index > max_code_index {
+        return Err(Error::invalid(INVALID_TABLE_SIZE));
+    }
+
+    // the packed table can never be larger than the remaining compressed data
+    if table_size > remaining_compressed.len() {
         return Err(Error::invalid(INVALID_TABLE_SIZE));
     }
 
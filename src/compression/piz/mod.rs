@@ -31,7 +31,7 @@ struct ChannelData {
 
 pub fn decompress(
     channels: &ChannelList,
-    compressed: ByteVec,
+    compressed: Bytes<'_>,
     rectangle: IntegerBounds,
     expected_byte_size: usize, // TODO remove expected byte size as it can be computed with `rectangle.size.area() * channels.bytes_per_pixel`
     pedantic: bool
@@ -49,7 +49,7 @@ pub fn decompress(
 
     let mut bitmap = vec![0_u8; BITMAP_SIZE]; // FIXME use bit_vec!
 
-    let mut remaining_input = compressed.as_slice();
+    let mut remaining_input = compressed;
     let min_non_zero = u16::read(&mut remaining_input)? as usize;
     let max_non_zero = u16::read(&mut remaining_input)? as usize;
 
@@ -65,6 +65,10 @@ pub fn decompress(
 
     {
         let length = i32::read(&mut remaining_input)?;
+        if length < 0 || length as i64 > remaining_input.len() as i64 {
+            return Err(Error::invalid("compression data"));
+        }
+
         if pedantic && length as i64 != remaining_input.len() as i64 {
             // TODO length might be smaller than remaining??
             return Err(Error::invalid("compression data"));
@@ -308,7 +312,7 @@ mod test {
             .collect();
 
         let compressed = piz::compress(&channels, &pixel_bytes, rectangle).unwrap();
-        let decompressed = piz::decompress(&channels, compressed, rectangle, pixel_bytes.len(), true).unwrap();
+        let decompressed = piz::decompress(&channels, &compressed, rectangle, pixel_bytes.len(), true).unwrap();
 
         assert_eq!(pixel_bytes, decompressed);
     }
@@ -107,6 +107,14 @@ pub fn encode_14_or_16_bit(
 
 #[inline]
 pub fn decode(buffer: &mut [u16], count: Vec2<usize>, size: Vec2<usize>, max_value: u16) -> IoResult<()> {
+    // the last index touched by the transform must be inside the buffer, otherwise the data is corrupt
+    if count.area() != 0 {
+        let last_index = (count.y() - 1) * size.y() + (count.x() - 1) * size.x();
+        if last_index >= buffer.len() {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "wavelet size exceeds buffer"));
+        }
+    }
+
     if is_14_bit(max_value) { decode_14_or_16_bit(buffer, count, size, true) }
     else { decode_14_or_16_bit(buffer, count, size, false) }
 }
@@ -30,7 +30,6 @@
 use super::*;
 
 use crate::error::Result;
-use inflate::inflate_bytes_zlib;
 use lebe::io::ReadPrimitive;
 use deflate::write::ZlibEncoder;
 
@@ -138,8 +137,7 @@ pub fn compress(channels: &ChannelList, mut remaining_bytes: Bytes<'_>, area: In
 pub fn decompress(channels: &ChannelList, bytes: Bytes<'_>, area: IntegerBounds, expected_byte_size: usize, pedantic: bool) -> Result<ByteVec> {
     if bytes.is_empty() { return Ok(Vec::new()) }
 
-    let raw = inflate_bytes_zlib(bytes)
-        .map_err(|msg| Error::invalid(msg))?; // TODO share code with zip?
+    let raw = zip::inflate_bytes_zlib_bounded(bytes, expected_byte_size)?;
 
     let mut read = raw.as_slice();
     let mut out = Vec::with_capacity(expected_byte_size.min(2048*4));
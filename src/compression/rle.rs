@@ -2,6 +2,7 @@ use super::*;
 use super::optimize_bytes::*;
 use super::Error;
 use super::Result;
+use crate::error::UnitResult;
 
 // inspired by  https://github.com/openexr/openexr/blob/master/OpenEXR/IlmImf/ImfRle.cpp
 
@@ -18,12 +19,15 @@ pub fn decompress_bytes(mut remaining: Bytes<'_>, expected_byte_size: usize, ped
         if count < 0 {
             // take the next '-count' bytes as-is
             let values = take_n(&mut remaining, (-count) as usize)?;
+            if decompressed.len() + values.len() > expected_byte_size { return Err(Error::invalid("data amount")); }
             decompressed.extend_from_slice(values);
         }
         else {
             // repeat the next value 'count + 1' times
             let value = take_1(&mut remaining)?;
-            decompressed.resize(decompressed.len() + count as usize + 1, value);
+            let run_length = count as usize + 1;
+            if decompressed.len() + run_length > expected_byte_size { return Err(Error::invalid("data amount")); }
+            decompressed.resize(decompressed.len() + run_length, value);
         }
     }
 
@@ -41,6 +45,35 @@ pub fn decompress_bytes(mut remaining: Bytes<'_>, expected_byte_size: usize, ped
     Ok(decompressed)
 }
 
+/// Decompress the bytes directly into the target, which must have exactly the size of the decompressed data.
+pub fn decompress_bytes_into(mut remaining: Bytes<'_>, target: &mut [u8], pedantic: bool) -> UnitResult {
+    let mut samples = InterleavedSamplesWriter::new(target);
+
+    while !remaining.is_empty() && !samples.is_complete() {
+        let count = take_1(&mut remaining)? as i8 as i32;
+
+        if count < 0 {
+            // take the next '-count' bytes as-is
+            for &value in take_n(&mut remaining, (-count) as usize)? {
+                if !samples.push_difference(value) { return Err(Error::invalid("data amount")); }
+            }
+        }
+        else {
+            // repeat the next value 'count + 1' times
+            let value = take_1(&mut remaining)?;
+            for _ in 0 ..= count {
+                if !samples.push_difference(value) { return Err(Error::invalid("data amount")); }
+            }
+        }
+    }
+
+    if !samples.is_complete() || (pedantic && !remaining.is_empty()) {
+        return Err(Error::invalid("data amount"));
+    }
+
+    Ok(())
+}
+
 pub fn compress_bytes(data: Bytes<'_>) -> Result<ByteVec> {
     let mut data = Vec::from(data); // TODO no alloc
     separate_bytes_fragments(&mut data);
@@ -119,5 +152,12 @@ mod test {
         assert_eq!(decompressed, data);
     }
 
+    #[test]
+    fn reject_expanding_beyond_expected_size(){
+        let data = vec![ 7_u8; 1000 ];
+        let compressed = super::compress_bytes(&data).unwrap();
+        assert!(super::decompress_bytes(&compressed, 100, false).is_err());
+    }
+
     // TODO fuzz testing
 }
\ No newline at end of file
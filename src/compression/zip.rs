@@ -6,9 +6,10 @@ use super::*;
 use super::optimize_bytes::*;
 
 use std::io;
-use crate::error::Result;
+use std::ops::Range;
+use crate::error::{Result, UnitResult};
 use deflate::write::ZlibEncoder;
-use inflate::inflate_bytes_zlib;
+use inflate::InflateStream;
 
 // scanline decompression routine, see https://github.com/openexr/openexr/blob/master/OpenEXR/IlmImf/ImfScanLineInputFile.cpp
 // 1. Uncompress the data, if necessary (If the line is uncompressed, it's in XDR format, regardless of the compressor's output format.)
@@ -16,15 +17,83 @@ use inflate::inflate_bytes_zlib;
 // 4. Fill the frame buffer with pixel data, respective to sampling and whatnot
 
 
-pub fn decompress_bytes(data: Bytes<'_>) -> Result<ByteVec> {
-    let mut decompressed = inflate_bytes_zlib(data)
-        .map_err(|msg| Error::invalid(msg))?;
+pub fn decompress_bytes(data: Bytes<'_>, expected_byte_size: usize) -> Result<ByteVec> {
+    let mut decompressed = inflate_bytes_zlib_bounded(data, expected_byte_size)?;
 
     differences_to_samples(&mut decompressed);
     interleave_byte_blocks(&mut decompressed);
     Ok(decompressed)
 }
 
+/// Decompress the bytes, like `decompress_bytes`, but only reorder the bytes inside the specified ranges.
+/// All other bytes of the result are zero. The predictor still has to be reverted for all bytes,
+/// as each byte depends on all previous bytes.
+pub fn decompress_byte_ranges(data: Bytes<'_>, expected_byte_size: usize, ranges: &[Range<usize>]) -> Result<ByteVec> {
+    let mut separated = inflate_bytes_zlib_bounded(data, expected_byte_size)?;
+    differences_to_samples(&mut separated);
+
+    let mut interleaved = vec![0_u8; separated.len()];
+    let second_half_start = (separated.len() + 1) / 2;
+
+    for range in ranges {
+        let bytes = interleaved.get_mut(range.clone())
+            .ok_or_else(|| Error::invalid("decompressed data is smaller than expected"))?;
+
+        // the even bytes are stored in the first half, and the odd bytes in the second half
+        for (index, byte) in (range.start ..).zip(bytes) {
+            *byte = separated[index / 2 + (index % 2) * second_half_start];
+        }
+    }
+
+    Ok(interleaved)
+}
+
+/// Inflate zlib data, but abort as soon as the output would be larger than `max_byte_size`.
+/// This prevents malicious files from causing huge allocations with a small compressed chunk.
+pub fn inflate_bytes_zlib_bounded(mut remaining: Bytes<'_>, max_byte_size: usize) -> Result<ByteVec> {
+    let mut decoder = InflateStream::from_zlib();
+    let mut decompressed = Vec::with_capacity(max_byte_size.min(8*2048));
+
+    loop {
+        let (consumed_byte_count, decoded) = decoder.update(remaining)
+            .map_err(|msg| Error::invalid(msg))?;
+
+        if decompressed.len() + decoded.len() > max_byte_size {
+            return Err(Error::invalid("decompressed data is larger than expected"));
+        }
+
+        decompressed.extend_from_slice(decoded);
+        remaining = &remaining[consumed_byte_count ..];
+
+        if consumed_byte_count == 0 { break; }
+    }
+
+    Ok(decompressed)
+}
+
+/// Decompress the bytes directly into the target, which must have exactly the size of the decompressed data.
+pub fn decompress_bytes_into(mut remaining: Bytes<'_>, target: &mut [u8]) -> UnitResult {
+    let mut decoder = InflateStream::from_zlib();
+    let mut samples = InterleavedSamplesWriter::new(target);
+
+    loop {
+        let (consumed_byte_count, decoded) = decoder.update(remaining)
+            .map_err(|msg| Error::invalid(msg))?;
+
+        for &difference in decoded {
+            if !samples.push_difference(difference) {
+                return Err(Error::invalid("decompressed data is larger than expected"));
+            }
+        }
+
+        remaining = &remaining[consumed_byte_count ..];
+        if consumed_byte_count == 0 { break; }
+    }
+
+    if samples.is_complete() { Ok(()) }
+    else { Err(Error::invalid("decompressed data is smaller than expected")) }
+}
+
 pub fn compress_bytes(packed: Bytes<'_>) -> Result<ByteVec> {
     let mut packed = Vec::from(packed); // TODO no alloc
     separate_bytes_fragments(&mut packed);
@@ -41,3 +110,40 @@ pub fn compress_bytes(packed: Bytes<'_>) -> Result<ByteVec> {
         Ok(compressor.finish()?)
     }
 }
+
+#[cfg(test)]
+mod test {
+
+    #[test]
+    fn roundtrip(){
+        let data: Vec<u8> = (0 .. 2048_u32).map(|index| (index % 13) as u8).collect();
+        let compressed = super::compress_bytes(&data).unwrap();
+        let decompressed = super::decompress_bytes(&compressed, data.len()).unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    fn decompress_only_some_byte_ranges(){
+        let data: Vec<u8> = (0 .. 2047_u32).map(|index| (index % 13) as u8).collect();
+        let compressed = super::compress_bytes(&data).unwrap();
+
+        let ranges = [ 3 .. 100, 1500 .. 2047 ];
+        let decompressed = super::decompress_byte_ranges(&compressed, data.len(), &ranges).unwrap();
+        assert_eq!(decompressed.len(), data.len());
+
+        for range in ranges.iter().cloned() {
+            assert_eq!(decompressed[range.clone()], data[range]);
+        }
+
+        assert_eq!(decompressed[100 .. 1500], [0; 1400][..]);
+    }
+
+    #[test]
+    fn reject_expanding_beyond_expected_size(){
+        let data = vec![ 0_u8; 1024 * 1024 ];
+        let compressed = super::compress_bytes(&data).unwrap();
+        assert!(compressed.len() < 4096);
+
+        assert!(super::decompress_bytes(&compressed, 4096).is_err());
+    }
+}
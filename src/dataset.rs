@@ -0,0 +1,228 @@
+//! Load a large number of images as planar `f32` samples, for example to train a neural network.
+//! Decodes multiple files at once on a bounded thread pool, while the loaded images are consumed in order.
+//! Only available with the `dataset` feature.
+//!
+//! ```no_run
+//! for image in exr::dataset::load(vec!["a.exr", "b.exr"]).prefetch_count(8) {
+//!     let image = image.expect("image could not be loaded");
+//!     println!("{} has the channels {:?}", image.path.display(), image.channel_names);
+//! }
+//! ```
+
+use crate::prelude::*;
+use crate::error::Result;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+
+
+/// The samples of the first valid layer in a file, converted to `f32`, with one plane per channel.
+/// The planes are stored one after another in a single vector,
+/// which is the channel-major layout expected by most machine learning frameworks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlanarImage {
+
+    /// The file that these samples have been loaded from.
+    pub path: PathBuf,
+
+    /// The width and height of each plane.
+    pub resolution: Vec2<usize>,
+
+    /// The name of each plane, sorted alphabetically, as in the file.
+    pub channel_names: SmallVec<[Text; 4]>,
+
+    /// All samples of all planes, where each plane contains `resolution.area()` samples, row by row.
+    pub samples: Vec<f32>,
+}
+
+impl PlanarImage {
+
+    /// Load the first valid layer of the file, converting all samples to `f32`.
+    /// Subsampled channels are not supported.
+    pub fn read_from_file(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+
+        let image = read().no_deep_data().f32_planes().largest_resolution_level().all_channels()
+            .first_valid_layer().all_attributes().non_parallel()
+            .from_file(&path)?;
+
+        let layer = image.layer_data;
+        let resolution = layer.size;
+
+        if layer.channel_data.list.iter().any(|channel| channel.sampling != Vec2(1, 1)) {
+            return Err(Error::unsupported("subsampled channels in dataset"));
+        }
+
+        let channel_names = layer.channel_data.list.iter()
+            .map(|channel| channel.name.clone()).collect();
+
+        let mut samples = Vec::with_capacity(resolution.area() * layer.channel_data.list.len());
+        for channel in layer.channel_data.list {
+            samples.extend_from_slice(&channel.sample_data);
+        }
+
+        Ok(PlanarImage { path, resolution, channel_names, samples })
+    }
+
+    /// The samples of the channel at the specified index.
+    pub fn plane(&self, channel_index: usize) -> &[f32] {
+        let plane_size = self.resolution.area();
+        &self.samples[channel_index * plane_size .. (channel_index + 1) * plane_size]
+    }
+
+    /// The samples of the channel with the specified name, if any.
+    pub fn channel_plane(&self, name: impl AsRef<str>) -> Option<&[f32]> {
+        self.channel_names.iter()
+            .position(|channel_name| channel_name.eq(name.as_ref()))
+            .map(|index| self.plane(index))
+    }
+}
+
+
+/// Load all the specified files as `PlanarImage`s.
+/// Call `into_iter()` or use a `for` loop to start loading the images.
+/// The images are returned in the same order as the paths.
+pub fn load<Paths>(paths: Paths) -> LoadDataset<Paths::IntoIter>
+    where Paths: IntoIterator, Paths::Item: Into<PathBuf>
+{
+    LoadDataset {
+        paths: paths.into_iter(),
+        thread_count: None,
+        prefetch_count: 4,
+    }
+}
+
+/// A temporary loader which can be configured before iterating over the images.
+#[derive(Debug, Clone)]
+pub struct LoadDataset<Paths> {
+    paths: Paths,
+    thread_count: Option<usize>,
+    prefetch_count: usize,
+}
+
+impl<Paths> LoadDataset<Paths> {
+
+    /// Decode at most this many files at once. By default, this uses as many threads as there are CPUs,
+    /// or the number in the environment variable `EXR_THREADS`, see `block::default_thread_count`.
+    pub fn thread_count(self, thread_count: usize) -> Self {
+        Self { thread_count: Some(thread_count.max(1)), ..self }
+    }
+
+    /// Keep at most this many decoded images in memory, ahead of the image that is currently consumed.
+    /// Defaults to four images.
+    pub fn prefetch_count(self, prefetch_count: usize) -> Self {
+        Self { prefetch_count: prefetch_count.max(1), ..self }
+    }
+}
+
+impl<Paths> IntoIterator for LoadDataset<Paths> where Paths: Iterator, Paths::Item: Into<PathBuf> {
+    type Item = Result<PlanarImage>;
+    type IntoIter = DatasetIterator<Paths>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let pool = threadpool::Builder::new()
+            .thread_name("OpenEXR Dataset Loader".to_string())
+            .num_threads(self.thread_count.unwrap_or_else(crate::block::default_thread_count))
+            .build();
+
+        DatasetIterator {
+            paths: self.paths,
+            pool,
+            prefetch_count: self.prefetch_count,
+            pending: VecDeque::with_capacity(self.prefetch_count),
+        }
+    }
+}
+
+/// Loads the images on a thread pool, while always returning them in the order of the paths.
+#[derive(Debug)]
+pub struct DatasetIterator<Paths> {
+    paths: Paths,
+    pool: threadpool::ThreadPool,
+    prefetch_count: usize,
+
+    /// Each image that is currently being loaded, in the order of the paths.
+    pending: VecDeque<flume::Receiver<Result<PlanarImage>>>,
+}
+
+impl<Paths> DatasetIterator<Paths> where Paths: Iterator, Paths::Item: Into<PathBuf> {
+
+    /// Start loading the next images, until enough images are being prefetched.
+    fn start_loading(&mut self) {
+        while self.pending.len() < self.prefetch_count {
+            let path = match self.paths.next() {
+                Some(path) => path.into(),
+                None => break,
+            };
+
+            let (sender, receiver) = flume::bounded(1);
+
+            self.pool.execute(move || {
+                // the receiver may have been dropped, in which case the image is not needed anymore
+                let _ = sender.send(PlanarImage::read_from_file(path));
+            });
+
+            self.pending.push_back(receiver);
+        }
+    }
+}
+
+impl<Paths> Iterator for DatasetIterator<Paths> where Paths: Iterator, Paths::Item: Into<PathBuf> {
+    type Item = Result<PlanarImage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.start_loading();
+        let next = self.pending.pop_front()?;
+        self.start_loading();
+
+        Some(next.recv().unwrap_or_else(|_| Err(Error::invalid("dataset image could not be loaded (thread panicked)"))))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (min, max) = self.paths.size_hint();
+        (min + self.pending.len(), max.map(|max| max + self.pending.len()))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::prelude::*;
+    use crate::dataset::{self, PlanarImage};
+
+    #[test]
+    fn load_in_order() {
+        let temporary_directory = tempfile::tempdir().unwrap();
+        let directory = temporary_directory.path();
+
+        let paths: Vec<_> = (0 .. 6).map(|index| {
+            let path = directory.join(format!("{}.exr", index));
+            write_rgb_file(&path, 3, 2, |x, _y| (index as f32, x as f32, 0.5_f32)).unwrap();
+            path
+        }).collect();
+
+        let mut paths_with_missing = paths.clone();
+        paths_with_missing.insert(2, directory.join("missing.exr"));
+
+        let images: Vec<_> = dataset::load(paths_with_missing).thread_count(3).prefetch_count(2).into_iter().collect();
+        assert_eq!(images.len(), 7);
+        assert!(images[2].is_err(), "missing file");
+
+        let images: Vec<PlanarImage> = images.into_iter().filter_map(|image| image.ok()).collect();
+
+        for (index, (image, path)) in images.iter().zip(&paths).enumerate() {
+            assert_eq!(&image.path, path);
+            assert_eq!(image.resolution, Vec2(3, 2));
+            assert_eq!(image.channel_names.as_slice(), &[ Text::from("B"), Text::from("G"), Text::from("R") ]);
+            assert_eq!(image.samples.len(), 3 * 6);
+            assert_eq!(image.channel_plane("R").unwrap(), &[ index as f32; 6 ]);
+            assert_eq!(image.channel_plane("G").unwrap(), &[ 0.0, 1.0, 2.0, 0.0, 1.0, 2.0 ]);
+            assert_eq!(image.plane(0), &[ 0.5; 6 ]);
+        }
+    }
+
+    #[test]
+    fn reject_subsampled_channels() {
+        let result = PlanarImage::read_from_file("tests/images/valid/openexr/Chromaticities/Rec709_YC.exr");
+        assert!(matches!(result, Err(Error::NotSupported(_))), "{:?}", result);
+    }
+}
@@ -9,6 +9,7 @@ use std::convert::TryFrom;
 use std::error;
 use std::fmt;
 use std::num::TryFromIntError;
+use std::path::PathBuf;
 
 
 // Export types
@@ -21,8 +22,8 @@ pub type UnitResult = Result<()>;
 
 
 /// An error that may happen while reading or writing an exr file.
-/// Distinguishes between three types of errors:
-/// unsupported features, invalid data, and file system errors.
+/// Distinguishes between four types of errors:
+/// unsupported features, invalid data, errors of the byte stream, and files that cannot be opened.
 #[derive(Debug)]
 pub enum Error {
 
@@ -44,6 +45,18 @@ pub enum Error {
     /// The underlying byte stream could not be read successfully,
     /// probably due to file system related errors.
     Io(IoError),
+
+    /// The file at the path could not be opened or created,
+    /// for example because it does not exist or because of missing permissions.
+    /// Errors that happen while reading or writing an opened file are reported as `Io`.
+    File {
+
+        /// The path of the file, as specified when reading or writing.
+        path: PathBuf,
+
+        /// The reason why the file could not be opened or created.
+        source: IoError,
+    },
 }
 
 
@@ -83,6 +96,7 @@ impl error::Error for Error {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match *self {
             Error::Io(ref err) => Some(err),
+            Error::File { ref source, .. } => Some(source),
             _ => None,
         }
     }
@@ -92,6 +106,7 @@ impl fmt::Display for Error {
     fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Error::Io(err) => err.fmt(formatter),
+            Error::File { path, source } => write!(formatter, "file {}: {}", path.display(), source),
             Error::NotSupported(message) => write!(formatter, "not supported: {}", message),
             Error::Invalid(message) => write!(formatter, "invalid: {}", message),
             Error::Aborted => write!(formatter, "cancelled"),
@@ -0,0 +1,327 @@
+//! Convert between associated (premultiplied) and unassociated (straight) alpha.
+//!
+//! OpenEXR specifies that color channels are stored multiplied by alpha.
+//! Many applications work with unassociated alpha instead,
+//! and mixing up the two conventions produces dark or bright fringes around transparent edges.
+//! This module provides explicit conversions, and tracks which convention
+//! a layer uses in the custom layer attribute named `alphaMode`.
+//!
+//! To un-premultiply on read, use `read()...unassociate_alpha()`, which divides the color samples
+//! of each associated layer by alpha while the blocks are loaded, or call `Layer::convert_alpha(AlphaMode::Unassociated)`
+//! after loading the image. To premultiply on write, use `image.write().associate_alpha()`,
+//! which multiplies the color samples of each layer that is marked as unassociated,
+//! convert the layer back with `AlphaMode::Associated`,
+//! or wrap the pixel storage in `Premultiply`, which premultiplies each pixel as it is written.
+
+use std::collections::HashMap;
+use half::f16;
+use crate::image::*;
+use crate::image::pixel_vec::PixelVec;
+use crate::image::write::channels::GetPixel;
+use crate::block::UncompressedBlock;
+use crate::meta::attribute::{ChannelList, ChannelDescription, SampleType};
+use crate::math::Vec2;
+
+
+/// The name of the custom layer attribute that stores the `AlphaMode` of a layer.
+pub const ALPHA_MODE_ATTRIBUTE_NAME: &str = "alphaMode";
+
+/// Whether the color channels of a layer have been multiplied by alpha.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AlphaMode {
+
+    /// The color channels have been multiplied by alpha, also called premultiplied alpha.
+    /// This is the convention of the OpenEXR specification.
+    Associated,
+
+    /// The color channels have not been multiplied by alpha, also called straight alpha.
+    Unassociated,
+}
+
+impl Default for AlphaMode {
+    fn default() -> Self { AlphaMode::Associated }
+}
+
+impl AlphaMode {
+    fn attribute_value(self) -> &'static str {
+        match self {
+            AlphaMode::Associated => "associated",
+            AlphaMode::Unassociated => "unassociated",
+        }
+    }
+}
+
+impl LayerAttributes {
+
+    /// Whether the color channels of this layer have been multiplied by alpha.
+    /// Returns `AlphaMode::Associated`, as required by the specification,
+    /// unless the custom `alphaMode` attribute states otherwise.
+    pub fn alpha_mode(&self) -> AlphaMode {
+        match self.other.get(&Text::from(ALPHA_MODE_ATTRIBUTE_NAME)) {
+            Some(AttributeValue::Text(text)) if text.eq_case_insensitive(AlphaMode::Unassociated.attribute_value())
+                => AlphaMode::Unassociated,
+
+            _ => AlphaMode::Associated,
+        }
+    }
+
+    /// Record whether the color channels of this layer have been multiplied by alpha.
+    /// Does not modify any pixels.
+    /// The attribute is omitted for associated alpha, as that is the default.
+    pub fn set_alpha_mode(&mut self, mode: AlphaMode) {
+        let name = Text::from(ALPHA_MODE_ATTRIBUTE_NAME);
+
+        match mode {
+            AlphaMode::Associated => { self.other.remove(&name); },
+            AlphaMode::Unassociated => {
+                self.other.insert(name, AttributeValue::Text(Text::from(mode.attribute_value())));
+            },
+        }
+    }
+
+    /// Record whether the color channels of this layer have been multiplied by alpha.
+    /// Does not modify any pixels.
+    pub fn with_alpha_mode(mut self, mode: AlphaMode) -> Self {
+        self.set_alpha_mode(mode);
+        self
+    }
+}
+
+/// Multiply the color of an rgba pixel by its alpha.
+#[inline]
+pub fn premultiply((r, g, b, a): (f32, f32, f32, f32)) -> (f32, f32, f32, f32) {
+    (r * a, g * a, b * a, a)
+}
+
+/// Divide the color of an rgba pixel by its alpha.
+/// Pixels with an alpha of zero are returned unchanged, as their color cannot be recovered.
+#[inline]
+pub fn unpremultiply((r, g, b, a): (f32, f32, f32, f32)) -> (f32, f32, f32, f32) {
+    if a == 0.0 { (r, g, b, a) }
+    else { (r / a, g / a, b / a, a) }
+}
+
+/// Multiply or divide the color samples of the block by the alpha samples of the same pixels,
+/// converting the block from the other alpha mode to the specified mode.
+/// The channels `R`, `G`, and `B` are converted using the channel `A`. Channels with a layer name prefix,
+/// such as `diffuse.R`, are converted using the alpha channel with the same prefix, such as `diffuse.A`.
+/// Channels without such an alpha channel, with a different sampling rate, or with integer samples are not changed.
+/// As for `unpremultiply`, samples with an alpha of zero are not divided.
+pub fn convert_block_alpha(channels: &ChannelList, block: &mut UncompressedBlock, mode: AlphaMode) {
+    let alpha_channels: SmallVec<[Option<usize>; 8]> = channels.list.iter()
+        .map(|channel| alpha_channel_index(channels, channel)).collect();
+
+    if alpha_channels.iter().all(Option::is_none) { return; }
+
+    // the alpha samples of each line, by the index of the alpha channel and the row of the line
+    let mut alpha_lines = HashMap::new();
+    for line in block.lines(channels) {
+        let channel = line.location.channel;
+
+        if alpha_channels.contains(&Some(channel)) {
+            let sample_type = channels.list[channel].sample_type;
+            let alpha: Vec<f32> = line.value.chunks_exact(sample_type.bytes_per_sample())
+                .map(|sample| read_float_sample(sample, sample_type)).collect();
+
+            alpha_lines.insert((channel, line.location.position.y()), alpha);
+        }
+    }
+
+    for line in block.lines_mut(channels) {
+        let alpha = match alpha_channels[line.location.channel] {
+            Some(alpha_channel) => &alpha_lines[&(alpha_channel, line.location.position.y())],
+            None => continue,
+        };
+
+        let sample_type = channels.list[line.location.channel].sample_type;
+        for (sample, &alpha) in line.value.chunks_exact_mut(sample_type.bytes_per_sample()).zip(alpha) {
+            let color = read_float_sample(sample, sample_type);
+
+            let color = match mode {
+                AlphaMode::Associated => color * alpha,
+                AlphaMode::Unassociated => if alpha == 0.0 { color } else { color / alpha },
+            };
+
+            write_float_sample(sample, sample_type, color);
+        }
+    }
+}
+
+/// Whether any of the channels would be converted by `convert_block_alpha`.
+pub fn has_alpha_channels(channels: &ChannelList) -> bool {
+    channels.list.iter().any(|channel| alpha_channel_index(channels, channel).is_some())
+}
+
+/// The index of the alpha channel that belongs to this color channel, if any.
+fn alpha_channel_index(channels: &ChannelList, channel: &ChannelDescription) -> Option<usize> {
+    if channel.sample_type == SampleType::U32 { return None; }
+
+    let name = channel.name.to_string();
+    let (prefix, suffix) = match name.rfind('.') {
+        Some(dot) => name.split_at(dot + 1),
+        None => ("", name.as_str()),
+    };
+
+    if !matches!(suffix, "R" | "G" | "B") { return None; }
+    let alpha_name = Text::from(format!("{}A", prefix).as_str());
+
+    channels.list.iter().position(|alpha|
+        alpha.name == alpha_name && alpha.sampling == channel.sampling && alpha.sample_type != SampleType::U32
+    )
+}
+
+fn read_float_sample(sample: &[u8], sample_type: SampleType) -> f32 {
+    match sample_type {
+        SampleType::F16 => f16::from_le_bytes([sample[0], sample[1]]).to_f32(),
+        SampleType::F32 => f32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]),
+        SampleType::U32 => unreachable!("integer samples are never converted"),
+    }
+}
+
+fn write_float_sample(sample: &mut [u8], sample_type: SampleType, value: f32) {
+    match sample_type {
+        SampleType::F16 => sample.copy_from_slice(&f16::from_f32(value).to_le_bytes()),
+        SampleType::F32 => sample.copy_from_slice(&value.to_le_bytes()),
+        SampleType::U32 => unreachable!("integer samples are never converted"),
+    }
+}
+
+/// Wraps your pixel storage, containing unassociated alpha,
+/// and premultiplies every pixel while it is written to a file.
+/// Use this for `SpecificChannels::rgba(Premultiply(my_pixels))`, without modifying or copying your pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Premultiply<Storage>(pub Storage);
+
+impl<Storage> GetPixel for Premultiply<Storage> where Storage: GetPixel<Pixel = (f32, f32, f32, f32)> {
+    type Pixel = (f32, f32, f32, f32);
+
+    fn get_pixel(&self, position: Vec2<usize>) -> Self::Pixel {
+        premultiply(self.0.get_pixel(position))
+    }
+}
+
+impl<Channels> Layer<SpecificChannels<PixelVec<(f32, f32, f32, f32)>, Channels>> {
+
+    /// Convert the pixels of this layer to the specified alpha mode,
+    /// and record the new mode in the layer attributes.
+    /// Does nothing if the layer attributes state that the pixels already use the specified mode.
+    pub fn convert_alpha(&mut self, mode: AlphaMode) {
+        if self.attributes.alpha_mode() == mode { return; }
+
+        let convert = match mode {
+            AlphaMode::Associated => premultiply,
+            AlphaMode::Unassociated => unpremultiply,
+        };
+
+        for pixel in &mut self.channel_data.pixels.pixels {
+            *pixel = convert(*pixel);
+        }
+
+        self.attributes.set_alpha_mode(mode);
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::prelude::*;
+    use crate::image::alpha::{AlphaMode, Premultiply};
+    use crate::image::pixel_vec::PixelVec;
+    use std::io::Cursor;
+
+    #[test]
+    fn convert_roundtrip() {
+        let pixels = vec![ (0.5, 0.25, 1.0, 0.5), (1.0, 1.0, 1.0, 0.0), (0.2, 0.4, 0.6, 1.0) ];
+        let straight = PixelVec::new((3, 1), pixels.clone());
+
+        let image = Image::from_channels((3, 1), SpecificChannels::rgba(Premultiply(straight)));
+
+        let mut bytes = Vec::new();
+        image.write().non_parallel().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        let mut image = read().no_deep_data().largest_resolution_level()
+            .rgba_channels(PixelVec::<(f32, f32, f32, f32)>::constructor, PixelVec::set_pixel)
+            .first_valid_layer().all_attributes().non_parallel()
+            .from_buffered(Cursor::new(&bytes)).unwrap();
+
+        let layer = &mut image.layer_data;
+        assert_eq!(layer.attributes.alpha_mode(), AlphaMode::Associated);
+        assert_eq!(layer.channel_data.pixels.pixels[0], (0.25, 0.125, 0.5, 0.5));
+
+        layer.convert_alpha(AlphaMode::Unassociated);
+        assert_eq!(layer.attributes.alpha_mode(), AlphaMode::Unassociated);
+        assert_eq!(layer.channel_data.pixels.pixels[0], pixels[0]);
+        assert_eq!(layer.channel_data.pixels.pixels[2], pixels[2]);
+
+        // converting twice does not divide again
+        layer.convert_alpha(AlphaMode::Unassociated);
+        assert_eq!(layer.channel_data.pixels.pixels[0], pixels[0]);
+
+        let mut bytes = Vec::new();
+        image.write().non_parallel().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        let image = read().no_deep_data().largest_resolution_level().all_channels()
+            .first_valid_layer().all_attributes().non_parallel()
+            .from_buffered(Cursor::new(&bytes)).unwrap();
+
+        assert_eq!(image.layer_data.attributes.alpha_mode(), AlphaMode::Unassociated, "attribute is preserved in file");
+    }
+
+    #[test]
+    fn associate_on_write_and_unassociate_on_read() {
+        let pixels = vec![ (0.5, 0.25, 1.0, 0.5), (0.0, 0.0, 0.0, 0.0), (0.2, 0.4, 0.6, 1.0) ];
+        let straight = PixelVec::new((3, 1), pixels.clone());
+
+        let attributes = LayerAttributes::named("straight").with_alpha_mode(AlphaMode::Unassociated);
+        let layer = Layer::new((3, 1), attributes, Encoding::UNCOMPRESSED, SpecificChannels::rgba(straight));
+        let image = Image::from_layer(layer);
+
+        let mut bytes = Vec::new();
+        image.write().associate_alpha().non_parallel().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        let read_rgba = || read().no_deep_data().largest_resolution_level()
+            .rgba_channels(PixelVec::<(f32, f32, f32, f32)>::constructor, PixelVec::set_pixel)
+            .first_valid_layer().all_attributes().non_parallel();
+
+        let associated = read_rgba().from_buffered(Cursor::new(&bytes)).unwrap().layer_data;
+        assert_eq!(associated.attributes.alpha_mode(), AlphaMode::Associated);
+        assert_eq!(associated.channel_data.pixels.pixels[0], (0.25, 0.125, 0.5, 0.5));
+
+        let unassociated = read_rgba().unassociate_alpha().from_buffered(Cursor::new(&bytes)).unwrap().layer_data;
+        assert_eq!(unassociated.attributes.alpha_mode(), AlphaMode::Unassociated);
+        assert_eq!(unassociated.channel_data.pixels.pixels, pixels);
+    }
+
+    #[test]
+    fn convert_prefixed_channels_of_block() {
+        use crate::block::{BlockIndex, UncompressedBlock};
+        use crate::image::alpha::convert_block_alpha;
+        use crate::meta::attribute::{ChannelList, ChannelDescription, SampleType};
+
+        let channels = ChannelList::new(smallvec![
+            ChannelDescription::named("diffuse.A", SampleType::F32),
+            ChannelDescription::named("diffuse.R", SampleType::F16),
+            ChannelDescription::named("id", SampleType::U32),
+            ChannelDescription::named("specular.R", SampleType::F32),
+        ]);
+
+        let index = BlockIndex { layer: 0, level: Vec2(0, 0), pixel_position: Vec2(0, 0), pixel_size: Vec2(2, 1) };
+        let data = [ 0.5_f32, 0.0 ].iter().flat_map(|sample| sample.to_le_bytes())
+            .chain([ f16::ONE, f16::ONE ].iter().flat_map(|sample| sample.to_le_bytes()))
+            .chain([ 7_u32, 8 ].iter().flat_map(|sample| sample.to_le_bytes()))
+            .chain([ 3.0_f32, 4.0 ].iter().flat_map(|sample| sample.to_le_bytes()))
+            .collect();
+
+        let original = UncompressedBlock { index, data };
+        let mut block = original.clone();
+
+        convert_block_alpha(&channels, &mut block, AlphaMode::Associated);
+        let diffuse: Vec<f16> = block.lines(&channels).nth(1).unwrap().read_samples().collect::<Result<_>>().unwrap();
+        assert_eq!(diffuse, vec![ f16::from_f32(0.5), f16::ZERO ]);
+        assert_eq!(block.data[12 ..], original.data[12 ..], "integer channel and channel without alpha are unchanged");
+
+        convert_block_alpha(&channels, &mut block, AlphaMode::Unassociated);
+        let diffuse: Vec<f16> = block.lines(&channels).nth(1).unwrap().read_samples().collect::<Result<_>>().unwrap();
+        assert_eq!(diffuse, vec![ f16::ONE, f16::ZERO ], "zero alpha cannot be recovered");
+    }
+}
@@ -0,0 +1,170 @@
+//! Conventional names for the layers and channels of render passes,
+//! also called arbitrary output variables or AOVs.
+//!
+//! Multi-pass renders store each pass in its own layer. Using the same names in every file
+//! allows compositing software to find the passes without manual configuration.
+//! Use `Aov::layer_name` and `Aov::channel_names` to look up the conventional names,
+//! or create a layer that follows the conventions with `Layer::aov`, `Layer::normals` or `Layer::z_depth`.
+//! The unlayered `R`, `G`, `B` and `A` channels of a file contain the final color of the render.
+//! Motion vectors are described in the `motion` module.
+
+use crate::image::*;
+use crate::error::{Result, Error};
+use crate::image::depth::DEPTH_CHANNEL_NAME;
+use crate::image::write::channels::GetPixel;
+
+
+/// The names of the red, green, and blue channels of color passes.
+pub const RGB_CHANNEL_NAMES: [&str; 3] = [ "R", "G", "B" ];
+
+/// The names of the channels of a vector, such as a normal or a position, in a layer of its own.
+pub const VECTOR_CHANNEL_NAMES: [&str; 3] = [ "X", "Y", "Z" ];
+
+/// A common render pass.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Aov {
+
+    /// The color contributed by diffuse reflection.
+    Diffuse,
+
+    /// The color contributed by specular reflection.
+    Specular,
+
+    /// The color of light sources and emissive surfaces.
+    Emission,
+
+    /// The camera-space depth of each pixel, in the `Z` channel.
+    Depth,
+
+    /// The surface normal of each pixel.
+    Normal,
+}
+
+impl Aov {
+
+    /// All render passes, in the conventional order of layers in a file.
+    pub const ALL: [Aov; 5] = [
+        Aov::Diffuse, Aov::Specular, Aov::Emission, Aov::Depth, Aov::Normal,
+    ];
+
+    /// The conventional name of the layer that contains this pass.
+    pub fn layer_name(self) -> &'static str {
+        match self {
+            Aov::Diffuse => "diffuse",
+            Aov::Specular => "specular",
+            Aov::Emission => "emission",
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+        }
+    }
+
+    /// The conventional names of the channels of this pass, within its layer.
+    pub fn channel_names(self) -> &'static [&'static str] {
+        match self {
+            Aov::Diffuse | Aov::Specular | Aov::Emission => &RGB_CHANNEL_NAMES,
+            Aov::Depth => &[ DEPTH_CHANNEL_NAME ],
+            Aov::Normal => &VECTOR_CHANNEL_NAMES,
+        }
+    }
+
+    /// The sample type that is conventionally used for the channels of this pass.
+    /// Depth and vectors require the precision of `f32`, and colors are stored as `f16`.
+    pub fn sample_type(self) -> SampleType {
+        match self {
+            Aov::Diffuse | Aov::Specular | Aov::Emission => SampleType::F16,
+            Aov::Depth | Aov::Normal => SampleType::F32,
+        }
+    }
+
+    /// The pass with this conventional layer name, if any. Ignores the case of the name.
+    pub fn from_layer_name(layer_name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|aov| aov.layer_name().eq_ignore_ascii_case(layer_name))
+    }
+
+    /// The pass of the layer, if the layer name is conventional.
+    pub fn of_layer(attributes: &LayerAttributes) -> Option<Self> {
+        Self::from_layer_name(&attributes.layer_name.as_ref()?.to_string())
+    }
+}
+
+impl std::fmt::Display for Aov {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        formatter.write_str(self.layer_name())
+    }
+}
+
+fn channel<Sample: IntoSample>(aov: Aov, index: usize) -> ChannelDescription {
+    ChannelDescription::named(aov.channel_names()[index], Sample::PREFERRED_SAMPLE_TYPE)
+}
+
+impl<Pixels> Layer<SpecificChannels<Pixels, (ChannelDescription, ChannelDescription, ChannelDescription)>> {
+
+    /// Create a layer for a pass with three channels, such as the diffuse or specular pass,
+    /// named and with channels as is conventional for this pass.
+    /// Returns an error if the pass does not contain exactly three channels, such as `Aov::Depth`.
+    pub fn aov<R, G, B>(aov: Aov, size: impl Into<Vec2<usize>>, pixels: Pixels) -> Result<Self>
+        where R: IntoSample, G: IntoSample, B: IntoSample, Pixels: GetPixel<Pixel=(R, G, B)>
+    {
+        if aov.channel_names().len() != 3 {
+            return Err(Error::invalid(format!("the pass `{}` does not contain three channels", aov)));
+        }
+
+        Ok(Self::three_channel_aov::<R, G, B>(aov, size.into(), pixels))
+    }
+
+    /// Create a layer named `normal`, with the channels `X`, `Y` and `Z`.
+    pub fn normals<X, Y, Z>(size: impl Into<Vec2<usize>>, pixels: Pixels) -> Self
+        where X: IntoSample, Y: IntoSample, Z: IntoSample, Pixels: GetPixel<Pixel=(X, Y, Z)>
+    {
+        Self::three_channel_aov::<X, Y, Z>(Aov::Normal, size.into(), pixels)
+    }
+
+    fn three_channel_aov<R: IntoSample, G: IntoSample, B: IntoSample>(aov: Aov, size: Vec2<usize>, pixels: Pixels) -> Self {
+        let channels = SpecificChannels {
+            channels: (channel::<R>(aov, 0), channel::<G>(aov, 1), channel::<B>(aov, 2)),
+            pixels,
+        };
+
+        Layer { channel_data: channels, attributes: LayerAttributes::named(aov.layer_name()), size, encoding: Encoding::default() }
+    }
+}
+
+impl<Pixels> Layer<SpecificChannels<Pixels, (ChannelDescription,)>> {
+
+    /// Create a layer named `depth`, with the camera-space depth in the channel `Z`.
+    pub fn z_depth<Z>(size: impl Into<Vec2<usize>>, pixels: Pixels) -> Self
+        where Z: IntoSample, Pixels: GetPixel<Pixel=(Z,)>
+    {
+        let aov = Aov::Depth;
+        let channels = SpecificChannels { channels: (channel::<Z>(aov, 0),), pixels };
+        Layer { channel_data: channels, attributes: LayerAttributes::named(aov.layer_name()), size: size.into(), encoding: Encoding::default() }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn conventional_names() {
+        assert_eq!(Aov::from_layer_name("Diffuse"), Some(Aov::Diffuse));
+        assert_eq!(Aov::from_layer_name("unknown"), None);
+
+        for aov in Aov::ALL {
+            assert_eq!(Aov::of_layer(&LayerAttributes::named(aov.layer_name())), Some(aov));
+        }
+
+        let depth = Layer::z_depth((4, 3), |position: Vec2<usize>| (position.x() as f32,));
+        assert_eq!(depth.attributes.layer_name, Some(Text::from("depth")));
+        assert_eq!(depth.channel_data.channels.0, ChannelDescription::named("Z", SampleType::F32));
+
+        let diffuse = Layer::aov(Aov::Diffuse, (4, 3), |_: Vec2<usize>| (f16::ONE, f16::ONE, f16::ZERO)).unwrap();
+        assert_eq!(diffuse.channel_data.channels.2.sample_type, SampleType::F16);
+
+        let normals = Layer::normals((4, 3), |_: Vec2<usize>| (0.0_f32, 1.0_f32, 0.0_f32));
+        assert_eq!(normals.channel_data.channels.1.name, Text::from("Y"));
+
+        assert!(Layer::aov(Aov::Depth, (4, 3), |_: Vec2<usize>| (0.0_f32, 0.0_f32, 0.0_f32)).is_err());
+    }
+}
@@ -5,8 +5,10 @@ use crate::meta::attribute::{IntegerBounds, LevelMode, ChannelList};
 use crate::math::{Vec2, RoundingMode};
 use crate::image::{Layer, FlatSamples, SpecificChannels, AnyChannels, FlatSamplesPixel, AnyChannel};
 use crate::image::write::channels::{GetPixel, WritableChannels, ChannelsWriter};
+use crate::image::write::dither::Dithering;
 use crate::meta::header::{LayerAttributes, Header};
 use crate::block::BlockIndex;
+use crate::error::Result;
 
 /// Something that has a two-dimensional rectangular shape
 pub trait GetBounds {
@@ -171,11 +173,11 @@ impl<'slf, Channels:'slf> WritableChannels<'slf> for CroppedChannels<Channels> w
 
     type Writer = CroppedWriter<Channels::Writer>;
 
-    fn create_writer(&'slf self, header: &Header) -> Self::Writer {
+    fn create_writer(&'slf self, header: &Header) -> Result<Self::Writer> {
         let offset = (self.cropped_bounds.position - self.full_bounds.position)
-            .to_usize("invalid cropping bounds for cropped view").unwrap();
+            .to_usize("invalid cropping bounds for cropped view")?;
 
-        CroppedWriter { channels: self.full_channels.create_writer(header), offset }
+        Ok(CroppedWriter { channels: self.full_channels.create_writer(header)?, offset })
     }
 }
 
@@ -195,6 +197,15 @@ impl<'c, Channels> ChannelsWriter for CroppedWriter<Channels> where Channels: Ch
 
         self.channels.extract_uncompressed_block(header, block)
     }
+
+    fn extract_dithered_block(&self, header: &Header, block: BlockIndex, dithering: &Dithering) -> Vec<u8> {
+        let block = BlockIndex {
+            pixel_position: block.pixel_position + self.offset,
+            .. block
+        };
+
+        self.channels.extract_dithered_block(header, block, dithering)
+    }
 }
 
 impl<Samples, Channels> InspectSample for Layer<SpecificChannels<Samples, Channels>> where Samples: GetPixel {
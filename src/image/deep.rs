@@ -0,0 +1,439 @@
+//! In-memory deep data, where each pixel contains any number of samples at different depths.
+//!
+//! Reading and writing deep data from files is not supported yet,
+//! but deep samples from other sources can already be processed using this module,
+//! for example to flatten them into an ordinary image.
+
+use crate::math::Vec2;
+use crate::image::pixel_vec::PixelVec;
+use crate::error::{Result, Error};
+use std::ops::Range;
+use std::cmp::Ordering;
+
+
+/// A single sample of a deep pixel.
+/// The color is expected to be premultiplied by alpha, as required by the OpenEXR specification.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DeepSample<Color> {
+
+    /// The distance from the camera to the front of this sample. Stored in the `Z` channel.
+    pub depth: f32,
+
+    /// The distance from the camera to the back of this sample. Stored in the `ZBack` channel.
+    /// Equal to `depth` for point samples, which represent hard surfaces.
+    /// Samples where this is larger than `depth` represent a volume, like fog.
+    pub depth_back: f32,
+
+    /// The opacity of this sample. Stored in the `A` channel.
+    pub alpha: f32,
+
+    /// The premultiplied color of this sample, for example rgb.
+    pub color: Color,
+}
+
+/// The color of a deep sample, which can be composited.
+/// Implemented for `f32`, `(f32, f32, f32)` and arrays of `f32`.
+pub trait DeepColor: Copy + Default {
+
+    /// Multiply each color component by the factor.
+    fn scaled(self, factor: f32) -> Self;
+
+    /// Add each color component of the other color to this color.
+    fn plus(self, other: Self) -> Self;
+}
+
+impl DeepColor for f32 {
+    fn scaled(self, factor: f32) -> Self { self * factor }
+    fn plus(self, other: Self) -> Self { self + other }
+}
+
+impl DeepColor for (f32, f32, f32) {
+    fn scaled(self, factor: f32) -> Self { (self.0 * factor, self.1 * factor, self.2 * factor) }
+    fn plus(self, other: Self) -> Self { (self.0 + other.0, self.1 + other.1, self.2 + other.2) }
+}
+
+impl<const N: usize> DeepColor for [f32; N] where [f32; N]: Default {
+    fn scaled(self, factor: f32) -> Self { self.map(|value| value * factor) }
+
+    fn plus(mut self, other: Self) -> Self {
+        for (value, other) in self.iter_mut().zip(other) { *value += other; }
+        self
+    }
+}
+
+
+/// Stores the deep samples of all pixels in a single vector.
+/// The samples of all pixels are stored one after another, row by row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeepPixels<Color> {
+
+    /// The resolution of this layer.
+    pub resolution: Vec2<usize>,
+
+    /// For each pixel, the index in `samples` after the last sample of that pixel.
+    /// This is the cumulative sample count, as it is stored in the file.
+    pub sample_offsets: Vec<usize>,
+
+    /// The samples of all pixels, one pixel after another.
+    pub samples: Vec<DeepSample<Color>>,
+}
+
+impl<Color> DeepPixels<Color> {
+
+    /// Create deep pixels from the cumulative sample counts and all samples.
+    /// Returns an error if the offsets do not match the resolution or the number of samples.
+    pub fn new(
+        resolution: impl Into<Vec2<usize>>, sample_offsets: Vec<usize>, samples: Vec<DeepSample<Color>>
+    ) -> Result<Self>
+    {
+        let resolution = resolution.into();
+
+        if sample_offsets.len() != resolution.area() {
+            return Err(Error::invalid("deep sample offset count"));
+        }
+
+        if sample_offsets.iter().zip(sample_offsets.iter().skip(1)).any(|(previous, next)| previous > next) {
+            return Err(Error::invalid("deep sample offsets must not decrease"));
+        }
+
+        if sample_offsets.last().copied().unwrap_or(0) != samples.len() {
+            return Err(Error::invalid("deep sample count"));
+        }
+
+        Ok(Self { resolution, sample_offsets, samples })
+    }
+
+    /// Create deep pixels from a list of samples for each pixel, row by row.
+    /// Returns an error if the number of pixels does not match the resolution.
+    pub fn from_pixels(
+        resolution: impl Into<Vec2<usize>>,
+        pixels: impl IntoIterator<Item = impl IntoIterator<Item = DeepSample<Color>>>
+    ) -> Result<Self>
+    {
+        let mut samples = Vec::new();
+        let sample_offsets = pixels.into_iter()
+            .map(|pixel| { samples.extend(pixel); samples.len() })
+            .collect();
+
+        Self::new(resolution, sample_offsets, samples)
+    }
+
+    /// All samples of the pixel at the specified position, in the order they are stored.
+    /// Panics if the position is outside of the resolution.
+    pub fn samples_at(&self, position: Vec2<usize>) -> &[DeepSample<Color>] {
+        &self.samples[self.sample_range(self.compute_pixel_index(position))]
+    }
+
+    /// All samples of the pixel at the specified position, for modification.
+    /// Panics if the position is outside of the resolution.
+    pub fn samples_at_mut(&mut self, position: Vec2<usize>) -> &mut [DeepSample<Color>] {
+        let range = self.sample_range(self.compute_pixel_index(position));
+        &mut self.samples[range]
+    }
+
+    /// The number of samples in the pixel at the specified position.
+    pub fn sample_count_at(&self, position: Vec2<usize>) -> usize {
+        self.sample_range(self.compute_pixel_index(position)).len()
+    }
+
+    /// Iterate over the depth and color of each sample in the pixel at the specified position.
+    pub fn depth_colors_at(&self, position: Vec2<usize>) -> impl '_ + Iterator<Item = (f32, &Color)> {
+        self.samples_at(position).iter().map(|sample| (sample.depth, &sample.color))
+    }
+
+    /// Iterate over all pixels, row by row, with the position and the samples of each pixel.
+    pub fn pixels(&self) -> impl '_ + Iterator<Item = (Vec2<usize>, &[DeepSample<Color>])> {
+        (0 .. self.sample_offsets.len()).map(move |pixel_index| (
+            Vec2(pixel_index % self.resolution.width(), pixel_index / self.resolution.width()),
+            &self.samples[self.sample_range(pixel_index)]
+        ))
+    }
+
+    /// Compute the flat index of a pixel, which is also the index of its entry in `sample_offsets`.
+    pub fn compute_pixel_index(&self, position: Vec2<usize>) -> usize {
+        debug_assert!(position.x() < self.resolution.width() && position.y() < self.resolution.height(), "pixel position out of bounds");
+        position.flat_index_for_size(self.resolution)
+    }
+
+    /// The range of indices in `samples` that belong to the pixel with the specified flat index.
+    fn sample_range(&self, pixel_index: usize) -> Range<usize> {
+        let start = if pixel_index == 0 { 0 } else { self.sample_offsets[pixel_index - 1] };
+        start .. self.sample_offsets[pixel_index]
+    }
+
+    /// Replace the samples of each pixel with the result of the closure,
+    /// which receives the current samples of the pixel in a temporary vector.
+    fn modify_pixels(&mut self, mut modify: impl FnMut(&mut Vec<DeepSample<Color>>)) where Color: Copy {
+        let mut pixel_samples = Vec::new();
+        let mut samples = Vec::with_capacity(self.samples.len());
+
+        for pixel_index in 0 .. self.sample_offsets.len() {
+            pixel_samples.clear();
+            pixel_samples.extend_from_slice(&self.samples[self.sample_range(pixel_index)]);
+            modify(&mut pixel_samples);
+
+            samples.extend_from_slice(&pixel_samples);
+            self.sample_offsets[pixel_index] = samples.len();
+        }
+
+        self.samples = samples;
+    }
+
+    /// Sort the samples of each pixel by their depth, the nearest sample first.
+    /// Samples with the same front are ordered by their back.
+    pub fn sort_samples(&mut self) where Color: Copy {
+        self.modify_pixels(|samples| samples.sort_by(compare_depth));
+    }
+
+    /// Split volume samples of each pixel wherever the front or back of another sample in that pixel lies inside the volume.
+    /// Afterwards, any two samples of a pixel either cover exactly the same depth range, or do not overlap at all.
+    /// The opacity of each piece is computed such that compositing all pieces yields the original volume.
+    pub fn split_volumes(&mut self) where Color: DeepColor {
+        self.modify_pixels(split_pixel_volumes);
+    }
+
+    /// Combine all samples of each pixel that have both the same front and the same back,
+    /// as if they were a single mixed volume. Also sorts the samples.
+    /// Call `split_volumes` before to also merge samples that only partially overlap.
+    pub fn merge_overlapping(&mut self) where Color: DeepColor {
+        self.modify_pixels(merge_pixel_overlaps);
+    }
+
+    /// Split and merge the samples of each pixel, such that they are sorted by depth and no two samples overlap.
+    /// This is what the OpenEXR documentation calls a tidy image.
+    pub fn tidy(&mut self) where Color: DeepColor {
+        self.modify_pixels(|samples| {
+            split_pixel_volumes(samples);
+            merge_pixel_overlaps(samples);
+        });
+    }
+
+    /// Composite all samples of each pixel, from front to back, into a flat image.
+    /// Each resulting pixel contains the premultiplied color and the alpha.
+    /// Overlapping samples are split and merged as in `tidy` before compositing,
+    /// without modifying the samples in this storage.
+    pub fn flatten(&self) -> PixelVec<(Color, f32)> where Color: DeepColor {
+        let mut tidy = Vec::new();
+
+        let pixels = (0 .. self.sample_offsets.len()).map(|pixel_index| {
+            tidy.clear();
+            tidy.extend_from_slice(&self.samples[self.sample_range(pixel_index)]);
+            split_pixel_volumes(&mut tidy);
+            merge_pixel_overlaps(&mut tidy);
+            composite_front_to_back(&tidy)
+        }).collect();
+
+        PixelVec { resolution: self.resolution, pixels }
+    }
+}
+
+/// Split all volume samples of a pixel at the depths of all other samples.
+fn split_pixel_volumes<Color: DeepColor>(samples: &mut Vec<DeepSample<Color>>) {
+    let mut depths: Vec<f32> = samples.iter()
+        .flat_map(|sample| [sample.depth, sample.depth_back])
+        .collect();
+
+    depths.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    depths.dedup();
+
+    let original_samples = std::mem::take(samples);
+
+    for sample in original_samples {
+        let mut remaining = sample;
+
+        for &depth in &depths {
+            if depth > remaining.depth && depth < remaining.depth_back {
+                let (front, back) = split_volume_sample(remaining, depth);
+                samples.push(front);
+                remaining = back;
+            }
+        }
+
+        samples.push(remaining);
+    }
+
+    samples.sort_by(compare_depth);
+}
+
+/// Split a volume sample into two pieces at the specified depth, which must lie inside the volume.
+fn split_volume_sample<Color: DeepColor>(sample: DeepSample<Color>, depth: f32) -> (DeepSample<Color>, DeepSample<Color>) {
+    let alpha = sample.alpha.max(0.0).min(1.0);
+    let thickness = sample.depth_back - sample.depth;
+    let front_fraction = (depth - sample.depth) / thickness;
+    let back_fraction = (sample.depth_back - depth) / thickness;
+
+    let piece = |fraction: f32| {
+        if alpha == 1.0 { (1.0, sample.color) }
+        else if alpha > f32::MIN_POSITIVE {
+            let piece_alpha = -(fraction * (-alpha).ln_1p()).exp_m1();
+            (piece_alpha, sample.color.scaled(piece_alpha / alpha))
+        }
+        else { (alpha * fraction, sample.color.scaled(fraction)) }
+    };
+
+    let (front_alpha, front_color) = piece(front_fraction);
+    let (back_alpha, back_color) = piece(back_fraction);
+
+    (
+        DeepSample { depth: sample.depth, depth_back: depth, alpha: front_alpha, color: front_color },
+        DeepSample { depth, depth_back: sample.depth_back, alpha: back_alpha, color: back_color },
+    )
+}
+
+/// Merge all samples of a pixel with the same front and back into a single sample.
+fn merge_pixel_overlaps<Color: DeepColor>(samples: &mut Vec<DeepSample<Color>>) {
+    samples.sort_by(compare_depth);
+
+    samples.dedup_by(|next, merged| {
+        let overlaps = next.depth == merged.depth && next.depth_back == merged.depth_back;
+        if overlaps { *merged = merge_samples(*merged, *next); }
+        overlaps
+    });
+}
+
+/// Combine two samples covering the same depth range into one.
+fn merge_samples<Color: DeepColor>(first: DeepSample<Color>, second: DeepSample<Color>) -> DeepSample<Color> {
+    let first_alpha = first.alpha.max(0.0).min(1.0);
+    let second_alpha = second.alpha.max(0.0).min(1.0);
+    let alpha = first_alpha + second_alpha - first_alpha * second_alpha;
+
+    let color = {
+        if first_alpha == 1.0 && second_alpha == 1.0 { first.color.plus(second.color).scaled(0.5) }
+        else if first_alpha == 1.0 { first.color }
+        else if second_alpha == 1.0 { second.color }
+        else {
+            let first_density = -(-first_alpha).ln_1p();
+            let second_density = -(-second_alpha).ln_1p();
+            let density = first_density + second_density;
+
+            let first_weight = if first_density < first_alpha * f32::MAX { first_density / first_alpha } else { 1.0 };
+            let second_weight = if second_density < second_alpha * f32::MAX { second_density / second_alpha } else { 1.0 };
+            let weight = if density > 1.0 || alpha < density * f32::MAX { alpha / density } else { 1.0 };
+
+            first.color.scaled(first_weight).plus(second.color.scaled(second_weight)).scaled(weight)
+        }
+    };
+
+    DeepSample { depth: first.depth, depth_back: first.depth_back, alpha, color }
+}
+
+/// Order samples by their front, and then by their back.
+fn compare_depth<Color>(a: &DeepSample<Color>, b: &DeepSample<Color>) -> Ordering {
+    a.depth.partial_cmp(&b.depth).unwrap_or(Ordering::Equal)
+        .then(a.depth_back.partial_cmp(&b.depth_back).unwrap_or(Ordering::Equal))
+}
+
+/// Composite the sorted samples of a pixel, the nearest sample first.
+fn composite_front_to_back<Color: DeepColor>(sorted_samples: &[DeepSample<Color>]) -> (Color, f32) {
+    sorted_samples.iter().fold((Color::default(), 0.0), |(color, alpha), sample| {
+        let transmission = 1.0 - alpha;
+        (color.plus(sample.color.scaled(transmission)), alpha + sample.alpha * transmission)
+    })
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::image::deep::{DeepPixels, DeepSample};
+    use crate::math::Vec2;
+
+    fn sample(depth: f32, alpha: f32, color: f32) -> DeepSample<f32> {
+        DeepSample { depth, depth_back: depth, alpha, color }
+    }
+
+    #[test]
+    fn flatten_sorts_by_depth() {
+        let pixels = DeepPixels::from_pixels((2, 1), vec![
+            vec![ sample(5.0, 1.0, 0.25), sample(1.0, 0.5, 0.5) ],
+            vec![],
+        ]).unwrap();
+
+        let flat = pixels.flatten();
+        assert_eq!(flat.pixels, vec![ (0.5 + 0.25 * 0.5, 1.0), (0.0, 0.0) ]);
+    }
+
+    fn volume(depth: f32, depth_back: f32, alpha: f32, color: f32) -> DeepSample<f32> {
+        DeepSample { depth, depth_back, alpha, color }
+    }
+
+    #[test]
+    fn sort_samples() {
+        let mut pixels = DeepPixels::from_pixels((1, 2), vec![
+            vec![ sample(3.0, 0.5, 0.1), volume(1.0, 2.0, 0.5, 0.3), sample(1.0, 0.5, 0.2) ],
+            vec![ sample(2.0, 0.5, 0.4) ],
+        ]).unwrap();
+
+        pixels.sort_samples();
+
+        let depths: Vec<f32> = pixels.samples.iter().map(|sample| sample.depth).collect();
+        assert_eq!(depths, vec![ 1.0, 1.0, 3.0, 2.0 ]);
+        assert_eq!(pixels.samples[0].depth_back, 1.0, "point sample before volume");
+        assert_eq!(pixels.samples[1].depth_back, 2.0);
+        assert_eq!(pixels.sample_offsets, vec![ 3, 4 ]);
+    }
+
+    #[test]
+    fn split_volume_preserves_flat_result() {
+        let original = DeepPixels::from_pixels((1, 1), vec![
+            vec![ volume(1.0, 3.0, 0.75, 0.5), sample(2.0, 0.0, 0.0), sample(1.5, 0.0, 0.0) ],
+        ]).unwrap();
+
+        let mut split = original.clone();
+        split.split_volumes();
+
+        let ranges: Vec<(f32, f32)> = split.samples.iter().map(|sample| (sample.depth, sample.depth_back)).collect();
+        assert_eq!(ranges, vec![ (1.0, 1.5), (1.5, 1.5), (1.5, 2.0), (2.0, 2.0), (2.0, 3.0) ]);
+
+        let (color, alpha) = split.flatten().pixels[0];
+        assert!((alpha - 0.75).abs() < 0.0001, "alpha {}", alpha);
+        assert!((color - 0.5).abs() < 0.0001, "color {}", color);
+    }
+
+    #[test]
+    fn merge_overlapping_samples() {
+        let mut pixels = DeepPixels::from_pixels((1, 1), vec![
+            vec![ volume(1.0, 2.0, 0.5, 0.25), volume(1.0, 2.0, 0.5, 0.5), sample(4.0, 1.0, 1.0) ],
+        ]).unwrap();
+
+        pixels.merge_overlapping();
+        assert_eq!(pixels.samples.len(), 2);
+        assert_eq!(pixels.sample_offsets, vec![ 2 ]);
+
+        let merged = pixels.samples[0];
+        assert!((merged.alpha - 0.75).abs() < 0.0001, "alpha {}", merged.alpha);
+
+        // two equally dense volumes contribute equally to the color
+        assert!((merged.color - 0.5625).abs() < 0.0001, "color {}", merged.color);
+    }
+
+    #[test]
+    fn access_pixel_samples() {
+        let mut pixels = DeepPixels::from_pixels((2, 2), vec![
+            vec![ sample(1.0, 0.5, 0.1) ],
+            vec![],
+            vec![ sample(2.0, 0.5, 0.2), sample(3.0, 0.5, 0.3) ],
+            vec![ sample(4.0, 0.5, 0.4) ],
+        ]).unwrap();
+
+        assert_eq!(pixels.sample_count_at(Vec2(1, 0)), 0);
+        assert_eq!(pixels.sample_count_at(Vec2(0, 1)), 2);
+        assert_eq!(pixels.samples_at(Vec2(1, 1)), &[ sample(4.0, 0.5, 0.4) ]);
+
+        let pairs: Vec<(f32, f32)> = pixels.depth_colors_at(Vec2(0, 1)).map(|(depth, &color)| (depth, color)).collect();
+        assert_eq!(pairs, vec![ (2.0, 0.2), (3.0, 0.3) ]);
+
+        pixels.samples_at_mut(Vec2(0, 0))[0].alpha = 1.0;
+        assert_eq!(pixels.samples[0].alpha, 1.0);
+
+        let counts: Vec<(Vec2<usize>, usize)> = pixels.pixels().map(|(position, samples)| (position, samples.len())).collect();
+        assert_eq!(counts, vec![ (Vec2(0, 0), 1), (Vec2(1, 0), 0), (Vec2(0, 1), 2), (Vec2(1, 1), 1) ]);
+    }
+
+    #[test]
+    fn reject_invalid_offsets() {
+        assert!(DeepPixels::new((2, 1), vec![1, 0], vec![ sample(1.0, 1.0, 1.0) ]).is_err());
+        assert!(DeepPixels::new((2, 1), vec![0, 2], vec![ sample(1.0, 1.0, 1.0) ]).is_err());
+        assert!(DeepPixels::new((1, 1), vec![0, 1], vec![ sample(1.0, 1.0, 1.0) ]).is_err());
+        assert!(DeepPixels::new((2, 1), vec![0, 1], vec![ sample(1.0, 1.0, 1.0) ]).is_ok());
+    }
+}
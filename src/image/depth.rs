@@ -0,0 +1,292 @@
+//! Post-process the depth channels `Z` and `ZBack` of a layer.
+//!
+//! Load a layer with all channels, for example using `read_first_flat_layer_from_file`,
+//! then call `layer.depth()` to obtain the depth channels as `f32` planes.
+//! The planes can be normalized for display, converted to disparity,
+//! and converted between camera-space depth and normalized device coordinates,
+//! using the `worldToCamera` and `worldToNDC` attributes of the layer.
+
+use crate::image::*;
+use crate::math::{Vec2, Matrix4};
+use crate::meta::attribute::{ChannelDescription, IntegerBounds};
+use crate::image::write::samples::WritableSamples;
+use crate::error::{Result, Error};
+
+
+/// The name of the channel that contains the distance from the camera to the front of each pixel.
+pub const DEPTH_CHANNEL_NAME: &str = "Z";
+
+/// The name of the channel that contains the distance from the camera to the back of each pixel, in deep or volumetric images.
+pub const BACK_DEPTH_CHANNEL_NAME: &str = "ZBack";
+
+/// The samples of a single depth channel, converted to `f32`, row by row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DepthPlane {
+
+    /// The width and height of the plane. Smaller than the layer, if the channel is subsampled.
+    pub size: Vec2<usize>,
+
+    /// All samples, row by row. Background pixels are often infinite.
+    pub samples: Vec<f32>,
+}
+
+/// The depth channels of a layer, and the camera projection, if the layer has the required attributes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Depth {
+
+    /// The `Z` channel.
+    pub front: DepthPlane,
+
+    /// The `ZBack` channel, if present.
+    pub back: Option<DepthPlane>,
+
+    /// The projection of the camera, if the layer has the `worldToCamera` and `worldToNDC` attributes.
+    pub projection: Option<DepthProjection>,
+}
+
+/// Converts between the camera-space depth of a pixel and its z coordinate in normalized device coordinates.
+/// As specified for the `worldToCamera` attribute, the camera looks along the positive z axis,
+/// so the depth of a pixel is its z coordinate in camera space.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DepthProjection {
+    camera_to_normalized_device: Matrix4,
+}
+
+
+impl Layer<AnyChannels<FlatSamples>> {
+
+    /// The `Z` and `ZBack` channels of this layer, and the camera projection from the attributes of this layer.
+    /// Returns `None` if the layer has no `Z` channel.
+    /// Returns an error if the number of samples of a depth channel does not match the size of the layer.
+    pub fn depth(&self) -> Result<Option<Depth>> {
+        let front = match self.depth_plane(DEPTH_CHANNEL_NAME)? {
+            Some(front) => front,
+            None => return Ok(None),
+        };
+
+        Ok(Some(Depth {
+            front,
+            back: self.depth_plane(BACK_DEPTH_CHANNEL_NAME)?,
+            projection: DepthProjection::from_attributes(&self.attributes),
+        }))
+    }
+
+    /// The samples of the channel with this name, converted to `f32`.
+    /// Returns `None` if there is no channel with this name.
+    /// Returns an error if the number of samples does not match the size of the layer,
+    /// or if the data window of the layer does not start and end at a multiple of the sampling rate of the channel.
+    pub fn depth_plane(&self, channel_name: &str) -> Result<Option<DepthPlane>> {
+        let channel = match self.channel_data.list.iter().find(|channel| channel.name.eq(channel_name)) {
+            Some(channel) => channel,
+            None => return Ok(None),
+        };
+
+        let description = ChannelDescription {
+            name: channel.name.clone(),
+            sample_type: channel.sample_data.sample_type(),
+            quantize_linearly: channel.quantize_linearly,
+            sampling: channel.sampling,
+        };
+
+        let data_window = IntegerBounds::new(self.attributes.layer_position, self.size);
+        let sampling = description.sampling;
+
+        // a subsampled channel has samples in every n-th row and column of the whole image, not of the data window
+        let is_aligned = sampling.x() != 0 && sampling.y() != 0
+            && data_window.position.x() % sampling.x() as i32 == 0 && data_window.position.y() % sampling.y() as i32 == 0
+            && data_window.size.x() % sampling.x() == 0 && data_window.size.y() % sampling.y() == 0;
+
+        if !is_aligned {
+            return Err(Error::invalid("depth channel sampling factor not dividing data window"));
+        }
+
+        let size = description.subsampled_resolution(self.size);
+        DepthPlane::new(size, channel.sample_data.to_f32_vec()).map(Some)
+    }
+}
+
+impl DepthPlane {
+
+    /// Create a plane from samples, row by row.
+    /// Returns an error if the number of samples does not match the size.
+    pub fn new(size: impl Into<Vec2<usize>>, samples: Vec<f32>) -> Result<Self> {
+        let size = size.into();
+
+        if size.area() != samples.len() {
+            return Err(Error::invalid("depth sample count does not match the size"));
+        }
+
+        Ok(DepthPlane { size, samples })
+    }
+
+    /// The sample at this position.
+    pub fn get(&self, position: Vec2<usize>) -> f32 {
+        self.samples[position.flat_index_for_size(self.size)]
+    }
+
+    /// The smallest and largest finite sample, or `None` if no sample is finite.
+    pub fn finite_range(&self) -> Option<(f32, f32)> {
+        self.samples.iter().filter(|sample| sample.is_finite())
+            .fold(None, |range, &sample| match range {
+                None => Some((sample, sample)),
+                Some((min, max)) => Some((sample.min(min), sample.max(max))),
+            })
+    }
+
+    /// Apply a function to each sample.
+    pub fn map(&self, mut map_sample: impl FnMut(f32) -> f32) -> Self {
+        DepthPlane { size: self.size, samples: self.samples.iter().map(|&sample| map_sample(sample)).collect() }
+    }
+
+    /// Map the finite samples to the range from zero (near) to one (far).
+    /// Infinite background samples become one, and invalid samples stay invalid.
+    /// If all finite samples are equal, they become zero.
+    pub fn normalized(&self) -> Self {
+        let (min, max) = match self.finite_range() {
+            Some(range) => range,
+            None => return self.map(|sample| if sample.is_nan() { sample } else { 1.0 }),
+        };
+
+        let scale = if max > min { 1.0 / (max - min) } else { 0.0 };
+
+        self.map(|sample| {
+            if sample.is_nan() { sample }
+            else if sample.is_infinite() { if sample > 0.0 { 1.0 } else { 0.0 } }
+            else { (sample - min) * scale }
+        })
+    }
+
+    /// Subtract each sample from one, such that normalized near samples become bright.
+    pub fn inverted(&self) -> Self {
+        self.map(|sample| 1.0 - sample)
+    }
+
+    /// The reciprocal of each sample, which is proportional to the stereo disparity.
+    /// Infinite background samples become zero.
+    pub fn to_disparity(&self) -> Self {
+        self.map(|sample| 1.0 / sample)
+    }
+
+    /// Convert the camera-space depth of each sample to the z coordinate in normalized device coordinates.
+    pub fn to_normalized_device(&self, projection: &DepthProjection) -> Self {
+        self.map(|depth| projection.depth_to_normalized_device(depth))
+    }
+
+    /// Convert the z coordinate in normalized device coordinates of each sample to camera-space depth.
+    pub fn from_normalized_device(&self, projection: &DepthProjection) -> Self {
+        self.map(|z| projection.normalized_device_to_depth(z))
+    }
+}
+
+impl DepthProjection {
+
+    /// Combine the matrices of the `worldToCamera` and `worldToNDC` attributes.
+    /// Returns `None` if the world to camera matrix cannot be inverted.
+    pub fn new(world_to_camera: Matrix4, world_to_normalized_device: Matrix4) -> Option<Self> {
+        let camera_to_world = world_to_camera.inverse()?;
+        Some(DepthProjection { camera_to_normalized_device: camera_to_world * world_to_normalized_device })
+    }
+
+    /// The projection of the camera, if the layer has the `worldToCamera` and `worldToNDC` attributes.
+    pub fn from_attributes(attributes: &LayerAttributes) -> Option<Self> {
+        Self::new(attributes.world_to_camera_matrix()?, attributes.world_to_normalized_device_matrix()?)
+    }
+
+    /// Convert the camera-space depth of a pixel to the z coordinate in normalized device coordinates.
+    pub fn depth_to_normalized_device(&self, depth: f32) -> f32 {
+        let matrix = &self.camera_to_normalized_device;
+
+        // OpenEXR matrices transform row vectors, the point is (0, 0, depth, 1)
+        (depth * matrix.get(2, 2) + matrix.get(3, 2)) / (depth * matrix.get(2, 3) + matrix.get(3, 3))
+    }
+
+    /// Convert the z coordinate in normalized device coordinates of a pixel to camera-space depth.
+    pub fn normalized_device_to_depth(&self, z: f32) -> f32 {
+        let matrix = &self.camera_to_normalized_device;
+        (matrix.get(3, 2) - z * matrix.get(3, 3)) / (z * matrix.get(2, 3) - matrix.get(2, 2))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn perspective(near: f32, far: f32) -> Matrix4 {
+        Matrix4([
+            1.0, 0.0, 0.0, 0.0,
+            0.0, 1.0, 0.0, 0.0,
+            0.0, 0.0, (far + near) / (far - near), 1.0,
+            0.0, 0.0, -2.0 * far * near / (far - near), 0.0,
+        ])
+    }
+
+    #[test]
+    fn normalize_and_invert_depth() {
+        let plane = DepthPlane::new((2, 2), vec![ 2.0, 4.0, 6.0, f32::INFINITY ]).unwrap();
+        assert_eq!(plane.finite_range(), Some((2.0, 6.0)));
+        assert_eq!(plane.normalized().samples, vec![ 0.0, 0.5, 1.0, 1.0 ]);
+        assert_eq!(plane.normalized().inverted().samples, vec![ 1.0, 0.5, 0.0, 0.0 ]);
+        assert_eq!(plane.to_disparity().samples, vec![ 0.5, 0.25, 1.0 / 6.0, 0.0 ]);
+    }
+
+    #[test]
+    fn convert_depth_to_normalized_device_coordinates() {
+        // the world is translated, such that the projection is applied after the translation
+        let translation = Matrix4::translation(0.0, 0.0, -3.0);
+        let projection = DepthProjection::new(translation, translation * perspective(1.0, 100.0)).unwrap();
+
+        assert!((projection.depth_to_normalized_device(1.0) + 1.0).abs() < 1e-5, "near plane");
+        assert!((projection.depth_to_normalized_device(100.0) - 1.0).abs() < 1e-5, "far plane");
+
+        let plane = DepthPlane::new((3, 1), vec![ 1.0, 10.0, 50.0 ]).unwrap();
+        let roundtrip = plane.to_normalized_device(&projection).from_normalized_device(&projection);
+        for (depth, expected) in roundtrip.samples.iter().zip(&plane.samples) {
+            assert!((depth - expected).abs() < 1e-2 * expected, "expected {} but got {}", expected, depth);
+        }
+
+        assert_eq!(DepthProjection::new(Matrix4::default(), perspective(1.0, 2.0)), None, "singular matrix");
+    }
+
+    #[test]
+    fn depth_channels_of_layer() {
+        let channels = AnyChannels::sort(smallvec![
+            AnyChannel::new("Z", FlatSamples::F32(vec![ 1.0, 2.0, 3.0, 4.0, 5.0, 6.0 ])),
+            AnyChannel::new("B", FlatSamples::F16(vec![ f16::ZERO; 6 ])),
+        ]);
+
+        let mut attributes = LayerAttributes::named("depth");
+        attributes.world_to_camera = Some(perspective(1.0, 2.0).into());
+
+        let layer = Layer::new((3, 2), attributes, Encoding::UNCOMPRESSED, channels);
+        let depth = layer.depth().unwrap().unwrap();
+
+        assert_eq!(depth.front.size, Vec2(3, 2));
+        assert_eq!(depth.front.get(Vec2(1, 1)), 5.0);
+        assert_eq!(depth.back, None);
+        assert_eq!(depth.projection, None, "the world to ndc matrix is missing");
+    }
+
+    #[test]
+    fn subsampled_depth_size() {
+        let mut channel = AnyChannel::new("Z", FlatSamples::F32(vec![ 1.0, 2.0, 3.0, 4.0, 5.0, 6.0 ]));
+        channel.sampling = Vec2(2, 2);
+
+        let attributes = LayerAttributes::named("depth").with_position(Vec2(-2, 4));
+        let layer = Layer::new((6, 4), attributes.clone(), Encoding::UNCOMPRESSED, AnyChannels::sort(smallvec![ channel.clone() ]));
+        assert_eq!(layer.depth().unwrap().unwrap().front.size, Vec2(3, 2));
+
+        let odd_position = attributes.clone().with_position(Vec2(1, 4));
+        let layer = Layer::new((6, 4), odd_position, Encoding::UNCOMPRESSED, AnyChannels::sort(smallvec![ channel.clone() ]));
+        assert!(layer.depth().is_err(), "data window does not start at a multiple of the sampling rate");
+
+        let layer = Layer::new((5, 4), attributes.clone(), Encoding::UNCOMPRESSED, AnyChannels::sort(smallvec![ channel.clone() ]));
+        assert!(layer.depth().is_err(), "data window does not end at a multiple of the sampling rate");
+
+        channel.sample_data = FlatSamples::F32(vec![ 1.0 ]);
+        let layer = Layer::new((6, 4), attributes, Encoding::UNCOMPRESSED, AnyChannels::sort(smallvec![ channel ]));
+        assert!(layer.depth().is_err(), "sample count does not match the size");
+
+        assert!(DepthPlane::new((2, 2), vec![ 1.0 ]).is_err());
+    }
+}
@@ -26,7 +26,17 @@
 pub mod read;
 pub mod write;
 pub mod crop;
+pub mod alpha;
+pub mod ops;
+pub mod resize;
+pub mod deep;
+pub mod depth;
+pub mod aov;
+pub mod motion;
 pub mod pixel_vec;
+pub mod storage;
+pub mod pixel_struct;
+pub mod transcode;
 pub mod recursive;
 // pub mod channel_groups;
 
@@ -62,6 +72,14 @@ pub type RgbaLayersImage<Storage> = PixelLayersImage<Storage, RgbaChannels>;
 /// This image type contains a single layer containing a user-defined type of rgba pixels.
 pub type RgbaImage<Storage> = PixelImage<Storage, RgbaChannels>;
 
+/// A layer that borrows the channels of another layer, created with `Layer::view`.
+/// Can be written, cropped, or passed around without cloning the samples.
+pub type LayerRef<'channels, Channels> = Layer<&'channels Channels>;
+
+/// An image with layers that borrow the channels of other layers, created with `Image::view`.
+/// The layers may also be borrowed from different images, using `Image::from_layers`.
+pub type ImageRef<'channels, Channels> = Image<Layers<&'channels Channels>>;
+
 /// Contains information about the channels in an rgba image, in the order `(red, green, blue, alpha)`.
 /// The alpha channel is not required. May be `None` if the image did not contain an alpha channel.
 pub type RgbaChannels = (ChannelDescription, ChannelDescription, ChannelDescription, Option<ChannelDescription>);
@@ -292,8 +310,7 @@ use crate::block::samples::Sample;
 use crate::image::write::channels::*;
 use crate::image::write::layers::WritableLayers;
 use crate::image::write::samples::{WritableSamples};
-use crate::meta::{mip_map_levels, rip_map_levels};
-use crate::io::Data;
+use crate::meta::{mip_map_levels, rip_map_levels, compute_level_count};
 use crate::image::recursive::{NoneMore, Recursive, IntoRecursive};
 use std::marker::PhantomData;
 use std::ops::Not;
@@ -305,6 +322,17 @@ impl<Channels> Layer<Channels> {
     pub fn absolute_bounds(&self) -> IntegerBounds {
         IntegerBounds::new(self.attributes.layer_position, self.size)
     }
+
+    /// A layer with the same attributes, which borrows the channels of this layer.
+    /// Clones the attributes, but not the samples.
+    pub fn view(&self) -> LayerRef<'_, Channels> {
+        Layer {
+            channel_data: &self.channel_data,
+            attributes: self.attributes.clone(),
+            size: self.size,
+            encoding: self.encoding,
+        }
+    }
 }
 
 
@@ -334,6 +362,7 @@ pub trait IntoSample: IntoNativeSample {
 impl IntoSample for f16 { const PREFERRED_SAMPLE_TYPE: SampleType = SampleType::F16; }
 impl IntoSample for f32 { const PREFERRED_SAMPLE_TYPE: SampleType = SampleType::F32; }
 impl IntoSample for u32 { const PREFERRED_SAMPLE_TYPE: SampleType = SampleType::U32; }
+impl<T: IntoSample> IntoSample for Option<T> { const PREFERRED_SAMPLE_TYPE: SampleType = T::PREFERRED_SAMPLE_TYPE; }
 
 /// Used to construct a `SpecificChannels`.
 /// Call `with_named_channel` as many times as desired,
@@ -510,6 +539,25 @@ impl Layer<AnyChannels<FlatSamples>> {
     }
 }
 
+impl<Samples> Layer<AnyChannels<Samples>> {
+
+    /// Lookup the samples of the channel with the specified name, if the layer contains such a channel.
+    /// For flat samples, these are all samples of that channel, row after row,
+    /// without touching the other channels.
+    pub fn channel_plane(&self, name: impl Into<Text>) -> Option<&Samples> {
+        self.channel_data.channel_plane(name)
+    }
+}
+
+impl Layer<AnyChannels<FlatSamples>> {
+
+    /// Copy all samples of the channel with the specified name into a new vector, converting them to `f32`.
+    /// Returns `None` if the layer does not contain a channel with that name.
+    pub fn channel_plane_to_f32(&self, name: impl Into<Text>) -> Option<Vec<f32>> {
+        self.channel_plane(name).map(FlatSamples::to_f32_vec)
+    }
+}
+
 /// Iterate over all channels of a single pixel in the image
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct FlatSampleIterator<'s> {
@@ -540,6 +588,24 @@ impl<SampleData> AnyChannels<SampleData>{
         list.sort_unstable_by_key(|channel| channel.name.clone()); // TODO no clone?
         Self { list }
     }
+
+    /// Lookup the channel with the specified name.
+    pub fn find(&self, name: impl Into<Text>) -> Option<&AnyChannel<SampleData>> {
+        let name = name.into();
+        self.list.iter().find(|channel| channel.name == name)
+    }
+
+    /// Lookup the channel with the specified name, for modification.
+    pub fn find_mut(&mut self, name: impl Into<Text>) -> Option<&mut AnyChannel<SampleData>> {
+        let name = name.into();
+        self.list.iter_mut().find(|channel| channel.name == name)
+    }
+
+    /// Lookup the samples of the channel with the specified name.
+    /// For flat samples, these are all samples of that channel, row after row.
+    pub fn channel_plane(&self, name: impl Into<Text>) -> Option<&SampleData> {
+        self.find(name).map(|channel| &channel.sample_data)
+    }
 }
 
 // FIXME check content size of layer somewhere??? before writing?
@@ -641,6 +707,62 @@ impl<Samples> RipMaps<Samples> {
     }
 }
 
+impl Levels<FlatSamples> {
+
+    /// Use precomputed mip map levels, for example from a GPU pipeline.
+    /// The first level has the full resolution, and each following level has half the size of the previous level,
+    /// rounded with the specified rounding mode, down to a single pixel.
+    /// Returns an error if the number of levels or the sample count of any level does not match.
+    /// The layer must use tiles when being written.
+    pub fn from_mip_maps(resolution: impl Into<Vec2<usize>>, rounding_mode: RoundingMode, level_data: LevelMaps<FlatSamples>) -> Result<Self> {
+        let resolution = resolution.into();
+        let level_sizes: Vec<Vec2<usize>> = mip_map_levels(rounding_mode, resolution).map(|(_index, size)| size).collect();
+        validate_level_sizes(&level_sizes, &level_data)?;
+        Ok(Levels::Mip { rounding_mode, level_data })
+    }
+
+    /// Use precomputed rip map levels. The levels are ordered by their level index,
+    /// where the horizontal level index increases fastest, starting with the full resolution.
+    /// Returns an error if the number of levels or the sample count of any level does not match.
+    /// The layer must use tiles when being written.
+    pub fn from_rip_maps(resolution: impl Into<Vec2<usize>>, rounding_mode: RoundingMode, level_data: LevelMaps<FlatSamples>) -> Result<Self> {
+        let resolution = resolution.into();
+        let level_sizes: Vec<Vec2<usize>> = rip_map_levels(rounding_mode, resolution).map(|(_index, size)| size).collect();
+        validate_level_sizes(&level_sizes, &level_data)?;
+
+        let level_count = Vec2(
+            compute_level_count(rounding_mode, resolution.width()),
+            compute_level_count(rounding_mode, resolution.height()),
+        );
+
+        Ok(Levels::Rip { rounding_mode, level_data: RipMaps { map_data: level_data, level_count } })
+    }
+}
+
+/// Check that each level contains the samples of a level with the expected size.
+fn validate_level_sizes(expected_sizes: &[Vec2<usize>], levels: &[FlatSamples]) -> crate::error::UnitResult {
+    if levels.len() != expected_sizes.len() {
+        return Err(Error::invalid(format!(
+            "expected {} resolution levels, but found {}", expected_sizes.len(), levels.len()
+        )));
+    }
+
+    for (index, (size, level)) in expected_sizes.iter().zip(levels).enumerate() {
+        if level.len() != size.area() {
+            return Err(Error::invalid(format!(
+                "resolution level {} should contain {} samples ({} x {}), but contains {}",
+                index, size.area(), size.width(), size.height(), level.len()
+            )));
+        }
+
+        if level.sample_type() != levels[0].sample_type() {
+            return Err(Error::invalid("all resolution levels must have the same sample type"));
+        }
+    }
+
+    Ok(())
+}
+
 impl FlatSamples {
 
     /// The number of samples in the image. Should be the width times the height.
@@ -677,6 +799,59 @@ impl FlatSamples {
             FlatSamples::U32(vec) => Sample::U32(vec[index]),
         }
     }
+
+    /// Copy all samples into a new vector, converting them to `f32`.
+    pub fn to_f32_vec(&self) -> Vec<f32> {
+        match self {
+            FlatSamples::F16(vec) => vec.iter().map(|sample| sample.to_f32()).collect(),
+            FlatSamples::F32(vec) => vec.clone(),
+            FlatSamples::U32(vec) => vec.iter().map(|&sample| sample as f32).collect(),
+        }
+    }
+
+    /// The samples as a contiguous slice, if they are stored as `f16`.
+    pub fn as_f16_slice(&self) -> Option<&[f16]> {
+        if let FlatSamples::F16(vec) = self { Some(vec) } else { None }
+    }
+
+    /// The samples as a contiguous slice, if they are stored as `f32`.
+    pub fn as_f32_slice(&self) -> Option<&[f32]> {
+        if let FlatSamples::F32(vec) = self { Some(vec) } else { None }
+    }
+
+    /// The samples as a contiguous slice, if they are stored as `u32`.
+    pub fn as_u32_slice(&self) -> Option<&[u32]> {
+        if let FlatSamples::U32(vec) = self { Some(vec) } else { None }
+    }
+
+    /// The raw bits of the samples, if they are stored as `f16`.
+    /// Graphics apis usually expect half floats in this form. Does not copy.
+    #[cfg(feature = "bytemuck")]
+    pub fn as_f16_bits_slice(&self) -> Option<&[u16]> {
+        self.as_f16_slice().map(bytemuck::cast_slice)
+    }
+
+    /// The memory of all samples, in the native byte order of this machine, regardless of the sample type.
+    /// Can be uploaded to the gpu or passed to a C library without copying.
+    #[cfg(feature = "bytemuck")]
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            FlatSamples::F16(vec) => bytemuck::cast_slice(vec),
+            FlatSamples::F32(vec) => bytemuck::cast_slice(vec),
+            FlatSamples::U32(vec) => bytemuck::cast_slice(vec),
+        }
+    }
+
+    /// The mutable memory of all samples, in the native byte order of this machine.
+    /// Allows filling the samples directly, for example when downloading from the gpu.
+    #[cfg(feature = "bytemuck")]
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        match self {
+            FlatSamples::F16(vec) => bytemuck::cast_slice_mut(vec),
+            FlatSamples::F32(vec) => bytemuck::cast_slice_mut(vec),
+            FlatSamples::U32(vec) => bytemuck::cast_slice_mut(vec),
+        }
+    }
 }
 
 
@@ -745,6 +920,34 @@ impl Encoding {
         blocks: Blocks::Tiles(Vec2(256, 256)),
         line_order: LineOrder::Unspecified
     };
+
+    /// Prefer writing and reading speed over file size. Equivalent to `Encoding::FAST_LOSSLESS`.
+    pub const fn fast() -> Self { Encoding::FAST_LOSSLESS }
+
+    /// Prefer small files over speed, without losing any information. Equivalent to `Encoding::SMALL_LOSSLESS`.
+    pub const fn small() -> Self { Encoding::SMALL_LOSSLESS }
+
+    /// Lossless ZIP compression of single scan lines, in increasing order.
+    /// Compositing software usually reads the image line by line,
+    /// which requires decompressing only one line at a time.
+    pub const fn for_compositing() -> Self {
+        Encoding {
+            compression: Compression::ZIP1,
+            blocks: Blocks::ScanLines,
+            line_order: LineOrder::Increasing
+        }
+    }
+
+    /// Lossless ZIP compression of tiles with the specified size, in any order.
+    /// Renderers can load only the tiles they need, and each tile is compressed as a whole.
+    /// Combine with `Levels` in the layer to also write mip maps or rip maps.
+    pub const fn for_textures(tile_size: Vec2<usize>) -> Self {
+        Encoding {
+            compression: Compression::ZIP16,
+            blocks: Blocks::Tiles(tile_size),
+            line_order: LineOrder::Unspecified
+        }
+    }
 }
 
 impl Default for Encoding {
@@ -766,6 +969,20 @@ impl<'s, Channels: 's> Image<Layers<Channels>> where Channels: WritableChannels<
     }
 }
 
+impl<Channels> Image<Layers<Channels>> {
+    /// An image with the same attributes, whose layers borrow the channels of this image.
+    pub fn view(&self) -> ImageRef<'_, Channels> {
+        Image { attributes: self.attributes.clone(), layer_data: self.layer_data.iter().map(Layer::view).collect() }
+    }
+}
+
+impl<Channels> Image<Layer<Channels>> {
+    /// An image with the same attributes, whose layer borrows the channels of this image.
+    pub fn view(&self) -> Image<LayerRef<'_, Channels>> {
+        Image { attributes: self.attributes.clone(), layer_data: self.layer_data.view() }
+    }
+}
+
 
 impl<'s, ChannelData:'s> Image<Layer<ChannelData>> where ChannelData: WritableChannels<'s> {
 
@@ -787,6 +1004,38 @@ impl<'s, ChannelData:'s> Image<Layer<ChannelData>> where ChannelData: WritableCh
     }
 }
 
+impl Image<Layer<AnyChannels<FlatSamples>>> {
+
+    /// Create an image with red, green, blue, and alpha channels, from interleaved `f32` samples.
+    /// The samples are stored row by row, with four samples per pixel: `[r, g, b, a, r, g, b, a, ...]`.
+    /// Uses empty attributes and fast compression.
+    /// Panics if the number of samples does not match the size.
+    pub fn from_rgba_f32(size: impl Into<Vec2<usize>>, interleaved_samples: Vec<f32>) -> Self {
+        let size = size.into();
+        assert_eq!(interleaved_samples.len(), size.area() * 4, "rgba sample count does not match image size");
+
+        let channel = |name: &str, offset: usize| AnyChannel::new(name, FlatSamples::F32(
+            interleaved_samples.iter().skip(offset).step_by(4).copied().collect()
+        ));
+
+        // the channels are already sorted alphabetically
+        let channels = AnyChannels { list: smallvec![ channel("A", 3), channel("B", 2), channel("G", 1), channel("R", 0) ] };
+        Self::from_channels(size, channels)
+    }
+
+    /// Create an image with a single channel, for example a depth channel named `Z`.
+    /// The samples are stored row by row and can be a `Vec` of `f16`, `f32`, or `u32` values.
+    /// Uses empty attributes and fast compression.
+    /// Panics if the number of samples does not match the size.
+    pub fn from_single_channel(name: impl Into<Text>, size: impl Into<Vec2<usize>>, samples: impl Into<FlatSamples>) -> Self {
+        let size = size.into();
+        let samples = samples.into();
+        assert_eq!(samples.len(), size.area(), "sample count does not match image size");
+
+        Self::from_channels(size, AnyChannels { list: smallvec![ AnyChannel::new(name, samples) ] })
+    }
+}
+
 
 impl Image<NoneMore> {
 
@@ -838,6 +1087,10 @@ impl<'s, SampleData: 's> AnyChannel<SampleData> {
     }*/
 }
 
+impl From<Vec<f16>> for FlatSamples { fn from(samples: Vec<f16>) -> Self { FlatSamples::F16(samples) } }
+impl From<Vec<f32>> for FlatSamples { fn from(samples: Vec<f32>) -> Self { FlatSamples::F32(samples) } }
+impl From<Vec<u32>> for FlatSamples { fn from(samples: Vec<u32>) -> Self { FlatSamples::U32(samples) } }
+
 impl std::fmt::Debug for FlatSamples {
     fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if self.len() <= 6 {
@@ -1344,3 +1597,21 @@ pub mod validate_results {
 }
 
 
+
+
+#[cfg(all(test, feature = "bytemuck"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn view_samples_as_bytes() {
+        let mut samples = FlatSamples::F16(vec![ f16::ONE, f16::ZERO ]);
+        assert_eq!(samples.as_f16_bits_slice(), Some(&[ f16::ONE.to_bits(), 0 ][..]));
+        assert_eq!(&samples.as_bytes()[0 .. 2], &f16::ONE.to_ne_bytes());
+        assert_eq!(&samples.as_bytes()[2 .. 4], &[ 0, 0 ]);
+
+        samples.as_bytes_mut()[2 .. 4].copy_from_slice(&f16::ONE.to_ne_bytes());
+        assert_eq!(samples.as_f16_slice(), Some(&[ f16::ONE, f16::ONE ][..]));
+        assert_eq!(FlatSamples::F32(vec![ 1.0; 3 ]).as_bytes().len(), 12);
+    }
+}
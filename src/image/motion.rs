@@ -0,0 +1,149 @@
+//! Two-dimensional motion vectors, which describe how far each pixel moves between frames.
+//!
+//! Motion vectors are stored in a pair of channels, for example `velocity.x` and `velocity.y`,
+//! or as separate forward and backward vectors, such as `forward.u` and `forward.v`.
+//! In the file, the vectors are measured in pixels, with the y axis pointing down, like the pixel coordinates.
+//! Retiming and vector blur tools may instead expect normalized device coordinates,
+//! where the display window spans from minus one to one in each dimension.
+//!
+//! To read motion vectors, use `read().no_deep_data().largest_resolution_level().motion_vector_channels()`.
+//! Reading returns an error if either of the two channels is subsampled.
+//! To write motion vectors, use `SpecificChannels::motion_vectors`.
+
+use crate::image::*;
+use crate::image::write::channels::GetPixel;
+
+
+/// The names of the horizontal and vertical channels of a motion vector.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MotionVectorNames {
+
+    /// The name of the channel that contains the horizontal movement.
+    pub x: Text,
+
+    /// The name of the channel that contains the vertical movement.
+    pub y: Text,
+}
+
+/// The unit of the motion vectors in your pixel storage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MotionSpace {
+
+    /// Pixels, as stored in the file. No conversion is necessary.
+    Pixels,
+
+    /// Normalized device coordinates, where the width and the height of the display window are two.
+    /// The y axis points up, such that a vector of `(0, 1)` moves half the display height upwards.
+    NormalizedDevice,
+}
+
+impl MotionVectorNames {
+
+    /// Specify the names of the two channels.
+    pub fn new(x: impl Into<Text>, y: impl Into<Text>) -> Self {
+        Self { x: x.into(), y: y.into() }
+    }
+
+    /// The channels `velocity.x` and `velocity.y`.
+    pub fn velocity() -> Self { Self::new("velocity.x", "velocity.y") }
+
+    /// The channels `forward.u` and `forward.v`, which contain the movement to the next frame.
+    pub fn forward() -> Self { Self::new("forward.u", "forward.v") }
+
+    /// The channels `backward.u` and `backward.v`, which contain the movement from the previous frame.
+    pub fn backward() -> Self { Self::new("backward.u", "backward.v") }
+
+    /// The names that are tried when reading, if no other names are specified, in this order.
+    pub fn defaults() -> Vec<Self> {
+        vec![ Self::velocity(), Self::forward(), Self::backward() ]
+    }
+
+    /// Whether the channel list contains both channels.
+    pub fn is_contained_in(&self, channels: &ChannelList) -> bool {
+        let contains = |name: &Text| channels.list.iter().any(|channel| &channel.name == name);
+        contains(&self.x) && contains(&self.y)
+    }
+}
+
+impl MotionSpace {
+
+    /// Convert a vector from this space to another space.
+    /// The display size is the size of the display window of the image.
+    pub fn convert(self, vector: Vec2<f32>, target: MotionSpace, display_size: Vec2<usize>) -> Vec2<f32> {
+        let (width, height) = (display_size.width() as f32, display_size.height() as f32);
+
+        match (self, target) {
+            (MotionSpace::Pixels, MotionSpace::NormalizedDevice) => Vec2(vector.x() * 2.0 / width, -vector.y() * 2.0 / height),
+            (MotionSpace::NormalizedDevice, MotionSpace::Pixels) => Vec2(vector.x() * width / 2.0, -vector.y() * height / 2.0),
+            _ => vector,
+        }
+    }
+}
+
+/// Wraps your pixel storage, containing motion vectors in the specified space,
+/// and converts every pixel to pixel space while it is written to a file.
+/// Created by `SpecificChannels::motion_vectors`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MotionToPixels<Storage> {
+
+    /// The motion vectors, as a tuple of the horizontal and vertical movement.
+    pub storage: Storage,
+
+    /// The unit of the motion vectors in the storage.
+    pub space: MotionSpace,
+
+    /// The size of the display window of the image.
+    pub display_size: Vec2<usize>,
+}
+
+impl<Storage> GetPixel for MotionToPixels<Storage> where Storage: GetPixel<Pixel = (f32, f32)> {
+    type Pixel = (f32, f32);
+
+    fn get_pixel(&self, position: Vec2<usize>) -> Self::Pixel {
+        let vector = Vec2::from(self.storage.get_pixel(position));
+        self.space.convert(vector, MotionSpace::Pixels, self.display_size).into()
+    }
+}
+
+impl<Storage> SpecificChannels<MotionToPixels<Storage>, (ChannelDescription, ChannelDescription)>
+    where Storage: GetPixel<Pixel = (f32, f32)>
+{
+
+    /// Create two `f32` channels with the specified names, containing the motion vectors of your storage.
+    /// If the vectors are in normalized device coordinates, they are converted to pixels,
+    /// using the size of the display window of the image.
+    pub fn motion_vectors(names: MotionVectorNames, space: MotionSpace, display_size: impl Into<Vec2<usize>>, storage: Storage) -> Self {
+        SpecificChannels {
+            channels: (
+                ChannelDescription::named(names.x, SampleType::F32),
+                ChannelDescription::named(names.y, SampleType::F32),
+            ),
+            pixels: MotionToPixels { storage, space, display_size: display_size.into() }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn convert_between_pixels_and_normalized_device_coordinates() {
+        let size = Vec2(200, 100);
+        let pixels = Vec2(50.0, 25.0);
+
+        let normalized = MotionSpace::Pixels.convert(pixels, MotionSpace::NormalizedDevice, size);
+        assert_eq!(normalized, Vec2(0.5, -0.5));
+        assert_eq!(MotionSpace::NormalizedDevice.convert(normalized, MotionSpace::Pixels, size), pixels);
+        assert_eq!(MotionSpace::Pixels.convert(pixels, MotionSpace::Pixels, size), pixels);
+
+        let channels = SpecificChannels::motion_vectors(
+            MotionVectorNames::forward(), MotionSpace::NormalizedDevice, size,
+            |_: Vec2<usize>| (1.0_f32, 1.0_f32)
+        );
+
+        assert_eq!(channels.channels.0.name, Text::from("forward.u"));
+        assert_eq!(channels.pixels.get_pixel(Vec2(0, 0)), (100.0, -50.0));
+    }
+}
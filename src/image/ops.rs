@@ -0,0 +1,469 @@
+//! Simple compositing operations between two rgba layers,
+//! and the difference between two layers, for example to compare renders in regression tests.
+//! Also computes an exposure for displaying a layer with high dynamic range.
+//!
+//! All operations expect associated (premultiplied) alpha, which is the default in OpenEXR files.
+//! The layers may have data windows of different sizes and positions.
+//! The result covers both data windows, and outside of its data window,
+//! a layer is considered to be transparent black.
+
+use crate::image::*;
+use crate::image::pixel_vec::PixelVec;
+use crate::math::Vec2;
+
+
+/// A pixel with red, green, blue and alpha, in that order.
+pub type RgbaPixel = (f32, f32, f32, f32);
+
+/// A layer with rgba pixels, as loaded with `read().rgba_channels(PixelVec::constructor, PixelVec::set_pixel)`.
+pub type RgbaLayer<Channels = RgbaChannels> = Layer<SpecificChannels<PixelVec<RgbaPixel>, Channels>>;
+
+/// Place the foreground layer on top of the background layer, using the alpha of the foreground.
+/// The resulting layer has the attributes, channels and encoding of the foreground layer.
+pub fn over<Channels: Clone>(foreground: &RgbaLayer<Channels>, background: &RgbaLayer<Channels>) -> RgbaLayer<Channels> {
+    merge(foreground, background, pixel::over)
+}
+
+/// Add the pixels of both layers, including alpha.
+/// The resulting layer has the attributes, channels and encoding of the first layer.
+pub fn add<Channels: Clone>(first: &RgbaLayer<Channels>, second: &RgbaLayer<Channels>) -> RgbaLayer<Channels> {
+    merge(first, second, pixel::add)
+}
+
+/// Multiply the pixels of both layers, including alpha.
+/// The resulting layer has the attributes, channels and encoding of the first layer.
+pub fn multiply<Channels: Clone>(first: &RgbaLayer<Channels>, second: &RgbaLayer<Channels>) -> RgbaLayer<Channels> {
+    merge(first, second, pixel::multiply)
+}
+
+/// Combine each pixel of the first layer with the pixel of the second layer at the same position.
+/// The resulting layer covers the data windows of both layers,
+/// and has the attributes, channels and encoding of the first layer.
+pub fn merge<Channels: Clone>(
+    first: &RgbaLayer<Channels>, second: &RgbaLayer<Channels>,
+    merge_pixels: impl Fn(RgbaPixel, RgbaPixel) -> RgbaPixel
+) -> RgbaLayer<Channels>
+{
+    let bounds = first.absolute_bounds().union(second.absolute_bounds());
+    let (start, size) = (bounds.position, bounds.size);
+
+    let pixels = (0 .. size.height())
+        .flat_map(|y| (0 .. size.width()).map(move |x| Vec2(x, y)))
+        .map(|position| {
+            let absolute = start + position.to_i32();
+            merge_pixels(pixel_at(first, absolute), pixel_at(second, absolute))
+        })
+        .collect();
+
+    Layer {
+        channel_data: SpecificChannels {
+            channels: first.channel_data.channels.clone(),
+            pixels: PixelVec::new(size, pixels),
+        },
+
+        attributes: LayerAttributes {
+            layer_position: start,
+            .. first.attributes.clone()
+        },
+
+        size,
+        encoding: first.encoding,
+    }
+}
+
+/// How the difference between two layers is computed. The default compares absolute differences without a threshold.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DifferenceOptions {
+
+    /// Divide the difference of each sample by the larger absolute sample value.
+    /// Relative differences are between zero and two, and are zero where both samples are zero.
+    pub relative: bool,
+
+    /// Differences up to this value are ignored, and are zero in the difference layer.
+    pub threshold: f32,
+
+    /// The value of a fully white pixel, used to compute the PSNR and SSIM.
+    /// Use `1.0` for most images.
+    pub peak_value: f32,
+}
+
+/// The result of comparing two layers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference<Channels> {
+
+    /// Contains the difference of each sample, as specified by the options.
+    /// Has the attributes, channels and encoding of the first layer.
+    pub layer: RgbaLayer<Channels>,
+
+    /// Summary of the difference.
+    pub statistics: DifferenceStatistics,
+}
+
+/// Summary of the difference between two layers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DifferenceStatistics {
+
+    /// The largest difference of any sample, after applying the threshold.
+    pub max_difference: f32,
+
+    /// The average difference of all samples, after applying the threshold.
+    pub mean_difference: f32,
+
+    /// How many pixels have at least one sample with a difference above the threshold.
+    pub differing_pixels: usize,
+
+    /// Peak signal-to-noise ratio in decibel, computed from all four samples of each pixel.
+    /// Is infinite if both layers are equal. Does not depend on the threshold.
+    pub psnr: f64,
+
+    /// Mean structural similarity of the luminance, computed in windows of 8x8 pixels.
+    /// Is one if both layers are equal. Does not depend on the threshold.
+    pub ssim: f64,
+}
+
+impl Default for DifferenceOptions {
+    fn default() -> Self {
+        Self { relative: false, threshold: 0.0, peak_value: 1.0 }
+    }
+}
+
+/// Compute the difference of each sample of the two layers, and some statistics.
+/// The difference layer covers the data windows of both layers,
+/// and has the attributes, channels and encoding of the first layer.
+pub fn difference<Channels: Clone>(first: &RgbaLayer<Channels>, second: &RgbaLayer<Channels>, options: DifferenceOptions) -> Difference<Channels> {
+    let sample_difference = |first: f32, second: f32| {
+        let mut difference = (first - second).abs();
+
+        if options.relative {
+            let magnitude = first.abs().max(second.abs());
+            difference = if magnitude == 0.0 { 0.0 } else { difference / magnitude };
+        }
+
+        if difference <= options.threshold { 0.0 } else { difference }
+    };
+
+    let layer = merge(first, second, |first, second| (
+        sample_difference(first.0, second.0), sample_difference(first.1, second.1),
+        sample_difference(first.2, second.2), sample_difference(first.3, second.3),
+    ));
+
+    let differences = &layer.channel_data.pixels.pixels;
+    let samples = differences.iter().flat_map(|&(r, g, b, a)| [r, g, b, a]);
+
+    let max_difference = samples.clone().fold(0.0, f32::max);
+    let mean_difference = (samples.map(f64::from).sum::<f64>() / (differences.len() * 4).max(1) as f64) as f32;
+    let differing_pixels = differences.iter().filter(|&&pixel| pixel != (0.0, 0.0, 0.0, 0.0)).count();
+
+    let bounds = layer.absolute_bounds();
+    let statistics = DifferenceStatistics {
+        max_difference, mean_difference, differing_pixels,
+        psnr: psnr(first, second, bounds, options.peak_value),
+        ssim: ssim(first, second, bounds, options.peak_value),
+    };
+
+    Difference { layer, statistics }
+}
+
+/// Peak signal-to-noise ratio of all samples inside the absolute bounds.
+fn psnr<Channels>(first: &RgbaLayer<Channels>, second: &RgbaLayer<Channels>, bounds: IntegerBounds, peak_value: f32) -> f64 {
+    let squared_error_sum: f64 = absolute_positions(bounds)
+        .map(|position| {
+            let (first, second) = (pixel_at(first, position), pixel_at(second, position));
+            let squared = |first: f32, second: f32| (f64::from(first) - f64::from(second)).powi(2);
+            squared(first.0, second.0) + squared(first.1, second.1) + squared(first.2, second.2) + squared(first.3, second.3)
+        })
+        .sum();
+
+    let mean_squared_error = squared_error_sum / (bounds.size.area() * 4).max(1) as f64;
+    if mean_squared_error == 0.0 { return f64::INFINITY; }
+
+    10.0 * (f64::from(peak_value).powi(2) / mean_squared_error).log10()
+}
+
+/// Mean structural similarity of the luminance, in non-overlapping windows inside the absolute bounds.
+fn ssim<Channels>(first: &RgbaLayer<Channels>, second: &RgbaLayer<Channels>, bounds: IntegerBounds, peak_value: f32) -> f64 {
+    const WINDOW_SIZE: usize = 8;
+
+    let luminance = |pixel: RgbaPixel| 0.2126 * f64::from(pixel.0) + 0.7152 * f64::from(pixel.1) + 0.0722 * f64::from(pixel.2);
+    let c1 = (0.01 * f64::from(peak_value)).powi(2);
+    let c2 = (0.03 * f64::from(peak_value)).powi(2);
+
+    let windows = bounds.size.map(|size| (size + WINDOW_SIZE - 1) / WINDOW_SIZE);
+    let mut ssim_sum = 0.0;
+
+    for window_y in 0 .. windows.height() {
+        for window_x in 0 .. windows.width() {
+            let start = Vec2(window_x, window_y) * WINDOW_SIZE;
+            let end = (start + Vec2(WINDOW_SIZE, WINDOW_SIZE)).min(bounds.size);
+            let window = IntegerBounds::new(bounds.position + start.to_i32(), end - start);
+
+            let pairs: Vec<(f64, f64)> = absolute_positions(window)
+                .map(|position| (luminance(pixel_at(first, position)), luminance(pixel_at(second, position))))
+                .collect();
+
+            let count = pairs.len() as f64;
+            let first_mean = pairs.iter().map(|pair| pair.0).sum::<f64>() / count;
+            let second_mean = pairs.iter().map(|pair| pair.1).sum::<f64>() / count;
+
+            let (mut first_variance, mut second_variance, mut covariance) = (0.0, 0.0, 0.0);
+            for &(first, second) in &pairs {
+                first_variance += (first - first_mean).powi(2) / count;
+                second_variance += (second - second_mean).powi(2) / count;
+                covariance += (first - first_mean) * (second - second_mean) / count;
+            }
+
+            ssim_sum += ((2.0 * first_mean * second_mean + c1) * (2.0 * covariance + c2))
+                / ((first_mean.powi(2) + second_mean.powi(2) + c1) * (first_variance + second_variance + c2));
+        }
+    }
+
+    ssim_sum / windows.area().max(1) as f64
+}
+
+/// All absolute pixel positions inside the bounds, row by row.
+fn absolute_positions(bounds: IntegerBounds) -> impl Iterator<Item = Vec2<i32>> {
+    let (start, size) = (bounds.position, bounds.size);
+    (0 .. size.height())
+        .flat_map(move |y| (0 .. size.width()).map(move |x| start + Vec2(x, y).to_i32()))
+}
+
+/// The pixel at the absolute position, or transparent black if outside of the layer.
+fn pixel_at<Channels>(layer: &RgbaLayer<Channels>, absolute_position: Vec2<i32>) -> RgbaPixel {
+    let position = absolute_position - layer.attributes.layer_position;
+
+    match Vec2::<usize>::try_from(position) {
+        Ok(position) if position.x() < layer.size.width() && position.y() < layer.size.height()
+            => *layer.channel_data.pixels.get_pixel(position),
+
+        _ => (0.0, 0.0, 0.0, 0.0),
+    }
+}
+
+/// The luminance that the median pixel is mapped to by `auto_exposure`.
+pub const MIDDLE_GREY: f32 = 0.18;
+
+/// Compute an exposure in stops for displaying the layer, such that the median luminance becomes `MIDDLE_GREY`.
+/// Multiply the color of each pixel by `2^exposure` before displaying it.
+/// Ignores black, negative and invalid pixels, as well as the alpha channel.
+/// Returns zero if the layer contains no bright pixels.
+pub fn auto_exposure<Channels>(layer: &RgbaLayer<Channels>) -> f32 {
+    let mut histogram = ExposureHistogram::new();
+    for &(r, g, b, _) in &layer.channel_data.pixels.pixels { histogram.add_rgb(r, g, b); }
+    histogram.exposure(0.5)
+}
+
+/// Collects the luminance of pixels in a logarithmic histogram, to compute an exposure in stops.
+/// To compute the exposure while decoding an image, add each pixel in the `set_pixel` closure of the reader.
+/// The histogram covers luminances from `2^-20` to `2^12`, with a resolution of an eighth of a stop.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExposureHistogram {
+    bins: Vec<usize>,
+    sample_count: usize,
+}
+
+impl ExposureHistogram {
+    const MIN_STOPS: f32 = -20.0;
+    const MAX_STOPS: f32 = 12.0;
+    const BINS_PER_STOP: f32 = 8.0;
+
+    /// Create a histogram without any pixels.
+    pub fn new() -> Self {
+        let bin_count = ((Self::MAX_STOPS - Self::MIN_STOPS) * Self::BINS_PER_STOP) as usize;
+        ExposureHistogram { bins: vec![0; bin_count], sample_count: 0 }
+    }
+
+    /// Add the luminance of a linear rgb pixel, using the Rec. 709 primaries.
+    #[inline]
+    pub fn add_rgb(&mut self, r: f32, g: f32, b: f32) {
+        self.add_luminance(0.2126 * r + 0.7152 * g + 0.0722 * b)
+    }
+
+    /// Add a luminance value. Ignores values that are not positive or not finite.
+    #[inline]
+    pub fn add_luminance(&mut self, luminance: f32) {
+        if !(luminance > 0.0) || !luminance.is_finite() { return; }
+
+        let bin = (luminance.log2() - Self::MIN_STOPS) * Self::BINS_PER_STOP;
+        let bin = (bin.max(0.0) as usize).min(self.bins.len() - 1);
+
+        self.bins[bin] += 1;
+        self.sample_count += 1;
+    }
+
+    /// Combine the pixels of another histogram with this histogram,
+    /// for example after decoding parts of the image in separate threads.
+    pub fn merge(&mut self, other: &Self) {
+        for (bin, other_bin) in self.bins.iter_mut().zip(&other.bins) { *bin += other_bin; }
+        self.sample_count += other.sample_count;
+    }
+
+    /// The number of pixels that have been added, excluding ignored pixels.
+    pub fn sample_count(&self) -> usize { self.sample_count }
+
+    /// The approximate luminance below which the specified fraction of the pixels lies.
+    /// The fraction is clamped to the range from zero to one.
+    /// Returns `None` if no pixels have been added.
+    pub fn percentile(&self, fraction: f32) -> Option<f32> {
+        if self.sample_count == 0 { return None; }
+
+        let rank = (fraction.max(0.0).min(1.0) * (self.sample_count - 1) as f32) as usize;
+        let mut count_below = 0;
+
+        let bin = self.bins.iter().position(|&count| {
+            count_below += count;
+            count_below > rank
+        })?;
+
+        let stops = Self::MIN_STOPS + (bin as f32 + 0.5) / Self::BINS_PER_STOP;
+        Some(stops.exp2())
+    }
+
+    /// The exposure in stops that maps the luminance at the specified percentile to `MIDDLE_GREY`.
+    /// Using a percentile instead of the average prevents a few very bright pixels from darkening the whole image.
+    /// Returns zero if no pixels have been added.
+    pub fn exposure(&self, fraction: f32) -> f32 {
+        self.percentile(fraction)
+            .map(|luminance| (MIDDLE_GREY / luminance).log2())
+            .unwrap_or(0.0)
+    }
+}
+
+impl Default for ExposureHistogram {
+    fn default() -> Self { Self::new() }
+}
+
+/// The compositing operations for single pixels.
+pub mod pixel {
+    use super::RgbaPixel;
+
+    /// Place the foreground pixel on top of the background pixel.
+    #[inline]
+    pub fn over(foreground: RgbaPixel, background: RgbaPixel) -> RgbaPixel {
+        let (fr, fg, fb, fa) = foreground;
+        let (br, bg, bb, ba) = background;
+        let transmission = 1.0 - fa;
+        (fr + br * transmission, fg + bg * transmission, fb + bb * transmission, fa + ba * transmission)
+    }
+
+    /// Add all components of the pixels.
+    #[inline]
+    pub fn add(first: RgbaPixel, second: RgbaPixel) -> RgbaPixel {
+        (first.0 + second.0, first.1 + second.1, first.2 + second.2, first.3 + second.3)
+    }
+
+    /// Multiply all components of the pixels.
+    #[inline]
+    pub fn multiply(first: RgbaPixel, second: RgbaPixel) -> RgbaPixel {
+        (first.0 * second.0, first.1 * second.1, first.2 * second.2, first.3 * second.3)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::prelude::*;
+    use crate::image::ops::{self, RgbaLayer};
+    use crate::image::pixel_vec::PixelVec;
+
+    fn layer(position: (i32, i32), size: (usize, usize), pixel: (f32, f32, f32, f32)) -> RgbaLayer<impl Clone> {
+        let pixels = vec![pixel; size.0 * size.1];
+
+        Layer::new(
+            size, LayerAttributes::default().with_position(Vec2::from(position)), Encoding::UNCOMPRESSED,
+            SpecificChannels::rgba(PixelVec::new(size, pixels))
+        )
+    }
+
+    #[test]
+    fn over_different_data_windows() {
+        let foreground = layer((1, 0), (2, 1), (0.5, 0.0, 0.0, 0.5));
+        let background = layer((0, 0), (2, 2), (0.0, 1.0, 0.0, 1.0));
+
+        let result = ops::over(&foreground, &background);
+        assert_eq!(result.size, Vec2(3, 2));
+        assert_eq!(result.attributes.layer_position, Vec2(0, 0));
+
+        let pixels = &result.channel_data.pixels;
+        assert_eq!(*pixels.get_pixel(Vec2(0, 0)), (0.0, 1.0, 0.0, 1.0), "only background");
+        assert_eq!(*pixels.get_pixel(Vec2(1, 0)), (0.5, 0.5, 0.0, 1.0), "both layers");
+        assert_eq!(*pixels.get_pixel(Vec2(2, 0)), (0.5, 0.0, 0.0, 0.5), "only foreground");
+        assert_eq!(*pixels.get_pixel(Vec2(2, 1)), (0.0, 0.0, 0.0, 0.0), "neither layer");
+    }
+
+    #[test]
+    fn add_and_multiply() {
+        let first = layer((-1, -1), (1, 1), (0.5, 0.5, 0.5, 0.5));
+        let second = layer((-1, -1), (1, 1), (0.25, 1.0, 2.0, 1.0));
+
+        assert_eq!(ops::add(&first, &second).channel_data.pixels.pixels, vec![(0.75, 1.5, 2.5, 1.5)]);
+        assert_eq!(ops::multiply(&first, &second).channel_data.pixels.pixels, vec![(0.125, 0.5, 1.0, 0.5)]);
+        assert_eq!(ops::multiply(&first, &second).attributes.layer_position, Vec2(-1, -1));
+    }
+
+    #[test]
+    fn difference_of_equal_layers() {
+        let first = layer((0, 0), (9, 7), (0.5, 0.25, 1.0, 1.0));
+        let difference = ops::difference(&first, &first.clone(), ops::DifferenceOptions::default());
+
+        assert_eq!(difference.layer.size, Vec2(9, 7));
+        assert_eq!(difference.statistics.max_difference, 0.0);
+        assert_eq!(difference.statistics.differing_pixels, 0);
+        assert_eq!(difference.statistics.psnr, f64::INFINITY);
+        assert!((difference.statistics.ssim - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn difference_with_threshold() {
+        let first = layer((0, 0), (2, 1), (0.5, 0.5, 0.5, 1.0));
+        let second = layer((1, 0), (2, 1), (0.5, 0.55, 1.0, 1.0));
+
+        let options = ops::DifferenceOptions { threshold: 0.1, .. Default::default() };
+        let difference = ops::difference(&first, &second, options);
+
+        let pixels = &difference.layer.channel_data.pixels;
+        assert_eq!(*pixels.get_pixel(Vec2(0, 0)), (0.5, 0.5, 0.5, 1.0), "only first layer");
+        assert_eq!(*pixels.get_pixel(Vec2(1, 0)), (0.0, 0.0, 0.5, 0.0), "both layers");
+        assert_eq!(difference.statistics.max_difference, 1.0);
+        assert_eq!(difference.statistics.differing_pixels, 3);
+
+        let relative = ops::difference(&first, &second, ops::DifferenceOptions { relative: true, .. options });
+        assert_eq!(*relative.layer.channel_data.pixels.get_pixel(Vec2(1, 0)), (0.0, 0.0, 0.5, 0.0));
+        assert_eq!(*relative.layer.channel_data.pixels.get_pixel(Vec2(2, 0)), (1.0, 1.0, 1.0, 1.0));
+
+        assert!(difference.statistics.psnr.is_finite());
+        assert!(difference.statistics.ssim < 1.0);
+    }
+
+    #[test]
+    fn auto_exposure_ignores_outliers() {
+        let mut pixels = vec![(0.045, 0.045, 0.045, 1.0); 90];
+        pixels.extend(vec![(5000.0, 5000.0, 5000.0, 1.0); 9]);
+        pixels.push((f32::NAN, 0.0, 0.0, 1.0));
+
+        let outliers = Layer::new(
+            (10, 10), LayerAttributes::default(), Encoding::UNCOMPRESSED,
+            SpecificChannels::rgba(PixelVec::new((10, 10), pixels))
+        );
+
+        let exposure = ops::auto_exposure(&outliers);
+        assert!((exposure - 2.0).abs() < 0.1, "the median pixel should be two stops below middle grey, but exposure was {}", exposure);
+
+        assert_eq!(ops::auto_exposure(&layer((0, 0), (4, 4), (0.0, 0.0, 0.0, 1.0))), 0.0, "black layer");
+    }
+
+    #[test]
+    fn merge_exposure_histograms() {
+        let mut first = ops::ExposureHistogram::new();
+        let mut second = ops::ExposureHistogram::default();
+        assert_eq!(first.percentile(0.5), None);
+
+        first.add_luminance(1.0);
+        second.add_luminance(4.0);
+        second.add_luminance(-1.0);
+        first.merge(&second);
+
+        assert_eq!(first.sample_count(), 2);
+        assert!((first.percentile(0.0).unwrap() - 1.0).abs() < 0.1);
+        assert!((first.percentile(1.0).unwrap() - 4.0).abs() < 0.3);
+    }
+}
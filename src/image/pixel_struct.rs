@@ -0,0 +1,71 @@
+//! Store the fields of a struct as separate channels, instead of using tuples.
+//! With tuples, the position of each sample must match the order in which the channels are declared,
+//! which is easy to mix up for pixels with many channels.
+//!
+//! Enable the `derive` feature to implement `ExrPixel` using `#[derive(ExrPixel)]`:
+//!
+//! ```ignore
+//! use exr::prelude::*;
+//! use exr::prelude::pixel_vec::PixelVec;
+//!
+//! #[derive(ExrPixel, Clone, Copy, Default, Debug)]
+//! struct Pixel {
+//!     r: f32, g: f32, b: f32, // the channels `R`, `G`, and `B`
+//!     a: Option<f16>, // an optional channel `A`
+//!
+//!     #[exr(channel = "Z")]
+//!     depth: f32,
+//! }
+//!
+//! let image = read().no_deep_data().largest_resolution_level()
+//!     .struct_channels::<Pixel>()
+//!     .collect_pixels(PixelVec::<Pixel>::constructor, PixelVec::set_pixel)
+//!     .first_valid_layer().all_attributes()
+//!     .from_file("aov.exr")?;
+//!
+//! let layer = Layer::new(
+//!     Vec2(64, 64), LayerAttributes::default(), Encoding::default(),
+//!     Pixel::specific_channels(|_position| Pixel::default())
+//! );
+//! ```
+
+use crate::image::SpecificChannels;
+use crate::image::recursive::IntoRecursive;
+use crate::image::write::channels::GetPixel;
+use crate::image::read::specific_channels::ReadSpecificChannel;
+
+
+/// A pixel struct where each field is stored in a separate channel.
+/// Usually implemented using `#[derive(ExrPixel)]`, which requires the `derive` feature.
+/// The struct must also implement `IntoRecursive`, which converts the fields to samples in the order of the channels.
+pub trait ExrPixel: IntoRecursive + Copy + Default + Sync + 'static {
+
+    /// Declares which channels will be read from the file.
+    /// Will be of type `ReadRequiredChannel<ReadOptionalChannel<..., f16>, f32>`.
+    type ReadChannels: ReadSpecificChannel;
+
+    /// The samples of all fields, in the order of the channels. Will be of type `(f32, Option<f16>, ...)`.
+    type Tuple;
+
+    /// Declares the channels when writing the pixels to a file.
+    /// Will be of type `Recursive<Recursive<..., ChannelDescription>, ChannelDescription>`.
+    type ChannelDescriptions: Clone + Sync + IntoRecursive;
+
+    /// Declare each field as a required or optional channel.
+    /// Use `ReadLargestLevel::struct_channels` instead of calling this directly.
+    fn read_channels() -> Self::ReadChannels;
+
+    /// Create the pixel from the samples that have been read from the file.
+    fn from_tuple(tuple: Self::Tuple) -> Self;
+
+    /// Describe the channel of each field, for writing the pixels to a file.
+    fn channel_descriptions() -> Self::ChannelDescriptions;
+
+    /// Create the channels of a layer from the specified pixel storage,
+    /// for example a closure `Fn(Vec2<usize>) -> Self`.
+    fn specific_channels<Pixels>(pixels: Pixels) -> SpecificChannels<Pixels, Self::ChannelDescriptions>
+        where Pixels: GetPixel<Pixel = Self>
+    {
+        SpecificChannels { channels: Self::channel_descriptions(), pixels }
+    }
+}
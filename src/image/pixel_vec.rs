@@ -1,11 +1,18 @@
 
-//! Provides a predefined pixel storage.
-//! Currently only contains a simple flattened vector storage.
+//! Provides predefined pixel storages.
+//! Contains a simple flattened vector storage, a vector storage that stores the pixels tile by tile,
+//! and a storage that can be filled by multiple threads at the same time.
 //! Use the functions `create_pixel_vec::<YourPixelTuple>` and
 //! `set_pixel_in_vec::<YourPixelTuple>` for reading a predefined pixel vector.
 //! Use the function `PixelVec::new` to create a pixel vector which can be written to a file.
 
 use super::*;
+use crate::meta::header::Header;
+use crate::meta::compute_block_count;
+use crate::meta::attribute::SampleType;
+use crate::block::UncompressedBlock;
+use crate::error::{Error, UnitResult};
+use std::sync::atomic::{AtomicU32, Ordering};
 
 /// Store all samples in a single array.
 /// All samples will be converted to the type `T`.
@@ -87,6 +94,247 @@ impl<Px> GetPixel for PixelVec<Px> where Px: Clone + Sync {
     }
 }
 
+
+/// Store all pixels in a single array, but tile by tile instead of row by row,
+/// matching the tile layout of a file. Each tile is a contiguous slice of pixels,
+/// which can be copied or uploaded to the GPU individually.
+///
+/// The tiles are stored in increasing y order, and within each row of tiles, in increasing x order.
+/// The pixels inside each tile are stored row by row.
+/// Tiles at the right and bottom border of the image are smaller if the resolution is not divisible by the tile size,
+/// just as in the file, and do not contain any padding.
+///
+/// Use `TiledPixelVec::constructor_with_header` together with `collect_pixels_with_header`
+/// to use the tile size of the file, or the scan line blocks of a file without tiles.
+#[derive(Eq, PartialEq, Clone)]
+pub struct TiledPixelVec<T> {
+
+    /// The resolution of this layer.
+    pub resolution: Vec2<usize>,
+
+    /// The size of a tile that is not at the border of the image.
+    pub tile_size: Vec2<usize>,
+
+    /// All pixels, tile by tile. Use `tile` to access the pixels of a single tile.
+    pub pixels: Vec<T>,
+}
+
+impl<Pixel> TiledPixelVec<Pixel> {
+
+    /// Create a new tiled pixel storage, filled with default pixels.
+    /// Panics if the tile size is zero.
+    pub fn with_tile_size(resolution: impl Into<Vec2<usize>>, tile_size: impl Into<Vec2<usize>>) -> Self where Pixel: Default + Clone {
+        let resolution = resolution.into();
+        Self::new(resolution, tile_size, vec![Pixel::default(); resolution.area()])
+    }
+
+    /// Create a new tiled pixel storage, filled with default pixels, using the block size of the layer.
+    /// Accepts a `Channels` parameter, which is not used, so that it can be passed to `collect_pixels_with_header`.
+    pub fn constructor_with_header<Channels>(header: &Header, _: &Channels) -> Self where Pixel: Default + Clone {
+        Self::with_tile_size(header.layer_size, header.max_block_pixel_size())
+    }
+
+    /// Create a new tiled pixel storage, checking the length of the provided pixels vector.
+    /// The pixels must already be ordered tile by tile.
+    /// Panics if the tile size is zero.
+    pub fn new(resolution: impl Into<Vec2<usize>>, tile_size: impl Into<Vec2<usize>>, pixels: Vec<Pixel>) -> Self {
+        let (resolution, tile_size) = (resolution.into(), tile_size.into());
+        assert!(tile_size.area() != 0, "tile size must not be zero");
+        assert_eq!(resolution.area(), pixels.len(), "expected {} samples, but vector length is {}", resolution.area(), pixels.len());
+        Self { resolution, tile_size, pixels }
+    }
+
+    /// The number of tiles in each dimension.
+    pub fn tile_count(&self) -> Vec2<usize> {
+        Vec2(
+            compute_block_count(self.resolution.width(), self.tile_size.width()),
+            compute_block_count(self.resolution.height(), self.tile_size.height()),
+        )
+    }
+
+    /// The pixel bounds of the specified tile, which is smaller than the tile size at the border of the image.
+    /// Panics for invalid tile indices.
+    pub fn tile_bounds(&self, tile_index: Vec2<usize>) -> IntegerBounds {
+        let tile_count = self.tile_count();
+        assert!(tile_index.x() < tile_count.x() && tile_index.y() < tile_count.y(), "tile index out of bounds");
+
+        let position = Vec2(tile_index.x() * self.tile_size.width(), tile_index.y() * self.tile_size.height());
+        let size = Vec2(
+            self.tile_size.width().min(self.resolution.width() - position.x()),
+            self.tile_size.height().min(self.resolution.height() - position.y()),
+        );
+
+        IntegerBounds::new(position.to_i32(), size)
+    }
+
+    /// The pixels of the specified tile, row by row.
+    /// Panics for invalid tile indices.
+    pub fn tile(&self, tile_index: Vec2<usize>) -> &[Pixel] {
+        let start = self.tile_start_index(tile_index);
+        &self.pixels[start .. start + self.tile_bounds(tile_index).size.area()]
+    }
+
+    /// The pixels of the specified tile, row by row.
+    /// Panics for invalid tile indices.
+    pub fn tile_mut(&mut self, tile_index: Vec2<usize>) -> &mut [Pixel] {
+        let start = self.tile_start_index(tile_index);
+        let end = start + self.tile_bounds(tile_index).size.area();
+        &mut self.pixels[start .. end]
+    }
+
+    /// Examine a pixel of a `TiledPixelVec<T>` image.
+    /// Can usually be used as a function reference instead of calling it directly.
+    #[inline]
+    pub fn get_pixel(&self, position: Vec2<usize>) -> &Pixel where Pixel: Sync {
+        &self.pixels[self.compute_pixel_index(position)]
+    }
+
+    /// Update a pixel of a `TiledPixelVec<T>` image.
+    /// Can usually be used as a function reference instead of calling it directly.
+    #[inline]
+    pub fn set_pixel(&mut self, position: Vec2<usize>, pixel: Pixel) {
+        let index = self.compute_pixel_index(position);
+        self.pixels[index] = pixel;
+    }
+
+    /// Compute the flat index of a specific pixel, which can be used with `TiledPixelVec.pixels[index]`.
+    /// Panics for invalid pixel coordinates.
+    #[inline]
+    pub fn compute_pixel_index(&self, position: Vec2<usize>) -> usize {
+        assert!(position.x() < self.resolution.width() && position.y() < self.resolution.height(), "pixel position out of bounds");
+
+        let tile_index = Vec2(position.x() / self.tile_size.width(), position.y() / self.tile_size.height());
+        let tile_width = self.tile_bounds(tile_index).size.width();
+        let in_tile = Vec2(position.x() % self.tile_size.width(), position.y() % self.tile_size.height());
+
+        self.tile_start_index(tile_index) + in_tile.y() * tile_width + in_tile.x()
+    }
+
+    /// The index of the first pixel of the specified tile.
+    fn tile_start_index(&self, tile_index: Vec2<usize>) -> usize {
+        // all rows of tiles above cover the full width of the image
+        let tile_row_start_y = tile_index.y() * self.tile_size.height();
+        let tile_row_height = self.tile_size.height().min(self.resolution.height() - tile_row_start_y);
+
+        tile_row_start_y * self.resolution.width()
+            + tile_index.x() * self.tile_size.width() * tile_row_height
+    }
+}
+
+impl<Px> GetPixel for TiledPixelVec<Px> where Px: Clone + Sync {
+    type Pixel = Px;
+    fn get_pixel(&self, position: Vec2<usize>) -> Self::Pixel {
+        self.get_pixel(position).clone()
+    }
+}
+
+
+/// Store the `f32` samples of all channels in a single array,
+/// which can be updated through a shared reference by multiple threads at the same time.
+/// Each sample is stored as the bits of an atomic integer, so no mutex is required.
+/// This allows each decompressor thread to insert its decompressed blocks directly,
+/// instead of sending all blocks to a single thread.
+/// Use `ChunksReader::decompress_parallel_shared` to fill this storage.
+///
+/// The samples are stored pixel by pixel, and within each pixel, channel by channel,
+/// in the same order as the channels in the header.
+pub struct SharedPixelVec {
+
+    /// The resolution of this layer.
+    pub resolution: Vec2<usize>,
+
+    /// The number of samples in each pixel.
+    pub channel_count: usize,
+
+    samples: Vec<AtomicU32>,
+}
+
+impl SharedPixelVec {
+
+    /// Create a new storage, with all samples being zero.
+    pub fn new(resolution: impl Into<Vec2<usize>>, channel_count: usize) -> Self {
+        let resolution = resolution.into();
+        let samples = (0 .. resolution.area() * channel_count).map(|_| AtomicU32::new(0)).collect();
+        Self { resolution, channel_count, samples }
+    }
+
+    /// Create a new storage, with all samples being zero, matching the size and channels of the layer.
+    pub fn for_header(header: &Header) -> Self {
+        Self::new(header.layer_size, header.channels.list.len())
+    }
+
+    /// Update a single sample. Can be called from multiple threads at the same time.
+    /// Panics for invalid positions or channel indices.
+    #[inline]
+    pub fn set_sample(&self, position: Vec2<usize>, channel: usize, value: f32) {
+        self.samples[self.compute_sample_index(position, channel)].store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Examine a single sample.
+    /// Panics for invalid positions or channel indices.
+    #[inline]
+    pub fn get_sample(&self, position: Vec2<usize>, channel: usize) -> f32 {
+        f32::from_bits(self.samples[self.compute_sample_index(position, channel)].load(Ordering::Relaxed))
+    }
+
+    /// Insert all samples of a decompressed block. Can be called from multiple threads at the same time.
+    /// Returns an error if the block does not belong to the largest resolution level,
+    /// if the channels of the header do not match this storage, or if any channel is subsampled.
+    pub fn insert_block(&self, header: &Header, block: &UncompressedBlock) -> UnitResult {
+        if block.index.level != Vec2(0, 0) {
+            return Err(Error::unsupported("shared pixel storage only contains the largest resolution level"));
+        }
+
+        if header.channels.list.len() != self.channel_count || header.layer_size != self.resolution {
+            return Err(Error::invalid("shared pixel storage does not match the header"));
+        }
+
+        if header.channels.list.iter().any(|channel| channel.sampling != Vec2(1, 1)) {
+            return Err(Error::unsupported("subsampled channels in shared pixel storage"));
+        }
+
+        for line in block.lines(&header.channels) {
+            let location = line.location;
+            if location.position.y() >= self.resolution.height() || location.position.x() + location.sample_count > self.resolution.width() {
+                return Err(Error::invalid("block position"));
+            }
+
+            let sample_type = header.channels.list[location.channel].sample_type;
+            let byte_size = match sample_type { SampleType::F16 => 2, SampleType::F32 | SampleType::U32 => 4 };
+
+            for x in 0 .. location.sample_count {
+                let position = Vec2(location.position.x() + x, location.position.y());
+                let bytes = line.value.get(x * byte_size .. (x + 1) * byte_size)
+                    .ok_or_else(|| Error::invalid("block size"))?;
+
+                let value = match sample_type {
+                    SampleType::F16 => f16::from_le_bytes([bytes[0], bytes[1]]).to_f32(),
+                    SampleType::F32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+                    SampleType::U32 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32,
+                };
+
+                self.set_sample(position, location.channel, value);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Extract all samples, pixel by pixel, and within each pixel, channel by channel.
+    pub fn into_samples(self) -> Vec<f32> {
+        self.samples.into_iter().map(|sample| f32::from_bits(sample.into_inner())).collect()
+    }
+
+    /// Compute the flat index of a specific sample.
+    /// Panics for invalid positions or channel indices.
+    #[inline]
+    pub fn compute_sample_index(&self, position: Vec2<usize>, channel: usize) -> usize {
+        assert!(channel < self.channel_count, "channel index out of bounds");
+        assert!(position.x() < self.resolution.width() && position.y() < self.resolution.height(), "pixel position out of bounds");
+        position.flat_index_for_size(self.resolution) * self.channel_count + channel
+    }
+}
+
 use std::fmt::*;
 
 impl<T> Debug for PixelVec<T> {
@@ -95,3 +343,15 @@ impl<T> Debug for PixelVec<T> {
     }
 }
 
+impl Debug for SharedPixelVec {
+    #[inline] fn fmt(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(formatter, "[f32; {}] shared", self.samples.len())
+    }
+}
+
+impl<T> Debug for TiledPixelVec<T> {
+    #[inline] fn fmt(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(formatter, "[{}; {}] in tiles of {}x{}", std::any::type_name::<T>(), self.pixels.len(), self.tile_size.width(), self.tile_size.height())
+    }
+}
+
@@ -2,7 +2,7 @@
 
 use crate::image::*;
 use crate::meta::header::{Header};
-use crate::error::{Result, UnitResult};
+use crate::error::{Result, UnitResult, Error};
 use crate::block::UncompressedBlock;
 use crate::block::lines::{LineRef};
 use crate::math::Vec2;
@@ -17,7 +17,48 @@ use crate::block::chunk::TileCoordinates;
 pub struct ReadAnyChannels<ReadSamples> {
 
     /// The sample reading specification
-    pub read_samples: ReadSamples
+    pub read_samples: ReadSamples,
+}
+
+impl<ReadSamples> ReadAnyChannels<ReadSamples> {
+
+    /// Only read the channels whose name matches this glob pattern, or any other pattern specified this way.
+    /// For example, `.channels_matching("diffuse.*").channels_matching("*.A")` reads all channels of the diffuse layer
+    /// and all alpha channels. In the glob pattern, `*` matches any number of characters, and `?` matches one character.
+    /// The other channels are not allocated, and their samples are not decompressed where possible.
+    /// Layers without any matching channel are skipped.
+    pub fn channels_matching(self, pattern: impl Into<Text>) -> ReadMatchingChannels<ReadSamples> {
+        ReadMatchingChannels { read_samples: self.read_samples, channel_patterns: SmallVec::new() }
+            .channels_matching(pattern)
+    }
+}
+
+/// A template that creates an [AnyChannelsReader] for each layer in the image,
+/// which loads only the channels that match one of the patterns.
+/// Layers without any matching channel are skipped. Created with [ReadAnyChannels::channels_matching].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ReadMatchingChannels<ReadSamples> {
+
+    /// The sample reading specification
+    pub read_samples: ReadSamples,
+
+    /// Only read the channels whose name matches one of these glob patterns. See `Text::matches_glob`.
+    pub channel_patterns: SmallVec<[Text; 4]>,
+}
+
+impl<ReadSamples> ReadMatchingChannels<ReadSamples> {
+
+    /// Additionally read the channels whose name matches this glob pattern.
+    /// See [ReadAnyChannels::channels_matching].
+    pub fn channels_matching(mut self, pattern: impl Into<Text>) -> Self {
+        self.channel_patterns.push(pattern.into());
+        self
+    }
+
+    /// Whether the channel with this name should be read.
+    pub fn reads_channel(&self, name: &Text) -> bool {
+        self.channel_patterns.iter().any(|pattern| name.matches_glob(pattern))
+    }
 }
 
 /// A template that creates a new [`SampleReader`] for each channel in each layer.
@@ -31,12 +72,13 @@ pub trait ReadSamples {
 }
 
 /// Processes pixel blocks from a file and accumulates them into a collection of arbitrary channels.
-/// Loads all channels for each layer.
+/// Loads all channels for each layer, or only the channels that match the patterns.
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct AnyChannelsReader<SamplesReader> {
 
-    /// Stores a separate sample reader per channel in the layer
-    sample_channels_reader: SmallVec<[AnyChannelReader<SamplesReader>; 4]>,
+    /// Stores a separate sample reader per channel in the layer.
+    /// Contains `None` for each channel that is not read.
+    sample_channels_reader: SmallVec<[Option<AnyChannelReader<SamplesReader>>; 4]>,
 }
 
 /// Processes pixel blocks from a file and accumulates them into a single arbitrary channel.
@@ -78,24 +120,52 @@ impl<'s, S: 's + ReadSamples> ReadChannels<'s> for ReadAnyChannels<S> {
     type Reader = AnyChannelsReader<S::Reader>;
 
     fn create_channels_reader(&self, header: &Header) -> Result<Self::Reader> {
-        let samples: Result<_> = header.channels.list.iter()
-            .map(|channel: &ChannelDescription| Ok(AnyChannelReader {
-                samples: self.read_samples.create_sample_reader(header, channel)?,
+        create_any_channels_reader(&self.read_samples, header, |_| true)
+    }
+}
+
+impl<'s, S: 's + ReadSamples> ReadChannels<'s> for ReadMatchingChannels<S> {
+    type Reader = AnyChannelsReader<S::Reader>;
+
+    fn create_channels_reader(&self, header: &Header) -> Result<Self::Reader> {
+        if !self.reads_layer(header) {
+            return Err(Error::invalid("layer does not contain any channel matching the specified patterns"));
+        }
+
+        create_any_channels_reader(&self.read_samples, header, |name| self.reads_channel(name))
+    }
+
+    fn reads_layer(&self, header: &Header) -> bool {
+        header.channels.list.iter().any(|channel| self.reads_channel(&channel.name))
+    }
+}
+
+/// Create a reader for each channel in the header that should be read, and `None` for all other channels.
+fn create_any_channels_reader<S: ReadSamples>(
+    read_samples: &S, header: &Header, reads_channel: impl Fn(&Text) -> bool
+) -> Result<AnyChannelsReader<S::Reader>>
+{
+    let samples: Result<_> = header.channels.list.iter()
+        .map(|channel: &ChannelDescription| {
+            if !reads_channel(&channel.name) { return Ok(None) }
+
+            Ok(Some(AnyChannelReader {
+                samples: read_samples.create_sample_reader(header, channel)?,
                 name: channel.name.clone(),
                 sampling_rate: channel.sampling,
                 quantize_linearly: channel.quantize_linearly
             }))
-            .collect();
+        })
+        .collect();
 
-        Ok(AnyChannelsReader { sample_channels_reader: samples? })
-    }
+    Ok(AnyChannelsReader { sample_channels_reader: samples? })
 }
 
 impl<S: SamplesReader> ChannelsReader for AnyChannelsReader<S> {
     type Channels = AnyChannels<S::Samples>;
 
     fn filter_block(&self, tile: TileCoordinates) -> bool {
-        self.sample_channels_reader.iter().any(|channel| channel.samples.filter_block(tile))
+        self.sample_channels_reader.iter().flatten().any(|channel| channel.samples.filter_block(tile))
     }
 
     fn read_block(&mut self, header: &Header, decompressed: UncompressedBlock) -> UnitResult {
@@ -106,15 +176,21 @@ impl<S: SamplesReader> ChannelsReader for AnyChannelsReader<S> {
 
         Ok(())*/
         for line in decompressed.lines(&header.channels) {
-            self.sample_channels_reader[line.location.channel].samples.read_line(line)?;
+            if let Some(channel) = &mut self.sample_channels_reader[line.location.channel] {
+                channel.samples.read_line(line)?;
+            }
         }
 
         Ok(())
     }
 
+    fn reads_channel(&self, channel: &ChannelDescription) -> bool {
+        self.sample_channels_reader.iter().flatten().any(|reader| reader.name == channel.name)
+    }
+
     fn into_channels(self) -> Self::Channels {
         AnyChannels { // not using `new()` as the channels are already sorted
-            list: self.sample_channels_reader.into_iter()
+            list: self.sample_channels_reader.into_iter().flatten()
                 .map(|channel| AnyChannel {
                     sample_data: channel.samples.into_samples(),
 
@@ -4,13 +4,22 @@
 use crate::image::*;
 use crate::meta::header::{Header, ImageAttributes};
 use crate::error::{Result, UnitResult};
-use crate::block::{UncompressedBlock, BlockIndex};
+use crate::block::{UncompressedBlock, BlockIndex, RequestedChannels};
+use std::collections::HashSet;
 use crate::block::chunk::TileCoordinates;
 use std::path::Path;
 use std::io::{Read, BufReader};
 use std::io::Seek;
-use crate::meta::MetaData;
-use crate::block::reader::ChunksReader;
+use crate::meta::{MetaData, BlockDescription, compute_level_size};
+use crate::math::RoundingMode;
+use crate::block::reader::{ChunksReader, FilteredChunksReader};
+use crate::block::source::{ChunkSource, SourceReader};
+use crate::io::ForwardSeek;
+use crate::block::cache::{ChunkCache, ChunkKey, FileId};
+use crate::compression::filter::SampleFilter;
+use std::sync::Arc;
+use std::borrow::Cow;
+use crate::image::alpha::{AlphaMode, convert_block_alpha, has_alpha_channels};
 
 /// Specify whether to read the image in parallel,
 /// whether to use pedantic error handling,
@@ -21,6 +30,15 @@ pub struct ReadImage<OnProgress, ReadLayers> {
     read_layers: ReadLayers,
     pedantic: bool,
     parallel: bool,
+    thread_count: Option<usize>,
+    buffer_size: usize,
+    prefetch_chunks: Option<usize>,
+    io_uring_queue_depth: Option<usize>,
+    unbuffered_queue_depth: Option<usize>,
+    max_attribute_size: Option<usize>,
+    chunk_cache: Option<(Arc<ChunkCache>, FileId)>,
+    sample_filters: Vec<Arc<dyn SampleFilter>>,
+    unassociate_alpha: bool,
 }
 
 impl<F, L> ReadImage<F, L> where F: FnMut(f64)
@@ -29,7 +47,15 @@ impl<F, L> ReadImage<F, L> where F: FnMut(f64)
     pub fn new(read_layers: L, on_progress: F) -> Self {
         Self {
             on_progress, read_layers,
-            pedantic: false, parallel: true,
+            pedantic: false, parallel: true, thread_count: None,
+            buffer_size: crate::io::DEFAULT_BUFFER_SIZE,
+            prefetch_chunks: None,
+            io_uring_queue_depth: None,
+            unbuffered_queue_depth: None,
+            max_attribute_size: None,
+            chunk_cache: None,
+            sample_filters: Vec::new(),
+            unassociate_alpha: false,
         }
     }
 
@@ -51,6 +77,66 @@ impl<F, L> ReadImage<F, L> where F: FnMut(f64)
     /// This might be slower but uses less memory and less synchronization.
     pub fn non_parallel(self) -> Self { Self { parallel: false, ..self } }
 
+    /// Decompress blocks on at most this many threads at once. A single thread decompresses all blocks on the current thread.
+    /// By default, this is the number of cpus, or the number in the environment variable `EXR_THREADS`,
+    /// see `block::default_thread_count`. Has no effect if `non_parallel` is specified.
+    pub fn threads(self, thread_count: usize) -> Self { Self { thread_count: Some(thread_count.max(1)), ..self } }
+
+    /// Specify the number of bytes that are buffered when reading from a file or an unbuffered reader.
+    /// The default is 8 KiB. A larger buffer can speed up reading large files, for example from network storage.
+    /// A size of zero disables buffering, which only makes sense if the reader is cheap to call for small reads.
+    /// This has no effect on `from_buffered`, which never adds another buffer,
+    /// and should be used instead for in-memory or already buffered readers.
+    pub fn buffer_size(self, bytes: usize) -> Self { Self { buffer_size: bytes, ..self } }
+
+    /// When reading from a file, read the upcoming chunks in a background thread while the previous chunks are decompressed.
+    /// At most `chunk_count` chunks are read ahead of time. This greatly improves throughput where each read has a high latency,
+    /// for example on network storage or spinning disks. Has no effect on `from_unbuffered` and `from_buffered`.
+    /// Use `ChunksReader::prefetch` to prefetch from other byte sources.
+    pub fn prefetch_chunks(self, chunk_count: usize) -> Self { Self { prefetch_chunks: Some(chunk_count), ..self } }
+
+    /// When reading from a file on linux, read the chunks using `io_uring`, with at most `queue_depth` reads in flight at once.
+    /// This keeps fast storage busy while the previous chunks are decompressed. Replaces `prefetch_chunks`.
+    /// Falls back to reading one chunk after another if the kernel does not allow `io_uring`.
+    /// Has no effect on `from_unbuffered` and `from_buffered`. See the `block::uring` module for more details.
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    pub fn io_uring(self, queue_depth: usize) -> Self { Self { io_uring_queue_depth: Some(queue_depth), ..self } }
+
+    /// When reading from a file, read the chunks with at most `queue_depth` reads in flight at once, each aligned to the sector size.
+    /// On windows, this also bypasses the file cache of the operating system,
+    /// which avoids evicting more useful data from the cache when playing back image sequences.
+    /// On other platforms, the file is still cached by the operating system.
+    /// Replaces `prefetch_chunks`. Has no effect on `from_unbuffered` and `from_buffered`.
+    /// See the `block::unbuffered` module for more details.
+    #[cfg(any(unix, windows))]
+    pub fn unbuffered_io(self, queue_depth: usize) -> Self { Self { unbuffered_queue_depth: Some(queue_depth), ..self } }
+
+    /// Return an error if any attribute in the file is larger than the specified number of bytes,
+    /// before any pixels are decompressed. Attributes such as preview images and id manifests may be large.
+    /// By default, attributes can have any size that the file format supports.
+    pub fn max_attribute_size(self, bytes: usize) -> Self { Self { max_attribute_size: Some(bytes), ..self } }
+
+    /// Take the blocks from the cache if they have been decompressed before, and keep all newly decompressed blocks in the cache.
+    /// The pixel bytes of each block are copied between the cache and the image, instead of being decompressed again.
+    /// The file id identifies the file in the cache, and must be different for each file.
+    /// Share the cache across reads of overlapping regions, for example when panning in an image viewer.
+    /// See the `block::cache` module for more details.
+    pub fn chunk_cache(self, cache: Arc<ChunkCache>, file: FileId) -> Self { Self { chunk_cache: Some((cache, file)), ..self } }
+
+    /// Revert this custom sample filter when reading, in addition to the built-in filters.
+    /// Reading a file that uses an unknown filter fails. See the `compression::filter` module.
+    pub fn sample_filter(mut self, filter: Arc<dyn SampleFilter>) -> Self {
+        self.sample_filters.push(filter);
+        self
+    }
+
+    /// Divide the color channels of each layer with associated alpha by alpha while reading,
+    /// and state `AlphaMode::Unassociated` in the attributes of these layers.
+    /// Layers that are already marked as unassociated are not changed.
+    /// The alpha channel is always decompressed, even if it is not read.
+    /// See `alpha::convert_block_alpha` for which channels are converted.
+    pub fn unassociate_alpha(self) -> Self { Self { unassociate_alpha: true, ..self } }
+
     /// Specify a function to be called regularly throughout the loading process.
     /// Replaces all previously specified progress functions in this reader.
     pub fn on_progress<OnProgress>(self, on_progress: OnProgress) -> ReadImage<OnProgress, L>
@@ -60,7 +146,16 @@ impl<F, L> ReadImage<F, L> where F: FnMut(f64)
             on_progress,
             read_layers: self.read_layers,
             pedantic: self.pedantic,
-            parallel: self.parallel
+            parallel: self.parallel,
+            thread_count: self.thread_count,
+            buffer_size: self.buffer_size,
+            prefetch_chunks: self.prefetch_chunks,
+            io_uring_queue_depth: self.io_uring_queue_depth,
+            unbuffered_queue_depth: self.unbuffered_queue_depth,
+            max_attribute_size: self.max_attribute_size,
+            chunk_cache: self.chunk_cache,
+            sample_filters: self.sample_filters,
+            unassociate_alpha: self.unassociate_alpha,
         }
     }
 
@@ -72,10 +167,31 @@ impl<F, L> ReadImage<F, L> where F: FnMut(f64)
     pub fn from_file<Layers>(self, path: impl AsRef<Path>) -> Result<Image<Layers>>
         where for<'s> L: ReadLayers<'s, Layers = Layers>
     {
-        self.from_unbuffered(std::fs::File::open(path)?)
+        let file = crate::io::open_file(path.as_ref())?;
+
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        if let Some(queue_depth) = self.io_uring_queue_depth {
+            let chunks = crate::block::read(BufReader::with_capacity(self.buffer_size, file.try_clone()?), self.pedantic)?;
+            return self.read_filtered_chunks(chunks, move |filtered_chunks| Ok(filtered_chunks.read_with_io_uring(file, queue_depth)));
+        }
+
+        #[cfg(any(unix, windows))]
+        if let Some(queue_depth) = self.unbuffered_queue_depth {
+            let unbuffered_file = crate::block::unbuffered::open_unbuffered(path.as_ref())?;
+            let chunks = crate::block::read(BufReader::with_capacity(self.buffer_size, file), self.pedantic)?;
+            return self.read_filtered_chunks(chunks, move |filtered_chunks| filtered_chunks.read_unbuffered(unbuffered_file, queue_depth));
+        }
+
+        match self.prefetch_chunks {
+            None => self.from_unbuffered(file),
+            Some(chunk_count) => {
+                let chunks = crate::block::read(BufReader::with_capacity(self.buffer_size, file), self.pedantic)?;
+                self.read_filtered_chunks(chunks, |filtered_chunks| filtered_chunks.prefetch(chunk_count))
+            }
+        }
     }
 
-    /// Buffer the reader and then read the exr image from it.
+    /// Buffer the reader and then read the exr image from it. See `buffer_size`.
     /// Use [`ReadImage::read_from_buffered`] instead, if your reader is an in-memory reader.
     /// Use [`ReadImage::read_from_file`] instead, if you have a file path.
     #[inline]
@@ -83,10 +199,11 @@ impl<F, L> ReadImage<F, L> where F: FnMut(f64)
     pub fn from_unbuffered<Layers>(self, unbuffered: impl Read + Seek) -> Result<Image<Layers>>
         where for<'s> L: ReadLayers<'s, Layers = Layers>
     {
-        self.from_buffered(BufReader::new(unbuffered))
+        let buffer_size = self.buffer_size;
+        self.from_buffered(BufReader::with_capacity(buffer_size, unbuffered))
     }
 
-    /// Read the exr image from a buffered reader.
+    /// Read the exr image from a buffered reader. Does not add another buffer.
     /// Use [`ReadImage::read_from_file`] instead, if you have a file path.
     /// Use [`ReadImage::read_from_unbuffered`] instead, if this is not an in-memory reader.
     // TODO Use Parallel<> Wrapper to only require sendable byte source where parallel decompression is required
@@ -98,39 +215,357 @@ impl<F, L> ReadImage<F, L> where F: FnMut(f64)
         self.from_chunks(chunks)
     }
 
+    /// Buffer the stream and then read the exr image from it, without seeking,
+    /// for example from standard input or from a network socket.
+    /// The chunks may be stored in any order. They are read in the order in which they are stored,
+    /// and each chunk that is not required for the image is skipped by reading and discarding its bytes.
+    /// Returns an error if the offset tables of the file contain overlapping chunks, as the stream cannot seek back.
+    #[must_use]
+    pub fn from_stream<Layers>(self, stream: impl Read) -> Result<Image<Layers>>
+        where for<'s> L: ReadLayers<'s, Layers = Layers>
+    {
+        let buffer_size = self.buffer_size;
+        self.from_buffered(ForwardSeek::new(BufReader::with_capacity(buffer_size, stream)))
+    }
+
+    /// Read the exr image from a source that can fetch any range of bytes at once, for example an object storage.
+    /// The meta data is fetched first, and then each required chunk is fetched with a separate call.
+    /// See the `block::source` module for more details.
+    #[must_use]
+    pub fn from_source<Layers>(self, source: impl ChunkSource) -> Result<Image<Layers>>
+        where for<'s> L: ReadLayers<'s, Layers = Layers>
+    {
+        let chunks = crate::block::read(SourceReader::new(source), self.pedantic)?;
+        self.read_filtered_chunks(chunks, |filtered_chunks| Ok(filtered_chunks.fetch_chunks()))
+    }
+
+    /// Read the exr image from a file, even if some blocks of pixels are missing or damaged.
+    /// This is useful for files that have not been written completely, for example because rendering was interrupted.
+    /// Blocks that cannot be read or decompressed are skipped, and listed in the returned `PartialImage`.
+    /// The meta data of the file must still be valid. Does not prefetch chunks.
+    #[must_use]
+    pub fn from_file_partial<Layers>(self, path: impl AsRef<Path>) -> Result<PartialImage<Layers>>
+        where for<'s> L: ReadLayers<'s, Layers = Layers>
+    {
+        let file = crate::io::open_file(path.as_ref())?;
+        self.from_unbuffered_partial(file)
+    }
+
+    /// Buffer the reader and then read the exr image from it, even if some blocks of pixels are missing or damaged.
+    /// See `from_file_partial` and `buffer_size`.
+    #[must_use]
+    pub fn from_unbuffered_partial<Layers>(self, unbuffered: impl Read + Seek) -> Result<PartialImage<Layers>>
+        where for<'s> L: ReadLayers<'s, Layers = Layers>
+    {
+        let buffer_size = self.buffer_size;
+        self.from_buffered_partial(BufReader::with_capacity(buffer_size, unbuffered))
+    }
+
+    /// Read the exr image from a buffered reader, even if some blocks of pixels are missing or damaged.
+    /// Does not add another buffer. See `from_file_partial`.
+    #[must_use]
+    pub fn from_buffered_partial<Layers>(self, buffered: impl Read + Seek) -> Result<PartialImage<Layers>>
+        where for<'s> L: ReadLayers<'s, Layers = Layers>
+    {
+        let chunks = crate::block::read(buffered, self.pedantic)?;
+        self.read_chunks_partially(chunks, Ok, true)
+    }
+
     /// Read the exr image from an initialized chunks reader
     /// that has already extracted the meta data from the file.
     /// Use [`ReadImage::read_from_file`] instead, if you have a file path.
     /// Use [`ReadImage::read_from_buffered`] instead, if this is an in-memory reader.
     // TODO Use Parallel<> Wrapper to only require sendable byte source where parallel decompression is required
     #[must_use]
-    pub fn from_chunks<Layers>(mut self, chunks_reader: crate::block::reader::Reader<impl Read + Seek>) -> Result<Image<Layers>>
+    pub fn from_chunks<Layers>(self, chunks_reader: crate::block::reader::Reader<impl Read + Seek>) -> Result<Image<Layers>>
         where for<'s> L: ReadLayers<'s, Layers = Layers>
     {
-        let Self { pedantic, parallel, ref mut on_progress, ref mut read_layers } = self;
+        self.read_filtered_chunks(chunks_reader, Ok)
+    }
+
+    /// Filter the chunks that are required for the image, optionally wrap them into another chunks reader, and decompress them.
+    fn read_filtered_chunks<Layers, R, Chunks>(
+        self, chunks_reader: crate::block::reader::Reader<R>,
+        wrap_chunks: impl FnOnce(FilteredChunksReader<R>) -> Result<Chunks>
+    ) -> Result<Image<Layers>>
+        where for<'s> L: ReadLayers<'s, Layers = Layers>, R: Read + Seek, Chunks: ChunksReader
+    {
+        let partial = self.read_chunks_partially(chunks_reader, wrap_chunks, false)?;
+        debug_assert!(partial.is_complete(), "blocks missing without error");
+        Ok(partial.image)
+    }
+
+    /// Filter the chunks that are required for the image, optionally wrap them into another chunks reader, and decompress them.
+    /// If `skip_invalid_blocks` is false, returns an error for the first block that cannot be read, decompressed, or inserted.
+    /// Otherwise, skips those blocks and reports them as missing.
+    fn read_chunks_partially<Layers, R, Chunks>(
+        mut self, chunks_reader: crate::block::reader::Reader<R>,
+        wrap_chunks: impl FnOnce(FilteredChunksReader<R>) -> Result<Chunks>,
+        skip_invalid_blocks: bool,
+    ) -> Result<PartialImage<Layers>>
+        where for<'s> L: ReadLayers<'s, Layers = Layers>, R: Read + Seek, Chunks: ChunksReader
+    {
+        let chunks_reader = chunks_reader.with_sample_filters(&self.sample_filters);
+        let Self { pedantic, parallel, thread_count, max_attribute_size, unassociate_alpha, ref chunk_cache, ref mut on_progress, ref mut read_layers, .. } = self;
+        let thread_count = thread_count.unwrap_or_else(crate::block::default_thread_count);
+        let parallel = parallel && thread_count > 1;
+        trace_span!(DEBUG, "read image", layers = chunks_reader.headers().len(), parallel, skip_invalid_blocks);
+
+        if let Some(max_attribute_size) = max_attribute_size {
+            for header in chunks_reader.headers() {
+                header.validate_attribute_sizes(max_attribute_size)?;
+            }
+        }
+
+        // the color samples of these layers are divided by alpha after decompressing each block
+        let unassociated_layers: Vec<bool> = chunks_reader.headers().iter().map(|header|
+            unassociate_alpha && header.own_attributes.alpha_mode() == AlphaMode::Associated && has_alpha_channels(&header.channels)
+        ).collect();
+
+        let mut layer_headers = Cow::Borrowed(chunks_reader.headers());
+        for (index, _) in unassociated_layers.iter().enumerate().filter(|(_, &unassociated)| unassociated) {
+            layer_headers.to_mut()[index].own_attributes.set_alpha_mode(AlphaMode::Unassociated);
+        }
+
+        let convert_alpha = |mut block: UncompressedBlock, headers: &[Header]| {
+            if unassociated_layers[block.index.layer] {
+                convert_block_alpha(&headers[block.index.layer].channels, &mut block, AlphaMode::Unassociated);
+            }
+
+            block
+        };
+
+        let layers_reader = read_layers.create_layers_reader(&layer_headers)?;
+        let layer_sizes = chunks_reader.headers().iter().map(|header| (header.layer_size, match header.blocks {
+            BlockDescription::Tiles(tiles) => tiles.rounding_mode,
+            BlockDescription::ScanLines => RoundingMode::Down,
+        })).collect();
 
-        let layers_reader = read_layers.create_layers_reader(chunks_reader.headers())?;
         let mut image_collector = ImageWithAttributesReader::new(chunks_reader.headers(), layers_reader)?;
 
-        let block_reader = chunks_reader
+        // also includes chunks that are not contained in the file at all
+        let mut missing_blocks = HashSet::new();
+        if skip_invalid_blocks {
+            for (layer, header) in chunks_reader.headers().iter().enumerate() {
+                for tile in header.blocks_increasing_y_order() {
+                    let data_indices = header.get_absolute_block_pixel_coordinates(tile.location)?;
+
+                    let block = BlockIndex {
+                        layer, level: tile.location.level_index,
+                        pixel_position: data_indices.position.to_usize("data indices start")?,
+                        pixel_size: data_indices.size,
+                    };
+
+                    if image_collector.filter_block(chunks_reader.meta_data(), tile.location, block) {
+                        missing_blocks.insert(block);
+                    }
+                }
+            }
+        }
+
+        // the blocks that are required, but have already been decompressed before
+        let mut cached_blocks = Vec::new();
+
+        let mut block_reader = chunks_reader
             .filter_chunks(pedantic, |meta, tile, block| {
-                image_collector.filter_block(meta, tile, block)
-            })?
-            .on_progress(on_progress);
+                if !image_collector.filter_block(meta, tile, block) { return false; }
+
+                if let Some((cache, file)) = chunk_cache {
+                    let key = ChunkKey { file: *file, layer: block.layer, chunk_index: match meta.headers[block.layer].chunk_index_increasing_y(tile) {
+                        Ok(chunk_index) => chunk_index,
+                        Err(_) => return true, // let the reader report the invalid tile
+                    }};
+
+                    if let Some(cached) = cache.get(key) {
+                        cached_blocks.push(cached);
+                        return false;
+                    }
+                }
+
+                true
+            })?;
+
+        // avoid reconstructing the samples of channels that are not read,
+        // unless the blocks are cached, as they may be used for other channels later,
+        // or unless alpha is required to convert the color channels
+        if chunk_cache.is_none() && !unassociated_layers.contains(&true) {
+            if let Some(requested_channels) = image_collector.requested_channels(block_reader.headers()) {
+                block_reader = block_reader.with_requested_channels(requested_channels);
+            }
+        }
+
+        for cached in cached_blocks {
+            missing_blocks.remove(&cached.index);
+
+            // the image reader takes ownership of the block, so the block is copied,
+            // unless the cache has evicted the block in the meantime and this is the last reference
+            let block = Arc::try_unwrap(cached).unwrap_or_else(|shared| (*shared).clone());
+            image_collector.read_block(block_reader.headers(), convert_alpha(block, block_reader.headers()))?;
+        }
+
+        // keep a copy of each decompressed block in the cache
+        let headers = block_reader.headers().to_vec();
+        let cache_block = |block: UncompressedBlock| -> Result<UncompressedBlock> {
+            if let Some((cache, file)) = chunk_cache {
+                cache.insert(ChunkKey::for_block(*file, &headers, block.index)?, Arc::new(block.clone()));
+            }
+
+            Ok(block)
+        };
+
+        let block_reader = wrap_chunks(block_reader)?.on_progress(on_progress);
+
+        if skip_invalid_blocks {
+            let mut insert_valid = |blocks: &mut dyn Iterator<Item=Result<UncompressedBlock>>| {
+                let mut blocks = blocks.map(|block| block.and_then(cache_block).map(|block| convert_alpha(block, &headers)));
+                image_collector.read_valid_blocks(&headers, &mut blocks, &mut missing_blocks)
+            };
+
+            if parallel {
+                match block_reader.parallel_decompressor_with_threads(pedantic, thread_count) {
+                    Ok(mut decompressor) => insert_valid(&mut decompressor),
+                    Err(block_reader) => insert_valid(&mut block_reader.sequential_decompressor(pedantic)),
+                }
+            }
+            else {
+                insert_valid(&mut block_reader.sequential_decompressor(pedantic))
+            }
+        }
 
         // TODO propagate send requirement further upwards
-        if parallel {
-            block_reader.decompress_parallel(pedantic, |meta_data, block|{
-                image_collector.read_block(&meta_data.headers, block)
+        else if parallel {
+            block_reader.decompress_parallel_with_threads(pedantic, thread_count, |meta_data, block|{
+                image_collector.read_block(&meta_data.headers, convert_alpha(cache_block(block)?, &meta_data.headers))
             })?;
         }
         else {
             block_reader.decompress_sequential(pedantic, |meta_data, block|{
-                image_collector.read_block(&meta_data.headers, block)
+                image_collector.read_block(&meta_data.headers, convert_alpha(cache_block(block)?, &meta_data.headers))
             })?;
         }
 
-        Ok(image_collector.into_image())
+        Ok(PartialImage::new(image_collector.into_image(), missing_blocks, layer_sizes))
+    }
+}
+
+/// An image that was read from a file that might be incomplete, for example because the file is truncated.
+/// Contains all pixels that could be decoded, and the blocks of pixels that are missing.
+/// Missing pixels keep the value they had when the pixel storage was created.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartialImage<Layers> {
+
+    /// The decoded image, containing all pixels that could be read.
+    pub image: Image<Layers>,
+
+    /// The blocks of pixels that could not be read,
+    /// sorted by layer, resolution level, and position.
+    pub missing_blocks: Vec<BlockIndex>,
+
+    /// For each layer in the file, the size and the rounding mode of the resolution levels.
+    layer_sizes: Vec<(Vec2<usize>, RoundingMode)>,
+}
+
+impl<Layers> PartialImage<Layers> {
+    fn new(image: Image<Layers>, missing_blocks: HashSet<BlockIndex>, layer_sizes: Vec<(Vec2<usize>, RoundingMode)>) -> Self {
+        let mut missing_blocks: Vec<BlockIndex> = missing_blocks.into_iter().collect();
+
+        missing_blocks.sort_unstable_by_key(|block| (
+            block.layer, block.level.y(), block.level.x(),
+            block.pixel_position.y(), block.pixel_position.x()
+        ));
+
+        Self { image, missing_blocks, layer_sizes }
+    }
+
+    /// Whether all pixels could be read.
+    pub fn is_complete(&self) -> bool { self.missing_blocks.is_empty() }
+
+    /// Whether the pixel at the position, in the specified layer and resolution level, could be read.
+    /// Use `Vec2(0, 0)` as the level for the largest resolution.
+    /// The position is relative to the data window of the layer.
+    pub fn is_pixel_valid(&self, layer_index: usize, level: Vec2<usize>, position: Vec2<usize>) -> bool {
+        !self.missing_blocks.iter().any(|block|
+            block.layer == layer_index && block.level == level
+                && position.x() >= block.pixel_position.x() && position.x() < block.pixel_position.x() + block.pixel_size.width()
+                && position.y() >= block.pixel_position.y() && position.y() < block.pixel_position.y() + block.pixel_size.height()
+        )
+    }
+
+    /// Compute which pixels of a layer could be read, for example to display missing regions.
+    /// The layer index refers to the layers in the file. Use `Vec2(0, 0)` as the level for the largest resolution.
+    /// Returns `None` if the file does not contain the layer.
+    pub fn validity_mask(&self, layer_index: usize, level: Vec2<usize>) -> Option<ValidityMask> {
+        let &(layer_size, rounding_mode) = self.layer_sizes.get(layer_index)?;
+
+        let level_size = Vec2(
+            compute_level_size(rounding_mode, layer_size.width(), level.x()),
+            compute_level_size(rounding_mode, layer_size.height(), level.y()),
+        );
+
+        let mut mask = ValidityMask::all_valid(level_size);
+
+        for block in &self.missing_blocks {
+            if block.layer == layer_index && block.level == level {
+                mask.mark_invalid(block.pixel_position, block.pixel_size);
+            }
+        }
+
+        Some(mask)
+    }
+
+    /// Return the image if all pixels could be read, or an error otherwise.
+    pub fn complete(self) -> Result<Image<Layers>> {
+        if self.is_complete() { Ok(self.image) }
+        else { Err(crate::error::Error::invalid(format!("{} blocks of pixels are missing", self.missing_blocks.len()))) }
+    }
+}
+
+/// Specifies for each pixel of a layer whether it was read from the file,
+/// or whether it still contains the value it had when the pixel storage was created.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidityMask {
+
+    /// The resolution of the layer or level.
+    pub resolution: Vec2<usize>,
+
+    /// For each pixel, whether it was read from the file. Stored row by row.
+    pub valid: Vec<bool>,
+}
+
+impl ValidityMask {
+
+    /// A mask where all pixels are valid.
+    pub fn all_valid(resolution: Vec2<usize>) -> Self {
+        Self { resolution, valid: vec![true; resolution.area()] }
+    }
+
+    /// Mark all pixels in the rectangle as invalid. Pixels outside of the resolution are ignored.
+    pub fn mark_invalid(&mut self, position: Vec2<usize>, size: Vec2<usize>) {
+        let end_x = (position.x() + size.width()).min(self.resolution.width());
+        let end_y = (position.y() + size.height()).min(self.resolution.height());
+
+        for y in position.y() .. end_y {
+            let row = y * self.resolution.width();
+
+            for valid in &mut self.valid[row + position.x().min(end_x) .. row + end_x] {
+                *valid = false;
+            }
+        }
+    }
+
+    /// Whether the pixel was read from the file. Panics for positions outside of the resolution.
+    pub fn is_valid(&self, position: Vec2<usize>) -> bool {
+        self.valid[position.flat_index_for_size(self.resolution)]
+    }
+
+    /// The number of pixels that were read from the file.
+    pub fn valid_pixel_count(&self) -> usize {
+        self.valid.iter().filter(|&&valid| valid).count()
+    }
+
+    /// Whether all pixels were read from the file.
+    pub fn is_complete(&self) -> bool {
+        self.valid.iter().all(|&valid| valid)
     }
 }
 
@@ -157,11 +592,32 @@ impl<L> ImageWithAttributesReader<L> where L: LayersReader {
         self.layers_reader.filter_block(meta, tile, block)
     }
 
+    /// For each layer, which channels need to be decompressed, if not all channels
+    fn requested_channels(&self, headers: &[Header]) -> Option<RequestedChannels> {
+        self.layers_reader.requested_channels(headers)
+    }
+
     /// Load a single pixel block, which has not been filtered, into the reader, accumulating the image
     fn read_block(&mut self, headers: &[Header], block: UncompressedBlock) -> UnitResult {
         self.layers_reader.read_block(headers, block)
     }
 
+    /// Load all blocks that can be read, decompressed, and inserted, skipping all other blocks.
+    /// Removes each inserted block from the missing blocks.
+    fn read_valid_blocks(
+        &mut self, headers: &[Header],
+        blocks: &mut dyn Iterator<Item=Result<UncompressedBlock>>,
+        missing_blocks: &mut HashSet<BlockIndex>
+    ) {
+        for block in blocks.flatten() {
+            let index = block.index;
+
+            if self.read_block(headers, block).is_ok() {
+                missing_blocks.remove(&index);
+            }
+        }
+    }
+
     /// Deliver the complete accumulated image
     fn into_image(self) -> Image<L::Layers> {
         Image {
@@ -203,6 +659,9 @@ pub trait LayersReader {
     /// Load a single pixel block, which has not been filtered, into the reader, accumulating the layer
     fn read_block(&mut self, headers: &[Header], block: UncompressedBlock) -> UnitResult;
 
+    /// For each layer, which channels need to be decompressed. Returns `None` if all channels are read.
+    fn requested_channels(&self, _headers: &[Header]) -> Option<RequestedChannels> { None }
+
     /// Deliver the final accumulated layers for the image
     fn into_layers(self) -> Self::Layers;
 }
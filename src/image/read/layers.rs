@@ -3,7 +3,7 @@
 use crate::image::*;
 use crate::meta::header::{Header, LayerAttributes};
 use crate::error::{Result, UnitResult, Error};
-use crate::block::{UncompressedBlock, BlockIndex};
+use crate::block::{UncompressedBlock, BlockIndex, RequestedChannels};
 use crate::math::Vec2;
 use crate::image::read::image::{ReadLayers, LayersReader};
 use crate::block::chunk::TileCoordinates;
@@ -27,6 +27,18 @@ pub struct ReadFirstValidLayer<ReadChannels> {
     pub read_channels: ReadChannels,
 }
 
+/// Specify to read two selections of channels from the same layer in a single pass.
+/// Created with [`ReadChannels::and_channels`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ReadChannelsPair<First, Second> {
+
+    /// The first channel reading specification
+    pub first: First,
+
+    /// The second channel reading specification
+    pub second: Second,
+}
+
 /// A template that creates a [`ChannelsReader`] once for all channels per layer.
 pub trait ReadChannels<'s> {
 
@@ -36,6 +48,11 @@ pub trait ReadChannels<'s> {
     /// Create a single reader for all channels of a specific layer
     fn create_channels_reader(&'s self, header: &Header) -> Result<Self::Reader>;
 
+    /// Whether the layer with this header contains any of the requested channels.
+    /// Layers that are not read are skipped by `all_layers` and `first_valid_layer`.
+    /// Returns true by default, such that every layer is read.
+    fn reads_layer(&self, _header: &Header) -> bool { true }
+
 
     /// Read only the first layer which meets the previously specified requirements
     /// For example, skips layers with deep data, if specified earlier.
@@ -48,8 +65,17 @@ pub trait ReadChannels<'s> {
 
     /// Reads all layers, including an empty list. Aborts if any of the layers are invalid,
     /// even if only one of the layers contains unexpected data.
+    /// Skips the layers that contain none of the requested channels, see `ReadChannels::reads_layer`.
     fn all_layers(self) -> ReadAllLayers<Self> where Self:Sized { ReadAllLayers { read_channels: self } }
 
+    /// Additionally read another selection of channels from the same layer, into a separate storage.
+    /// For example, read the `RGBA` channels into one image and the `Z` channel into another.
+    /// Each block of pixels is decompressed only once and then passed to both readers.
+    /// The layer will contain a tuple of both channel collections.
+    fn and_channels<Other>(self, other: Other) -> ReadChannelsPair<Self, Other> where Self:Sized {
+        ReadChannelsPair { first: self, second: other }
+    }
+
     // TODO pub fn all_valid_layers(self) -> ReadAllValidLayers<Self> { ReadAllValidLayers { read_channels: self } }
 }
 
@@ -59,7 +85,7 @@ pub trait ReadChannels<'s> {
 /// [`SpecificChannelsReader`] or [`AnyChannelsReader<FlatSamplesReader>`].
 #[derive(Debug, Clone, PartialEq)]
 pub struct AllLayersReader<ChannelsReader> {
-    layer_readers: SmallVec<[LayerReader<ChannelsReader>; 2]>, // TODO unpack struct?
+    layer_readers: SmallVec<[Option<LayerReader<ChannelsReader>>; 2]>, // TODO unpack struct?
 }
 
 /// Processes pixel blocks from a file and accumulates them into a single layers, using only the first.
@@ -94,11 +120,79 @@ pub trait ChannelsReader {
     /// Load a single pixel block, which has not been filtered, into the reader, accumulating the channel data
     fn read_block(&mut self, header: &Header, block: UncompressedBlock) -> UnitResult;
 
+    /// Whether this reader uses the samples of the channel.
+    /// The samples of unused channels may contain arbitrary values in the blocks passed to `read_block`.
+    fn reads_channel(&self, _channel: &ChannelDescription) -> bool { true }
+
     /// Deliver the final accumulated channel collection for the image
     fn into_channels(self) -> Self::Channels;
 }
 
 
+/// Processes pixel blocks from a file and passes each block to one or both of the contained readers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelsReaderPair<First, Second> {
+    first: First,
+    second: Second,
+}
+
+impl<'s, First, Second> ReadChannels<'s> for ReadChannelsPair<First, Second>
+    where First: ReadChannels<'s>, Second: ReadChannels<'s>
+{
+    type Reader = ChannelsReaderPair<First::Reader, Second::Reader>;
+
+    fn create_channels_reader(&'s self, header: &Header) -> Result<Self::Reader> {
+        Ok(ChannelsReaderPair {
+            first: self.first.create_channels_reader(header)?,
+            second: self.second.create_channels_reader(header)?,
+        })
+    }
+
+    fn reads_layer(&self, header: &Header) -> bool {
+        self.first.reads_layer(header) && self.second.reads_layer(header)
+    }
+}
+
+impl<First: ChannelsReader, Second: ChannelsReader> ChannelsReader for ChannelsReaderPair<First, Second> {
+    type Channels = (First::Channels, Second::Channels);
+
+    fn filter_block(&self, tile: TileCoordinates) -> bool {
+        self.first.filter_block(tile) || self.second.filter_block(tile)
+    }
+
+    fn read_block(&mut self, header: &Header, block: UncompressedBlock) -> UnitResult {
+        let tile = header.get_tile_coordinates_containing(block.index.level, block.index.pixel_position)?;
+
+        match (self.first.filter_block(tile), self.second.filter_block(tile)) {
+            (true, true) => {
+                self.first.read_block(header, block.clone())?;
+                self.second.read_block(header, block)
+            },
+
+            (true, false) => self.first.read_block(header, block),
+            (false, true) => self.second.read_block(header, block),
+            (false, false) => Ok(()),
+        }
+    }
+
+    fn reads_channel(&self, channel: &ChannelDescription) -> bool {
+        self.first.reads_channel(channel) || self.second.reads_channel(channel)
+    }
+
+    fn into_channels(self) -> Self::Channels {
+        (self.first.into_channels(), self.second.into_channels())
+    }
+}
+
+
+impl<C: ChannelsReader> LayerReader<C> {
+
+    /// For each channel of the layer, whether its samples are read.
+    fn requested_channels(&self, header: &Header) -> SmallVec<[bool; 8]> {
+        header.channels.list.iter().map(|channel| self.channels_reader.reads_channel(channel)).collect()
+    }
+}
+
 impl<C> LayerReader<C> {
     fn new(header: &Header, channels_reader: C) -> Result<Self> {
         Ok(LayerReader {
@@ -123,7 +217,10 @@ impl<'s, C> ReadLayers<'s> for ReadAllLayers<C> where C: ReadChannels<'s> {
 
     fn create_layers_reader(&'s self, headers: &[Header]) -> Result<Self::Reader> {
         let readers: Result<_> = headers.iter()
-            .map(|header| LayerReader::new(header, self.read_channels.create_channels_reader(header)?))
+            .map(|header| {
+                if !self.read_channels.reads_layer(header) { return Ok(None) }
+                Ok(Some(LayerReader::new(header, self.read_channels.create_channels_reader(header)?)?))
+            })
             .collect();
 
         Ok(AllLayersReader {
@@ -137,18 +234,29 @@ impl<C> LayersReader for AllLayersReader<C> where C: ChannelsReader {
 
     fn filter_block(&self, _: &MetaData, tile: TileCoordinates, block: BlockIndex) -> bool {
         let layer = self.layer_readers.get(block.layer).expect("invalid layer index argument");
-        layer.channels_reader.filter_block(tile)
+        layer.as_ref().map_or(false, |layer| layer.channels_reader.filter_block(tile))
     }
 
     fn read_block(&mut self, headers: &[Header], block: UncompressedBlock) -> UnitResult {
         self.layer_readers
             .get_mut(block.index.layer).expect("invalid layer index argument")
+            .as_mut().expect("block of skipped layer should have been filtered out")
             .channels_reader.read_block(headers.get(block.index.layer).expect("invalid header index in block"), block)
     }
 
+    fn requested_channels(&self, headers: &[Header]) -> Option<RequestedChannels> {
+        // the blocks of skipped layers are not decompressed at all
+        Some(self.layer_readers.iter().zip(headers)
+            .map(|(layer, header)| match layer {
+                Some(layer) => layer.requested_channels(header),
+                None => smallvec![ false; header.channels.list.len() ],
+            })
+            .collect())
+    }
+
     fn into_layers(self) -> Self::Layers {
         self.layer_readers
-            .into_iter()
+            .into_iter().flatten()
             .map(|layer| Layer {
                 channel_data: layer.channels_reader.into_channels(),
                 attributes: layer.attributes,
@@ -166,6 +274,7 @@ impl<'s, C> ReadLayers<'s> for ReadFirstValidLayer<C> where C: ReadChannels<'s>
 
     fn create_layers_reader(&'s self, headers: &[Header]) -> Result<Self::Reader> {
         headers.iter().enumerate()
+            .filter(|(_, header)| self.read_channels.reads_layer(header))
             .flat_map(|(index, header)|
                 self.read_channels.create_channels_reader(header)
                     .and_then(|reader| Ok(FirstValidLayerReader {
@@ -192,6 +301,16 @@ impl<C> LayersReader for FirstValidLayerReader<C> where C: ChannelsReader {
         self.layer_reader.channels_reader.read_block(&headers[self.layer_index], block)
     }
 
+    fn requested_channels(&self, headers: &[Header]) -> Option<RequestedChannels> {
+        // the blocks of all other layers are not decompressed at all
+        Some(headers.iter().enumerate()
+            .map(|(index, header)|
+                if index == self.layer_index { self.layer_reader.requested_channels(header) }
+                else { smallvec![ false; header.channels.list.len() ] }
+            )
+            .collect())
+    }
+
     fn into_layers(self) -> Self::Layers {
         Layer {
             channel_data: self.layer_reader.channels_reader.into_channels(),
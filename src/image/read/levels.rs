@@ -12,6 +12,7 @@ use crate::math::Vec2;
 use crate::block::lines::LineRef;
 use crate::block::samples::*;
 use crate::meta::header::{Header};
+use crate::image::pixel_struct::ExrPixel;
 
 
 // Note: In the resulting image, the `FlatSamples` are placed
@@ -95,6 +96,59 @@ impl<DeepOrFlatSamples> ReadLargestLevel<DeepOrFlatSamples> {
     pub fn specific_channels(self) -> ReadZeroChannels {
         ReadZeroChannels { }
     }
+
+    /// Read only layers that contain the channels declared by the fields of the pixel struct,
+    /// skipping any other channels in the layer. Fields with an `Option` type are optional channels.
+    /// Call `collect_pixels` afterwards to define the pixel container for your pixel struct.
+    ///
+    /// Throws an error for images with deep data or subsampling.
+    pub fn struct_channels<Pixel: ExrPixel>(self) -> ReadStructChannels<Pixel::ReadChannels, Pixel> {
+        ReadStructChannels::default()
+    }
+
+    /// Read only layers that contain all of the specified channels, skipping any other channels in the layer.
+    /// Each pixel will be an array with one sample per channel, in the order of the specified names,
+    /// which is convenient for a large number of channels that share one sample type.
+    /// Call `collect_pixels` afterwards to define the pixel container for your set of channels.
+    ///
+    /// Throws an error for deep data, or if any of the specified channels is subsampled.
+    /// Use `specific_channels` instead if the channels should have different sample types or be optional.
+    pub fn specific_channel_array<Sample, const N: usize>(self, channel_names: [impl Into<Text>; N]) -> ReadChannelArray<Sample, N> {
+        ReadChannelArray::new(channel_names.map(Into::into))
+    }
+
+    /// Read only layers that contain the three channels of a vector, skipping any other channels in the layer,
+    /// for example the positions `P.x`, `P.y`, `P.z` or the normals `N.x`, `N.y`, `N.z`.
+    /// Each pixel will be a `Vec3<f32>`. By default, the suffixes `.x`, `.X`, `.r` and `.R` are tried,
+    /// call `with_suffixes` on the result of this function to look for other channel names.
+    /// Call `collect_pixels` afterwards to define the pixel container.
+    ///
+    /// Throws an error for deep data, or if any of the three channels is subsampled.
+    pub fn vector_channels(self, base_name: impl Into<Text>) -> ReadVectorChannels {
+        ReadVectorChannels::new(base_name)
+    }
+
+    /// Read only layers that contain a pair of motion vector channels, skipping any other channels in the layer,
+    /// for example `velocity.x` and `velocity.y`. See `MotionVectorNames::defaults()` for the names that are tried.
+    /// Each pixel will be a `Vec2<f32>`, measured in pixels unless `in_space` is called on the result of this function.
+    /// Call `collect_pixels` afterwards to define the pixel container.
+    ///
+    /// Throws an error for deep data, or if any of the two channels is subsampled.
+    pub fn motion_vector_channels(self) -> ReadMotionVectors {
+        ReadMotionVectors::default()
+    }
+
+    /// Read the channels with the specified names, where the names are only known at runtime,
+    /// for example in a viewer that displays the channels selected by the user.
+    /// Each pixel will be a list with one optional sample per name,
+    /// which is `None` if the layer does not contain a channel with that name.
+    /// Call `collect_pixels` afterwards to define the pixel container.
+    /// The samples are converted to the sample type of the pixel, which can be `f16`, `f32`, `u32` or `Sample`.
+    ///
+    /// Throws an error for images with deep data or subsampling.
+    pub fn dynamic_channels<Sample>(self, channel_names: impl IntoIterator<Item=impl Into<Text>>) -> ReadDynamicChannels<Sample> {
+        ReadDynamicChannels::new(channel_names.into_iter().map(Into::into).collect())
+    }
 }
 
 /// Specify to read all contained resolution levels from the image, if any.
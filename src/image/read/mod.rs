@@ -25,6 +25,11 @@
 //!     All layers containing non-deep data with arbitrary channels are loaded from the file.
 //!     Fails if any layer in the image contains deep data.
 //!
+//! 1. `read_first_flat_layer_and_rgba_from_file(path, your_constructor, your_pixel_setter)`:
+//!     The first layer containing rgba channels is loaded from the file, both with all channels
+//!     and as rgba pixels stored the way you specify, for example as a preview.
+//!     Each block is decompressed only once.
+//!
 //! 1. `read_all_data_from_file(path)`:
 //!     All layers with arbitrary channels and all resolution levels are extracted from the file.
 //!
@@ -48,11 +53,15 @@ pub mod any_channels;
 pub mod levels;
 pub mod samples;
 pub mod specific_channels;
+pub mod sequence;
+
+#[cfg(feature = "remote")]
+pub mod remote;
 
 use crate::error::{Result};
 use crate::image::read::samples::{ReadFlatSamples};
 use std::path::Path;
-use crate::image::{AnyImage, AnyChannels, FlatSamples, Image, Layer, FlatImage, PixelLayersImage, RgbaChannels};
+use crate::image::{AnyImage, AnyChannels, FlatSamples, Image, Layer, FlatImage, PixelLayersImage, RgbaChannels, SpecificChannels};
 use crate::image::read::image::ReadLayers;
 use crate::image::read::layers::ReadChannels;
 use crate::math::Vec2;
@@ -159,6 +168,38 @@ pub fn read_first_rgba_layer_from_file<R,G,B,A, Set:'static, Create:'static, Pix
         .from_file(path)
 }
 
+/// No deep data, no resolution levels, choosing the first layer with rgba channels.
+/// Loads all channels of that layer, and additionally the rgba channels, stored the way you specify.
+/// This is useful for showing a preview immediately while keeping all the original data.
+/// Each block of pixels is decompressed only once.
+/// Uses parallel decompression and relaxed error handling.
+/// Inspect the source code of this function if you need customization.
+/// The alpha channel will contain the value `1.0` if no alpha channel can be found in the image.
+// FIXME Set and Create should not need to be static
+pub fn read_first_flat_layer_and_rgba_from_file<R,G,B,A, Set:'static, Create:'static, Pixels: 'static>(
+    path: impl AsRef<Path>, create: Create, set_pixel: Set
+)
+    -> Result<Image<Layer<(AnyChannels<FlatSamples>, SpecificChannels<Pixels, RgbaChannels>)>>>
+    where
+        R: FromNativeSample, G: FromNativeSample, B: FromNativeSample, A: FromNativeSample,
+        Create: Fn(Vec2<usize>, &RgbaChannels) -> Pixels,
+        Set: Fn(&mut Pixels, Vec2<usize>, (R,G,B,A)),
+{
+    let rgba = read()
+        .no_deep_data()
+        .largest_resolution_level()
+        .rgba_channels(create, set_pixel);
+
+    read()
+        .no_deep_data()
+        .largest_resolution_level()
+        .all_channels()
+        .and_channels(rgba)
+        .first_valid_layer()
+        .all_attributes()
+        .from_file(path)
+}
+
 
 /// Utilizes the builder pattern to configure an image reader. This is the initial struct.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -0,0 +1,219 @@
+//! Read exr files from a web server, downloading only the required bytes using http range requests.
+//! Enable the `remote` feature to use this module.
+//!
+//! The meta data and the offset tables are downloaded first, usually with a single request.
+//! Afterwards, only the chunks that are required for the image are requested, each with a single request.
+//! The `RemoteFile` is a `ChunkSource`, see the `block::source` module.
+//! For example, reading only a few scan lines of a huge image from a cloud storage
+//! does not download the whole file.
+//!
+//! ```no_run
+//! use exr::prelude::*;
+//!
+//! let image = read()
+//!     .no_deep_data().largest_resolution_level()
+//!     .all_channels().all_layers().all_attributes()
+//!     .from_url("https://example.com/image.exr").unwrap();
+//! ```
+
+use std::convert::TryFrom;
+use std::io::{Read, ErrorKind};
+use std::ops::Range;
+
+use crate::block::source::ChunkSource;
+use crate::error::{Result, IoError, IoResult};
+use crate::image::Image;
+use crate::image::read::image::{ReadImage, ReadLayers};
+
+
+/// A file on a web server, which is downloaded in parts using http range requests.
+/// Pass it to `ReadImage::from_source`, or wrap it in a `block::source::SourceReader`
+/// to use it like any other `Read + Seek` byte source.
+#[derive(Debug)]
+pub struct RemoteFile {
+    agent: ureq::Agent,
+    url: String,
+    byte_size: Option<u64>,
+    request_count: usize,
+    downloaded_byte_count: u64,
+}
+
+impl RemoteFile {
+
+    /// Prepare reading the file at the url. Does not send any request yet.
+    pub fn new(url: impl Into<String>) -> Self {
+        Self::with_agent(ureq::Agent::new(), url)
+    }
+
+    /// Prepare reading the file at the url, sending the requests with the specified agent,
+    /// for example to configure timeouts, proxies, or authentication headers.
+    pub fn with_agent(agent: ureq::Agent, url: impl Into<String>) -> Self {
+        Self { agent, url: url.into(), byte_size: None, request_count: 0, downloaded_byte_count: 0 }
+    }
+
+    /// The url of the file.
+    pub fn url(&self) -> &str { &self.url }
+
+    /// The number of requests sent so far.
+    pub fn request_count(&self) -> usize { self.request_count }
+
+    /// The number of bytes downloaded so far.
+    pub fn downloaded_byte_count(&self) -> u64 { self.downloaded_byte_count }
+}
+
+impl ChunkSource for RemoteFile {
+
+    /// Download the bytes in the range with a single request.
+    fn get_bytes(&mut self, bytes: Range<u64>) -> IoResult<Vec<u8>> {
+        if bytes.start >= bytes.end || self.byte_size.map_or(false, |size| bytes.start >= size) {
+            return Ok(Vec::new());
+        }
+
+        self.request_count += 1;
+
+        let response = self.agent.get(&self.url)
+            .set("Range", &format!("bytes={}-{}", bytes.start, bytes.end - 1))
+            .call();
+
+        let response = match response {
+            Ok(response) => response,
+
+            // the range starts after the end of the file
+            Err(ureq::Error::Status(416, _)) => return Ok(Vec::new()),
+            Err(error) => return Err(IoError::new(ErrorKind::Other, error)),
+        };
+
+        if response.status() != 206 {
+            return Err(IoError::new(ErrorKind::Other, format!(
+                "the server does not support range requests for `{}`, status {}", self.url, response.status()
+            )));
+        }
+
+        // the content range has the format `bytes start-end/size`, where the size may be `*`
+        if let Some(size) = response.header("Content-Range")
+            .and_then(|range| range.rsplit('/').next())
+            .and_then(|size| size.trim().parse::<u64>().ok())
+        {
+            self.byte_size = Some(size);
+        }
+
+        let byte_count = bytes.end - bytes.start;
+        let mut downloaded = Vec::with_capacity(usize::try_from(byte_count).unwrap_or(0).min(1024 * 1024 * 64));
+        response.into_reader().take(byte_count).read_to_end(&mut downloaded)?;
+
+        self.downloaded_byte_count += downloaded.len() as u64;
+        Ok(downloaded)
+    }
+
+    /// The number of bytes in the file. Sends a request if the size is not known yet.
+    fn byte_size(&mut self) -> IoResult<u64> {
+        if let Some(size) = self.byte_size { return Ok(size); }
+
+        self.request_count += 1;
+        let response = self.agent.head(&self.url).call()
+            .map_err(|error| IoError::new(ErrorKind::Other, error))?;
+
+        let size = response.header("Content-Length")
+            .and_then(|length| length.trim().parse::<u64>().ok())
+            .ok_or_else(|| IoError::new(ErrorKind::Other, "the server did not report the size of the file"))?;
+
+        self.byte_size = Some(size);
+        Ok(size)
+    }
+}
+
+
+impl<F, L> ReadImage<F, L> where F: FnMut(f64) {
+
+    /// Read the exr image from a web server, downloading only the required chunks, each with a single request.
+    /// The server must support http range requests. See the `image::read::remote` module for more details.
+    #[must_use]
+    pub fn from_url<Layers>(self, url: &str) -> Result<Image<Layers>>
+        where for<'s> L: ReadLayers<'s, Layers = Layers>
+    {
+        self.from_source(RemoteFile::new(url))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prelude::*;
+    use crate::image::pixel_vec::PixelVec;
+    use crate::block::reader::ChunksReader;
+    use crate::block::source::SourceReader;
+    use std::io::{Write, BufRead, BufReader, Cursor};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+
+    /// Serve the bytes at a local url, answering range requests, until the test ends.
+    fn serve(bytes: Vec<u8>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/image.exr", listener.local_addr().unwrap());
+        let bytes = Arc::new(bytes);
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = BufReader::new(stream.try_clone().unwrap());
+
+                let mut range = None;
+                let mut line = String::new();
+                while request.read_line(&mut line).unwrap() > 2 {
+                    if let Some(value) = line.to_lowercase().strip_prefix("range: bytes=") {
+                        let (start, end) = value.trim().split_once('-').unwrap();
+                        range = Some(start.parse::<usize>().unwrap() .. end.parse::<usize>().unwrap() + 1);
+                    }
+
+                    line.clear();
+                }
+
+                let range = range.expect("only range requests are expected");
+                let content = &bytes[range.start.min(bytes.len()) .. range.end.min(bytes.len())];
+
+                write!(stream, "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nContent-Range: bytes {}-{}/{}\r\nConnection: close\r\n\r\n",
+                       content.len(), range.start, range.start + content.len() - 1, bytes.len()).unwrap();
+
+                stream.write_all(content).unwrap();
+            }
+        });
+
+        url
+    }
+
+    #[test]
+    fn read_only_required_chunks_from_url() {
+        let size = Vec2(256, 512);
+        let channels = SpecificChannels::rgb(|position: Vec2<usize>| (position.x() as f32, position.y() as f32, 0.5_f32));
+        let image = Image::from_encoded_channels(size, Encoding::UNCOMPRESSED, channels);
+
+        let mut bytes = Vec::new();
+        image.write().non_parallel().to_buffered(Cursor::new(&mut bytes)).unwrap();
+        let url = serve(bytes.clone());
+
+        let mut remote = SourceReader::new(RemoteFile::new(url.as_str())).with_fetch_size(4096);
+        let blocks = crate::block::read(&mut remote, true).unwrap()
+            .read_scanline_range(100 .. 102, true).unwrap()
+            .sequential_decompressor(true)
+            .collect::<crate::error::Result<Vec<_>>>().unwrap();
+
+        assert_eq!(blocks.len(), 2);
+        assert!(remote.source().downloaded_byte_count() < bytes.len() as u64 / 8, "downloaded too many bytes");
+
+        // the size of the file is known from the first response
+        let mut remote = RemoteFile::new(url.as_str());
+        assert_eq!(remote.get_bytes(4 .. 8).unwrap(), &bytes[4 .. 8]);
+        assert_eq!(remote.byte_size().unwrap(), bytes.len() as u64);
+        assert_eq!(remote.request_count(), 1);
+
+        let decoded = read().no_deep_data().largest_resolution_level()
+            .rgb_channels(PixelVec::<(f32, f32, f32)>::constructor, PixelVec::set_pixel)
+            .first_valid_layer().all_attributes()
+            .from_url(&url).unwrap();
+
+        let pixels = &decoded.layer_data.channel_data.pixels;
+        assert_eq!(pixels.resolution, size);
+        assert_eq!(*pixels.get_pixel(Vec2(37, 481)), (37.0, 481.0, 0.5));
+    }
+}
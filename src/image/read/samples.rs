@@ -9,6 +9,7 @@ use crate::meta::attribute::{ChannelDescription, SampleType};
 use crate::image::read::any_channels::{SamplesReader, ReadSamples};
 use crate::image::read::levels::{ReadSamplesLevel, ReadAllLevels, ReadLargestLevel};
 use crate::block::chunk::TileCoordinates;
+use half::slice::HalfFloatSliceExt;
 // use crate::image::read::layers::ReadChannels;
 
 /// Specify to read only flat samples and no "deep data"
@@ -30,6 +31,27 @@ impl ReadFlatSamples {
     pub fn all_resolution_levels(self) -> ReadAllLevels<Self> { ReadAllLevels { read_samples: self } }
 
     // TODO pub fn specific_resolution_level<F: Fn(&[Vec2<usize>])->usize >(self, select_level: F) -> ReadLevelBy<Self> { ReadAllLevels { read_samples: self } }
+
+    /// Specify to convert all samples to `f32` while reading,
+    /// storing each channel as a separate plane in a `Vec<f32>`.
+    /// Loading `all_channels()` then produces an `AnyChannels<Vec<f32>>`.
+    /// This is the fastest way to load `f32` samples, as each line of samples is copied directly,
+    /// without constructing any pixels.
+    pub fn f32_planes(self) -> ReadF32Planes { ReadF32Planes }
+}
+
+/// Specify to read only flat samples, converting all samples to `f32`.
+/// Each channel is stored as a separate plane in a `Vec<f32>`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ReadF32Planes;
+
+impl ReadF32Planes {
+
+    /// Specify to read only the highest resolution level, skipping all smaller variations.
+    pub fn largest_resolution_level(self) -> ReadLargestLevel<Self> { ReadLargestLevel { read_samples: self } }
+
+    /// Specify to read all contained resolution levels from the image, if any.
+    pub fn all_resolution_levels(self) -> ReadAllLevels<Self> { ReadAllLevels { read_samples: self } }
 }
 
 
@@ -120,3 +142,78 @@ impl SamplesReader for FlatSamplesReader {
     }
 }
 
+
+/// Processes pixel blocks from a file and accumulates them into a plane of `f32` samples.
+#[derive(Debug, Clone, PartialEq)]
+pub struct F32PlaneReader {
+    level: Vec2<usize>,
+    resolution: Vec2<usize>,
+    sample_type: SampleType,
+    samples: Vec<f32>,
+
+    /// Reused for each line of `f16` samples, to convert multiple samples at once.
+    f16_line: Vec<f16>,
+}
+
+impl ReadSamples for ReadF32Planes {
+    type Reader = F32PlaneReader;
+
+    fn create_sample_reader(&self, header: &Header, channel: &ChannelDescription) -> Result<Self::Reader> {
+        self.create_samples_level_reader(header, channel, Vec2(0, 0), header.layer_size)
+    }
+}
+
+impl ReadSamplesLevel for ReadF32Planes {
+    type Reader = F32PlaneReader;
+
+    fn create_samples_level_reader(&self, _header: &Header, channel: &ChannelDescription, level: Vec2<usize>, resolution: Vec2<usize>) -> Result<Self::Reader> {
+        Ok(F32PlaneReader {
+            level, resolution, // TODO sampling
+            sample_type: channel.sample_type,
+            samples: vec![0.0; resolution.area()],
+            f16_line: Vec::new(),
+        })
+    }
+}
+
+impl SamplesReader for F32PlaneReader {
+    type Samples = Vec<f32>;
+
+    fn filter_block(&self, tile: TileCoordinates) -> bool {
+        tile.level_index == self.level
+    }
+
+    fn read_line(&mut self, line: LineRef<'_>) -> UnitResult {
+        let index = line.location;
+
+        // the index is generated by ourselves and must always be correct
+        debug_assert_eq!(index.level, self.level, "line should have been filtered");
+        debug_assert!(index.position.x() + index.sample_count <= self.resolution.width(), "line index calculation bug");
+        debug_assert!(index.position.y() < self.resolution.height(), "line index calculation bug");
+
+        let start_index = index.position.y() * self.resolution.width() + index.position.x();
+        let samples = &mut self.samples[start_index .. start_index + index.sample_count];
+
+        match self.sample_type {
+            SampleType::F32 => line.read_samples_into_slice(samples).expect("writing line bytes failed"),
+
+            SampleType::F16 => {
+                self.f16_line.resize(index.sample_count, f16::ZERO);
+                line.read_samples_into_slice(&mut self.f16_line).expect("writing line bytes failed");
+                self.f16_line.convert_to_f32_slice(samples);
+            },
+
+            SampleType::U32 => {
+                for (target, sample) in samples.iter_mut().zip(line.read_samples::<u32>()) {
+                    *target = sample.expect("writing line bytes failed") as f32;
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    fn into_samples(self) -> Vec<f32> {
+        self.samples
+    }
+}
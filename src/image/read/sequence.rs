@@ -0,0 +1,380 @@
+//! Play back and scrub through image sequences, decompressing the upcoming frames in the background.
+//!
+//! A `FrameSequence` keeps the decompressed blocks of the frames around the playhead in a `ChunkCache`,
+//! using the index of the frame as the file id.
+//! Tell the sequence where the playhead is and in which direction it moves, using `set_playhead`.
+//! The sequence then decompresses the upcoming frames on background threads, nearest frames first,
+//! and discards the blocks of all frames that are too far away from the playhead.
+//! Reading a frame with `read_frame` then only decompresses the blocks that are not in the cache yet.
+//!
+//! ```no_run
+//! use std::sync::Arc;
+//! use exr::prelude::*;
+//! use exr::block::cache::ChunkCache;
+//! use exr::image::read::sequence::{FrameSequence, Playback};
+//!
+//! let paths = (1001 ..= 1100).map(|frame| format!("shot/render.{}.exr", frame).into()).collect();
+//! let cache = Arc::new(ChunkCache::new(4 * 1024 * 1024 * 1024));
+//! let mut sequence = FrameSequence::new(paths, cache).prefetch_frames(12);
+//!
+//! for frame in 0 .. sequence.len() {
+//!     sequence.set_playhead(frame, Playback::Forward);
+//!
+//!     let image = sequence.read_frame(frame, read()
+//!         .no_deep_data().largest_resolution_level()
+//!         .all_channels().all_layers().all_attributes()
+//!     ).unwrap();
+//!
+//!     // display the image ...
+//! }
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::io::BufReader;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, Condvar};
+use std::thread::JoinHandle;
+
+use crate::block::cache::{ChunkCache, FileId};
+use crate::error::Result;
+use crate::image::Image;
+use crate::image::read::image::{ReadImage, ReadLayers};
+
+
+/// How the playhead of a `FrameSequence` moves. Decides which frames are prefetched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Playback {
+
+    /// The playhead stays at the frame, but may be moved in either direction next.
+    /// Prefetches the frames before and after the playhead.
+    Paused,
+
+    /// The playhead moves towards higher frame indices.
+    Forward,
+
+    /// The playhead moves towards lower frame indices.
+    Backward,
+}
+
+/// Whether the blocks of a frame are in the cache of a `FrameSequence`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FrameState {
+
+    /// The frame will be prefetched soon.
+    Queued,
+
+    /// The frame is being prefetched right now.
+    Loading,
+
+    /// The frame has been prefetched. Its blocks may still be evicted if the cache is too small.
+    Loaded,
+
+    /// The frame could not be prefetched. Reading it will return the error.
+    Failed,
+}
+
+/// Prefetches the frames around the playhead of an image sequence into a `ChunkCache`.
+/// See the module documentation for more details.
+#[derive(Debug)]
+pub struct FrameSequence {
+    paths: Arc<Vec<PathBuf>>,
+    cache: Arc<ChunkCache>,
+    shared: Arc<SharedState>,
+    workers: Vec<JoinHandle<()>>,
+
+    prefetch_frame_count: usize,
+    kept_frame_count_behind: usize,
+    thread_count: usize,
+    pedantic: bool,
+}
+
+#[derive(Debug, Default)]
+struct SharedState {
+    prefetch: Mutex<PrefetchState>,
+    changed: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct PrefetchState {
+
+    /// The frames that should be loaded next, most urgent first.
+    queue: VecDeque<usize>,
+
+    /// The frames that are queued, loading, or loaded.
+    frames: HashMap<usize, FrameState>,
+
+    /// The frames that are not evicted.
+    kept_frames: Range<usize>,
+
+    shutdown: bool,
+}
+
+impl FrameSequence {
+
+    /// Prepare prefetching the frames at the paths into the cache. Does not read any file yet.
+    /// The cache should be large enough to contain all prefetched frames.
+    /// Do not share the cache with other files, as the frame indices are used as the file ids.
+    pub fn new(paths: Vec<PathBuf>, cache: Arc<ChunkCache>) -> Self {
+        FrameSequence {
+            paths: Arc::new(paths), cache,
+            shared: Arc::new(SharedState::default()),
+            workers: Vec::new(),
+            prefetch_frame_count: 8,
+            kept_frame_count_behind: 2,
+            thread_count: crate::block::default_thread_count(),
+            pedantic: false,
+        }
+    }
+
+    /// Prefetch this many frames ahead of the playhead, in the direction of playback. The default is 8 frames.
+    /// When paused, this many frames are prefetched in both directions.
+    pub fn prefetch_frames(mut self, frame_count: usize) -> Self { self.prefetch_frame_count = frame_count; self }
+
+    /// Keep this many frames behind the playhead in the cache, instead of evicting them, for example to step back a few frames.
+    /// The default is 2 frames.
+    pub fn keep_frames_behind(mut self, frame_count: usize) -> Self { self.kept_frame_count_behind = frame_count; self }
+
+    /// Prefetch at most this many frames at once. By default, this is the number of cpus,
+    /// or the number in the environment variable `EXR_THREADS`, see `block::default_thread_count`.
+    pub fn threads(mut self, thread_count: usize) -> Self { self.thread_count = thread_count.max(1); self }
+
+    /// Return an error for any unusual information in the prefetched files, see `ReadImage::pedantic`.
+    pub fn pedantic(mut self) -> Self { self.pedantic = true; self }
+
+    /// The number of frames in the sequence.
+    pub fn len(&self) -> usize { self.paths.len() }
+
+    /// Whether the sequence contains no frames.
+    pub fn is_empty(&self) -> bool { self.paths.is_empty() }
+
+    /// The path of the frame.
+    pub fn path(&self, frame: usize) -> &Path { &self.paths[frame] }
+
+    /// The cache that contains the decompressed blocks of the frames.
+    pub fn cache(&self) -> &Arc<ChunkCache> { &self.cache }
+
+    /// Whether the frame is prefetched. Returns `None` if the frame will not be prefetched.
+    pub fn frame_state(&self, frame: usize) -> Option<FrameState> {
+        self.lock().frames.get(&frame).copied()
+    }
+
+    /// Move the playhead. Starts prefetching the frames around the playhead,
+    /// and discards the blocks of all frames that are too far away from the playhead.
+    /// Call this whenever the playhead moves or the playback changes.
+    pub fn set_playhead(&mut self, frame: usize, playback: Playback) {
+        let frame_count = self.len();
+        let ahead = self.prefetch_frame_count;
+        let behind = self.kept_frame_count_behind;
+
+        // the frames in the order in which they are needed, and the frames that are not evicted
+        let (wanted, kept_frames): (Vec<usize>, Range<usize>) = match playback {
+            Playback::Forward => (
+                (frame ..= frame.saturating_add(ahead)).collect(),
+                frame.saturating_sub(behind) .. frame.saturating_add(ahead).saturating_add(1),
+            ),
+
+            Playback::Backward => (
+                (frame.saturating_sub(ahead) ..= frame).rev().collect(),
+                frame.saturating_sub(ahead) .. frame.saturating_add(behind).saturating_add(1),
+            ),
+
+            Playback::Paused => (
+                std::iter::once(frame)
+                    .chain((1 ..= ahead).flat_map(|distance| vec![
+                        frame.checked_add(distance), frame.checked_sub(distance)
+                    ]).flatten())
+                    .collect(),
+
+                frame.saturating_sub(ahead.max(behind)) .. frame.saturating_add(ahead).saturating_add(1),
+            ),
+        };
+
+        let kept_frames = kept_frames.start.min(frame_count) .. kept_frames.end.min(frame_count);
+
+        {
+            let mut state = self.lock();
+            let PrefetchState { queue, frames, .. } = &mut *state;
+
+            // forget the frames that were queued for the previous playhead
+            for previous in queue.drain(..) { frames.remove(&previous); }
+
+            for &wanted in wanted.iter().filter(|&&wanted| wanted < frame_count) {
+                if !frames.contains_key(&wanted) {
+                    frames.insert(wanted, FrameState::Queued);
+                    queue.push_back(wanted);
+                }
+            }
+
+            // frames that are still loading are evicted by the worker after loading
+            let cache = &self.cache;
+            frames.retain(|&frame, frame_state| {
+                let keep = kept_frames.contains(&frame) || *frame_state == FrameState::Loading;
+                if !keep { cache.remove_file(frame as FileId); }
+                keep
+            });
+
+            state.kept_frames = kept_frames;
+        }
+
+        self.spawn_workers();
+        self.shared.changed.notify_all();
+    }
+
+    /// Block the current thread until all queued frames have been prefetched.
+    pub fn wait_until_prefetched(&self) {
+        let mut state = self.lock();
+
+        while !state.queue.is_empty() || state.frames.values().any(|&frame| frame == FrameState::Loading) {
+            state = self.shared.changed.wait(state).unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+    }
+
+    /// Read the frame, taking all prefetched blocks from the cache.
+    /// If the frame is being prefetched right now, waits for it instead of decompressing it twice.
+    /// Panics if the frame index is out of bounds.
+    #[must_use]
+    pub fn read_frame<F, L, Layers>(&self, frame: usize, read: ReadImage<F, L>) -> Result<Image<Layers>>
+        where F: FnMut(f64), for<'s> L: ReadLayers<'s, Layers = Layers>
+    {
+        let path = &self.paths[frame];
+
+        {
+            let mut state = self.lock();
+            while state.frames.get(&frame) == Some(&FrameState::Loading) {
+                state = self.shared.changed.wait(state).unwrap_or_else(|poisoned| poisoned.into_inner());
+            }
+        }
+
+        read.chunk_cache(self.cache.clone(), frame as FileId).from_file(path)
+    }
+
+    fn spawn_workers(&mut self) {
+        while self.workers.len() < self.thread_count {
+            let paths = self.paths.clone();
+            let cache = self.cache.clone();
+            let shared = self.shared.clone();
+            let pedantic = self.pedantic;
+
+            self.workers.push(std::thread::spawn(move || prefetch_frames(&paths, &cache, &shared, pedantic)));
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, PrefetchState> { self.shared.lock() }
+}
+
+impl SharedState {
+    fn lock(&self) -> MutexGuard<'_, PrefetchState> {
+        // the state is always consistent, even if another thread panicked
+        self.prefetch.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Drop for FrameSequence {
+    fn drop(&mut self) {
+        self.lock().shutdown = true;
+        self.shared.changed.notify_all();
+
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Load the queued frames into the cache, one after another, until the sequence is dropped.
+fn prefetch_frames(paths: &[PathBuf], cache: &ChunkCache, shared: &SharedState, pedantic: bool) {
+    loop {
+        let frame = {
+            let mut state = shared.lock();
+
+            loop {
+                if state.shutdown { return; }
+
+                if let Some(frame) = state.queue.pop_front() {
+                    state.frames.insert(frame, FrameState::Loading);
+                    break frame;
+                }
+
+                state = shared.changed.wait(state).unwrap_or_else(|poisoned| poisoned.into_inner());
+            }
+        };
+
+        let result = crate::io::open_file(&paths[frame])
+            .and_then(|file| cache.load_file(frame as FileId, BufReader::new(file), pedantic));
+
+        {
+            let mut state = shared.lock();
+
+            if state.kept_frames.contains(&frame) {
+                state.frames.insert(frame, if result.is_ok() { FrameState::Loaded } else { FrameState::Failed });
+            }
+            else {
+                // the playhead has moved away while the frame was loading
+                state.frames.remove(&frame);
+                cache.remove_file(frame as FileId);
+            }
+        }
+
+        shared.changed.notify_all();
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prelude::*;
+    use crate::image::pixel_vec::PixelVec;
+
+    #[test]
+    fn prefetch_and_evict_frames_around_playhead() {
+        let directory = tempfile::tempdir().unwrap();
+
+        let paths: Vec<PathBuf> = (0 .. 6).map(|frame| {
+            let path = directory.path().join(format!("frame.{}.exr", frame));
+            let channels = SpecificChannels::rgb(move |position: Vec2<usize>| (position.x() as f32, position.y() as f32, frame as f32));
+            Image::from_encoded_channels(Vec2(32, 48), Encoding::FAST_LOSSLESS, channels).write().to_file(&path).unwrap();
+            path
+        }).collect();
+
+        let cache = Arc::new(ChunkCache::new(1024 * 1024));
+        let mut sequence = FrameSequence::new(paths.clone(), cache.clone())
+            .prefetch_frames(2).keep_frames_behind(1).threads(2);
+
+        sequence.set_playhead(0, Playback::Forward);
+        sequence.wait_until_prefetched();
+
+        for frame in 0 ..= 2 { assert_eq!(sequence.frame_state(frame), Some(FrameState::Loaded)); }
+        assert_eq!(sequence.frame_state(3), None);
+
+        let blocks_per_frame = cache.len() / 3;
+        assert!(blocks_per_frame > 0);
+
+        sequence.set_playhead(4, Playback::Forward);
+        sequence.wait_until_prefetched();
+
+        // evicts the frames that are too far behind the playhead
+        for frame in 0 ..= 3 { assert_eq!(sequence.frame_state(frame), None); }
+        for frame in 4 ..= 5 { assert_eq!(sequence.frame_state(frame), Some(FrameState::Loaded)); }
+        assert_eq!(cache.len(), 2 * blocks_per_frame);
+
+        let misses = cache.statistics().misses;
+        let image = sequence.read_frame(4, read().no_deep_data().largest_resolution_level()
+            .rgb_channels(PixelVec::<(f32, f32, f32)>::constructor, PixelVec::set_pixel)
+            .first_valid_layer().all_attributes()
+        ).unwrap();
+
+        assert_eq!(cache.statistics().misses, misses, "prefetched frame should not be decompressed again");
+        assert_eq!(*image.layer_data.channel_data.pixels.get_pixel(Vec2(7, 41)), (7.0, 41.0, 4.0));
+
+        // keeps one frame behind the playhead
+        sequence.set_playhead(5, Playback::Forward);
+        assert_eq!(sequence.frame_state(4), Some(FrameState::Loaded));
+
+        sequence.set_playhead(1, Playback::Paused);
+        sequence.wait_until_prefetched();
+        for frame in 0 ..= 3 { assert_eq!(sequence.frame_state(frame), Some(FrameState::Loaded)); }
+        for frame in 4 ..= 5 { assert_eq!(sequence.frame_state(frame), None); }
+
+    }
+}
@@ -10,8 +10,13 @@ use crate::error::*;
 use crate::block::UncompressedBlock;
 use crate::image::read::layers::{ChannelsReader, ReadChannels};
 use crate::block::chunk::TileCoordinates;
+use crate::image::pixel_struct::ExrPixel;
+use crate::image::motion::{MotionVectorNames, MotionSpace};
+use crate::image::storage::PixelStorage;
 
 use std::marker::PhantomData;
+use std::convert::TryInto;
+use smallvec::SmallVec;
 
 
 /// Can be attached one more channel reader.
@@ -32,7 +37,17 @@ pub trait ReadSpecificChannel: Sized + CheckDuplicates {
     fn required<Sample>(self, channel_name: impl Into<Text>) -> ReadRequiredChannel<Self, Sample> {
         let channel_name = channel_name.into();
         assert!(self.already_contains(&channel_name).not(), "a channel with the name `{}` is already defined", channel_name);
-        ReadRequiredChannel { channel_name, previous_channels: self, px: Default::default() }
+        self.required_matching(channel_name, ChannelMatching::Exact)
+    }
+
+    /// Plan to read an additional channel from the image, finding the channel in the file using the specified policy.
+    /// For example, `required_matching("A", ChannelMatching::aliases(["alpha", "Alpha"]))`
+    /// also reads the alpha channel of files that name it `alpha` or `Alpha`.
+    /// If no channel matches when the image is read, the image will not be loaded.
+    fn required_matching<Sample>(self, channel_name: impl Into<Text>, matching: ChannelMatching) -> ReadRequiredChannel<Self, Sample> {
+        let channel_name = channel_name.into();
+        assert!(self.already_contains(&channel_name).not(), "a channel with the name `{}` is already defined", channel_name);
+        ReadRequiredChannel { channel_name, matching, previous_channels: self, px: Default::default() }
     }
 
     /// Plan to read an additional channel from the image, with the specified name.
@@ -45,7 +60,26 @@ pub trait ReadSpecificChannel: Sized + CheckDuplicates {
     {
         let channel_name = channel_name.into();
         assert!(self.already_contains(&channel_name).not(), "a channel with the name `{}` is already defined", channel_name);
-        ReadOptionalChannel { channel_name, previous_channels: self, default_sample }
+        self.optional_matching(channel_name, default_sample, ChannelMatching::Exact)
+    }
+
+    /// Plan to read an additional channel from the image, finding the channel in the file using the specified policy.
+    /// If no channel matches, the specified default sample will be returned instead.
+    fn optional_matching<Sample>(self, channel_name: impl Into<Text>, default_sample: Sample, matching: ChannelMatching)
+        -> ReadOptionalChannel<Self, Sample>
+    {
+        let channel_name = channel_name.into();
+        assert!(self.already_contains(&channel_name).not(), "a channel with the name `{}` is already defined", channel_name);
+        ReadOptionalChannel { channel_name, matching, previous_channels: self, default_sample }
+    }
+
+    /// Resolve all previously specified channels within a layer that is stored in the channel names,
+    /// for example find `R` as `beauty.R` when calling `within_layer("beauty")`.
+    /// This allows reading files that store multiple layers in a single header.
+    /// The channel descriptions of the resulting image will contain the names without the layer prefix.
+    /// Channels specified after this call are resolved without the layer prefix.
+    fn within_layer(self, layer_name: impl Into<Text>) -> ReadChannelsWithinLayer<Self> {
+        ReadChannelsWithinLayer { layer_name: layer_name.into(), channels: self }
     }
 
     /// Using two closures, define how to store the pixels.
@@ -67,6 +101,145 @@ pub trait ReadSpecificChannel: Sized + CheckDuplicates {
     {
         CollectPixels { read_channels: self, set_pixel, create_pixels, px: Default::default() }
     }
+
+    /// Like `collect_pixels`, but the first closure receives the header of the layer instead of only its size.
+    /// The header contains the complete channel list, the layer name, the position of the data window,
+    /// and all other attributes of the layer, which can be used to decide how to store the pixels.
+    fn collect_pixels_with_header<Pixel, PixelStorage, CreatePixels, SetPixel>(
+        self, create_pixels: CreatePixels, set_pixel: SetPixel
+    ) -> CollectPixels<Self, Pixel, PixelStorage, CreatePixelsWithHeader<CreatePixels>, SetPixel>
+        where
+            <Self::RecursivePixelReader as RecursivePixelReader>::RecursivePixel: IntoTuple<Pixel>,
+            <Self::RecursivePixelReader as RecursivePixelReader>::RecursiveChannelDescriptions: IntoNonRecursive,
+            CreatePixels: Fn(
+                &Header,
+                &<<Self::RecursivePixelReader as RecursivePixelReader>::RecursiveChannelDescriptions as IntoNonRecursive>::NonRecursive
+            ) -> PixelStorage,
+            SetPixel: Fn(&mut PixelStorage, Vec2<usize>, Pixel),
+    {
+        CollectPixels { read_channels: self, set_pixel, create_pixels: CreatePixelsWithHeader(create_pixels), px: Default::default() }
+    }
+
+    /// Store the pixels in a `PixelStorage`, such as `PixelVec` or your own storage type,
+    /// instead of defining closures. The pixels are inserted line by line.
+    fn collect_storage<Storage>(self) -> CollectPixels<Self, Storage::Pixel, Storage, CreateStorage, SetStorageLines>
+        where
+            Storage: PixelStorage,
+            <Self::RecursivePixelReader as RecursivePixelReader>::RecursivePixel: IntoTuple<Storage::Pixel>,
+    {
+        CollectPixels { read_channels: self, set_pixel: SetStorageLines, create_pixels: CreateStorage, px: Default::default() }
+    }
+}
+
+/// Inserts the pixels of a line into the pixel storage, after they have been read.
+/// Implemented for closures that insert a single pixel,
+/// and for `SetStorageLines`, which inserts the whole line into a `PixelStorage`.
+pub trait SetPixels<PixelStorage, Pixel> {
+
+    /// Insert a horizontal line of pixels, starting at the specified position.
+    fn set_line(&self, storage: &mut PixelStorage, position: Vec2<usize>, pixels: impl Iterator<Item = Pixel>);
+}
+
+impl<PixelStorage, Pixel, SetPixel> SetPixels<PixelStorage, Pixel> for SetPixel
+    where SetPixel: Fn(&mut PixelStorage, Vec2<usize>, Pixel)
+{
+    fn set_line(&self, storage: &mut PixelStorage, position: Vec2<usize>, pixels: impl Iterator<Item = Pixel>) {
+        for (x, pixel) in pixels.enumerate() {
+            self(storage, position + Vec2(x, 0), pixel);
+        }
+    }
+}
+
+/// Creates a `PixelStorage` for each layer. Created by `collect_storage`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct CreateStorage;
+
+/// Inserts whole lines into a `PixelStorage`. Created by `collect_storage`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SetStorageLines;
+
+impl<ChannelDescriptions, Storage: PixelStorage> CreatePixelStorage<ChannelDescriptions, Storage> for CreateStorage {
+    fn create_pixel_storage(&self, header: &Header, _: &ChannelDescriptions) -> Storage {
+        Storage::create_for_header(header)
+    }
+}
+
+impl<Storage: PixelStorage> SetPixels<Storage, Storage::Pixel> for SetStorageLines {
+    fn set_line(&self, storage: &mut Storage, position: Vec2<usize>, pixels: impl Iterator<Item = Storage::Pixel>) {
+        storage.set_line(position, pixels)
+    }
+}
+
+/// Creates the pixel storage of a layer, before any pixels are read.
+/// Implemented for closures that take the layer size and the channel descriptions,
+/// and for `CreatePixelsWithHeader`, which takes the whole header instead of the layer size.
+pub trait CreatePixelStorage<ChannelDescriptions, PixelStorage> {
+
+    /// Create the storage for the pixels of the layer described by the header.
+    fn create_pixel_storage(&self, header: &Header, channels: &ChannelDescriptions) -> PixelStorage;
+}
+
+/// Wraps a closure that creates the pixel storage using the complete header of a layer.
+/// Created by `collect_pixels_with_header`.
+#[derive(Copy, Clone, Debug)]
+pub struct CreatePixelsWithHeader<CreatePixels>(pub CreatePixels);
+
+impl<ChannelDescriptions, PixelStorage, CreatePixels> CreatePixelStorage<ChannelDescriptions, PixelStorage> for CreatePixels
+    where CreatePixels: Fn(Vec2<usize>, &ChannelDescriptions) -> PixelStorage
+{
+    fn create_pixel_storage(&self, header: &Header, channels: &ChannelDescriptions) -> PixelStorage {
+        self(header.layer_size, channels)
+    }
+}
+
+impl<ChannelDescriptions, PixelStorage, CreatePixels> CreatePixelStorage<ChannelDescriptions, PixelStorage> for CreatePixelsWithHeader<CreatePixels>
+    where CreatePixels: Fn(&Header, &ChannelDescriptions) -> PixelStorage
+{
+    fn create_pixel_storage(&self, header: &Header, channels: &ChannelDescriptions) -> PixelStorage {
+        (self.0)(header, channels)
+    }
+}
+
+/// How the name of a specified channel is compared to the channel names in a file.
+/// Files from different applications do not always agree on the capitalization or spelling of channels.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ChannelMatching {
+
+    /// The channel in the file must have exactly the specified name.
+    Exact,
+
+    /// The channel in the file must have the specified name, ignoring capitalization.
+    /// A channel with exactly the specified name is preferred.
+    CaseInsensitive,
+
+    /// The channel in the file must have exactly the specified name or one of these alternative names.
+    /// The specified name is preferred, then the aliases in the order of this list.
+    Aliases(SmallVec<[Text; 4]>),
+}
+
+impl ChannelMatching {
+
+    /// Also accept channels with one of these alternative names.
+    pub fn aliases(names: impl IntoIterator<Item = impl Into<Text>>) -> Self {
+        ChannelMatching::Aliases(names.into_iter().map(Into::into).collect())
+    }
+
+    /// Find the channel that matches the name best, and also return its byte offset in a pixel.
+    pub fn find_channel<'c>(&self, name: &Text, channels: &'c ChannelList) -> Option<(usize, &'c ChannelDescription)> {
+        let find = |predicate: &dyn Fn(&Text) -> bool| channels.channels_with_byte_offset()
+            .find(|(_, channel)| predicate(&channel.name));
+
+        find(&|channel_name| channel_name == name).or_else(|| match self {
+            ChannelMatching::Exact => None,
+
+            ChannelMatching::CaseInsensitive => find(&|channel_name|
+                channel_name.as_slice().eq_ignore_ascii_case(name.as_slice())
+            ),
+
+            ChannelMatching::Aliases(aliases) => aliases.iter()
+                .find_map(|alias| find(&|channel_name| channel_name == alias)),
+        })
+    }
 }
 
 /// A reader containing sub-readers for reading the pixel content of an image.
@@ -82,11 +255,20 @@ pub trait RecursivePixelReader {
     /// The pixel type. Will be converted to a tuple at the end of the process.
     type RecursivePixel: Copy + Default + 'static;
 
-    /// Read the line of pixels.
+    /// Read the line of pixels. Returns an error if the line does not contain enough bytes.
     fn read_pixels<'s, FullPixel>(
         &self, bytes: &'s[u8], pixels: &mut [FullPixel],
         get_pixel: impl Fn(&mut FullPixel) -> &mut Self::RecursivePixel
-    );
+    ) -> UnitResult;
+
+    /// Whether any of the channels was found in the file.
+    /// If not, all pixels contain only default samples, and no block needs to be decompressed.
+    /// Returns true by default, such that all blocks are decompressed.
+    fn reads_any_channel(&self) -> bool { true }
+
+    /// Whether one of the channels is read from the channel in the file with this name.
+    /// Returns true by default, such that all channels are prepared.
+    fn reads_channel(&self, _name: &Text) -> bool { true }
 }
 
 // does not use the generic `Recursive` struct to reduce the number of angle brackets in the public api
@@ -96,6 +278,7 @@ pub trait RecursivePixelReader {
 pub struct ReadOptionalChannel<ReadChannels, Sample> {
     previous_channels: ReadChannels,
     channel_name: Text,
+    matching: ChannelMatching,
     default_sample: Sample,
 }
 
@@ -106,9 +289,18 @@ pub struct ReadOptionalChannel<ReadChannels, Sample> {
 pub struct ReadRequiredChannel<ReadChannels, Sample> {
     previous_channels: ReadChannels,
     channel_name: Text,
+    matching: ChannelMatching,
     px: PhantomData<Sample>,
 }
 
+/// Used to read the previously specified channels from a layer inside the channel names,
+/// such as `beauty.R` in a single header. Created with `within_layer`.
+#[derive(Clone, Debug)]
+pub struct ReadChannelsWithinLayer<ReadChannels> {
+    channels: ReadChannels,
+    layer_name: Text,
+}
+
 /// Specifies how to collect all the specified channels into a number of individual pixels.
 #[derive(Copy, Clone, Debug)]
 pub struct CollectPixels<ReadChannels, Pixel, PixelStorage, CreatePixels, SetPixel> {
@@ -130,14 +322,20 @@ impl<Inner: CheckDuplicates, Sample> CheckDuplicates for ReadOptionalChannel<Inn
     }
 }
 
+impl<Inner: CheckDuplicates> CheckDuplicates for ReadChannelsWithinLayer<Inner> {
+    fn already_contains(&self, name: &Text) -> bool {
+        self.channels.already_contains(name)
+    }
+}
+
 impl<'s, InnerChannels, Pixel, PixelStorage, CreatePixels, SetPixel: 's>
 ReadChannels<'s> for CollectPixels<InnerChannels, Pixel, PixelStorage, CreatePixels, SetPixel>
     where
         InnerChannels: ReadSpecificChannel,
         <InnerChannels::RecursivePixelReader as RecursivePixelReader>::RecursivePixel: IntoTuple<Pixel>,
         <InnerChannels::RecursivePixelReader as RecursivePixelReader>::RecursiveChannelDescriptions: IntoNonRecursive,
-        CreatePixels: Fn(Vec2<usize>, &<<InnerChannels::RecursivePixelReader as RecursivePixelReader>::RecursiveChannelDescriptions as IntoNonRecursive>::NonRecursive) -> PixelStorage,
-        SetPixel: Fn(&mut PixelStorage, Vec2<usize>, Pixel),
+        CreatePixels: CreatePixelStorage<<<InnerChannels::RecursivePixelReader as RecursivePixelReader>::RecursiveChannelDescriptions as IntoNonRecursive>::NonRecursive, PixelStorage>,
+        SetPixel: SetPixels<PixelStorage, Pixel>,
 {
     type Reader = SpecificChannelsReader<
         PixelStorage, &'s SetPixel,
@@ -151,8 +349,17 @@ ReadChannels<'s> for CollectPixels<InnerChannels, Pixel, PixelStorage, CreatePix
         let pixel_reader = self.read_channels.create_recursive_reader(&header.channels)?;
         let channel_descriptions = pixel_reader.get_descriptions().into_non_recursive();// TODO not call this twice
 
-        let create = &self.create_pixels;
-        let pixel_storage = create(header.layer_size, &channel_descriptions);
+        let mut pixel_storage = self.create_pixels.create_pixel_storage(header, &channel_descriptions);
+
+        // the blocks of this layer will not be decompressed, so fill in the default samples right away
+        if !pixel_reader.reads_any_channel() {
+            let mut pixel_line = vec![ Default::default(); header.layer_size.width() ];
+            pixel_reader.read_pixels(&[], &mut pixel_line, |px| px)?;
+
+            for y in 0 .. header.layer_size.height() {
+                self.set_pixel.set_line(&mut pixel_storage, Vec2(0, y), pixel_line.iter().map(|pixel| pixel.into_tuple()));
+            }
+        }
 
         Ok(SpecificChannelsReader {
             set_pixel: &self.set_pixel,
@@ -172,30 +379,641 @@ pub struct SpecificChannelsReader<PixelStorage, SetPixel, PixelReader, Pixel> {
     px: PhantomData<Pixel>
 }
 
-impl<PixelStorage, SetPixel, PxReader, Pixel>
-ChannelsReader for SpecificChannelsReader<PixelStorage, SetPixel, PxReader, Pixel>
+impl<'s, PixelStorage, SetPixel, PxReader, Pixel>
+ChannelsReader for SpecificChannelsReader<PixelStorage, &'s SetPixel, PxReader, Pixel>
     where PxReader: RecursivePixelReader,
           PxReader::RecursivePixel: IntoTuple<Pixel>,
           PxReader::RecursiveChannelDescriptions: IntoNonRecursive,
-          SetPixel: Fn(&mut PixelStorage, Vec2<usize>, Pixel),
+          SetPixel: SetPixels<PixelStorage, Pixel>,
 {
     type Channels = SpecificChannels<PixelStorage, <PxReader::RecursiveChannelDescriptions as IntoNonRecursive>::NonRecursive>;
 
-    fn filter_block(&self, tile: TileCoordinates) -> bool { tile.is_largest_resolution_level() } // TODO all levels
+    fn filter_block(&self, tile: TileCoordinates) -> bool {
+        // skip decompression if the block contains none of the channels, for example in a layer without any of the optional channels.
+        // blocks are never skipped because of their position, as this reader always reads the whole layer
+        tile.is_largest_resolution_level() && self.pixel_reader.reads_any_channel() // TODO all levels
+    }
+
+    fn reads_channel(&self, channel: &ChannelDescription) -> bool {
+        self.pixel_reader.reads_channel(&channel.name)
+    }
 
     fn read_block(&mut self, header: &Header, block: UncompressedBlock) -> UnitResult {
-        let mut pixels = vec![PxReader::RecursivePixel::default(); block.index.pixel_size.width()]; // TODO allocate once in self
+        let byte_lines = byte_lines(header, &block)?;
 
-        let byte_lines = block.data.chunks_exact(header.channels.bytes_per_pixel * block.index.pixel_size.width());
-        debug_assert_eq!(byte_lines.len(), block.index.pixel_size.height(), "invalid block lines split");
+        let mut pixels = vec![PxReader::RecursivePixel::default(); block.index.pixel_size.width()]; // TODO allocate once in self
 
         for (y_offset, line_bytes) in byte_lines.enumerate() { // TODO sampling
             // this two-step copy method should be very cache friendly in theory, and also reduce sample_type lookup count
-            self.pixel_reader.read_pixels(line_bytes, &mut pixels, |px| px);
+            self.pixel_reader.read_pixels(line_bytes, &mut pixels, |px| px)?;
+
+            let position = block.index.pixel_position + Vec2(0, y_offset);
+            self.set_pixel.set_line(&mut self.pixel_storage, position, pixels.iter().map(|pixel| pixel.into_tuple()));
+        }
+
+        Ok(())
+    }
+
+    fn into_channels(self) -> Self::Channels {
+        SpecificChannels { channels: self.pixel_reader.get_descriptions().into_non_recursive(), pixels: self.pixel_storage }
+    }
+}
+
+
+/// Specifies to read the channels declared by the fields of a pixel struct.
+/// Created with `struct_channels` on the read builder.
+/// Call `collect_pixels` to define how the resulting pixel structs should be stored.
+#[derive(Clone, Debug)]
+pub struct ReadStructChannels<ReadChannels, Pixel> {
+    read_channels: ReadChannels,
+    px: PhantomData<Pixel>,
+}
+
+impl<Pixel: ExrPixel> Default for ReadStructChannels<Pixel::ReadChannels, Pixel> {
+
+    /// Read the channels declared by the fields of the pixel struct.
+    fn default() -> Self {
+        ReadStructChannels { read_channels: Pixel::read_channels(), px: PhantomData::default() }
+    }
+}
+
+impl<Pixel: ExrPixel> ReadStructChannels<Pixel::ReadChannels, Pixel> {
+
+    /// Using two closures, define how to store the pixels.
+    /// The first closure creates an image, and the second closure inserts a single pixel struct.
+    pub fn collect_pixels<PixelStorage, CreatePixels, SetPixel>(
+        self, create_pixels: CreatePixels, set_pixel: SetPixel
+    ) -> CollectPixels<
+        Pixel::ReadChannels, Pixel::Tuple, PixelStorage, CreatePixels,
+        impl Fn(&mut PixelStorage, Vec2<usize>, Pixel::Tuple)
+    >
+        where
+            <<Pixel::ReadChannels as ReadSpecificChannel>::RecursivePixelReader as RecursivePixelReader>::RecursivePixel: IntoTuple<Pixel::Tuple>,
+            <<Pixel::ReadChannels as ReadSpecificChannel>::RecursivePixelReader as RecursivePixelReader>::RecursiveChannelDescriptions: IntoNonRecursive,
+            CreatePixels: Fn(
+                Vec2<usize>,
+                &<<<Pixel::ReadChannels as ReadSpecificChannel>::RecursivePixelReader as RecursivePixelReader>::RecursiveChannelDescriptions as IntoNonRecursive>::NonRecursive
+            ) -> PixelStorage,
+            SetPixel: Fn(&mut PixelStorage, Vec2<usize>, Pixel),
+    {
+        self.read_channels.collect_pixels(
+            create_pixels,
+            move |pixels: &mut PixelStorage, position: Vec2<usize>, samples: Pixel::Tuple|
+                set_pixel(pixels, position, Pixel::from_tuple(samples))
+        )
+    }
+
+    /// Like `collect_pixels`, but the first closure receives the header of the layer instead of only its size.
+    pub fn collect_pixels_with_header<PixelStorage, CreatePixels, SetPixel>(
+        self, create_pixels: CreatePixels, set_pixel: SetPixel
+    ) -> CollectPixels<
+        Pixel::ReadChannels, Pixel::Tuple, PixelStorage, CreatePixelsWithHeader<CreatePixels>,
+        impl Fn(&mut PixelStorage, Vec2<usize>, Pixel::Tuple)
+    >
+        where
+            <<Pixel::ReadChannels as ReadSpecificChannel>::RecursivePixelReader as RecursivePixelReader>::RecursivePixel: IntoTuple<Pixel::Tuple>,
+            <<Pixel::ReadChannels as ReadSpecificChannel>::RecursivePixelReader as RecursivePixelReader>::RecursiveChannelDescriptions: IntoNonRecursive,
+            CreatePixels: Fn(
+                &Header,
+                &<<<Pixel::ReadChannels as ReadSpecificChannel>::RecursivePixelReader as RecursivePixelReader>::RecursiveChannelDescriptions as IntoNonRecursive>::NonRecursive
+            ) -> PixelStorage,
+            SetPixel: Fn(&mut PixelStorage, Vec2<usize>, Pixel),
+    {
+        self.read_channels.collect_pixels_with_header(
+            create_pixels,
+            move |pixels: &mut PixelStorage, position: Vec2<usize>, samples: Pixel::Tuple|
+                set_pixel(pixels, position, Pixel::from_tuple(samples))
+        )
+    }
+}
+
+
+/// Specifies to read a fixed number of channels, all converted to the same sample type,
+/// into an array for each pixel. Created with `specific_channel_array` on the read builder.
+/// Call `collect_pixels` to define how the resulting `[Sample; N]` pixels should be stored.
+#[derive(Clone, Debug)]
+pub struct ReadChannelArray<Sample, const N: usize> {
+    channel_names: [Text; N],
+    px: PhantomData<Sample>,
+}
+
+impl<Sample, const N: usize> ReadChannelArray<Sample, N> {
+
+    /// Plan to read the channels with the specified names.
+    /// If any of the channels cannot be found in the image when the image is read, the layer will not be loaded.
+    /// Panics if a name appears more than once.
+    pub fn new(channel_names: [Text; N]) -> Self {
+        for (index, name) in channel_names.iter().enumerate() {
+            assert!(channel_names[.. index].contains(name).not(), "a channel with the name `{}` is already defined", name);
+        }
+
+        Self { channel_names, px: PhantomData::default() }
+    }
+
+    /// Using two closures, define how to store the pixels.
+    /// The first closure creates an image, and the second closure inserts a single pixel.
+    /// The pixel is an array containing one sample for each channel,
+    /// in the order that the channel names were specified.
+    /// The sample type can be `f16`, `f32`, `u32` or `Sample`.
+    pub fn collect_pixels<PixelStorage, CreatePixels, SetPixel>(
+        self, create_pixels: CreatePixels, set_pixel: SetPixel
+    ) -> CollectPixels<Self, [Sample; N], PixelStorage, CreatePixels, SetPixel>
+        where
+            Sample: FromNativeSample,
+            CreatePixels: Fn(Vec2<usize>, &[ChannelDescription; N]) -> PixelStorage,
+            SetPixel: Fn(&mut PixelStorage, Vec2<usize>, [Sample; N]),
+    {
+        CollectPixels { read_channels: self, set_pixel, create_pixels, px: Default::default() }
+    }
+
+    /// Like `collect_pixels`, but the first closure receives the header of the layer instead of only its size.
+    pub fn collect_pixels_with_header<PixelStorage, CreatePixels, SetPixel>(
+        self, create_pixels: CreatePixels, set_pixel: SetPixel
+    ) -> CollectPixels<Self, [Sample; N], PixelStorage, CreatePixelsWithHeader<CreatePixels>, SetPixel>
+        where
+            Sample: FromNativeSample,
+            CreatePixels: Fn(&Header, &[ChannelDescription; N]) -> PixelStorage,
+            SetPixel: Fn(&mut PixelStorage, Vec2<usize>, [Sample; N]),
+    {
+        CollectPixels { read_channels: self, set_pixel, create_pixels: CreatePixelsWithHeader(create_pixels), px: Default::default() }
+    }
+}
+
+impl<'s, Sample, PixelStorage, CreatePixels, SetPixel: 's, const N: usize>
+ReadChannels<'s> for CollectPixels<ReadChannelArray<Sample, N>, [Sample; N], PixelStorage, CreatePixels, SetPixel>
+    where
+        Sample: FromNativeSample,
+        CreatePixels: CreatePixelStorage<[ChannelDescription; N], PixelStorage>,
+        SetPixel: Fn(&mut PixelStorage, Vec2<usize>, [Sample; N]),
+{
+    type Reader = ChannelArrayReader<PixelStorage, &'s SetPixel, Sample, N>;
+
+    fn create_channels_reader(&'s self, header: &Header) -> Result<Self::Reader> {
+        if header.deep { return Err(Error::invalid("`SpecificChannels` does not support deep data yet")) }
+
+        let sample_readers = channel_array_readers(&self.read_channels.channel_names, header)?;
+        let channel_descriptions = sample_readers.clone().map(|reader| reader.channel);
+        let pixel_storage = self.create_pixels.create_pixel_storage(header, &channel_descriptions);
+
+        Ok(ChannelArrayReader {
+            set_pixel: &self.set_pixel,
+            pixel_storage,
+            sample_readers,
+        })
+    }
+}
+
+/// The reader that holds the temporary data that is required to read an array of channels.
+#[derive(Clone, Debug)]
+pub struct ChannelArrayReader<PixelStorage, SetPixel, Sample, const N: usize> {
+    set_pixel: SetPixel,
+    pixel_storage: PixelStorage,
+    sample_readers: [SampleReader<Sample>; N],
+}
+
+impl<PixelStorage, SetPixel, Sample, const N: usize>
+ChannelsReader for ChannelArrayReader<PixelStorage, SetPixel, Sample, N>
+    where Sample: FromNativeSample, SetPixel: Fn(&mut PixelStorage, Vec2<usize>, [Sample; N]),
+{
+    type Channels = SpecificChannels<PixelStorage, [ChannelDescription; N]>;
+
+    fn filter_block(&self, tile: TileCoordinates) -> bool { tile.is_largest_resolution_level() } // TODO all levels
+
+    fn reads_channel(&self, channel: &ChannelDescription) -> bool {
+        self.sample_readers.iter().any(|reader| reader.channel.name == channel.name)
+    }
+
+    fn read_block(&mut self, header: &Header, block: UncompressedBlock) -> UnitResult {
+        let (set_pixel, pixel_storage) = (&self.set_pixel, &mut self.pixel_storage);
+        read_array_block(&self.sample_readers, header, &block, |position, pixel| set_pixel(pixel_storage, position, pixel))
+    }
+
+    fn into_channels(self) -> Self::Channels {
+        SpecificChannels { channels: self.sample_readers.map(|reader| reader.channel), pixels: self.pixel_storage }
+    }
+}
+
+/// Find the channel with each of the names, or return an error if any of the channels is missing.
+fn channel_array_readers<Sample, const N: usize>(channel_names: &[Text; N], header: &Header) -> Result<[SampleReader<Sample>; N]> {
+    let sample_readers: Vec<SampleReader<Sample>> = channel_names.iter()
+        .map(|name| header.channels.channels_with_byte_offset()
+            .find(|(_, channel)| &channel.name == name)
+            .map(|(channel_byte_offset, channel)| SampleReader {
+                channel_byte_offset, channel: channel.clone(), px: Default::default()
+            })
+            .ok_or_else(|| Error::invalid(format!(
+                "layer does not contain all of your specified channels (`{}` is missing)", name
+            )))
+        )
+        .collect::<Result<_>>()?;
+
+    if sample_readers.iter().any(|reader| reader.channel.sampling != Vec2(1, 1)) {
+        return Err(Error::unsupported("reading subsampled channels into an array"));
+    }
+
+    Ok(sample_readers.try_into().unwrap_or_else(|_| unreachable!("one reader per channel name")))
+}
+
+/// Read one array of samples for each pixel in the block.
+fn read_array_block<Sample: FromNativeSample, const N: usize>(
+    sample_readers: &[SampleReader<Sample>; N], header: &Header, block: &UncompressedBlock,
+    mut set_pixel: impl FnMut(Vec2<usize>, [Sample; N])
+) -> UnitResult {
+    let mut pixels = vec![[Sample::default(); N]; block.index.pixel_size.width()]; // TODO allocate once in self
+    let byte_lines = byte_lines(header, block)?;
+
+    for (y_offset, line_bytes) in byte_lines.enumerate() { // TODO sampling
+        for (channel_index, sample_reader) in sample_readers.iter().enumerate() {
+            sample_reader.read_own_samples(line_bytes, &mut pixels, |pixel| &mut pixel[channel_index])?;
+        }
+
+        for (x_offset, pixel) in pixels.iter().enumerate() {
+            set_pixel(block.index.pixel_position + Vec2(x_offset, y_offset), *pixel);
+        }
+    }
+
+    Ok(())
+}
+
+
+/// Specifies to read the three channels of a vector, such as the positions `P.x`, `P.y`, `P.z`
+/// or the normals `N.x`, `N.y`, `N.z`, into one `Vec3<f32>` for each pixel.
+/// Created with `vector_channels` on the read builder.
+/// The channel names are the base name followed by one of the suffix patterns.
+/// The first pattern that matches all three channels of a layer is used.
+/// Call `collect_pixels` to define how the resulting pixels should be stored.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ReadVectorChannels {
+    base_name: Text,
+    suffix_patterns: Vec<[Text; 3]>,
+}
+
+impl ReadVectorChannels {
+
+    /// The suffixes that are tried if no other suffixes are specified, in this order.
+    pub const DEFAULT_SUFFIXES: [[&'static str; 3]; 4] = [
+        [ ".x", ".y", ".z" ],
+        [ ".X", ".Y", ".Z" ],
+        [ ".r", ".g", ".b" ],
+        [ ".R", ".G", ".B" ],
+    ];
+
+    /// Plan to read the vector channels with the specified base name, for example `P` or `N`,
+    /// trying each of the `DEFAULT_SUFFIXES`.
+    pub fn new(base_name: impl Into<Text>) -> Self {
+        Self {
+            base_name: base_name.into(),
+            suffix_patterns: Self::DEFAULT_SUFFIXES.iter()
+                .map(|suffixes| suffixes.map(Text::from)).collect(),
+        }
+    }
+
+    /// Only try the specified suffix patterns, in this order, instead of the `DEFAULT_SUFFIXES`.
+    /// For example, use `[["_X", "_Y", "_Z"]]` to read the channels `P_X`, `P_Y`, `P_Z`.
+    /// Panics if a pattern contains a suffix more than once, or if no pattern is specified.
+    pub fn with_suffixes(self, suffix_patterns: impl IntoIterator<Item=[impl Into<Text>; 3]>) -> Self {
+        let suffix_patterns: Vec<[Text; 3]> = suffix_patterns.into_iter()
+            .map(|suffixes| suffixes.map(Into::into)).collect();
+
+        assert!(suffix_patterns.is_empty().not(), "at least one suffix pattern is required");
+
+        for [x, y, z] in &suffix_patterns {
+            assert!(x != y && y != z && x != z, "the suffixes `{}`, `{}`, `{}` are not unique", x, y, z);
+        }
+
+        Self { suffix_patterns, .. self }
+    }
+
+    /// The names of the three channels in the list that make up the vector,
+    /// using the first suffix pattern that matches all three channels.
+    pub fn find_channel_names(&self, channels: &ChannelList) -> Option<[Text; 3]> {
+        self.suffix_patterns.iter()
+            .map(|suffixes| suffixes.clone().map(|suffix| {
+                let name: TextBytes = self.base_name.as_slice().iter().chain(suffix.as_slice()).copied().collect();
+                Text::from_bytes_unchecked(name)
+            }))
+            .find(|names| names.iter().all(|name| channels.list.iter().any(|channel| &channel.name == name)))
+    }
+
+    /// Using two closures, define how to store the pixels.
+    /// The first closure creates an image, and the second closure inserts a single pixel.
+    /// The channel descriptions are ordered by the coordinate of the vector.
+    pub fn collect_pixels<PixelStorage, CreatePixels, SetPixel>(
+        self, create_pixels: CreatePixels, set_pixel: SetPixel
+    ) -> CollectPixels<Self, Vec3<f32>, PixelStorage, CreatePixels, SetPixel>
+        where
+            CreatePixels: Fn(Vec2<usize>, &[ChannelDescription; 3]) -> PixelStorage,
+            SetPixel: Fn(&mut PixelStorage, Vec2<usize>, Vec3<f32>),
+    {
+        CollectPixels { read_channels: self, set_pixel, create_pixels, px: Default::default() }
+    }
+}
+
+impl<'s, PixelStorage, CreatePixels, SetPixel: 's>
+ReadChannels<'s> for CollectPixels<ReadVectorChannels, Vec3<f32>, PixelStorage, CreatePixels, SetPixel>
+    where
+        CreatePixels: CreatePixelStorage<[ChannelDescription; 3], PixelStorage>,
+        SetPixel: Fn(&mut PixelStorage, Vec2<usize>, Vec3<f32>),
+{
+    type Reader = VectorChannelsReader<PixelStorage, &'s SetPixel>;
+
+    fn create_channels_reader(&'s self, header: &Header) -> Result<Self::Reader> {
+        if header.deep { return Err(Error::invalid("`SpecificChannels` does not support deep data yet")) }
+
+        let channel_names = self.read_channels.find_channel_names(&header.channels)
+            .ok_or_else(|| Error::invalid(format!(
+                "layer does not contain the vector channels `{}`", self.read_channels.base_name
+            )))?;
+
+        let sample_readers = channel_array_readers(&channel_names, header)?;
+        let channel_descriptions = sample_readers.clone().map(|reader| reader.channel);
+        let pixel_storage = self.create_pixels.create_pixel_storage(header, &channel_descriptions);
+
+        Ok(VectorChannelsReader {
+            set_pixel: &self.set_pixel,
+            pixel_storage,
+            sample_readers,
+        })
+    }
+}
+
+/// The reader that holds the temporary data that is required to read vector channels.
+#[derive(Clone, Debug)]
+pub struct VectorChannelsReader<PixelStorage, SetPixel> {
+    set_pixel: SetPixel,
+    pixel_storage: PixelStorage,
+    sample_readers: [SampleReader<f32>; 3],
+}
+
+impl<PixelStorage, SetPixel> ChannelsReader for VectorChannelsReader<PixelStorage, SetPixel>
+    where SetPixel: Fn(&mut PixelStorage, Vec2<usize>, Vec3<f32>),
+{
+    type Channels = SpecificChannels<PixelStorage, [ChannelDescription; 3]>;
+
+    fn filter_block(&self, tile: TileCoordinates) -> bool { tile.is_largest_resolution_level() } // TODO all levels
+
+    fn reads_channel(&self, channel: &ChannelDescription) -> bool {
+        self.sample_readers.iter().any(|reader| reader.channel.name == channel.name)
+    }
+
+    fn read_block(&mut self, header: &Header, block: UncompressedBlock) -> UnitResult {
+        let (set_pixel, pixel_storage) = (&self.set_pixel, &mut self.pixel_storage);
+        read_array_block(&self.sample_readers, header, &block, |position, pixel| set_pixel(pixel_storage, position, Vec3::from(pixel)))
+    }
+
+    fn into_channels(self) -> Self::Channels {
+        SpecificChannels { channels: self.sample_readers.map(|reader| reader.channel), pixels: self.pixel_storage }
+    }
+}
+
+
+/// Specifies to read a pair of motion vector channels, such as `velocity.x` and `velocity.y`,
+/// into one `Vec2<f32>` for each pixel. Created with `motion_vector_channels` on the read builder.
+/// The first pair of names that is contained in a layer is used.
+/// Call `collect_pixels` to define how the resulting pixels should be stored.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ReadMotionVectors {
+    names: Vec<MotionVectorNames>,
+    space: MotionSpace,
+}
+
+impl Default for ReadMotionVectors {
+    fn default() -> Self {
+        Self { names: MotionVectorNames::defaults(), space: MotionSpace::Pixels }
+    }
+}
+
+impl ReadMotionVectors {
+
+    /// Only try the specified channel names, in this order, instead of `MotionVectorNames::defaults()`.
+    /// Panics if no names are specified.
+    pub fn with_names(self, names: impl IntoIterator<Item=MotionVectorNames>) -> Self {
+        let names: Vec<MotionVectorNames> = names.into_iter().collect();
+        assert!(!names.is_empty(), "at least one pair of channel names is required");
+        Self { names, .. self }
+    }
+
+    /// Convert the vectors from pixels to the specified space while reading,
+    /// using the size of the display window of the image.
+    pub fn in_space(self, space: MotionSpace) -> Self {
+        Self { space, .. self }
+    }
+
+    /// Using two closures, define how to store the pixels.
+    /// The first closure creates an image, and the second closure inserts a single pixel.
+    /// The channel descriptions are ordered horizontal first.
+    pub fn collect_pixels<PixelStorage, CreatePixels, SetPixel>(
+        self, create_pixels: CreatePixels, set_pixel: SetPixel
+    ) -> CollectPixels<Self, Vec2<f32>, PixelStorage, CreatePixels, SetPixel>
+        where
+            CreatePixels: Fn(Vec2<usize>, &[ChannelDescription; 2]) -> PixelStorage,
+            SetPixel: Fn(&mut PixelStorage, Vec2<usize>, Vec2<f32>),
+    {
+        CollectPixels { read_channels: self, set_pixel, create_pixels, px: Default::default() }
+    }
+}
+
+impl<'s, PixelStorage, CreatePixels, SetPixel: 's>
+ReadChannels<'s> for CollectPixels<ReadMotionVectors, Vec2<f32>, PixelStorage, CreatePixels, SetPixel>
+    where
+        CreatePixels: CreatePixelStorage<[ChannelDescription; 2], PixelStorage>,
+        SetPixel: Fn(&mut PixelStorage, Vec2<usize>, Vec2<f32>),
+{
+    type Reader = MotionVectorsReader<PixelStorage, &'s SetPixel>;
+
+    fn create_channels_reader(&'s self, header: &Header) -> Result<Self::Reader> {
+        if header.deep { return Err(Error::invalid("`SpecificChannels` does not support deep data yet")) }
+
+        let names = self.read_channels.names.iter()
+            .find(|names| names.is_contained_in(&header.channels))
+            .ok_or_else(|| Error::invalid("layer does not contain motion vector channels"))?;
+
+        let sample_readers = channel_array_readers(&[ names.x.clone(), names.y.clone() ], header)?;
+        let channel_descriptions = sample_readers.clone().map(|reader| reader.channel);
+        let pixel_storage = self.create_pixels.create_pixel_storage(header, &channel_descriptions);
+
+        Ok(MotionVectorsReader {
+            set_pixel: &self.set_pixel,
+            pixel_storage,
+            sample_readers,
+            space: self.read_channels.space,
+            display_size: header.shared_attributes.display_window.size,
+        })
+    }
+}
+
+/// The reader that holds the temporary data that is required to read motion vector channels.
+#[derive(Clone, Debug)]
+pub struct MotionVectorsReader<PixelStorage, SetPixel> {
+    set_pixel: SetPixel,
+    pixel_storage: PixelStorage,
+    sample_readers: [SampleReader<f32>; 2],
+    space: MotionSpace,
+    display_size: Vec2<usize>,
+}
+
+impl<PixelStorage, SetPixel> ChannelsReader for MotionVectorsReader<PixelStorage, SetPixel>
+    where SetPixel: Fn(&mut PixelStorage, Vec2<usize>, Vec2<f32>),
+{
+    type Channels = SpecificChannels<PixelStorage, [ChannelDescription; 2]>;
+
+    fn filter_block(&self, tile: TileCoordinates) -> bool { tile.is_largest_resolution_level() } // TODO all levels
+
+    fn reads_channel(&self, channel: &ChannelDescription) -> bool {
+        self.sample_readers.iter().any(|reader| reader.channel.name == channel.name)
+    }
+
+    fn read_block(&mut self, header: &Header, block: UncompressedBlock) -> UnitResult {
+        let (set_pixel, pixel_storage) = (&self.set_pixel, &mut self.pixel_storage);
+        let (space, display_size) = (self.space, self.display_size);
+
+        read_array_block(&self.sample_readers, header, &block, |position, [x, y]| {
+            let vector = MotionSpace::Pixels.convert(Vec2(x, y), space, display_size);
+            set_pixel(pixel_storage, position, vector)
+        })
+    }
+
+    fn into_channels(self) -> Self::Channels {
+        SpecificChannels { channels: self.sample_readers.map(|reader| reader.channel), pixels: self.pixel_storage }
+    }
+}
+
+
+/// Specifies to read a list of channels that is only known at runtime, for example from user input.
+/// Created with `dynamic_channels` on the read builder.
+/// Each pixel will be a list with one optional sample per specified channel,
+/// where the sample is `None` if the layer does not contain that channel.
+/// All samples are converted to the same sample type, which can be `f16`, `f32`, `u32` or `Sample`.
+/// Call `collect_pixels` to define how the resulting pixels should be stored.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ReadDynamicChannels<Sample> {
+    channel_names: Vec<Text>,
+    px: PhantomData<Sample>,
+}
+
+/// A pixel of dynamically selected channels.
+/// Contains one sample per requested channel, or `None` if the channel is not in the layer.
+pub type DynamicPixel<Sample = crate::block::samples::Sample> = SmallVec<[Option<Sample>; 8]>;
+
+/// The channel descriptions of dynamically selected channels.
+/// Contains one description per requested channel, or `None` if the channel is not in the layer.
+pub type DynamicChannelDescriptions = SmallVec<[Option<ChannelDescription>; 8]>;
+
+impl<Sample> ReadDynamicChannels<Sample> {
+
+    /// Plan to read the channels with the specified names.
+    /// Channels that are not contained in a layer will be `None` in each pixel.
+    /// Duplicate names are allowed and will yield the same samples twice.
+    pub fn new(channel_names: Vec<Text>) -> Self {
+        Self { channel_names, px: PhantomData::default() }
+    }
+
+    /// Using two closures, define how to store the pixels.
+    /// The first closure creates an image, and the second closure inserts a single pixel.
+    /// The pixel contains one optional sample for each channel,
+    /// in the order that the channel names were specified.
+    /// The sample type can usually be inferred from the second closure.
+    pub fn collect_pixels<PixelStorage, CreatePixels, SetPixel>(
+        self, create_pixels: CreatePixels, set_pixel: SetPixel
+    ) -> CollectPixels<Self, DynamicPixel<Sample>, PixelStorage, CreatePixels, SetPixel>
+        where
+            Sample: FromNativeSample,
+            CreatePixels: Fn(Vec2<usize>, &DynamicChannelDescriptions) -> PixelStorage,
+            SetPixel: Fn(&mut PixelStorage, Vec2<usize>, DynamicPixel<Sample>),
+    {
+        CollectPixels { read_channels: self, set_pixel, create_pixels, px: Default::default() }
+    }
+
+    /// Like `collect_pixels`, but the first closure receives the header of the layer instead of only its size.
+    pub fn collect_pixels_with_header<PixelStorage, CreatePixels, SetPixel>(
+        self, create_pixels: CreatePixels, set_pixel: SetPixel
+    ) -> CollectPixels<Self, DynamicPixel<Sample>, PixelStorage, CreatePixelsWithHeader<CreatePixels>, SetPixel>
+        where
+            Sample: FromNativeSample,
+            CreatePixels: Fn(&Header, &DynamicChannelDescriptions) -> PixelStorage,
+            SetPixel: Fn(&mut PixelStorage, Vec2<usize>, DynamicPixel<Sample>),
+    {
+        CollectPixels { read_channels: self, set_pixel, create_pixels: CreatePixelsWithHeader(create_pixels), px: Default::default() }
+    }
+}
+
+impl<'s, Sample, PixelStorage, CreatePixels, SetPixel: 's>
+ReadChannels<'s> for CollectPixels<ReadDynamicChannels<Sample>, DynamicPixel<Sample>, PixelStorage, CreatePixels, SetPixel>
+    where
+        Sample: FromNativeSample,
+        CreatePixels: CreatePixelStorage<DynamicChannelDescriptions, PixelStorage>,
+        SetPixel: Fn(&mut PixelStorage, Vec2<usize>, DynamicPixel<Sample>),
+{
+    type Reader = DynamicChannelsReader<PixelStorage, &'s SetPixel, Sample>;
+
+    fn create_channels_reader(&'s self, header: &Header) -> Result<Self::Reader> {
+        if header.deep { return Err(Error::invalid("`SpecificChannels` does not support deep data yet")) }
+
+        let sample_readers: SmallVec<[Option<SampleReader<Sample>>; 8]> = self.read_channels.channel_names.iter()
+            .map(|name| header.channels.channels_with_byte_offset()
+                .find(|(_, channel)| &channel.name == name)
+                .map(|(channel_byte_offset, channel)| SampleReader {
+                    channel_byte_offset, channel: channel.clone(), px: Default::default()
+                })
+            )
+            .collect();
+
+        let channel_descriptions: DynamicChannelDescriptions = sample_readers.iter()
+            .map(|reader| reader.as_ref().map(|reader| reader.channel.clone()))
+            .collect();
+
+        let pixel_storage = self.create_pixels.create_pixel_storage(header, &channel_descriptions);
+
+        Ok(DynamicChannelsReader {
+            set_pixel: &self.set_pixel,
+            pixel_storage,
+            sample_readers,
+            channel_descriptions,
+        })
+    }
+}
+
+/// The reader that holds the temporary data that is required to read a runtime selection of channels.
+#[derive(Clone, Debug)]
+pub struct DynamicChannelsReader<PixelStorage, SetPixel, Sample> {
+    set_pixel: SetPixel,
+    pixel_storage: PixelStorage,
+    sample_readers: SmallVec<[Option<SampleReader<Sample>>; 8]>,
+    channel_descriptions: DynamicChannelDescriptions,
+}
+
+impl<PixelStorage, SetPixel, Sample> ChannelsReader for DynamicChannelsReader<PixelStorage, SetPixel, Sample>
+    where Sample: FromNativeSample, SetPixel: Fn(&mut PixelStorage, Vec2<usize>, DynamicPixel<Sample>),
+{
+    type Channels = SpecificChannels<PixelStorage, DynamicChannelDescriptions>;
+
+    fn filter_block(&self, tile: TileCoordinates) -> bool { tile.is_largest_resolution_level() } // TODO all levels
+
+    fn reads_channel(&self, channel: &ChannelDescription) -> bool {
+        self.sample_readers.iter().flatten().any(|reader| reader.channel.name == channel.name)
+    }
+
+    fn read_block(&mut self, header: &Header, block: UncompressedBlock) -> UnitResult {
+        let empty_pixel: DynamicPixel<Sample> = self.sample_readers.iter()
+            .map(|reader| reader.as_ref().map(|_| Sample::default()))
+            .collect();
+
+        let mut pixels = vec![empty_pixel; block.index.pixel_size.width()]; // TODO allocate once in self
+        let byte_lines = byte_lines(header, &block)?;
+
+        for (y_offset, line_bytes) in byte_lines.enumerate() { // TODO sampling
+            for (channel_index, sample_reader) in self.sample_readers.iter().enumerate() {
+                if let Some(sample_reader) = sample_reader {
+                    sample_reader.read_own_samples(
+                        line_bytes, &mut pixels,
+                        |pixel| pixel[channel_index].get_or_insert_with(Sample::default)
+                    )?;
+                }
+            }
 
             for (x_offset, pixel) in pixels.iter().enumerate() {
                 let set_pixel = &self.set_pixel;
-                set_pixel(&mut self.pixel_storage, block.index.pixel_position + Vec2(x_offset, y_offset), pixel.into_tuple());
+                set_pixel(&mut self.pixel_storage, block.index.pixel_position + Vec2(x_offset, y_offset), pixel.clone());
             }
         }
 
@@ -203,7 +1021,7 @@ ChannelsReader for SpecificChannelsReader<PixelStorage, SetPixel, PxReader, Pixe
     }
 
     fn into_channels(self) -> Self::Channels {
-        SpecificChannels { channels: self.pixel_reader.get_descriptions().into_non_recursive(), pixels: self.pixel_storage }
+        SpecificChannels { channels: self.channel_descriptions, pixels: self.pixel_storage }
     }
 }
 
@@ -217,6 +1035,28 @@ impl ReadSpecificChannel for NoneMore {
     fn create_recursive_reader(&self, _: &ChannelList) -> Result<Self::RecursivePixelReader> { Ok(NoneMore) }
 }
 
+impl<ReadChannels: ReadSpecificChannel> ReadSpecificChannel for ReadChannelsWithinLayer<ReadChannels> {
+    type RecursivePixelReader = ReadChannels::RecursivePixelReader;
+
+    fn create_recursive_reader(&self, channels: &ChannelList) -> Result<Self::RecursivePixelReader> {
+        let mut prefix = self.layer_name.as_slice().to_vec();
+        prefix.push(b'.');
+
+        // channels of other layers are renamed to an empty name instead of being removed,
+        // such that the byte offsets of all channels stay the same
+        let channels_within_layer = channels.list.iter()
+            .map(|channel| ChannelDescription {
+                name: channel.name.as_slice().strip_prefix(prefix.as_slice())
+                    .map(Text::from_slice_unchecked).unwrap_or_default(),
+
+                .. channel.clone()
+            })
+            .collect();
+
+        self.channels.create_recursive_reader(&ChannelList::new(channels_within_layer))
+    }
+}
+
 impl<DefaultSample, ReadChannels> ReadSpecificChannel for ReadOptionalChannel<ReadChannels, DefaultSample>
     where ReadChannels: ReadSpecificChannel, DefaultSample: FromNativeSample + 'static,
 {
@@ -226,8 +1066,7 @@ impl<DefaultSample, ReadChannels> ReadSpecificChannel for ReadOptionalChannel<Re
         debug_assert!(self.previous_channels.already_contains(&self.channel_name).not(), "duplicate channel name: {}", self.channel_name);
 
         let inner_samples_reader = self.previous_channels.create_recursive_reader(channels)?;
-        let reader = channels.channels_with_byte_offset()
-            .find(|(_, channel)| channel.name == self.channel_name)
+        let reader = self.matching.find_channel(&self.channel_name, channels)
             .map(|(channel_byte_offset, channel)| SampleReader {
                 channel_byte_offset, channel: channel.clone(),
                 px: Default::default()
@@ -246,8 +1085,7 @@ impl<Sample, ReadChannels> ReadSpecificChannel for ReadRequiredChannel<ReadChann
 
     fn create_recursive_reader(&self, channels: &ChannelList) -> Result<Self::RecursivePixelReader> {
         let previous_samples_reader = self.previous_channels.create_recursive_reader(channels)?;
-        let (channel_byte_offset, channel) = channels.channels_with_byte_offset()
-                .find(|(_, channel)| channel.name == self.channel_name)
+        let (channel_byte_offset, channel) = self.matching.find_channel(&self.channel_name, channels)
                 .ok_or_else(|| Error::invalid(format!(
                     "layer does not contain all of your specified channels (`{}` is missing)",
                     self.channel_name
@@ -277,33 +1115,57 @@ pub struct OptionalSampleReader<DefaultSample> {
 }
 
 impl<Sample: FromNativeSample> SampleReader<Sample> {
+
+    /// Read the samples of this channel from a line of pixels.
+    /// The line contains the samples of all channels, one channel after another.
     fn read_own_samples<'s, FullPixel>(
         &self, bytes: &'s[u8], pixels: &mut [FullPixel],
         get_pixel: impl Fn(&mut FullPixel) -> &mut Sample
-    ){
+    ) -> UnitResult {
+        let bytes_per_sample = self.channel.sample_type.bytes_per_sample();
         let start_index = pixels.len() * self.channel_byte_offset;
-        let byte_count = pixels.len() * self.channel.sample_type.bytes_per_sample();
-        let mut own_bytes_reader = &bytes[start_index .. start_index + byte_count]; // TODO check block size somewhere
+        let end_index = start_index + pixels.len() * bytes_per_sample;
 
-        let error_msg = "error when reading from in-memory slice";
+        let own_bytes = bytes.get(start_index .. end_index)
+            .ok_or_else(|| Error::invalid("line does not contain all samples of the channel"))?;
 
-        // match outside the loop to avoid matching on every single sample
+        // match outside the loop to avoid matching on every single sample,
+        // and to obtain one loop for each sample type, with the sample size known at compile time
         match self.channel.sample_type {
-            SampleType::F16 => for pixel in pixels.iter_mut() {
-                *get_pixel(pixel) = Sample::from_f16(f16::read(&mut own_bytes_reader).expect(error_msg));
-            },
+            SampleType::F16 => read_typed_samples(own_bytes, pixels, get_pixel, |bytes| Sample::from_f16(f16::from_le_bytes(bytes))),
+            SampleType::F32 => read_typed_samples(own_bytes, pixels, get_pixel, |bytes| Sample::from_f32(f32::from_le_bytes(bytes))),
+            SampleType::U32 => read_typed_samples(own_bytes, pixels, get_pixel, |bytes| Sample::from_u32(u32::from_le_bytes(bytes))),
+        }
 
-            SampleType::F32 => for pixel in pixels.iter_mut() {
-                *get_pixel(pixel) = Sample::from_f32(f32::read(&mut own_bytes_reader).expect(error_msg));
-            },
+        Ok(())
+    }
+}
 
-            SampleType::U32 => for pixel in pixels.iter_mut() {
-                *get_pixel(pixel) = Sample::from_u32(u32::read(&mut own_bytes_reader).expect(error_msg));
-            },
-        }
+/// Decode one sample per pixel from the bytes of a single channel.
+/// As the byte size of a sample is a constant, the compiler can remove all bounds checks inside this loop.
+#[inline]
+fn read_typed_samples<FullPixel, Sample, const BYTES: usize>(
+    own_bytes: &[u8], pixels: &mut [FullPixel],
+    get_pixel: impl Fn(&mut FullPixel) -> &mut Sample,
+    decode: impl Fn([u8; BYTES]) -> Sample
+) {
+    // the sub-slice contains exactly one sample per pixel
+    for (pixel, sample_bytes) in pixels.iter_mut().zip(own_bytes.chunks_exact(BYTES)) {
+        let sample_bytes: [u8; BYTES] = sample_bytes.try_into().expect("chunk size is constant");
+        *get_pixel(pixel) = decode(sample_bytes);
+    }
+}
+
+/// Split the block into lines of pixels, containing the samples of all channels.
+/// Returns an error if the number of bytes does not match the size of the block.
+fn byte_lines<'b>(header: &Header, block: &'b UncompressedBlock) -> Result<std::slice::ChunksExact<'b, u8>> {
+    let line_byte_size = header.channels.bytes_per_pixel * block.index.pixel_size.width();
 
-        debug_assert!(own_bytes_reader.is_empty(), "bytes left after reading all samples");
+    if line_byte_size == 0 || block.data.len() != line_byte_size * block.index.pixel_size.height() {
+        return Err(Error::invalid("block size does not match the channels and pixel size"));
     }
+
+    Ok(block.data.chunks_exact(line_byte_size))
 }
 
 
@@ -316,7 +1178,10 @@ impl RecursivePixelReader for NoneMore {
     fn read_pixels<'s, FullPixel>(
         &self, _: &'s[u8], _: &mut [FullPixel],
         _: impl Fn(&mut FullPixel) -> &mut NoneMore
-    ){}
+    ) -> UnitResult { Ok(()) }
+
+    fn reads_any_channel(&self) -> bool { false }
+    fn reads_channel(&self, _: &Text) -> bool { false }
 }
 
 impl<Sample, InnerReader: RecursivePixelReader>
@@ -332,9 +1197,15 @@ impl<Sample, InnerReader: RecursivePixelReader>
     fn read_pixels<'s, FullPixel>(
         &self, bytes: &'s[u8], pixels: &mut [FullPixel],
         get_pixel: impl Fn(&mut FullPixel) -> &mut Self::RecursivePixel
-    ) {
-        self.value.read_own_samples(bytes, pixels, |px| &mut get_pixel(px).value);
-        self.inner.read_pixels(bytes, pixels, |px| &mut get_pixel(px).inner);
+    ) -> UnitResult {
+        self.value.read_own_samples(bytes, pixels, |px| &mut get_pixel(px).value)?;
+        self.inner.read_pixels(bytes, pixels, |px| &mut get_pixel(px).inner)
+    }
+
+    fn reads_any_channel(&self) -> bool { true }
+
+    fn reads_channel(&self, name: &Text) -> bool {
+        &self.value.channel.name == name || self.inner.reads_channel(name)
     }
 }
 
@@ -353,9 +1224,9 @@ for Recursive<InnerReader, OptionalSampleReader<Sample>>
     fn read_pixels<'s, FullPixel>(
         &self, bytes: &'s[u8], pixels: &mut [FullPixel],
         get_pixel: impl Fn(&mut FullPixel) -> &mut Self::RecursivePixel
-    ) {
+    ) -> UnitResult {
         if let Some(reader) = &self.value.reader {
-            reader.read_own_samples(bytes, pixels, |px| &mut get_pixel(px).value);
+            reader.read_own_samples(bytes, pixels, |px| &mut get_pixel(px).value)?;
         }
         else {
             // if this channel is optional and was not found in the file, fill the default sample
@@ -364,8 +1235,208 @@ for Recursive<InnerReader, OptionalSampleReader<Sample>>
             }
         }
 
-        self.inner.read_pixels(bytes, pixels, |px| &mut get_pixel(px).inner);
+        self.inner.read_pixels(bytes, pixels, |px| &mut get_pixel(px).inner)
+    }
+
+    fn reads_any_channel(&self) -> bool {
+        self.value.reader.is_some() || self.inner.reads_any_channel()
+    }
+
+    fn reads_channel(&self, name: &Text) -> bool {
+        self.value.reader.as_ref().map_or(false, |reader| &reader.channel.name == name)
+            || self.inner.reads_channel(name)
     }
 }
 
 
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::image::pixel_vec::PixelVec;
+    use crate::block::BlockIndex;
+    use smallvec::smallvec;
+
+    #[test]
+    fn block_with_missing_bytes_is_an_error() {
+        let header = Header::builder()
+            .layer_size((4, 2))
+            .channel(ChannelDescription::named("R", SampleType::F32))
+            .channel(ChannelDescription::named("G", SampleType::F16))
+            .build().unwrap();
+
+        let read_channels = ReadZeroChannels::default()
+            .required("R").required("G")
+            .collect_pixels(PixelVec::<(f32, f32)>::constructor, PixelVec::set_pixel);
+
+        let block = |byte_count: usize| UncompressedBlock {
+            index: BlockIndex { layer: 0, pixel_position: Vec2(0, 0), pixel_size: Vec2(4, 2), level: Vec2(0, 0) },
+            data: (0 .. byte_count).map(|index| index as u8).collect(),
+        };
+
+        let mut reader = read_channels.create_channels_reader(&header).unwrap();
+        assert!(reader.read_block(&header, block(4 * 2 * 6)).is_ok());
+        assert!(reader.read_block(&header, block(4 * 2 * 6 - 1)).is_err());
+        assert!(reader.read_block(&header, block(4 * 6)).is_err());
+        assert!(reader.read_block(&header, block(4 * 2 * 6 + 6)).is_err());
+    }
+
+    #[test]
+    fn channel_array_rejects_subsampled_channels() {
+        // the header validation rejects subsampling, so the header is not built with the checked builder
+        let header = Header::new(Text::from("subsampled"), Vec2(4, 2), smallvec![
+            ChannelDescription::named("R", SampleType::F32),
+            ChannelDescription { sampling: Vec2(2, 2), .. ChannelDescription::named("G", SampleType::F32) },
+        ]);
+
+        let read_channels = |names: [Text; 1]| ReadChannelArray::<f32, 1>::new(names)
+            .collect_pixels(PixelVec::<[f32; 1]>::constructor, PixelVec::set_pixel);
+
+        assert!(read_channels([ Text::from("R") ]).create_channels_reader(&header).is_ok());
+
+        match read_channels([ Text::from("G") ]).create_channels_reader(&header) {
+            Err(Error::NotSupported(_)) => {},
+            other => panic!("unexpected result {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn skip_blocks_without_any_channel() {
+        let header = Header::builder()
+            .layer_size((3, 2))
+            .channel(ChannelDescription::named("beauty.R", SampleType::F16))
+            .channel(ChannelDescription::named("depth.Z", SampleType::F32))
+            .build().unwrap();
+
+        let tile = TileCoordinates { tile_index: Vec2(0, 0), level_index: Vec2(0, 0) };
+
+        let missing = ReadZeroChannels::default()
+            .optional("Z", 7.0_f32).optional("A", 1.0_f32)
+            .collect_pixels(PixelVec::<(f32, f32)>::constructor, PixelVec::set_pixel);
+
+        let reader = missing.create_channels_reader(&header).unwrap();
+        assert!(!reader.filter_block(tile), "no block needs to be decompressed");
+        assert_eq!(reader.into_channels().pixels.pixels, vec![ (7.0, 1.0); 6 ]);
+
+        let present = ReadZeroChannels::default()
+            .optional("Z", 7.0_f32).within_layer("depth")
+            .collect_pixels(PixelVec::<(f32,)>::constructor, PixelVec::set_pixel);
+
+        assert!(present.create_channels_reader(&header).unwrap().filter_block(tile));
+    }
+
+    #[test]
+    fn read_vector_channels() {
+        let header = Header::builder()
+            .layer_size((2, 1))
+            .channel(ChannelDescription::named("N.X", SampleType::F16))
+            .channel(ChannelDescription::named("N.Y", SampleType::F32))
+            .channel(ChannelDescription::named("N.Z", SampleType::F32))
+            .channel(ChannelDescription::named("P.x", SampleType::F32))
+            .build().unwrap();
+
+        let missing = ReadVectorChannels::new("P")
+            .collect_pixels(PixelVec::<Vec3<f32>>::constructor, PixelVec::set_pixel);
+
+        assert!(missing.create_channels_reader(&header).is_err());
+
+        let normals = ReadVectorChannels::new("N")
+            .collect_pixels(PixelVec::<Vec3<f32>>::constructor, PixelVec::set_pixel);
+
+        // the samples of a line are stored channel by channel, the first channel contains f16 samples
+        let mut data: Vec<u8> = [ f16::ONE, f16::ZERO ].iter().flat_map(|sample| sample.to_le_bytes()).collect();
+        data.extend([ 2.0_f32, 3.0, 4.0, 5.0, 6.0, 7.0 ].iter().flat_map(|sample| sample.to_le_bytes()));
+
+        let block = UncompressedBlock {
+            index: BlockIndex { layer: 0, pixel_position: Vec2(0, 0), pixel_size: Vec2(2, 1), level: Vec2(0, 0) },
+            data,
+        };
+
+        let mut reader = normals.create_channels_reader(&header).unwrap();
+        reader.read_block(&header, block).unwrap();
+
+        let channels = reader.into_channels();
+        assert_eq!(channels.channels[0].name, Text::from("N.X"));
+        assert_eq!(channels.pixels.pixels, vec![ Vec3(1.0, 2.0, 4.0), Vec3(0.0, 3.0, 5.0) ]);
+
+        let custom = ReadVectorChannels::new("P").with_suffixes([ [ ".x", ".y", ".z" ], [ ".X", ".Y", ".Z" ] ]);
+        assert_eq!(custom.find_channel_names(&header.channels), None);
+        assert_eq!(ReadVectorChannels::new("N").find_channel_names(&header.channels).unwrap()[2], Text::from("N.Z"));
+    }
+
+    #[test]
+    fn vector_channels_reject_deep_data_and_subsampling() {
+        // the header validation rejects subsampling, so the header is not built with the checked builder
+        let header = Header::new(Text::from("subsampled"), Vec2(4, 2), smallvec![
+            ChannelDescription::named("N.x", SampleType::F32),
+            ChannelDescription { sampling: Vec2(2, 2), .. ChannelDescription::named("N.y", SampleType::F32) },
+            ChannelDescription::named("N.z", SampleType::F32),
+            ChannelDescription::named("P.x", SampleType::F32),
+            ChannelDescription::named("P.y", SampleType::F32),
+            ChannelDescription::named("P.z", SampleType::F32),
+        ]);
+
+        let read_channels = |base_name: &str| ReadVectorChannels::new(base_name)
+            .collect_pixels(PixelVec::<Vec3<f32>>::constructor, PixelVec::set_pixel);
+
+        assert!(read_channels("P").create_channels_reader(&header).is_ok());
+
+        match read_channels("N").create_channels_reader(&header) {
+            Err(Error::NotSupported(_)) => {},
+            other => panic!("unexpected result {:?}", other.map(|_| ())),
+        }
+
+        let deep = Header { deep: true, .. header };
+        assert!(read_channels("P").create_channels_reader(&deep).is_err());
+    }
+
+    #[test]
+    fn motion_vectors_reject_subsampling() {
+        // the header validation rejects subsampling, so the header is not built with the checked builder
+        let header = Header::new(Text::from("subsampled"), Vec2(4, 2), smallvec![
+            ChannelDescription::named("forward.u", SampleType::F32),
+            ChannelDescription { sampling: Vec2(2, 1), .. ChannelDescription::named("forward.v", SampleType::F32) },
+            ChannelDescription::named("velocity.x", SampleType::F32),
+            ChannelDescription::named("velocity.y", SampleType::F32),
+        ]);
+
+        let read_motion = |names: MotionVectorNames| ReadMotionVectors::default().with_names([ names ])
+            .collect_pixels(PixelVec::<Vec2<f32>>::constructor, PixelVec::set_pixel);
+
+        assert!(read_motion(MotionVectorNames::velocity()).create_channels_reader(&header).is_ok());
+
+        match read_motion(MotionVectorNames::forward()).create_channels_reader(&header) {
+            Err(Error::NotSupported(_)) => {},
+            other => panic!("unexpected result {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn read_motion_vectors_in_normalized_device_coordinates() {
+        let header = Header::builder()
+            .layer_size((2, 1))
+            .channel(ChannelDescription::named("forward.u", SampleType::F32))
+            .channel(ChannelDescription::named("forward.v", SampleType::F32))
+            .build().unwrap();
+
+        let missing = ReadMotionVectors::default().with_names([ MotionVectorNames::velocity() ])
+            .collect_pixels(PixelVec::<Vec2<f32>>::constructor, PixelVec::set_pixel);
+
+        assert!(missing.create_channels_reader(&header).is_err());
+
+        let motion = ReadMotionVectors::default().in_space(MotionSpace::NormalizedDevice)
+            .collect_pixels(PixelVec::<Vec2<f32>>::constructor, PixelVec::set_pixel);
+
+        let block = UncompressedBlock {
+            index: BlockIndex { layer: 0, pixel_position: Vec2(0, 0), pixel_size: Vec2(2, 1), level: Vec2(0, 0) },
+            data: [ 1.0_f32, -2.0, 0.5, 0.0 ].iter().flat_map(|sample| sample.to_le_bytes()).collect(),
+        };
+
+        let mut reader = motion.create_channels_reader(&header).unwrap();
+        reader.read_block(&header, block).unwrap();
+
+        let channels = reader.into_channels();
+        assert_eq!(channels.channels[1].name, Text::from("forward.v"));
+        assert_eq!(channels.pixels.pixels, vec![ Vec2(1.0, -1.0), Vec2(-2.0, 0.0) ]);
+    }
+}
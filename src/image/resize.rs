@@ -0,0 +1,264 @@
+//! Resample a pixel storage to a different resolution.
+//! Useful for generating proxies and thumbnails directly from loaded pixels.
+//!
+//! The samples in an exr file are usually stored in linear light,
+//! so the filters are applied to the sample values directly, without any gamma conversion.
+
+use crate::image::pixel_vec::PixelVec;
+use crate::block::samples::{FromNativeSample, IntoNativeSample};
+use crate::math::Vec2;
+use std::f32::consts::PI;
+
+
+/// How to compute the value of a pixel from the surrounding pixels when resizing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Filter {
+
+    /// The average of all covered pixels. Fast, but blocky when enlarging an image.
+    Box,
+
+    /// Linear interpolation between neighbouring pixels (a triangle filter when shrinking an image).
+    Bilinear,
+
+    /// A windowed sinc with three lobes. Sharp, but can produce ringing near hard edges.
+    Lanczos3,
+}
+
+impl Filter {
+
+    /// How many source pixels, in each direction, are considered for a single target pixel when not shrinking.
+    fn radius(self) -> f32 {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Bilinear => 1.0,
+            Filter::Lanczos3 => 3.0,
+        }
+    }
+
+    /// The weight of a source pixel at the specified distance to the target pixel.
+    fn weight(self, distance: f32) -> f32 {
+        match self {
+            Filter::Box => if distance > -0.5 && distance <= 0.5 { 1.0 } else { 0.0 },
+            Filter::Bilinear => (1.0 - distance.abs()).max(0.0),
+            Filter::Lanczos3 => {
+                if distance.abs() >= 3.0 { 0.0 }
+                else { sinc(distance) * sinc(distance / 3.0) }
+            },
+        }
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x == 0.0 { 1.0 }
+    else { (PI * x).sin() / (PI * x) }
+}
+
+
+/// A pixel that can be resized. Implemented for single samples and tuples of up to eight samples.
+/// The samples can be `f16`, `f32`, `u32` or `Sample`.
+pub trait ResizePixel: Clone {
+
+    /// How many samples this pixel contains.
+    const SAMPLE_COUNT: usize;
+
+    /// Write the samples of this pixel into the slice, which has the length `SAMPLE_COUNT`.
+    fn write_f32_samples(&self, samples: &mut [f32]);
+
+    /// Create a pixel from the slice, which has the length `SAMPLE_COUNT`.
+    /// Integer samples are rounded to the nearest integer.
+    fn from_f32_samples(samples: &[f32]) -> Self;
+}
+
+/// Convert a filtered value to a sample.
+/// Rounds instead of truncating if the sample is an integer,
+/// so that filtering does not darken integer channels.
+fn sample_from_f32<Sample>(value: f32) -> Sample where Sample: IntoNativeSample + FromNativeSample {
+    let is_integer = Sample::from_f32(0.5).to_f32() == 0.0;
+
+    if is_integer { Sample::from_f32((value + 0.5).max(0.0)) } // the conversion clamps values that are too large
+    else { Sample::from_f32(value) }
+}
+
+impl<Sample> ResizePixel for Sample where Sample: IntoNativeSample + FromNativeSample {
+    const SAMPLE_COUNT: usize = 1;
+    fn write_f32_samples(&self, samples: &mut [f32]) { samples[0] = self.to_f32(); }
+    fn from_f32_samples(samples: &[f32]) -> Self { sample_from_f32(samples[0]) }
+}
+
+macro_rules! impl_resize_pixel_for_tuple {
+    ( $count: expr; $( $Sample: ident $index: tt ),* ) => {
+        impl<$( $Sample ),*> ResizePixel for ( $( $Sample, )* )
+            where $( $Sample: IntoNativeSample + FromNativeSample ),*
+        {
+            const SAMPLE_COUNT: usize = $count;
+
+            fn write_f32_samples(&self, samples: &mut [f32]) {
+                $( samples[$index] = self.$index.to_f32(); )*
+            }
+
+            fn from_f32_samples(samples: &[f32]) -> Self {
+                ( $( sample_from_f32::<$Sample>(samples[$index]), )* )
+            }
+        }
+    };
+}
+
+impl_resize_pixel_for_tuple!(1; A 0);
+impl_resize_pixel_for_tuple!(2; A 0, B 1);
+impl_resize_pixel_for_tuple!(3; A 0, B 1, C 2);
+impl_resize_pixel_for_tuple!(4; A 0, B 1, C 2, D 3);
+impl_resize_pixel_for_tuple!(5; A 0, B 1, C 2, D 3, E 4);
+impl_resize_pixel_for_tuple!(6; A 0, B 1, C 2, D 3, E 4, F 5);
+impl_resize_pixel_for_tuple!(7; A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+impl_resize_pixel_for_tuple!(8; A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+
+
+impl<Pixel> PixelVec<Pixel> where Pixel: ResizePixel {
+
+    /// Create a copy of this image with a different resolution, using the specified filter.
+    /// Both horizontal and vertical directions are resampled independently.
+    /// Integer samples will be rounded to the nearest integer, and negative values will be clamped to zero.
+    pub fn resized(&self, new_size: impl Into<Vec2<usize>>, filter: Filter) -> Self {
+        let new_size = new_size.into();
+        let channels = Pixel::SAMPLE_COUNT;
+
+        if new_size.area() == 0 || self.resolution.area() == 0 {
+            let pixels = vec![Pixel::from_f32_samples(&vec![0.0; channels]); new_size.area()];
+            return PixelVec { resolution: new_size, pixels };
+        }
+
+        let mut samples = vec![0.0; self.pixels.len() * channels];
+        for (pixel, pixel_samples) in self.pixels.iter().zip(samples.chunks_exact_mut(channels)) {
+            pixel.write_f32_samples(pixel_samples);
+        }
+
+        // resize horizontally, then vertically, where the stride is the distance between two neighbours
+        let horizontal_lines = self.resolution.height();
+        let samples = resample_axis(
+            &samples, horizontal_lines, channels,
+            &compute_weights(self.resolution.width(), new_size.width(), filter),
+            |line, index| (line * self.resolution.width() + index) * channels,
+            |line, index| (line * new_size.width() + index) * channels,
+        );
+
+        let vertical_lines = new_size.width();
+        let samples = resample_axis(
+            &samples, vertical_lines, channels,
+            &compute_weights(self.resolution.height(), new_size.height(), filter),
+            |line, index| (index * new_size.width() + line) * channels,
+            |line, index| (index * new_size.width() + line) * channels,
+        );
+
+        PixelVec {
+            resolution: new_size,
+            pixels: samples.chunks_exact(channels).map(Pixel::from_f32_samples).collect(),
+        }
+    }
+}
+
+/// Resample multiple parallel lines of pixels, where each line is resized to one pixel per entry in `weights`.
+/// The index closures compute the index of the first sample of a pixel in a line.
+fn resample_axis(
+    source: &[f32], line_count: usize, channels: usize,
+    weights: &[(usize, Vec<f32>)],
+    source_index: impl Fn(usize, usize) -> usize,
+    target_index: impl Fn(usize, usize) -> usize,
+) -> Vec<f32>
+{
+    let mut target = vec![0.0; line_count * weights.len() * channels];
+
+    for line in 0 .. line_count {
+        for (target_pixel, (first_source_pixel, pixel_weights)) in weights.iter().enumerate() {
+            let target_start = target_index(line, target_pixel);
+            let target_samples = &mut target[target_start .. target_start + channels];
+
+            for (source_pixel, &weight) in (*first_source_pixel ..).zip(pixel_weights) {
+                let source_start = source_index(line, source_pixel);
+                let source_samples = &source[source_start .. source_start + channels];
+
+                for (target_sample, &source_sample) in target_samples.iter_mut().zip(source_samples) {
+                    *target_sample += source_sample * weight;
+                }
+            }
+        }
+    }
+
+    target
+}
+
+/// For each target pixel, computes the first source pixel and the weights of all contributing source pixels.
+fn compute_weights(source_length: usize, target_length: usize, filter: Filter) -> Vec<(usize, Vec<f32>)> {
+    let scale = source_length as f32 / target_length as f32;
+
+    // when shrinking, widen the filter to cover all source pixels
+    let filter_scale = scale.max(1.0);
+    let support = filter.radius() * filter_scale;
+
+    (0 .. target_length).map(|target_pixel| {
+        let center = (target_pixel as f32 + 0.5) * scale;
+        let start = ((center - support).floor().max(0.0) as usize).min(source_length - 1);
+        let end = ((center + support).ceil() as usize).clamp(start + 1, source_length);
+
+        let mut weights: Vec<f32> = (start .. end)
+            .map(|source_pixel| filter.weight((source_pixel as f32 + 0.5 - center) / filter_scale))
+            .collect();
+
+        let sum: f32 = weights.iter().sum();
+
+        if sum == 0.0 {
+            // the filter did not hit any pixel center, so use the nearest pixel
+            let nearest = (center as usize).min(source_length - 1);
+            (nearest, vec![1.0])
+        }
+        else {
+            for weight in &mut weights { *weight /= sum; }
+            (start, weights)
+        }
+    }).collect()
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::image::pixel_vec::PixelVec;
+    use crate::image::resize::Filter;
+    use crate::prelude::f16;
+
+    #[test]
+    fn constant_color_stays_constant() {
+        let image = PixelVec::new((7, 5), vec![(0.5_f32, f16::from_f32(2.0), 8_u32); 7 * 5]);
+
+        for &filter in &[ Filter::Box, Filter::Bilinear, Filter::Lanczos3 ] {
+            for &size in &[ (3, 2), (7, 5), (16, 11), (1, 1) ] {
+                let resized = image.resized(size, filter);
+                assert_eq!(resized.resolution.area(), size.0 * size.1);
+
+                for &(a, b, c) in &resized.pixels {
+                    assert!((a - 0.5).abs() < 0.0001, "{:?} {:?}: {}", filter, size, a);
+                    assert!((b.to_f32() - 2.0).abs() < 0.01, "{:?} {:?}: {}", filter, size, b);
+                    assert_eq!(c, 8, "{:?} {:?}", filter, size);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn box_filter_averages() {
+        let image = PixelVec::new((4, 2), vec![
+            0.0_f32, 2.0, 4.0, 8.0,
+            2.0, 4.0, 6.0, 10.0,
+        ]);
+
+        let resized = image.resized((2, 1), Filter::Box);
+        assert_eq!(resized.pixels, vec![ 2.0, 7.0 ]);
+
+        let resized = image.resized((8, 4), Filter::Box);
+        assert_eq!(resized.pixels[.. 8], [ 0.0, 0.0, 2.0, 2.0, 4.0, 4.0, 8.0, 8.0 ]);
+    }
+
+    #[test]
+    fn integer_samples_are_rounded() {
+        let image = PixelVec::new((4, 1), vec![ 1_u32, 2, 6, 6 ]);
+        assert_eq!(image.resized((2, 1), Filter::Box).pixels, vec![ 2, 6 ]);
+    }
+}
@@ -0,0 +1,127 @@
+//! A common interface for pixel storages, such as `PixelVec`, `TiledPixelVec`, or your own types.
+//!
+//! Any type that implements `PixelStorage` can be filled by the reader, using `collect_storage`,
+//! and can be written to a file, because every storage also implements `GetPixel`.
+//! Swapping the storage, for example to a staging buffer that is uploaded to the gpu,
+//! therefore only requires changing the type of the image.
+//!
+//! ```no_run
+//! use exr::prelude::*;
+//! use exr::image::pixel_vec::PixelVec;
+//!
+//! let image = read().no_deep_data().largest_resolution_level()
+//!     .specific_channels().required("R").required("G").required("B")
+//!     .collect_storage::<PixelVec<(f32, f32, f32)>>()
+//!     .first_valid_layer().all_attributes()
+//!     .from_file("image.exr").unwrap();
+//!
+//! image.write().to_file("copy.exr").unwrap();
+//! ```
+
+use crate::image::write::channels::GetPixel;
+use crate::image::pixel_vec::{PixelVec, TiledPixelVec};
+use crate::meta::header::Header;
+use crate::math::Vec2;
+
+
+/// A storage for the pixels of a single layer, which can be read and written line by line.
+/// Examining pixels is defined by the `GetPixel` trait, which also allows writing the storage to a file.
+pub trait PixelStorage: GetPixel + Sized {
+
+    /// Create a storage for a layer with the specified resolution, filled with default pixels.
+    fn create(resolution: Vec2<usize>) -> Self;
+
+    /// Create a storage for the layer described by the header, filled with default pixels.
+    /// Uses the size of the layer by default. Storages that depend on the block layout override this.
+    fn create_for_header(header: &Header) -> Self {
+        Self::create(header.layer_size)
+    }
+
+    /// The resolution of the layer.
+    fn resolution(&self) -> Vec2<usize>;
+
+    /// Update a single pixel.
+    fn set_pixel(&mut self, position: Vec2<usize>, pixel: Self::Pixel);
+
+    /// Update a horizontal line of pixels, starting at the specified position.
+    /// Storages that store the pixels of a line next to each other can override this to copy the whole line at once.
+    fn set_line(&mut self, position: Vec2<usize>, pixels: impl Iterator<Item = Self::Pixel>) {
+        for (x, pixel) in pixels.enumerate() {
+            self.set_pixel(position + Vec2(x, 0), pixel);
+        }
+    }
+
+    /// Examine a horizontal line of pixels, starting at the specified position.
+    /// The length of the slice defines the number of pixels.
+    fn get_line(&self, position: Vec2<usize>, pixels: &mut [Self::Pixel]) {
+        for (x, pixel) in pixels.iter_mut().enumerate() {
+            *pixel = self.get_pixel(position + Vec2(x, 0));
+        }
+    }
+}
+
+impl<Pixel> PixelStorage for PixelVec<Pixel> where Pixel: Default + Clone + Sync {
+    fn create(resolution: Vec2<usize>) -> Self {
+        PixelVec { resolution, pixels: vec![Pixel::default(); resolution.area()] }
+    }
+
+    fn resolution(&self) -> Vec2<usize> { self.resolution }
+
+    fn set_pixel(&mut self, position: Vec2<usize>, pixel: Pixel) {
+        PixelVec::set_pixel(self, position, pixel)
+    }
+
+    fn set_line(&mut self, position: Vec2<usize>, pixels: impl Iterator<Item = Pixel>) {
+        let start = self.compute_pixel_index(position);
+        for (target, pixel) in self.pixels[start ..].iter_mut().zip(pixels) {
+            *target = pixel;
+        }
+    }
+
+    fn get_line(&self, position: Vec2<usize>, pixels: &mut [Pixel]) {
+        let start = self.compute_pixel_index(position);
+        pixels.clone_from_slice(&self.pixels[start .. start + pixels.len()]);
+    }
+}
+
+impl<Pixel> PixelStorage for TiledPixelVec<Pixel> where Pixel: Default + Clone + Sync {
+    fn create(resolution: Vec2<usize>) -> Self {
+        Self::with_tile_size(resolution, resolution.max(Vec2(1, 1)))
+    }
+
+    fn create_for_header(header: &Header) -> Self {
+        Self::with_tile_size(header.layer_size, header.max_block_pixel_size())
+    }
+
+    fn resolution(&self) -> Vec2<usize> { self.resolution }
+
+    fn set_pixel(&mut self, position: Vec2<usize>, pixel: Pixel) {
+        TiledPixelVec::set_pixel(self, position, pixel)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lines_of_storages() {
+        fn fill_and_check<Storage: PixelStorage<Pixel = (f32, u32)>>(mut storage: Storage) {
+            let resolution = storage.resolution();
+
+            for y in 0 .. resolution.height() {
+                storage.set_line(Vec2(0, y), (0 .. resolution.width()).map(|x| (x as f32, y as u32)));
+            }
+
+            let mut line = vec![ (0.0, 0); 3 ];
+            storage.get_line(Vec2(2, 4), &mut line);
+            assert_eq!(line, vec![ (2.0, 4), (3.0, 4), (4.0, 4) ]);
+            assert_eq!(storage.get_pixel(Vec2(6, 1)), (6.0, 1));
+        }
+
+        fill_and_check(PixelVec::create(Vec2(7, 5)));
+        fill_and_check(TiledPixelVec::create(Vec2(7, 5)));
+        fill_and_check(TiledPixelVec::with_tile_size(Vec2(7, 5), Vec2(2, 3)));
+    }
+}
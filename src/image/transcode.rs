@@ -0,0 +1,408 @@
+//! Change how the pixels of an image are divided into blocks, without changing the pixels or the attributes.
+//! Some applications read scan line images faster, while others require tiles,
+//! for example to load only the visible part of a large texture.
+//!
+//! ```no_run
+//! use exr::prelude::*;
+//! use exr::image::transcode;
+//!
+//! transcode::repack_file("scan_lines.exr", "tiles.exr", Blocks::Tiles(Vec2(64, 64)))
+//!     .expect("image could not be converted");
+//! ```
+//!
+//! This module also contains other lossless or simple conversions of whole images,
+//! like changing the compression, converting 32-bit floats to 16-bit floats,
+//! extracting a single layer, or cropping all layers to the display window.
+//! The `exrconvert` command line tool exposes these conversions, see the `cli` feature.
+//!
+//! To edit the attributes or single channels of an image that is still needed afterwards,
+//! use `borrow_samples`, which does not copy the samples of the original image.
+
+use crate::image::*;
+use crate::image::read::{read, read_all_data_from_file};
+use crate::image::read::layers::ReadChannels;
+use crate::image::read::image::ReadLayers;
+use crate::image::write::WritableImage;
+use crate::error::{Result, UnitResult};
+use crate::meta::attribute::{LevelMode, IntegerBounds};
+use crate::image::crop::{Crop, ApplyCroppedView};
+use crate::compression::Compression;
+use half::f16;
+use std::path::Path;
+use std::io::{Read, Seek, Write};
+use std::borrow::Cow;
+
+
+/// Change the blocks of all layers in the image. The pixels and the attributes are not changed.
+/// If the image is converted to scan lines, an unspecified line order is replaced by increasing line order,
+/// because scan line images require a specified line order.
+///
+/// Returns an error when converting a layer with multiple resolution levels to scan lines,
+/// or when converting a layer with subsampled channels to tiles, as these combinations are not supported by the file format.
+pub fn repack_blocks(mut image: AnyImage, blocks: Blocks) -> Result<AnyImage> {
+    for layer in image.layer_data.iter_mut() {
+        repack_layer(layer, blocks)?;
+    }
+
+    Ok(image)
+}
+
+/// Change the blocks of a single layer. The pixels and the attributes are not changed.
+/// See `repack_blocks` for more details.
+pub fn repack_layer(layer: &mut Layer<AnyChannels<Levels<FlatSamples>>>, blocks: Blocks) -> UnitResult {
+    match blocks {
+        Blocks::ScanLines => {
+            let has_levels = layer.channel_data.list.iter()
+                .any(|channel| channel.sample_data.level_mode() != LevelMode::Singular);
+
+            if has_levels {
+                return Err(Error::invalid("resolution levels require tiles"));
+            }
+
+            if layer.encoding.line_order == LineOrder::Unspecified {
+                layer.encoding.line_order = LineOrder::Increasing;
+            }
+        },
+
+        Blocks::Tiles(tile_size) => {
+            if tile_size.area() == 0 {
+                return Err(Error::invalid("tile size"));
+            }
+
+            if layer.channel_data.list.iter().any(|channel| channel.sampling != Vec2(1, 1)) {
+                return Err(Error::invalid("subsampled channels require scan lines"));
+            }
+        },
+    }
+
+    layer.encoding.blocks = blocks;
+    Ok(())
+}
+
+/// Read all layers of the source file, change the blocks of all layers, and write the result to the destination file.
+/// Does not support deep data. See `repack_blocks` for more details.
+pub fn repack_file(source: impl AsRef<Path>, destination: impl AsRef<Path>, blocks: Blocks) -> UnitResult {
+    let image = read_all_data_from_file(source)?;
+    repack_blocks(image, blocks)?.write().to_file(destination)
+}
+
+/// Read all layers of the source, change the blocks of all layers, and write the result to the destination.
+/// Does not support deep data. See `repack_blocks` for more details.
+pub fn repack_buffered(source: impl Read + Seek, destination: impl Write + Seek, blocks: Blocks) -> UnitResult {
+    let image = read().no_deep_data().all_resolution_levels().all_channels().all_layers().all_attributes()
+        .from_buffered(source)?;
+
+    repack_blocks(image, blocks)?.write().to_buffered(destination)
+}
+
+/// Change the compression of all layers. The pixels and the attributes are not changed,
+/// unless the new compression is lossy.
+pub fn recompress(mut image: AnyImage, compression: Compression) -> AnyImage {
+    for layer in image.layer_data.iter_mut() {
+        layer.encoding.compression = compression;
+    }
+
+    image
+}
+
+/// Convert the samples of all 32-bit float channels to 16-bit floats, in all resolution levels.
+/// Other channels are not changed. Values outside the 16-bit float range become infinite.
+pub fn convert_f32_to_f16(mut image: AnyImage) -> AnyImage {
+    for layer in image.layer_data.iter_mut() {
+        for channel in layer.channel_data.list.iter_mut() {
+            for samples in channel.sample_data.levels_as_slice_mut() {
+                if let FlatSamples::F32(values) = samples {
+                    *samples = FlatSamples::F16(values.iter().map(|&value| f16::from_f32(value)).collect());
+                }
+            }
+        }
+    }
+
+    image
+}
+
+/// Remove all layers except the one with the specified name.
+/// Returns an error if no layer has this name.
+pub fn extract_layer(mut image: AnyImage, layer_name: &str) -> Result<AnyImage> {
+    let index = image.layer_data.iter()
+        .position(|layer| layer.attributes.layer_name.as_ref().map_or(false, |name| name.eq(layer_name)))
+        .ok_or(Error::invalid("layer name not found"))?;
+
+    let layer = image.layer_data.swap_remove(index);
+    image.layer_data.clear();
+    image.layer_data.push(layer);
+    Ok(image)
+}
+
+/// Crop all layers such that they only contain pixels inside the display window of the image.
+/// Layers that are completely outside the display window are removed.
+///
+/// Returns an error if no layer remains, or when cropping a layer with multiple resolution levels or subsampled channels,
+/// as these are not supported yet.
+pub fn crop_to_display_window(mut image: AnyImage) -> Result<AnyImage> {
+    let display_window = image.attributes.display_window;
+    let mut layers = Layers::new();
+
+    for layer in image.layer_data.drain(..) {
+        if let Some(bounds) = layer.absolute_bounds().intersection(display_window) {
+            layers.push(crop_layer(layer, bounds)?);
+        }
+    }
+
+    if layers.is_empty() {
+        return Err(Error::invalid("all layers are outside of the display window"));
+    }
+
+    image.layer_data = layers;
+    Ok(image)
+}
+
+/// Remove all pixels of the layer outside the specified absolute bounds.
+/// The bounds must be inside the layer. See `crop_to_display_window` for more details.
+pub fn crop_layer(layer: Layer<AnyChannels<Levels<FlatSamples>>>, bounds: IntegerBounds) -> Result<Layer<AnyChannels<Levels<FlatSamples>>>> {
+    if !layer.absolute_bounds().contains(bounds) || bounds.size.area() == 0 {
+        return Err(Error::invalid("crop bounds"));
+    }
+
+    if layer.absolute_bounds() == bounds {
+        return Ok(layer);
+    }
+
+    if layer.channel_data.list.iter().any(|channel| channel.sampling != Vec2(1, 1)) {
+        return Err(Error::unsupported("cropping subsampled channels"));
+    }
+
+    let Layer { channel_data, attributes, encoding, size } = layer;
+    let mut flat_channels = SmallVec::with_capacity(channel_data.list.len());
+
+    for channel in channel_data.list {
+        let samples = match channel.sample_data {
+            Levels::Singular(samples) => samples,
+            _ => return Err(Error::unsupported("cropping resolution levels")),
+        };
+
+        flat_channels.push(AnyChannel {
+            name: channel.name, sample_data: samples,
+            quantize_linearly: channel.quantize_linearly, sampling: channel.sampling,
+        });
+    }
+
+    let flat_layer = Layer { channel_data: AnyChannels { list: flat_channels }, attributes, encoding, size };
+    let cropped = flat_layer.crop(bounds).reallocate_cropped();
+
+    Ok(Layer {
+        channel_data: AnyChannels {
+            list: cropped.channel_data.list.into_iter().map(|channel| AnyChannel {
+                name: channel.name, sample_data: Levels::Singular(channel.sample_data),
+                quantize_linearly: channel.quantize_linearly, sampling: channel.sampling,
+            }).collect()
+        },
+
+        attributes: cropped.attributes,
+        encoding: cropped.encoding,
+        size: cropped.size,
+    })
+}
+
+/// An image that borrows the samples of an `AnyImage`, created with `borrow_samples`.
+/// The attributes and encodings can be changed, and the samples of single channels can be replaced,
+/// while all other samples are written directly from the original image.
+pub type BorrowedImage<'samples> = Image<Layers<AnyChannels<Levels<Cow<'samples, FlatSamples>>>>>;
+
+/// Create an image with the same attributes, which borrows all samples of the original image.
+/// Only clones the attributes and channel descriptions. See `BorrowedImage`.
+pub fn borrow_samples(image: &AnyImage) -> BorrowedImage<'_> {
+    let layers = image.layer_data.iter().map(|layer| Layer {
+        channel_data: AnyChannels {
+            list: layer.channel_data.list.iter().map(|channel| AnyChannel {
+                name: channel.name.clone(), sample_data: borrow_levels(&channel.sample_data),
+                quantize_linearly: channel.quantize_linearly, sampling: channel.sampling,
+            }).collect()
+        },
+
+        attributes: layer.attributes.clone(),
+        encoding: layer.encoding,
+        size: layer.size,
+    });
+
+    Image { attributes: image.attributes.clone(), layer_data: layers.collect() }
+}
+
+fn borrow_levels(levels: &Levels<FlatSamples>) -> Levels<Cow<'_, FlatSamples>> {
+    match levels {
+        Levels::Singular(samples) => Levels::Singular(Cow::Borrowed(samples)),
+        Levels::Mip { rounding_mode, level_data } => Levels::Mip {
+            rounding_mode: *rounding_mode,
+            level_data: level_data.iter().map(Cow::Borrowed).collect(),
+        },
+        Levels::Rip { rounding_mode, level_data } => Levels::Rip {
+            rounding_mode: *rounding_mode,
+            level_data: RipMaps {
+                map_data: level_data.map_data.iter().map(Cow::Borrowed).collect(),
+                level_count: level_data.level_count,
+            },
+        },
+    }
+}
+
+/// Like `convert_f32_to_f16`, but for a borrowed image.
+/// Only the converted channels are allocated, all other channels remain borrowed.
+pub fn convert_borrowed_f32_to_f16(mut image: BorrowedImage<'_>) -> BorrowedImage<'_> {
+    for layer in image.layer_data.iter_mut() {
+        for channel in layer.channel_data.list.iter_mut() {
+            for samples in channel.sample_data.levels_as_slice_mut() {
+                if let FlatSamples::F32(values) = samples.as_ref() {
+                    *samples = Cow::Owned(FlatSamples::F16(values.iter().map(|&value| f16::from_f32(value)).collect()));
+                }
+            }
+        }
+    }
+
+    image
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::prelude::*;
+    use crate::image::transcode;
+    use crate::meta::BlockDescription;
+    use crate::math::RoundingMode;
+    use std::io::Cursor;
+    use std::borrow::Cow;
+
+    fn write_to_buffer(image: &AnyImage) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        image.write().non_parallel().to_buffered(Cursor::new(&mut bytes)).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn repack_scan_lines_to_tiles_and_back() {
+        let size = Vec2(37, 21);
+        let samples = FlatSamples::F32((0 .. size.area()).map(|index| index as f32).collect());
+        let channels = AnyChannels::sort(smallvec![ AnyChannel::new("Y", Levels::Singular(samples)) ]);
+        let layer = Layer::new(size, LayerAttributes::named("repack"), Encoding::SMALL_LOSSLESS, channels);
+        let image = Image::from_layers(ImageAttributes::new(IntegerBounds::from_dimensions(size)), smallvec![ layer ]);
+
+        let mut tiled = Vec::new();
+        transcode::repack_buffered(Cursor::new(write_to_buffer(&image)), Cursor::new(&mut tiled), Blocks::Tiles(Vec2(16, 8))).unwrap();
+
+        let meta = MetaData::read_from_buffered(Cursor::new(&tiled), false).unwrap();
+        assert!(matches!(meta.headers[0].blocks, BlockDescription::Tiles(tiles) if tiles.tile_size == Vec2(16, 8)));
+
+        let mut scan_lines = Vec::new();
+        transcode::repack_buffered(Cursor::new(&tiled), Cursor::new(&mut scan_lines), Blocks::ScanLines).unwrap();
+
+        let result = read_from_buffer(&scan_lines);
+        assert_eq!(result.layer_data[0].encoding.blocks, Blocks::ScanLines);
+        assert_eq!(result.layer_data[0].channel_data, image.layer_data[0].channel_data);
+        assert_eq!(result.layer_data[0].attributes, image.layer_data[0].attributes);
+    }
+
+    #[test]
+    fn resolution_levels_require_tiles() {
+        let size = Vec2(8, 8);
+        let levels = Levels::from_mip_maps(size, RoundingMode::Down, vec![
+            FlatSamples::F32(vec![ 0.0; 64 ]), FlatSamples::F32(vec![ 0.0; 16 ]),
+            FlatSamples::F32(vec![ 0.0; 4 ]), FlatSamples::F32(vec![ 0.0; 1 ]),
+        ]).unwrap();
+
+        let channels = AnyChannels::sort(smallvec![ AnyChannel::new("Y", levels) ]);
+        let mut layer = Layer::new(size, LayerAttributes::named("levels"), Encoding::for_textures(Vec2(4, 4)), channels);
+
+        assert!(transcode::repack_layer(&mut layer, Blocks::ScanLines).is_err());
+        assert!(transcode::repack_layer(&mut layer, Blocks::Tiles(Vec2(2, 2))).is_ok());
+        assert_eq!(layer.encoding.blocks, Blocks::Tiles(Vec2(2, 2)));
+    }
+
+    fn layer(name: &str, position: Vec2<i32>, size: Vec2<usize>) -> Layer<AnyChannels<Levels<FlatSamples>>> {
+        let samples = FlatSamples::F32((0 .. size.area()).map(|index| index as f32).collect());
+        let channels = AnyChannels::sort(smallvec![ AnyChannel::new("Y", Levels::Singular(samples)) ]);
+        let attributes = LayerAttributes { layer_position: position, .. LayerAttributes::named(name) };
+        Layer::new(size, attributes, Encoding::SMALL_LOSSLESS, channels)
+    }
+
+    #[test]
+    fn convert_samples_and_layers() {
+        let image = Image::from_layers(
+            ImageAttributes::new(IntegerBounds::from_dimensions((8, 8))),
+            smallvec![ layer("first", Vec2(0, 0), Vec2(8, 8)), layer("second", Vec2(0, 0), Vec2(4, 4)) ]
+        );
+
+        let image = transcode::recompress(image, Compression::PIZ);
+        let image = transcode::convert_f32_to_f16(image);
+        let image = transcode::extract_layer(image, "second").unwrap();
+
+        assert_eq!(image.layer_data.len(), 1);
+        assert_eq!(image.layer_data[0].encoding.compression, Compression::PIZ);
+        assert_eq!(image.layer_data[0].attributes.layer_name, Some(Text::from("second")));
+
+        let samples = &image.layer_data[0].channel_data.list[0].sample_data;
+        assert_eq!(samples.levels_as_slice()[0].as_f16_slice().unwrap()[5], f16::from_f32(5.0));
+
+        assert!(transcode::extract_layer(image, "third").is_err());
+    }
+
+    #[test]
+    fn edit_borrowed_samples() {
+        let mut image = Image::from_layers(
+            ImageAttributes::new(IntegerBounds::from_dimensions((8, 8))),
+            smallvec![ layer("first", Vec2(0, 0), Vec2(8, 8)) ]
+        );
+
+        let depth = FlatSamples::U32(vec![ 3; 64 ]);
+        image.layer_data[0].channel_data.list.push(AnyChannel::new("Z", Levels::Singular(depth)));
+
+        let mut borrowed = transcode::convert_borrowed_f32_to_f16(transcode::borrow_samples(&image));
+        borrowed.layer_data[0].attributes.owner = Some(Text::from("editor"));
+
+        let channels = &borrowed.layer_data[0].channel_data.list;
+        assert!(matches!(channels[0].sample_data.levels_as_slice()[0], Cow::Owned(FlatSamples::F16(_))));
+        assert!(matches!(channels[1].sample_data.levels_as_slice()[0], Cow::Borrowed(_)));
+
+        let mut bytes = Vec::new();
+        borrowed.write().non_parallel().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        let result = read_from_buffer(&bytes);
+        assert_eq!(result.layer_data[0].attributes.owner, Some(Text::from("editor")));
+        assert_eq!(result.layer_data[0].channel_data, transcode::convert_f32_to_f16(image.clone()).layer_data[0].channel_data);
+    }
+
+    #[test]
+    fn crop_layers_to_display_window() {
+        let image = Image::from_layers(
+            ImageAttributes::new(IntegerBounds::new((2, 1), (4, 4))),
+            smallvec![
+                layer("inside", Vec2(2, 1), Vec2(4, 4)),
+                layer("overlapping", Vec2(0, 0), Vec2(5, 3)),
+                layer("outside", Vec2(10, 10), Vec2(2, 2)),
+            ]
+        );
+
+        let image = transcode::crop_to_display_window(image).unwrap();
+        assert_eq!(image.layer_data.len(), 2);
+
+        let inside = &image.layer_data[0];
+        assert_eq!(inside.absolute_bounds(), IntegerBounds::new((2, 1), (4, 4)));
+
+        let overlapping = &image.layer_data[1];
+        assert_eq!(overlapping.absolute_bounds(), IntegerBounds::new((2, 1), (3, 2)));
+        assert_eq!(
+            overlapping.channel_data.list[0].sample_data.levels_as_slice()[0].to_f32_vec(),
+            vec![ 7.0, 8.0, 9.0, 12.0, 13.0, 14.0 ]
+        );
+
+        let outside = Image::from_layers(
+            ImageAttributes::new(IntegerBounds::from_dimensions((1, 1))),
+            smallvec![ layer("outside", Vec2(10, 10), Vec2(2, 2)) ]
+        );
+
+        assert!(transcode::crop_to_display_window(outside).is_err());
+    }
+
+    fn read_from_buffer(bytes: &[u8]) -> AnyImage {
+        read().no_deep_data().all_resolution_levels().all_channels().all_layers().all_attributes()
+            .non_parallel().from_buffered(Cursor::new(bytes)).unwrap()
+    }
+}
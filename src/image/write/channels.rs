@@ -8,8 +8,14 @@ use crate::block::*;
 use crate::image::recursive::*;
 use crate::block::samples::*;
 use crate::image::write::samples::*;
+use crate::image::write::dither::{Dithering, dither_f32_to_f16};
+
+use crate::block::lines::LineIndex;
 
 use std::marker::PhantomData;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ops::Range;
 
 
 /// Enables an image containing this list of channels to be written to a file.
@@ -24,8 +30,9 @@ pub trait WritableChannels<'slf> {
     /// The type of temporary writer
     type Writer: ChannelsWriter;
 
-    /// Create a temporary writer for this list of channels
-    fn create_writer(&'slf self, header: &Header) -> Self::Writer;
+    /// Create a temporary writer for this list of channels.
+    /// Returns an error if the channels of the header do not match these channels.
+    fn create_writer(&'slf self, header: &Header) -> Result<Self::Writer>;
 }
 
 /// A temporary writer for a list of channels
@@ -33,6 +40,40 @@ pub trait ChannelsWriter: Sync {
 
     /// Deliver a block of pixels, containing all channel data, to be stored in the file
     fn extract_uncompressed_block(&self, header: &Header, block: BlockIndex) -> Vec<u8>; // TODO return uncompressed block?
+
+    /// Like `extract_uncompressed_block`, but dithers the selected channels when converting `f32` samples to `f16`.
+    /// Writers that never convert samples to `f16` use this default implementation, which ignores the dithering.
+    fn extract_dithered_block(&self, header: &Header, block: BlockIndex, dithering: &Dithering) -> Vec<u8> {
+        let _ = dithering;
+        self.extract_uncompressed_block(header, block)
+    }
+}
+
+
+/// The byte ranges of the lines in a block, found by the row and the channel of each line.
+/// Subsampled channels do not contain a line in every row,
+/// so a line cannot be found by counting the lines of the block.
+#[derive(Debug, Clone)]
+pub(crate) struct BlockLines {
+    byte_ranges: HashMap<(usize, usize), Range<usize>>,
+}
+
+impl BlockLines {
+
+    /// Locate all lines of a block that contains the specified channels.
+    pub fn new(block_index: BlockIndex, channels: &ChannelList) -> Self {
+        let byte_ranges = LineIndex::lines_in_block(block_index, channels)
+            .map(|(byte_range, line)| ((line.position.y(), line.channel), byte_range))
+            .collect();
+
+        Self { byte_ranges }
+    }
+
+    /// The bytes of the line of the channel in the specified row of the block.
+    /// Panics if the channel has no line in this row.
+    pub fn line<'b>(&self, block_bytes: &'b [u8], y: usize, channel: usize) -> &'b [u8] {
+        &block_bytes[self.byte_ranges[&(y, channel)].clone()]
+    }
 }
 
 
@@ -56,6 +97,12 @@ impl<F, P> GetPixel for F where F: Sync + Fn(Vec2<usize>) -> P {
     fn get_pixel(&self, position: Vec2<usize>) -> P { self(position) }
 }
 
+// allows keeping the pixels of an image that was read, without copying them, unless they are modified
+impl<'cow, Storage> GetPixel for Cow<'cow, Storage> where Storage: Clone + GetPixel {
+    type Pixel = Storage::Pixel;
+    fn get_pixel(&self, position: Vec2<usize>) -> Self::Pixel { Storage::get_pixel(self, position) }
+}
+
 impl<'samples, Samples> WritableChannels<'samples> for AnyChannels<Samples>
     where Samples: 'samples + WritableSamples<'samples>
 {
@@ -82,12 +129,12 @@ impl<'samples, Samples> WritableChannels<'samples> for AnyChannels<Samples>
     }
 
     type Writer = AnyChannelsWriter<Samples::Writer>;
-    fn create_writer(&'samples self, header: &Header) -> Self::Writer {
+    fn create_writer(&'samples self, header: &Header) -> Result<Self::Writer> {
         let channels = self.list.iter()
             .map(|chan| chan.sample_data.create_samples_writer(header))
             .collect();
 
-        AnyChannelsWriter { channels }
+        Ok(AnyChannelsWriter { channels })
     }
 }
 
@@ -142,15 +189,34 @@ where
         Channels
     >;
 
-    fn create_writer(&'c self, header: &Header) -> Self::Writer {
-        SpecificChannelsWriter {
+    fn create_writer(&'c self, header: &Header) -> Result<Self::Writer> {
+        Ok(SpecificChannelsWriter {
             channels: self,
             recursive_channel_writer: self.channels.clone().into_recursive().create_recursive_writer(&header.channels),
-        }
+        })
     }
 }
 
 
+// make borrowed channels writable, as in `LayerRef`:
+impl<'slf, 'channels: 'slf, Channels: 'channels> WritableChannels<'slf> for &'channels Channels
+    where Channels: WritableChannels<'slf>
+{
+    fn infer_channel_list(&self) -> ChannelList {
+        Channels::infer_channel_list(self)
+    }
+
+    fn infer_level_modes(&self) -> (LevelMode, RoundingMode) {
+        Channels::infer_level_modes(self)
+    }
+
+    type Writer = Channels::Writer;
+
+    fn create_writer(&'slf self, header: &Header) -> Result<Self::Writer> {
+        Channels::create_writer(self, header)
+    }
+}
+
 
 /// A temporary writer for a layer of channels, alpha being optional
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -169,6 +235,21 @@ for SpecificChannelsWriter<'channels, PxWriter, Storage, Channels>
         PxWriter: Sync + RecursivePixelWriter<<Storage::Pixel as IntoRecursive>::Recursive>,
 {
     fn extract_uncompressed_block(&self, header: &Header, block_index: BlockIndex) -> Vec<u8> {
+        self.extract_block(header, block_index, None)
+    }
+
+    fn extract_dithered_block(&self, header: &Header, block_index: BlockIndex, dithering: &Dithering) -> Vec<u8> {
+        self.extract_block(header, block_index, Some(dithering))
+    }
+}
+
+impl<'channels, PxWriter, Storage, Channels> SpecificChannelsWriter<'channels, PxWriter, Storage, Channels>
+    where
+        Storage: GetPixel,
+        Storage::Pixel: IntoRecursive,
+        PxWriter: Sync + RecursivePixelWriter<<Storage::Pixel as IntoRecursive>::Recursive>,
+{
+    fn extract_block(&self, header: &Header, block_index: BlockIndex, dithering: Option<&Dithering>) -> Vec<u8> {
         let block_bytes = block_index.pixel_size.area() * header.channels.bytes_per_pixel;
         let mut block_bytes = vec![0_u8; block_bytes];
 
@@ -187,7 +268,8 @@ for SpecificChannelsWriter<'channels, PxWriter, Storage, Channels>
                 self.channels.pixels.get_pixel(block_index.pixel_position + Vec2(x, y)).into_recursive()
             ));
 
-            self.recursive_channel_writer.write_pixels(line_bytes, pixel_line.as_slice(), |px| px);
+            let dithering = dithering.map(|dithering| (dithering, block_index.pixel_position + Vec2(0, y)));
+            self.recursive_channel_writer.write_pixels(line_bytes, pixel_line.as_slice(), |px| px, dithering);
         }
 
         block_bytes
@@ -232,6 +314,7 @@ impl<InnerDescriptions, InnerPixel, Sample: IntoNativeSample>
 
         Recursive::new(self.inner.create_recursive_writer(channels), SampleWriter {
             start_byte_offset, target_sample_type,
+            channel_name: self.value.name.clone(),
             px: PhantomData::default()
         })
     }
@@ -256,14 +339,14 @@ for Recursive<InnerDescriptions, Option<ChannelDescription>>
         let channel = self.value.as_ref().map(|required_channel|
             channels.channels_with_byte_offset()
                 .find(|(_offset, channel)| channel == &required_channel)
-                .map(|(offset, channel)| (offset, channel.sample_type))
+                .map(|(offset, channel)| (offset, channel.sample_type, channel.name.clone()))
                 .expect("a channel has not been put into channel list")
         );
 
         Recursive::new(
             self.inner.create_recursive_writer(channels),
-            channel.map(|(start_byte_offset, target_sample_type)| SampleWriter {
-                start_byte_offset, target_sample_type,
+            channel.map(|(start_byte_offset, target_sample_type, channel_name)| SampleWriter {
+                start_byte_offset, target_sample_type, channel_name,
                 px: PhantomData::default(),
             })
         )
@@ -281,7 +364,11 @@ for Recursive<InnerDescriptions, Option<ChannelDescription>>
 pub trait RecursivePixelWriter<Pixel>: Sync {
 
     /// Write pixels to a slice of bytes. Recursively do this for all channels.
-    fn write_pixels<FullPixel>(&self, bytes: &mut [u8], pixels: &[FullPixel], get_pixel: impl Fn(&FullPixel) -> &Pixel);
+    /// If dithering is specified, it contains the position of the first pixel in the line.
+    fn write_pixels<FullPixel>(
+        &self, bytes: &mut [u8], pixels: &[FullPixel], get_pixel: impl Fn(&FullPixel) -> &Pixel,
+        dithering: Option<(&Dithering, Vec2<usize>)>
+    );
 }
 
 type RecursiveWriter<Inner, Sample> = Recursive<Inner, SampleWriter<Sample>>;
@@ -292,17 +379,33 @@ type OptionalRecursiveWriter<Inner, Sample> = Recursive<Inner, Option<SampleWrit
 pub struct SampleWriter<Sample> {
     target_sample_type: SampleType,
     start_byte_offset: usize,
+    channel_name: Text,
     px: PhantomData<Sample>,
 }
 
 impl<Sample> SampleWriter<Sample> where Sample: IntoNativeSample {
-    fn write_own_samples(&self, bytes: &mut [u8], samples: impl ExactSizeIterator<Item=Sample>) {
+    fn write_own_samples(&self, bytes: &mut [u8], samples: impl ExactSizeIterator<Item=Sample>, dithering: Option<(&Dithering, Vec2<usize>)>) {
         let byte_start_index = samples.len() * self.start_byte_offset;
         let byte_count = samples.len() * self.target_sample_type.bytes_per_sample();
         let ref mut byte_writer = &mut bytes[byte_start_index..byte_start_index + byte_count];
 
         let write_error_msg = "invalid memory buffer length when writing";
 
+        // samples that are already stored as f16 are exactly representable and not changed by dithering
+        let dithered_line = dithering
+            .filter(|(dithering, _)| self.target_sample_type == SampleType::F16 && dithering.is_dithered(&self.channel_name))
+            .map(|(_, line_position)| line_position);
+
+        if let Some(line_position) = dithered_line {
+            for (x, sample) in samples.enumerate() {
+                let threshold = Dithering::threshold(line_position + Vec2(x, 0));
+                dither_f32_to_f16(sample.to_f32(), threshold).write(byte_writer).expect(write_error_msg);
+            }
+
+            debug_assert!(byte_writer.is_empty(), "all samples are written, but more were expected");
+            return;
+        }
+
         // match outside the loop to avoid matching on every single sample
         match self.target_sample_type {
             // TODO does this boil down to a `memcpy` where the sample type equals the type parameter?
@@ -316,7 +419,7 @@ impl<Sample> SampleWriter<Sample> where Sample: IntoNativeSample {
 }
 
 impl RecursivePixelWriter<NoneMore> for NoneMore {
-    fn write_pixels<FullPixel>(&self, _: &mut [u8], _: &[FullPixel], _: impl Fn(&FullPixel) -> &NoneMore) {}
+    fn write_pixels<FullPixel>(&self, _: &mut [u8], _: &[FullPixel], _: impl Fn(&FullPixel) -> &NoneMore, _: Option<(&Dithering, Vec2<usize>)>) {}
 }
 
 impl<Inner, InnerPixel, Sample: IntoNativeSample>
@@ -325,9 +428,12 @@ impl<Inner, InnerPixel, Sample: IntoNativeSample>
     where Inner: RecursivePixelWriter<InnerPixel>
 {
     // TODO impl exact size iterator <item = Self::Pixel>
-    fn write_pixels<FullPixel>(&self, bytes: &mut [u8], pixels: &[FullPixel], get_pixel: impl Fn(&FullPixel) -> &Recursive<InnerPixel, Sample>){
-        self.value.write_own_samples(bytes, pixels.iter().map(|px| get_pixel(px).value));
-        self.inner.write_pixels(bytes, pixels, |px| &get_pixel(px).inner);
+    fn write_pixels<FullPixel>(
+        &self, bytes: &mut [u8], pixels: &[FullPixel], get_pixel: impl Fn(&FullPixel) -> &Recursive<InnerPixel, Sample>,
+        dithering: Option<(&Dithering, Vec2<usize>)>
+    ){
+        self.value.write_own_samples(bytes, pixels.iter().map(|px| get_pixel(px).value), dithering);
+        self.inner.write_pixels(bytes, pixels, |px| &get_pixel(px).inner, dithering);
     }
 }
 
@@ -336,12 +442,15 @@ impl<Inner, InnerPixel, Sample> RecursivePixelWriter<Recursive<InnerPixel, Sampl
     where Inner: RecursivePixelWriter<InnerPixel>,
         Sample: IntoNativeSample
 {
-    fn write_pixels<FullPixel>(&self, bytes: &mut [u8], pixels: &[FullPixel], get_pixel: impl Fn(&FullPixel) -> &Recursive<InnerPixel, Sample>) {
+    fn write_pixels<FullPixel>(
+        &self, bytes: &mut [u8], pixels: &[FullPixel], get_pixel: impl Fn(&FullPixel) -> &Recursive<InnerPixel, Sample>,
+        dithering: Option<(&Dithering, Vec2<usize>)>
+    ) {
         if let Some(writer) = &self.value {
-            writer.write_own_samples(bytes, pixels.iter().map(|px| get_pixel(px).value));
+            writer.write_own_samples(bytes, pixels.iter().map(|px| get_pixel(px).value), dithering);
         }
 
-        self.inner.write_pixels(bytes, pixels, |px| &get_pixel(px).inner);
+        self.inner.write_pixels(bytes, pixels, |px| &get_pixel(px).inner, dithering);
     }
 }
 
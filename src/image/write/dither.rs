@@ -0,0 +1,251 @@
+//! Dither `f32` samples when they are converted to `f16` channels while writing,
+//! to avoid visible banding in smooth gradients.
+//!
+//! Instead of rounding each sample to the nearest `f16`, the sample is rounded up or down
+//! depending on a blue noise threshold, such that the average of neighbouring pixels is preserved.
+//! Blue noise has no low frequencies, so the noise is barely visible.
+//! Pass a `Dithering` to `image.write().dither_f16_samples(dithering)`.
+//! Only channels that are converted from `f32` storage to `f16` channels, such as in `SpecificChannels`, are dithered.
+//! Channels that are already stored as `f16` samples are written unchanged.
+
+use half::f16;
+use crate::math::Vec2;
+use crate::meta::attribute::Text;
+
+
+/// Selects which `f16` channels are dithered while writing.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Dithering {
+
+    /// The names of the dithered channels.
+    pub channels: Vec<Text>,
+
+    /// Whether all `f16` channels are dithered, regardless of the list.
+    pub all_channels: bool,
+}
+
+/// The width and height of the tiled blue noise texture.
+const NOISE_SIZE: usize = 32;
+
+impl Dithering {
+
+    /// Dither all `f16` channels.
+    pub fn all_channels() -> Self {
+        Self { channels: Vec::new(), all_channels: true }
+    }
+
+    /// Dither only the `f16` channels with the specified names.
+    pub fn channels(names: impl IntoIterator<Item=impl Into<Text>>) -> Self {
+        Self { channels: names.into_iter().map(Into::into).collect(), all_channels: false }
+    }
+
+    /// Whether the channel with this name is dithered.
+    pub fn is_dithered(&self, channel_name: &Text) -> bool {
+        self.all_channels || self.channels.contains(channel_name)
+    }
+
+    /// The threshold for the pixel at the position, between zero and one.
+    /// The pixels of each layer are covered with a tiled blue noise texture.
+    pub fn threshold(position: Vec2<usize>) -> f32 {
+        let rank = BLUE_NOISE_RANKS[(position.y() % NOISE_SIZE) * NOISE_SIZE + position.x() % NOISE_SIZE];
+        (rank as f32 + 0.5) / BLUE_NOISE_RANKS.len() as f32
+    }
+}
+
+/// Round the sample up or down to one of the two nearest `f16` values.
+/// The sample is rounded up if the threshold is smaller than the fractional position of the sample between them.
+/// With uniformly distributed thresholds, the average of the results equals the original sample.
+/// Samples that are exactly representable, not finite, or too large for `f16`, are converted as usual.
+pub fn dither_f32_to_f16(sample: f32, threshold: f32) -> f16 {
+    let nearest = f16::from_f32(sample);
+    if !sample.is_finite() || !nearest.is_finite() || nearest.to_f32() == sample { return nearest; }
+
+    let other = if nearest.to_f32() < sample { next_f16_up(nearest) } else { next_f16_down(nearest) };
+    if !other.is_finite() { return nearest; }
+
+    let (lower, upper) = if nearest.to_f32() < sample { (nearest, other) } else { (other, nearest) };
+    let fraction = (sample - lower.to_f32()) / (upper.to_f32() - lower.to_f32());
+
+    if threshold < fraction { upper } else { lower }
+}
+
+/// The next larger `f16` value. Only used for finite values.
+fn next_f16_up(value: f16) -> f16 {
+    let bits = value.to_bits();
+    if bits == 0x8000 { f16::from_bits(1) } // negative zero
+    else if bits & 0x8000 == 0 { f16::from_bits(bits + 1) }
+    else { f16::from_bits(bits - 1) }
+}
+
+/// The next smaller `f16` value. Only used for finite values.
+fn next_f16_down(value: f16) -> f16 {
+    let bits = value.to_bits();
+    if bits == 0 { f16::from_bits(0x8001) } // positive zero
+    else if bits & 0x8000 == 0 { f16::from_bits(bits - 1) }
+    else { f16::from_bits(bits + 1) }
+}
+
+/// The rank of each pixel in a tileable blue noise texture, row by row.
+/// Contains each rank from zero to the number of pixels exactly once.
+/// Generated using the void-and-cluster method, see `generate_blue_noise_ranks` in the tests.
+const BLUE_NOISE_RANKS: [u16; NOISE_SIZE * NOISE_SIZE] = [
+     747,  635,  230,  501,  816,    8,  274,  126,  408,   32,  677,  922,   68,  695,  196,  476,  940,  368,  877,  304,  811,  954,  145,  748,  254,  113,  971,  194,   61,  764,  467,   12,
+     288, 1007,   74,  890,  422,  724,  951,  633,  912,  741,  224,  458,  154, 1001,  312,  744,  129,  666,   55,  708,  225,  393,  670,  447,  864,  618,  316,  433,  828,  599,  216,  863,
+     396,  527,  679,  308,  563,  198,  362,  512,  178,  336,  867,  605,  808,  513,  628,  866,  234,  548,  426,  992,  584,    4,  917,  208,   72,  485,  899,  674,  270,  990,  136,  565,
+      49,  834,  167,  945,  110,  874,  702,   43,  786,  544,   82,  384,  273,  102,  372,   24,  970,  324,  805,  177,  477,  789,  348,  560, 1009,  770,  165,   22,  520,  353,  703,  929,
+     244,  344,  479,  738,  604,  265,  460,  847,  292,  994,  665,  907,  710,  950,  775,  592,  469,  719,  100,  902,  287,  641,  137,  711,  275,  373,  585,  726,  916,   95,  452,  774,
+     646,  984,  798,   17,  370, 1012,   88,  615,  400,  143,  473,    7,  217,  435,  150,  279,  845,  204,  626,  518,   45,  841,  961,  454,   39,  825,  221,  419,  799,  258,  612,  149,
+     492,   81,  424,  233,  660,  508,  903,  218,  768,  879,  263,  578,  826,  652,  530,  897,   73,  394, 1013,  329,  706,  402,  212,  537,  880,  653,  968,   70,  558, 1016,  369,  893,
+     298,  715,  568,  920,  818,  158,  318,  687,   38,  523,  716,  973,  321,   58,  991,  345,  690,  551,  773,  156,  927,  595,   91,  758,  289,  134,  498,  328,  181,  692,    1,  824,
+     115,  965,  189,  327,   59,  752,  579,  434,  957,  343,  111,  415,  180,  759,  478,  131,  815,  255,   52,  474,  266,  802,  361, 1003,  437,  608,  924,  746,  859,  465,  227,  543,
+     383,  607,  858,  500,  410,  982,   97,  857,  176,  787,  637,  919,  547,  875,  241,  617,  438,  959,  658,  848,  542,    9,  663,  187,  723,   40,  243,  376,  104,  620,  944,  776,
+     681,  260,   23,  734,  631,  222,  522,  290,  597,  456,  246,   29,  668,  371,  718,   13,  900,  186,  378,  112,  977,  405,  865,  296,  906,  549,  836,  678,  996,  294,  428,  157,
+     909,  453, 1011,  168,  838,  364,  923,  755,   51, 1018,  833,  502,  297,  101,  999,  534,  313,  751,  577,  280,  698,  142,  614,  475,   98,  398,  171,  503,   28,  564,  854,   69,
+     524,  793,  314,  552,   77,  701,  138,  423,  649,  337,  146,  731,  946,  794,  170,  411,  861,   65,  471,  881,  777,  515,  226,  993,  750,  634,  960,  269,  772,  197,  722,  358,
+     220,  103,  667,  399,  955,  283,  588,  821,  210,  885,  406,  570,  214,  463,  603,  700,  249,  638, 1021,  205,   78,  359,  823,   26,  325,  133,  807,  365,  915,  440,  622,  969,
+     593,  896,  762,  200,  852,  488,    6,  986,  533,   99,  697,   54,  908,  335,   37,  966,  130,  800,  322,  431,  623,  953,  672,  553,  910,  455,  571,   56,  661,  125,  309,   18,
+     484,  291,  444,   41,  610,  745,  386,  261,  763,  457,  975,  282,  640,  850,  760,  506,  379,  561,   15,  918,  495,  161,  285,  425,  195,  689,  239,  856,  496, 1020,  735,  830,
+     928,  117,  654, 1005,  342,  151,  936,  659,  174,  339,  817,  510,  116,  417,  182,  262,  937,  743,  193,  827,  713,   60,  853,  740,   84,  979,  754,  340,  172,  557,  247,  392,
+     183,  541,  803,  228,  871,  472,   63,  556,  889,   31,  590,  206,  729, 1004,  567,  673,   87,  445,  647,  363,  248,  580, 1006,  320,  602,  487,    5,  941,  418,  796,   62,  683,
+     981,  730,  382,   76,  572,  705,  813,  281,  439,  675,  948,  374,  837,    0,  341,  886,  809,  302,  995,  119,  883,  397,  516,  128,  814,  377,  253,  644,  106,  901,  589,  332,
+      11,  271,  491,  952,  307,  190,  395,  988,  135,  780,  245,  499,  144,  627,  466,  162,  529,   53,  600,  481,  707,   20,  779,  213,  958,  676,  878,  532,  737,  219,  480,  843,
+     621,  891,  147,  783,  632,  911,   93,  720,  521,  331,   66,  872,  704,  272,  978,  739,  238,  921,  771,  185,  284,  939,  645,  459,  301,   64,  164,  441,  311,  949,  141,  409,
+      96,  528,  699,  414,   33,  486,  587,  223,  840,  630, 1019,  391,  538,  913,   44,  407,  662,  330,  421,  976,  550,  355,   89,  895,  742,  582,  849, 1000,   35,  636,  801,  721,
+    1014,  199,  323,  974,  259,  869,  360,  962,   10,  451,  184,  804,   85,  351,  583,  791,  121,  870,   30,  728,  132,  855,  609,  191,  387,  504,  257,  685,  389,  540,  242,  356,
+     468,  586,  806,  124,  650,  761,  159,  671,  295,  555,  736,  267,  669,  152,  860,  211,  464,  569,  643,  264,  509,  785,  442, 1015,   25,  812,  109,  767,  179,  942,   75,  876,
+     682,   27,  926,  446,  539,   83,  427,  790,  938,  123,  882,  436,  980,  493,  709,  933,  310, 1010,  192,  904,  338,   47,  229,  680,  315,  925,  606,  333,  483,  832,  601,  286,
+     784,  380,  232,  712,  305, 1008,  598,  235,  494,  651,  349,   36,  613,  236,  388,   21,  535,   79,  686,  429,  749,  934,  581,  846,  526,  148,  416,  987,    2,  693,  430,  118,
+     972,  536,  844,  163,  894,   19,  820,  385,   67,  835,  201,  932,  766,  108,  839,  629,  781,  367,  822,  114,  616,  173,  390,   71,  753,  250,  656,  792,  215,  300,  935,  188,
+     642,  326,   94,  619,  505,  352,  696,  169,  985,  574,  714,  482,  303,  566,  997,  277,  140,  947,  470,  276,  983,  519,  299,  963,  461,  892,   57,  514,  884,  575,  757,  489,
+     237,  725,  998,  401,  769,  252,  943,  531,  432,  268,   92,  381,  873,  175,  412,  511,  717,  207,  562,  862,   80,  727,  819,  127,  594,  357,  733,  166,  404,   86,  366,   46,
+     829,  462,   34,  209,  868,  105,  639,   48,  732,  851,  625,  956,    3,  657,  756,   90,  888,  664,   14,  354,  648,  231,  413,  684,  202,  931,  278,  624, 1023,  810,  655,  914,
+     306,  596,  930,  546,  688,  449,  319,  887,  203,  350,  139,  778,  507,  240,  967,  334,  443,  256, 1017,  765,  450,  898,   50, 1002,  490,   16,  797,  448,  120,  251,  525,  155,
+     420,  122,  788,  346,  153,  989,  576,  782,  497, 1022,  559,  293,  403,  842,  573,   42,  795,  591,  160,  517,  107,  611,  317,  545,  831,  375,  694,  554,  905,  347,  691,  964,
+];
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cmp::Ordering;
+
+    /// Rank the pixels using the void-and-cluster method, by Robert Ulichney.
+    /// Starting with a relaxed pattern of a few pixels, pixels are removed from the tightest cluster,
+    /// or added to the largest void, which is the pixel with the least energy.
+    fn generate_blue_noise_ranks() -> Vec<u16> {
+        const PIXEL_COUNT: usize = NOISE_SIZE * NOISE_SIZE;
+        const SIGMA: f64 = 1.5;
+
+        // the energy that a pixel adds to each other pixel, depending on their wrapping offset
+        let kernel: Vec<f64> = (0 .. PIXEL_COUNT).map(|index| {
+            let wrapped = |coordinate: usize| coordinate.min(NOISE_SIZE - coordinate) as f64;
+            let (x, y) = (wrapped(index % NOISE_SIZE), wrapped(index / NOISE_SIZE));
+            (-(x*x + y*y) / (2.0 * SIGMA * SIGMA)).exp()
+        }).collect();
+
+        let update_energy = |energy: &mut [f64], pixel: usize, sign: f64| {
+            let (pixel_x, pixel_y) = (pixel % NOISE_SIZE, pixel / NOISE_SIZE);
+
+            for (index, pixel_energy) in energy.iter_mut().enumerate() {
+                let x = (index % NOISE_SIZE + NOISE_SIZE - pixel_x) % NOISE_SIZE;
+                let y = (index / NOISE_SIZE + NOISE_SIZE - pixel_y) % NOISE_SIZE;
+                *pixel_energy += sign * kernel[y * NOISE_SIZE + x];
+            }
+        };
+
+        let tightest_cluster = |energy: &[f64], pattern: &[bool]| (0 .. PIXEL_COUNT)
+            .filter(|&index| pattern[index]).max_by(|&a, &b| energy[a].partial_cmp(&energy[b]).unwrap_or(Ordering::Equal));
+
+        let largest_void = |energy: &[f64], pattern: &[bool]| (0 .. PIXEL_COUNT)
+            .filter(|&index| !pattern[index]).min_by(|&a, &b| energy[a].partial_cmp(&energy[b]).unwrap_or(Ordering::Equal));
+
+        // start with a few pseudo-random pixels
+        let mut pattern = vec![ false; PIXEL_COUNT ];
+        let mut energy = vec![ 0.0_f64; PIXEL_COUNT ];
+        let mut random = 0x2545_f491_u32;
+
+        let initial_count = PIXEL_COUNT / 10;
+        while pattern.iter().filter(|&&set| set).count() < initial_count {
+            random ^= random << 13; random ^= random >> 17; random ^= random << 5;
+
+            let pixel = random as usize % PIXEL_COUNT;
+            if !pattern[pixel] { pattern[pixel] = true; update_energy(&mut energy, pixel, 1.0); }
+        }
+
+        // distribute the initial pixels evenly, by moving the tightest cluster to the largest void
+        loop {
+            let cluster = tightest_cluster(&energy, &pattern).expect("initial pixels exist");
+            pattern[cluster] = false;
+            update_energy(&mut energy, cluster, -1.0);
+
+            let void = largest_void(&energy, &pattern).expect("initial pattern is not full");
+            pattern[void] = true;
+            update_energy(&mut energy, void, 1.0);
+
+            if void == cluster { break; }
+        }
+
+        let mut ranks = vec![ 0_u16; PIXEL_COUNT ];
+
+        // the initial pixels receive the smallest ranks, the tightest cluster is ranked last
+        {
+            let (mut pattern, mut energy) = (pattern.clone(), energy.clone());
+
+            for rank in (0 .. initial_count).rev() {
+                let cluster = tightest_cluster(&energy, &pattern).expect("one initial pixel per rank");
+                ranks[cluster] = rank as u16;
+                pattern[cluster] = false;
+                update_energy(&mut energy, cluster, -1.0);
+            }
+        }
+
+        // fill the remaining pixels, the largest void is ranked first
+        for rank in initial_count .. PIXEL_COUNT {
+            let void = largest_void(&energy, &pattern).expect("one remaining pixel per rank");
+            ranks[void] = rank as u16;
+            pattern[void] = true;
+            update_energy(&mut energy, void, 1.0);
+        }
+
+        ranks
+    }
+
+    #[test]
+    fn dithering_preserves_the_average() {
+        let sample = 1.0 + 0.3 * f16::EPSILON.to_f32();
+
+        let sum: f32 = (0 .. NOISE_SIZE).flat_map(|y| (0 .. NOISE_SIZE).map(move |x| Vec2(x, y)))
+            .map(|position| dither_f32_to_f16(sample, Dithering::threshold(position)).to_f32())
+            .sum();
+
+        let average = sum / (NOISE_SIZE * NOISE_SIZE) as f32;
+        assert!((average - sample).abs() < 0.01 * f16::EPSILON.to_f32());
+        assert_eq!(f16::from_f32(sample), f16::ONE, "without dithering, all samples are rounded down");
+
+        assert_eq!(dither_f32_to_f16(0.5, 0.3), f16::from_f32(0.5));
+        assert_eq!(dither_f32_to_f16(-1.0 - 0.3 * f16::EPSILON.to_f32(), 0.9), f16::from_f32(-1.0 - f16::EPSILON.to_f32()));
+        assert_eq!(dither_f32_to_f16(1.0e9, 0.5), f16::INFINITY);
+    }
+
+    #[test]
+    fn blue_noise_contains_each_threshold_once() {
+        assert_eq!(generate_blue_noise_ranks(), BLUE_NOISE_RANKS.to_vec());
+
+        let texture: Vec<f32> = (0 .. NOISE_SIZE).flat_map(|y| (0 .. NOISE_SIZE).map(move |x| Vec2(x, y)))
+            .map(Dithering::threshold).collect();
+
+        let mut thresholds = texture.clone();
+        thresholds.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+
+        for (index, threshold) in thresholds.iter().enumerate() {
+            assert_eq!(*threshold, (index as f32 + 0.5) / thresholds.len() as f32);
+        }
+
+        // neighbouring thresholds differ more than in white noise, where they differ by a third on average,
+        // because blue noise contains no low frequencies
+        let neighbour_difference: f32 = (0 .. texture.len() - 1)
+            .map(|index| (texture[index] - texture[index + 1]).abs()).sum::<f32>() / texture.len() as f32;
+
+        assert!(neighbour_difference > 0.37, "average neighbour difference is {}", neighbour_difference);
+    }
+}
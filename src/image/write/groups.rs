@@ -0,0 +1,219 @@
+//! Write several groups of channels into a single layer.
+
+use crate::prelude::*;
+use crate::meta::{header::*, attribute::*};
+use crate::math::RoundingMode;
+use crate::block::*;
+use crate::image::write::channels::*;
+use crate::image::write::dither::Dithering;
+use crate::error::{Result, Error};
+
+
+/// Writes two groups of channels into the same layer,
+/// for example `RGBA` from one closure, `N.x`, `N.y`, `N.z` from another closure, and `Z` from a third closure,
+/// all reading from the same storage. Use `and` to add more groups.
+/// This avoids assembling an intermediate `AnyChannels` list.
+/// The channels of all groups are automatically sorted alphabetically in the file.
+/// Each block is extracted from all groups separately, and then the lines are copied into a single block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelGroups<First, Second> {
+    first: First,
+    second: Second,
+}
+
+impl<First, Second> ChannelGroups<First, Second> {
+
+    /// Write both groups of channels into one layer.
+    /// Returns an error if both groups contain a channel with the same name,
+    /// or if the groups have different resolution levels.
+    pub fn new<'s>(first: First, second: Second) -> Result<Self>
+        where First: WritableChannels<'s>, Second: WritableChannels<'s>
+    {
+        if first.infer_level_modes() != second.infer_level_modes() {
+            return Err(Error::invalid("all channel groups must have the same resolution levels"));
+        }
+
+        let second_list = second.infer_channel_list();
+        let duplicate = first.infer_channel_list().list.into_iter()
+            .find(|channel| second_list.list.iter().any(|other| other.name == channel.name));
+
+        if let Some(duplicate) = duplicate {
+            return Err(Error::invalid(format!("more than one channel group contains a channel named `{}`", duplicate.name)));
+        }
+
+        Ok(Self { first, second })
+    }
+
+    /// Additionally write another group of channels into the same layer.
+    pub fn and<'s, Other>(self, other: Other) -> Result<ChannelGroups<Self, Other>>
+        where Self: WritableChannels<'s>, Other: WritableChannels<'s>
+    {
+        ChannelGroups::new(self, other)
+    }
+
+    /// Discard the grouping, returning the original channel groups.
+    pub fn into_inner(self) -> (First, Second) { (self.first, self.second) }
+}
+
+impl<'s, First, Second> WritableChannels<'s> for ChannelGroups<First, Second>
+    where First: WritableChannels<'s>, Second: WritableChannels<'s>
+{
+    fn infer_channel_list(&self) -> ChannelList {
+        let mut list = self.first.infer_channel_list().list;
+        list.extend(self.second.infer_channel_list().list);
+
+        list.sort_unstable_by_key(|channel| channel.name.clone()); // TODO no clone?
+        ChannelList::new(list)
+    }
+
+    fn infer_level_modes(&self) -> (LevelMode, RoundingMode) {
+        self.first.infer_level_modes()
+    }
+
+    type Writer = ChannelGroupsWriter<First::Writer, Second::Writer>;
+
+    fn create_writer(&'s self, header: &Header) -> Result<Self::Writer> {
+        let first_header = Header { channels: self.first.infer_channel_list(), .. header.clone() };
+        let second_header = Header { channels: self.second.infer_channel_list(), .. header.clone() };
+
+        if header.channels.list.len() != first_header.channels.list.len() + second_header.channels.list.len() {
+            return Err(Error::invalid("header does not contain the channels of all groups"));
+        }
+
+        let group_channel_indices = header.channels.list.iter()
+            .map(|file_channel| {
+                let index_in = |group_header: &Header| group_header.channels.list.iter()
+                    .position(|group_channel| group_channel.name == file_channel.name);
+
+                index_in(&first_header).map(|index| (false, index))
+                    .or_else(|| index_in(&second_header).map(|index| (true, index)))
+                    .ok_or_else(|| Error::invalid(format!("header channel `{}` is in none of the channel groups", file_channel.name)))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(ChannelGroupsWriter {
+            first_writer: self.first.create_writer(&first_header)?,
+            second_writer: self.second.create_writer(&second_header)?,
+            first_header, second_header,
+            group_channel_indices,
+        })
+    }
+}
+
+/// A temporary writer that merges the lines of two channels writers.
+#[derive(Debug, Clone)]
+pub struct ChannelGroupsWriter<FirstWriter, SecondWriter> {
+    first_writer: FirstWriter,
+    second_writer: SecondWriter,
+    first_header: Header,
+    second_header: Header,
+
+    /// For each channel in the file, whether it is in the second group, and its index within that group.
+    group_channel_indices: SmallVec<[(bool, usize); 8]>,
+}
+
+impl<First, Second> ChannelsWriter for ChannelGroupsWriter<First, Second> where First: ChannelsWriter, Second: ChannelsWriter {
+    fn extract_uncompressed_block(&self, header: &Header, block_index: BlockIndex) -> Vec<u8> {
+        let first_bytes = self.first_writer.extract_uncompressed_block(&self.first_header, block_index);
+        let second_bytes = self.second_writer.extract_uncompressed_block(&self.second_header, block_index);
+        self.interleave_groups(header, block_index, &first_bytes, &second_bytes)
+    }
+
+    fn extract_dithered_block(&self, header: &Header, block_index: BlockIndex, dithering: &Dithering) -> Vec<u8> {
+        let first_bytes = self.first_writer.extract_dithered_block(&self.first_header, block_index, dithering);
+        let second_bytes = self.second_writer.extract_dithered_block(&self.second_header, block_index, dithering);
+        self.interleave_groups(header, block_index, &first_bytes, &second_bytes)
+    }
+}
+
+impl<First, Second> ChannelGroupsWriter<First, Second> {
+
+    /// Combine the blocks of both groups into a block containing all channels.
+    fn interleave_groups(&self, header: &Header, block_index: BlockIndex, first_bytes: &[u8], second_bytes: &[u8]) -> Vec<u8> {
+        let first_lines = BlockLines::new(block_index, &self.first_header.channels);
+        let second_lines = BlockLines::new(block_index, &self.second_header.channels);
+
+        UncompressedBlock::collect_block_data_from_lines(&header.channels, block_index, |line| {
+            let y = line.location.position.y();
+
+            let source = match self.group_channel_indices[line.location.channel] {
+                (false, index) => first_lines.line(first_bytes, y, index),
+                (true, index) => second_lines.line(second_bytes, y, index),
+            };
+
+            line.value.copy_from_slice(source);
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::image::pixel_vec::PixelVec;
+    use std::io::Cursor;
+
+    #[test]
+    fn write_groups_from_one_storage() {
+        let size = Vec2(5, 3);
+        let storage = PixelVec::new(size, (0 .. size.area()).map(|index| index as f32).collect());
+        let value = |position: Vec2<usize>| *storage.get_pixel(position);
+
+        let rgba = SpecificChannels::rgba(|position| { let v = value(position); (v, v * 2.0, v * 3.0, 1.0_f32) });
+
+        let normals = SpecificChannels::build()
+            .with_channel("N.x").with_channel("N.y").with_channel("N.z")
+            .with_pixel_fn(|position| { let v = value(position); (-v, 0.0_f32, v) });
+
+        let depth = SpecificChannels::build().with_channel("Z")
+            .with_pixel_fn(|position| (f16::from_f32(value(position) * 0.5),));
+
+        let groups = ChannelGroups::new(rgba, normals).unwrap().and(depth).unwrap();
+
+        let mut bytes = Vec::new();
+        Image::from_channels(size, groups).write().non_parallel().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        let image = crate::image::read::read().no_deep_data().largest_resolution_level().all_channels().first_valid_layer().all_attributes()
+            .from_buffered(Cursor::new(&bytes)).unwrap();
+
+        let channels = &image.layer_data.channel_data.list;
+        let names: Vec<String> = channels.iter().map(|channel| channel.name.to_string()).collect();
+        assert_eq!(names, vec!["A", "B", "G", "N.x", "N.y", "N.z", "R", "Z"]);
+
+        let samples = |name: &str| -> Vec<f32> {
+            channels.iter().find(|channel| channel.name.eq(name)).unwrap().sample_data.values_as_f32().collect()
+        };
+
+        for index in 0 .. size.area() {
+            let v = index as f32;
+            assert_eq!(samples("R")[index], v);
+            assert_eq!(samples("G")[index], v * 2.0);
+            assert_eq!(samples("N.x")[index], -v);
+            assert_eq!(samples("N.z")[index], v);
+            assert_eq!(samples("Z")[index], v * 0.5);
+        }
+    }
+
+    #[test]
+    fn duplicate_channels_in_groups() {
+        let first = SpecificChannels::build().with_channel("Z").with_pixel_fn(|_| (0.0_f32,));
+        let second = SpecificChannels::build().with_channel("Z").with_pixel_fn(|_| (1.0_f32,));
+        assert!(ChannelGroups::new(first, second).is_err());
+    }
+
+    #[test]
+    fn header_does_not_match_groups() {
+        let first = SpecificChannels::build().with_channel("Y").with_pixel_fn(|_| (0.0_f32,));
+        let second = SpecificChannels::build().with_channel("Z").with_pixel_fn(|_| (1.0_f32,));
+        let groups = ChannelGroups::new(first, second).unwrap();
+
+        let header = |names: &[&str]| Header::new(
+            Text::from("groups"), Vec2(2, 2),
+            names.iter().map(|&name| ChannelDescription::named(name, SampleType::F32)).collect()
+        );
+
+        assert!(groups.create_writer(&header(&["Y", "Z"])).is_ok());
+        assert!(groups.create_writer(&header(&["X", "Z"])).is_err(), "unknown channel");
+        assert!(groups.create_writer(&header(&["Z"])).is_err(), "missing channel");
+    }
+}
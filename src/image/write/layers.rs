@@ -1,13 +1,15 @@
 //! How to write either a single or a list of layers.
 
 use crate::meta::header::{ImageAttributes, Header};
-use crate::meta::{Headers, compute_chunk_count};
+use crate::meta::Headers;
 use crate::block::BlockIndex;
 use crate::image::{Layers, Layer};
 use crate::meta::attribute::{TileDescription};
 use crate::prelude::{SmallVec};
 use crate::image::write::channels::{WritableChannels, ChannelsWriter};
+use crate::image::write::dither::Dithering;
 use crate::image::recursive::{Recursive, NoneMore};
+use crate::error::Result;
 
 /// Enables an image containing this list of layers to be written to a file.
 pub trait WritableLayers<'slf> {
@@ -18,8 +20,9 @@ pub trait WritableLayers<'slf> {
     /// The type of temporary writer
     type Writer: LayersWriter;
 
-    /// Create a temporary writer for this list of layers
-    fn create_writer(&'slf self, headers: &[Header]) -> Self::Writer;
+    /// Create a temporary writer for this list of layers.
+    /// Returns an error if the headers do not match these layers.
+    fn create_writer(&'slf self, headers: &[Header]) -> Result<Self::Writer>;
 }
 
 /// A temporary writer for a list of channels
@@ -27,6 +30,9 @@ pub trait LayersWriter: Sync {
 
     /// Deliver a block of pixels from a single layer to be stored in the file
     fn extract_uncompressed_block(&self, headers: &[Header], block: BlockIndex) -> Vec<u8>;
+
+    /// Like `extract_uncompressed_block`, but dithers the selected channels when converting `f32` samples to `f16`.
+    fn extract_dithered_block(&self, headers: &[Header], block: BlockIndex, dithering: &Dithering) -> Vec<u8>;
 }
 
 /// A temporary writer for an arbitrary list of layers
@@ -48,11 +54,23 @@ impl<'slf, Channels: 'slf> WritableLayers<'slf> for Layers<Channels> where Chann
     }
 
     type Writer = AllLayersWriter<Channels::Writer>;
-    fn create_writer(&'slf self, headers: &[Header]) -> Self::Writer {
+    fn create_writer(&'slf self, headers: &[Header]) -> Result<Self::Writer> {
         slice_create_writer(self.as_slice(), headers)
     }
 }
 
+// impl for borrowed slices, for example of layers from different images
+impl<'slf, 'layers: 'slf, Channels: 'layers> WritableLayers<'slf> for &'layers [Layer<Channels>] where Channels: WritableChannels<'slf> {
+    fn infer_headers(&self, image_attributes: &ImageAttributes) -> Headers {
+        slice_infer_headers(self, image_attributes)
+    }
+
+    type Writer = AllLayersWriter<Channels::Writer>;
+    fn create_writer(&'slf self, headers: &[Header]) -> Result<Self::Writer> {
+        slice_create_writer(self, headers)
+    }
+}
+
 fn slice_infer_headers<'slf, Channels:'slf + WritableChannels<'slf>>(
     slice: &[Layer<Channels>], image_attributes: &ImageAttributes
 ) -> Headers
@@ -62,13 +80,13 @@ fn slice_infer_headers<'slf, Channels:'slf + WritableChannels<'slf>>(
 
 fn slice_create_writer<'slf, Channels:'slf + WritableChannels<'slf>>(
     slice: &'slf [Layer<Channels>], headers: &[Header]
-) -> AllLayersWriter<Channels::Writer>
+) -> Result<AllLayersWriter<Channels::Writer>>
 {
-    AllLayersWriter {
+    Ok(AllLayersWriter {
         layers: slice.iter().zip(headers.chunks_exact(1)) // TODO no array-vs-first
             .map(|(layer, header)| layer.create_writer(header))
-            .collect()
-    }
+            .collect::<Result<_>>()?
+    })
 }
 
 
@@ -82,37 +100,26 @@ impl<'slf, Channels: WritableChannels<'slf>> WritableLayers<'slf> for Layer<Chan
             },
         };
 
-        let chunk_count = compute_chunk_count(
-            self.encoding.compression, self.size, blocks
-        );
-
-        let header = Header {
-            channels: self.channel_data.infer_channel_list(),
-            compression: self.encoding.compression,
-
-            blocks,
-            chunk_count,
-
-            line_order: self.encoding.line_order,
-            layer_size: self.size,
-            shared_attributes: image_attributes.clone(),
-            own_attributes: self.attributes.clone(),
-
-
-            deep: false, // TODO deep data
-            deep_data_version: None,
-            max_samples_per_pixel: None,
-        };
+        // the headers are validated later, together with all other headers of the image
+        let header = Header::builder()
+            .layer_size(self.size)
+            .channels(self.channel_data.infer_channel_list().list)
+            .compression(self.encoding.compression)
+            .blocks(blocks)
+            .line_order(self.encoding.line_order)
+            .image_attributes(image_attributes.clone())
+            .layer_attributes(self.attributes.clone())
+            .build_unchecked(); // TODO deep data
 
         smallvec![ header ]// TODO no array-vs-first
     }
 
     type Writer = LayerWriter</*'l,*/ Channels::Writer>;
-    fn create_writer(&'slf self, headers: &[Header]) -> Self::Writer {
+    fn create_writer(&'slf self, headers: &[Header]) -> Result<Self::Writer> {
         let channels = self.channel_data
-            .create_writer(headers.first().expect("inferred header error")); // TODO no array-vs-first
+            .create_writer(headers.first().expect("inferred header error"))?; // TODO no array-vs-first
 
-        LayerWriter { channels }
+        Ok(LayerWriter { channels })
     }
 }
 
@@ -120,12 +127,20 @@ impl<C> LayersWriter for AllLayersWriter<C> where C: ChannelsWriter {
     fn extract_uncompressed_block(&self, headers: &[Header], block: BlockIndex) -> Vec<u8> {
         self.layers[block.layer].extract_uncompressed_block(std::slice::from_ref(&headers[block.layer]), block) // TODO no array-vs-first
     }
+
+    fn extract_dithered_block(&self, headers: &[Header], block: BlockIndex, dithering: &Dithering) -> Vec<u8> {
+        self.layers[block.layer].extract_dithered_block(std::slice::from_ref(&headers[block.layer]), block, dithering)
+    }
 }
 
 impl<C> LayersWriter for LayerWriter<C> where C: ChannelsWriter {
     fn extract_uncompressed_block(&self, headers: &[Header], block: BlockIndex) -> Vec<u8> {
         self.channels.extract_uncompressed_block(headers.first().expect("invalid inferred header"), block) // TODO no array-vs-first
     }
+
+    fn extract_dithered_block(&self, headers: &[Header], block: BlockIndex, dithering: &Dithering) -> Vec<u8> {
+        self.channels.extract_dithered_block(headers.first().expect("invalid inferred header"), block, dithering)
+    }
 }
 
 
@@ -136,7 +151,7 @@ impl<'slf> WritableLayers<'slf> for NoneMore {
     fn infer_headers(&self, _: &ImageAttributes) -> Headers { SmallVec::new() }
 
     type Writer = NoneMore;
-    fn create_writer(&'slf self, _: &[Header]) -> Self::Writer { NoneMore }
+    fn create_writer(&'slf self, _: &[Header]) -> Result<Self::Writer> { Ok(NoneMore) }
 }
 
 impl<'slf, InnerLayers, Channels> WritableLayers<'slf> for Recursive<InnerLayers, Layer<Channels>>
@@ -150,15 +165,15 @@ impl<'slf, InnerLayers, Channels> WritableLayers<'slf> for Recursive<InnerLayers
 
     type Writer = RecursiveLayersWriter<InnerLayers::Writer, Channels::Writer>;
 
-    fn create_writer(&'slf self, headers: &[Header]) -> Self::Writer {
+    fn create_writer(&'slf self, headers: &[Header]) -> Result<Self::Writer> {
         let (own_header, inner_headers) = headers.split_last()
             .expect("header has not been inferred correctly");
 
         let layer_index = inner_headers.len();
-        RecursiveLayersWriter {
-            inner: self.inner.create_writer(inner_headers),
-            value: (layer_index, self.value.create_writer(std::slice::from_ref(own_header))) // TODO no slice
-        }
+        Ok(RecursiveLayersWriter {
+            inner: self.inner.create_writer(inner_headers)?,
+            value: (layer_index, self.value.create_writer(std::slice::from_ref(own_header))?) // TODO no slice
+        })
     }
 }
 
@@ -168,6 +183,10 @@ impl LayersWriter for NoneMore {
     fn extract_uncompressed_block(&self, _: &[Header], _: BlockIndex) -> Vec<u8> {
         panic!("recursive length mismatch bug");
     }
+
+    fn extract_dithered_block(&self, _: &[Header], _: BlockIndex, _: &Dithering) -> Vec<u8> {
+        panic!("recursive length mismatch bug");
+    }
 }
 
 impl<InnerLayersWriter, Channels> LayersWriter for RecursiveLayersWriter<InnerLayersWriter, Channels>
@@ -183,6 +202,17 @@ impl<InnerLayersWriter, Channels> LayersWriter for RecursiveLayersWriter<InnerLa
             self.inner.extract_uncompressed_block(headers, block)
         }
     }
+
+    fn extract_dithered_block(&self, headers: &[Header], block: BlockIndex, dithering: &Dithering) -> Vec<u8> {
+        let (layer_index, layer) = &self.value;
+        if *layer_index == block.layer {
+            let header = headers.get(*layer_index).expect("layer index bug");
+            layer.extract_dithered_block(std::slice::from_ref(header), block, dithering)
+        }
+        else {
+            self.inner.extract_dithered_block(headers, block, dithering)
+        }
+    }
 }
 
 
@@ -15,17 +15,38 @@
 pub mod layers;
 pub mod samples;
 pub mod channels;
+pub mod rename;
+pub mod groups;
+pub mod validation;
+pub mod quantization;
+pub mod dither;
 
 
 
 use crate::meta::Headers;
 use crate::error::UnitResult;
 use std::io::{Seek, BufWriter};
-use crate::io::Write;
+use crate::io::{Write, ForwardWrite};
 use crate::image::{Image, ignore_progress, SpecificChannels, IntoSample};
 use crate::image::write::layers::{WritableLayers, LayersWriter};
 use crate::math::Vec2;
-use crate::block::writer::ChunksWriter;
+use crate::block::writer::{ChunksWriter, OffsetTablePlacement};
+use crate::block::{BlockIndex, UncompressedBlock};
+use crate::compression::{Compression, SpeedBias};
+use crate::meta::{compute_chunk_count, BlockDescription};
+use crate::meta::attribute::{TileDescription, LevelMode, LineOrder};
+use crate::math::RoundingMode;
+use crate::image::write::validation::SampleValidation;
+use crate::image::write::quantization::Quantization;
+use crate::image::write::dither::Dithering;
+use std::sync::Arc;
+use crate::meta::attribute::Text;
+use crate::meta::header::LayerAttributes;
+use crate::image::alpha::{AlphaMode, convert_block_alpha};
+
+/// The name and version of this crate, for example `exr 1.3.0`.
+/// Written to the `software` attribute by `WriteImageWithOptions::fill_software_name`.
+pub const SOFTWARE_NAME: &str = concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION"));
 
 /// An oversimplified function for "just write the damn file already" use cases.
 /// Have a look at the examples to see how you can write an image with more flexibility (it's not that hard).
@@ -74,6 +95,18 @@ impl<'img, WritableLayers> WritableImage<'img, WritableLayers> for &'img Image<W
             image: self,
             check_compatibility: true,
             parallel: true,
+            thread_count: None,
+            auto_compression: None,
+            auto_tiles: false,
+            deterministic: false,
+            buffer_size: crate::io::DEFAULT_BUFFER_SIZE,
+            validation: None,
+            quantization: None,
+            dithering: None,
+            fill_software_name: false,
+            layer_defaults: None,
+            associate_alpha: false,
+            offset_tables: OffsetTablePlacement::default(),
             on_progress: ignore_progress
         }
     }
@@ -87,6 +120,18 @@ pub struct WriteImageWithOptions<'img, Layers, OnProgress> {
     on_progress: OnProgress,
     check_compatibility: bool,
     parallel: bool,
+    thread_count: Option<usize>,
+    auto_compression: Option<SpeedBias>,
+    auto_tiles: bool,
+    deterministic: bool,
+    buffer_size: usize,
+    validation: Option<Arc<SampleValidation>>,
+    quantization: Option<Quantization>,
+    dithering: Option<Dithering>,
+    fill_software_name: bool,
+    layer_defaults: Option<LayerAttributes>,
+    associate_alpha: bool,
+    offset_tables: OffsetTablePlacement,
 }
 
 
@@ -95,13 +140,33 @@ impl<'img, L, F> WriteImageWithOptions<'img, L, F>
 {
     /// Generate file meta data for this image. The meta data structure is close to the data in the file.
     pub fn infer_meta_data(&self) -> Headers { // TODO this should perform all validity checks? and none after that?
-        self.image.layer_data.infer_headers(&self.image.attributes)
+        let mut headers = self.image.layer_data.infer_headers(&self.image.attributes);
+
+        if let Some(layer_defaults) = &self.layer_defaults {
+            for header in headers.iter_mut() {
+                header.own_attributes = header.own_attributes.clone().inherit(layer_defaults);
+            }
+        }
+
+        headers
     }
 
     /// Do not compress multiple pixel blocks on multiple threads at once.
     /// Might use less memory and synchronization, but will be slower in most situations.
     pub fn non_parallel(self) -> Self { Self { parallel: false, ..self } }
 
+    /// Compress blocks on at most this many threads at once. A single thread compresses all blocks on the current thread.
+    /// By default, this is the number of cpus, or the number in the environment variable `EXR_THREADS`,
+    /// see `block::default_thread_count`. Has no effect if `non_parallel` is specified.
+    pub fn threads(self, thread_count: usize) -> Self { Self { thread_count: Some(thread_count.max(1)), ..self } }
+
+    /// Specify the number of bytes that are buffered when writing to a file or an unbuffered writer.
+    /// The default is 8 KiB. A larger buffer can speed up writing large files, for example to network storage.
+    /// A size of zero disables buffering, which only makes sense if the writer is cheap to call for small writes.
+    /// This has no effect on `to_buffered`, which never adds another buffer,
+    /// and should be used instead for in-memory or already buffered writers.
+    pub fn buffer_size(self, bytes: usize) -> Self { Self { buffer_size: bytes, ..self } }
+
     /// Skip some checks that ensure a file can be opened by other exr software.
     /// For example, it is no longer checked that no two headers or two attributes have the same name,
     /// which might be an expensive check for images with an exorbitant number of headers.
@@ -112,6 +177,69 @@ impl<'img, L, F> WriteImageWithOptions<'img, L, F>
     /// __You must care for not producing an invalid file yourself.__
     pub fn skip_compatibility_checks(self) -> Self { Self { check_compatibility: false, ..self } }
 
+    /// Ignore the compression method of each layer, and instead choose a lossless compression method
+    /// by compressing a few sample blocks of the layer with each candidate. See `Compression::choose_for`.
+    /// The choice takes some time, which is worth it for larger images.
+    pub fn auto_compression(self, bias: SpeedBias) -> Self { Self { auto_compression: Some(bias), ..self } }
+
+    /// Ignore the blocks of each layer, and instead write tiles with a size chosen
+    /// from the layer size, the bytes per pixel, and the compression method. See `Header::auto_tile_size`.
+    /// Resolution levels of layers that are already tiled are kept.
+    /// Layers with subsampled channels are still written as scan lines, as tiles do not support subsampling.
+    /// When combined with `auto_compression`, the tile size is chosen for the original compression method.
+    pub fn tiles_auto(self) -> Self { Self { auto_tiles: true, ..self } }
+
+    /// Guarantee that the same image always results in the same bytes, even when compressing in parallel.
+    /// Layers with unspecified line order are written in increasing line order,
+    /// such that the order of the chunks does not depend on which thread finishes first.
+    /// When combined with `auto_compression`, the compression method is chosen by file size only,
+    /// as if `SpeedBias::Size` was specified, because timing measurements differ between runs.
+    /// Custom attributes are always written sorted by name, and the compression settings are always the same.
+    pub fn deterministic(self) -> Self { Self { deterministic: true, ..self } }
+
+    /// Check the samples of each block before compressing it, using the rules of the validation.
+    /// Keep a clone of the `Arc` to inspect the number of violations after writing.
+    /// Writing is aborted with an error if a rule with `OnViolation::Error` is violated.
+    /// Replaces all previously specified validations in this writer.
+    pub fn validate_samples(self, validation: Arc<SampleValidation>) -> Self {
+        Self { validation: Some(validation), ..self }
+    }
+
+    /// Discard the least significant mantissa bits of the float samples before compressing them,
+    /// which makes the file much smaller for noisy images, especially with ZIP compression.
+    /// The samples are quantized after the validation of `validate_samples`.
+    /// Replaces all previously specified quantizations in this writer.
+    pub fn quantize_samples(self, quantization: Quantization) -> Self {
+        Self { quantization: Some(quantization), ..self }
+    }
+
+    /// Dither the selected channels when converting `f32` samples to `f16` channels,
+    /// which avoids visible banding in smooth gradients. See the `dither` module.
+    /// Replaces all previously specified dithering in this writer.
+    pub fn dither_f16_samples(self, dithering: Dithering) -> Self {
+        Self { dithering: Some(dithering), ..self }
+    }
+
+    /// Write the name and version of this crate, see `SOFTWARE_NAME`, to the `software` attribute
+    /// of each layer that does not specify its software name yet.
+    /// Helps to find out which library wrote a file when investigating problems with the file.
+    pub fn fill_software_name(self) -> Self { Self { fill_software_name: true, ..self } }
+
+    /// Each layer inherits these attributes, unless the layer specifies its own value. See `LayerAttributes::inherit`.
+    /// The defaults are not stored in the file itself, instead, the attributes are copied to each layer.
+    pub fn layer_defaults(self, layer_defaults: LayerAttributes) -> Self { Self { layer_defaults: Some(layer_defaults), ..self } }
+
+    /// Premultiply the color channels of each layer whose attributes state `AlphaMode::Unassociated`,
+    /// and write these layers as associated alpha, as required by the specification.
+    /// Without this option, such layers are written unchanged, together with their `alphaMode` attribute.
+    /// See `alpha::convert_block_alpha` for which channels are converted.
+    pub fn associate_alpha(self) -> Self { Self { associate_alpha: true, ..self } }
+
+    /// Choose how the offset tables are written. By default, they are filled in after all chunks have been written,
+    /// which requires seeking back once. Use `OffsetTablePlacement::Zeroed` to never seek back,
+    /// for example to write to an append-only file system. See `OffsetTablePlacement` for which readers accept which files.
+    pub fn offset_tables(self, placement: OffsetTablePlacement) -> Self { Self { offset_tables: placement, ..self } }
+
     /// Specify a function to be called regularly throughout the writing process.
     /// Replaces all previously specified progress functions in this reader.
     pub fn on_progress<OnProgress>(self, on_progress: OnProgress) -> WriteImageWithOptions<'img, L, OnProgress>
@@ -121,7 +249,19 @@ impl<'img, L, F> WriteImageWithOptions<'img, L, F>
             on_progress,
             image: self.image,
             check_compatibility: self.check_compatibility,
-            parallel: self.parallel
+            parallel: self.parallel,
+            thread_count: self.thread_count,
+            auto_compression: self.auto_compression,
+            auto_tiles: self.auto_tiles,
+            deterministic: self.deterministic,
+            buffer_size: self.buffer_size,
+            validation: self.validation,
+            quantization: self.quantization,
+            dithering: self.dithering,
+            fill_software_name: self.fill_software_name,
+            layer_defaults: self.layer_defaults,
+            associate_alpha: self.associate_alpha,
+            offset_tables: self.offset_tables,
         }
     }
 
@@ -136,36 +276,148 @@ impl<'img, L, F> WriteImageWithOptions<'img, L, F>
         )
     }
 
-    /// Buffer the writer and then write the exr image to it.
+    /// Buffer the writer and then write the exr image to it. See `buffer_size`.
     /// Use `to_buffered` instead, if your writer is an in-memory buffer.
     /// Use `to_file` instead, if you have a file path.
     /// If your writer cannot seek, you can write to an in-memory vector of bytes first, using `to_buffered`.
     #[inline]
     #[must_use]
     pub fn to_unbuffered(self, unbuffered: impl Write + Seek) -> UnitResult {
-        self.to_buffered(BufWriter::new(unbuffered))
+        let buffer_size = self.buffer_size;
+        self.to_buffered(BufWriter::with_capacity(buffer_size, unbuffered))
     }
 
-    /// Write the exr image to a writer.
+    /// Buffer the stream and then write the exr image to it, without seeking,
+    /// for example to standard output or to a network socket.
+    /// Replaces the offset table placement with `OffsetTablePlacement::Zeroed`,
+    /// so readers must read all chunks to find them. See `OffsetTablePlacement` for which readers accept which files.
+    #[must_use]
+    pub fn to_stream(self, stream: impl Write) -> UnitResult {
+        let buffer_size = self.buffer_size;
+        self.offset_tables(OffsetTablePlacement::Zeroed)
+            .to_buffered(ForwardWrite::new(BufWriter::with_capacity(buffer_size, stream)))
+    }
+
+    /// Write the exr image to a writer. Does not add another buffer.
     /// Use `to_file` instead, if you have a file path.
     /// Use `to_unbuffered` instead, if this is not an in-memory writer.
     /// If your writer cannot seek, you can write to an in-memory vector of bytes first.
     #[must_use]
     pub fn to_buffered(self, write: impl Write + Seek) -> UnitResult {
-        let headers = self.infer_meta_data();
-        let layers = self.image.layer_data.create_writer(&headers);
+        let mut headers = self.infer_meta_data();
+        let layers = self.image.layer_data.create_writer(&headers)?;
+
+        // the pixels of these layers are premultiplied while writing, so the file contains associated alpha
+        let mut associated_layers = vec![ false; headers.len() ];
+        if self.associate_alpha {
+            for (header, associated) in headers.iter_mut().zip(&mut associated_layers) {
+                if header.own_attributes.alpha_mode() == AlphaMode::Unassociated {
+                    header.own_attributes.set_alpha_mode(AlphaMode::Associated);
+                    *associated = true;
+                }
+            }
+        }
 
-        crate::block::write(
-            write, headers, self.check_compatibility,
+        if self.deterministic {
+            for header in headers.iter_mut() {
+                if header.line_order == LineOrder::Unspecified {
+                    header.line_order = LineOrder::Increasing;
+                }
+            }
+        }
+
+        if self.fill_software_name {
+            for header in headers.iter_mut() {
+                header.own_attributes.software_name.get_or_insert_with(|| Text::from(SOFTWARE_NAME));
+            }
+        }
+
+        if self.auto_tiles {
+            for header in headers.iter_mut() {
+                let is_subsampled = header.channels.list.iter().any(|channel| channel.sampling != Vec2(1, 1));
+                if is_subsampled { continue; }
+
+                let (level_mode, rounding_mode) = match header.blocks {
+                    BlockDescription::Tiles(tiles) => (tiles.level_mode, tiles.rounding_mode),
+                    BlockDescription::ScanLines => (LevelMode::Singular, RoundingMode::Down),
+                };
+
+                header.blocks = BlockDescription::Tiles(TileDescription {
+                    tile_size: header.auto_tile_size(), level_mode, rounding_mode
+                });
+
+                header.chunk_count = compute_chunk_count(header.compression, header.layer_size, header.blocks);
+            }
+        }
+
+        if let Some(bias) = self.auto_compression {
+            let bias = if self.deterministic { SpeedBias::Size } else { bias };
+
+            for layer_index in 0 .. headers.len() {
+                let mut candidate_headers = headers.clone();
+
+                let compression = Compression::choose_for(&headers[layer_index], |candidate_header, block_index| {
+                    candidate_headers[layer_index] = candidate_header.clone();
+
+                    let index = BlockIndex { layer: layer_index, .. block_index };
+                    let data = layers.extract_uncompressed_block(&candidate_headers, index);
+
+                    match &self.quantization {
+                        None => data,
+                        Some(quantization) => {
+                            let mut block = UncompressedBlock { index, data };
+                            quantization.quantize_block(candidate_header, &mut block);
+                            block.data
+                        }
+                    }
+                }, bias)?;
+
+                let header = &mut headers[layer_index];
+                header.compression = compression;
+                header.chunk_count = compute_chunk_count(compression, header.layer_size, header.blocks);
+            }
+        }
+
+        crate::block::writer::write_chunks_with_offset_tables(
+            write, headers, self.check_compatibility, self.offset_tables,
             move |meta, chunk_writer|{
 
-                let blocks = meta.collect_ordered_block_data(|block_index|
-                     layers.extract_uncompressed_block(&meta.headers, block_index)
-                );
+                let dithering = self.dithering.as_ref();
+                let blocks = meta.collect_ordered_block_data(|block_index| match dithering {
+                    Some(dithering) => layers.extract_dithered_block(&meta.headers, block_index, dithering),
+                    None => layers.extract_uncompressed_block(&meta.headers, block_index),
+                });
+
+                // stop producing blocks at the first invalid block, and return its error after the writer stopped
+                let mut validation_error = None;
+                let validation = self.validation.as_deref();
+                let quantization = self.quantization.as_ref();
+
+                let blocks = blocks.scan((), |_, (index, mut block)| {
+                    if associated_layers[block.index.layer] {
+                        convert_block_alpha(&meta.headers[block.index.layer].channels, &mut block, AlphaMode::Associated);
+                    }
+
+                    let result = validation.map_or(Ok(()), |validation|
+                        validation.validate_block(&meta.headers[block.index.layer], &mut block)
+                    );
+
+                    if let Some(quantization) = quantization {
+                        quantization.quantize_block(&meta.headers[block.index.layer], &mut block);
+                    }
+
+                    match result {
+                        Ok(()) => Some((index, block)),
+                        Err(error) => { validation_error = Some(error); None },
+                    }
+                });
 
                 let chunk_writer = chunk_writer.on_progress(self.on_progress);
-                if self.parallel { chunk_writer.compress_all_blocks_parallel(&meta, blocks)?; }
+                let thread_count = self.thread_count.unwrap_or_else(crate::block::default_thread_count);
+                if self.parallel && thread_count > 1 { chunk_writer.compress_all_blocks_parallel_with_threads(&meta, thread_count, blocks)?; }
                 else { chunk_writer.compress_all_blocks_sequential(&meta, blocks)?; }
+
+                if let Some(error) = validation_error { return Err(error); }
                 /*let blocks_writer = chunk_writer.as_blocks_writer(&meta);
 
                 // TODO propagate send requirement further upwards
@@ -0,0 +1,169 @@
+//! Discard the least significant mantissa bits of float samples before compressing them.
+//!
+//! Noisy renders compress poorly with lossless methods like ZIP,
+//! because the lowest bits of each sample are essentially random.
+//! Zeroing these bits makes the compression lossy, but with a bounded relative error:
+//! keeping `n` mantissa bits changes each sample by less than `2^-n` of its magnitude.
+//! Pass a `Quantization` to `image.write().quantize_samples(quantization)`.
+
+use half::f16;
+use crate::block::UncompressedBlock;
+use crate::meta::attribute::{SampleType, Text};
+use crate::meta::header::Header;
+
+
+/// How many mantissa bits of the float samples of each channel are kept while writing.
+/// Integer samples and channels without a setting are not changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Quantization {
+
+    /// The name of each quantized channel, and the number of mantissa bits to keep.
+    pub list: Vec<(Text, u32)>,
+
+    /// The number of mantissa bits to keep for all channels that are not in the list.
+    /// If `None`, these channels are not changed.
+    pub other_channels: Option<u32>,
+}
+
+impl Quantization {
+
+    /// A quantization that does not change any channel.
+    pub fn new() -> Self { Self::default() }
+
+    /// Keep only the specified number of mantissa bits in all float channels.
+    /// An `f32` has 23 mantissa bits, and an `f16` has 10 mantissa bits.
+    pub fn all_channels(kept_mantissa_bits: u32) -> Self {
+        Self { list: Vec::new(), other_channels: Some(kept_mantissa_bits) }
+    }
+
+    /// Keep only the specified number of mantissa bits in the channel with this name.
+    /// Replaces the previous setting for this channel.
+    pub fn with_channel(mut self, channel_name: impl Into<Text>, kept_mantissa_bits: u32) -> Self {
+        let channel_name = channel_name.into();
+        self.list.retain(|(name, _)| name != &channel_name);
+        self.list.push((channel_name, kept_mantissa_bits));
+        self
+    }
+
+    /// The number of mantissa bits kept in the channel with this name, or `None` if the channel is not changed.
+    pub fn kept_mantissa_bits(&self, channel_name: &Text) -> Option<u32> {
+        self.list.iter().find(|(name, _)| name == channel_name)
+            .map(|&(_, bits)| bits).or(self.other_channels)
+    }
+
+    /// Quantize the float samples of each channel in the block.
+    pub fn quantize_block(&self, header: &Header, block: &mut UncompressedBlock) {
+        if header.deep { return; }
+
+        for line in block.lines_mut(&header.channels) {
+            let channel = &header.channels.list[line.location.channel];
+
+            let kept_bits = match self.kept_mantissa_bits(&channel.name) {
+                Some(bits) => bits,
+                None => continue,
+            };
+
+            match channel.sample_type {
+                SampleType::F16 => for sample in line.value.chunks_exact_mut(2) {
+                    let value = quantize_f16(f16::from_le_bytes([sample[0], sample[1]]), kept_bits);
+                    sample.copy_from_slice(&value.to_le_bytes());
+                },
+
+                SampleType::F32 => for sample in line.value.chunks_exact_mut(4) {
+                    let value = quantize_f32(f32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]), kept_bits);
+                    sample.copy_from_slice(&value.to_le_bytes());
+                },
+
+                SampleType::U32 => {},
+            }
+        }
+    }
+}
+
+/// Zero all but the specified number of most significant mantissa bits.
+/// Infinity and `NaN` are not changed.
+pub fn quantize_f32(sample: f32, kept_mantissa_bits: u32) -> f32 {
+    const MANTISSA_BITS: u32 = 23;
+    if !sample.is_finite() || kept_mantissa_bits >= MANTISSA_BITS { return sample; }
+
+    let mask = !0_u32 << (MANTISSA_BITS - kept_mantissa_bits);
+    f32::from_bits(sample.to_bits() & mask)
+}
+
+/// Zero all but the specified number of most significant mantissa bits.
+/// Infinity and `NaN` are not changed.
+pub fn quantize_f16(sample: f16, kept_mantissa_bits: u32) -> f16 {
+    const MANTISSA_BITS: u32 = 10;
+    if !sample.is_finite() || kept_mantissa_bits >= MANTISSA_BITS { return sample; }
+
+    let mask = !0_u16 << (MANTISSA_BITS - kept_mantissa_bits);
+    f16::from_bits(sample.to_bits() & mask)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::block::BlockIndex;
+    use crate::math::Vec2;
+    use crate::meta::attribute::ChannelDescription;
+
+    #[test]
+    fn quantization_error_is_bounded() {
+        for &sample in &[ 0.1_f32, -3.7, 12345.678, 1.0e-30 ] {
+            for kept_bits in 0 .. 24 {
+                let quantized = quantize_f32(sample, kept_bits);
+                assert!((quantized - sample).abs() <= sample.abs() * 0.5_f32.powi(kept_bits as i32));
+            }
+        }
+
+        assert_eq!(quantize_f32(1.75, 1), 1.5);
+        assert_eq!(quantize_f16(f16::from_f32(1.75), 0), f16::ONE);
+        assert!(quantize_f32(f32::NAN, 0).is_nan());
+        assert_eq!(quantize_f16(f16::INFINITY, 0), f16::INFINITY);
+    }
+
+    #[test]
+    fn quantize_selected_channels_of_block() {
+        let header = Header::builder()
+            .layer_size((2, 1))
+            .channel(ChannelDescription::named("A", SampleType::F32))
+            .channel(ChannelDescription::named("B", SampleType::U32))
+            .channel(ChannelDescription::named("C", SampleType::F32))
+            .build().unwrap();
+
+        let samples = |values: [f32; 2], integers: [u32; 2], other: [f32; 2]| -> Vec<u8> {
+            values.iter().flat_map(|sample| sample.to_le_bytes())
+                .chain(integers.iter().flat_map(|sample| sample.to_le_bytes()))
+                .chain(other.iter().flat_map(|sample| sample.to_le_bytes()))
+                .collect()
+        };
+
+        let mut block = UncompressedBlock {
+            index: BlockIndex { layer: 0, pixel_position: Vec2(0, 0), pixel_size: Vec2(2, 1), level: Vec2(0, 0) },
+            data: samples([ 1.75, 1.875 ], [ 7, 9 ], [ 1.75, 1.875 ]),
+        };
+
+        Quantization::all_channels(1).with_channel("C", 2).quantize_block(&header, &mut block);
+        assert_eq!(block.data, samples([ 1.5, 1.5 ], [ 7, 9 ], [ 1.75, 1.75 ]));
+    }
+
+    #[test]
+    fn quantize_subsampled_channels() {
+        // the header validation rejects subsampling, so the header is not built with the checked builder
+        let header = Header::new(Text::from("subsampled"), Vec2(4, 2), smallvec![
+            ChannelDescription::named("A", SampleType::F32),
+            ChannelDescription { sampling: Vec2(2, 2), .. ChannelDescription::named("Z", SampleType::F32) },
+        ]);
+
+        // the first row contains four alpha samples and two depth samples, the second row only contains four alpha samples
+        let block = |samples: [f32; 10]| UncompressedBlock {
+            index: BlockIndex { layer: 0, pixel_position: Vec2(0, 0), pixel_size: Vec2(4, 2), level: Vec2(0, 0) },
+            data: samples.iter().flat_map(|sample| sample.to_le_bytes()).collect(),
+        };
+
+        let mut quantized = block([ 1.75; 10 ]);
+        Quantization::new().with_channel("Z", 1).quantize_block(&header, &mut quantized);
+        assert_eq!(quantized, block([ 1.75, 1.75, 1.75, 1.75,  1.5, 1.5,  1.75, 1.75, 1.75, 1.75 ]));
+    }
+}
@@ -0,0 +1,265 @@
+//! Write channels under different names than they have in your storage.
+
+use crate::prelude::*;
+use crate::meta::{header::*, attribute::*};
+use crate::math::RoundingMode;
+use crate::block::*;
+use crate::image::write::channels::*;
+use crate::image::write::dither::Dithering;
+use crate::error::{Result, Error};
+
+
+/// Writes the wrapped channels with different names in the file,
+/// for example to store `albedo.R` as `diffuse.red` to match a legacy naming convention.
+/// The channels are automatically reordered to be alphabetical by their new names.
+/// Only if the new names change the order of the channels, the lines of each block are copied into the new order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenamedChannels<Channels> {
+    channels: Channels,
+    renames: SmallVec<[(Text, Text); 4]>,
+}
+
+impl<Channels> RenamedChannels<Channels> {
+
+    /// Write the specified channels, but rename them according to the specified `(storage name, file name)` pairs.
+    /// Channels that are not mentioned keep their original name.
+    /// Returns an error if a storage name does not exist in the channels,
+    /// or if two channels would end up with the same name in the file.
+    pub fn new<'s>(
+        channels: Channels,
+        renames: impl IntoIterator<Item=(impl Into<Text>, impl Into<Text>)>
+    ) -> Result<Self> where Channels: WritableChannels<'s>
+    {
+        let renames: SmallVec<[(Text, Text); 4]> = renames.into_iter()
+            .map(|(from, to)| (from.into(), to.into()))
+            .collect();
+
+        let original_list = channels.infer_channel_list();
+
+        for (index, (from, _)) in renames.iter().enumerate() {
+            if original_list.list.iter().all(|channel| &channel.name != from) {
+                return Err(Error::invalid(format!("cannot rename channel `{}` because it does not exist", from)));
+            }
+
+            if renames[.. index].iter().any(|(previous, _)| previous == from) {
+                return Err(Error::invalid(format!("channel `{}` is renamed more than once", from)));
+            }
+        }
+
+        let renamed = Self { channels, renames };
+
+        let mut new_names: SmallVec<[Text; 8]> = original_list.list.iter()
+            .map(|channel| renamed.file_name(&channel.name).clone())
+            .collect();
+
+        new_names.sort_unstable();
+
+        if let Some(duplicate) = new_names.iter().zip(new_names.iter().skip(1)).find(|(prev, next)| prev == next) {
+            return Err(Error::invalid(format!("more than one channel would be named `{}`", duplicate.0)));
+        }
+
+        Ok(renamed)
+    }
+
+    /// The name that the channel with the specified storage name will have in the file.
+    pub fn file_name<'n>(&'n self, storage_name: &'n Text) -> &'n Text {
+        self.renames.iter()
+            .find(|(from, _)| from == storage_name)
+            .map_or(storage_name, |(_, to)| to)
+    }
+
+    /// Discard the renaming, returning the original channels.
+    pub fn into_inner(self) -> Channels { self.channels }
+}
+
+impl<'s, Channels> WritableChannels<'s> for RenamedChannels<Channels> where Channels: WritableChannels<'s> {
+    fn infer_channel_list(&self) -> ChannelList {
+        let mut list: SmallVec<[ChannelDescription; 5]> = self.channels.infer_channel_list().list.into_iter()
+            .map(|channel| ChannelDescription { name: self.file_name(&channel.name).clone(), .. channel })
+            .collect();
+
+        list.sort_unstable_by_key(|channel| channel.name.clone()); // TODO no clone?
+        ChannelList::new(list)
+    }
+
+    fn infer_level_modes(&self) -> (LevelMode, RoundingMode) {
+        self.channels.infer_level_modes()
+    }
+
+    type Writer = RenamedChannelsWriter<Channels::Writer>;
+
+    fn create_writer(&'s self, header: &Header) -> Result<Self::Writer> {
+        let storage_header = Header { channels: self.channels.infer_channel_list(), .. header.clone() };
+
+        if header.channels.list.len() != storage_header.channels.list.len() {
+            return Err(Error::invalid("header does not contain the renamed channels"));
+        }
+
+        let storage_channel_indices = header.channels.list.iter()
+            .map(|file_channel| {
+                storage_header.channels.list.iter()
+                    .position(|storage_channel| self.file_name(&storage_channel.name) == &file_channel.name)
+                    .ok_or_else(|| Error::invalid(format!("header channel `{}` is not one of the renamed channels", file_channel.name)))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(RenamedChannelsWriter {
+            channels_writer: self.channels.create_writer(&storage_header)?,
+            storage_header,
+            storage_channel_indices,
+        })
+    }
+}
+
+/// A temporary writer that reorders the lines of another channels writer.
+#[derive(Debug, Clone)]
+pub struct RenamedChannelsWriter<ChannelsWriter> {
+    channels_writer: ChannelsWriter,
+    storage_header: Header,
+
+    /// For each channel in the file, the index of the channel in the storage.
+    storage_channel_indices: SmallVec<[usize; 8]>,
+}
+
+impl<Writer> ChannelsWriter for RenamedChannelsWriter<Writer> where Writer: ChannelsWriter {
+    fn extract_uncompressed_block(&self, header: &Header, block_index: BlockIndex) -> Vec<u8> {
+        let storage_bytes = self.channels_writer.extract_uncompressed_block(&self.storage_header, block_index);
+        self.reorder_lines(header, block_index, storage_bytes)
+    }
+
+    fn extract_dithered_block(&self, header: &Header, block_index: BlockIndex, dithering: &Dithering) -> Vec<u8> {
+        let storage_dithering = self.storage_dithering(header, dithering);
+        let storage_bytes = self.channels_writer.extract_dithered_block(&self.storage_header, block_index, &storage_dithering);
+        self.reorder_lines(header, block_index, storage_bytes)
+    }
+}
+
+impl<Writer> RenamedChannelsWriter<Writer> {
+
+    /// The dithering selects channels by their name in the file,
+    /// but the wrapped writer only knows the name of the channels in the storage.
+    fn storage_dithering(&self, header: &Header, dithering: &Dithering) -> Dithering {
+        let channels = header.channels.list.iter().zip(&self.storage_channel_indices)
+            .filter(|(file_channel, _)| dithering.is_dithered(&file_channel.name))
+            .map(|(_, &storage_index)| self.storage_header.channels.list[storage_index].name.clone())
+            .collect();
+
+        Dithering { channels, all_channels: dithering.all_channels }
+    }
+
+    /// Move the lines of the storage block to the position of their renamed channel.
+    fn reorder_lines(&self, header: &Header, block_index: BlockIndex, storage_bytes: Vec<u8>) -> Vec<u8> {
+        let is_reordered = self.storage_channel_indices.iter().enumerate()
+            .any(|(file_index, &storage_index)| file_index != storage_index);
+
+        if !is_reordered { return storage_bytes; }
+
+        let storage_lines = BlockLines::new(block_index, &self.storage_header.channels);
+
+        UncompressedBlock::collect_block_data_from_lines(&header.channels, block_index, |line| {
+            let storage_channel = self.storage_channel_indices[line.location.channel];
+            line.value.copy_from_slice(storage_lines.line(&storage_bytes, line.location.position.y(), storage_channel));
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::prelude::*;
+    use crate::image::write::rename::RenamedChannels;
+    use std::io::Cursor;
+
+    fn channels() -> AnyChannels<FlatSamples> {
+        AnyChannels::sort(smallvec![
+            AnyChannel::new("A", FlatSamples::F32(vec![1.0, 2.0, 3.0, 4.0])),
+            AnyChannel::new("B", FlatSamples::U32(vec![5, 6, 7, 8])),
+            AnyChannel::new("C", FlatSamples::F16(vec![f16::ONE; 4])),
+        ])
+    }
+
+    #[test]
+    fn rename_and_reorder() {
+        let renamed = RenamedChannels::new(channels(), vec![("A", "Z"), ("C", "0")]).unwrap();
+        let image = Image::from_channels((2, 2), renamed);
+
+        let mut bytes = Vec::new();
+        image.write().non_parallel().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        let image = read().no_deep_data().largest_resolution_level().all_channels()
+            .first_valid_layer().all_attributes().non_parallel()
+            .from_buffered(Cursor::new(&bytes)).unwrap();
+
+        let original = channels();
+        let channels = &image.layer_data.channel_data;
+
+        let names: Vec<String> = channels.list.iter().map(|channel| channel.name.to_string()).collect();
+        assert_eq!(names, ["0", "B", "Z"]);
+
+        assert_eq!(channels.channel_plane("Z"), original.channel_plane("A"));
+        assert_eq!(channels.channel_plane("B"), original.channel_plane("B"));
+        assert_eq!(channels.channel_plane("0"), original.channel_plane("C"));
+    }
+
+    #[test]
+    fn rename_without_reordering() {
+        let renamed = RenamedChannels::new(channels(), vec![("B", "B2")]).unwrap();
+        let image = Image::from_channels((2, 2), renamed);
+
+        let mut bytes = Vec::new();
+        image.write().non_parallel().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+        let image = read().no_deep_data().largest_resolution_level().all_channels()
+            .first_valid_layer().all_attributes().non_parallel()
+            .from_buffered(Cursor::new(&bytes)).unwrap();
+
+        let original = channels();
+        let channels = &image.layer_data.channel_data;
+        assert_eq!(channels.channel_plane("B2"), original.channel_plane("B"));
+        assert_eq!(channels.channel_plane("C"), original.channel_plane("C"));
+    }
+
+    #[test]
+    fn dither_renamed_channels() {
+        use crate::image::write::dither::Dithering;
+
+        let channels = SpecificChannels::build()
+            .with_channel_details::<f32>(ChannelDescription::named("Y", SampleType::F16))
+            .with_pixel_fn(|position: Vec2<usize>| (1.0 + position.x() as f32 * 0.001,));
+
+        fn read_samples(bytes: &[u8]) -> FlatSamples {
+            let image = read().no_deep_data().largest_resolution_level().all_channels()
+                .first_valid_layer().all_attributes().non_parallel()
+                .from_buffered(Cursor::new(bytes)).unwrap();
+
+            image.layer_data.channel_data.list[0].sample_data.clone()
+        }
+
+        let (mut renamed_bytes, mut original_bytes, mut undithered_bytes) = (Vec::new(), Vec::new(), Vec::new());
+        let renamed = RenamedChannels::new(channels.clone(), vec![("Y", "L")]).unwrap();
+
+        Image::from_channels((64, 4), renamed).write().non_parallel().dither_f16_samples(Dithering::channels(["L"]))
+            .to_buffered(Cursor::new(&mut renamed_bytes)).unwrap();
+
+        Image::from_channels((64, 4), channels.clone()).write().non_parallel().dither_f16_samples(Dithering::channels(["Y"]))
+            .to_buffered(Cursor::new(&mut original_bytes)).unwrap();
+
+        Image::from_channels((64, 4), channels).write().non_parallel().dither_f16_samples(Dithering::channels(["L"]))
+            .to_buffered(Cursor::new(&mut undithered_bytes)).unwrap();
+
+        let (renamed_samples, original_samples) = (read_samples(&renamed_bytes), read_samples(&original_bytes));
+        let undithered_samples = read_samples(&undithered_bytes);
+
+        assert_eq!(renamed_samples, original_samples);
+        assert_ne!(renamed_samples, undithered_samples);
+    }
+
+    #[test]
+    fn report_conflicts() {
+        assert!(RenamedChannels::new(channels(), vec![("A", "B")]).is_err(), "duplicate name");
+        assert!(RenamedChannels::new(channels(), vec![("A", "X"), ("C", "X")]).is_err(), "duplicate name");
+        assert!(RenamedChannels::new(channels(), vec![("A", "X"), ("A", "Y")]).is_err(), "renamed twice");
+        assert!(RenamedChannels::new(channels(), vec![("X", "Y")]).is_err(), "missing channel");
+        assert!(RenamedChannels::new(channels(), vec![("A", "B"), ("B", "A")]).is_ok(), "swapped names");
+    }
+}
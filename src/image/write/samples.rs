@@ -5,6 +5,7 @@ use crate::meta::header::Header;
 use crate::block::lines::LineRefMut;
 use crate::image::{FlatSamples, Levels, RipMaps};
 use crate::math::{Vec2, RoundingMode};
+use std::borrow::Cow;
 use crate::meta::{rip_map_levels, mip_map_levels, rip_map_indices, mip_map_indices, BlockDescription};
 
 /// Enable an image with this sample grid to be written to a file.
@@ -96,6 +97,31 @@ impl<'samples> WritableLevel<'samples> for FlatSamples {
     }
 }
 
+// used if the samples are either borrowed from another image or owned,
+// for example after replacing only some of the channels of a borrowed image
+impl<'slf, 'cow: 'slf, Samples> WritableSamples<'slf> for Cow<'cow, Samples>
+    where Samples: 'cow + Clone + WritableSamples<'slf>
+{
+    fn sample_type(&self) -> SampleType { Samples::sample_type(self) }
+    fn infer_level_modes(&self) -> (LevelMode, RoundingMode) { Samples::infer_level_modes(self) }
+
+    type Writer = Samples::Writer;
+    fn create_samples_writer(&'slf self, header: &Header) -> Self::Writer {
+        Samples::create_samples_writer(self, header)
+    }
+}
+
+impl<'slf, 'cow: 'slf, Samples> WritableLevel<'slf> for Cow<'cow, Samples>
+    where Samples: 'cow + Clone + WritableLevel<'slf>
+{
+    fn sample_type(&self) -> SampleType { Samples::sample_type(self) }
+
+    type Writer = Samples::Writer;
+    fn create_level_writer(&'slf self, size: Vec2<usize>) -> Self::Writer {
+        Samples::create_level_writer(self, size)
+    }
+}
+
 impl<'samples> SamplesWriter for FlatSamplesWriter<'samples> {
     fn extract_line(&self, line: LineRefMut<'_>) {
         let image_width = self.resolution.width(); // header.layer_size.width();
@@ -0,0 +1,279 @@
+//! Check the samples of each block while writing an image,
+//! such that invalid render output is noticed when the file is written.
+//!
+//! Create a `SampleValidation` containing one validator per channel rule,
+//! for example rejecting `NaN` in the alpha channel or requiring a positive depth,
+//! and pass it to `image.write().validate_samples(validation)`.
+//! Each validator either aborts writing, clamps the sample, or only counts the violation.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use half::f16;
+use crate::block::UncompressedBlock;
+use crate::error::{Error, UnitResult};
+use crate::math::Vec2;
+use crate::meta::attribute::{SampleType, Text};
+use crate::meta::header::Header;
+
+
+/// A list of validators, which are applied to the samples of each block before it is compressed.
+/// Counts the violations of each validator, which can be inspected after writing the image.
+#[derive(Debug, Default)]
+pub struct SampleValidation {
+    validators: Vec<SampleValidator>,
+    violations: Vec<AtomicUsize>,
+}
+
+/// A rule for the samples of all channels with a specific name, in all layers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampleValidator {
+
+    /// The name of the validated channel, for example `A` or `Z`.
+    pub channel_name: Text,
+
+    /// The condition that each sample must satisfy.
+    pub rule: SampleRule,
+
+    /// What to do with samples that do not satisfy the rule.
+    pub on_violation: OnViolation,
+}
+
+/// A condition that each sample of a channel must satisfy.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SampleRule {
+
+    /// The sample must not be `NaN`. Clamping replaces `NaN` with zero.
+    NotNan,
+
+    /// The sample must neither be `NaN` nor infinite.
+    /// Clamping replaces `NaN` with zero, and infinity with the largest finite value of the sample type.
+    Finite,
+
+    /// The sample must be within the inclusive range. Use infinity for unbounded sides.
+    /// Clamping moves the sample into the range, and replaces `NaN` with the value in the range that is closest to zero.
+    Range {
+
+        /// The smallest valid sample.
+        min: f64,
+
+        /// The largest valid sample.
+        max: f64,
+    },
+}
+
+/// What to do with a sample that violates a rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OnViolation {
+
+    /// Abort writing the image with an error.
+    Error,
+
+    /// Replace the sample with a valid value, and count the violation.
+    Clamp,
+
+    /// Keep the sample, but count the violation, such that a warning can be shown after writing.
+    Warn,
+}
+
+
+impl SampleRule {
+
+    /// The sample must be at least `min`, for example zero for depth channels.
+    pub fn at_least(min: f64) -> Self { SampleRule::Range { min, max: f64::INFINITY } }
+
+    /// The sample must be within the range, for example from zero to one for alpha channels.
+    pub fn range(min: f64, max: f64) -> Self { SampleRule::Range { min, max } }
+
+    /// Whether the sample satisfies this rule.
+    pub fn is_satisfied_by(self, sample: f64) -> bool {
+        match self {
+            SampleRule::NotNan => !sample.is_nan(),
+            SampleRule::Finite => sample.is_finite(),
+            SampleRule::Range { min, max } => sample >= min && sample <= max,
+        }
+    }
+
+    /// A valid replacement for the invalid sample.
+    /// The `largest` value is the largest finite value of the sample type.
+    fn clamp(self, sample: f64, largest: f64) -> f64 {
+        let clamped = match self {
+            SampleRule::NotNan => 0.0,
+            SampleRule::Finite if sample.is_nan() => 0.0,
+            SampleRule::Finite => sample.signum() * largest,
+            SampleRule::Range { min, max } if sample.is_nan() => 0.0_f64.max(min).min(max),
+            SampleRule::Range { min, max } => sample.max(min).min(max),
+        };
+
+        clamped.max(-largest).min(largest)
+    }
+}
+
+impl SampleValidation {
+
+    /// A validation without any rules, which accepts all samples.
+    pub fn new() -> Self { Self::default() }
+
+    /// Add a rule for all channels with the specified name.
+    pub fn with_rule(mut self, channel_name: impl Into<Text>, rule: SampleRule, on_violation: OnViolation) -> Self {
+        self.validators.push(SampleValidator { channel_name: channel_name.into(), rule, on_violation });
+        self.violations.push(AtomicUsize::new(0));
+        self
+    }
+
+    /// All validators, in the order they were added.
+    pub fn validators(&self) -> &[SampleValidator] { &self.validators }
+
+    /// The number of invalid samples found by each validator so far, in the order the validators were added.
+    /// With `OnViolation::Error`, at most one violation is counted per block.
+    pub fn violation_counts(&self) -> Vec<usize> {
+        self.violations.iter().map(|count| count.load(Ordering::Relaxed)).collect()
+    }
+
+    /// The number of invalid samples found by all validators so far.
+    pub fn total_violation_count(&self) -> usize {
+        self.violation_counts().into_iter().sum()
+    }
+
+    /// Check all samples of the block, and clamp invalid samples if specified.
+    /// Returns an error at the first sample that violates a rule with `OnViolation::Error`.
+    pub fn validate_block(&self, header: &Header, block: &mut UncompressedBlock) -> UnitResult {
+        if self.validators.is_empty() || header.deep { return Ok(()); }
+
+        for line in block.lines_mut(&header.channels) {
+            let channel = &header.channels.list[line.location.channel];
+
+            let validators = self.validators.iter().zip(&self.violations)
+                .filter(|(validator, _)| validator.channel_name == channel.name);
+
+            for (validator, violations) in validators {
+                let invalid_x = validate_samples(line.value, channel.sample_type, validator, violations);
+
+                if let Some(x) = invalid_x {
+                    let position = line.location.position + Vec2(x * channel.sampling.x(), 0);
+
+                    return Err(Error::invalid(format!(
+                        "sample of channel `{}` at pixel ({}, {}) of layer {} violates the rule {:?}",
+                        channel.name, position.x(), position.y(), line.location.layer, validator.rule
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Check and possibly clamp each sample in a line of a single channel.
+/// Returns the index of the first invalid sample, if the validator requires an error.
+fn validate_samples(samples: &mut [u8], sample_type: SampleType, validator: &SampleValidator, violations: &AtomicUsize) -> Option<usize> {
+    let largest = match sample_type {
+        SampleType::F16 => f16::MAX.to_f64(),
+        SampleType::F32 => f32::MAX as f64,
+        SampleType::U32 => u32::MAX as f64,
+    };
+
+    let mut violation_count = 0;
+    let mut invalid_x = None;
+
+    for (x, sample) in samples.chunks_exact_mut(sample_type.bytes_per_sample()).enumerate() {
+        let value = match sample_type {
+            SampleType::F16 => f16::from_le_bytes([sample[0], sample[1]]).to_f64(),
+            SampleType::F32 => f32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]) as f64,
+            SampleType::U32 => u32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]) as f64,
+        };
+
+        if validator.rule.is_satisfied_by(value) { continue; }
+        violation_count += 1;
+
+        match validator.on_violation {
+            OnViolation::Error => { invalid_x = Some(x); break; },
+            OnViolation::Warn => {},
+            OnViolation::Clamp => {
+                let clamped = validator.rule.clamp(value, largest);
+
+                match sample_type {
+                    SampleType::F16 => sample.copy_from_slice(&f16::from_f64(clamped).to_le_bytes()),
+                    SampleType::F32 => sample.copy_from_slice(&(clamped as f32).to_le_bytes()),
+                    SampleType::U32 => sample.copy_from_slice(&(clamped as u32).to_le_bytes()),
+                }
+            },
+        }
+    }
+
+    violations.fetch_add(violation_count, Ordering::Relaxed);
+    invalid_x
+}
+
+impl PartialEq for SampleValidation {
+    fn eq(&self, other: &Self) -> bool {
+        self.validators == other.validators
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::block::BlockIndex;
+    use crate::meta::attribute::ChannelDescription;
+
+    fn block(alpha: [f32; 3], depth: [f16; 3]) -> UncompressedBlock {
+        let mut data: Vec<u8> = alpha.iter().flat_map(|sample| sample.to_le_bytes()).collect();
+        data.extend(depth.iter().flat_map(|sample| sample.to_le_bytes()));
+
+        UncompressedBlock {
+            index: BlockIndex { layer: 0, pixel_position: Vec2(0, 0), pixel_size: Vec2(3, 1), level: Vec2(0, 0) },
+            data,
+        }
+    }
+
+    #[test]
+    fn validate_clamp_and_count_samples() {
+        let header = Header::builder()
+            .layer_size((3, 1))
+            .channel(ChannelDescription::named("A", SampleType::F32))
+            .channel(ChannelDescription::named("Z", SampleType::F16))
+            .build().unwrap();
+
+        let validation = SampleValidation::new()
+            .with_rule("A", SampleRule::Finite, OnViolation::Clamp)
+            .with_rule("Z", SampleRule::at_least(0.0), OnViolation::Warn);
+
+        let mut clamped = block([ f32::NAN, 0.5, f32::NEG_INFINITY ], [ f16::ONE, f16::from_f32(-2.0), f16::NAN ]);
+        validation.validate_block(&header, &mut clamped).unwrap();
+
+        assert_eq!(clamped, block([ 0.0, 0.5, -f32::MAX ], [ f16::ONE, f16::from_f32(-2.0), f16::NAN ]));
+        assert_eq!(validation.violation_counts(), vec![ 2, 2 ]);
+        assert_eq!(validation.total_violation_count(), 4);
+
+        let strict = SampleValidation::new().with_rule("Z", SampleRule::NotNan, OnViolation::Error);
+        assert!(strict.validate_block(&header, &mut block([ 0.0; 3 ], [ f16::ZERO; 3 ])).is_ok());
+        assert!(strict.validate_block(&header, &mut block([ 0.0; 3 ], [ f16::ZERO, f16::NAN, f16::ZERO ])).is_err());
+        assert_eq!(strict.violation_counts(), vec![ 1 ]);
+    }
+
+    #[test]
+    fn validate_subsampled_channels() {
+        // the header validation rejects subsampling, so the header is not built with the checked builder
+        let header = Header::new(Text::from("subsampled"), Vec2(4, 2), smallvec![
+            ChannelDescription::named("A", SampleType::F32),
+            ChannelDescription { sampling: Vec2(2, 2), .. ChannelDescription::named("Z", SampleType::F32) },
+        ]);
+
+        // the first row contains four alpha samples and two depth samples, the second row only contains four alpha samples
+        let block = |samples: [f32; 10]| UncompressedBlock {
+            index: BlockIndex { layer: 0, pixel_position: Vec2(0, 0), pixel_size: Vec2(4, 2), level: Vec2(0, 0) },
+            data: samples.iter().flat_map(|sample| sample.to_le_bytes()).collect(),
+        };
+
+        let validation = SampleValidation::new()
+            .with_rule("A", SampleRule::at_least(0.0), OnViolation::Clamp)
+            .with_rule("Z", SampleRule::NotNan, OnViolation::Error);
+
+        let mut clamped = block([ 0.0, 1.0, 2.0, 3.0,  5.0, 6.0,  -1.0, 1.0, 2.0, -3.0 ]);
+        validation.validate_block(&header, &mut clamped).unwrap();
+        assert_eq!(clamped, block([ 0.0, 1.0, 2.0, 3.0,  5.0, 6.0,  0.0, 1.0, 2.0, 0.0 ]));
+
+        let error = validation.validate_block(&header, &mut block([ 0.0, 1.0, 2.0, 3.0,  5.0, f32::NAN,  0.0, 1.0, 2.0, 3.0 ]));
+        assert!(error.unwrap_err().to_string().contains("at pixel (2, 0)"));
+    }
+}
@@ -8,13 +8,18 @@ pub use ::std::io::{Read, Write};
 use half::slice::{HalfFloatSliceExt};
 use lebe::prelude::*;
 use ::half::f16;
-use crate::error::{Error, Result, UnitResult, IoResult};
-use std::io::{Seek, SeekFrom};
+use crate::error::{Error, Result, UnitResult, IoResult, usize_to_u64};
+use std::io::{Seek, SeekFrom, IoSlice};
 use std::path::Path;
 use std::fs::File;
 use std::convert::TryFrom;
+use std::borrow::Cow;
 
 
+/// The size of the buffer used when reading or writing an unbuffered byte stream,
+/// unless specified otherwise. The same as the default of the standard library.
+pub const DEFAULT_BUFFER_SIZE: usize = 8 * 1024;
+
 /// Skip reading uninteresting bytes without allocating.
 #[inline]
 pub fn skip_bytes(read: &mut impl Read, count: usize) -> IoResult<()> {
@@ -37,13 +42,44 @@ pub fn skip_bytes(read: &mut impl Read, count: usize) -> IoResult<()> {
     Ok(())
 }
 
+/// Write all bytes of both slices, using a single vectored write where possible.
+/// Large slices are passed directly to the destination, instead of being copied into a buffer first.
+#[inline]
+pub fn write_all_vectored(write: &mut impl Write, first: &[u8], second: &[u8]) -> IoResult<()> {
+    let mut written = 0;
+    let total = first.len() + second.len();
+
+    while written < total {
+        let count = if written < first.len() {
+            write.write_vectored(&[ IoSlice::new(&first[written ..]), IoSlice::new(second) ])
+        }
+        else {
+            write.write(&second[written - first.len() ..])
+        };
+
+        match count {
+            Ok(0) => return Err(std::io::Error::new(std::io::ErrorKind::WriteZero, "failed to write whole buffer")),
+            Ok(count) => written += count,
+            Err(error) if error.kind() == std::io::ErrorKind::Interrupted => {},
+            Err(error) => return Err(error),
+        }
+    }
+
+    Ok(())
+}
+
 /// If an error occurs while writing, attempts to delete the partially written file.
 /// Creates a file just before the first write operation, not when this function is called.
+/// Returns `Error::File` if the file cannot be created, without deleting any existing file.
 #[inline]
 pub fn attempt_delete_file_on_write_error<'p>(path: &'p Path, write: impl FnOnce(LateFile<'p>) -> UnitResult) -> UnitResult {
     match write(LateFile::from(path)) {
-        Err(error) => { // FIXME deletes existing file if creation of new file fails?
-            let _deleted = std::fs::remove_file(path); // ignore deletion errors
+        Err(Error::Io(error)) if FileCreationError::is_inside(&error) => {
+            Err(file_error(path, FileCreationError::unwrap(error)))
+        },
+
+        Err(error) => {
+            let _deleted = std::fs::remove_file(extended_length_path(path)); // ignore deletion errors
             Err(error)
         },
 
@@ -51,6 +87,118 @@ pub fn attempt_delete_file_on_write_error<'p>(path: &'p Path, write: impl FnOnce
     }
 }
 
+/// Open a file for reading. Supports long paths on windows.
+/// Returns `Error::File` if the file cannot be opened.
+pub fn open_file(path: &Path) -> Result<File> {
+    File::open(extended_length_path(path))
+        .map_err(|error| file_error(path, error))
+}
+
+/// Wrap an error that occurred while opening or creating the file at the path.
+pub fn file_error(path: &Path, error: std::io::Error) -> Error {
+    Error::File { path: path.to_path_buf(), source: error }
+}
+
+/// On windows, absolute paths are limited to 260 characters,
+/// unless they are converted to extended-length paths with the `\\?\` prefix.
+/// Longer absolute paths are converted. Other paths are not changed.
+#[cfg(windows)]
+pub fn extended_length_path(path: &Path) -> Cow<'_, Path> {
+    use std::path::{Component, Prefix, PathBuf};
+    use std::ffi::OsString;
+
+    // extended-length paths are not normalized by windows, so `..` would not be resolved
+    let is_long = path.as_os_str().len() >= 260;
+    if !is_long || !path.is_absolute() || path.components().any(|component| component == Component::ParentDir) {
+        return Cow::Borrowed(path);
+    }
+
+    let mut components = path.components();
+    let mut extended = match components.next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::Disk(_) => {
+                let mut extended = OsString::from(r"\\?\");
+                extended.push(prefix.as_os_str());
+                extended
+            },
+
+            Prefix::UNC(server, share) => {
+                let mut extended = OsString::from(r"\\?\UNC\");
+                extended.push(server);
+                extended.push(r"\");
+                extended.push(share);
+                extended
+            },
+
+            _ => return Cow::Borrowed(path), // already an extended-length path, or a device
+        },
+
+        _ => return Cow::Borrowed(path),
+    };
+
+    // also replaces `/` with `\`, which is not supported in extended-length paths
+    let mut requires_separator = false;
+    for component in components {
+        match component {
+            Component::RootDir => {
+                extended.push(r"\");
+                requires_separator = false;
+            },
+
+            Component::Normal(name) => {
+                if requires_separator { extended.push(r"\"); }
+                extended.push(name);
+                requires_separator = true;
+            },
+
+            _ => {}, // skip `.`
+        }
+    }
+
+    Cow::Owned(PathBuf::from(extended))
+}
+
+/// On windows, absolute paths are limited to 260 characters,
+/// unless they are converted to extended-length paths.
+/// On other platforms, the path is not changed.
+#[cfg(not(windows))]
+pub fn extended_length_path(path: &Path) -> Cow<'_, Path> {
+    Cow::Borrowed(path)
+}
+
+/// Marks an error that happened while creating a `LateFile`,
+/// so that it can be distinguished from errors while writing the file.
+#[derive(Debug)]
+struct FileCreationError(std::io::Error);
+
+impl FileCreationError {
+    fn wrap(error: std::io::Error) -> std::io::Error {
+        std::io::Error::new(error.kind(), FileCreationError(error))
+    }
+
+    fn is_inside(error: &std::io::Error) -> bool {
+        error.get_ref().map_or(false, |inner| inner.is::<FileCreationError>())
+    }
+
+    fn unwrap(error: std::io::Error) -> std::io::Error {
+        let kind = error.kind();
+
+        match error.into_inner().map(|inner| inner.downcast::<FileCreationError>()) {
+            Some(Ok(creation_error)) => creation_error.0,
+            Some(Err(other)) => std::io::Error::new(kind, other),
+            None => std::io::Error::from(kind),
+        }
+    }
+}
+
+impl std::fmt::Display for FileCreationError {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(formatter)
+    }
+}
+
+impl std::error::Error for FileCreationError {}
+
 #[derive(Debug)]
 pub struct LateFile<'p> {
     path: &'p Path,
@@ -63,7 +211,11 @@ impl<'p> From<&'p Path> for LateFile<'p> {
 
 impl<'p> LateFile<'p> {
     fn file(&mut self) -> std::io::Result<&mut File> {
-        if self.file.is_none() { self.file = Some(File::create(self.path)?); }
+        if self.file.is_none() {
+            let file = File::create(extended_length_path(self.path)).map_err(FileCreationError::wrap)?;
+            self.file = Some(file);
+        }
+
         Ok(self.file.as_mut().unwrap()) // will not be reached if creation fails
     }
 }
@@ -73,6 +225,10 @@ impl<'p> std::io::Write for LateFile<'p> {
         self.file()?.write(buffer)
     }
 
+    fn write_vectored(&mut self, buffers: &[IoSlice<'_>]) -> std::io::Result<usize> {
+        self.file()?.write_vectored(buffers)
+    }
+
     fn flush(&mut self) -> std::io::Result<()> {
         if let Some(file) = &mut self.file { file.flush() }
         else { Ok(()) }
@@ -152,11 +308,19 @@ impl<T: Read> Read for PeekRead<T> {
     }
 }
 
+impl<T> PeekRead<T> {
+
+    /// Obtain the inner reader, discarding any previously peeked value.
+    pub(crate) fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
 impl<T: Read + Seek> PeekRead<Tracking<T>> {
 
     /// Seek this read to the specified byte position.
     /// Discards any previously peeked value.
-    pub fn skip_to(&mut self, position: usize) -> std::io::Result<()> {
+    pub fn skip_to(&mut self, position: u64) -> std::io::Result<()> {
         self.inner.seek_read_to(position)?;
         self.peeked = None;
         Ok(())
@@ -166,26 +330,27 @@ impl<T: Read + Seek> PeekRead<Tracking<T>> {
 impl<T: Read> PeekRead<Tracking<T>> {
 
     /// Current number of bytes read.
-    pub fn byte_position(&self) -> usize {
+    pub fn byte_position(&self) -> u64 {
         self.inner.byte_position()
     }
 }
 
 /// Keep track of what byte we are at.
 /// Used to skip back to a previous place after writing some information.
+/// The position is a `u64` on all platforms, as files may be larger than the address space of 32-bit targets.
 #[derive(Debug)]
 pub struct Tracking<T> {
 
     /// Do not expose to prevent seeking without updating position
     inner: T,
 
-    position: usize,
+    position: u64,
 }
 
 impl<T: Read> Read for Tracking<T> {
     fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
         let count = self.inner.read(buffer)?;
-        self.position += count;
+        self.position += usize_to_u64(count);
         Ok(count)
     }
 }
@@ -193,7 +358,13 @@ impl<T: Read> Read for Tracking<T> {
 impl<T: Write> Write for Tracking<T> {
     fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> {
         let count = self.inner.write(buffer)?;
-        self.position += count;
+        self.position += usize_to_u64(count);
+        Ok(count)
+    }
+
+    fn write_vectored(&mut self, buffers: &[IoSlice<'_>]) -> std::io::Result<usize> {
+        let count = self.inner.write_vectored(buffers)?;
+        self.position += usize_to_u64(count);
         Ok(count)
     }
 
@@ -211,26 +382,36 @@ impl<T> Tracking<T> {
     }
 
     /// Current number of bytes written or read.
-    pub fn byte_position(&self) -> usize {
+    pub fn byte_position(&self) -> u64 {
         self.position
     }
+
+    /// Obtain the inner reader or writer, at the current byte position.
+    pub(crate) fn into_inner(self) -> T {
+        self.inner
+    }
 }
 
 impl<T: Read + Seek> Tracking<T> {
 
     /// Set the reader to the specified byte position.
     /// If it is only a couple of bytes, no seek system call is performed.
-    pub fn seek_read_to(&mut self, target_position: usize) -> std::io::Result<()> {
-        let delta = target_position as i128 - self.position as i128; // FIXME  panicked at 'attempt to subtract with overflow'
-        debug_assert!(delta.abs() < usize::MAX as i128);
+    pub fn seek_read_to(&mut self, target_position: u64) -> std::io::Result<()> {
+        let forward_delta = target_position.checked_sub(self.position);
 
-        if delta > 0 && delta < 16 { // TODO profile that this is indeed faster than a syscall! (should be because of bufread buffer discard)
-            skip_bytes(self, delta as usize)?;
-            self.position += delta as usize;
-        }
-        else if delta != 0 {
-            self.inner.seek(SeekFrom::Start(u64::try_from(target_position).unwrap()))?;
-            self.position = target_position;
+        match forward_delta {
+            Some(0) => {},
+
+            // TODO profile that this is indeed faster than a syscall! (should be because of bufread buffer discard)
+            Some(delta) if delta < 16 => {
+                skip_bytes(&mut self.inner, delta as usize)?; // cast is safe because delta is small
+                self.position = target_position;
+            },
+
+            _ => {
+                self.inner.seek(SeekFrom::Start(target_position))?;
+                self.position = target_position;
+            },
         }
 
         Ok(())
@@ -241,20 +422,128 @@ impl<T: Write + Seek> Tracking<T> {
 
     /// Move the writing cursor to the specified target byte index.
     /// If seeking forward, this will write zeroes.
-    pub fn seek_write_to(&mut self, target_position: usize) -> std::io::Result<()> {
+    pub fn seek_write_to(&mut self, target_position: u64) -> std::io::Result<()> {
         if target_position < self.position {
-            self.inner.seek(SeekFrom::Start(u64::try_from(target_position).unwrap()))?;
+            self.inner.seek(SeekFrom::Start(target_position))?;
         }
         else if target_position > self.position {
             std::io::copy(
-                &mut std::io::repeat(0).take(u64::try_from(target_position - self.position).unwrap()),
-                self
+                &mut std::io::repeat(0).take(target_position - self.position),
+                &mut self.inner
             )?;
         }
 
         self.position = target_position;
         Ok(())
     }
+
+    /// Move the writing cursor to a byte index that has already been written.
+    /// Unlike `seek_write_to`, this never writes zeroes, so it can be used to jump forward over existing bytes.
+    pub fn seek_write_to_written(&mut self, target_position: u64) -> std::io::Result<()> {
+        if target_position != self.position {
+            self.inner.seek(SeekFrom::Start(target_position))?;
+            self.position = target_position;
+        }
+
+        Ok(())
+    }
+}
+
+/// Make a byte stream that cannot seek, such as standard input or a network socket, usable as a `Seek` reader.
+/// Seeking forward reads and discards the skipped bytes. Seeking backwards returns an error.
+/// This is enough to read an exr file, as long as the chunks are read in the order in which they are stored.
+#[derive(Debug)]
+pub struct ForwardSeek<T> {
+    inner: T,
+    position: u64,
+}
+
+impl<T> ForwardSeek<T> {
+
+    /// Wrap a stream that is located at the start of the file.
+    pub fn new(inner: T) -> Self {
+        ForwardSeek { inner, position: 0 }
+    }
+
+    /// Obtain the inner stream, at the current byte position.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: Read> Read for ForwardSeek<T> {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        let count = self.inner.read(buffer)?;
+        self.position += usize_to_u64(count);
+        Ok(count)
+    }
+}
+
+impl<T: Read> Seek for ForwardSeek<T> {
+    fn seek(&mut self, target: SeekFrom) -> std::io::Result<u64> {
+        let target_position = match target {
+            SeekFrom::Start(position) => Some(position),
+            SeekFrom::Current(delta) if delta >= 0 => self.position.checked_add(delta as u64),
+            SeekFrom::Current(delta) => self.position.checked_sub(delta.unsigned_abs()),
+            SeekFrom::End(_) => None,
+        };
+
+        let delta = target_position.and_then(|target_position| target_position.checked_sub(self.position))
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Other, "cannot seek backwards in a stream"))?;
+
+        let skipped = std::io::copy(&mut self.inner.by_ref().take(delta), &mut std::io::sink())?;
+        self.position += skipped;
+
+        if skipped < delta {
+            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "cannot skip more bytes than exist"));
+        }
+
+        Ok(self.position)
+    }
+}
+
+/// Make a byte destination that cannot seek, such as standard output or a pipe, usable as a `Seek` writer.
+/// Seeking to the current position succeeds. Seeking anywhere else returns an error.
+/// This is enough to write an exr file with `OffsetTablePlacement::Zeroed`, which never seeks back.
+#[derive(Debug)]
+pub struct ForwardWrite<T> {
+    inner: T,
+    position: u64,
+}
+
+impl<T> ForwardWrite<T> {
+
+    /// Wrap a stream that is located at the start of the file.
+    pub fn new(inner: T) -> Self {
+        ForwardWrite { inner, position: 0 }
+    }
+
+    /// Obtain the inner stream, at the current byte position.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: Write> Write for ForwardWrite<T> {
+    fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> {
+        let count = self.inner.write(buffer)?;
+        self.position += usize_to_u64(count);
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: Write> Seek for ForwardWrite<T> {
+    fn seek(&mut self, target: SeekFrom) -> std::io::Result<u64> {
+        match target {
+            SeekFrom::Start(position) if position == self.position => Ok(self.position),
+            SeekFrom::Current(0) => Ok(self.position),
+            _ => Err(std::io::Error::new(std::io::ErrorKind::Other, "cannot seek in a stream")),
+        }
+    }
 }
 
 
@@ -442,6 +731,81 @@ mod test {
 
         assert!(u8::read_from_little_endian(&mut peek).is_err());
     }
-}
 
+    #[test]
+    fn write_all_vectored_with_partial_writes(){
+        use crate::io::write_all_vectored;
+        use std::io::{Write, IoSlice};
+
+        /// Accepts at most three bytes per call, and only from the first slice.
+        struct Slow(Vec<u8>);
+
+        impl Write for Slow {
+            fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> {
+                let count = buffer.len().min(3);
+                self.0.extend_from_slice(&buffer[.. count]);
+                Ok(count)
+            }
+
+            fn write_vectored(&mut self, buffers: &[IoSlice<'_>]) -> std::io::Result<usize> {
+                self.write(buffers.iter().find(|buffer| !buffer.is_empty()).map_or(&[], |buffer| &buffer[..]))
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+        }
+
+        let mut slow = Slow(Vec::new());
+        write_all_vectored(&mut slow, &[0, 1, 2, 3, 4], &[5, 6, 7, 8, 9, 10, 11]).unwrap();
+        assert_eq!(slow.0, (0 .. 12).collect::<Vec<u8>>());
 
+        let mut fast = Vec::new();
+        write_all_vectored(&mut fast, &[], &[0, 1]).unwrap();
+        assert_eq!(fast, vec![0, 1]);
+    }
+
+    #[test]
+    fn track_positions_after_4gb(){
+        use crate::io::Tracking;
+        use std::io::{Cursor, Read, Write};
+
+        let far_position = 5 * 1024 * 1024 * 1024_u64;
+
+        // cursors can be seeked past their end without allocating
+        let mut read = Tracking::new(Cursor::new(vec![ 0_u8, 1, 2, 3 ]));
+        read.seek_read_to(far_position).unwrap();
+        assert_eq!(read.byte_position(), far_position);
+
+        read.seek_read_to(1).unwrap();
+        let mut buffer = [0_u8; 2];
+        read.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer, [1, 2]);
+        assert_eq!(read.byte_position(), 3);
+
+        read.seek_read_to(far_position + 3).unwrap();
+        assert_eq!(read.byte_position(), far_position + 3);
+
+        let mut write = Tracking::new(Cursor::new(Vec::new()));
+        write.write_all(&[ 0, 1, 2, 3 ]).unwrap();
+        write.seek_write_to_written(far_position).unwrap();
+        assert_eq!(write.byte_position(), far_position);
+
+        write.seek_write_to(2).unwrap();
+        write.write_all(&[ 7 ]).unwrap();
+        assert_eq!(write.byte_position(), 3);
+    }
+
+    #[test]
+    fn seek_read_forward_by_few_bytes(){
+        use crate::io::Tracking;
+        use std::io::{Cursor, Read};
+
+        let mut read = Tracking::new(Cursor::new((0 .. 32).collect::<Vec<u8>>()));
+        read.seek_read_to(5).unwrap();
+        assert_eq!(read.byte_position(), 5);
+
+        let mut byte = [0_u8];
+        read.read_exact(&mut byte).unwrap();
+        assert_eq!(byte, [5]);
+        assert_eq!(read.byte_position(), 6);
+    }
+}
@@ -1,7 +1,8 @@
 
 
 //! Read and write OpenEXR images.
-//! This library uses no foreign code or unsafe Rust.
+//! This library uses no foreign code. It uses no unsafe Rust, unless the optional `io-uring` feature is enabled,
+//! which adds the unsafe code in `block::uring` that submits reads to the linux kernel.
 //!
 //! See the [README.md](https://github.com/johannesvollmer/exrs/blob/master/README.md) for crate information.
 //! Read __the [GUIDE.md](https://github.com/johannesvollmer/exrs/blob/master/GUIDE.md) for a API introduction__.
@@ -33,9 +34,13 @@
     redundant_semicolons
 )]
 
-#![forbid(unsafe_code)]
+#![cfg_attr(not(feature = "io-uring"), forbid(unsafe_code))]
+#![cfg_attr(feature = "io-uring", deny(unsafe_code))]
 #![warn(missing_docs)]
 
+#[macro_use]
+mod trace;
+
 pub mod io; // public to allow for custom attribute byte parsing
 
 pub mod math;
@@ -46,6 +51,12 @@ pub mod image;
 pub mod error;
 pub mod block;
 
+#[cfg(feature = "dataset")]
+pub mod dataset;
+
+#[cfg(feature = "testing")]
+pub mod testing;
+
 #[macro_use]
 extern crate smallvec;
 
@@ -63,6 +74,7 @@ pub mod prelude {
         };
 
         pub use crate::image::crop::{Crop, CropWhere, CropResult, InspectSample, CroppedChannels, ApplyCroppedView};
+        pub use crate::image::pixel_struct::ExrPixel;
     }
 
     pub use traits::*;
@@ -73,20 +85,27 @@ pub mod prelude {
         read_all_rgba_layers_from_file,
         read_all_data_from_file,
         read_all_flat_layers_from_file,
-        read_first_flat_layer_from_file
+        read_first_flat_layer_from_file,
+        read_first_flat_layer_and_rgba_from_file
     };
+    pub use crate::image::read::specific_channels::ChannelMatching;
+    pub use crate::image::read::image::{PartialImage, ValidityMask};
 
     // image data structures
     pub use crate::image::*;
     pub use crate::meta::{ attribute, MetaData, header::{ LayerAttributes, ImageAttributes } };
     pub use crate::block::samples::Sample;
     pub use crate::meta::attribute::{
-        AttributeValue, Compression, Text, IntegerBounds,
+        AttributeValue, Compression, Text, TextEncoding, IntegerBounds,
         LineOrder, SampleType, TileDescription, ChannelDescription
     };
+    pub use crate::compression::SpeedBias;
+
+    #[cfg(feature = "derive")]
+    pub use exr_derive::ExrPixel;
 
     // common math
-    pub use crate::math::Vec2;
+    pub use crate::math::{Vec2, Vec3, Matrix4};
 
     // error handling
     pub use crate::error::{ Result, Error };
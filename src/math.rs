@@ -5,9 +5,9 @@
 //! Simple math utilities.
 
 use std::convert::TryFrom;
-use crate::error::{i32_to_usize};
+use crate::error::{i32_to_usize, Error};
 use crate::error::Result;
-use std::ops::{Add, Sub, Div, Mul};
+use std::ops::{Add, Sub, Div, Mul, Rem, AddAssign, SubAssign, MulAssign, DivAssign};
 use std::fmt::Debug;
 
 /// Simple two-dimensional vector of any numerical type.
@@ -28,6 +28,17 @@ impl<T> Vec2<T> {
         Vec2(self.0.min(other.0), self.1.min(other.1))
     }
 
+    /// Returns the vector with each coordinate limited to the range of the corresponding coordinates.
+    /// Panics if a minimum coordinate is larger than the maximum coordinate.
+    pub fn clamp(self, min: Self, max: Self) -> Self where T: Ord {
+        Vec2(self.0.clamp(min.0, max.0), self.1.clamp(min.1, max.1))
+    }
+
+    /// Apply a function to both coordinates, for example to round floating point coordinates.
+    pub fn map<R>(self, mut convert: impl FnMut(T) -> R) -> Vec2<R> {
+        Vec2(convert(self.0), convert(self.1))
+    }
+
     /// Try to convert all components of this vector to a new type,
     /// yielding either a vector of that new type, or an error.
     pub fn try_from<S>(value: Vec2<S>) -> std::result::Result<Self, T::Error> where T: TryFrom<S> {
@@ -40,6 +51,7 @@ impl<T> Vec2<T> {
 
     /// Seeing this vector as a dimension or size (width and height),
     /// this returns the area that this dimensions contains (`width * height`).
+    /// On 32-bit targets, the area of large sizes may overflow. Use `checked_area` for sizes from untrusted files.
     #[inline] pub fn area(self) -> T where T: std::ops::Mul<T, Output = T> {
         self.0 * self.1
     }
@@ -95,6 +107,26 @@ impl Vec2<usize> {
         Vec2(x, y)
     }
 
+    /// Try to convert to [`Vec2<i32>`], returning an error on values larger than `i32::MAX`.
+    pub fn try_to_i32(self, error_message: &'static str) -> Result<Vec2<i32>> {
+        Vec2::try_from(self).map_err(|_| Error::invalid(error_message))
+    }
+
+    /// The area of this size (`width * height`), or `None` if it exceeds `usize::MAX`.
+    /// This can happen for large images on 32-bit targets, such as `wasm32` or `armv7`.
+    #[inline]
+    pub fn checked_area(self) -> Option<usize> {
+        self.0.checked_mul(self.1)
+    }
+
+    /// The area of this size (`width * height`), or an error if it exceeds the address space of this machine.
+    #[inline]
+    pub fn try_area(self, purpose: &'static str) -> Result<usize> {
+        self.checked_area().ok_or_else(|| Error::unsupported(
+            format!("{} of {}x{} pixels exceeds the address space of this machine", purpose, self.0, self.1)
+        ))
+    }
+
 }
 
 
@@ -126,6 +158,78 @@ impl<T: std::ops::Mul<T>> std::ops::Mul<Vec2<T>> for Vec2<T> {
     }
 }
 
+impl<T: std::ops::Rem<T>> std::ops::Rem<Vec2<T>> for Vec2<T> {
+    type Output = Vec2<T::Output>;
+    fn rem(self, other: Vec2<T>) -> Self::Output {
+        Vec2(self.0 % other.0, self.1 % other.1)
+    }
+}
+
+impl<T: AddAssign<T>> AddAssign<Vec2<T>> for Vec2<T> {
+    fn add_assign(&mut self, other: Vec2<T>) { self.0 += other.0; self.1 += other.1; }
+}
+
+impl<T: SubAssign<T>> SubAssign<Vec2<T>> for Vec2<T> {
+    fn sub_assign(&mut self, other: Vec2<T>) { self.0 -= other.0; self.1 -= other.1; }
+}
+
+impl<T: MulAssign<T>> MulAssign<Vec2<T>> for Vec2<T> {
+    fn mul_assign(&mut self, other: Vec2<T>) { self.0 *= other.0; self.1 *= other.1; }
+}
+
+impl<T: DivAssign<T>> DivAssign<Vec2<T>> for Vec2<T> {
+    fn div_assign(&mut self, other: Vec2<T>) { self.0 /= other.0; self.1 /= other.1; }
+}
+
+/// Implements the arithmetic operators between a vector and a single scalar of a primitive type,
+/// applying the scalar to both coordinates, for example `Vec2(4, 6) / 2`.
+macro_rules! impl_scalar_operators {
+    ( $( $scalar: ty ),* ) => { $(
+        impl Add<$scalar> for Vec2<$scalar> {
+            type Output = Self;
+            fn add(self, scalar: $scalar) -> Self { Vec2(self.0 + scalar, self.1 + scalar) }
+        }
+
+        impl Sub<$scalar> for Vec2<$scalar> {
+            type Output = Self;
+            fn sub(self, scalar: $scalar) -> Self { Vec2(self.0 - scalar, self.1 - scalar) }
+        }
+
+        impl Mul<$scalar> for Vec2<$scalar> {
+            type Output = Self;
+            fn mul(self, scalar: $scalar) -> Self { Vec2(self.0 * scalar, self.1 * scalar) }
+        }
+
+        impl Div<$scalar> for Vec2<$scalar> {
+            type Output = Self;
+            fn div(self, scalar: $scalar) -> Self { Vec2(self.0 / scalar, self.1 / scalar) }
+        }
+
+        impl Rem<$scalar> for Vec2<$scalar> {
+            type Output = Self;
+            fn rem(self, scalar: $scalar) -> Self { Vec2(self.0 % scalar, self.1 % scalar) }
+        }
+
+        impl AddAssign<$scalar> for Vec2<$scalar> {
+            fn add_assign(&mut self, scalar: $scalar) { self.0 += scalar; self.1 += scalar; }
+        }
+
+        impl SubAssign<$scalar> for Vec2<$scalar> {
+            fn sub_assign(&mut self, scalar: $scalar) { self.0 -= scalar; self.1 -= scalar; }
+        }
+
+        impl MulAssign<$scalar> for Vec2<$scalar> {
+            fn mul_assign(&mut self, scalar: $scalar) { self.0 *= scalar; self.1 *= scalar; }
+        }
+
+        impl DivAssign<$scalar> for Vec2<$scalar> {
+            fn div_assign(&mut self, scalar: $scalar) { self.0 /= scalar; self.1 /= scalar; }
+        }
+    )* };
+}
+
+impl_scalar_operators!(usize, isize, u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
 impl<T> std::ops::Neg for Vec2<T> where T: std::ops::Neg<Output=T> {
     type Output = Vec2<T>;
     fn neg(self) -> Self::Output { Vec2(-self.0, -self.1) }
@@ -139,6 +243,54 @@ impl<T> From<Vec2<T>> for (T, T) {
     fn from(vec2: Vec2<T>) -> Self { (vec2.0, vec2.1) }
 }
 
+
+/// Simple three-dimensional vector of any numerical type,
+/// for example the pixels of a position or normal channel group.
+/// Supports only few operations, as this is used mainly as data struct.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+pub struct Vec3<T> (pub T, pub T, pub T);
+
+impl<T> Vec3<T> {
+
+    /// The first coordinate.
+    pub fn x(self) -> T { self.0 }
+
+    /// The second coordinate.
+    pub fn y(self) -> T { self.1 }
+
+    /// The third coordinate.
+    pub fn z(self) -> T { self.2 }
+
+    /// Apply a function to all coordinates.
+    pub fn map<R>(self, mut convert: impl FnMut(T) -> R) -> Vec3<R> {
+        Vec3(convert(self.0), convert(self.1), convert(self.2))
+    }
+}
+
+impl Vec3<f32> {
+
+    /// The euclidean length of this vector.
+    pub fn length(self) -> f32 {
+        (self.0 * self.0 + self.1 * self.1 + self.2 * self.2).sqrt()
+    }
+}
+
+impl<T> From<(T, T, T)> for Vec3<T> {
+    fn from((x, y, z): (T, T, T)) -> Self { Vec3(x, y, z) }
+}
+
+impl<T> From<Vec3<T>> for (T, T, T) {
+    fn from(vec3: Vec3<T>) -> Self { (vec3.0, vec3.1, vec3.2) }
+}
+
+impl<T> From<[T; 3]> for Vec3<T> {
+    fn from([x, y, z]: [T; 3]) -> Self { Vec3(x, y, z) }
+}
+
+impl<T> From<Vec3<T>> for [T; 3] {
+    fn from(vec3: Vec3<T>) -> Self { [vec3.0, vec3.1, vec3.2] }
+}
+
 /// Computes `floor(log(x)/log(2))`. Returns 0 where argument is 0.
 // TODO does rust std not provide this?
 pub(crate) fn floor_log_2(mut number: u32) -> u32 {
@@ -204,4 +356,207 @@ impl RoundingMode {
     }
 }
 
+
+/// A 4×4 matrix of `f32` values, stored row by row, like the `M44f` attributes of OpenEXR,
+/// for example `worldToCamera` and `worldToNDC`.
+/// Following the OpenEXR convention, the matrix transforms row vectors,
+/// so the translation is stored in the last row, and `a * b` applies `a` first, then `b`.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct Matrix4 (pub [f32; 4*4]);
+
+impl Matrix4 {
+
+    /// The matrix that does not change any point.
+    pub const IDENTITY: Self = Matrix4([
+        1.0, 0.0, 0.0, 0.0,
+        0.0, 1.0, 0.0, 0.0,
+        0.0, 0.0, 1.0, 0.0,
+        0.0, 0.0, 0.0, 1.0,
+    ]);
+
+    /// Create a matrix from its four rows.
+    pub fn from_rows(rows: [[f32; 4]; 4]) -> Self {
+        let mut values = [0.0; 16];
+        for (row_index, row) in rows.iter().enumerate() {
+            values[row_index * 4 .. row_index * 4 + 4].copy_from_slice(row);
+        }
+
+        Matrix4(values)
+    }
+
+    /// A matrix that moves each point by the offset.
+    pub fn translation(x: f32, y: f32, z: f32) -> Self {
+        let mut matrix = Self::IDENTITY;
+        matrix.0[12 .. 15].copy_from_slice(&[ x, y, z ]);
+        matrix
+    }
+
+    /// A matrix that scales each point by the factors.
+    pub fn scale(x: f32, y: f32, z: f32) -> Self {
+        Self::from_rows([
+            [ x, 0.0, 0.0, 0.0 ],
+            [ 0.0, y, 0.0, 0.0 ],
+            [ 0.0, 0.0, z, 0.0 ],
+            [ 0.0, 0.0, 0.0, 1.0 ],
+        ])
+    }
+
+    /// The value in the specified row and column.
+    #[inline]
+    pub fn get(&self, row: usize, column: usize) -> f32 {
+        self.0[row * 4 + column]
+    }
+
+    /// Swap rows and columns, for example to convert to the column vector convention.
+    pub fn transposed(&self) -> Self {
+        let mut values = [0.0; 16];
+        for row in 0 .. 4 {
+            for column in 0 .. 4 { values[column * 4 + row] = self.get(row, column); }
+        }
+
+        Matrix4(values)
+    }
+
+    /// The matrix that undoes this transformation, or `None` if this matrix cannot be inverted.
+    // gauss-jordan elimination with partial pivoting, in double precision
+    pub fn inverse(&self) -> Option<Self> {
+        let mut left: Vec<f64> = self.0.iter().map(|&value| f64::from(value)).collect();
+        let mut right: Vec<f64> = Self::IDENTITY.0.iter().map(|&value| f64::from(value)).collect();
+
+        for column in 0 .. 4 {
+            let pivot_row = (column .. 4).max_by(|&a, &b|
+                left[a * 4 + column].abs().partial_cmp(&left[b * 4 + column].abs())
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            )?;
+
+            let pivot = left[pivot_row * 4 + column];
+            if !(pivot.abs() > 1.0e-12) { return None; }
+
+            for index in 0 .. 4 {
+                left.swap(column * 4 + index, pivot_row * 4 + index);
+                right.swap(column * 4 + index, pivot_row * 4 + index);
+            }
+
+            for index in 0 .. 4 {
+                left[column * 4 + index] /= pivot;
+                right[column * 4 + index] /= pivot;
+            }
+
+            for row in (0 .. 4).filter(|&row| row != column) {
+                let factor = left[row * 4 + column];
+
+                for index in 0 .. 4 {
+                    left[row * 4 + index] -= factor * left[column * 4 + index];
+                    right[row * 4 + index] -= factor * right[column * 4 + index];
+                }
+            }
+        }
+
+        let mut values = [0.0; 16];
+        for (value, &inverted) in values.iter_mut().zip(&right) { *value = inverted as f32; }
+        Some(Matrix4(values))
+    }
+
+    /// Transform the homogeneous row vector `(x, y, z, w)`.
+    pub fn transform(&self, vector: [f32; 4]) -> [f32; 4] {
+        let mut result = [0.0; 4];
+        for (column, result) in result.iter_mut().enumerate() {
+            *result = (0 .. 4).map(|row| vector[row] * self.get(row, column)).sum();
+        }
+
+        result
+    }
+
+    /// Transform the point `(x, y, z)`, including the perspective division.
+    pub fn transform_point(&self, point: (f32, f32, f32)) -> (f32, f32, f32) {
+        let [x, y, z, w] = self.transform([ point.0, point.1, point.2, 1.0 ]);
+        (x / w, y / w, z / w)
+    }
+}
+
+impl Mul<Matrix4> for Matrix4 {
+    type Output = Matrix4;
+
+    fn mul(self, right: Matrix4) -> Matrix4 {
+        let mut values = [0.0; 16];
+
+        for row in 0 .. 4 {
+            for column in 0 .. 4 {
+                values[row * 4 + column] = (0 .. 4).map(|index| self.get(row, index) * right.get(index, column)).sum();
+            }
+        }
+
+        Matrix4(values)
+    }
+}
+
+impl From<[f32; 4*4]> for Matrix4 {
+    fn from(values: [f32; 4*4]) -> Self { Matrix4(values) }
+}
+
+impl From<Matrix4> for [f32; 4*4] {
+    fn from(matrix: Matrix4) -> Self { matrix.0 }
+}
+
 // TODO log2 tests
+
+
+#[cfg(test)]
+mod test {
+    use crate::math::{Vec2, Matrix4};
+
+    #[test]
+    fn vector_and_scalar_operators() {
+        assert_eq!(Vec2(4, 6) / 2, Vec2(2, 3));
+        assert_eq!(Vec2(4, 6) * 2, Vec2(8, 12));
+        assert_eq!(Vec2(4, 6) + 1, Vec2(5, 7));
+        assert_eq!(Vec2(4, 6) - 1, Vec2(3, 5));
+        assert_eq!(Vec2(7, 9) % Vec2(4, 5), Vec2(3, 4));
+        assert_eq!(Vec2(0.5_f32, 1.0) * 2.0, Vec2(1.0, 2.0));
+
+        let mut vector = Vec2(1_usize, 2);
+        vector += Vec2(1, 1);
+        vector *= 3;
+        vector -= 1;
+        vector /= Vec2(5, 4);
+        assert_eq!(vector, Vec2(1, 2));
+
+        assert_eq!(Vec2(-3, 12).clamp(Vec2(0, 0), Vec2(10, 10)), Vec2(0, 10));
+        assert_eq!(Vec2(1.4_f32, 1.6).map(f32::round), Vec2(1.0, 2.0));
+    }
+
+    #[test]
+    fn checked_conversions() {
+        assert_eq!(Vec2(3_usize, 4).try_to_i32("test").unwrap(), Vec2(3, 4));
+        assert!(Vec2(usize::MAX, 4).try_to_i32("test").is_err());
+        assert!(Vec2(-1_i32, 4).to_usize("test").is_err());
+        assert_eq!(Vec2::<u8>::try_from(Vec2(255_i32, 0)).unwrap(), Vec2(255_u8, 0));
+        assert!(Vec2::<u8>::try_from(Vec2(256_i32, 0)).is_err());
+    }
+
+    #[test]
+    fn checked_area() {
+        assert_eq!(Vec2(3_usize, 4).checked_area(), Some(12));
+        assert_eq!(Vec2(usize::MAX, 2).checked_area(), None);
+        assert_eq!(Vec2(usize::MAX, 0).try_area("test").unwrap(), 0);
+        assert!(Vec2(usize::MAX / 2 + 1, 2).try_area("test").is_err());
+    }
+
+    #[test]
+    fn matrix_multiplication_and_inversion() {
+        let transform = Matrix4::scale(2.0, 4.0, 0.5) * Matrix4::translation(1.0, 2.0, 3.0);
+        assert_eq!(transform.transform_point((1.0, 1.0, 2.0)), (3.0, 6.0, 4.0), "scales first, then translates");
+        assert_eq!(Matrix4::IDENTITY * transform, transform);
+
+        let inverse = transform.inverse().unwrap();
+        assert_eq!(inverse.transform_point((3.0, 6.0, 4.0)), (1.0, 1.0, 2.0));
+
+        let product = transform * inverse;
+        for (value, expected) in product.0.iter().zip(&Matrix4::IDENTITY.0) {
+            assert!((value - expected).abs() < 1e-6);
+        }
+
+        assert_eq!(Matrix4::scale(1.0, 0.0, 1.0).inverse(), None);
+        assert_eq!(Matrix4::translation(1.0, 2.0, 3.0).transposed().get(0, 3), 1.0);
+    }
+}
@@ -82,8 +82,9 @@ pub enum AttributeValue {
     /// 3D float vector.
     FloatVec3((f32, f32, f32)),
 
-    /// A custom attribute.
-    /// Contains the type name of this value.
+    /// An attribute with a type that this library does not know, for example
+    /// the `idmanifest` of cryptomatte files, or the types of a newer version of the file format.
+    /// The value is not parsed, but preserved, such that it is written back unchanged.
     Custom {
 
         /// The name of the type this attribute is an instance of.
@@ -404,6 +405,29 @@ pub type TextBytes = SmallVec<[u8; 24]>;
 /// A byte slice, interpreted as text
 pub type TextSlice = [u8];
 
+/// How the bytes of a `Text` are converted from and to a string.
+/// Older software writes ISO 8859-1 (Latin-1), while newer versions of OpenEXR specify UTF-8.
+/// The raw bytes of a text are always preserved when reading and writing a file,
+/// so a file never fails to parse because of the encoding of its texts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TextEncoding {
+
+    /// Each byte is a single char, as in ISO 8859-1 (Latin-1).
+    /// Decoding never fails. Encoding fails for chars above `U+00FF`.
+    /// This is how `Display` and `Text::from(&str)` convert texts.
+    Latin1,
+
+    /// Decoding fails for bytes that are not valid UTF-8.
+    Utf8,
+
+    /// When decoding, invalid UTF-8 sequences are replaced with `U+FFFD`.
+    Utf8Lossy,
+
+    /// When decoding, valid UTF-8 is decoded as UTF-8, and any other text is decoded as Latin-1.
+    /// Useful for files with Latin-1 texts from older software, such as artist names.
+    Utf8OrLatin1,
+}
+
 
 use crate::io::*;
 use crate::meta::{sequence_end};
@@ -411,7 +435,7 @@ use crate::error::*;
 use crate::math::{RoundingMode, Vec2};
 use half::f16;
 use std::convert::{TryFrom};
-use std::borrow::Borrow;
+use std::borrow::{Borrow, Cow};
 use std::hash::{Hash, Hasher};
 use bit_field::BitField;
 
@@ -451,11 +475,112 @@ impl Text {
         Text { bytes }
     }
 
+    /// Create the name of a vendor-specific custom attribute, such as `com.studio.renderTime`,
+    /// by joining a reverse domain namespace and a name with a dot.
+    /// Namespaced names cannot collide with standard attribute names, or with the attributes of other vendors.
+    /// Returns an error if the namespace or the name is empty, if the namespace
+    /// contains an empty component, or if the name contains a dot or an unsupported character.
+    pub fn namespaced(namespace: impl AsRef<str>, name: impl AsRef<str>) -> Result<Self> {
+        let (namespace, name) = (namespace.as_ref(), name.as_ref());
+
+        if namespace.split('.').any(str::is_empty) {
+            return Err(Error::invalid(format!("attribute namespace `{}` contains an empty component", namespace)));
+        }
+
+        if name.is_empty() || name.contains('.') {
+            return Err(Error::invalid(format!("namespaced attribute name `{}` must not be empty or contain a dot", name)));
+        }
+
+        Self::new_or_none(format!("{}.{}", namespace, name))
+            .ok_or_else(|| Error::invalid("namespaced attribute name contains unsupported characters"))
+    }
+
     /// The internal ASCII bytes this text is made of.
     pub fn as_slice(&self) -> &TextSlice {
         self.bytes.as_slice()
     }
 
+    /// Whether this text matches the glob pattern, where `*` matches any number of characters,
+    /// including dots, and `?` matches a single character. All other characters must match exactly.
+    /// For example, `diffuse.*` matches all channels of the `diffuse` layer, and `*.A` matches all alpha channels.
+    pub fn matches_glob(&self, pattern: &Text) -> bool {
+        let (pattern, text) = (pattern.as_slice(), self.as_slice());
+        let (mut pattern_index, mut text_index) = (0, 0);
+
+        // where to continue if the current attempt fails: after the last star, and the text consumed by that star
+        let mut last_star: Option<(usize, usize)> = None;
+
+        while text_index < text.len() {
+            match pattern.get(pattern_index) {
+                Some(b'*') => {
+                    last_star = Some((pattern_index, text_index));
+                    pattern_index += 1;
+                },
+
+                Some(&byte) if byte == b'?' || byte == text[text_index] => {
+                    pattern_index += 1;
+                    text_index += 1;
+                },
+
+                _ => match last_star {
+                    Some((star_index, star_text_index)) => {
+                        last_star = Some((star_index, star_text_index + 1));
+                        pattern_index = star_index + 1;
+                        text_index = star_text_index + 1;
+                    },
+
+                    None => return false,
+                },
+            }
+        }
+
+        pattern[pattern_index ..].iter().all(|&byte| byte == b'*')
+    }
+
+    /// Create a `Text` containing the UTF-8 bytes of the string. Supports all chars.
+    pub fn from_utf8(string: impl AsRef<str>) -> Self {
+        Self::from_slice_unchecked(string.as_ref().as_bytes())
+    }
+
+    /// Create a `Text` from a string, using the specified encoding.
+    /// Returns an error if the encoding does not support all chars of the string.
+    /// All UTF-8 variants of the encoding encode the string as UTF-8.
+    pub fn encode(string: impl AsRef<str>, encoding: TextEncoding) -> Result<Self> {
+        match encoding {
+            TextEncoding::Latin1 => Self::new_or_none(string)
+                .ok_or_else(|| Error::unsupported("text character outside of latin-1")),
+
+            TextEncoding::Utf8 | TextEncoding::Utf8Lossy | TextEncoding::Utf8OrLatin1 =>
+                Ok(Self::from_utf8(string)),
+        }
+    }
+
+    /// Convert the bytes of this text to a string, using the specified encoding.
+    /// Only returns an error for `TextEncoding::Utf8`, if the bytes are not valid UTF-8.
+    /// Borrows from this text where possible.
+    pub fn decode(&self, encoding: TextEncoding) -> Result<Cow<'_, str>> {
+        let bytes = self.as_slice();
+
+        Ok(match encoding {
+            TextEncoding::Latin1 => Cow::Owned(self.chars().collect()),
+            TextEncoding::Utf8Lossy => String::from_utf8_lossy(bytes),
+
+            TextEncoding::Utf8 => Cow::Borrowed(
+                std::str::from_utf8(bytes).map_err(|_| Error::invalid("text is not valid utf-8"))?
+            ),
+
+            TextEncoding::Utf8OrLatin1 => match std::str::from_utf8(bytes) {
+                Ok(string) => Cow::Borrowed(string),
+                Err(_) => Cow::Owned(self.chars().collect()),
+            },
+        })
+    }
+
+    /// Convert the bytes of this text to a string, replacing invalid UTF-8 sequences with `U+FFFD`.
+    pub fn to_utf8_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(self.as_slice())
+    }
+
     /// Check whether this string is valid, adjusting `long_names` if required.
     /// If `long_names` is not provided, text length will be entirely unchecked.
     pub fn validate(&self, null_terminated: bool, long_names: Option<&mut bool>) -> UnitResult {
@@ -793,6 +918,38 @@ impl IntegerBounds {
         self.end() - Vec2(1,1)
     }
 
+    /// Whether this rectangle does not contain any pixels.
+    pub fn is_empty(self) -> bool {
+        self.size.area() == 0
+    }
+
+    /// Whether the specified position is inside this rectangle.
+    pub fn contains_position(self, position: Vec2<i32>) -> bool {
+        let end = self.end();
+        position.x() >= self.position.x() && position.y() >= self.position.y()
+            && position.x() < end.x() && position.y() < end.y()
+    }
+
+    /// The rectangle that is covered by both rectangles, or `None` if they do not overlap.
+    pub fn intersection(self, other: Self) -> Option<Self> {
+        let start = self.position.max(other.position);
+        let end = self.end().min(other.end());
+
+        if end.x() <= start.x() || end.y() <= start.y() { None }
+        else { Some(Self::new(start, (end - start).to_usize("intersection size").ok()?)) }
+    }
+
+    /// The smallest rectangle that contains both rectangles.
+    /// Empty rectangles are ignored.
+    pub fn union(self, other: Self) -> Self {
+        if other.is_empty() { return self; }
+        if self.is_empty() { return other; }
+
+        let start = self.position.min(other.position);
+        let end = self.end().max(other.end());
+        Self::new(start, (end - start).to_usize("union size").expect("union size bug"))
+    }
+
     /// Validate this instance.
     pub fn validate(&self, max: Option<Vec2<usize>>) -> UnitResult {
         if let Some(max) = max {
@@ -911,26 +1068,38 @@ impl SampleType {
         i32::BYTE_SIZE
     }
 
+    /// The number that identifies this sample type in a file.
+    pub fn type_id(self) -> i32 {
+        match self {
+            SampleType::U32 => 0,
+            SampleType::F16 => 1,
+            SampleType::F32 => 2,
+        }
+    }
+
+    /// The sample type identified by the number in a file.
+    /// Returns `Error::NotSupported` for sample types that are not known to this implementation,
+    /// as they may be introduced by future versions of the specification.
+    pub fn from_type_id(type_id: i32) -> Result<Self> {
+        match type_id {
+            0 => Ok(SampleType::U32),
+            1 => Ok(SampleType::F16),
+            2 => Ok(SampleType::F32),
+            _ if type_id < 0 => Err(Error::invalid("pixel type attribute value")),
+            _ => Err(Error::unsupported(format!("pixel type {} (only u32, f16 and f32 samples are supported)", type_id))),
+        }
+    }
+
     /// Without validation, write this instance to the byte stream.
     pub fn write<W: Write>(&self, write: &mut W) -> UnitResult {
-        match *self {
-            SampleType::U32 => 0_i32,
-            SampleType::F16 => 1_i32,
-            SampleType::F32 => 2_i32,
-        }.write(write)?;
-
+        self.type_id().write(write)?;
         Ok(())
     }
 
     /// Read the value without validating.
     pub fn read<R: Read>(read: &mut R) -> Result<Self> {
         // there's definitely going to be more than 255 different pixel types in the future
-        Ok(match i32::read(read)? {
-            0 => SampleType::U32,
-            1 => SampleType::F16,
-            2 => SampleType::F32,
-            _ => return Err(Error::invalid("pixel type attribute value")),
-        })
+        Self::from_type_id(i32::read(read)?)
     }
 }
 
@@ -1565,10 +1734,14 @@ pub fn byte_size(name: &Text, value: &AttributeValue) -> usize {
 }
 
 /// Without validation, write this attribute to the byte stream.
+/// Returns an error if the attribute is larger than `i32::MAX` bytes, which is not supported by the file format.
 pub fn write<W: Write>(name: &[u8], value: &AttributeValue, write: &mut W) -> UnitResult {
+    let byte_size = i32::try_from(value.byte_size())
+        .map_err(|_| Error::unsupported("attribute larger than 2GB"))?;
+
     Text::write_null_terminated_bytes(name, write)?;
     Text::write_null_terminated_bytes(value.kind_name(), write)?;
-    i32::write(value.byte_size() as i32, write)?;
+    i32::write(byte_size, write)?;
     value.write(write)
 }
 
@@ -1935,6 +2108,48 @@ mod test {
         }
     }
 
+    #[test]
+    fn text_glob_patterns() {
+        let matches = |text: &str, pattern: &str| Text::from(text).matches_glob(&Text::from(pattern));
+
+        assert!(matches("diffuse.R", "diffuse.*"));
+        assert!(matches("diffuse.indirect.R", "diffuse.*"));
+        assert!(!matches("specular.R", "diffuse.*"));
+        assert!(matches("beauty.A", "*.A"));
+        assert!(!matches("A", "*.A"));
+        assert!(matches("A", "?"));
+        assert!(!matches("AB", "?"));
+        assert!(matches("normal.X", "n*l.?"));
+        assert!(matches("", "**"));
+        assert!(matches("R", "R"));
+        assert!(!matches("R", "G"));
+    }
+
+    #[test]
+    fn text_encodings() {
+        let artist = Text::from_bytes_unchecked(SmallVec::from_slice(b"Bj\xF6rk")); // latin-1 from an older file
+        assert!(artist.decode(TextEncoding::Utf8).is_err());
+        assert_eq!(artist.decode(TextEncoding::Latin1).unwrap(), "Björk");
+        assert_eq!(artist.decode(TextEncoding::Utf8OrLatin1).unwrap(), "Björk");
+        assert_eq!(artist.decode(TextEncoding::Utf8Lossy).unwrap(), "Bj\u{FFFD}rk");
+
+        let utf8 = Text::encode("Björk 東京", TextEncoding::Utf8).unwrap();
+        assert_eq!(utf8.as_slice(), "Björk 東京".as_bytes());
+        assert_eq!(utf8.decode(TextEncoding::Utf8).unwrap(), "Björk 東京");
+        assert_eq!(utf8.decode(TextEncoding::Utf8OrLatin1).unwrap(), "Björk 東京");
+        assert_eq!(utf8.to_utf8_lossy(), "Björk 東京");
+
+        assert!(Text::encode("東京", TextEncoding::Latin1).is_err());
+        assert_eq!(Text::encode("Björk", TextEncoding::Latin1).unwrap(), artist);
+
+        // the raw bytes are preserved in the file
+        for text in &[ artist, utf8 ] {
+            let mut bytes = Vec::new();
+            text.write_i32_sized(&mut bytes).unwrap();
+            assert_eq!(&Text::read_i32_sized(&mut Cursor::new(bytes), 1024).unwrap(), text);
+        }
+    }
+
     #[test]
     fn rounding_up(){
         let round_up = RoundingMode::Up;
@@ -2052,6 +2267,13 @@ mod test {
                     ],
                 )),
             ),
+            (
+                Text::from("vendor settings"),
+                AttributeValue::Custom {
+                    kind: Text::from("com.vendor.settings"),
+                    bytes: vec![ 3, 1, 4, 1, 5, 9, 2, 6 ],
+                },
+            ),
         ];
 
         for (name, value) in &attributes {
@@ -2144,4 +2366,41 @@ mod test {
         }
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn integer_bounds_set_operations(){
+        let a = IntegerBounds::new(Vec2(-2, 0), Vec2(4, 3));
+        let b = IntegerBounds::new(Vec2(1, 2), Vec2(5, 5));
+
+        assert_eq!(a.intersection(b), Some(IntegerBounds::new(Vec2(1, 2), Vec2(1, 1))));
+        assert_eq!(a.union(b), IntegerBounds::new(Vec2(-2, 0), Vec2(8, 7)));
+        assert_eq!(a.intersection(IntegerBounds::new(Vec2(2, 0), Vec2(1, 1))), None, "touching edges");
+        assert_eq!(a.union(IntegerBounds::zero()), a, "empty bounds are ignored");
+
+        assert!(a.contains_position(Vec2(-2, 0)));
+        assert!(a.contains_position(Vec2(1, 2)));
+        assert!(!a.contains_position(Vec2(2, 2)), "end is exclusive");
+
+        assert!(a.union(b).contains(a));
+        assert!(a.union(b).contains(b));
+        assert!(!a.contains(b));
+    }
+
+    #[test]
+    fn unknown_sample_type_is_unsupported(){
+        for &sample_type in &[ SampleType::U32, SampleType::F16, SampleType::F32 ] {
+            assert_eq!(SampleType::from_type_id(sample_type.type_id()).unwrap(), sample_type);
+        }
+
+        let mut bytes = Vec::new();
+        Text::from("Z").write_null_terminated(&mut bytes).unwrap();
+        3_i32.write(&mut bytes).unwrap(); // a sample type that might be introduced in the future
+        bytes.extend_from_slice(&[ 0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0 ]);
+
+        match ChannelDescription::read(&mut bytes.as_slice()) {
+            Err(Error::NotSupported(_)) => {},
+            other => panic!("unknown sample type should be unsupported, but was {:?}", other),
+        }
+
+        assert!(matches!(SampleType::from_type_id(-1), Err(Error::Invalid(_))));
+    }
+}
@@ -0,0 +1,372 @@
+//! Human-readable and JSON representations of the meta data of a file,
+//! for debugging and for tools that inspect files.
+//!
+//! Use `format!("{}", meta_data)` to print all headers,
+//! or `meta_data.to_json()` to obtain a machine-readable dump.
+//! Both contain all attributes that would be written to a file, including the channels,
+//! the data and display windows, the tiles, the compression method, and the custom attributes.
+//! The pixels of preview images are omitted, only the size of the preview is included.
+
+use std::fmt::{self, Display, Formatter, Write};
+use crate::meta::MetaData;
+use crate::meta::header::Header;
+use crate::meta::attribute::*;
+use crate::math::{Vec2, RoundingMode};
+
+
+impl Display for MetaData {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(
+            formatter, "exr file version {}, {} layer{}{}",
+            self.requirements.file_format_version, self.headers.len(),
+            if self.headers.len() == 1 { "" } else { "s" },
+            if self.requirements.has_deep_data { ", deep data" } else { "" },
+        )?;
+
+        for (index, header) in self.headers.iter().enumerate() {
+            writeln!(formatter, "\nlayer #{}:", index)?;
+            write!(formatter, "{}", header)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for Header {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        for (name, value) in self.ordered_attributes() {
+            writeln!(
+                formatter, "    {} ({}): {}",
+                Text::from_slice_unchecked(name), Text::from_slice_unchecked(value.kind_name()), value
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for AttributeValue {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        use AttributeValue::*;
+
+        match self {
+            ChannelList(channels) => {
+                for (index, channel) in channels.list.iter().enumerate() {
+                    if index != 0 { formatter.write_str(", ")?; }
+                    write!(formatter, "{} ({})", channel.name, sample_type_name(channel.sample_type))?;
+
+                    if channel.sampling != Vec2(1, 1) {
+                        write!(formatter, " subsampled {}x{}", channel.sampling.x(), channel.sampling.y())?;
+                    }
+                }
+
+                Ok(())
+            },
+
+            Chromaticities(value) => write!(
+                formatter, "red {}, green {}, blue {}, white {}",
+                vec2(value.red), vec2(value.green), vec2(value.blue), vec2(value.white)
+            ),
+
+            Compression(value) => write!(formatter, "{}", value),
+            EnvironmentMap(value) => formatter.write_str(environment_map_name(*value)),
+
+            KeyCode(value) => write!(
+                formatter, "manufacturer {}, type {}, roll {}, count {}, perforation offset {}, perforations per frame {}, perforations per count {}",
+                value.film_manufacturer_code, value.film_type, value.film_roll_prefix,
+                value.count, value.perforation_offset, value.perforations_per_frame, value.perforations_per_count
+            ),
+
+            LineOrder(value) => formatter.write_str(line_order_name(*value)),
+            Matrix3x3(value) => write_matrix(formatter, value, 3),
+            Matrix4x4(value) => write_matrix(formatter, value, 4),
+            Preview(value) => write!(formatter, "{}x{} pixels", value.size.width(), value.size.height()),
+            Rational((numerator, denominator)) => write!(formatter, "{}/{}", numerator, denominator),
+            BlockType(value) => write!(formatter, "{}", crate::meta::attribute::Text::from_slice_unchecked(value.to_text_bytes())),
+
+            TextVector(texts) => {
+                for (index, text) in texts.iter().enumerate() {
+                    if index != 0 { formatter.write_str(", ")?; }
+                    write!(formatter, "{:?}", text.to_utf8_lossy())?;
+                }
+
+                Ok(())
+            },
+
+            TileDescription(tiles) => write!(
+                formatter, "{}x{} tiles, {}, rounding {}",
+                tiles.tile_size.width(), tiles.tile_size.height(),
+                level_mode_name(tiles.level_mode), rounding_mode_name(tiles.rounding_mode)
+            ),
+
+            TimeCode(value) => write!(
+                formatter, "{:02}:{:02}:{:02}:{:02}{}",
+                value.hours, value.minutes, value.seconds, value.frame,
+                if value.drop_frame { " (drop frame)" } else { "" }
+            ),
+
+            Text(text) => write!(formatter, "{:?}", text.to_utf8_lossy()),
+            F64(value) => write!(formatter, "{}", value),
+            F32(value) => write!(formatter, "{}", value),
+            I32(value) => write!(formatter, "{}", value),
+
+            IntegerBounds(bounds) => write!(
+                formatter, "{}x{} at ({}, {})",
+                bounds.size.width(), bounds.size.height(), bounds.position.x(), bounds.position.y()
+            ),
+
+            FloatRect(rect) => write!(formatter, "from {} to {}", vec2(rect.min), vec2(rect.max)),
+            IntVec2(value) => write!(formatter, "{}", vec2(*value)),
+            FloatVec2(value) => write!(formatter, "{}", vec2(*value)),
+            IntVec3((x, y, z)) => write!(formatter, "({}, {}, {})", x, y, z),
+            FloatVec3((x, y, z)) => write!(formatter, "({}, {}, {})", x, y, z),
+            Custom { bytes, .. } => write!(formatter, "{} bytes", bytes.len()),
+        }
+    }
+}
+
+
+impl MetaData {
+
+    /// A JSON object containing the requirements and all attributes of all headers.
+    /// See `Header::to_json`.
+    pub fn to_json(&self) -> String {
+        let requirements = &self.requirements;
+
+        let headers: Vec<String> = self.headers.iter().map(Header::to_json).collect();
+
+        format!(
+            "{{\"requirements\":{{\"version\":{},\"singleLayerAndTiled\":{},\"longNames\":{},\"deepData\":{},\"multipleLayers\":{}}},\"headers\":[{}]}}",
+            requirements.file_format_version, requirements.is_single_layer_and_tiled,
+            requirements.has_long_names, requirements.has_deep_data, requirements.has_multiple_layers,
+            headers.join(",")
+        )
+    }
+}
+
+impl Header {
+
+    /// A JSON object, mapping the name of each attribute to the value of the attribute,
+    /// in the order the attributes will be written to a file.
+    /// Includes the required attributes, such as the channels and the data window. See `AttributeValue::to_json`.
+    pub fn to_json(&self) -> String {
+        let attributes: Vec<String> = self.ordered_attributes()
+            .map(|(name, value)| format!("{}:{}", json_string(&Text::from_slice_unchecked(name).to_utf8_lossy()), value.to_json()))
+            .collect();
+
+        format!("{{{}}}", attributes.join(","))
+    }
+}
+
+impl AttributeValue {
+
+    /// The value as JSON. Vectors and matrices are arrays, and structured values are objects.
+    /// Floating point numbers that are not finite are `null`, as JSON does not support them.
+    /// Values of unknown types contain the type name and the number of bytes.
+    pub fn to_json(&self) -> String {
+        use AttributeValue::*;
+
+        match self {
+            ChannelList(channels) => json_array(channels.list.iter().map(|channel| format!(
+                "{{\"name\":{},\"sampleType\":\"{}\",\"quantizeLinearly\":{},\"sampling\":{}}}",
+                json_text(&channel.name), sample_type_name(channel.sample_type),
+                channel.quantize_linearly, json_vec2(channel.sampling)
+            ))),
+
+            Chromaticities(value) => format!(
+                "{{\"red\":{},\"green\":{},\"blue\":{},\"white\":{}}}",
+                json_vec2(value.red), json_vec2(value.green), json_vec2(value.blue), json_vec2(value.white)
+            ),
+
+            Compression(value) => json_string(compression_name(*value)),
+            EnvironmentMap(value) => json_string(environment_map_name(*value)),
+
+            KeyCode(value) => format!(
+                "{{\"filmManufacturerCode\":{},\"filmType\":{},\"filmRollPrefix\":{},\"count\":{},\"perforationOffset\":{},\"perforationsPerFrame\":{},\"perforationsPerCount\":{}}}",
+                value.film_manufacturer_code, value.film_type, value.film_roll_prefix,
+                value.count, value.perforation_offset, value.perforations_per_frame, value.perforations_per_count
+            ),
+
+            LineOrder(value) => json_string(line_order_name(*value)),
+            Matrix3x3(value) => json_array(value.iter().map(|&value| json_number(f64::from(value)))),
+            Matrix4x4(value) => json_array(value.iter().map(|&value| json_number(f64::from(value)))),
+
+            Preview(value) => format!(
+                "{{\"width\":{},\"height\":{}}}",
+                value.size.width(), value.size.height()
+            ),
+
+            Rational((numerator, denominator)) => format!("[{},{}]", numerator, denominator),
+            BlockType(value) => json_text(&crate::meta::attribute::Text::from_slice_unchecked(value.to_text_bytes())),
+            TextVector(texts) => json_array(texts.iter().map(json_text)),
+
+            TileDescription(tiles) => format!(
+                "{{\"tileSize\":{},\"levelMode\":\"{}\",\"roundingMode\":\"{}\"}}",
+                json_vec2(tiles.tile_size), level_mode_name(tiles.level_mode), rounding_mode_name(tiles.rounding_mode)
+            ),
+
+            TimeCode(value) => format!(
+                "{{\"hours\":{},\"minutes\":{},\"seconds\":{},\"frame\":{},\"dropFrame\":{},\"colorFrame\":{},\"fieldPhase\":{}}}",
+                value.hours, value.minutes, value.seconds, value.frame,
+                value.drop_frame, value.color_frame, value.field_phase
+            ),
+
+            Text(text) => json_text(text),
+            F64(value) => json_number(*value),
+            F32(value) => json_number(f64::from(*value)),
+            I32(value) => value.to_string(),
+
+            IntegerBounds(bounds) => format!(
+                "{{\"position\":{},\"size\":{}}}",
+                json_vec2(bounds.position), json_vec2(bounds.size)
+            ),
+
+            FloatRect(rect) => format!("{{\"min\":{},\"max\":{}}}", json_vec2(rect.min), json_vec2(rect.max)),
+            IntVec2(value) => json_vec2(*value),
+            FloatVec2(value) => json_vec2(*value),
+            IntVec3((x, y, z)) => format!("[{},{},{}]", x, y, z),
+            FloatVec3((x, y, z)) => json_array([ x, y, z ].iter().map(|&&value| json_number(f64::from(value)))),
+
+            Custom { kind, bytes } => format!(
+                "{{\"type\":{},\"byteSize\":{}}}",
+                json_text(kind), bytes.len()
+            ),
+        }
+    }
+}
+
+
+fn sample_type_name(sample_type: SampleType) -> &'static str {
+    match sample_type {
+        SampleType::F16 => "f16",
+        SampleType::F32 => "f32",
+        SampleType::U32 => "u32",
+    }
+}
+
+fn compression_name(compression: Compression) -> &'static str {
+    match compression {
+        Compression::Uncompressed => "uncompressed",
+        Compression::RLE => "rle",
+        Compression::ZIP1 => "zip1",
+        Compression::ZIP16 => "zip16",
+        Compression::PIZ => "piz",
+        Compression::PXR24 => "pxr24",
+        Compression::B44 => "b44",
+        Compression::B44A => "b44a",
+        Compression::DWAA(_) => "dwaa",
+        Compression::DWAB(_) => "dwab",
+    }
+}
+
+fn environment_map_name(environment_map: EnvironmentMap) -> &'static str {
+    match environment_map {
+        EnvironmentMap::LatitudeLongitude => "latitudeLongitude",
+        EnvironmentMap::Cube => "cube",
+    }
+}
+
+fn line_order_name(line_order: LineOrder) -> &'static str {
+    match line_order {
+        LineOrder::Increasing => "increasing",
+        LineOrder::Decreasing => "decreasing",
+        LineOrder::Unspecified => "unspecified",
+    }
+}
+
+fn level_mode_name(level_mode: LevelMode) -> &'static str {
+    match level_mode {
+        LevelMode::Singular => "singular",
+        LevelMode::MipMap => "mipMap",
+        LevelMode::RipMap => "ripMap",
+    }
+}
+
+fn rounding_mode_name(rounding_mode: RoundingMode) -> &'static str {
+    match rounding_mode {
+        RoundingMode::Down => "down",
+        RoundingMode::Up => "up",
+    }
+}
+
+fn vec2<T: Display + Copy>(vector: Vec2<T>) -> String {
+    format!("({}, {})", vector.x(), vector.y())
+}
+
+fn write_matrix(formatter: &mut Formatter<'_>, values: &[f32], columns: usize) -> fmt::Result {
+    for (index, row) in values.chunks(columns).enumerate() {
+        if index != 0 { formatter.write_str(", ")?; }
+
+        let row: Vec<String> = row.iter().map(f32::to_string).collect();
+        write!(formatter, "[{}]", row.join(" "))?;
+    }
+
+    Ok(())
+}
+
+fn json_vec2<T: Display + Copy>(vector: Vec2<T>) -> String {
+    format!("[{},{}]", vector.x(), vector.y())
+}
+
+/// Format a number as JSON. As JSON has no representation for infinity and NaN, those are written as `null`.
+pub fn json_number<T: Into<f64> + Display + Copy>(value: T) -> String {
+    if value.into().is_finite() { value.to_string() } else { String::from("null") }
+}
+
+fn json_array(values: impl Iterator<Item=String>) -> String {
+    format!("[{}]", values.collect::<Vec<String>>().join(","))
+}
+
+fn json_text(text: &Text) -> String {
+    json_string(&text.to_utf8_lossy())
+}
+
+/// Format a string as JSON, escaping quotes, backslashes, and control characters.
+pub fn json_string(string: &str) -> String {
+    let mut json = String::with_capacity(string.len() + 2);
+    json.push('"');
+
+    for character in string.chars() {
+        match character {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            control if control.is_control() => write!(json, "\\u{:04x}", control as u32).expect("string formatting failed"),
+            other => json.push(other),
+        }
+    }
+
+    json.push('"');
+    json
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dump_header() {
+        let header = Header::builder()
+            .layer_size((16, 8))
+            .name("beauty \"main\"")
+            .channel(ChannelDescription::named("R", SampleType::F16))
+            .channel(ChannelDescription::named("G", SampleType::F32))
+            .compression(Compression::ZIP16)
+            .attribute("com.studio.ratio", AttributeValue::F32(f32::NAN))
+            .build().unwrap();
+
+        let json = header.to_json();
+        assert!(json.contains("\"channels\":[{\"name\":\"G\",\"sampleType\":\"f32\",\"quantizeLinearly\":false,\"sampling\":[1,1]},"), "{}", json);
+        assert!(json.contains("\"dataWindow\":{\"position\":[0,0],\"size\":[16,8]}"), "{}", json);
+        assert!(json.contains("\"compression\":\"zip16\""), "{}", json);
+        assert!(json.contains("\"name\":\"beauty \\\"main\\\"\""), "{}", json);
+        assert!(json.contains("\"com.studio.ratio\":null"), "{}", json);
+
+        let text = header.to_string();
+        assert!(text.contains("channels (chlist): G (f32), R (f16)"), "{}", text);
+        assert!(text.contains("dataWindow (box2i): 16x8 at (0, 0)"), "{}", text);
+    }
+}
@@ -3,9 +3,11 @@
 //! Defines some data types that list all standard attributes.
 
 use std::collections::HashMap;
+use std::borrow::Cow;
 use crate::meta::attribute::*; // FIXME shouldn't this need some more imports????
 use crate::meta::*;
-use crate::math::Vec2;
+use crate::math::{Vec2, Matrix4};
+use crate::compression::filter::{self, ChannelFilters};
 
 // TODO rename header to LayerDescription!
 
@@ -154,6 +156,10 @@ pub struct LayerAttributes {
     /// Name of the owner.
     pub owner: Option<Text>,
 
+    /// The name of the computer that produced this image, which helps to track down
+    /// problems that only occur on some machines of a render farm.
+    pub host_computer: Option<Text>,
+
     /// Additional textual information.
     pub comments: Option<Text>,
 
@@ -204,10 +210,12 @@ pub struct LayerAttributes {
 
     /// The matrix that transforms 3D points from the world to the camera coordinate space.
     /// Left-handed coordinate system, y up, z forward.
+    /// See `LayerAttributes::world_to_camera_matrix`.
     pub world_to_camera: Option<Matrix4x4>,
 
     /// The matrix that transforms 3D points from the world to the "Normalized Device Coordinate" space.
     /// Left-handed coordinate system, y up, z forward.
+    /// See `LayerAttributes::world_to_normalized_device_matrix`.
     pub world_to_normalized_device: Option<Matrix4x4>,
 
     /// Specifies whether the pixels in a deep image are sorted and non-overlapping.
@@ -241,6 +249,27 @@ pub struct LayerAttributes {
     /// Does not contain the attributes already present in the `Header` or `LayerAttributes` struct.
     /// Does not contain attributes that are standardized to be the same for all layers: no chromaticities and no time codes.
     pub other: HashMap<Text, AttributeValue>,
+
+    /// Reversible filters that are applied to the samples of some channels before ZIP compression.
+    /// Stored in the file as a custom attribute. See `exr::compression::filter`.
+    pub sample_filters: ChannelFilters,
+
+    /// The order in which the attributes of this header appeared in the file.
+    /// See `LayerAttributes::attribute_order`.
+    pub attribute_order: AttributeOrder,
+}
+
+/// The names of the attributes of a header, in the order they appear in the file.
+/// Not compared when comparing layer attributes, as the order does not change the meaning of the attributes.
+#[derive(Clone, Debug, Default, Eq)]
+pub struct AttributeOrder {
+
+    /// The names of the attributes, in the order they appear in the file.
+    pub names: Vec<Text>,
+}
+
+impl PartialEq for AttributeOrder {
+    fn eq(&self, _: &Self) -> bool { true }
 }
 
 
@@ -259,11 +288,27 @@ impl LayerAttributes {
         Self { layer_position: data_position, ..self }
     }
 
+    /// The names of the attributes of this layer, in the order they appeared in the file,
+    /// including the required attributes and the image attributes.
+    /// Some parsers depend on the order of attributes, so the order is preserved when rewriting a file.
+    /// Empty for layers that were not read from a file.
+    ///
+    /// The order is not compared when comparing two instances of `LayerAttributes`.
+    pub fn attribute_order(&self) -> &[Text] {
+        &self.attribute_order.names
+    }
+
+    /// Set the order in which the attributes will be written.
+    /// When writing, attributes are written in this order, followed by all attributes that are not in this list.
+    pub fn with_attribute_order(self, attribute_names: impl IntoIterator<Item = impl Into<Text>>) -> Self {
+        Self { attribute_order: AttributeOrder { names: attribute_names.into_iter().map(Into::into).collect() }, ..self }
+    }
+
     /// Set all common camera projection attributes at once.
     pub fn with_camera_frustum(
         self,
-        world_to_camera: Matrix4x4,
-        world_to_normalized_device: Matrix4x4,
+        world_to_camera: impl Into<Matrix4>,
+        world_to_normalized_device: impl Into<Matrix4>,
         field_of_view: impl Into<Vec2<f32>>,
         depth_clip_range: std::ops::Range<f32>,
     ) -> Self
@@ -271,8 +316,8 @@ impl LayerAttributes {
         let fov = field_of_view.into();
 
         Self {
-            world_to_normalized_device: Some(world_to_normalized_device),
-            world_to_camera: Some(world_to_camera),
+            world_to_normalized_device: Some(world_to_normalized_device.into().0),
+            world_to_camera: Some(world_to_camera.into().0),
             horizontal_field_of_view: Some(fov.x()),
             vertical_field_of_view: Some(fov.y()),
             near_clip_plane: Some(depth_clip_range.start),
@@ -280,6 +325,67 @@ impl LayerAttributes {
             ..self
         }
     }
+
+    /// The `world_to_camera` attribute as a matrix that can be inverted and multiplied.
+    pub fn world_to_camera_matrix(&self) -> Option<Matrix4> {
+        self.world_to_camera.map(Matrix4::from)
+    }
+
+    /// The `world_to_normalized_device` attribute as a matrix that can be inverted and multiplied.
+    pub fn world_to_normalized_device_matrix(&self) -> Option<Matrix4> {
+        self.world_to_normalized_device.map(Matrix4::from)
+    }
+
+    /// Fill in the attributes that this layer does not specify, using the values of the defaults.
+    /// Used to apply `WriteImageWithOptions::layer_defaults` to each layer when writing an image.
+    ///
+    /// An attribute is inherited if it has its default value in this layer,
+    /// which is `None` for most attributes. As a consequence, a layer cannot override an inherited value
+    /// with the default value, for example, a shared `owner` cannot be removed from a single layer.
+    /// Custom attributes and sample filters are merged, preferring the entries of this layer.
+    /// The layer name, the layer position and the attribute order are never inherited.
+    pub fn inherit(mut self, defaults: &LayerAttributes) -> Self {
+        let default_self = Self::default();
+
+        macro_rules! inherit_default_fields {
+            ( $( $name: ident ),* ) => { $(
+
+                if self.$name == default_self.$name {
+                    self.$name = defaults.$name.clone();
+                }
+
+            )* };
+        }
+
+        inherit_default_fields! {
+            screen_window_center, screen_window_width,
+            white_luminance, adopted_neutral, horizontal_density,
+            rendering_transform_name, look_modification_transform_name,
+            owner, host_computer, comments,
+            capture_date, utc_offset,
+            longitude, latitude, altitude,
+            focus, exposure, aperture, iso_speed,
+            environment_map, film_key_code, wrap_mode_name,
+            frames_per_second, multi_view_names,
+            world_to_camera, world_to_normalized_device,
+            deep_image_state, original_data_window,
+            preview, view_name, software_name,
+            near_clip_plane, far_clip_plane,
+            horizontal_field_of_view, vertical_field_of_view
+        }
+
+        for (name, value) in &defaults.other {
+            self.other.entry(name.clone()).or_insert_with(|| value.clone());
+        }
+
+        for (channel_name, filter) in &defaults.sample_filters.list {
+            if !self.sample_filters.list.iter().any(|(name, _)| name == channel_name) {
+                self.sample_filters.list.push((channel_name.clone(), filter.clone()));
+            }
+        }
+
+        self
+    }
 }
 
 impl ImageAttributes {
@@ -379,12 +485,32 @@ impl Header {
     /// Iterate over all blocks, in the order specified by the headers line order attribute.
     /// Unspecified line order is treated as increasing line order.
     /// Also enumerates the index of each block in the header, as if it were sorted in increasing line order.
+    ///
+    /// With decreasing line order, the rows of tiles in each resolution level are reversed,
+    /// but the levels and the tiles within each row are still in increasing order, as in the reference implementation.
     pub fn enumerate_ordered_blocks(&self) -> impl Iterator<Item=(usize, TileIndices)> + Send {
         let increasing_y = self.blocks_increasing_y_order().enumerate();
 
         // TODO without box?
         let ordered: Box<dyn Send + Iterator<Item=(usize, TileIndices)>> = {
-            if self.line_order == LineOrder::Decreasing { Box::new(increasing_y.rev()) }
+            if self.line_order == LineOrder::Decreasing {
+                let mut blocks: Vec<(usize, TileIndices)> = increasing_y.collect();
+
+                // the tiles of each level are contiguous in increasing line order
+                let mut level_start = 0;
+                while level_start < blocks.len() {
+                    let level_index = blocks[level_start].1.location.level_index;
+                    let level_end = blocks[level_start ..].iter()
+                        .position(|(_, tile)| tile.location.level_index != level_index)
+                        .map_or(blocks.len(), |level_block_count| level_start + level_block_count);
+
+                    // stable sort keeps the increasing x order within each row
+                    blocks[level_start .. level_end].sort_by_key(|(_, tile)| std::cmp::Reverse(tile.location.tile_index.y()));
+                    level_start = level_end;
+                }
+
+                Box::new(blocks.into_iter())
+            }
             else { Box::new(increasing_y) }
         };
 
@@ -414,7 +540,7 @@ impl Header {
             fn divide_and_rest(total_size: usize, block_size: usize) -> impl Iterator<Item=(usize, usize)> {
                 let block_count = compute_block_count(total_size, block_size);
                 (0..block_count).map(move |block_index| (
-                    block_index, calculate_block_size(total_size, block_size, block_index).expect("block size calculation bug")
+                    block_index, calculate_block_size(total_size, block_size, block_index * block_size).expect("block size calculation bug")
                 ))
             }
 
@@ -459,20 +585,145 @@ impl Header {
         vec.into_iter() // TODO without collect
     }
 
-    /* TODO
-    /// The block indices of this header, ordered as they would appear in the file.
-    pub fn ordered_block_indices<'s>(&'s self, layer_index: usize) -> impl 's + Iterator<Item=BlockIndex> {
-        self.enumerate_ordered_blocks().map(|(chunk_index, tile)|{
-            let data_indices = self.get_absolute_block_pixel_coordinates(tile.location).expect("tile coordinate bug");
-
-            BlockIndex {
-                layer: layer_index,
-                level: tile.location.level_index,
-                pixel_position: data_indices.position.to_usize("data indices start").expect("data index bug"),
-                pixel_size: data_indices.size,
+    /// Compute the index of the block at the specified tile coordinates,
+    /// as if all blocks of this header were sorted in `LineOrder::Increasing`.
+    /// This is the index that is required for writing a chunk with a `ChunksWriter`.
+    pub fn chunk_index_increasing_y(&self, tile: TileCoordinates) -> Result<usize> {
+        let index_in_level = |level_size: Vec2<usize>, tile_size: Vec2<usize>| -> Result<usize> {
+            let tile_count = Vec2(
+                compute_block_count(level_size.width(), tile_size.width()),
+                compute_block_count(level_size.height(), tile_size.height()),
+            );
+
+            if tile.tile_index.x() >= tile_count.x() || tile.tile_index.y() >= tile_count.y() {
+                return Err(Error::invalid("chunk tile index"));
+            }
+
+            Ok(tile.tile_index.y() * tile_count.x() + tile.tile_index.x())
+        };
+
+        let levels: Vec<(Vec2<usize>, Vec2<usize>)> = match self.blocks {
+            BlockDescription::ScanLines => vec![ (Vec2(0, 0), self.layer_size) ],
+            BlockDescription::Tiles(tiles) => match tiles.level_mode {
+                LevelMode::Singular => vec![ (Vec2(0, 0), self.layer_size) ],
+                LevelMode::MipMap => mip_map_levels(tiles.rounding_mode, self.layer_size)
+                    .map(|(level, size)| (Vec2(level, level), size)).collect(),
+                LevelMode::RipMap => rip_map_levels(tiles.rounding_mode, self.layer_size).collect(),
+            },
+        };
+
+        let tile_size = self.max_block_pixel_size();
+        let mut preceding_chunks = 0;
+
+        for (level_index, level_size) in levels {
+            if level_index == tile.level_index {
+                return Ok(preceding_chunks + index_in_level(level_size, tile_size)?);
             }
+
+            preceding_chunks += compute_block_count(level_size.width(), tile_size.width())
+                * compute_block_count(level_size.height(), tile_size.height());
+        }
+
+        Err(Error::invalid("chunk level index"))
+    }
+
+    /// The block indices of this header, ordered as they would appear in the file.
+    pub fn ordered_block_indices(&self, layer_index: usize) -> impl '_ + Iterator<Item=BlockIndex> {
+        self.enumerate_ordered_blocks().map(move |(_, tile)|{
+            self.get_block_index(layer_index, tile.location).expect("tile coordinate bug")
         })
-    }*/
+    }
+
+    /// The pixel section and resolution level of the specified tile,
+    /// identifying the block globally with the specified layer index.
+    pub fn get_block_index(&self, layer_index: usize, tile: TileCoordinates) -> Result<BlockIndex> {
+        let data_indices = self.get_absolute_block_pixel_coordinates(tile)?;
+
+        Ok(BlockIndex {
+            layer: layer_index,
+            level: tile.level_index,
+            pixel_position: data_indices.position.to_usize("data indices start")?,
+            pixel_size: data_indices.size,
+        })
+    }
+
+    /// The resolution of the specified mip map or rip map level.
+    /// Returns an error if this header does not contain that level.
+    pub fn level_size(&self, level_index: Vec2<usize>) -> Result<Vec2<usize>> {
+        let tiles = match self.blocks {
+            BlockDescription::Tiles(tiles) if tiles.level_mode != LevelMode::Singular => tiles,
+
+            _ => return {
+                if level_index == Vec2(0, 0) { Ok(self.layer_size) }
+                else { Err(Error::invalid("level index of a layer without levels")) }
+            },
+        };
+
+        let round = tiles.rounding_mode;
+        let level_count = match tiles.level_mode {
+            LevelMode::MipMap => {
+                if level_index.x() != level_index.y() { return Err(Error::invalid("mip map level index")) }
+                let count = compute_level_count(round, self.layer_size.width().max(self.layer_size.height()));
+                Vec2(count, count)
+            },
+
+            _ => Vec2(
+                compute_level_count(round, self.layer_size.width()),
+                compute_level_count(round, self.layer_size.height())
+            ),
+        };
+
+        if level_index.x() >= level_count.x() || level_index.y() >= level_count.y() {
+            return Err(Error::invalid("level index"));
+        }
+
+        Ok(Vec2(
+            compute_level_size(round, self.layer_size.width(), level_index.x()),
+            compute_level_size(round, self.layer_size.height(), level_index.y()),
+        ))
+    }
+
+    /// The tile (or scan line block) that contains the specified pixel of the specified resolution level.
+    /// The pixel position starts at `0` in the top left corner of the level, and is not affected by the layer position.
+    pub fn get_tile_coordinates_containing(&self, level_index: Vec2<usize>, pixel_position: Vec2<usize>) -> Result<TileCoordinates> {
+        let level_size = self.level_size(level_index)?;
+
+        if pixel_position.x() >= level_size.width() || pixel_position.y() >= level_size.height() {
+            return Err(Error::invalid("pixel position outside of level"));
+        }
+
+        let tile_size = self.max_block_pixel_size();
+
+        Ok(TileCoordinates {
+            tile_index: Vec2(pixel_position.x() / tile_size.width(), pixel_position.y() / tile_size.height()),
+            level_index,
+        })
+    }
+
+    /// All tiles (or scan line blocks) of the specified resolution level
+    /// that contain at least one pixel of the specified section, in increasing y order.
+    /// The section starts at `0` in the top left corner of the level, and is not affected by the layer position.
+    /// Parts of the section outside of the level are ignored.
+    pub fn get_tiles_intersecting(&self, level_index: Vec2<usize>, section: IntegerBounds) -> Result<impl Iterator<Item=TileCoordinates>> {
+        let level_size = self.level_size(level_index)?;
+        let tile_size = self.max_block_pixel_size();
+
+        let start = section.position.max(Vec2(0, 0)).to_usize("section start")?;
+        let end = section.end().max(Vec2(0, 0)).to_usize("section end")?;
+        let end = Vec2(end.x().min(level_size.width()), end.y().min(level_size.height()));
+
+        let tiles = |start: usize, end: usize, tile_size: usize| {
+            if start >= end { 0 .. 0 }
+            else { start / tile_size .. RoundingMode::Up.divide(end, tile_size) }
+        };
+
+        let tiles_x = tiles(start.x(), end.x(), tile_size.width());
+        let tiles_y = tiles(start.y(), end.y(), tile_size.height());
+
+        Ok(tiles_y.flat_map(move |y| tiles_x.clone().map(move |x| TileCoordinates {
+            tile_index: Vec2(x, y), level_index
+        })))
+    }
 
     // TODO reuse this function everywhere
     /// The default pixel resolution of a single block (tile or scan line block).
@@ -531,21 +782,17 @@ impl Header {
                 tile.coordinates
             },
 
-            CompressedBlock::ScanLine(ref block) => {
-                let size = self.compression.scan_lines_per_block() as i32;
-                let y = (block.y_coordinate - self.own_attributes.layer_position.y()) / size;
-
-                if y < 0 {
-                    return Err(Error::invalid("scan block y coordinate"));
-                }
+            CompressedBlock::DeepTile(ref tile) => {
+                tile.coordinates
+            },
 
-                TileCoordinates {
-                    tile_index: Vec2(0, y as usize),
-                    level_index: Vec2(0, 0)
-                }
+            CompressedBlock::ScanLine(ref block) => {
+                self.get_scan_line_block_tile_coordinates(block.y_coordinate)?
             },
 
-            _ => return Err(Error::unsupported("deep data not supported yet"))
+            CompressedBlock::DeepScanLine(ref block) => {
+                self.get_scan_line_block_tile_coordinates(block.y_coordinate)?
+            },
         })
     }
 
@@ -564,6 +811,39 @@ impl Header {
         })
     }
 
+    /// Choose a tile size for this layer, based on the compression method,
+    /// the number of bytes per pixel, and the size of the layer.
+    /// Used by `WriteImageWithOptions::tiles_auto`.
+    ///
+    /// DWAA, DWAB, and B44 compress small fixed blocks of pixels (8×8 and 4×4),
+    /// so larger tiles do not improve the compression ratio, and 64×64 tiles are chosen.
+    /// ZIP, PIZ, and PXR24 find more redundancy in larger blocks, so 128×128 tiles are chosen.
+    /// Uncompressed and RLE data do not benefit from larger tiles, and 64×64 tiles are chosen,
+    /// which allows loading small sections of the image without reading many unneeded pixels.
+    ///
+    /// The tile is halved while a single uncompressed tile exceeds 512 KiB, for layers with many channels,
+    /// but never below 16×16 pixels. Finally, the tile is not larger than the layer itself.
+    pub fn auto_tile_size(&self) -> Vec2<usize> {
+        const MAX_TILE_BYTES: usize = 512 * 1024;
+        const MIN_TILE_SIZE: usize = 16;
+
+        let mut tile_size = match self.compression {
+            Compression::ZIP1 | Compression::ZIP16 | Compression::PIZ | Compression::PXR24 => 128,
+            Compression::Uncompressed | Compression::RLE | Compression::B44 | Compression::B44A
+                | Compression::DWAA(_) | Compression::DWAB(_) => 64,
+        };
+
+        let bytes_per_pixel = self.channels.bytes_per_pixel.max(1);
+        while tile_size > MIN_TILE_SIZE && tile_size * tile_size * bytes_per_pixel > MAX_TILE_BYTES {
+            tile_size /= 2;
+        }
+
+        Vec2(
+            tile_size.min(self.layer_size.width().max(1)),
+            tile_size.min(self.layer_size.height().max(1))
+        )
+    }
+
     /// Maximum byte length of an uncompressed or compressed block, used for validation.
     pub fn max_block_byte_size(&self) -> usize {
         self.channels.bytes_per_pixel * match self.blocks {
@@ -575,39 +855,67 @@ impl Header {
 
     /// Returns the number of bytes that the pixels of this header will require
     /// when stored without compression. Respects multi-resolution levels and subsampling.
+    /// Panics if the byte count does not fit into the address space of this machine.
     pub fn total_pixel_bytes(&self) -> usize {
+        self.checked_total_pixel_bytes().and_then(|bytes| usize::try_from(bytes).ok())
+            .expect("too large pixel byte count for this machine")
+    }
+
+    /// The number of bytes that the pixels of this header will require when stored without compression.
+    /// Computed with 64 bits on all platforms, such that layers larger than 4GB do not overflow on 32-bit targets.
+    /// Returns `None` only if the byte count exceeds even `u64::MAX`.
+    pub(crate) fn checked_total_pixel_bytes(&self) -> Option<u64> {
         assert!(!self.deep);
 
-        let pixel_count_of_levels = |size: Vec2<usize>| -> usize {
+        let area = |size: Vec2<usize>| usize_to_u64(size.width()).checked_mul(usize_to_u64(size.height()));
+        let sum = |sum: u64, area: Option<u64>| sum.checked_add(area?);
+
+        let pixel_count_of_levels = |size: Vec2<usize>| -> Option<u64> {
             match self.blocks {
-                BlockDescription::ScanLines => size.area(),
+                BlockDescription::ScanLines => area(size),
                 BlockDescription::Tiles(tile_description) => match tile_description.level_mode {
-                    LevelMode::Singular => size.area(),
+                    LevelMode::Singular => area(size),
 
                     LevelMode::MipMap => mip_map_levels(tile_description.rounding_mode, size)
-                        .map(|(_, size)| size.area()).sum(),
+                        .map(|(_, size)| area(size)).try_fold(0, sum),
 
                     LevelMode::RipMap => rip_map_levels(tile_description.rounding_mode, size)
-                        .map(|(_, size)| size.area()).sum(),
+                        .map(|(_, size)| area(size)).try_fold(0, sum),
                 }
             }
         };
 
         self.channels.list.iter()
             .map(|channel: &ChannelDescription|
-                pixel_count_of_levels(channel.subsampled_resolution(self.layer_size)) * channel.sample_type.bytes_per_sample()
+                pixel_count_of_levels(channel.subsampled_resolution(self.layer_size))?
+                    .checked_mul(usize_to_u64(channel.sample_type.bytes_per_sample()))
             )
-            .sum()
-
+            .try_fold(0, sum)
     }
 
     /// Approximates the maximum number of bytes that the pixels of this header will consume in a file.
     /// Due to compression, the actual byte size may be smaller.
-    pub fn max_pixel_file_bytes(&self) -> usize {
+    /// Returns a `u64`, as the pixels of a single layer may exceed 4GB.
+    pub fn max_pixel_file_bytes(&self) -> u64 {
         assert!(!self.deep);
 
-        self.chunk_count * 64 // at most 64 bytes overhead for each chunk (header index, tile description, chunk size, and more)
-            + self.total_pixel_bytes()
+        usize_to_u64(self.chunk_count).saturating_mul(64) // at most 64 bytes overhead for each chunk (header index, tile description, chunk size, and more)
+            .saturating_add(self.checked_total_pixel_bytes().unwrap_or(u64::MAX))
+    }
+
+    /// Check that the bytes of the largest block of this layer can be addressed on this machine.
+    /// On 32-bit targets, the byte size of a block of a very wide layer may exceed `usize::MAX`.
+    /// After this check succeeded, `max_block_byte_size` does not overflow.
+    pub fn validate_block_byte_size(&self) -> UnitResult {
+        let block_size = match self.blocks {
+            BlockDescription::Tiles(tiles) => tiles.tile_size,
+            BlockDescription::ScanLines => Vec2(self.layer_size.width(), self.compression.scan_lines_per_block()),
+        };
+
+        block_size.try_area("block")?
+            .checked_mul(self.channels.bytes_per_pixel)
+            .map(|_| ())
+            .ok_or(Error::unsupported("block byte size exceeds the address space of this machine"))
     }
 
     /// Validate this instance.
@@ -619,6 +927,7 @@ impl Header {
 
         self.data_window().validate(None)?;
         self.shared_attributes.display_window.validate(None)?;
+        self.validate_block_byte_size()?;
 
         if strict {
             if is_multilayer {
@@ -651,6 +960,17 @@ impl Header {
         let allow_subsampling = !self.deep && self.blocks == BlockDescription::ScanLines;
         self.channels.validate(allow_subsampling, self.data_window(), strict)?;
 
+        if !self.own_attributes.sample_filters.is_empty() {
+            self.own_attributes.sample_filters.validate(&self.channels)?;
+
+            if self.compression != Compression::ZIP1 && self.compression != Compression::ZIP16 {
+                return Err(Error::invalid("sample filters require zip compression"));
+            }
+        }
+
+        // the size of each attribute is stored as an i32 in the file
+        self.validate_attribute_sizes(i32::MAX as usize)?;
+
         for (name, value) in &self.shared_attributes.other {
             attribute::validate(name, value, long_names, allow_subsampling, self.data_window(), strict)?;
         }
@@ -667,19 +987,13 @@ impl Header {
         // check if attribute names appear twice
         if strict {
             for (name, _) in &self.shared_attributes.other {
-                if !self.own_attributes.other.contains_key(name) {
+                if self.own_attributes.other.contains_key(name) {
                     return Err(Error::invalid(format!("duplicate attribute name: `{}`", name)));
                 }
             }
 
-            for &reserved in header::standard_names::ALL.iter() {
-                let name  = Text::from_bytes_unchecked(SmallVec::from_slice(reserved));
-                if self.own_attributes.other.contains_key(&name) || self.shared_attributes.other.contains_key(&name) {
-                    return Err(Error::invalid(format!(
-                        "attribute name `{}` is reserved and cannot be custom",
-                        Text::from_bytes_unchecked(reserved.into())
-                    )));
-                }
+            for (name, value) in self.shared_attributes.other.iter().chain(&self.own_attributes.other) {
+                validate_custom_attribute_name(name, value)?;
             }
         }
 
@@ -708,6 +1022,51 @@ impl Header {
         Ok(())
     }
 
+    /// Check that no attribute value in this header is larger than the specified number of bytes.
+    /// The file format does not support attributes larger than `i32::MAX` bytes.
+    /// Only attributes with a variable size are checked: texts, text vectors, the preview image, the channel list, and custom attributes.
+    pub fn validate_attribute_sizes(&self, max_byte_size: usize) -> UnitResult {
+        use crate::meta::header::standard_names::*;
+
+        let check = |name: &[u8], byte_size: usize| -> UnitResult {
+            if byte_size <= max_byte_size { Ok(()) }
+            else {
+                Err(Error::invalid(format!(
+                    "attribute `{}` has {} bytes, but at most {} bytes are allowed",
+                    Text::from_slice_unchecked(name), byte_size, max_byte_size
+                )))
+            }
+        };
+
+        check(CHANNELS, self.channels.byte_size())?;
+
+        let own = &self.own_attributes;
+        let texts = [
+            (NAME, &own.layer_name), (RENDERING_TRANSFORM, &own.rendering_transform_name),
+            (LOOK_MOD_TRANSFORM, &own.look_modification_transform_name), (OWNER, &own.owner),
+            (HOST_COMPUTER, &own.host_computer), (COMMENTS, &own.comments), (CAPTURE_DATE, &own.capture_date), (WRAP_MODES, &own.wrap_mode_name),
+            (VIEW, &own.view_name), (SOFTWARE, &own.software_name),
+        ];
+
+        for &(name, text) in texts.iter() {
+            if let Some(text) = text { check(name, text.as_slice().len())?; }
+        }
+
+        if let Some(views) = &own.multi_view_names {
+            check(MULTI_VIEW, views.iter().map(Text::i32_sized_byte_size).sum())?;
+        }
+
+        if let Some(preview) = &own.preview {
+            check(PREVIEW, preview.byte_size())?;
+        }
+
+        for (name, value) in self.shared_attributes.other.iter().chain(own.other.iter()) {
+            check(name.as_slice(), value.byte_size())?;
+        }
+
+        Ok(())
+    }
+
     /// Read the headers without validating them.
     pub fn read_all(read: &mut PeekRead<impl Read>, version: &Requirements, pedantic: bool) -> Result<Headers> {
         if !version.is_multilayer() {
@@ -758,6 +1117,8 @@ impl Header {
         let mut layer_attributes = LayerAttributes::default();
         let mut image_attributes = ImageAttributes::new(IntegerBounds::zero());
 
+        let mut attribute_order = Vec::new();
+
         // read each attribute in this header
         while !sequence_end::has_come(read)? {
             let (attribute_name, value) = attribute::read(read, max_string_len)?;
@@ -765,6 +1126,8 @@ impl Header {
             // if the attribute value itself is ok, record it
             match value {
                 Ok(value) => {
+                    attribute_order.push(attribute_name.clone());
+
                     use crate::meta::header::standard_names as name;
                     use crate::meta::attribute::AttributeValue::*;
 
@@ -801,6 +1164,7 @@ impl Header {
                         (name::X_DENSITY, F32(value)) => layer_attributes.horizontal_density = Some(value),
 
                         (name::OWNER, Text(value)) => layer_attributes.owner = Some(value),
+                        (name::HOST_COMPUTER, Text(value)) => layer_attributes.host_computer = Some(value),
                         (name::COMMENTS, Text(value)) => layer_attributes.comments = Some(value),
                         (name::CAPTURE_DATE, Text(value)) => layer_attributes.capture_date = Some(value),
                         (name::UTC_OFFSET, F32(value)) => layer_attributes.utc_offset = Some(value),
@@ -830,6 +1194,8 @@ impl Header {
                         (name::FOV_Y, F32(value)) => layer_attributes.vertical_field_of_view = Some(value),
                         (name::SOFTWARE, Text(value)) => layer_attributes.software_name = Some(value),
 
+                        (filter::ATTRIBUTE_NAME, TextVector(value)) => layer_attributes.sample_filters = ChannelFilters::from_attribute(&value)?,
+
                         (name::PIXEL_ASPECT, F32(value)) => image_attributes.pixel_aspect = value,
                         (name::TIME_CODE, TimeCode(value)) => image_attributes.time_code = Some(value),
                         (name::CHROMATICITIES, Chromaticities(value)) => image_attributes.chromaticities = Some(value),
@@ -870,6 +1236,7 @@ impl Header {
 
         image_attributes.display_window = display_window.ok_or(missing_attribute("display window"))?;
         layer_attributes.layer_position = data_window.position;
+        layer_attributes.attribute_order = AttributeOrder { names: attribute_order };
 
         let data_size = data_window.size;
 
@@ -916,18 +1283,34 @@ impl Header {
     }
 
     /// Without validation, write this instance to the byte stream.
+    /// The attributes are written in the order of `ordered_attributes`.
     pub fn write(&self, write: &mut impl Write) -> UnitResult {
+        for (name, value) in self.ordered_attributes() {
+            attribute::write(name, &value, write)?;
+        }
+
+        sequence_end::write(write)?;
+        Ok(())
+    }
+
+    /// All attributes of this header, including the required attributes, the image attributes, and the custom attributes,
+    /// in the order they will be written to a file.
+    /// For headers that were read from a file, this is the order in which the attributes appeared in the file,
+    /// followed by any attributes that have been added since. See `LayerAttributes::attribute_order`.
+    /// The custom attributes are borrowed, all other attributes are constructed from the fields of this header.
+    pub fn ordered_attributes(&self) -> impl Iterator<Item = (&[u8], Cow<'_, AttributeValue>)> {
+        let mut attributes: Vec<(&[u8], Cow<'_, AttributeValue>)> = Vec::with_capacity(16 + self.own_attributes.other.len());
 
-        macro_rules! write_attributes {
+        macro_rules! push_attributes {
             ( $($name: ident : $variant: ident = $value: expr),* ) => { $(
-                attribute::write($name, & $variant ($value .clone()), write)?; // TODO without clone
+                attributes.push(($name, Cow::Owned($variant ($value .clone())))); // TODO without clone
             )* };
         }
 
-        macro_rules! write_optional_attributes {
+        macro_rules! push_optional_attributes {
             ( $($name: ident : $variant: ident = $value: expr),* ) => { $(
                 if let Some(value) = $value {
-                    attribute::write($name, & $variant (value.clone()), write)?; // TODO without clone
+                    attributes.push(($name, Cow::Owned($variant (value.clone())))); // TODO without clone
                 };
             )* };
         }
@@ -944,13 +1327,13 @@ impl Header {
             I32(i32::try_from(value).expect("u32 exceeds i32 range"))
         }
 
-        write_optional_attributes!(
+        push_optional_attributes!(
             TILES: TileDescription = &tiles,
             DEEP_DATA_VERSION: I32 = &self.deep_data_version,
             MAX_SAMPLES: usize_as_i32 = &self.max_samples_per_pixel
         );
 
-        write_attributes!(
+        push_attributes!(
             // chunks is not actually required, but always computed in this library anyways
             CHUNKS: usize_as_i32 = &self.chunk_count,
 
@@ -967,7 +1350,7 @@ impl Header {
             WINDOW_WIDTH: F32 = &self.own_attributes.screen_window_width
         );
 
-        write_optional_attributes!(
+        push_optional_attributes!(
             NAME: Text = &self.own_attributes.layer_name,
             WHITE_LUMINANCE: F32 = &self.own_attributes.white_luminance,
             ADOPTED_NEUTRAL: FloatVec2 = &self.own_attributes.adopted_neutral,
@@ -975,6 +1358,7 @@ impl Header {
             LOOK_MOD_TRANSFORM: Text = &self.own_attributes.look_modification_transform_name,
             X_DENSITY: F32 = &self.own_attributes.horizontal_density,
             OWNER: Text = &self.own_attributes.owner,
+            HOST_COMPUTER: Text = &self.own_attributes.host_computer,
             COMMENTS: Text = &self.own_attributes.comments,
             CAPTURE_DATE: Text = &self.own_attributes.capture_date,
             UTC_OFFSET: F32 = &self.own_attributes.utc_offset,
@@ -1005,26 +1389,39 @@ impl Header {
             SOFTWARE: Text = &self.own_attributes.software_name
         );
 
+        if !self.own_attributes.sample_filters.is_empty() {
+            attributes.push((filter::ATTRIBUTE_NAME, Cow::Owned(TextVector(self.own_attributes.sample_filters.to_attribute()))));
+        }
+
         // dwa writes compression parameters as attribute.
         match self.compression {
             attribute::Compression::DWAA(Some(level)) |
             attribute::Compression::DWAB(Some(level)) =>
-                attribute::write(DWA_COMPRESSION_LEVEL, &F32(level), write)?,
+                attributes.push((DWA_COMPRESSION_LEVEL, Cow::Owned(F32(level)))),
 
             _ => {}
         };
 
 
-        for (name, value) in &self.shared_attributes.other {
-            attribute::write(name.as_slice(), value, write)?;
+        // sort the custom attributes by name, such that the bytes do not depend on the order of the hash maps
+        for custom_attributes in &[ &self.shared_attributes.other, &self.own_attributes.other ] {
+            let mut custom_attributes: Vec<_> = custom_attributes.iter().collect();
+            custom_attributes.sort_unstable_by(|(name, _), (other_name, _)| name.as_slice().cmp(other_name.as_slice()));
+
+            for (name, value) in custom_attributes {
+                attributes.push((name.as_slice(), Cow::Borrowed(value)));
+            }
         }
 
-        for (name, value) in &self.own_attributes.other {
-            attribute::write(name.as_slice(), value, write)?;
+        // restore the order of the original file, keeping new attributes at the end (the sort is stable)
+        let order = self.own_attributes.attribute_order();
+        if !order.is_empty() {
+            attributes.sort_by_key(|(name, _)|
+                order.iter().position(|ordered_name| ordered_name.as_slice() == *name).unwrap_or(order.len())
+            );
         }
 
-        sequence_end::write(write)?;
-        Ok(())
+        attributes.into_iter()
     }
 
     /// The rectangle describing the bounding box of this layer
@@ -1032,14 +1429,62 @@ impl Header {
     pub fn data_window(&self) -> IntegerBounds {
         IntegerBounds::new(self.own_attributes.layer_position, self.layer_size)
     }
+
+    /// Whether this layer contains a channel with exactly this name.
+    pub fn has_channel(&self, name: &str) -> bool {
+        self.channels.list.iter().any(|channel| channel.name.eq(name))
+    }
+
+    /// Whether this layer contains all of the channels, for example `header.has_channels(["R", "G", "B"])`.
+    /// The names are compared exactly.
+    pub fn has_channels(&self, names: impl IntoIterator<Item = impl AsRef<str>>) -> bool {
+        names.into_iter().all(|name| self.has_channel(name.as_ref()))
+    }
+
+    /// Find the first channel whose name matches, ignoring capitalization.
+    /// For example, `"z"` finds the channel named `Z`.
+    pub fn find_channel_case_insensitive(&self, name: &str) -> Option<&ChannelDescription> {
+        self.channels.list.iter().find(|channel| channel.name.eq_case_insensitive(name))
+    }
+
+    /// Whether this layer contains an alpha channel, named `A` or `a`.
+    pub fn has_alpha(&self) -> bool {
+        self.find_channel_case_insensitive("A").is_some()
+    }
 }
 
 
+/// Check that a custom attribute does not use the name of a standard attribute.
+/// Standard attributes must be specified using the fields of `LayerAttributes` or `ImageAttributes`,
+/// otherwise they would be parsed as the standard attribute when reading the file again,
+/// or the file could not be read at all, if the type of the value does not match.
+/// Vendor-specific attributes can use a namespace to avoid collisions, see `Text::namespaced`.
+fn validate_custom_attribute_name(name: &Text, value: &AttributeValue) -> UnitResult {
+    let required_type = match standard_names::type_name_of(name.as_slice()) {
+        None => return Ok(()),
+        Some(required_type) => required_type,
+    };
+
+    if value.kind_name() != required_type {
+        Err(Error::invalid(format!(
+            "custom attribute `{}` has type `{}`, but the name is reserved for a standard attribute of type `{}`",
+            name, Text::from_slice_unchecked(value.kind_name()), Text::from_slice_unchecked(required_type)
+        )))
+    }
+    else {
+        Err(Error::invalid(format!(
+            "attribute name `{}` is reserved, specify the standard attribute in the layer or image attributes instead of a custom attribute",
+            name
+        )))
+    }
+}
 
 /// Collection of required attribute names.
 pub mod standard_names {
+    use crate::meta::attribute::type_names as ty;
+
     macro_rules! define_required_attribute_names {
-        ( $($name: ident  :  $value: expr),* ) => {
+        ( $($name: ident  :  $value: expr => $kind: ident),* ) => {
 
             /// A list containing all reserved names.
             pub const ALL: &'static [&'static [u8]] = &[
@@ -1050,59 +1495,256 @@ pub mod standard_names {
                 /// The byte-string name of this required attribute as it appears in an exr file.
                 pub const $name: &'static [u8] = $value;
             )*
+
+            /// The type name of the value that is required for the attribute with this reserved name,
+            /// or `None` if the name is not reserved.
+            pub fn type_name_of(name: &[u8]) -> Option<&'static [u8]> {
+                $( if name == $value { return Some(ty::$kind); } )*
+                None
+            }
         };
     }
 
+    /// Whether the name is reserved for a standard attribute and cannot be used for a custom attribute.
+    pub fn is_reserved(name: &[u8]) -> bool {
+        type_name_of(name).is_some()
+    }
+
     define_required_attribute_names! {
-        TILES: b"tiles",
-        NAME: b"name",
-        BLOCK_TYPE: b"type",
-        DEEP_DATA_VERSION: b"version",
-        CHUNKS: b"chunkCount",
-        MAX_SAMPLES: b"maxSamplesPerPixel",
-        CHANNELS: b"channels",
-        COMPRESSION: b"compression",
-        DATA_WINDOW: b"dataWindow",
-        DISPLAY_WINDOW: b"displayWindow",
-        LINE_ORDER: b"lineOrder",
-        PIXEL_ASPECT: b"pixelAspectRatio",
-        WINDOW_CENTER: b"screenWindowCenter",
-        WINDOW_WIDTH: b"screenWindowWidth",
-        WHITE_LUMINANCE: b"whiteLuminance",
-        ADOPTED_NEUTRAL: b"adoptedNeutral",
-        RENDERING_TRANSFORM: b"renderingTransform",
-        LOOK_MOD_TRANSFORM: b"lookModTransform",
-        X_DENSITY: b"xDensity",
-        OWNER: b"owner",
-        COMMENTS: b"comments",
-        CAPTURE_DATE: b"capDate",
-        UTC_OFFSET: b"utcOffset",
-        LONGITUDE: b"longitude",
-        LATITUDE: b"latitude",
-        ALTITUDE: b"altitude",
-        FOCUS: b"focus",
-        EXPOSURE_TIME: b"expTime",
-        APERTURE: b"aperture",
-        ISO_SPEED: b"isoSpeed",
-        ENVIRONMENT_MAP: b"envmap",
-        KEY_CODE: b"keyCode",
-        TIME_CODE: b"timeCode",
-        WRAP_MODES: b"wrapmodes",
-        FRAMES_PER_SECOND: b"framesPerSecond",
-        MULTI_VIEW: b"multiView",
-        WORLD_TO_CAMERA: b"worldToCamera",
-        WORLD_TO_NDC: b"worldToNDC",
-        DEEP_IMAGE_STATE: b"deepImageState",
-        ORIGINAL_DATA_WINDOW: b"originalDataWindow",
-        DWA_COMPRESSION_LEVEL: b"dwaCompressionLevel",
-        PREVIEW: b"preview",
-        VIEW: b"view",
-        CHROMATICITIES: b"chromaticities",
-        NEAR: b"near",
-        FAR: b"far",
-        FOV_X: b"fieldOfViewHorizontal",
-        FOV_Y: b"fieldOfViewVertical",
-        SOFTWARE: b"software"
+        TILES: b"tiles" => TILES,
+        NAME: b"name" => TEXT,
+        BLOCK_TYPE: b"type" => TEXT,
+        DEEP_DATA_VERSION: b"version" => I32,
+        CHUNKS: b"chunkCount" => I32,
+        MAX_SAMPLES: b"maxSamplesPerPixel" => I32,
+        CHANNELS: b"channels" => CHANNEL_LIST,
+        COMPRESSION: b"compression" => COMPRESSION,
+        DATA_WINDOW: b"dataWindow" => I32BOX2,
+        DISPLAY_WINDOW: b"displayWindow" => I32BOX2,
+        LINE_ORDER: b"lineOrder" => LINE_ORDER,
+        PIXEL_ASPECT: b"pixelAspectRatio" => F32,
+        WINDOW_CENTER: b"screenWindowCenter" => F32VEC2,
+        WINDOW_WIDTH: b"screenWindowWidth" => F32,
+        WHITE_LUMINANCE: b"whiteLuminance" => F32,
+        ADOPTED_NEUTRAL: b"adoptedNeutral" => F32VEC2,
+        RENDERING_TRANSFORM: b"renderingTransform" => TEXT,
+        LOOK_MOD_TRANSFORM: b"lookModTransform" => TEXT,
+        X_DENSITY: b"xDensity" => F32,
+        OWNER: b"owner" => TEXT,
+        HOST_COMPUTER: b"hostComputer" => TEXT,
+        COMMENTS: b"comments" => TEXT,
+        CAPTURE_DATE: b"capDate" => TEXT,
+        UTC_OFFSET: b"utcOffset" => F32,
+        LONGITUDE: b"longitude" => F32,
+        LATITUDE: b"latitude" => F32,
+        ALTITUDE: b"altitude" => F32,
+        FOCUS: b"focus" => F32,
+        EXPOSURE_TIME: b"expTime" => F32,
+        APERTURE: b"aperture" => F32,
+        ISO_SPEED: b"isoSpeed" => F32,
+        ENVIRONMENT_MAP: b"envmap" => ENVIRONMENT_MAP,
+        KEY_CODE: b"keyCode" => KEY_CODE,
+        TIME_CODE: b"timeCode" => TIME_CODE,
+        WRAP_MODES: b"wrapmodes" => TEXT,
+        FRAMES_PER_SECOND: b"framesPerSecond" => RATIONAL,
+        MULTI_VIEW: b"multiView" => TEXT_VECTOR,
+        WORLD_TO_CAMERA: b"worldToCamera" => F32MATRIX4X4,
+        WORLD_TO_NDC: b"worldToNDC" => F32MATRIX4X4,
+        DEEP_IMAGE_STATE: b"deepImageState" => RATIONAL,
+        ORIGINAL_DATA_WINDOW: b"originalDataWindow" => I32BOX2,
+        DWA_COMPRESSION_LEVEL: b"dwaCompressionLevel" => F32,
+        PREVIEW: b"preview" => PREVIEW,
+        VIEW: b"view" => TEXT,
+        CHROMATICITIES: b"chromaticities" => CHROMATICITIES,
+        NEAR: b"near" => F32,
+        FAR: b"far" => F32,
+        FOV_X: b"fieldOfViewHorizontal" => F32,
+        FOV_Y: b"fieldOfViewVertical" => F32,
+        SOFTWARE: b"software" => TEXT
+    }
+}
+
+
+/// Assembles a `Header` step by step, while computing the chunk count automatically.
+/// The layer size and at least one channel are required, all other properties are optional.
+/// Call `build` to check the header for mistakes before any pixels are written,
+/// instead of producing a corrupt file.
+///
+/// Unless specified otherwise, the header uses the same defaults as `Header::new`,
+/// except that scan line blocks are stored in increasing line order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeaderBuilder {
+    layer_size: Option<Vec2<usize>>,
+    channels: SmallVec<[ChannelDescription; 5]>,
+    compression: Compression,
+    blocks: BlockDescription,
+    line_order: Option<LineOrder>,
+    shared_attributes: Option<ImageAttributes>,
+    own_attributes: LayerAttributes,
+}
+
+impl Header {
+
+    /// Start assembling a header. Call `layer_size` and `channel` on the result,
+    /// then call `build` to obtain the validated header.
+    pub fn builder() -> HeaderBuilder {
+        HeaderBuilder::default()
+    }
+}
+
+impl Default for HeaderBuilder {
+    fn default() -> Self {
+        HeaderBuilder {
+            layer_size: None,
+            channels: SmallVec::new(),
+            compression: Compression::RLE,
+            blocks: BlockDescription::Tiles(TileDescription {
+                tile_size: Vec2(64, 64),
+                level_mode: LevelMode::Singular,
+                rounding_mode: RoundingMode::Down
+            }),
+            line_order: None,
+            shared_attributes: None,
+            own_attributes: LayerAttributes::default(),
+        }
+    }
+}
+
+impl HeaderBuilder {
+
+    /// Required. Set the resolution of this layer.
+    pub fn layer_size(self, layer_size: impl Into<Vec2<usize>>) -> Self {
+        Self { layer_size: Some(layer_size.into()), .. self }
+    }
+
+    /// Required at least once. Add a channel to this layer.
+    /// The channels do not need to be sorted.
+    pub fn channel(mut self, channel: ChannelDescription) -> Self {
+        self.channels.push(channel);
+        self
+    }
+
+    /// Add multiple channels to this layer. The channels do not need to be sorted.
+    pub fn channels(mut self, channels: impl IntoIterator<Item=ChannelDescription>) -> Self {
+        self.channels.extend(channels);
+        self
+    }
+
+    /// Set the name of this layer. Required if the file contains multiple layers.
+    pub fn name(mut self, name: impl Into<Text>) -> Self {
+        self.own_attributes.layer_name = Some(name.into());
+        self
+    }
+
+    /// Set the offset of this layer.
+    pub fn position(mut self, position: Vec2<i32>) -> Self {
+        self.own_attributes.layer_position = position;
+        self
+    }
+
+    /// Set how the pixel data of all channels in this layer is compressed.
+    pub fn compression(self, compression: Compression) -> Self {
+        Self { compression, .. self }
+    }
+
+    /// Set whether the layer is divided into scan line blocks or tiles.
+    pub fn blocks(self, blocks: BlockDescription) -> Self {
+        Self { blocks, .. self }
+    }
+
+    /// Set in what order the blocks of this layer are stored in the file.
+    /// Defaults to increasing for scan line blocks, and to unspecified for tiles.
+    pub fn line_order(self, line_order: LineOrder) -> Self {
+        Self { line_order: Some(line_order), .. self }
+    }
+
+    /// Set the display window, that is, the global clipping rectangle.
+    /// Defaults to the size of this layer.
+    pub fn display_window(mut self, display_window: IntegerBounds) -> Self {
+        let layer_size = self.layer_size.unwrap_or_default();
+        self.shared_attributes.get_or_insert_with(|| ImageAttributes::with_size(layer_size)).display_window = display_window;
+        self
+    }
+
+    /// Set **all** attributes of the header that are not shared with all other headers in the image.
+    /// Replaces the previously specified name and position.
+    pub fn layer_attributes(self, own_attributes: LayerAttributes) -> Self {
+        Self { own_attributes, .. self }
+    }
+
+    /// Set **all** attributes of the header that are shared with all other headers in the image.
+    /// Replaces the previously specified display window.
+    pub fn image_attributes(self, shared_attributes: ImageAttributes) -> Self {
+        Self { shared_attributes: Some(shared_attributes), .. self }
+    }
+
+    /// Add a custom attribute to this layer.
+    /// The name must not be reserved for a standard attribute, which is checked by `build`.
+    /// Consider `Text::namespaced` for the names of vendor-specific attributes.
+    pub fn attribute(mut self, name: impl Into<Text>, value: AttributeValue) -> Self {
+        self.own_attributes.other.insert(name.into(), value);
+        self
+    }
+
+    /// Check whether the header would be valid, without consuming this builder.
+    /// Checks the tile size, the number of chunks, the channel names, and all attribute values.
+    pub fn validate(&self) -> UnitResult {
+        self.clone().build().map(|_| ())
+    }
+
+    /// Check all properties and create the header.
+    /// Sorts the channels alphabetically and computes the chunk count.
+    pub fn build(self) -> Result<Header> {
+        let layer_size = self.layer_size.ok_or(Error::invalid("header builder is missing the layer size"))?;
+        if layer_size.area() == 0 { return Err(Error::invalid("layer size must not be zero")); }
+        if self.channels.is_empty() { return Err(Error::invalid("header builder requires at least one channel")); }
+
+        if let BlockDescription::Tiles(tiles) = self.blocks {
+            tiles.validate()?;
+        }
+
+        let header = self.build_unchecked();
+
+        if let Some(duplicate) = header.channels.list.windows(2).find(|pair| pair[0].name == pair[1].name) {
+            return Err(Error::invalid(format!("channel name `{}` is duplicate", duplicate[0].name)));
+        }
+
+        // the chunk count is stored as an i32 attribute
+        if i32::try_from(header.chunk_count).is_err() {
+            return Err(Error::invalid(format!("the layer would contain too many chunks ({})", header.chunk_count)));
+        }
+
+        // whether the layer name is required depends on the other headers, which are checked when writing the file
+        header.validate(false, &mut false, true)?;
+        Ok(header)
+    }
+
+    /// Create the header without checking any properties. A missing layer size is treated as zero.
+    pub(crate) fn build_unchecked(self) -> Header {
+        let layer_size = self.layer_size.unwrap_or_default();
+
+        let mut channels = self.channels;
+        channels.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+
+        // scan line blocks require a specified line order
+        let line_order = self.line_order.unwrap_or(match self.blocks {
+            BlockDescription::ScanLines => LineOrder::Increasing,
+            BlockDescription::Tiles(_) => LineOrder::Unspecified,
+        });
+
+        Header {
+            channels: ChannelList::new(channels),
+            compression: self.compression,
+            blocks: self.blocks,
+            chunk_count: compute_chunk_count(self.compression, layer_size, self.blocks),
+            line_order,
+            layer_size,
+            deep: false,
+            deep_data_version: None,
+            max_samples_per_pixel: None,
+            shared_attributes: self.shared_attributes.unwrap_or_else(|| ImageAttributes::with_size(layer_size)),
+            own_attributes: self.own_attributes,
+        }
     }
 }
 
@@ -1120,6 +1762,7 @@ impl Default for LayerAttributes {
             look_modification_transform_name: None,
             horizontal_density: None,
             owner: None,
+            host_computer: None,
             comments: None,
             capture_date: None,
             utc_offset: None,
@@ -1146,7 +1789,9 @@ impl Default for LayerAttributes {
             far_clip_plane: None,
             horizontal_field_of_view: None,
             vertical_field_of_view: None,
-            other: Default::default()
+            other: Default::default(),
+            sample_filters: Default::default(),
+            attribute_order: Default::default(),
         }
     }
 }
@@ -1175,7 +1820,7 @@ impl std::fmt::Debug for LayerAttributes {
             screen_window_center, screen_window_width,
             white_luminance, adopted_neutral, horizontal_density,
             rendering_transform_name, look_modification_transform_name,
-            owner, comments,
+            owner, host_computer, comments,
             capture_date, utc_offset,
             longitude, latitude, altitude,
             focus, exposure, aperture, iso_speed,
@@ -1185,7 +1830,8 @@ impl std::fmt::Debug for LayerAttributes {
             deep_image_state, original_data_window,
             preview, view_name,
             vertical_field_of_view, horizontal_field_of_view,
-            near_clip_plane, far_clip_plane, software_name
+            near_clip_plane, far_clip_plane, software_name,
+            sample_filters
         }
 
         for (name, value) in &self.other {
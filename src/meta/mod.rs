@@ -2,9 +2,14 @@
 //! Describes all meta data possible in an exr file.
 //! Contains functionality to read and write meta data from bytes.
 //! Browse the `exr::image` module to get started with the high-level interface.
+//!
+//! Also contains the geometry of resolution levels and blocks, such as `compute_level_size`
+//! and `Header::get_tiles_intersecting`, which can be used to plan which blocks to process,
+//! without reading any pixels.
 
 pub mod attribute;
 pub mod header;
+pub mod dump;
 
 
 use crate::io::*;
@@ -12,7 +17,6 @@ use ::smallvec::SmallVec;
 use self::attribute::*;
 use crate::block::chunk::{TileCoordinates, CompressedBlock};
 use crate::error::*;
-use std::fs::File;
 use std::io::{BufReader};
 use crate::math::*;
 use std::collections::{HashSet};
@@ -61,6 +65,48 @@ pub type OffsetTables = SmallVec<[OffsetTable; 3]>;
 pub type OffsetTable = Vec<u64>;
 
 
+/// Describes how an image will be read, to estimate the memory required for reading it.
+/// See `MetaData::estimated_peak_memory`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MemoryEstimateOptions {
+
+    /// Whether all resolution levels are loaded, instead of only the largest level.
+    pub all_resolution_levels: bool,
+
+    /// How many blocks are decompressed at the same time.
+    /// One for sequential reading, or about the number of threads for parallel reading.
+    pub blocks_in_flight: usize,
+
+    /// How many compressed chunks are read ahead, see `ReadImage::prefetch_chunks`.
+    pub prefetched_chunks: usize,
+}
+
+impl MemoryEstimateOptions {
+
+    /// Read only the largest resolution level on the current thread.
+    pub fn sequential() -> Self {
+        Self { all_resolution_levels: false, blocks_in_flight: 1, prefetched_chunks: 0 }
+    }
+
+    /// Read only the largest resolution level, decompressing on one thread per cpu, which is the default.
+    /// Respects the environment variable `EXR_THREADS`, see `block::default_thread_count`.
+    pub fn parallel() -> Self {
+        let threads = crate::block::default_thread_count();
+        Self { blocks_in_flight: threads + 2, .. Self::sequential() } // same as `ParallelBlockDecompressor`
+    }
+
+    /// Load all resolution levels instead of only the largest level.
+    pub fn all_resolution_levels(self) -> Self { Self { all_resolution_levels: true, ..self } }
+
+    /// Read ahead the specified number of compressed chunks.
+    pub fn prefetch_chunks(self, chunk_count: usize) -> Self { Self { prefetched_chunks: chunk_count, ..self } }
+}
+
+impl Default for MemoryEstimateOptions {
+    fn default() -> Self { Self::parallel() }
+}
+
+
 /// A summary of requirements that must be met to read this exr file.
 /// Used to determine whether this file can be read by a given reader.
 /// It includes the OpenEXR version number. This library aims to support version `2.0`.
@@ -85,6 +131,34 @@ pub struct Requirements {
 
     /// Whether this file contains multiple layers.
     pub has_multiple_layers: bool,
+
+    /// The reserved feature flags that are set in this file, see `Requirements::unknown_flags`.
+    unknown_flags: u32,
+}
+
+/// A feature of an exr file that this library cannot read. See `Requirements::unsupported_features`.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Hash)]
+pub enum UnsupportedFeature {
+
+    /// The file format has a version other than `2`.
+    FileFormatVersion(u8),
+
+    /// The file contains deep data. The meta data can still be read, but the pixels cannot.
+    DeepData,
+
+    /// The file declares features of a newer version of the file format.
+    /// Contains the raw bits of the unknown flags.
+    UnknownFlags(u32),
+}
+
+impl std::fmt::Display for UnsupportedFeature {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UnsupportedFeature::FileFormatVersion(version) => write!(formatter, "file format version {}", version),
+            UnsupportedFeature::DeepData => write!(formatter, "deep data"),
+            UnsupportedFeature::UnknownFlags(flags) => write!(formatter, "unknown feature flags {:#010x}", flags),
+        }
+    }
 }
 
 
@@ -220,7 +294,8 @@ pub fn compute_block_count(full_res: usize, tile_size: usize) -> usize {
 /// Compute the start position and size of a block inside a dimension.
 #[inline]
 pub fn calculate_block_position_and_size(total_size: usize, block_size: usize, block_index: usize) -> Result<(usize, usize)> {
-    let block_position = block_size * block_index;
+    let block_position = block_size.checked_mul(block_index)
+        .ok_or(Error::invalid("block index"))?;
 
     Ok((
         block_position,
@@ -237,11 +312,9 @@ pub fn calculate_block_size(total_size: usize, block_size: usize, block_position
         return Err(Error::invalid("block index"))
     }
 
-    if block_position + block_size <= total_size {
-        Ok(block_size)
-    }
-    else {
-        Ok(total_size - block_position)
+    match block_position.checked_add(block_size) {
+        Some(block_end) if block_end <= total_size => Ok(block_size),
+        _ => Ok(total_size - block_position),
     }
 }
 
@@ -355,7 +428,7 @@ impl MetaData {
     /// Does not validate the meta data.
     #[must_use]
     pub fn read_from_file(path: impl AsRef<::std::path::Path>, pedantic: bool) -> Result<Self> {
-        Self::read_from_unbuffered(File::open(path)?, pedantic)
+        Self::read_from_unbuffered(crate::io::open_file(path.as_ref())?, pedantic)
     }
 
     /// Buffer the reader and then read the exr meta data from it.
@@ -464,6 +537,44 @@ impl MetaData {
         )
     }
 
+    /// Estimate the number of bytes that all pixels of all layers require after decoding,
+    /// in their original sample types, including all resolution levels.
+    /// The number of samples in deep layers is unknown before reading them,
+    /// so deep layers are estimated with the maximum samples per pixel if specified, or otherwise one sample per pixel.
+    pub fn estimated_decoded_size(&self) -> usize {
+        self.headers.iter().map(|header| estimated_decoded_layer_size(header, true)).fold(0, usize::saturating_add)
+    }
+
+    /// Estimate the maximum number of bytes allocated at the same time while reading the image.
+    /// This includes the decoded pixels, the offset tables, and the blocks that are decompressed or read ahead.
+    /// Use this to decide whether to load the whole image, to read only some layers or a smaller resolution level,
+    /// or to process the blocks one after another instead.
+    /// The actual memory usage also depends on how the pixels are stored, for example when converting to `f32` samples.
+    pub fn estimated_peak_memory(&self, options: MemoryEstimateOptions) -> usize {
+        let decoded: usize = self.headers.iter()
+            .map(|header| estimated_decoded_layer_size(header, options.all_resolution_levels))
+            .fold(0, usize::saturating_add);
+
+        let offset_tables: usize = self.headers.iter()
+            .map(|header| header.chunk_count * u64::BYTE_SIZE)
+            .sum();
+
+        let largest_block = self.headers.iter()
+            .map(|header| header.max_block_byte_size())
+            .max().unwrap_or(0);
+
+        // each decompressing block holds the compressed and the uncompressed bytes
+        let blocks = options.blocks_in_flight * largest_block * 2 + options.prefetched_chunks * largest_block;
+
+        decoded + offset_tables + blocks
+    }
+
+    /// The layers that contain an alpha channel, together with their index.
+    /// See `Header::has_alpha`.
+    pub fn layers_with_alpha(&self) -> impl '_ + Iterator<Item = (usize, &Header)> {
+        self.headers.iter().enumerate().filter(|(_, header)| header.has_alpha())
+    }
+
     /// Validates this meta data. Returns the minimal possible requirements.
     pub fn validate(headers: &[Header], pedantic: bool) -> Result<Requirements> {
         if headers.len() == 0 {
@@ -486,6 +597,7 @@ impl MetaData {
             is_single_layer_and_tiled: !is_multilayer && first_header_has_tiles,
             has_multiple_layers: is_multilayer,
             has_deep_data: deep,
+            unknown_flags: 0,
         };
 
         for header in headers {
@@ -547,6 +659,45 @@ impl MetaData {
 
 
 
+/// Whether all pixels of the image can be decoded on this machine.
+/// On 32-bit targets, such as `wasm32` or `armv7`, the byte size of large layers or blocks
+/// may exceed the address space, such that the image cannot be loaded into memory.
+/// Use this to reject such images before reading any pixels.
+pub fn supports_image(meta_data: &MetaData) -> bool {
+    meta_data.headers.iter()
+        .map(|header| header.validate_block_byte_size().ok().and_then(|_| checked_decoded_layer_size(header, true)))
+        .try_fold(0_usize, |sum, layer_size| sum.checked_add(layer_size?))
+        .is_some()
+}
+
+/// The bytes of all samples of a layer, see `MetaData::estimated_decoded_size`.
+/// Saturates at `usize::MAX` for layers that do not fit into the address space of this machine.
+fn estimated_decoded_layer_size(header: &Header, all_resolution_levels: bool) -> usize {
+    checked_decoded_layer_size(header, all_resolution_levels).unwrap_or(usize::MAX)
+}
+
+/// The bytes of all samples of a layer, or `None` if the byte count exceeds `usize::MAX`.
+fn checked_decoded_layer_size(header: &Header, all_resolution_levels: bool) -> Option<usize> {
+    let pixel_count = header.layer_size.checked_area()?;
+
+    if header.deep {
+        let samples_per_pixel = header.max_samples_per_pixel.unwrap_or(1);
+        let sample_count_table_bytes = pixel_count.checked_mul(u32::BYTE_SIZE)?;
+
+        return pixel_count.checked_mul(header.channels.bytes_per_pixel)?
+            .checked_mul(samples_per_pixel)?
+            .checked_add(sample_count_table_bytes);
+    }
+
+    if all_resolution_levels { usize::try_from(header.checked_total_pixel_bytes()?).ok() }
+    else {
+        header.channels.list.iter()
+            .map(|channel| channel.subsampled_resolution(header.layer_size).checked_area()?.checked_mul(channel.sample_type.bytes_per_sample()))
+            .try_fold(0_usize, |sum, channel_bytes| sum.checked_add(channel_bytes?))
+    }
+}
+
+
 impl Requirements {
 
     // this is actually used for control flow, as the number of headers may be 1 in a multilayer file
@@ -555,6 +706,22 @@ impl Requirements {
         self.has_multiple_layers
     }
 
+    /// The feature flags that are reserved in the file format, but set in this file.
+    /// These are features of a newer version of the file format, which this library does not know.
+    /// Contains the raw bits of the version field, and is zero for all files that this library can read.
+    pub fn unknown_flags(&self) -> u32 {
+        self.unknown_flags
+    }
+
+    /// Read the requirements of an exr file, without reading the headers, and without validating.
+    /// Use this to find out whether a file can be read by this library, see `unsupported_features`.
+    #[must_use]
+    pub fn read_from_file(path: impl AsRef<::std::path::Path>) -> Result<Self> {
+        let mut read = BufReader::new(crate::io::open_file(path.as_ref())?);
+        magic_number::validate_exr(&mut read)?;
+        Self::read(&mut read)
+    }
+
     /// Read the value without validating.
     pub fn read<R: Read>(read: &mut R) -> Result<Self> {
         use ::bit_field::BitField;
@@ -562,7 +729,7 @@ impl Requirements {
         let version_and_flags = u32::read(read)?;
 
         // take the 8 least significant bits, they contain the file format version number
-        let version = (version_and_flags & 0x000F) as u8;
+        let version = (version_and_flags & 0x00FF) as u8;
 
         // the 24 most significant bits are treated as a set of boolean flags
         let is_single_tile = version_and_flags.get_bit(9);
@@ -573,16 +740,12 @@ impl Requirements {
         // all remaining bits except 9, 10, 11 and 12 are reserved and should be 0
         // if a file has any of these bits set to 1, it means this file contains
         // a feature that we don't support
-        let unknown_flags = version_and_flags >> 13; // all flags excluding the 12 bits we already parsed
-
-        if unknown_flags != 0 { // TODO test if this correctly detects unsupported files
-            return Err(Error::unsupported("too new file feature flags"));
-        }
+        let unknown_flags = version_and_flags & !(0x00FF | 0b1111 << 9);
 
         let version = Requirements {
             file_format_version: version,
             is_single_layer_and_tiled: is_single_tile, has_long_names,
-            has_deep_data, has_multiple_layers,
+            has_deep_data, has_multiple_layers, unknown_flags,
         };
 
         Ok(version)
@@ -601,44 +764,76 @@ impl Requirements {
         version_and_flags.set_bit(10, self.has_long_names);
         version_and_flags.set_bit(11, self.has_deep_data);
         version_and_flags.set_bit(12, self.has_multiple_layers);
+
         // all remaining bits except 9, 10, 11 and 12 are reserved and should be 0
+        version_and_flags |= self.unknown_flags;
 
         version_and_flags.write(write)?;
         Ok(())
     }
 
-    /// Validate this instance.
-    pub fn validate(&self) -> UnitResult {
-        if self.file_format_version == 2 {
+    /// The features of the file that this library cannot read, in no particular order.
+    /// Empty if the file can be read completely.
+    pub fn unsupported_features(&self) -> Vec<UnsupportedFeature> {
+        let mut unsupported = Vec::new();
+
+        if self.file_format_version != 2 {
+            unsupported.push(UnsupportedFeature::FileFormatVersion(self.file_format_version));
+        }
 
-            match (
-                self.is_single_layer_and_tiled, self.has_deep_data, self.has_multiple_layers,
-                self.file_format_version
-            ) {
-                // Single-part scan line. One normal scan line image.
-                (false, false, false, 1..=2) => Ok(()),
+        if self.has_deep_data { // TODO deep data
+            unsupported.push(UnsupportedFeature::DeepData);
+        }
 
-                // Single-part tile. One normal tiled image.
-                (true, false, false, 1..=2) => Ok(()),
+        if self.unknown_flags != 0 {
+            unsupported.push(UnsupportedFeature::UnknownFlags(self.unknown_flags));
+        }
 
-                // Multi-part (new in 2.0).
-                // Multiple normal images (scan line and/or tiled).
-                (false, false, true, 2) => Ok(()),
+        unsupported
+    }
 
-                // Single-part deep data (new in 2.0).
-                // One deep tile or deep scan line part
-                (false, true, false, 2) => Ok(()),
+    /// Whether this library can read the pixels of the file. See `unsupported_features` for the reasons.
+    pub fn is_supported_by_this_crate(&self) -> bool {
+        self.unsupported_features().is_empty()
+    }
 
-                // Multi-part deep data (new in 2.0).
-                // Multiple parts (any combination of:
-                // tiles, scan lines, deep tiles and/or deep scan lines).
-                (false, true, true, 2) => Ok(()),
+    /// Validate this instance. Returns an error that lists all unsupported features
+    /// which prevent reading the headers. Files with deep data are not rejected here,
+    /// such that their meta data can still be read.
+    pub fn validate(&self) -> UnitResult {
+        let unsupported: Vec<String> = self.unsupported_features().into_iter()
+            .filter(|feature| *feature != UnsupportedFeature::DeepData)
+            .map(|feature| feature.to_string())
+            .collect();
 
-                _ => Err(Error::invalid("file feature flags"))
-            }
+        if !unsupported.is_empty() {
+            return Err(Error::unsupported(format!("file features: {}", unsupported.join(", "))));
         }
-        else {
-            Err(Error::unsupported("file versions other than 2.0 are not supported"))
+
+        match (
+            self.is_single_layer_and_tiled, self.has_deep_data, self.has_multiple_layers,
+            self.file_format_version
+        ) {
+            // Single-part scan line. One normal scan line image.
+            (false, false, false, 1..=2) => Ok(()),
+
+            // Single-part tile. One normal tiled image.
+            (true, false, false, 1..=2) => Ok(()),
+
+            // Multi-part (new in 2.0).
+            // Multiple normal images (scan line and/or tiled).
+            (false, false, true, 2) => Ok(()),
+
+            // Single-part deep data (new in 2.0).
+            // One deep tile or deep scan line part
+            (false, true, false, 2) => Ok(()),
+
+            // Multi-part deep data (new in 2.0).
+            // Multiple parts (any combination of:
+            // tiles, scan lines, deep tiles and/or deep scan lines).
+            (false, true, true, 2) => Ok(()),
+
+            _ => Err(Error::invalid("file feature flags"))
         }
     }
 }
@@ -649,6 +844,65 @@ mod test {
     use super::*;
     use crate::meta::header::{ImageAttributes, LayerAttributes};
 
+    #[test]
+    fn inherit_all_layer_attributes() {
+        use crate::compression::filter::{ChannelFilters, Delta};
+        use crate::meta::header::AttributeOrder;
+
+        // lists every field without `..`, such that a new field must be considered here
+        let defaults = LayerAttributes {
+            layer_name: Some(Text::from("defaults")),
+            layer_position: Vec2(3, 4),
+            screen_window_center: Vec2(0.5, 0.25),
+            screen_window_width: 2.0,
+            white_luminance: Some(100.0),
+            adopted_neutral: Some(Vec2(0.3, 0.3)),
+            rendering_transform_name: Some(Text::from("rendering")),
+            look_modification_transform_name: Some(Text::from("look")),
+            horizontal_density: Some(72.0),
+            owner: Some(Text::from("owner")),
+            host_computer: Some(Text::from("host")),
+            comments: Some(Text::from("comments")),
+            capture_date: Some(Text::from("2020:01:01 00:00:00")),
+            utc_offset: Some(3600.0),
+            longitude: Some(1.0),
+            latitude: Some(2.0),
+            altitude: Some(3.0),
+            focus: Some(4.0),
+            exposure: Some(5.0),
+            aperture: Some(6.0),
+            iso_speed: Some(7.0),
+            environment_map: Some(EnvironmentMap::Cube),
+            film_key_code: Some(KeyCode {
+                film_manufacturer_code: 1, film_type: 2, film_roll_prefix: 3, count: 4,
+                perforation_offset: 5, perforations_per_frame: 6, perforations_per_count: 7,
+            }),
+            wrap_mode_name: Some(Text::from("clamp")),
+            frames_per_second: Some((24, 1)),
+            multi_view_names: Some(vec![ Text::from("left"), Text::from("right") ]),
+            world_to_camera: Some([ 1.0; 16 ]),
+            world_to_normalized_device: Some([ 2.0; 16 ]),
+            deep_image_state: Some((1, 2)),
+            original_data_window: Some(IntegerBounds::from_dimensions(Vec2(8, 8))),
+            preview: Some(Preview { size: Vec2(1, 1), pixel_data: vec![ 1, 2, 3, 4 ] }),
+            view_name: Some(Text::from("left")),
+            software_name: Some(Text::from("software")),
+            near_clip_plane: Some(0.1),
+            far_clip_plane: Some(100.0),
+            horizontal_field_of_view: Some(1.0),
+            vertical_field_of_view: Some(0.5),
+            other: vec![ (Text::from("custom"), AttributeValue::I32(1)) ].into_iter().collect(),
+            sample_filters: ChannelFilters { list: vec![ (Text::from("Z"), std::sync::Arc::new(Delta)) ] },
+            attribute_order: AttributeOrder::default(),
+        };
+
+        let inherited = LayerAttributes::default().inherit(&defaults);
+
+        // the layer name and position are never inherited
+        let expected = LayerAttributes { layer_name: None, layer_position: Vec2(0, 0), .. defaults };
+        assert_eq!(inherited, expected, "all other fields must be inherited");
+    }
+
     #[test]
     fn round_trip_requirements() {
         let requirements = Requirements {
@@ -656,7 +910,8 @@ mod test {
             is_single_layer_and_tiled: true,
             has_long_names: false,
             has_deep_data: true,
-            has_multiple_layers: false
+            has_multiple_layers: false,
+            unknown_flags: 0,
         };
 
         let mut data: Vec<u8> = Vec::new();
@@ -665,6 +920,48 @@ mod test {
         assert_eq!(requirements, read);
     }
 
+    #[test]
+    fn size_of_last_block() {
+        let channels = smallvec![ ChannelDescription::named("Y", SampleType::F32) ];
+        let tiles = TileDescription { tile_size: Vec2(16, 16), level_mode: LevelMode::Singular, rounding_mode: RoundingMode::Down };
+
+        let header = Header::new(Text::from("blocks"), Vec2(40, 100), channels)
+            .with_encoding(Compression::Uncompressed, BlockDescription::Tiles(tiles), LineOrder::Increasing);
+
+        let last_block = header.blocks_increasing_y_order().last().unwrap();
+        assert_eq!(last_block.location.tile_index, Vec2(2, 6));
+        assert_eq!(last_block.size, Vec2(8, 4));
+    }
+
+    #[test]
+    fn list_unsupported_features() {
+        let read = |version_and_flags: u32| Requirements::read(&mut version_and_flags.to_le_bytes().as_ref()).unwrap();
+
+        let multilayer = read(2 | 1 << 10 | 1 << 12);
+        assert!(multilayer.is_supported_by_this_crate());
+        assert!(multilayer.validate().is_ok());
+
+        let deep = read(2 | 1 << 11);
+        assert_eq!(deep.unsupported_features(), vec![ UnsupportedFeature::DeepData ]);
+        assert!(deep.validate().is_ok(), "the meta data of deep files can be read");
+
+        let future = read(3 | 1 << 9 | 1 << 13 | 1 << 20);
+        assert!(future.is_single_layer_and_tiled);
+        assert_eq!(future.unsupported_features(), vec![
+            UnsupportedFeature::FileFormatVersion(3),
+            UnsupportedFeature::UnknownFlags(1 << 13 | 1 << 20)
+        ]);
+
+        match future.validate() {
+            Err(Error::NotSupported(message)) => assert_eq!(message, "file features: file format version 3, unknown feature flags 0x00102000"),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let mut bytes = Vec::new();
+        future.write(&mut bytes).unwrap();
+        assert_eq!(bytes, (3_u32 | 1 << 9 | 1 << 13 | 1 << 20).to_le_bytes());
+    }
+
     #[test]
     fn round_trip(){
         let header = Header {
@@ -708,7 +1005,8 @@ mod test {
                 is_single_layer_and_tiled: false,
                 has_long_names: false,
                 has_deep_data: false,
-                has_multiple_layers: false
+                has_multiple_layers: false,
+                unknown_flags: 0,
             },
             headers: smallvec![ header ],
         };
@@ -817,5 +1115,195 @@ mod test {
         assert_eq!(low_requirements.has_deep_data, false);
         assert_eq!(low_requirements.has_multiple_layers, true);
     }
-}
 
+    #[test]
+    fn block_geometry_of_mip_map() {
+        let tiles = TileDescription { tile_size: Vec2(16, 16), level_mode: LevelMode::MipMap, rounding_mode: RoundingMode::Down };
+        let header = Header::new(Text::from("mip"), Vec2(70, 40), smallvec![ ChannelDescription::named("Y", SampleType::F16) ])
+            .with_encoding(Compression::RLE, BlockDescription::Tiles(tiles), LineOrder::Increasing);
+
+        assert_eq!(header.level_size(Vec2(1, 1)).unwrap(), Vec2(35, 20));
+        assert!(header.level_size(Vec2(1, 0)).is_err(), "mip maps have no rip levels");
+        assert!(header.level_size(Vec2(7, 7)).is_err(), "level does not exist");
+
+        let tile = header.get_tile_coordinates_containing(Vec2(0, 0), Vec2(20, 33)).unwrap();
+        assert_eq!(tile, TileCoordinates { tile_index: Vec2(1, 2), level_index: Vec2(0, 0) });
+        assert!(header.get_tile_coordinates_containing(Vec2(1, 1), Vec2(35, 0)).is_err());
+
+        let section = IntegerBounds::new(Vec2(10, 10), Vec2(30, 10));
+        let intersecting: Vec<_> = header.get_tiles_intersecting(Vec2(0, 0), section).unwrap()
+            .map(|tile| tile.tile_index).collect();
+
+        assert_eq!(intersecting, vec![ Vec2(0, 0), Vec2(1, 0), Vec2(2, 0), Vec2(0, 1), Vec2(1, 1), Vec2(2, 1) ]);
+
+        let outside = IntegerBounds::new(Vec2(-10, 50), Vec2(5, 5));
+        assert_eq!(header.get_tiles_intersecting(Vec2(0, 0), outside).unwrap().count(), 0);
+
+        let block_indices: Vec<_> = header.ordered_block_indices(3).collect();
+        assert_eq!(block_indices.len(), header.chunk_count);
+
+        for (tile, block) in header.blocks_increasing_y_order().zip(block_indices) {
+            assert_eq!(block.layer, 3);
+            assert_eq!(block.pixel_size, tile.size);
+            assert_eq!(header.get_tile_coordinates_containing(block.level, block.pixel_position).unwrap(), tile.location);
+
+            let level = IntegerBounds::from_dimensions(header.level_size(block.level).unwrap());
+            assert!(header.get_tiles_intersecting(block.level, level).unwrap().any(|other| other == tile.location));
+        }
+    }
+
+    #[test]
+    fn header_builder_validation() {
+        let builder = Header::builder()
+            .layer_size(Vec2(100, 50))
+            .channel(ChannelDescription::named("R", SampleType::F16))
+            .channel(ChannelDescription::named("A", SampleType::F32))
+            .compression(Compression::ZIP16)
+            .line_order(LineOrder::Increasing);
+
+        let header = builder.clone().build().unwrap();
+        assert_eq!(header.channels.list[0].name, Text::from("A"), "channels are sorted");
+        assert_eq!(header.chunk_count, compute_chunk_count(Compression::ZIP16, Vec2(100, 50), header.blocks));
+        assert_eq!(header.shared_attributes.display_window, IntegerBounds::from_dimensions(Vec2(100, 50)));
+
+        assert!(Header::builder().channel(ChannelDescription::named("R", SampleType::F16)).build().is_err(), "missing size");
+        assert!(Header::builder().layer_size(Vec2(4, 4)).build().is_err(), "missing channels");
+
+        let duplicate = builder.clone().channel(ChannelDescription::named("R", SampleType::U32)).validate();
+        assert!(matches!(duplicate, Err(Error::Invalid(message)) if message.contains("`R`")));
+
+        let empty_tiles = TileDescription { tile_size: Vec2(0, 8), level_mode: LevelMode::Singular, rounding_mode: RoundingMode::Down };
+        assert!(builder.clone().blocks(BlockDescription::Tiles(empty_tiles)).validate().is_err());
+
+        let unspecified_scan_lines = builder.clone().blocks(BlockDescription::ScanLines).line_order(LineOrder::Unspecified);
+        assert!(unspecified_scan_lines.validate().is_err());
+
+        let default_scan_lines = Header::builder().layer_size(Vec2(4, 4))
+            .channel(ChannelDescription::named("Y", SampleType::F32))
+            .blocks(BlockDescription::ScanLines).build().unwrap();
+
+        assert_eq!(default_scan_lines.line_order, LineOrder::Increasing);
+
+        let reserved = builder.attribute("dataWindow", AttributeValue::I32(3));
+        assert!(reserved.validate().is_err(), "reserved attribute name");
+    }
+
+    #[test]
+    fn auto_tile_size() {
+        let header = |compression: Compression, size: Vec2<usize>, channels: usize| Header::builder()
+            .layer_size(size).compression(compression)
+            .channels((0 .. channels).map(|index| ChannelDescription::named(format!("C{}", index).as_str(), SampleType::F32)))
+            .build().unwrap();
+
+        assert_eq!(header(Compression::DWAA(None), Vec2(1920, 1080), 4).auto_tile_size(), Vec2(64, 64));
+        assert_eq!(header(Compression::ZIP16, Vec2(1920, 1080), 4).auto_tile_size(), Vec2(128, 128));
+        assert_eq!(header(Compression::RLE, Vec2(1920, 1080), 4).auto_tile_size(), Vec2(64, 64));
+        assert_eq!(header(Compression::ZIP16, Vec2(100, 20), 4).auto_tile_size(), Vec2(100, 20), "not larger than the layer");
+        assert_eq!(header(Compression::PIZ, Vec2(1920, 1080), 12).auto_tile_size(), Vec2(64, 64), "many channels");
+        assert_eq!(header(Compression::PIZ, Vec2(1920, 1080), 4000).auto_tile_size(), Vec2(16, 16), "minimum size");
+    }
+
+    #[test]
+    fn estimated_memory() {
+        let header = Header::builder()
+            .layer_size((64, 32)).compression(Compression::ZIP16)
+            .blocks(BlockDescription::Tiles(TileDescription {
+                tile_size: Vec2(16, 16), level_mode: LevelMode::MipMap, rounding_mode: RoundingMode::Down
+            }))
+            .channels(vec![
+                ChannelDescription::named("Y", SampleType::F16),
+                ChannelDescription::named("Z", SampleType::F32),
+            ])
+            .build().unwrap();
+
+        let meta = MetaData { requirements: MetaData::validate(std::slice::from_ref(&header), true).unwrap(), headers: smallvec![ header ] };
+
+        let largest_level = 64 * 32 * (2 + 4);
+        let all_levels = (64*32 + 32*16 + 16*8 + 8*4 + 4*2 + 2*1 + 1*1) * (2 + 4);
+        assert_eq!(meta.estimated_decoded_size(), all_levels);
+
+        let offset_tables = meta.headers[0].chunk_count * 8;
+        let block = 16 * 16 * (2 + 4);
+
+        let sequential = MemoryEstimateOptions::sequential();
+        assert_eq!(meta.estimated_peak_memory(sequential), largest_level + offset_tables + 2 * block);
+        assert_eq!(meta.estimated_peak_memory(sequential.all_resolution_levels()), all_levels + offset_tables + 2 * block);
+        assert_eq!(meta.estimated_peak_memory(sequential.prefetch_chunks(3)), largest_level + offset_tables + 5 * block);
+        assert!(meta.estimated_peak_memory(MemoryEstimateOptions::parallel()) > meta.estimated_peak_memory(sequential));
+    }
+
+    #[test]
+    fn channel_queries() {
+        let header = |channels: &[&str]| Header::builder()
+            .layer_size((8, 8))
+            .channels(channels.iter().map(|&name| ChannelDescription::named(name, SampleType::F16)))
+            .build().unwrap();
+
+        let rgb = header(&["R", "G", "B", "Z"]);
+        assert!(rgb.has_channels(["R", "G", "B"]));
+        assert!(rgb.has_channels(Vec::<String>::new()));
+        assert!(!rgb.has_channels(["R", "G", "B", "A"]));
+        assert!(!rgb.has_channel("z"), "exact comparison");
+        assert_eq!(rgb.find_channel_case_insensitive("z").map(|channel| &channel.name), Some(&Text::from("Z")));
+        assert!(rgb.find_channel_case_insensitive("Y").is_none());
+
+        let meta = MetaData {
+            headers: smallvec![ rgb, header(&["a", "Y"]), header(&["A", "B", "G", "R"]) ],
+            requirements: Requirements { file_format_version: 2, is_single_layer_and_tiled: false, has_long_names: false, has_deep_data: false, has_multiple_layers: true, unknown_flags: 0 },
+        };
+
+        let alpha_layers: Vec<usize> = meta.layers_with_alpha().map(|(index, _)| index).collect();
+        assert_eq!(alpha_layers, vec![1, 2]);
+    }
+
+    #[test]
+    fn block_positions_do_not_overflow() {
+        assert_eq!(calculate_block_position_and_size(100, 16, 6).unwrap(), (96, 4));
+        assert!(calculate_block_position_and_size(100, 16, usize::MAX / 8).is_err());
+        assert!(calculate_block_size(100, usize::MAX, 10).is_ok());
+    }
+
+    #[test]
+    fn supports_image_on_this_machine() {
+        let channels = (0 .. 16).map(|index| ChannelDescription::named(format!("{}", index).as_str(), SampleType::F32));
+        let header = Header::builder().layer_size((64, 64)).channels(channels).build().unwrap();
+        let requirements = Requirements { file_format_version: 2, is_single_layer_and_tiled: false, has_long_names: false, has_deep_data: false, has_multiple_layers: false, unknown_flags: 0 };
+        let meta = |header: Header| MetaData { headers: smallvec![ header ], requirements };
+        assert!(supports_image(&meta(header.clone())));
+
+        // each dimension of a tile may be as large as 2^30, which results in too many bytes per tile
+        let tiles = TileDescription { tile_size: Vec2(1 << 29, 1 << 29), level_mode: LevelMode::Singular, rounding_mode: RoundingMode::Down };
+        let huge_tiles = header.clone().with_encoding(Compression::Uncompressed, BlockDescription::Tiles(tiles), LineOrder::Increasing);
+        assert!(huge_tiles.validate_block_byte_size().is_err());
+        assert!(!supports_image(&meta(huge_tiles.clone())));
+        assert!(huge_tiles.validate(false, &mut false, false).is_err());
+
+        let huge_layer = Header::new(Text::from("huge"), (usize::MAX / 2, 4), smallvec![ ChannelDescription::named("Y", SampleType::F16) ]);
+        assert!(!supports_image(&meta(huge_layer.clone())));
+        assert_eq!(meta(huge_layer).estimated_decoded_size(), usize::MAX, "estimates saturate");
+    }
+
+    #[test]
+    fn reserved_attribute_names_are_rejected() {
+        let builder = || Header::builder().layer_size((4, 4))
+            .channel(ChannelDescription::named("Y", SampleType::F16));
+
+        let wrong_type = builder().attribute("owner", AttributeValue::I32(3)).build().unwrap_err();
+        assert!(wrong_type.to_string().contains("type `int`"), "{}", wrong_type);
+        assert!(wrong_type.to_string().contains("type `string`"), "{}", wrong_type);
+
+        let correct_type = builder().attribute("owner", AttributeValue::Text(Text::from("me"))).build().unwrap_err();
+        assert!(correct_type.to_string().contains("reserved"), "{}", correct_type);
+
+        let namespaced = Text::namespaced("com.studio", "owner").unwrap();
+        assert_eq!(namespaced, Text::from("com.studio.owner"));
+        assert!(builder().attribute(namespaced, AttributeValue::I32(3)).build().is_ok());
+
+        assert!(Text::namespaced("com..studio", "owner").is_err());
+        assert!(Text::namespaced("", "owner").is_err());
+        assert!(Text::namespaced("com.studio", "render.time").is_err());
+
+        assert_eq!(header::standard_names::type_name_of(b"dataWindow"), Some(attribute::type_names::I32BOX2));
+        assert!(!header::standard_names::is_reserved(b"com.studio.owner"));
+    }
+}
@@ -0,0 +1,433 @@
+//! Generate arbitrary images and check that they are unchanged after writing and reading them again.
+//! Also detects changes in decoding, by comparing decoded images to a snapshot of hashes written by this crate.
+//! This is the harness that this crate uses to test itself,
+//! exposed for property tests and fuzzers of other crates.
+//! Requires the `testing` feature.
+//!
+//! ```no_run
+//! let mut generator = exr::testing::ImageGenerator::from_seed(42);
+//!
+//! for _ in 0 .. 100 {
+//!     let image = generator.arbitrary_image();
+//!     exr::testing::assert_roundtrip(&image);
+//! }
+//! ```
+
+use crate::prelude::*;
+use crate::image::validate_results::ValidateResult;
+use crate::meta::attribute::LineOrder;
+use crate::error::Result;
+use std::io::Cursor;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+
+/// All compression methods which this crate can write.
+pub const WRITABLE_COMPRESSIONS: [Compression; 8] = [
+    Compression::Uncompressed, Compression::RLE, Compression::ZIP1, Compression::ZIP16,
+    Compression::PIZ, Compression::PXR24, Compression::B44, Compression::B44A,
+];
+
+/// Channel names that generated layers choose from.
+const CHANNEL_NAMES: [&str; 8] = [ "R", "G", "B", "A", "Y", "Z", "N.x", "mask" ];
+
+
+/// Generates pseudo random images, with random sizes, channels, compression methods, and tiles.
+/// The same seed always produces the same images.
+/// The sample values form smooth gradients, such that lossy compression methods
+/// are still accurate enough to pass the `assert_roundtrip` check.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ImageGenerator {
+    state: u64,
+
+    /// The maximum width and height of a generated layer. Defaults to 64.
+    pub max_layer_size: usize,
+
+    /// The maximum number of layers in a generated image. Defaults to 3.
+    pub max_layer_count: usize,
+}
+
+impl ImageGenerator {
+
+    /// Create a generator that always produces the same sequence of images for the same seed.
+    pub fn from_seed(seed: u64) -> Self {
+        // scramble the seed with a splitmix64 step, such that similar seeds produce different images
+        let mut state = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        state = (state ^ (state >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        state = (state ^ (state >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        state ^= state >> 31;
+
+        // a xorshift state of zero would only produce zeroes
+        Self { state: state.max(1), max_layer_size: 64, max_layer_count: 3 }
+    }
+
+    /// Create a generator from arbitrary bytes, for example the input of a fuzzer.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut hash = FnvHash::new();
+        hash.write(bytes);
+        Self::from_seed(hash.0)
+    }
+
+    /// Set the maximum width and height of generated layers.
+    pub fn with_max_layer_size(self, max_layer_size: usize) -> Self {
+        Self { max_layer_size: max_layer_size.max(1), ..self }
+    }
+
+    /// Set the maximum number of layers in generated images.
+    pub fn with_max_layer_count(self, max_layer_count: usize) -> Self {
+        Self { max_layer_count: max_layer_count.max(1), ..self }
+    }
+
+    /// Generate the next pseudo random number (xorshift64*).
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Generate a pseudo random number within the range, including the maximum.
+    pub fn next_usize(&mut self, min: usize, max: usize) -> usize {
+        debug_assert!(min <= max, "invalid range");
+        min + (self.next_u64() % (max - min + 1) as u64) as usize
+    }
+
+    /// Generate a pseudo random number between zero and one.
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1_u64 << 24) as f32
+    }
+
+    /// Generate an image with up to `max_layer_count` layers.
+    pub fn arbitrary_image(&mut self) -> FlatImage {
+        let layer_count = self.next_usize(1, self.max_layer_count);
+
+        let layers: Layers<_> = (0 .. layer_count)
+            .map(|index| {
+                let name = if layer_count == 1 && self.next_usize(0, 1) == 0 { None }
+                    else { Some(Text::from(format!("layer{}", index).as_str())) };
+
+                self.arbitrary_layer(name)
+            })
+            .collect();
+
+        let display_size = layers.iter()
+            .map(|layer| layer.size).fold(Vec2(1, 1), |a, b| Vec2(a.0.max(b.0), a.1.max(b.1)));
+
+        Image::from_layers(ImageAttributes::new(IntegerBounds::from_dimensions(display_size)), layers)
+    }
+
+    /// Generate a single layer with the specified name.
+    pub fn arbitrary_layer(&mut self, name: Option<Text>) -> Layer<AnyChannels<FlatSamples>> {
+        let size = Vec2(self.next_usize(1, self.max_layer_size), self.next_usize(1, self.max_layer_size));
+        let encoding = self.arbitrary_encoding();
+
+        let channel_count = self.next_usize(1, 5);
+        let mut names: Vec<&str> = CHANNEL_NAMES.to_vec();
+
+        let channels = (0 .. channel_count)
+            .map(|_| {
+                let name = names.remove(self.next_usize(0, names.len() - 1));
+                AnyChannel::new(name, self.arbitrary_samples(size))
+            })
+            .collect();
+
+        let mut attributes = LayerAttributes::default();
+        attributes.layer_name = name;
+
+        let position = Vec2(self.next_usize(0, 20) as i32 - 10, self.next_usize(0, 20) as i32 - 10);
+        attributes.layer_position = position;
+
+        Layer::new(size, attributes, encoding, AnyChannels::sort(channels))
+    }
+
+    /// Generate an encoding with any compression method that can be written,
+    /// using either scan lines or tiles of any size.
+    pub fn arbitrary_encoding(&mut self) -> Encoding {
+        let compression = WRITABLE_COMPRESSIONS[self.next_usize(0, WRITABLE_COMPRESSIONS.len() - 1)];
+
+        let blocks = if self.next_usize(0, 1) == 0 { Blocks::ScanLines } else {
+            Blocks::Tiles(Vec2(self.next_usize(1, 32), self.next_usize(1, 32)))
+        };
+
+        Encoding { compression, blocks, line_order: LineOrder::Increasing }
+    }
+
+    /// Generate samples of any type, forming a smooth gradient.
+    pub fn arbitrary_samples(&mut self, size: Vec2<usize>) -> FlatSamples {
+        let (offset, slope_x, slope_y) = (self.next_f32(), self.next_f32(), self.next_f32());
+
+        let values = (0 .. size.area()).map(move |index| {
+            let (x, y) = (index % size.width(), index / size.width());
+            offset + slope_x * x as f32 / size.width() as f32 + slope_y * y as f32 / size.height() as f32
+        });
+
+        match self.next_usize(0, 2) {
+            0 => FlatSamples::F16(values.map(f16::from_f32).collect()),
+            1 => FlatSamples::F32(values.collect()),
+            _ => FlatSamples::U32(values.map(|value| (value * 1000.0) as u32).collect()),
+        }
+    }
+}
+
+/// Write the image into memory and read it again, including all layers and attributes.
+pub fn roundtrip(image: &FlatImage) -> Result<FlatImage> {
+    let mut bytes = Vec::new();
+    image.write().non_parallel().to_buffered(Cursor::new(&mut bytes))?;
+
+    crate::image::read::read()
+        .no_deep_data().largest_resolution_level().all_channels().all_layers().all_attributes()
+        .non_parallel().from_buffered(Cursor::new(bytes))
+}
+
+/// Check that the image does not change when writing it and reading it again.
+/// Lossy compression methods are compared approximately.
+/// Returns a description of the first difference, if any.
+pub fn check_roundtrip(image: &FlatImage) -> std::result::Result<(), String> {
+    let result = roundtrip(image).map_err(|error| format!("roundtrip failed: {}", error))?;
+    image.validate_result(&result, Default::default(), String::new())
+}
+
+/// Check that the image does not change when writing it and reading it again.
+/// Lossy compression methods are compared approximately.
+/// Panics with a description of the first difference, if any.
+pub fn assert_roundtrip(image: &FlatImage) {
+    if let Err(message) = check_roundtrip(image) {
+        panic!("image changed after roundtrip: {}", message);
+    }
+}
+
+
+/// Compute a hash of all decoded samples of an image, including all layers and resolution levels,
+/// and the names, sizes, and sample types of the layers and channels.
+/// Attributes are not included. The hash is stable across platforms and versions of this crate.
+pub fn hash_image(image: &AnyImage) -> u64 {
+    let mut hash = FnvHash::new();
+
+    for layer in &image.layer_data {
+        hash.write(layer.attributes.layer_name.as_ref().map(Text::as_slice).unwrap_or(&[]));
+        hash.write_usize(layer.size.width());
+        hash.write_usize(layer.size.height());
+
+        for channel in &layer.channel_data.list {
+            hash.write(channel.name.as_slice());
+
+            for level in channel.sample_data.levels_as_slice() {
+                hash.write_usize(level.len());
+
+                match level {
+                    FlatSamples::F16(samples) => {
+                        hash.write(&[0]);
+                        for sample in samples { hash.write(&sample.to_bits().to_le_bytes()); }
+                    },
+
+                    FlatSamples::F32(samples) => {
+                        hash.write(&[1]);
+                        for sample in samples { hash.write(&sample.to_bits().to_le_bytes()); }
+                    },
+
+                    FlatSamples::U32(samples) => {
+                        hash.write(&[2]);
+                        for sample in samples { hash.write(&sample.to_le_bytes()); }
+                    },
+                }
+            }
+        }
+    }
+
+    hash.0
+}
+
+/// Read all layers, channels, and resolution levels of the file, and compute its `hash_image`.
+pub fn hash_file(path: impl AsRef<Path>) -> Result<u64> {
+    let image = crate::image::read::read()
+        .no_deep_data().all_resolution_levels().all_channels().all_layers().all_attributes()
+        .from_file(path)?;
+
+    Ok(hash_image(&image))
+}
+
+/// Hash all exr files in the directory and its sub directories.
+/// The paths are relative to the directory, use `/` as separator, and are sorted.
+/// Contains an error for each file that cannot be decoded.
+pub fn hash_directory(directory: impl AsRef<Path>) -> std::io::Result<BTreeMap<String, Result<u64>>> {
+    let directory = directory.as_ref();
+
+    Ok(exr_files_in(directory)?.into_iter()
+        .map(|path| {
+            let relative = path.strip_prefix(directory).unwrap_or(&path).components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>().join("/");
+
+            let hash = hash_file(&path);
+            (relative, hash)
+        })
+        .collect())
+}
+
+/// Parse snapshot hashes, as written by `write_snapshot_hashes`.
+/// Each line contains a hexadecimal hash and a relative path, separated by a space.
+/// Empty lines and lines starting with `#` are ignored.
+pub fn parse_snapshot_hashes(text: &str) -> std::result::Result<BTreeMap<String, u64>, String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let mut parts = line.splitn(2, ' ');
+            let hash = parts.next().and_then(|hash| u64::from_str_radix(hash, 16).ok());
+            let path = parts.next().map(str::trim);
+
+            match (hash, path) {
+                (Some(hash), Some(path)) if !path.is_empty() => Ok((path.to_string(), hash)),
+                _ => Err(format!("invalid snapshot hash line `{}`", line)),
+            }
+        })
+        .collect()
+}
+
+/// Hash all exr files in the directory and write the hashes to the snapshot file.
+/// Files that cannot be decoded are listed as comments.
+/// Returns the number of files that were hashed.
+/// As the hashes are computed by this crate, the file is a snapshot that detects changes in decoding,
+/// not a proof that the pixels match the reference implementation.
+pub fn write_snapshot_hashes(directory: impl AsRef<Path>, snapshot_file: impl AsRef<Path>) -> std::io::Result<usize> {
+    let mut text = String::from("# decoded sample hashes, see `exr::testing::hash_image`\n");
+    let mut count = 0;
+
+    for (path, hash) in hash_directory(directory)? {
+        match hash {
+            Ok(hash) => { text += &format!("{:016x} {}\n", hash, path); count += 1; },
+            Err(error) => text += &format!("# {}: {}\n", path, error),
+        }
+    }
+
+    std::fs::write(snapshot_file, text)?;
+    Ok(count)
+}
+
+/// Decode all exr files in the directory and compare them to the snapshot hashes.
+pub fn check_snapshot(directory: impl AsRef<Path>, snapshot: &BTreeMap<String, u64>) -> std::io::Result<SnapshotReport> {
+    let mut hashes = hash_directory(directory)?;
+    let mut report = SnapshotReport::default();
+
+    for (path, &expected) in snapshot {
+        match hashes.remove(path) {
+            None => report.missing.push(path.clone()),
+            Some(Ok(hash)) if hash == expected => report.matching.push(path.clone()),
+            Some(Ok(hash)) => report.mismatching.push((path.clone(), format!("expected hash {:016x}, found {:016x}", expected, hash))),
+            Some(Err(error)) => report.mismatching.push((path.clone(), error.to_string())),
+        }
+    }
+
+    for (path, hash) in hashes {
+        match hash {
+            Ok(_) => report.unlisted.push(path),
+            Err(error) => report.unreadable.push((path, error.to_string())),
+        }
+    }
+
+    Ok(report)
+}
+
+/// The result of `check_snapshot`. All paths are relative to the checked directory.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct SnapshotReport {
+
+    /// Files that were decoded exactly as expected.
+    pub matching: Vec<String>,
+
+    /// Files that were decoded differently than expected, or could not be decoded anymore, with a description.
+    pub mismatching: Vec<(String, String)>,
+
+    /// Files that are listed in the snapshot, but were not found in the directory.
+    pub missing: Vec<String>,
+
+    /// Files that were decoded, but are not listed in the snapshot.
+    pub unlisted: Vec<String>,
+
+    /// Files not listed in the snapshot that could not be decoded, for example because of unsupported compression methods.
+    pub unreadable: Vec<(String, String)>,
+}
+
+impl SnapshotReport {
+
+    /// Whether all files in the snapshot were found and decoded as before.
+    pub fn matches_snapshot(&self) -> bool {
+        self.mismatching.is_empty() && self.missing.is_empty()
+    }
+}
+
+fn exr_files_in(directory: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+
+        if path.is_dir() { files.extend(exr_files_in(&path)?); }
+        else if path.extension().map_or(false, |extension| extension.eq_ignore_ascii_case("exr")) {
+            files.push(path);
+        }
+    }
+
+    Ok(files)
+}
+
+/// The 64-bit FNV-1a hash, which is simple and stable across platforms.
+struct FnvHash(u64);
+
+impl FnvHash {
+    fn new() -> Self { FnvHash(0xCBF2_9CE4_8422_2325) }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0100_0000_01B3);
+        }
+    }
+
+    fn write_usize(&mut self, value: usize) {
+        self.write(&(value as u64).to_le_bytes());
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn same_seed_generates_same_images() {
+        let mut first = ImageGenerator::from_seed(7);
+        let mut second = ImageGenerator::from_seed(7);
+
+        for _ in 0 .. 4 {
+            first.arbitrary_image().assert_equals_result(&second.arbitrary_image());
+        }
+    }
+
+    #[test]
+    fn any_seed_generates_random_numbers() {
+        for &seed in &[ 0, 1, 0x9E37_79B9_7F4A_7C15, u64::MAX ] {
+            let mut generator = ImageGenerator::from_seed(seed);
+            let numbers: Vec<u64> = (0 .. 4).map(|_| generator.next_u64()).collect();
+            assert!(numbers.iter().all(|&number| number != 0), "seed {} produced {:?}", seed, numbers);
+        }
+    }
+
+    #[test]
+    fn parse_snapshot_hash_lines() {
+        let snapshot = parse_snapshot_hashes("# comment\n\n00000000000000ff dir/file name.exr\n").unwrap();
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(snapshot["dir/file name.exr"], 255);
+
+        assert!(parse_snapshot_hashes("not-a-hash file.exr").is_err());
+        assert!(parse_snapshot_hashes("00ff").is_err());
+    }
+
+    #[test]
+    fn arbitrary_images_roundtrip() {
+        let mut generator = ImageGenerator::from_bytes(b"exr");
+
+        for _ in 0 .. 64 {
+            assert_roundtrip(&generator.arbitrary_image());
+        }
+    }
+}
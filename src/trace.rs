@@ -0,0 +1,88 @@
+//! Optional instrumentation using the `tracing` crate, enabled by the `tracing` feature.
+//!
+//! Reading and writing an image, reading each chunk, and compressing or decompressing each block
+//! are wrapped in spans, such that any `tracing` subscriber can measure where the time is spent.
+//! Parallel compression and decompression also report the number of threads,
+//! and the worker threads enter the span of the image that the block belongs to.
+//! Without the feature, these macros expand to nothing.
+
+/// Enter a span with the specified level, name, and fields, until the end of the current scope.
+macro_rules! trace_span {
+    ( $level: ident, $name: expr $(, $($fields: tt)+ )? ) => {
+        #[cfg(feature = "tracing")]
+        let _entered_span = tracing::span!(tracing::Level::$level, $name $(, $($fields)+ )?).entered();
+    };
+}
+
+/// Emit an event with the specified level, fields, and message.
+macro_rules! trace_event {
+    ( $level: ident, $($arguments: tt)+ ) => {
+        #[cfg(feature = "tracing")]
+        tracing::event!(tracing::Level::$level, $($arguments)+);
+    };
+}
+
+/// Store the current span in a variable with the specified name,
+/// such that it can be moved to a worker thread and entered with `enter_captured_span`.
+macro_rules! capture_current_span {
+    ( $variable: ident ) => {
+        #[cfg(feature = "tracing")]
+        let $variable = tracing::Span::current();
+    };
+}
+
+/// Enter a span that was stored with `capture_current_span`, until the end of the current scope.
+macro_rules! enter_captured_span {
+    ( $variable: ident ) => {
+        #[cfg(feature = "tracing")]
+        let _entered_parent_span = $variable.entered();
+    };
+}
+
+
+#[cfg(all(test, feature = "tracing"))]
+mod test {
+    use std::io::Cursor;
+    use std::sync::{Arc, Mutex};
+    use tracing::{span, Event, Metadata, Subscriber};
+    use crate::prelude::*;
+
+    /// Records the name of each span that is created.
+    struct SpanNames(Arc<Mutex<Vec<&'static str>>>);
+
+    impl Subscriber for SpanNames {
+        fn enabled(&self, _: &Metadata<'_>) -> bool { true }
+
+        fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
+            let mut names = self.0.lock().unwrap();
+            names.push(span.metadata().name());
+            span::Id::from_u64(names.len() as u64)
+        }
+
+        fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+        fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+        fn event(&self, _: &Event<'_>) {}
+        fn enter(&self, _: &span::Id) {}
+        fn exit(&self, _: &span::Id) {}
+    }
+
+    #[test]
+    fn spans_for_images_and_blocks() {
+        let names = Arc::new(Mutex::new(Vec::new()));
+
+        tracing::subscriber::with_default(SpanNames(names.clone()), || {
+            let image = Image::from_channels((8, 8), SpecificChannels::rgb(|_: Vec2<usize>| (0.5_f32, 0.5_f32, 0.5_f32)));
+
+            let mut bytes = Vec::new();
+            image.write().non_parallel().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+            read().no_deep_data().largest_resolution_level().all_channels().first_valid_layer().all_attributes()
+                .non_parallel().from_buffered(Cursor::new(&bytes)).unwrap();
+        });
+
+        let names = names.lock().unwrap();
+        for expected in [ "write image", "compress block", "read meta data", "read image", "read chunk", "decompress block" ] {
+            assert!(names.contains(&expected), "missing span `{}` in {:?}", expected, names);
+        }
+    }
+}
@@ -0,0 +1,39 @@
+//! Checks that the decoded pixels of the `openexr-images` corpus did not change,
+//! by comparing them to a snapshot of hashes that was written by this crate.
+//! Requires the `testing` feature: `cargo test --features testing --test decoded_snapshot`.
+//!
+//! The snapshot detects changes in decoding, but does not prove that the pixels match the reference implementation.
+//! After an intended change in decoding, update the snapshot with `exr::testing::write_snapshot_hashes`.
+//!
+//! To check a full copy of the corpus (https://github.com/AcademySoftwareFoundation/openexr-images),
+//! set `EXR_SNAPSHOT_IMAGES` to its directory and `EXR_SNAPSHOT_HASHES` to a snapshot file of that directory,
+//! then run `cargo test --features testing --test decoded_snapshot -- --ignored`.
+
+#![cfg(feature = "testing")]
+
+extern crate exr;
+
+use exr::testing::*;
+
+fn assert_matches_snapshot(images: &str, snapshot: &str) {
+    let snapshot = std::fs::read_to_string(snapshot).expect("cannot read snapshot hashes");
+    let snapshot = parse_snapshot_hashes(&snapshot).unwrap();
+    assert!(!snapshot.is_empty(), "no snapshot hashes");
+
+    let report = check_snapshot(images, &snapshot).expect("cannot read images");
+    assert!(report.matches_snapshot(), "mismatching: {:#?}, missing: {:#?}", report.mismatching, report.missing);
+    assert_eq!(report.matching.len(), snapshot.len());
+}
+
+#[test]
+fn bundled_images_match_snapshot() {
+    assert_matches_snapshot("tests/images/valid/openexr", "tests/images/decoded_hashes_snapshot.txt");
+}
+
+#[test]
+#[ignore]
+fn external_images_match_snapshot() {
+    let images = std::env::var("EXR_SNAPSHOT_IMAGES").expect("set EXR_SNAPSHOT_IMAGES to the openexr-images directory");
+    let snapshot = std::env::var("EXR_SNAPSHOT_HASHES").expect("set EXR_SNAPSHOT_HASHES to the snapshot hash file");
+    assert_matches_snapshot(&images, &snapshot);
+}
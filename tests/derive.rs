@@ -0,0 +1,122 @@
+#![cfg(feature = "derive")]
+
+extern crate exr;
+
+use std::io::Cursor;
+use exr::prelude::*;
+use exr::prelude::pixel_vec::PixelVec;
+use exr::image::write::channels::WritableChannels;
+
+#[derive(ExrPixel, Clone, Copy, Default, Debug, PartialEq)]
+struct AovPixel {
+    r: f32,
+    g: f32,
+    b: f32,
+    a: Option<f16>,
+
+    #[exr(channel = "Z")]
+    depth: f32,
+
+    #[exr(channel = "object.id")]
+    object_id: u32,
+}
+
+#[derive(ExrPixel, Clone, Copy, Default, Debug, PartialEq)]
+struct AovPixelWithoutAlpha {
+    r: f32, g: f32, b: f32,
+
+    #[exr(channel = "Z")]
+    depth: f32,
+
+    #[exr(channel = "object.id")]
+    object_id: u32,
+}
+
+#[derive(ExrPixel, Clone, Copy, Default, Debug, PartialEq)]
+struct PixelWithDefaults {
+    r: f32,
+
+    #[exr(default = 1)]
+    a: f16,
+
+    #[exr(channel = "Z", default = "-1.5")]
+    depth: f32,
+}
+
+fn pixel(position: Vec2<usize>) -> AovPixel {
+    AovPixel {
+        r: position.x() as f32, g: position.y() as f32, b: 0.25,
+        a: Some(f16::from_f32(0.5)),
+        depth: (position.x() * position.y()) as f32,
+        object_id: position.x() as u32 + 7,
+    }
+}
+
+fn write_to_buffer(channels: impl for<'c> WritableChannels<'c>) -> Vec<u8> {
+    let layer = Layer::new(Vec2(9, 5), LayerAttributes::named("aov"), Encoding::FAST_LOSSLESS, channels);
+
+    let mut buffer = Vec::new();
+    Image::from_layer(layer).write().to_buffered(Cursor::new(&mut buffer)).unwrap();
+    buffer
+}
+
+fn read_from_buffer(buffer: &[u8]) -> PixelVec<AovPixel> {
+    let image = read().no_deep_data().largest_resolution_level()
+        .struct_channels::<AovPixel>()
+        .collect_pixels(PixelVec::<AovPixel>::constructor, PixelVec::set_pixel)
+        .first_valid_layer().all_attributes()
+        .from_buffered(Cursor::new(buffer)).unwrap();
+
+    image.layer_data.channel_data.pixels
+}
+
+#[test]
+fn roundtrip_derived_pixel() {
+    let buffer = write_to_buffer(AovPixel::specific_channels(pixel));
+
+    let channel_names: Vec<Text> = exr::meta::MetaData::read_from_buffered(Cursor::new(&buffer), false).unwrap()
+        .headers[0].channels.list.iter().map(|channel| channel.name.clone()).collect();
+
+    assert_eq!(channel_names, vec![ Text::from("A"), Text::from("B"), Text::from("G"), Text::from("R"), Text::from("Z"), Text::from("object.id") ]);
+
+    let pixels = read_from_buffer(&buffer);
+    assert_eq!(pixels.resolution, Vec2(9, 5));
+    assert_eq!(pixels.get_pixel(Vec2(3, 4)), &pixel(Vec2(3, 4)));
+    assert_eq!(pixels.get_pixel(Vec2(8, 1)), &pixel(Vec2(8, 1)));
+}
+
+#[test]
+fn missing_optional_field_is_none() {
+    let buffer = write_to_buffer(AovPixelWithoutAlpha::specific_channels(|position: Vec2<usize>| AovPixelWithoutAlpha {
+        r: 1.0, g: 2.0, b: 3.0, depth: 4.0, object_id: position.y() as u32
+    }));
+
+    let pixels = read_from_buffer(&buffer);
+    assert_eq!(pixels.get_pixel(Vec2(2, 3)), &AovPixel { r: 1.0, g: 2.0, b: 3.0, a: None, depth: 4.0, object_id: 3 });
+}
+
+#[test]
+fn missing_field_with_default_value() {
+    let buffer = write_to_buffer(AovPixelWithoutAlpha::specific_channels(|position: Vec2<usize>| AovPixelWithoutAlpha {
+        r: 1.0, g: 2.0, b: 3.0, depth: 4.0, object_id: position.y() as u32
+    }));
+
+    let image = read().no_deep_data().largest_resolution_level()
+        .struct_channels::<PixelWithDefaults>()
+        .collect_pixels(PixelVec::<PixelWithDefaults>::constructor, PixelVec::set_pixel)
+        .first_valid_layer().all_attributes()
+        .from_buffered(Cursor::new(&buffer)).unwrap();
+
+    let pixel = image.layer_data.channel_data.pixels.get_pixel(Vec2(2, 3));
+    assert_eq!(pixel, &PixelWithDefaults { r: 1.0, a: f16::ONE, depth: 4.0 }, "existing channels ignore the default");
+
+    let without_depth = write_to_buffer(SpecificChannels::build().with_channel("R").with_pixel_fn(|_| (0.5_f32,)));
+    let image = read().no_deep_data().largest_resolution_level()
+        .struct_channels::<PixelWithDefaults>()
+        .collect_pixels(PixelVec::<PixelWithDefaults>::constructor, PixelVec::set_pixel)
+        .first_valid_layer().all_attributes()
+        .from_buffered(Cursor::new(&without_depth)).unwrap();
+
+    let pixel = image.layer_data.channel_data.pixels.get_pixel(Vec2(0, 0));
+    assert_eq!(pixel, &PixelWithDefaults { r: 0.5, a: f16::ONE, depth: -1.5 });
+}
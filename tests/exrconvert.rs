@@ -0,0 +1,39 @@
+#![cfg(feature = "cli")]
+
+//! Run the `exrconvert` binary on the test images.
+
+extern crate exr;
+
+use std::process::Command;
+use exr::prelude::*;
+use exr::meta::BlockDescription;
+
+fn exrconvert(arguments: &[&str]) -> bool {
+    Command::new(env!("CARGO_BIN_EXE_exrconvert")).args(arguments).status().unwrap().success()
+}
+
+#[test]
+fn convert_compression_samples_and_blocks() {
+    let path = std::env::temp_dir().join("exrs_exrconvert_test.exr");
+
+    assert!(exrconvert(&[
+        "tests/images/valid/custom/crowskull/crow_pxr24.exr", path.to_str().unwrap(),
+        "--compression", "piz", "--f16", "--tiles", "64x32",
+    ]));
+
+    let meta = MetaData::read_from_file(&path, true).unwrap();
+    let header = &meta.headers[0];
+    assert_eq!(header.compression, Compression::PIZ);
+    assert!(matches!(header.blocks, BlockDescription::Tiles(tiles) if tiles.tile_size == Vec2(64, 32)));
+    assert!(header.channels.list.iter().all(|channel| channel.sample_type == SampleType::F16));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn reject_invalid_arguments() {
+    assert!(!exrconvert(&["only_one_path.exr"]));
+    assert!(!exrconvert(&["a.exr", "b.exr", "--tiles", "0x4"]));
+    assert!(!exrconvert(&["a.exr", "b.exr", "--compression", "jpeg"]));
+    assert!(!exrconvert(&["tests/images/valid/custom/crowskull/crow_zip_half.exr", "b.exr", "--layer", "missing"]));
+}
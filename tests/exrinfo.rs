@@ -0,0 +1,36 @@
+#![cfg(feature = "cli")]
+
+//! Run the `exrinfo` binary on the test images, which also exercises the public meta data api.
+
+use std::process::Command;
+
+fn exrinfo(arguments: &[&str]) -> (bool, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_exrinfo")).args(arguments).output().unwrap();
+    (output.status.success(), String::from_utf8(output.stdout).unwrap())
+}
+
+#[test]
+fn prints_layers_channels_and_statistics() {
+    let (success, text) = exrinfo(&["tests/images/valid/custom/crowskull/crow_zip_half.exr"]);
+    assert!(success);
+    assert!(text.contains("compression zip block compression"));
+    assert!(text.contains("A f16, min 1, max 1, mean 1"));
+    assert!(text.contains("displayWindow: "));
+    assert!(text.trim_end().ends_with("valid"));
+}
+
+#[test]
+fn prints_json() {
+    let (success, json) = exrinfo(&["--json", "--no-statistics", "tests/images/valid/custom/crowskull/crow_dwa.exr"]);
+    assert!(success);
+    assert!(json.starts_with("{\"files\":[{\"path\":\"tests/images/valid/custom/crowskull/crow_dwa.exr\",\"layers\":[{"));
+    assert!(json.contains("\"statistics\":null"));
+    assert!(json.contains("\"validation\":{\"valid\":true,\"errors\":[],\"warnings\":[]}"));
+}
+
+#[test]
+fn reports_invalid_files() {
+    let (success, json) = exrinfo(&["--json", "tests/images/invalid/does-not-exist.exr"]);
+    assert!(!success);
+    assert!(json.contains("\"valid\":false"));
+}
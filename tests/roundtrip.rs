@@ -11,7 +11,10 @@ use std::ffi::OsStr;
 use exr::prelude::*;
 use exr::error::{Error, UnitResult};
 use exr::prelude::pixel_vec::PixelVec;
+use exr::prelude::pixel_vec::TiledPixelVec;
+use exr::math::RoundingMode;
 use exr::image::validate_results::ValidateResult;
+use exr::image::write::layers::WritableLayers;
 use rayon::prelude::IntoParallelIterator;
 use rayon::iter::ParallelIterator;
 
@@ -21,6 +24,14 @@ fn exr_files() -> impl Iterator<Item=PathBuf> {
         .map(walkdir::DirEntry::into_path)
 }
 
+/// write the image to an in-memory file on the current thread,
+/// returning the bytes to be read back by the test.
+fn roundtrip_in_memory<'i>(image: &'i Image<impl WritableLayers<'i>>) -> exr::error::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    image.write().non_parallel().to_buffered(Cursor::new(&mut bytes))?;
+    Ok(bytes)
+}
+
 /// read all images in a directory.
 /// does not check any content, just checks whether a read error or panic happened.
 fn check_files<T>(
@@ -51,6 +62,7 @@ fn check_files<T>(
                 Ok(Err(Error::NotSupported(message))) => Result::Unsupported(message.to_string()),
 
                 Ok(Err(Error::Io(io))) => Result::Error(format!("IoError: {:?}", io)),
+                Ok(Err(Error::File { path, source })) => Result::Error(format!("FileError: {:?} {:?}", path, source)),
                 Ok(Err(Error::Invalid(message))) => Result::Error(format!("Invalid: {:?}", message)),
                 Ok(Err(Error::Aborted)) => panic!("a test produced `Error::Abort`"),
 
@@ -89,8 +101,7 @@ fn round_trip_all_files_full() {
 
         let image = read_image.clone().from_file(path)?;
 
-        let mut tmp_bytes = Vec::new();
-        image.write().non_parallel().to_buffered(Cursor::new(&mut tmp_bytes))?;
+        let tmp_bytes = roundtrip_in_memory(&image)?;
 
         let image2 = read_image.from_buffered(Cursor::new(tmp_bytes))?;
 
@@ -109,8 +120,7 @@ fn round_trip_all_files_simple() {
 
         let image = read_image.clone().from_file(path)?;
 
-        let mut tmp_bytes = Vec::new();
-        image.write().non_parallel().to_buffered(&mut Cursor::new(&mut tmp_bytes))?;
+        let tmp_bytes = roundtrip_in_memory(&image)?;
 
         let image2 = read_image.from_buffered(Cursor::new(&tmp_bytes))?;
 
@@ -144,10 +154,7 @@ fn round_trip_all_files_rgba() {
 
         let image = image_reader.clone().from_file(path)?;
 
-        let mut tmp_bytes = Vec::new();
-
-        image.write().non_parallel()
-            .to_buffered(&mut Cursor::new(&mut tmp_bytes))?;
+        let tmp_bytes = roundtrip_in_memory(&image)?;
 
         let image2 = image_reader.from_buffered(Cursor::new(&tmp_bytes))?;
 
@@ -208,8 +215,7 @@ fn roundtrip_unusual_2() -> UnitResult {
 
     let image = Image::from_channels(size, channels);
 
-    let mut tmp_bytes = Vec::new();
-    image.write().non_parallel().to_buffered(&mut Cursor::new(&mut tmp_bytes))?;
+    let tmp_bytes = roundtrip_in_memory(&image)?;
 
     let image_reader = read()
         .no_deep_data()
@@ -265,8 +271,7 @@ fn roundtrip_unusual_7() -> UnitResult {
 
     let image = Image::from_channels(size, channels);
 
-    let mut tmp_bytes = Vec::new();
-    image.write().non_parallel().to_buffered(&mut Cursor::new(&mut tmp_bytes))?;
+    let tmp_bytes = roundtrip_in_memory(&image)?;
 
     let image_reader = read()
         .no_deep_data()
@@ -293,4 +298,1544 @@ fn roundtrip_unusual_7() -> UnitResult {
 
     assert_eq!(pixels1.pixels, pixels2.pixels);
     Ok(())
-}
\ No newline at end of file
+}
+
+#[test]
+fn roundtrip_channel_array() -> UnitResult {
+    let size = Vec2(3, 2);
+    let pixels = (0..size.area())
+        .map(|index| (index as f32, f16::from_f32(index as f32 * 0.5), index as u32 * 3))
+        .collect::<Vec<_>>();
+
+    let pixels = PixelVec { resolution: size, pixels };
+
+    let channels = SpecificChannels::build()
+        .with_channel("A")
+        .with_channel("B")
+        .with_channel("C")
+        .with_pixels(pixels.clone()
+    );
+
+    let image = Image::from_channels(size, channels);
+
+    let tmp_bytes = roundtrip_in_memory(&image)?;
+
+    let image2 = read()
+        .no_deep_data()
+        .largest_resolution_level()
+        .specific_channel_array(["C", "A", "B"])
+        .collect_pixels(PixelVec::<[f32; 3]>::constructor, PixelVec::set_pixel)
+        .first_valid_layer()
+        .all_attributes()
+        .non_parallel()
+        .from_buffered(Cursor::new(&tmp_bytes))?;
+
+    let expected: Vec<[f32; 3]> = pixels.pixels.iter()
+        .map(|&(a, b, c)| [c as f32, a, b.to_f32()])
+        .collect();
+
+    let channel_names = image2.layer_data.channel_data.channels.iter()
+        .map(|channel| channel.name.to_string()).collect::<Vec<_>>();
+
+    assert_eq!(channel_names, ["C", "A", "B"]);
+    assert_eq!(image2.layer_data.channel_data.pixels.pixels, expected);
+    Ok(())
+}
+
+#[test]
+fn roundtrip_dynamic_channels() -> UnitResult {
+    use exr::image::read::specific_channels::DynamicPixel;
+
+    let size = Vec2(3, 2);
+    let pixels = (0..size.area())
+        .map(|index| (index as f32, f16::from_f32(index as f32 * 0.5)))
+        .collect::<Vec<_>>();
+
+    let pixels = PixelVec { resolution: size, pixels };
+
+    let channels = SpecificChannels::build()
+        .with_channel("A")
+        .with_channel("B")
+        .with_pixels(pixels.clone()
+    );
+
+    let image = Image::from_channels(size, channels);
+
+    let tmp_bytes = roundtrip_in_memory(&image)?;
+
+    let selection: Vec<Text> = vec![Text::from("B"), Text::from("missing"), Text::from("A")];
+
+    let image2 = read()
+        .no_deep_data()
+        .largest_resolution_level()
+        .dynamic_channels(selection)
+        .collect_pixels(PixelVec::<DynamicPixel>::constructor, PixelVec::set_pixel)
+        .first_valid_layer()
+        .all_attributes()
+        .non_parallel()
+        .from_buffered(Cursor::new(&tmp_bytes))?;
+
+    let channels = &image2.layer_data.channel_data.channels;
+    assert_eq!(channels[0].as_ref().map(|channel| channel.name.to_string()), Some("B".to_string()));
+    assert!(channels[1].is_none());
+    assert_eq!(channels[2].as_ref().map(|channel| channel.name.to_string()), Some("A".to_string()));
+
+    for (pixel, &(a, b)) in image2.layer_data.channel_data.pixels.pixels.iter().zip(&pixels.pixels) {
+        assert_eq!(pixel.as_slice(), &[ Some(Sample::F16(b)), None, Some(Sample::F32(a)) ]);
+    }
+
+    let typed = read()
+        .no_deep_data()
+        .largest_resolution_level()
+        .dynamic_channels(["B", "missing", "A"])
+        .collect_pixels(PixelVec::<DynamicPixel<f32>>::constructor, PixelVec::set_pixel)
+        .first_valid_layer()
+        .all_attributes()
+        .non_parallel()
+        .from_buffered(Cursor::new(&tmp_bytes))?;
+
+    for (pixel, &(a, b)) in typed.layer_data.channel_data.pixels.pixels.iter().zip(&pixels.pixels) {
+        assert_eq!(pixel.as_slice(), &[ Some(b.to_f32()), None, Some(a) ]);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn read_truncated_file_partially() -> UnitResult {
+    let size = Vec2(16, 64);
+    let channels = SpecificChannels::rgb(|position: Vec2<usize>| (position.x() as f32, position.y() as f32, 0.5_f32));
+    let encoding = Encoding { compression: Compression::ZIP16, blocks: Blocks::ScanLines, line_order: LineOrder::Increasing };
+
+    let mut bytes = Vec::new();
+    Image::from_encoded_channels(size, encoding, channels).write().non_parallel()
+        .to_buffered(&mut Cursor::new(&mut bytes))?;
+
+    // cut the last chunk in half
+    bytes.truncate(bytes.len() - 20);
+
+    let read_image = read()
+        .no_deep_data().largest_resolution_level()
+        .rgb_channels(PixelVec::<(f32, f32, f32)>::constructor, PixelVec::set_pixel)
+        .first_valid_layer().all_attributes();
+
+    assert!(read_image.clone().from_buffered(Cursor::new(&bytes)).is_err(), "truncated file should not be read completely");
+
+    for &parallel in [ false, true ].iter() {
+        let read_image = if parallel { read_image.clone() } else { read_image.clone().non_parallel() };
+        let partial = read_image.from_buffered_partial(Cursor::new(&bytes))?;
+
+        assert!(!partial.is_complete());
+        assert_eq!(partial.missing_blocks.len(), 1);
+
+        let missing = partial.missing_blocks[0];
+        assert_eq!(missing.pixel_position, Vec2(0, 48));
+        assert_eq!(missing.pixel_size, Vec2(16, 16));
+
+        assert!(partial.is_pixel_valid(0, Vec2(0, 0), Vec2(3, 47)));
+        assert!(!partial.is_pixel_valid(0, Vec2(0, 0), Vec2(3, 48)));
+
+        let mask = partial.validity_mask(0, Vec2(0, 0)).unwrap();
+        assert_eq!(mask.resolution, size);
+        assert_eq!(mask.valid_pixel_count(), 16 * 48);
+        assert!(mask.is_valid(Vec2(15, 47)));
+        assert!(!mask.is_valid(Vec2(0, 48)));
+        assert!(!mask.is_complete());
+        assert!(partial.validity_mask(1, Vec2(0, 0)).is_none());
+
+        let pixels = &partial.image.layer_data.channel_data.pixels;
+        assert_eq!(pixels.get_pixel(Vec2(3, 47)), &(3.0, 47.0, 0.5));
+        assert_eq!(pixels.get_pixel(Vec2(3, 48)), &(0.0, 0.0, 0.0));
+
+        assert!(partial.complete().is_err());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn roundtrip_tiled_pixel_vec() -> UnitResult {
+    let size = Vec2(10, 7);
+    let channels = SpecificChannels::rgb(|position: Vec2<usize>| (position.x() as f32, position.y() as f32, 0.5_f32));
+    let encoding = Encoding { blocks: Blocks::Tiles(Vec2(4, 3)), .. Encoding::FAST_LOSSLESS };
+
+    let tmp_bytes = roundtrip_in_memory(&Image::from_encoded_channels(size, encoding, channels))?;
+
+    let image = read()
+        .no_deep_data().largest_resolution_level()
+        .specific_channels().required("R").required("G").required("B")
+        .collect_pixels_with_header(TiledPixelVec::<(f32, f32, f32)>::constructor_with_header, TiledPixelVec::set_pixel)
+        .first_valid_layer().all_attributes().non_parallel()
+        .from_buffered(Cursor::new(&tmp_bytes))?;
+
+    let pixels = &image.layer_data.channel_data.pixels;
+    assert_eq!(pixels.tile_size, Vec2(4, 3));
+    assert_eq!(pixels.tile_count(), Vec2(3, 3));
+
+    for y in 0 .. size.height() {
+        for x in 0 .. size.width() {
+            assert_eq!(*pixels.get_pixel(Vec2(x, y)), (x as f32, y as f32, 0.5));
+        }
+    }
+
+    // the smaller tile at the bottom right corner is stored contiguously
+    let corner = pixels.tile(Vec2(2, 2));
+    assert_eq!(corner.len(), 2);
+    assert_eq!(corner, &[(8.0, 6.0, 0.5), (9.0, 6.0, 0.5)]);
+
+    let tile = pixels.tile(Vec2(1, 0));
+    assert_eq!(tile.len(), 12);
+    assert_eq!(tile[5], (5.0, 1.0, 0.5));
+    Ok(())
+}
+
+#[test]
+fn roundtrip_generic_pixel_storage() -> UnitResult {
+    fn roundtrip<Storage>(bytes: &[u8]) -> Result<Storage>
+        where Storage: exr::image::storage::PixelStorage<Pixel = (f32, f32, f32)>
+    {
+        let image = read()
+            .no_deep_data().largest_resolution_level()
+            .specific_channels().required("R").required("G").required("B")
+            .collect_storage::<Storage>()
+            .first_valid_layer().all_attributes().non_parallel()
+            .from_buffered(Cursor::new(bytes))?;
+
+        // the storage can be written again without any conversion
+        let mut written_again = Vec::new();
+        image.write().non_parallel().to_buffered(Cursor::new(&mut written_again))?;
+        assert_eq!(read_first_rgba_layer_from_buffered(&written_again)?.layer_data.size, image.layer_data.size);
+
+        Ok(image.layer_data.channel_data.pixels)
+    }
+
+    let size = Vec2(10, 7);
+    let channels = SpecificChannels::rgb(|position: Vec2<usize>| (position.x() as f32, position.y() as f32, 0.5_f32));
+    let encoding = Encoding { blocks: Blocks::Tiles(Vec2(4, 3)), .. Encoding::FAST_LOSSLESS };
+
+    let tmp_bytes = roundtrip_in_memory(&Image::from_encoded_channels(size, encoding, channels))?;
+
+    let flat: PixelVec<(f32, f32, f32)> = roundtrip(&tmp_bytes)?;
+    let tiled: TiledPixelVec<(f32, f32, f32)> = roundtrip(&tmp_bytes)?;
+    assert_eq!(tiled.tile_size, Vec2(4, 3));
+
+    for y in 0 .. size.height() {
+        for x in 0 .. size.width() {
+            assert_eq!(*flat.get_pixel(Vec2(x, y)), (x as f32, y as f32, 0.5));
+            assert_eq!(*tiled.get_pixel(Vec2(x, y)), (x as f32, y as f32, 0.5));
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn roundtrip_create_pixels_with_header() -> UnitResult {
+    let size = Vec2(3, 2);
+    let channels = SpecificChannels::build()
+        .with_channel("G")
+        .with_channel("R")
+        .with_channel("Z")
+        .with_pixel_fn(|position: Vec2<usize>| (position.x() as f32, position.y() as f32, 0.5_f32));
+
+    let layer = Layer::new(size, LayerAttributes::named("main").with_position(Vec2(7, -2)), Encoding::FAST_LOSSLESS, channels);
+
+    let tmp_bytes = roundtrip_in_memory(&Image::from_layer(layer))?;
+
+    struct Storage { layer_name: Option<Text>, position: Vec2<i32>, channel_count: usize, samples: Vec<f32> }
+
+    let image = read()
+        .no_deep_data().largest_resolution_level()
+        .specific_channels().required("R")
+        .collect_pixels_with_header(
+            |header: &exr::meta::header::Header, _channels: &(ChannelDescription,)| Storage {
+                layer_name: header.own_attributes.layer_name.clone(),
+                position: header.own_attributes.layer_position,
+                channel_count: header.channels.list.len(),
+                samples: vec![0.0; header.layer_size.area()],
+            },
+
+            move |storage: &mut Storage, position: Vec2<usize>, (red,): (f32,)|
+                storage.samples[position.flat_index_for_size(size)] = red
+        )
+        .first_valid_layer().all_attributes().non_parallel()
+        .from_buffered(Cursor::new(&tmp_bytes))?;
+
+    let storage = &image.layer_data.channel_data.pixels;
+    assert_eq!(storage.layer_name, Some(Text::from("main")));
+    assert_eq!(storage.position, Vec2(7, -2));
+    assert_eq!(storage.channel_count, 3);
+    assert_eq!(storage.samples, vec![0.0, 0.0, 0.0, 1.0, 1.0, 1.0]);
+    Ok(())
+}
+
+#[test]
+fn roundtrip_two_channel_selections() -> UnitResult {
+    let size = Vec2(5, 3);
+    let channels = SpecificChannels::build()
+        .with_channel("B")
+        .with_channel("G")
+        .with_channel("R")
+        .with_channel("Z")
+        .with_pixel_fn(|position: Vec2<usize>| (0.25_f32, position.y() as f32, position.x() as f32, 7.0_f32));
+
+    let tmp_bytes = roundtrip_in_memory(&Image::from_channels(size, channels))?;
+
+    let depth = read().no_deep_data().largest_resolution_level()
+        .specific_channels().required("Z")
+        .collect_pixels(PixelVec::<(f32,)>::constructor, PixelVec::set_pixel);
+
+    let image = read().no_deep_data().largest_resolution_level()
+        .rgba_channels(PixelVec::<(f32, f32, f32, f32)>::constructor, PixelVec::set_pixel)
+        .and_channels(depth)
+        .first_valid_layer().all_attributes().non_parallel()
+        .from_buffered(Cursor::new(&tmp_bytes))?;
+
+    let (rgba, depth) = &image.layer_data.channel_data;
+    assert_eq!(rgba.pixels.get_pixel(Vec2(4, 2)), &(4.0, 2.0, 0.25, 1.0));
+    assert_eq!(depth.pixels.get_pixel(Vec2(4, 2)), &(7.0,));
+    assert_eq!(depth.channels.0.name, Text::from("Z"));
+    Ok(())
+}
+
+#[test]
+fn read_only_some_channels_of_zip_image() -> UnitResult {
+    use exr::compression::filter::{ChannelFilters, Delta};
+
+    let size = Vec2(37, 40);
+    let channels = SpecificChannels::build()
+        .with_channel("B").with_channel("G").with_channel("R").with_channel("Z")
+        .with_pixel_fn(|position: Vec2<usize>| (
+            f16::from_f32(0.25), position.y() as f32, f16::from_f32(position.x() as f32), 100.0 + position.x() as f32 * 0.5
+        ));
+
+    let mut attributes = LayerAttributes::default();
+    attributes.sample_filters = ChannelFilters::default().with_filter("Z", Delta);
+
+    let encoding = Encoding { compression: Compression::ZIP16, .. Encoding::default() };
+    let image = Image::from_layer(Layer::new(size, attributes, encoding, channels));
+
+    let mut bytes = Vec::new();
+    image.write().non_parallel().to_buffered(Cursor::new(&mut bytes))?;
+
+    for &parallel in &[ false, true ] {
+        let depth = read().no_deep_data().largest_resolution_level()
+            .specific_channels().required("Z")
+            .collect_pixels(PixelVec::<(f32,)>::constructor, PixelVec::set_pixel);
+
+        let read_depth = depth.clone().first_valid_layer().all_attributes();
+        let read_depth = if parallel { read_depth } else { read_depth.non_parallel() };
+        let depth_image = read_depth.from_buffered(Cursor::new(&bytes))?;
+        assert_eq!(depth_image.layer_data.channel_data.pixels.get_pixel(Vec2(36, 39)), &(118.0,));
+
+        let read_both = read().no_deep_data().largest_resolution_level()
+            .specific_channels().required("R").required("G")
+            .collect_pixels(PixelVec::<(f32, f32)>::constructor, PixelVec::set_pixel)
+            .and_channels(depth)
+            .first_valid_layer().all_attributes();
+
+        let read_both = if parallel { read_both } else { read_both.non_parallel() };
+        let both = read_both.from_buffered(Cursor::new(&bytes))?;
+
+        let (red_green, depth) = &both.layer_data.channel_data;
+        assert_eq!(red_green.pixels.get_pixel(Vec2(36, 39)), &(36.0, 39.0));
+        assert_eq!(depth.pixels, depth_image.layer_data.channel_data.pixels);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn read_flat_layer_and_rgba_in_one_pass() -> UnitResult {
+    let path = "tests/images/valid/custom/crowskull/crow_zip_half.exr";
+    let image = read_first_flat_layer_and_rgba_from_file(
+        path, PixelVec::<(f32, f32, f32, f32)>::constructor, PixelVec::set_pixel
+    )?;
+
+    let (all_channels, rgba) = &image.layer_data.channel_data;
+    let red = all_channels.list.iter().find(|channel| channel.name.eq("R")).expect("no red channel");
+
+    for (index, red_sample) in red.sample_data.values_as_f32().enumerate() {
+        assert_eq!(rgba.pixels.pixels[index].0, red_sample);
+    }
+
+    assert_eq!(image.layer_data.channel_data.0, read_first_flat_layer_from_file(path)?.layer_data.channel_data);
+    Ok(())
+}
+
+#[test]
+fn roundtrip_matching_channel_names() -> UnitResult {
+    let size = Vec2(3, 2);
+    let pixels = (0..size.area())
+        .map(|index| (index as f32, index as f32 * 2.0, index as f32 * 3.0))
+        .collect::<Vec<_>>();
+
+    let channels = SpecificChannels::build()
+        .with_channel("alpha")
+        .with_channel("G")
+        .with_channel("r")
+        .with_pixels(PixelVec { resolution: size, pixels: pixels.clone() });
+
+    let tmp_bytes = roundtrip_in_memory(&Image::from_channels(size, channels))?;
+
+    let image = read()
+        .no_deep_data().largest_resolution_level()
+        .specific_channels()
+        .required_matching("R", ChannelMatching::CaseInsensitive)
+        .required("G")
+        .optional_matching("A", 1.0, ChannelMatching::aliases(["Alpha", "alpha"]))
+        .optional_matching("Z", -1.0, ChannelMatching::CaseInsensitive)
+        .collect_pixels(PixelVec::<(f32, f32, f32, f32)>::constructor, PixelVec::set_pixel)
+        .first_valid_layer().all_attributes().non_parallel()
+        .from_buffered(Cursor::new(&tmp_bytes))?;
+
+    let (r, g, a, z) = &image.layer_data.channel_data.channels;
+    assert_eq!(r.name, Text::from("r"));
+    assert_eq!(g.name, Text::from("G"));
+    assert_eq!(a.as_ref().map(|channel| channel.name.to_string()), Some("alpha".to_string()));
+    assert!(z.is_none());
+
+    for (pixel, &(alpha, g, r)) in image.layer_data.channel_data.pixels.pixels.iter().zip(&pixels) {
+        assert_eq!(*pixel, (r, g, alpha, -1.0));
+    }
+
+    let exact = read()
+        .no_deep_data().largest_resolution_level()
+        .specific_channels().required("R")
+        .collect_pixels(PixelVec::<(f32,)>::constructor, PixelVec::set_pixel)
+        .first_valid_layer().all_attributes().non_parallel()
+        .from_buffered(Cursor::new(&tmp_bytes));
+
+    assert!(exact.is_err(), "exact matching is the default");
+    Ok(())
+}
+
+#[test]
+fn roundtrip_channels_within_layer() -> UnitResult {
+    let size = Vec2(3, 2);
+    let pixels = (0..size.area())
+        .map(|index| (index as f32, index as f32 * 2.0, index as f32 * 3.0, index as f32 * 4.0))
+        .collect::<Vec<_>>();
+
+    let channels = SpecificChannels::build()
+        .with_channel("R")
+        .with_channel("beauty.G")
+        .with_channel("beauty.R")
+        .with_channel("depth.Z")
+        .with_pixels(PixelVec { resolution: size, pixels: pixels.clone() });
+
+    let tmp_bytes = roundtrip_in_memory(&Image::from_channels(size, channels))?;
+
+    let image = read()
+        .no_deep_data().largest_resolution_level()
+        .specific_channels()
+        .required("R")
+        .required("G")
+        .optional("Z", -1.0)
+        .within_layer("beauty")
+        .collect_pixels(PixelVec::<(f32, f32, f32)>::constructor, PixelVec::set_pixel)
+        .first_valid_layer().all_attributes().non_parallel()
+        .from_buffered(Cursor::new(&tmp_bytes))?;
+
+    let (r, g, z) = &image.layer_data.channel_data.channels;
+    assert_eq!(r.name, Text::from("R"));
+    assert_eq!(g.name, Text::from("G"));
+    assert!(z.is_none(), "`depth.Z` is not in the layer");
+
+    for (pixel, &(_, beauty_g, beauty_r, _)) in image.layer_data.channel_data.pixels.pixels.iter().zip(&pixels) {
+        assert_eq!(*pixel, (beauty_r, beauty_g, -1.0));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn read_channels_matching_patterns() -> UnitResult {
+    let size = Vec2(3, 2);
+    let channels = SpecificChannels::build()
+        .with_channel("beauty.A")
+        .with_channel("beauty.R")
+        .with_channel("diffuse.G")
+        .with_channel("diffuse.R")
+        .with_channel("Z")
+        .with_pixel_fn(|position: Vec2<usize>| (1.0_f32, 2.0_f32, 3.0_f32, position.x() as f32, 5.0_f32));
+
+    let mut tmp_bytes = Vec::new();
+    let encoding = Encoding { compression: Compression::ZIP1, .. Encoding::FAST_LOSSLESS };
+    Image::from_encoded_channels(size, encoding, channels).write().non_parallel().to_buffered(&mut Cursor::new(&mut tmp_bytes))?;
+
+    let image = read().no_deep_data().largest_resolution_level()
+        .all_channels().channels_matching("diffuse.*").channels_matching("*.A")
+        .first_valid_layer().all_attributes()
+        .from_buffered(Cursor::new(&tmp_bytes))?;
+
+    let channels = &image.layer_data.channel_data.list;
+    let names: Vec<_> = channels.iter().map(|channel| channel.name.to_string()).collect();
+    assert_eq!(names, vec![ "beauty.A", "diffuse.G", "diffuse.R" ]);
+    assert_eq!(channels[2].sample_data.values_as_f32().collect::<Vec<_>>(), vec![ 0.0, 1.0, 2.0, 0.0, 1.0, 2.0 ]);
+
+    let missing = read().no_deep_data().largest_resolution_level()
+        .all_channels().channels_matching("specular.*")
+        .first_valid_layer().all_attributes()
+        .from_buffered(Cursor::new(&tmp_bytes));
+
+    assert!(missing.is_err(), "no layer contains a matching channel");
+
+    let layer = |name: &str, channel: &str| Layer::new(
+        size, LayerAttributes::named(name), Encoding::FAST_LOSSLESS,
+        SpecificChannels::build().with_channel(channel).with_pixel_fn(|_: Vec2<usize>| (0.5_f32,))
+    );
+
+    let layers = vec![ layer("diffuse", "diffuse.R"), layer("specular", "specular.R"), layer("specular2", "specular.G") ];
+    let tmp_bytes = roundtrip_in_memory(&Image::from_layers(ImageAttributes::with_size(size), layers))?;
+
+    let image = read().no_deep_data().largest_resolution_level()
+        .all_channels().channels_matching("specular.*")
+        .all_layers().all_attributes()
+        .from_buffered(Cursor::new(&tmp_bytes))?;
+
+    let names: Vec<_> = image.layer_data.iter().map(|layer| layer.channel_data.list[0].name.to_string()).collect();
+    assert_eq!(names, vec![ "specular.R", "specular.G" ], "layers without matching channels are skipped");
+
+    let image = read().no_deep_data().largest_resolution_level()
+        .all_channels().channels_matching("specular.*")
+        .first_valid_layer().all_attributes()
+        .from_buffered(Cursor::new(&tmp_bytes))?;
+
+    assert_eq!(image.layer_data.attributes.layer_name, Some(Text::from("specular")));
+    Ok(())
+}
+
+#[test]
+fn read_channel_plane() -> UnitResult {
+    let size = Vec2(3, 2);
+    let pixels = (0..size.area())
+        .map(|index| (index as f32, f16::from_f32(index as f32 * 0.5)))
+        .collect::<Vec<_>>();
+
+    let pixels = PixelVec { resolution: size, pixels };
+
+    let channels = SpecificChannels::build()
+        .with_channel("A")
+        .with_channel("Z")
+        .with_pixels(pixels.clone()
+    );
+
+    let tmp_bytes = roundtrip_in_memory(&Image::from_channels(size, channels))?;
+
+    let image = read()
+        .no_deep_data().largest_resolution_level().all_channels().first_valid_layer().all_attributes()
+        .non_parallel()
+        .from_buffered(Cursor::new(&tmp_bytes))?;
+
+    let depth: Vec<f16> = pixels.pixels.iter().map(|&(_, z)| z).collect();
+    let depth_plane = image.layer_data.channel_plane("Z").expect("missing Z channel");
+    assert_eq!(depth_plane.as_f16_slice(), Some(depth.as_slice()));
+    assert_eq!(depth_plane.as_f32_slice(), None);
+
+    let alpha: Vec<f32> = pixels.pixels.iter().map(|&(a, _)| a).collect();
+    assert_eq!(image.layer_data.channel_plane_to_f32("A"), Some(alpha));
+
+    assert!(image.layer_data.channel_plane("missing").is_none());
+    Ok(())
+}
+
+#[test]
+fn roundtrip_auto_compression() -> UnitResult {
+    let size = Vec2(64, 48);
+    let encoding = Encoding { compression: Compression::Uncompressed, .. Encoding::SMALL_LOSSLESS };
+    let channels = SpecificChannels::rgb(|position: Vec2<usize>| (position.x() as f32, (position.y() / 8) as f32, 0.5_f32));
+    let image = Image::from_encoded_channels(size, encoding, channels);
+
+    let mut tmp_bytes = Vec::new();
+    image.write().auto_compression(SpeedBias::Size).non_parallel().to_buffered(&mut Cursor::new(&mut tmp_bytes))?;
+
+    let image2 = read_first_flat_layer_from_buffered(&tmp_bytes)?;
+    let compression = image2.layer_data.encoding.compression;
+    assert_ne!(compression, Compression::Uncompressed);
+    assert!(!compression.may_loose_data());
+
+    let mut uncompressed_bytes = Vec::new();
+    image.write().non_parallel().to_buffered(&mut Cursor::new(&mut uncompressed_bytes))?;
+    let uncompressed = read_first_flat_layer_from_buffered(&uncompressed_bytes)?;
+    assert_eq!(image2.layer_data.channel_data, uncompressed.layer_data.channel_data);
+
+    Ok(())
+}
+
+#[test]
+fn roundtrip_encoding_presets() -> UnitResult {
+    let size = Vec2(70, 33);
+    let presets = [ Encoding::fast(), Encoding::small(), Encoding::for_compositing(), Encoding::for_textures(Vec2(32, 16)) ];
+
+    for &encoding in presets.iter() {
+        let channels = SpecificChannels::rgb(|position: Vec2<usize>| (position.x() as f32, position.y() as f32, 0.5_f32));
+        let image = Image::from_encoded_channels(size, encoding, channels);
+
+        let tmp_bytes = roundtrip_in_memory(&image)?;
+
+        let image2 = read_first_flat_layer_from_buffered(&tmp_bytes)?;
+        assert_eq!(image2.layer_data.encoding.compression, encoding.compression);
+        assert_eq!(image2.layer_data.encoding.blocks, encoding.blocks);
+
+        let red = &image2.layer_data.channel_data.list[2];
+        assert_eq!(red.sample_data.value_by_flat_index(69 + 32 * 70), Sample::F32(69.0));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn roundtrip_buffer_sizes() -> UnitResult {
+    let size = Vec2(40, 30);
+    let channels = SpecificChannels::rgb(|position: Vec2<usize>| (position.x() as f32, position.y() as f32, 0.5_f32));
+    let image = Image::from_encoded_channels(size, Encoding::FAST_LOSSLESS, channels);
+
+    for &buffer_size in [ 0, 7, 1024 * 1024 ].iter() {
+        let mut tmp_bytes = Vec::new();
+        image.write().non_parallel().buffer_size(buffer_size).to_unbuffered(&mut Cursor::new(&mut tmp_bytes))?;
+
+        let image2 = read().no_deep_data().largest_resolution_level().all_channels().first_valid_layer().all_attributes()
+            .non_parallel().buffer_size(buffer_size).from_unbuffered(Cursor::new(&tmp_bytes))?;
+
+        let red = &image2.layer_data.channel_data.list[2];
+        assert_eq!(red.sample_data.value_by_flat_index(39 + 29 * 40), Sample::F32(39.0));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn roundtrip_file_with_prefetched_chunks() -> UnitResult {
+    let directory = tempfile::tempdir()?;
+    let path = directory.path().join("prefetch.exr");
+    let channels = SpecificChannels::rgb(|position: Vec2<usize>| (position.x() as f32, position.y() as f32, 0.5_f32));
+    Image::from_encoded_channels(Vec2(64, 200), Encoding::FAST_LOSSLESS, channels).write().to_file(&path)?;
+
+    let read_image = |prefetch: bool| {
+        let reader = read().no_deep_data().largest_resolution_level().all_channels().first_valid_layer().all_attributes();
+        if prefetch { reader.prefetch_chunks(4).from_file(&path) } else { reader.from_file(&path) }
+    };
+
+    let prefetched = read_image(true)?;
+    assert_eq!(prefetched, read_image(false)?);
+
+    let red = &prefetched.layer_data.channel_data.list[2];
+    assert_eq!(red.sample_data.value_by_flat_index(63 + 199 * 64), Sample::F32(63.0));
+    Ok(())
+}
+
+#[test]
+fn file_errors_contain_the_path() {
+    let directory = tempfile::tempdir().unwrap();
+    let missing = directory.path().join("missing").join("image.exr");
+
+    let contains_path = |result: UnitResult| match result {
+        Err(Error::File { path, source }) => path == missing && source.kind() == std::io::ErrorKind::NotFound,
+
+        _ => false,
+    };
+
+    let result = read().no_deep_data().largest_resolution_level().all_channels().first_valid_layer().all_attributes()
+        .from_file(&missing);
+
+    assert!(contains_path(result.map(|_| ())));
+    assert!(contains_path(MetaData::read_from_file(&missing, false).map(|_| ())));
+
+    let image = Image::from_channels((3, 2), SpecificChannels::rgb(|_position| (0.5_f32, 0.5_f32, 0.5_f32)));
+    assert!(contains_path(image.write().to_file(&missing)));
+}
+
+#[cfg(unix)]
+#[test]
+fn roundtrip_file_with_non_utf8_path() -> UnitResult {
+    use std::os::unix::ffi::OsStrExt;
+
+    let directory = tempfile::tempdir()?;
+    let path = directory.path().join(OsStr::from_bytes(b"non_utf8_\xff\xfe.exr"));
+    assert!(path.to_str().is_none());
+
+    let image = Image::from_channels((3, 2), SpecificChannels::rgb(|_position| (0.5_f32, 0.25_f32, 0.5_f32)));
+    image.write().to_file(&path)?;
+
+    let image2 = read_first_flat_layer_from_file(&path)?;
+
+    let green = &image2.layer_data.channel_data.list[1];
+    assert_eq!(green.sample_data.value_by_flat_index(5), Sample::F32(0.25));
+    Ok(())
+}
+
+#[test]
+fn roundtrip_large_attributes() -> UnitResult {
+    let manifest = AttributeValue::Custom { kind: Text::from("idmanifest"), bytes: vec![ 7; 200_000 ] };
+    let comments = Text::from_utf8("x".repeat(100_000));
+
+    let mut attributes = LayerAttributes::named("large");
+    attributes.comments = Some(comments.clone());
+    attributes.other.insert(Text::from("manifest"), manifest.clone());
+
+    let channels = SpecificChannels::rgb(|_position| (0.5_f32, 0.5_f32, 0.5_f32));
+    let image = Image::from_layer(Layer::new((4, 3), attributes, Encoding::FAST_LOSSLESS, channels));
+
+    let mut tmp_bytes = Vec::new();
+    image.write().to_buffered(&mut Cursor::new(&mut tmp_bytes))?;
+
+    let read_with_limit = |max_attribute_size: usize| read().no_deep_data().largest_resolution_level().all_channels()
+        .first_valid_layer().all_attributes().max_attribute_size(max_attribute_size).from_buffered(Cursor::new(&tmp_bytes));
+
+    let image2 = read_with_limit(1_000_000)?;
+    assert_eq!(image2.layer_data.attributes.comments, Some(comments));
+    assert_eq!(image2.layer_data.attributes.other.get(&Text::from("manifest")), Some(&manifest));
+
+    let error = read_with_limit(150_000).expect_err("oversized attribute should be rejected");
+    assert!(matches!(error, Error::Invalid(message) if message.contains("manifest")));
+    Ok(())
+}
+
+#[test]
+fn roundtrip_auto_tiles() -> UnitResult {
+    let size = Vec2(300, 70);
+    let channels = SpecificChannels::rgb(|position: Vec2<usize>| (position.x() as f32, position.y() as f32, 0.5_f32));
+    let image = Image::from_encoded_channels(size, Encoding::for_compositing(), channels);
+
+    let mut tmp_bytes = Vec::new();
+    image.write().non_parallel().tiles_auto().to_buffered(&mut Cursor::new(&mut tmp_bytes))?;
+
+    let image2 = read_first_flat_layer_from_buffered(&tmp_bytes)?;
+    assert_eq!(image2.layer_data.encoding.blocks, Blocks::Tiles(Vec2(128, 70)));
+
+    let red = &image2.layer_data.channel_data.list[2];
+    assert_eq!(red.sample_data.value_by_flat_index(299 + 69 * 300), Sample::F32(299.0));
+    Ok(())
+}
+
+#[test]
+fn roundtrip_decreasing_line_order() -> UnitResult {
+    use exr::block::chunk::TileCoordinates;
+
+    let size = Vec2(70, 50);
+    let samples = |level_size: Vec2<usize>| FlatSamples::F32((0 .. level_size.area()).map(|index| index as f32).collect());
+    let mip_levels = exr::meta::mip_map_levels(RoundingMode::Down, size).map(|(_, level_size)| samples(level_size)).collect();
+
+    let layers = vec![
+        (Blocks::ScanLines, Levels::Singular(samples(size))),
+        (Blocks::Tiles(Vec2(16, 16)), Levels::Mip { rounding_mode: RoundingMode::Down, level_data: mip_levels }),
+    ];
+
+    for (blocks, levels) in layers {
+        for &parallel in [ false, true ].iter() {
+            let encoding = Encoding { compression: Compression::ZIP1, blocks, line_order: LineOrder::Decreasing };
+            let channels = AnyChannels::sort(smallvec::smallvec![ AnyChannel::new("Y", levels.clone()) ]);
+            let image = Image::from_layer(Layer::new(size, LayerAttributes::named("decreasing"), encoding, channels));
+
+            let mut tmp_bytes = Vec::new();
+            let writer = if parallel { image.write() } else { image.write().non_parallel() };
+            writer.to_buffered(&mut Cursor::new(&mut tmp_bytes))?;
+
+            let reader = exr::block::read(Cursor::new(&tmp_bytes), true)?;
+            let header = reader.headers()[0].clone();
+
+            let file_order: Vec<TileCoordinates> = reader.all_chunks(true)?
+                .map(|chunk| header.get_block_data_indices(&chunk?.compressed_block))
+                .collect::<exr::error::Result<_>>()?;
+
+            let expected_order: Vec<TileCoordinates> = header.enumerate_ordered_blocks().map(|(_, tile)| tile.location).collect();
+            assert_eq!(file_order, expected_order);
+
+            // rows are decreasing within each level, while the levels and the tiles within a row are increasing
+            assert!(file_order.windows(2).all(|pair| {
+                let (previous, next) = (pair[0], pair[1]);
+                previous.level_index.x() < next.level_index.x() || (
+                    previous.level_index == next.level_index && (
+                        previous.tile_index.y() > next.tile_index.y() ||
+                        (previous.tile_index.y() == next.tile_index.y() && previous.tile_index.x() < next.tile_index.x())
+                    )
+                )
+            }));
+
+            let image2 = read().no_deep_data().all_resolution_levels().all_channels().first_valid_layer().all_attributes()
+                .non_parallel().from_buffered(Cursor::new(&tmp_bytes))?;
+
+            assert_eq!(image2.layer_data.encoding.line_order, LineOrder::Decreasing);
+            assert_eq!(image2.layer_data.channel_data, image.layer_data.channel_data);
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn roundtrip_convenience_constructors() -> UnitResult {
+    let size = Vec2(5, 3);
+    let rgba: Vec<f32> = (0 .. size.area() * 4).map(|index| index as f32).collect();
+
+    let tmp_bytes = roundtrip_in_memory(&Image::from_rgba_f32(size, rgba))?;
+
+    let image = read_first_rgba_layer_from_buffered(&tmp_bytes)?;
+    assert_eq!(image.layer_data.channel_data.pixels.get_pixel(Vec2(1, 2)), &(44.0, 45.0, 46.0, 47.0));
+
+    let depth: Vec<f32> = (0 .. size.area()).map(|index| index as f32 * 0.5).collect();
+    let tmp_bytes = roundtrip_in_memory(&Image::from_single_channel("Z", size, depth.clone()))?;
+
+    let image = read_first_flat_layer_from_buffered(&tmp_bytes)?;
+    let channel = &image.layer_data.channel_data.list[0];
+    assert_eq!(channel.name, Text::from("Z"));
+    assert_eq!(channel.sample_data, FlatSamples::F32(depth));
+
+    Ok(())
+}
+
+fn read_first_rgba_layer_from_buffered(bytes: &[u8]) -> Result<PixelImage<PixelVec<(f32, f32, f32, f32)>, RgbaChannels>> {
+    read().no_deep_data().largest_resolution_level()
+        .rgba_channels(PixelVec::<(f32, f32, f32, f32)>::constructor, PixelVec::set_pixel)
+        .first_valid_layer().all_attributes().non_parallel()
+        .from_buffered(Cursor::new(bytes))
+}
+
+#[test]
+fn roundtrip_precomputed_mip_maps() -> UnitResult {
+    let size = Vec2(20, 12);
+    let level_sizes = [ Vec2(20, 12), Vec2(10, 6), Vec2(5, 3), Vec2(2, 1), Vec2(1, 1) ];
+
+    let levels: Vec<FlatSamples> = level_sizes.iter().enumerate()
+        .map(|(level, size)| FlatSamples::F32(vec![ level as f32; size.area() ]))
+        .collect();
+
+    let mut too_few_levels = levels.clone();
+    too_few_levels.pop();
+    assert!(Levels::from_mip_maps(size, RoundingMode::Down, too_few_levels).is_err());
+
+    let mut wrong_level_size = levels.clone();
+    wrong_level_size[2] = FlatSamples::F32(vec![ 2.0; 6 * 3 ]);
+    assert!(Levels::from_mip_maps(size, RoundingMode::Down, wrong_level_size).is_err());
+
+    let levels = Levels::from_mip_maps(size, RoundingMode::Down, levels)?;
+    let channels = AnyChannels::sort(smallvec::smallvec![ AnyChannel::new("Y", levels.clone()) ]);
+    let image = Image::from_encoded_channels(size, Encoding::for_textures(Vec2(8, 8)), channels);
+
+    let tmp_bytes = roundtrip_in_memory(&image)?;
+
+    let image2 = read().no_deep_data().all_resolution_levels().all_channels()
+        .first_valid_layer().all_attributes().non_parallel()
+        .from_buffered(Cursor::new(&tmp_bytes))?;
+
+    assert_eq!(image2.layer_data.channel_data.list[0].sample_data, levels);
+    Ok(())
+}
+
+fn read_first_flat_layer_from_buffered(bytes: &[u8]) -> Result<Image<Layer<AnyChannels<FlatSamples>>>> {
+    read().no_deep_data().largest_resolution_level().all_channels()
+        .first_valid_layer().all_attributes().non_parallel()
+        .from_buffered(Cursor::new(bytes))
+}
+
+#[test]
+fn roundtrip_f32_planes() -> UnitResult {
+    let size = Vec2(9, 5);
+    let channels = AnyChannels::sort(smallvec::smallvec![
+        AnyChannel::new("A", FlatSamples::F16((0 .. size.area()).map(|index| f16::from_f32(index as f32 * 0.5)).collect())),
+        AnyChannel::new("B", FlatSamples::F32((0 .. size.area()).map(|index| index as f32 * 0.25).collect())),
+        AnyChannel::new("C", FlatSamples::U32((0 .. size.area() as u32).collect())),
+    ]);
+
+    let encoding = Encoding { blocks: Blocks::Tiles(Vec2(4, 4)), .. Encoding::FAST_LOSSLESS };
+    let image = Image::from_layer(Layer::new(size, LayerAttributes::named("planes"), encoding, channels.clone()));
+
+    let tmp_bytes = roundtrip_in_memory(&image)?;
+
+    let image2 = read().no_deep_data().f32_planes().largest_resolution_level().all_channels()
+        .first_valid_layer().all_attributes().non_parallel()
+        .from_buffered(Cursor::new(&tmp_bytes))?;
+
+    let planes = &image2.layer_data.channel_data.list;
+    assert_eq!(planes.len(), 3);
+
+    for (plane, original) in planes.iter().zip(&channels.list) {
+        assert_eq!(plane.name, original.name);
+        assert_eq!(plane.sample_data, original.sample_data.to_f32_vec());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn read_partial_file_after_checkpoint() -> UnitResult {
+    use exr::block::{self, BlockIndex, UncompressedBlock, writer::{ChunksWriter, ChunkWriter}};
+    use exr::meta::{header::Header, BlockDescription};
+    use exr::meta::attribute::{ChannelDescription, LineOrder};
+
+    let path = std::env::temp_dir().join("exrs_checkpoint_test.exr");
+    let header = Header::new("partial".into(), (16, 32), smallvec::smallvec![ ChannelDescription::new("Y", SampleType::F32, true) ])
+        .with_encoding(Compression::RLE, BlockDescription::ScanLines, LineOrder::Increasing);
+
+    let read_samples = |path: &Path| -> exr::error::Result<Vec<f32>> {
+        let image = read().no_deep_data().largest_resolution_level().all_channels().first_valid_layer().all_attributes()
+            .non_parallel().from_file(path)?;
+
+        Ok(image.layer_data.channel_data.list[0].sample_data.to_f32_vec())
+    };
+
+    let mut partial_samples = Vec::new();
+    let file = std::io::BufWriter::new(std::fs::File::create(&path)?);
+
+    block::write(file, smallvec::smallvec![ header ], true, |meta, writer| {
+        let blocks: Vec<(usize, BlockIndex)> = block::enumerate_ordered_header_block_indices(&meta.headers).collect();
+        let (first_half, second_half) = blocks.split_at(blocks.len() / 2);
+
+        let write_blocks = |writer: &mut ChunkWriter<_>, blocks: &[(usize, BlockIndex)]| -> UnitResult {
+            for &(chunk_index, block_index) in blocks {
+                let block = UncompressedBlock::from_lines(&meta.headers[0].channels, block_index, |line| {
+                    let y = line.location.position.y();
+                    line.write_samples(|x| (y * 100 + x + 1) as f32).unwrap()
+                });
+
+                writer.write_chunk(chunk_index, block.compress_to_chunk(&meta.headers)?)?;
+            }
+
+            Ok(())
+        };
+
+        write_blocks(writer, first_half)?;
+        writer.checkpoint()?;
+
+        // reading the file now is like reading it after the process crashed
+        partial_samples = read_samples(&path)?;
+        write_blocks(writer, second_half)
+    })?;
+
+    assert_eq!(partial_samples[16 * 3 + 5], 306.0, "written before the checkpoint");
+    assert_eq!(partial_samples[16 * 31 + 5], 0.0, "not written before the checkpoint");
+
+    let complete_samples = read_samples(&path)?;
+    assert_eq!(complete_samples[16 * 3 + 5], 306.0);
+    assert_eq!(complete_samples[16 * 31 + 5], 3106.0);
+
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
+
+#[test]
+fn deterministic_bytes() -> UnitResult {
+    let image = || {
+        let mut attributes = LayerAttributes::named("deterministic");
+        for index in 0 .. 20 { // the hash map of each image has a different random order
+            attributes.other.insert(Text::from(format!("attribute{}", index).as_str()), AttributeValue::I32(index));
+        }
+
+        let channels = SpecificChannels::rgb(|position: Vec2<usize>| (position.x() as f32, position.y() as f32, 0.5_f32));
+        let encoding = Encoding { line_order: LineOrder::Unspecified, .. Encoding::SMALL_LOSSLESS };
+        Image::from_layer(Layer::new(Vec2(256, 256), attributes, encoding, channels))
+    };
+
+    let write = |parallel: bool| -> exr::error::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        let image = image();
+        let options = image.write().deterministic();
+        if parallel { options.to_buffered(Cursor::new(&mut bytes))?; }
+        else { options.non_parallel().to_buffered(Cursor::new(&mut bytes))?; }
+        Ok(bytes)
+    };
+
+    let first = write(true)?;
+    assert_eq!(first, write(true)?);
+    assert_eq!(first, write(false)?);
+    Ok(())
+}
+
+#[test]
+fn attribute_order_is_preserved() -> UnitResult {
+    // the custom attributes first, in reverse alphabetical order, then the owner, then all other attributes
+    let order = [ "zebra", "aardvark", "owner", "channels" ];
+
+    let mut attributes = LayerAttributes::named("ordered").with_attribute_order(order.iter().copied());
+    attributes.other.insert(Text::from("zebra"), AttributeValue::I32(1));
+    attributes.other.insert(Text::from("aardvark"), AttributeValue::I32(2));
+    attributes.owner = Some(Text::from("owner"));
+
+    let channels = SpecificChannels::rgb(|position: Vec2<usize>| (position.x() as f32, position.y() as f32, 0.5_f32));
+    let image = Image::from_layer(Layer::new(Vec2(8, 8), attributes, Encoding::UNCOMPRESSED, channels));
+
+    let mut bytes = Vec::new();
+    image.write().non_parallel().to_buffered(Cursor::new(&mut bytes))?;
+
+    let read_image = || read().no_deep_data().largest_resolution_level().all_channels().first_valid_layer().all_attributes().non_parallel();
+    let image = read_image().from_buffered(Cursor::new(&bytes))?;
+
+    let read_order: Vec<String> = image.layer_data.attributes.attribute_order().iter().map(Text::to_string).collect();
+    assert_eq!(&read_order[.. order.len()], &order);
+
+    let meta = MetaData::read_from_buffered(Cursor::new(&bytes), false)?;
+    let header_order: Vec<&[u8]> = meta.headers[0].ordered_attributes().map(|(name, _)| name).collect();
+    let read_order: Vec<&[u8]> = read_order.iter().map(|name| name.as_bytes()).collect();
+    assert_eq!(header_order, read_order, "attributes of a read header should be in file order");
+
+    // rewriting the file does not reorder the attributes
+    let mut rewritten_bytes = Vec::new();
+    image.write().non_parallel().to_buffered(Cursor::new(&mut rewritten_bytes))?;
+    assert_eq!(bytes, rewritten_bytes);
+    Ok(())
+}
+
+#[test]
+fn sample_filters_are_reverted() -> UnitResult {
+    use exr::compression::filter::{ChannelFilters, Delta, BytePlanes};
+
+    let channels = SpecificChannels::build()
+        .with_channel("N.x").with_channel("Z")
+        .with_pixel_fn(|position: Vec2<usize>| (
+            f16::from_f32(position.y() as f32 * 0.01),
+            1000.0 + position.x() as f32 * 0.25 + position.y() as f32
+        ));
+
+    let write = |filters: ChannelFilters| -> exr::error::Result<Vec<u8>> {
+        let mut attributes = LayerAttributes::named("depth");
+        attributes.sample_filters = filters;
+
+        let encoding = Encoding { compression: Compression::ZIP16, .. Encoding::default() };
+        let image = Image::from_layer(Layer::new(Vec2(64, 48), attributes, encoding, channels.clone()));
+
+        let mut bytes = Vec::new();
+        image.write().non_parallel().to_buffered(Cursor::new(&mut bytes))?;
+        Ok(bytes)
+    };
+
+    let filters = ChannelFilters::default().with_filter("Z", Delta).with_filter("N.x", BytePlanes);
+    let unfiltered_bytes = write(ChannelFilters::default())?;
+    let filtered_bytes = write(filters.clone())?;
+    assert_ne!(unfiltered_bytes, filtered_bytes);
+
+    let read_image = || read().no_deep_data().largest_resolution_level().all_channels().first_valid_layer().all_attributes().non_parallel();
+    let unfiltered = read_image().from_buffered(Cursor::new(&unfiltered_bytes))?;
+    let filtered = read_image().from_buffered(Cursor::new(&filtered_bytes))?;
+
+    assert_eq!(filtered.layer_data.attributes.sample_filters, filters);
+    assert_eq!(filtered.layer_data.channel_data, unfiltered.layer_data.channel_data);
+    Ok(())
+}
+
+#[test]
+fn custom_sample_filters_are_passed_to_the_reader() -> UnitResult {
+    use exr::compression::filter::{ChannelFilters, SampleFilter};
+    use exr::meta::attribute::SampleType;
+    use std::sync::Arc;
+
+    /// Inverts all bits of each sample.
+    #[derive(Debug)]
+    struct Invert;
+
+    impl SampleFilter for Invert {
+        fn name(&self) -> &str { "invert" }
+
+        fn apply(&self, samples: &mut [u8], _: SampleType) -> UnitResult {
+            for byte in samples { *byte = !*byte; }
+            Ok(())
+        }
+
+        fn revert(&self, samples: &mut [u8], sample_type: SampleType) -> UnitResult {
+            self.apply(samples, sample_type)
+        }
+    }
+
+    let channels = SpecificChannels::build().with_channel("Z")
+        .with_pixel_fn(|position: Vec2<usize>| (position.x() as f32 * 0.25 + position.y() as f32,));
+
+    let mut attributes = LayerAttributes::named("depth");
+    attributes.sample_filters = ChannelFilters::default().with_filter("Z", Invert);
+
+    let encoding = Encoding { compression: Compression::ZIP1, .. Encoding::default() };
+    let image = Image::from_layer(Layer::new(Vec2(16, 8), attributes, encoding, channels));
+
+    let mut bytes = Vec::new();
+    image.write().non_parallel().to_buffered(Cursor::new(&mut bytes))?;
+
+    let read_image = || read().no_deep_data().largest_resolution_level().all_channels().first_valid_layer().all_attributes().non_parallel();
+    assert!(read_image().from_buffered(Cursor::new(&bytes)).is_err(), "unknown filters cannot be reverted");
+
+    let image = read_image().sample_filter(Arc::new(Invert)).from_buffered(Cursor::new(&bytes))?;
+    let samples = &image.layer_data.channel_data.list[0].sample_data;
+    assert_eq!(samples.value_by_flat_index(17), Sample::F32(1.25));
+    Ok(())
+}
+
+#[test]
+fn camera_matrices_roundtrip() -> UnitResult {
+    let world_to_camera = Matrix4::translation(1.0, -2.0, 3.5);
+    let world_to_ndc = world_to_camera * Matrix4::scale(0.5, 0.5, 0.25);
+
+    let attributes = LayerAttributes::named("camera")
+        .with_camera_frustum(world_to_camera, world_to_ndc, Vec2(40.0, 30.0), 0.1 .. 1000.0);
+
+    let channels = SpecificChannels::rgb(|_: Vec2<usize>| (0.5_f32, 0.5_f32, 0.5_f32));
+    let image = Image::from_layer(Layer::new(Vec2(4, 4), attributes, Encoding::UNCOMPRESSED, channels));
+
+    let mut bytes = Vec::new();
+    image.write().non_parallel().to_buffered(Cursor::new(&mut bytes))?;
+
+    let image = read().no_deep_data().largest_resolution_level().all_channels().first_valid_layer().all_attributes()
+        .non_parallel().from_buffered(Cursor::new(&bytes))?;
+
+    let attributes = &image.layer_data.attributes;
+    assert_eq!(attributes.world_to_camera_matrix(), Some(world_to_camera));
+    assert_eq!(attributes.world_to_normalized_device_matrix(), Some(world_to_ndc));
+    assert_eq!(attributes.world_to_camera_matrix().unwrap().inverse().unwrap().transform_point((1.0, -2.0, 3.5)), (0.0, 0.0, 0.0));
+    Ok(())
+}
+
+#[test]
+fn validate_samples_while_writing() -> UnitResult {
+    use exr::image::write::validation::{SampleValidation, SampleRule, OnViolation};
+    use std::sync::Arc;
+
+    let channels = SpecificChannels::rgba(|position: Vec2<usize>|
+        (0.5_f32, 0.5_f32, 0.5_f32, if position.x() == 2 { f32::NAN } else { 1.0_f32 })
+    );
+
+    let image = Image::from_channels((8, 8), channels);
+
+    for parallel in [ true, false ] {
+        let strict = Arc::new(SampleValidation::new().with_rule("A", SampleRule::NotNan, OnViolation::Error));
+        let mut writer = image.write().validate_samples(strict.clone());
+        if !parallel { writer = writer.non_parallel(); }
+        assert!(writer.to_buffered(Cursor::new(Vec::new())).is_err(), "NaN alpha must be rejected");
+        assert!(strict.total_violation_count() > 0);
+    }
+
+    let clamping = Arc::new(SampleValidation::new().with_rule("A", SampleRule::range(0.0, 1.0), OnViolation::Clamp));
+
+    let mut bytes = Vec::new();
+    image.write().validate_samples(clamping.clone()).to_buffered(Cursor::new(&mut bytes))?;
+    assert_eq!(clamping.violation_counts(), vec![ 8 ]);
+
+    let image = read().no_deep_data().largest_resolution_level()
+        .rgba_channels(PixelVec::<(f32, f32, f32, f32)>::constructor, PixelVec::set_pixel)
+        .first_valid_layer().all_attributes().from_buffered(Cursor::new(&bytes))?;
+
+    let pixels = &image.layer_data.channel_data.pixels.pixels;
+    assert!(pixels.iter().all(|pixel| pixel.3 == 0.0 || pixel.3 == 1.0));
+    assert_eq!(pixels.iter().filter(|pixel| pixel.3 == 0.0).count(), 8);
+    Ok(())
+}
+
+#[test]
+fn quantized_samples_compress_better() -> UnitResult {
+    use exr::image::write::quantization::Quantization;
+
+    // deterministic noise
+    let noise = |position: Vec2<usize>| {
+        let mut hash = (position.x() * 7919 + position.y() * 104729) as u32 | 1;
+        for _ in 0 .. 3 { hash ^= hash << 13; hash ^= hash >> 17; hash ^= hash << 5; }
+        hash as f32 / u32::MAX as f32 + 0.1
+    };
+    let channels = SpecificChannels::rgb(|position: Vec2<usize>| (noise(position), noise(position) * 0.5, 0.25_f32));
+    let layer = Layer::new((64, 64), LayerAttributes::default(), Encoding::SMALL_LOSSLESS, channels);
+    let image = Image::from_layer(layer);
+
+    let mut lossless = Vec::new();
+    image.write().to_buffered(Cursor::new(&mut lossless))?;
+
+    let mut quantized = Vec::new();
+    image.write().quantize_samples(Quantization::all_channels(8)).to_buffered(Cursor::new(&mut quantized))?;
+    assert!(quantized.len() < lossless.len(), "{} bytes quantized, {} bytes lossless", quantized.len(), lossless.len());
+
+    let image = read().no_deep_data().largest_resolution_level()
+        .rgb_channels(PixelVec::<(f32, f32, f32)>::constructor, PixelVec::set_pixel)
+        .first_valid_layer().all_attributes().from_buffered(Cursor::new(&quantized))?;
+
+    for (index, pixel) in image.layer_data.channel_data.pixels.pixels.iter().enumerate() {
+        let expected = noise(Vec2(index % 64, index / 64));
+        assert!((pixel.0 - expected).abs() <= expected / 256.0);
+        assert_eq!(pixel.2, 0.25);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn dithered_f16_gradient_preserves_average() -> UnitResult {
+    use exr::image::write::dither::Dithering;
+
+    // a very flat gradient, which is rounded to the same f16 value in each row without dithering
+    let brightness = |position: Vec2<usize>| 1.0 + position.y() as f32 * 0.1 * f16::EPSILON.to_f32();
+
+    let channels = SpecificChannels::build()
+        .with_channel_details::<f32>(ChannelDescription::named("Y", SampleType::F16))
+        .with_pixel_fn(|position: Vec2<usize>| (brightness(position),));
+
+    let image = Image::from_channels((64, 10), channels);
+
+    let row_averages = |dithering: Option<Dithering>| -> Result<Vec<f32>> {
+        let mut writer = image.write();
+        if let Some(dithering) = dithering { writer = writer.dither_f16_samples(dithering); }
+
+        let mut bytes = Vec::new();
+        writer.to_buffered(Cursor::new(&mut bytes))?;
+
+        let image = read().no_deep_data().largest_resolution_level()
+            .specific_channels().required("Y")
+            .collect_pixels(PixelVec::<(f16,)>::constructor, PixelVec::set_pixel)
+            .first_valid_layer().all_attributes().from_buffered(Cursor::new(&bytes))?;
+
+        Ok(image.layer_data.channel_data.pixels.pixels.chunks(64)
+            .map(|row| row.iter().map(|pixel| pixel.0.to_f32()).sum::<f32>() / 64.0)
+            .collect())
+    };
+
+    let rounded = row_averages(None)?;
+    let dithered = row_averages(Some(Dithering::channels(["Y"])))?;
+    let unselected = row_averages(Some(Dithering::channels(["R"])))?;
+
+    assert_eq!(unselected, rounded);
+
+    let error = |averages: &[f32]| -> f32 {
+        averages.iter().enumerate().map(|(y, average)| (average - brightness(Vec2(0, y))).abs()).sum::<f32>() / 10.0
+    };
+
+    assert!(error(&rounded) > 0.2 * f16::EPSILON.to_f32());
+    assert!(error(&dithered) < 0.05 * f16::EPSILON.to_f32(), "average error {}", error(&dithered));
+
+    Ok(())
+}
+
+#[test]
+fn layers_inherit_layer_defaults() -> UnitResult {
+    let defaults = LayerAttributes {
+        owner: Some(Text::from("studio")),
+        frames_per_second: Some((24, 1)),
+        comments: Some(Text::from("shared comment")),
+        .. LayerAttributes::default()
+    };
+
+    let channels = SpecificChannels::rgb(|_: Vec2<usize>| (0.5_f32, 0.5_f32, 0.5_f32));
+    let layer = |attributes: LayerAttributes| Layer::new(Vec2(4, 4), attributes, Encoding::UNCOMPRESSED, channels.clone());
+
+    let overriding = LayerAttributes { comments: Some(Text::from("own comment")), .. LayerAttributes::named("overriding") };
+
+    let image = Image::from_layers(ImageAttributes::with_size((4, 4)), vec![ layer(LayerAttributes::named("inheriting")), layer(overriding) ]);
+
+    let mut bytes = Vec::new();
+    image.write().layer_defaults(defaults).non_parallel().to_buffered(Cursor::new(&mut bytes))?;
+
+    let image = read().no_deep_data().largest_resolution_level().all_channels().all_layers().all_attributes()
+        .non_parallel().from_buffered(Cursor::new(&bytes))?;
+
+    let (inheriting, overriding) = (&image.layer_data[0].attributes, &image.layer_data[1].attributes);
+    assert_eq!(inheriting.layer_name, Some(Text::from("inheriting")));
+    assert_eq!(inheriting.comments, Some(Text::from("shared comment")));
+    assert_eq!(overriding.layer_name, Some(Text::from("overriding")));
+    assert_eq!(overriding.comments, Some(Text::from("own comment")));
+
+    for attributes in [ inheriting, overriding ] {
+        assert_eq!(attributes.owner, Some(Text::from("studio")));
+        assert_eq!(attributes.frames_per_second, Some((24, 1)));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn fill_software_name_and_read_writer_attributes() -> UnitResult {
+    let channels = SpecificChannels::rgb(|_: Vec2<usize>| (0.5_f32, 0.5_f32, 0.5_f32));
+    let layer = |attributes: LayerAttributes| Layer::new(Vec2(4, 4), attributes, Encoding::UNCOMPRESSED, channels.clone());
+
+    let unnamed = LayerAttributes {
+        owner: Some(Text::from("studio")),
+        host_computer: Some(Text::from("render-node-17")),
+        .. LayerAttributes::named("unnamed")
+    };
+
+    let named = LayerAttributes { software_name: Some(Text::from("compositor 2.1")), .. LayerAttributes::named("named") };
+    let image = Image::from_layers(ImageAttributes::with_size((4, 4)), vec![ layer(unnamed), layer(named) ]);
+
+    let mut bytes = Vec::new();
+    image.write().non_parallel().fill_software_name().to_buffered(Cursor::new(&mut bytes))?;
+
+    let image = read().no_deep_data().largest_resolution_level().all_channels().all_layers().all_attributes()
+        .non_parallel().from_buffered(Cursor::new(&bytes))?;
+
+    let (unnamed, named) = (&image.layer_data[0].attributes, &image.layer_data[1].attributes);
+    assert_eq!(unnamed.software_name, Some(Text::from(exr::image::write::SOFTWARE_NAME)));
+    assert_eq!(unnamed.owner, Some(Text::from("studio")));
+    assert_eq!(unnamed.host_computer, Some(Text::from("render-node-17")));
+    assert!(unnamed.other.is_empty());
+
+    assert_eq!(named.software_name, Some(Text::from("compositor 2.1")));
+    assert_eq!(named.host_computer, None);
+    Ok(())
+}
+
+#[test]
+fn write_zeroed_offset_tables_without_seeking() -> UnitResult {
+    use exr::block::writer::OffsetTablePlacement;
+    use std::io::{Write, Seek, SeekFrom};
+
+    /// Can only be written at the end, like a file on an append-only file system.
+    struct AppendOnly(Vec<u8>);
+
+    impl Write for AppendOnly {
+        fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> { self.0.write(buffer) }
+        fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+    }
+
+    impl Seek for AppendOnly {
+        fn seek(&mut self, _: SeekFrom) -> std::io::Result<u64> {
+            Err(std::io::Error::new(std::io::ErrorKind::Other, "append only"))
+        }
+    }
+
+    let size = Vec2(64, 200);
+    let channels = SpecificChannels::rgb(|position: Vec2<usize>| (position.x() as f32, position.y() as f32, 0.5_f32));
+    let encoding = Encoding { blocks: Blocks::Tiles(Vec2(16, 16)), .. Encoding::FAST_LOSSLESS };
+    let image = Image::from_encoded_channels(size, encoding, channels);
+
+    let mut appended = AppendOnly(Vec::new());
+    image.write().offset_tables(OffsetTablePlacement::Zeroed).to_buffered(&mut appended)?;
+    assert!(image.write().to_buffered(&mut AppendOnly(Vec::new())).is_err(), "filling in the offset tables requires seeking");
+
+    let mut backfilled = Vec::new();
+    image.write().to_buffered(Cursor::new(&mut backfilled))?;
+    assert_eq!(appended.0.len(), backfilled.len());
+
+    let read_rgb = || read().no_deep_data().largest_resolution_level()
+        .rgb_channels(PixelVec::<(f32, f32, f32)>::constructor, PixelVec::set_pixel)
+        .first_valid_layer().all_attributes();
+
+    let expected = read_rgb().from_buffered(Cursor::new(&backfilled))?;
+    let reconstructed = read_rgb().from_buffered(Cursor::new(&appended.0))?;
+    assert_eq!(reconstructed.layer_data.channel_data.pixels, expected.layer_data.channel_data.pixels);
+
+    let pedantic = read_rgb().pedantic().from_buffered(Cursor::new(&appended.0))?;
+    assert_eq!(pedantic.layer_data.channel_data.pixels, expected.layer_data.channel_data.pixels, "pedantic reading validates the reconstructed offset tables");
+    Ok(())
+}
+
+#[test]
+fn zeroed_offset_tables_from_stream() -> UnitResult {
+    let size = Vec2(64, 200);
+    let channels = SpecificChannels::rgb(|position: Vec2<usize>| (position.x() as f32, position.y() as f32, 0.5_f32));
+    let encoding = Encoding { blocks: Blocks::Tiles(Vec2(16, 16)), .. Encoding::FAST_LOSSLESS };
+    let image = Image::from_encoded_channels(size, encoding, channels);
+
+    // a vector can seek, but the stream writer never tries to
+    let mut streamed = Vec::new();
+    image.write().to_stream(&mut streamed)?;
+
+    let read_rgb = || read().no_deep_data().largest_resolution_level()
+        .rgb_channels(PixelVec::<(f32, f32, f32)>::constructor, PixelVec::set_pixel)
+        .first_valid_layer().all_attributes();
+
+    // a byte slice cannot seek back to the chunks after reconstructing the offset tables
+    let decoded = read_rgb().from_stream(streamed.as_slice())?;
+    let pixels = &decoded.layer_data.channel_data.pixels;
+    assert_eq!(pixels.resolution, size);
+    assert_eq!(*pixels.get_pixel(Vec2(37, 181)), (37.0, 181.0, 0.5));
+
+    let sequential = read_rgb().non_parallel().from_stream(streamed.as_slice())?;
+    assert_eq!(sequential.layer_data.channel_data.pixels, decoded.layer_data.channel_data.pixels);
+    Ok(())
+}
+
+#[test]
+fn packed_scan_lines_contain_little_endian_samples() -> UnitResult {
+    let header = exr::meta::header::Header::new(Text::from("packed"), Vec2(2, 1), smallvec::smallvec![ ChannelDescription::named("Y", SampleType::F32) ])
+        .with_encoding(Compression::Uncompressed, exr::meta::BlockDescription::ScanLines, LineOrder::Increasing);
+    let line = [ 1.5_f32.to_le_bytes(), (-2.0_f32).to_le_bytes() ].concat();
+    assert_eq!(line[.. 4], [ 0x00, 0x00, 0xC0, 0x3F ]);
+
+    let mut bytes = Vec::new();
+    exr::block::write_packed_scan_lines(Cursor::new(&mut bytes), header, true, &[ &line ])?;
+
+    let image = read().no_deep_data().largest_resolution_level().all_channels()
+        .first_valid_layer().all_attributes().from_buffered(Cursor::new(&bytes))?;
+
+    assert_eq!(image.layer_data.channel_data.list[0].sample_data, FlatSamples::F32(vec![ 1.5, -2.0 ]));
+    Ok(())
+}
+
+#[test]
+fn write_packed_scan_lines() -> UnitResult {
+    let size = Vec2(37, 90);
+    let pixel = |position: Vec2<usize>| (f16::from_f32(position.x() as f32), position.y() as f32, (position.x() * position.y()) as u32);
+    let channels = SpecificChannels::build().with_channel("B").with_channel("G").with_channel("R").with_pixel_fn(pixel);
+
+    let encoding = Encoding { compression: Compression::ZIP16, blocks: Blocks::ScanLines, line_order: LineOrder::Increasing };
+    let image = Image::from_encoded_channels(size, encoding, channels);
+
+    let mut expected = Vec::new();
+    image.write().non_parallel().to_buffered(Cursor::new(&mut expected))?;
+    let header = exr::meta::MetaData::read_from_buffered(Cursor::new(&expected), false)?.headers[0].clone();
+
+    // the channels are sorted by name, and each line contains all samples of one channel after another
+    let packed_lines: Vec<Vec<u8>> = (0 .. size.height()).map(|y| {
+        let mut line = Vec::new();
+        for x in 0 .. size.width() { line.extend_from_slice(&pixel(Vec2(x, y)).0.to_le_bytes()); }
+        for x in 0 .. size.width() { line.extend_from_slice(&pixel(Vec2(x, y)).1.to_le_bytes()); }
+        for x in 0 .. size.width() { line.extend_from_slice(&pixel(Vec2(x, y)).2.to_le_bytes()); }
+        line
+    }).collect();
+
+    let mut packed = Vec::new();
+    exr::block::write_packed_scan_lines(Cursor::new(&mut packed), header.clone(), true, &packed_lines)?;
+    assert_eq!(packed, expected);
+
+    let write = |lines: &[Vec<u8>]| exr::block::write_packed_scan_lines(Cursor::new(Vec::new()), header.clone(), true, lines);
+    assert!(write(&packed_lines[1 ..]).is_err(), "too few lines");
+    assert!(write(&[ packed_lines.as_slice(), &packed_lines[.. 1] ].concat()).is_err(), "too many lines");
+    assert!(write(&[ &packed_lines[.. 1], &[ vec![0; 8] ] ].concat()).is_err(), "line byte size");
+
+    let mut decreasing = header;
+    decreasing.line_order = LineOrder::Decreasing;
+    assert!(exr::block::write_packed_scan_lines(Cursor::new(Vec::new()), decreasing, true, &packed_lines).is_err());
+    Ok(())
+}
+
+#[test]
+fn read_file_unbuffered() -> UnitResult {
+    let directory = tempfile::tempdir()?;
+    let path = directory.path().join("read_file_unbuffered.exr");
+
+    let size = Vec2(64, 200);
+    let channels = SpecificChannels::rgb(|position: Vec2<usize>| (position.x() as f32, position.y() as f32, 0.5_f32));
+    let encoding = Encoding { blocks: Blocks::Tiles(Vec2(16, 16)), .. Encoding::FAST_LOSSLESS };
+    Image::from_encoded_channels(size, encoding, channels).write().to_file(&path)?;
+
+    let decoded = read().no_deep_data().largest_resolution_level()
+        .rgb_channels(PixelVec::<(f32, f32, f32)>::constructor, PixelVec::set_pixel)
+        .first_valid_layer().all_attributes().unbuffered_io(4)
+        .from_file(&path)?;
+
+    let pixels = &decoded.layer_data.channel_data.pixels;
+    assert_eq!(pixels.resolution, size);
+    assert_eq!(*pixels.get_pixel(Vec2(37, 181)), (37.0, 181.0, 0.5));
+    Ok(())
+}
+
+#[test]
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+fn read_file_with_io_uring() -> UnitResult {
+    let directory = tempfile::tempdir()?;
+    let path = directory.path().join("read_file_with_io_uring.exr");
+
+    let size = Vec2(64, 200);
+    let channels = SpecificChannels::rgb(|position: Vec2<usize>| (position.x() as f32, position.y() as f32, 0.5_f32));
+    let encoding = Encoding { blocks: Blocks::Tiles(Vec2(16, 16)), .. Encoding::FAST_LOSSLESS };
+    Image::from_encoded_channels(size, encoding, channels).write().to_file(&path)?;
+
+    let decoded = read().no_deep_data().largest_resolution_level()
+        .rgb_channels(PixelVec::<(f32, f32, f32)>::constructor, PixelVec::set_pixel)
+        .first_valid_layer().all_attributes().io_uring(16)
+        .from_file(&path)?;
+
+    let pixels = &decoded.layer_data.channel_data.pixels;
+    assert_eq!(pixels.resolution, size);
+    assert_eq!(*pixels.get_pixel(Vec2(37, 181)), (37.0, 181.0, 0.5));
+    Ok(())
+}
+
+#[test]
+fn roundtrip_with_limited_threads() -> UnitResult {
+    let size = Vec2(64, 200);
+    let channels = SpecificChannels::rgb(|position: Vec2<usize>| (position.x() as f32, position.y() as f32, 0.5_f32));
+    let image = Image::from_encoded_channels(size, Encoding::SMALL_LOSSLESS, channels);
+
+    let mut single_threaded = Vec::new();
+    image.write().threads(1).to_buffered(Cursor::new(&mut single_threaded))?;
+
+    let mut two_threads = Vec::new();
+    image.write().threads(2).to_buffered(Cursor::new(&mut two_threads))?;
+
+    for bytes in &[ single_threaded, two_threads ] {
+        let decoded = read().no_deep_data().largest_resolution_level()
+            .rgb_channels(PixelVec::<(f32, f32, f32)>::constructor, PixelVec::set_pixel)
+            .first_valid_layer().all_attributes().threads(3)
+            .from_buffered(Cursor::new(bytes))?;
+
+        let pixels = &decoded.layer_data.channel_data.pixels;
+        assert_eq!(pixels.resolution, size);
+        assert_eq!(*pixels.get_pixel(Vec2(17, 123)), (17.0, 123.0, 0.5));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn write_borrowed_layers_of_different_images() -> UnitResult {
+    let size = Vec2(6, 4);
+    let first = Image::from_single_channel("Y", size, (0 .. size.area()).map(|index| index as f32).collect::<Vec<f32>>());
+    let second = Image::from_single_channel("Z", size, vec![ 7_u32; size.area() ]);
+
+    let mut first_layer = first.layer_data.view();
+    first_layer.attributes.layer_name = Some(Text::from("first"));
+
+    let mut second_layer = second.layer_data.view();
+    second_layer.attributes.layer_name = Some(Text::from("second"));
+
+    let image = Image::from_layers(first.attributes.clone(), vec![ first_layer, second_layer ]);
+    let mut bytes = Vec::new();
+    image.write().non_parallel().to_buffered(Cursor::new(&mut bytes))?;
+
+    let read_image = read().no_deep_data().largest_resolution_level().all_channels().all_layers().all_attributes()
+        .non_parallel().from_buffered(Cursor::new(&bytes))?;
+
+    assert_eq!(read_image.layer_data.len(), 2);
+    assert_eq!(read_image.layer_data[0].channel_data, first.layer_data.channel_data);
+    assert_eq!(read_image.layer_data[1].channel_data, second.layer_data.channel_data);
+
+    let pixels = Image::from_channels(size, SpecificChannels::build().with_channel("Y").with_pixel_fn(
+        |position: Vec2<usize>| (position.x() as f32 + position.y() as f32 * 10.0,)
+    ));
+
+    let cropped = pixels.layer_data.view().crop(IntegerBounds::new((1, 1), (2, 2)));
+    let mut cropped_bytes = Vec::new();
+    Image::from_layer(cropped).write().non_parallel().to_buffered(Cursor::new(&mut cropped_bytes))?;
+
+    let cropped = read_first_flat_layer_from_buffered(&cropped_bytes)?;
+    assert_eq!(cropped.layer_data.channel_data.list[0].sample_data, FlatSamples::F32(vec![ 11.0, 12.0, 21.0, 22.0 ]));
+    Ok(())
+}
+
+#[test]
+fn read_repeatedly_with_chunk_cache() -> UnitResult {
+    use exr::block::cache::{ChunkCache, ChunkCacheStatistics};
+    use std::sync::Arc;
+
+    let size = Vec2(64, 200);
+    let channels = SpecificChannels::rgb(|position: Vec2<usize>| (position.x() as f32, position.y() as f32, 0.5_f32));
+    let encoding = Encoding { blocks: Blocks::Tiles(Vec2(16, 16)), .. Encoding::FAST_LOSSLESS };
+
+    let mut bytes = Vec::new();
+    Image::from_encoded_channels(size, encoding, channels).write().to_buffered(Cursor::new(&mut bytes))?;
+
+    let cache = Arc::new(ChunkCache::new(1024 * 1024));
+    let tile_count = 4 * 13;
+
+    let read_rgb = || read().no_deep_data().largest_resolution_level()
+        .rgb_channels(PixelVec::<(f32, f32, f32)>::constructor, PixelVec::set_pixel)
+        .first_valid_layer().all_attributes()
+        .chunk_cache(cache.clone(), 7)
+        .from_buffered(Cursor::new(&bytes));
+
+    let first = read_rgb()?;
+    assert_eq!(cache.statistics(), ChunkCacheStatistics { hits: 0, misses: tile_count, evictions: 0 });
+    assert_eq!(cache.len(), tile_count);
+
+    let second = read_rgb()?;
+    assert_eq!(cache.statistics().hits, tile_count);
+    assert_eq!(first, second);
+
+    // reading fewer channels uses the same cached blocks
+    let red = read().no_deep_data().largest_resolution_level()
+        .specific_channels().required("R").collect_pixels(PixelVec::<(f32,)>::constructor, PixelVec::set_pixel)
+        .first_valid_layer().all_attributes()
+        .chunk_cache(cache.clone(), 7)
+        .from_buffered(Cursor::new(&bytes))?;
+
+    assert_eq!(cache.statistics().hits, 2 * tile_count);
+    assert_eq!(*red.layer_data.channel_data.pixels.get_pixel(Vec2(37, 181)), (37.0,));
+
+    // another file does not use the blocks of the first file
+    read().no_deep_data().largest_resolution_level().all_channels().first_valid_layer().all_attributes()
+        .chunk_cache(cache.clone(), 8).from_buffered(Cursor::new(&bytes))?;
+
+    assert_eq!(cache.statistics().misses, 2 * tile_count);
+    Ok(())
+}